@@ -0,0 +1,484 @@
+//! This is the module handling the `bundle` sub-command.
+//!
+//! A bundle is a single `tar.zst` file packaging a set of result files for external partners,
+//! with everything needed to check and reproduce them:
+//!
+//! - `manifest.json`, always the first entry: bundle version, producer and versions of the
+//!   crates defining the data schemas, time range, sources with their format and URL, license
+//!   notes and the size and SHA-256 checksum of every file,
+//! - `SHA256SUMS`, the same checksums in the format of `sha256sum -c`,
+//! - `data/...`, the files themselves, directories being added recursively.
+//!
+//! `bundle verify` checks the manifest and every checksum, `bundle extract` does the same before
+//! writing anything.
+//!
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, trace};
+
+use fetiche_common::Container;
+use fetiche_engine::Engine;
+
+use crate::{BundleOpts, BundleSubCommand, Status};
+
+/// Current version of the bundle layout
+pub const BUNDLE_VERSION: usize = 1;
+
+/// Name of the manifest inside the bundle
+const MANIFEST: &str = "manifest.json";
+
+/// Name of the checksum file inside the bundle
+const SUMS: &str = "SHA256SUMS";
+
+/// Prefix of all data files inside the bundle
+const DATA: &str = "data";
+
+/// zstd compression level
+const LEVEL: i32 = 9;
+
+/// Describe the content of a bundle
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Manifest {
+    /// Bundle layout version
+    pub version: usize,
+    /// Name of the dataset
+    pub name: String,
+    /// Creation time
+    pub created: DateTime<Utc>,
+    /// What created it, e.g. `acutectl/0.23.0`
+    pub producer: String,
+    /// Versions of the crates defining the data formats
+    pub schemas: BTreeMap<String, String>,
+    /// Start of the data
+    pub begin: Option<DateTime<Utc>>,
+    /// End of the data
+    pub end: Option<DateTime<Utc>>,
+    /// Where the data comes from
+    pub sources: Vec<Provenance>,
+    /// License notes
+    pub license: Option<String>,
+    /// All the files, path relative to the bundle
+    pub files: Vec<BundleFile>,
+}
+
+/// One source of the data
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Provenance {
+    /// Site name
+    pub name: String,
+    /// Format of the data from the site
+    pub format: Option<String>,
+    /// Base URL of the site
+    pub base_url: Option<String>,
+}
+
+/// One file of the bundle
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct BundleFile {
+    /// Path inside the bundle
+    pub path: String,
+    /// Size in bytes
+    pub size: u64,
+    /// SHA-256, in hex
+    pub sha256: String,
+    /// Container format, from the extension
+    pub container: Option<String>,
+}
+
+/// Handle `bundle create|verify|extract`
+///
+#[tracing::instrument(skip(engine))]
+pub fn handle_bundle(engine: &Engine, opts: &BundleOpts) -> Result<()> {
+    trace!("handle_bundle");
+
+    match &opts.subcmd {
+        BundleSubCommand::Create {
+            output,
+            name,
+            begin,
+            end,
+            sources,
+            license,
+            files,
+        } => {
+            let mut manifest = Manifest {
+                name: name.clone(),
+                begin: begin.as_deref().map(parse_date).transpose()?,
+                end: end.as_deref().map(parse_date).transpose()?,
+                license: license_from(license.as_deref())?,
+                ..Manifest::default()
+            };
+            manifest.sources = sources
+                .iter()
+                .map(|name| match engine.sources().get(name) {
+                    Some(site) => Provenance {
+                        name: name.clone(),
+                        format: Some(site.format.clone()),
+                        base_url: Some(site.base_url.clone()),
+                    },
+                    None => Provenance {
+                        name: name.clone(),
+                        ..Provenance::default()
+                    },
+                })
+                .collect();
+
+            let manifest = create_bundle(output, files, manifest)?;
+            eprintln!(
+                "{} file(s) bundled into {}",
+                manifest.files.len(),
+                output.to_string_lossy()
+            );
+        }
+        BundleSubCommand::Verify { bundle } => {
+            let manifest = verify_bundle(bundle)?;
+            eprintln!(
+                "{} is valid: {}, {} file(s).",
+                bundle.to_string_lossy(),
+                manifest.name,
+                manifest.files.len()
+            );
+        }
+        BundleSubCommand::Extract { bundle, dir } => {
+            let manifest = extract_bundle(bundle, dir)?;
+            eprintln!(
+                "{} file(s) extracted into {}",
+                manifest.files.len(),
+                dir.to_string_lossy()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Write all `files` into the bundle `output`, filling in the rest of the manifest.
+///
+#[tracing::instrument(skip(manifest))]
+pub fn create_bundle(output: &Path, files: &[PathBuf], mut manifest: Manifest) -> Result<Manifest> {
+    trace!("create_bundle");
+
+    // Collect everything first, we need all the checksums for the manifest
+    //
+    let mut all = BTreeMap::new();
+    for file in files {
+        collect(file, &bundle_path(file)?, &mut all)?;
+    }
+    if all.is_empty() {
+        return Err(Status::BadBundle("no file to bundle".to_string()).into());
+    }
+
+    manifest.version = BUNDLE_VERSION;
+    manifest.created = Utc::now();
+    manifest.producer = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    manifest.schemas = schemas();
+    manifest.files = all
+        .iter()
+        .map(|(path, file)| {
+            let (size, sha256) = checksum(File::open(file)?)?;
+            Ok(BundleFile {
+                path: path.clone(),
+                size,
+                sha256,
+                container: container_of(path),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let fh = File::create(output)?;
+    let enc = zstd::Encoder::new(fh, LEVEL)?;
+    let mut tar = tar::Builder::new(enc);
+
+    let json = serde_json::to_string_pretty(&manifest)?;
+    append(&mut tar, MANIFEST, json.as_bytes())?;
+    append(&mut tar, SUMS, sums(&manifest).as_bytes())?;
+    for (path, file) in &all {
+        info!("Adding {}", path);
+        tar.append_path_with_name(file, path)?;
+    }
+
+    tar.into_inner()?.finish()?.flush()?;
+    Ok(manifest)
+}
+
+/// Check the manifest and every file of a bundle.
+///
+#[tracing::instrument]
+pub fn verify_bundle(bundle: &Path) -> Result<Manifest> {
+    trace!("verify_bundle");
+
+    let name = bundle.to_string_lossy().to_string();
+    let bad = |s: &str| Status::BadBundle(format!("{}: {}", name, s));
+
+    let mut tar = open(bundle)?;
+    let mut entries = tar.entries()?;
+
+    // Manifest first
+    //
+    let manifest: Manifest = match entries.next() {
+        Some(entry) => {
+            let entry = entry?;
+            if entry.path()?.to_string_lossy() != MANIFEST {
+                return Err(bad("manifest is not the first entry").into());
+            }
+            serde_json::from_reader(entry).map_err(|e| bad(&e.to_string()))?
+        }
+        None => return Err(bad("empty").into()),
+    };
+    if manifest.version > BUNDLE_VERSION {
+        return Err(Status::BundleVersion(manifest.version, BUNDLE_VERSION).into());
+    }
+
+    // Then every file must be listed with the right checksum
+    //
+    let mut expected = manifest
+        .files
+        .iter()
+        .map(|f| (f.path.clone(), f))
+        .collect::<BTreeMap<_, _>>();
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        if path == SUMS {
+            continue;
+        }
+        let Some(file) = expected.remove(&path) else {
+            return Err(bad(&format!("{} is not in the manifest", path)).into());
+        };
+        let (size, sha256) = checksum(entry)?;
+        if size != file.size || sha256 != file.sha256 {
+            return Err(Status::BundleChecksum(path).into());
+        }
+    }
+    if let Some(path) = expected.keys().next() {
+        return Err(bad(&format!("{} is missing", path)).into());
+    }
+    Ok(manifest)
+}
+
+/// Verify then extract a bundle into `dir`.
+///
+#[tracing::instrument]
+pub fn extract_bundle(bundle: &Path, dir: &Path) -> Result<Manifest> {
+    trace!("extract_bundle");
+
+    let manifest = verify_bundle(bundle)?;
+
+    fs::create_dir_all(dir)?;
+    open(bundle)?.unpack(dir)?;
+    Ok(manifest)
+}
+
+/// Open a bundle for reading.
+///
+fn open(bundle: &Path) -> Result<tar::Archive<impl Read>> {
+    let fh = BufReader::new(File::open(bundle)?);
+    Ok(tar::Archive::new(zstd::Decoder::new(fh)?))
+}
+
+/// Path inside the bundle for a file or directory given on the command line.
+///
+fn bundle_path(file: &Path) -> Result<String> {
+    match file.file_name() {
+        Some(name) => Ok(format!("{}/{}", DATA, name.to_string_lossy())),
+        None => Err(Status::BadBundle(format!("invalid path {}", file.to_string_lossy())).into()),
+    }
+}
+
+/// Add a file, or a directory recursively, under `path` inside the bundle.
+///
+fn collect(file: &Path, path: &str, all: &mut BTreeMap<String, PathBuf>) -> Result<()> {
+    if file.is_dir() {
+        for entry in fs::read_dir(file)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            collect(&entry.path(), &format!("{}/{}", path, name), all)?;
+        }
+        return Ok(());
+    }
+    if all.insert(path.to_string(), file.to_path_buf()).is_some() {
+        return Err(Status::BadBundle(format!("{} given twice", path)).into());
+    }
+    Ok(())
+}
+
+/// Add an in-memory file.
+///
+fn append<W: Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Size and SHA-256 of everything in `r`.
+///
+fn checksum<R: Read>(mut r: R) -> Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 65536];
+    let mut size = 0;
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// Content of `SHA256SUMS`
+///
+fn sums(manifest: &Manifest) -> String {
+    manifest
+        .files
+        .iter()
+        .map(|f| format!("{}  {}\n", f.sha256, f.path))
+        .collect()
+}
+
+/// Container from the extension, if known
+///
+fn container_of(path: &str) -> Option<String> {
+    let ext = Path::new(path)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    Container::from_str(&ext).ok().map(|c| c.to_string())
+}
+
+/// Versions of the crates defining what is in the files
+///
+fn schemas() -> BTreeMap<String, String> {
+    [fetiche_formats::version(), fetiche_engine::version()]
+        .iter()
+        .filter_map(|v| v.split_once('/'))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// Parse a date for the time range
+///
+fn parse_date(s: &str) -> Result<DateTime<Utc>> {
+    dateparser::parse(s).map_err(|_| Status::BadBundle(format!("bad date {}", s)).into())
+}
+
+/// `--license` is either a file or the text itself
+///
+fn license_from(license: Option<&str>) -> Result<Option<String>> {
+    match license {
+        Some(l) if Path::new(l).is_file() => Ok(Some(fs::read_to_string(l)?)),
+        Some(l) => Ok(Some(l.to_string())),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn setup(dir: &Path) -> (PathBuf, Manifest) {
+        let split = dir.join("split");
+        fs::create_dir_all(&split).unwrap();
+        fs::write(dir.join("drones.csv"), "time,lat,lon\n1,2,3\n").unwrap();
+        fs::write(split.join("a.csv"), "time\n1\n").unwrap();
+        fs::write(split.join("b.csv"), "time\n2\n").unwrap();
+
+        let bundle = dir.join("out.tar.zst");
+        let manifest = Manifest {
+            name: "test".to_string(),
+            license: Some("CC-BY-4.0".to_string()),
+            begin: Some(parse_date("2024-01-01 00:00:00 UTC").unwrap()),
+            ..Manifest::default()
+        };
+        let manifest = create_bundle(&bundle, &[dir.join("drones.csv"), split], manifest).unwrap();
+        (bundle, manifest)
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let dir = tempdir().unwrap();
+        let (bundle, manifest) = setup(dir.path());
+
+        assert_eq!(BUNDLE_VERSION, manifest.version);
+        assert_eq!(3, manifest.files.len());
+        assert_eq!("data/drones.csv", manifest.files[0].path);
+        assert_eq!(Some("Csv".to_string()), manifest.files[0].container);
+        assert_eq!("data/split/a.csv", manifest.files[1].path);
+        assert!(manifest.schemas.contains_key("fetiche-formats"));
+
+        assert_eq!(manifest, verify_bundle(&bundle).unwrap());
+
+        let out = dir.path().join("out");
+        extract_bundle(&bundle, &out).unwrap();
+        assert_eq!(
+            "time,lat,lon\n1,2,3\n",
+            fs::read_to_string(out.join("data/drones.csv")).unwrap()
+        );
+        assert_eq!(
+            "time\n2\n",
+            fs::read_to_string(out.join("data/split/b.csv")).unwrap()
+        );
+        let sums = fs::read_to_string(out.join(SUMS)).unwrap();
+        assert_eq!(3, sums.lines().count());
+        assert!(sums.contains("  data/split/a.csv"));
+    }
+
+    #[test]
+    fn test_bundle_tampered() {
+        let dir = tempdir().unwrap();
+        let (bundle, mut manifest) = setup(dir.path());
+
+        // Rewrite the bundle with a bad checksum in the manifest
+        //
+        manifest.files[2].sha256 = "00".repeat(32);
+        let bad = dir.path().join("bad.tar.zst");
+        let mut tar =
+            tar::Builder::new(zstd::Encoder::new(File::create(&bad).unwrap(), 1).unwrap());
+        append(&mut tar, MANIFEST, &serde_json::to_vec(&manifest).unwrap()).unwrap();
+        let mut src = open(&bundle).unwrap();
+        for entry in src.entries().unwrap().skip(1) {
+            let mut entry = entry.unwrap();
+            let header = entry.header().clone();
+            let mut data = vec![];
+            entry.read_to_end(&mut data).unwrap();
+            tar.append(&header, data.as_slice()).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let err = verify_bundle(&bad).unwrap_err();
+        assert!(err.to_string().contains("data/split/b.csv"));
+
+        let out = dir.path().join("out");
+        assert!(extract_bundle(&bad, &out).is_err());
+        assert!(!out.exists());
+    }
+
+    #[test]
+    fn test_bundle_duplicate() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.csv");
+        fs::write(&file, "x").unwrap();
+
+        let bundle = dir.path().join("out.tar.zst");
+        assert!(create_bundle(&bundle, &[file.clone(), file], Manifest::default()).is_err());
+        assert!(create_bundle(&bundle, &[], Manifest::default()).is_err());
+    }
+}
@@ -1,11 +1,29 @@
-use std::fs::File;
+//! This is the module handling the `convert` sub-command.
+//!
+//! With `--sort-by` or `--stable-output`, the job output goes first into a temporary file next
+//! to the output file, which is then sorted (with an external merge sort if it does not fit in
+//! `--sort-buffer` lines) and/or normalised into the final output.
+//!
+//! `--into xlsx` converts into Cat21 with a header line, written into the same temporary file
+//! and then into an Excel workbook by `write_xlsx()`.
+//!
+//! `--columns` only writes some of the Cat21 columns (see the `Project` task in `fetiche-engine`),
+//! it can not be used with `--sort-by` as the sort keys are found by position.
+//!
+
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::time::Instant;
 
 use eyre::Result;
-use tracing::trace;
+use tracing::{info, trace};
 
-use fetiche_engine::{Convert, Engine, Read};
+use fetiche_common::ExtSort;
+use fetiche_engine::{write_xlsx, Convert, Engine, Project, Read};
+use fetiche_formats::{stable_line, Format, SortRule, CAT21_COLUMNS};
 
-use crate::ConvertOpts;
+use crate::{ConvertOpts, Status};
 
 #[tracing::instrument]
 pub fn convert_from_to(engine: &mut Engine, copts: &ConvertOpts) -> Result<()> {
@@ -16,6 +34,24 @@ pub fn convert_from_to(engine: &mut Engine, copts: &ConvertOpts) -> Result<()> {
     let from = &copts.from;
     let into = &copts.into;
 
+    if *into == Format::Xlsx {
+        return convert_into_xlsx(engine, copts);
+    }
+    // Without conversion, the output is in the input format
+    //
+    let fmt = match into {
+        Format::None => *from,
+        fmt => *fmt,
+    };
+    let rule = fmt.sort_rule();
+    if !copts.sort_by.is_empty() && rule.is_none() {
+        return Err(Status::UnsupportedSort(fmt.to_string()).into());
+    }
+    if copts.columns.is_some() && fmt != Format::Cat21 {
+        return Err(Status::UnsupportedColumns(fmt.to_string()).into());
+    }
+    let post = !copts.sort_by.is_empty() || copts.stable_output;
+
     // Prepare tasks
     //
     let mut r = Read::new(infile);
@@ -23,13 +59,242 @@ pub fn convert_from_to(engine: &mut Engine, copts: &ConvertOpts) -> Result<()> {
 
     let mut c = Convert::new();
     c.from(*from).into(*into);
+    if let Some(policy) = &copts.redact {
+        c.redact(engine.redaction(policy)?);
+    }
+    if let Some(radar) = &copts.radar {
+        let mut radar = radar.clone();
+        if let Some(day) = copts.day {
+            radar.day(day);
+        }
+        c.radar(radar);
+    }
 
     // Create job
     //
     let mut j = engine.create_job(&format!("{}->{}", infile, outfile));
     j.add(Box::new(r)).add(Box::new(c));
+    if let Some(columns) = &copts.columns {
+        j.add(Box::new(Project::new(outfile, columns, &CAT21_COLUMNS)?));
+    }
+
+    let fh = File::create(outfile)?;
+    let mut out = Counter::new(fh);
+
+    let start = Instant::now();
+    if post {
+        let tmp = format!("{}.part", outfile);
+        j.run(&mut File::create(&tmp)?)?;
+
+        let input = BufReader::new(File::open(&tmp)?);
+        let delim = rule.map(|r| r.delimiter).unwrap_or(b',');
+        let res = match copts.stable_output {
+            true => post_process(copts, rule, input, &mut Stable::new(&mut out, delim)),
+            false => post_process(copts, rule, input, &mut out),
+        };
+        fs::remove_file(&tmp)?;
+        res?;
+    } else {
+        j.run(&mut out)?;
+    }
+
+    if copts.profile {
+        let elapsed = start.elapsed().as_secs_f64();
+        let rate = out.rows as f64 / elapsed;
+        info!("{} rows, {} bytes in {:.3}s", out.rows, out.bytes, elapsed);
+        eprintln!(
+            "{} rows ({} bytes) in {:.3}s: {:.0} rows/sec",
+            out.rows, out.bytes, elapsed, rate
+        );
+    }
+    Ok(())
+}
+
+/// Convert into Cat21 then into an Excel workbook, Cat21 input is taken as is.
+///
+fn convert_into_xlsx(engine: &mut Engine, copts: &ConvertOpts) -> Result<()> {
+    trace!("convert_into_xlsx");
+
+    if !copts.sort_by.is_empty() || copts.stable_output {
+        return Err(Status::UnsupportedXlsx.into());
+    }
+
+    let infile = &copts.infile;
+    let outfile = &copts.outfile;
+
+    let mut r = Read::new(infile);
+    r.path(infile).format(copts.from);
+
+    let mut c = Convert::new();
+    match copts.from {
+        Format::Cat21 => c.from(Format::Cat21),
+        from => c.from(from).into(Format::Cat21).header(true),
+    };
+    if let Some(policy) = &copts.redact {
+        c.redact(engine.redaction(policy)?);
+    }
+    if let Some(radar) = &copts.radar {
+        let mut radar = radar.clone();
+        if let Some(day) = copts.day {
+            radar.day(day);
+        }
+        c.radar(radar);
+    }
+
+    let mut j = engine.create_job(&format!("{}->{}", infile, outfile));
+    j.add(Box::new(r)).add(Box::new(c));
+    if let Some(columns) = &copts.columns {
+        j.add(Box::new(Project::new(outfile, columns, &CAT21_COLUMNS)?));
+    }
+
+    let start = Instant::now();
+    let tmp = format!("{}.part", outfile);
+    j.run(&mut File::create(&tmp)?)?;
+
+    let res = fs::read_to_string(&tmp)
+        .map_err(eyre::Report::from)
+        .and_then(|data| write_xlsx(&data, Path::new(outfile), Format::Cat21, copts.per_day));
+    fs::remove_file(&tmp)?;
+    let rows = res?;
+
+    if copts.profile {
+        let elapsed = start.elapsed().as_secs_f64();
+        info!("{} rows in {:.3}s", rows, elapsed);
+        eprintln!(
+            "{} rows in {:.3}s: {:.0} rows/sec",
+            rows,
+            elapsed,
+            rows as f64 / elapsed
+        );
+    }
+    Ok(())
+}
+
+/// Sort (if asked to) the job output into the final one.
+///
+fn post_process<W: Write>(
+    copts: &ConvertOpts,
+    rule: Option<SortRule>,
+    input: BufReader<File>,
+    out: &mut W,
+) -> Result<()> {
+    match rule {
+        Some(rule) if !copts.sort_by.is_empty() => {
+            let dir = match Path::new(&copts.outfile).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => ".".into(),
+            };
+            let n = ExtSort::new(&dir)
+                .buffer(copts.sort_buffer)
+                .sort(input, out, |a, b| rule.compare(a, b, &copts.sort_by))?;
+            trace!("{} lines sorted", n);
+        }
+        _ => {
+            let mut input = input;
+            std::io::copy(&mut input, out)?;
+        }
+    }
+    Ok(out.flush()?)
+}
+
+/// Wrap the output to write every line with `stable_line()`.
+///
+struct Stable<W: Write> {
+    inner: W,
+    delimiter: u8,
+    /// Incomplete line
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Stable<W> {
+    fn new(inner: W, delimiter: u8) -> Self {
+        Stable {
+            inner,
+            delimiter,
+            buf: vec![],
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        self.inner
+            .write_all(stable_line(&line, self.delimiter).as_bytes())
+    }
+}
+
+impl<W: Write> Write for Stable<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        while let Some(i) = self.buf.iter().position(|&c| c == b'\n') {
+            let line = self.buf.drain(..=i).collect::<Vec<_>>();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buf.is_empty() {
+            let line = std::mem::take(&mut self.buf);
+            self.write_line(&line)?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Wrap the output to count lines (i.e. rows) and bytes going through.
+///
+struct Counter<W: Write> {
+    inner: W,
+    rows: usize,
+    bytes: usize,
+}
+
+impl<W: Write> Counter<W> {
+    fn new(inner: W) -> Self {
+        Counter {
+            inner,
+            rows: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.rows += buf[..n].iter().filter(|&&c| c == b'\n').count();
+        self.bytes += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut fh = File::create(outfile)?;
+    #[test]
+    fn test_counter() {
+        let mut out = Counter::new(vec![]);
+        write!(out, "a:b\nc:d\n").unwrap();
+        writeln!(out, "e:f").unwrap();
+        assert_eq!(3, out.rows);
+        assert_eq!(12, out.bytes);
+        assert_eq!(b"a:b\nc:d\ne:f\n".to_vec(), out.inner);
+    }
 
-    j.run(&mut fh)
+    #[test]
+    fn test_stable() {
+        let mut out = Stable::new(vec![], b':');
+        write!(out, "a:1.5\nb:").unwrap();
+        write!(out, "2.25:3").unwrap();
+        out.flush().unwrap();
+        assert_eq!(
+            "a:1.500000\nb:2.250000:3",
+            String::from_utf8(out.inner).unwrap()
+        );
+    }
 }
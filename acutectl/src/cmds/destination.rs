@@ -0,0 +1,250 @@
+//! Output destinations given as URLs with `--to`.
+//!
+//! Instead of combining `-o`, `--split-by`, `--write`, `--postgis`, etc., `fetch` and `stream`
+//! accept a single destination:
+//!
+//! - `stdout` (or `-`),
+//! - `file://PATH`, with `write`, `layout` and `trajectories` as query parameters,
+//! - `dir://PATH`, partitioned into one file per key with `split_by`, per hour otherwise,
+//! - `postgres://…` (or `postgis://…`), with `table` and `trajectories`, the other parameters
+//!   being passed to the database.
+//!
+//! `s3://`, `clickhouse://`, `kafka://` and `cot://` are recognised but have no sink yet.
+//!
+//! ```text
+//! $ acutectl fetch --to "file://flights.parquet?layout=tracks" opensky
+//! $ acutectl fetch --to "dir://tracks/?split_by=track" --tracks opensky
+//! $ acutectl stream --to dir:///var/lib/acute/asd asd
+//! ```
+//!
+//! The older options are kept and turned into a `Destination` so both paths end up in the same
+//! sinks.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use fetiche_common::Container;
+use fetiche_engine::{Layout, SplitBy};
+
+use crate::Status;
+
+/// Schemes we know about but can not write into yet
+const NOT_YET: [&str; 4] = ["s3", "clickhouse", "kafka", "cot"];
+
+/// Where the output of a command goes
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Destination {
+    /// Standard output
+    #[default]
+    Stdout,
+    /// A single file
+    File {
+        path: String,
+        /// Container, deduced from the extension if not set
+        write: Option<Container>,
+        layout: Option<Layout>,
+        trajectories: bool,
+    },
+    /// A directory, one file per key or per hour
+    Dir {
+        path: String,
+        split_by: Option<SplitBy>,
+    },
+    /// A PostGIS database
+    PostGis {
+        url: String,
+        table: Option<String>,
+        trajectories: bool,
+    },
+}
+
+/// Split `name=value&…`, keeping the order
+///
+fn parse_query(query: &str) -> Vec<(&str, &str)> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.split_once('=').unwrap_or((p, "")))
+        .collect()
+}
+
+/// Parse a parameter value
+///
+fn param<T: FromStr>(url: &str, name: &str, value: &str) -> Result<T, Status> {
+    value
+        .parse()
+        .map_err(|_| Status::BadDestination(url.to_string(), format!("bad {name} {value}")))
+}
+
+impl FromStr for Destination {
+    type Err = Status;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "stdout" || s == "-" {
+            return Ok(Destination::Stdout);
+        }
+        let Some((scheme, rest)) = s.split_once("://") else {
+            return Err(Status::BadDestination(
+                s.to_string(),
+                "no scheme".to_string(),
+            ));
+        };
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let bad = |why: &str| Status::BadDestination(s.to_string(), why.to_string());
+        if path.is_empty() {
+            return Err(bad("empty path"));
+        }
+
+        match scheme {
+            "file" => {
+                let (mut write, mut layout, mut trajectories) = (None, None, false);
+                for (name, value) in parse_query(query) {
+                    match name {
+                        "write" => write = Some(param(s, name, value)?),
+                        "layout" => layout = Some(param(s, name, value)?),
+                        "trajectories" => trajectories = param(s, name, value)?,
+                        _ => return Err(bad(&format!("unknown parameter {name}"))),
+                    }
+                }
+                Ok(Destination::File {
+                    path: path.to_string(),
+                    write,
+                    layout,
+                    trajectories,
+                })
+            }
+            "dir" => {
+                let mut split_by = None;
+                for (name, value) in parse_query(query) {
+                    match name {
+                        "split_by" => split_by = Some(param(s, name, value)?),
+                        _ => return Err(bad(&format!("unknown parameter {name}"))),
+                    }
+                }
+                Ok(Destination::Dir {
+                    path: path.to_string(),
+                    split_by,
+                })
+            }
+            "postgres" | "postgresql" | "postgis" => {
+                let (mut table, mut trajectories, mut rest) = (None, false, vec![]);
+                for (name, value) in parse_query(query) {
+                    match name {
+                        "table" => table = Some(value.to_string()),
+                        "trajectories" => trajectories = param(s, name, value)?,
+                        _ => rest.push(format!("{name}={value}")),
+                    }
+                }
+                let mut url = format!("postgres://{path}");
+                if !rest.is_empty() {
+                    url = format!("{url}?{}", rest.join("&"));
+                }
+                Ok(Destination::PostGis {
+                    url,
+                    table,
+                    trajectories,
+                })
+            }
+            _ if NOT_YET.contains(&scheme) => {
+                Err(Status::UnsupportedDestination(scheme.to_string()))
+            }
+            _ => Err(bad(&format!("unknown scheme {scheme}"))),
+        }
+    }
+}
+
+/// Shown in logs, PostGIS credentials are not
+///
+impl Display for Destination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Destination::Stdout => write!(f, "stdout"),
+            Destination::File { path, .. } => write!(f, "file://{path}"),
+            Destination::Dir { path, split_by } => match split_by {
+                Some(by) => write!(f, "dir://{path}?split_by={by}"),
+                None => write!(f, "dir://{path}"),
+            },
+            Destination::PostGis { url, table, .. } => {
+                let host = url.rsplit_once('@').map(|(_, h)| h).unwrap_or(url);
+                let host = host.trim_start_matches("postgres://");
+                let host = host.split_once('?').map(|(h, _)| h).unwrap_or(host);
+                write!(f, "postgres://{host}")?;
+                if let Some(table) = table {
+                    write!(f, "?table={table}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn file(path: &str, write: Option<Container>, layout: Option<Layout>) -> Destination {
+        Destination::File {
+            path: path.to_string(),
+            write,
+            layout,
+            trajectories: write == Some(Container::GeoParquet),
+        }
+    }
+
+    fn dir(path: &str, split_by: Option<SplitBy>) -> Destination {
+        Destination::Dir {
+            path: path.to_string(),
+            split_by,
+        }
+    }
+
+    #[rstest]
+    #[case("stdout", Destination::Stdout)]
+    #[case("-", Destination::Stdout)]
+    #[case("file://out.csv", file("out.csv", None, None))]
+    #[case(
+        "file:///tmp/f.parquet?layout=tracks",
+        file("/tmp/f.parquet", None, Some(Layout::Tracks))
+    )]
+    #[case(
+        "file://f?write=geoparquet&trajectories=true",
+        file("f", Some(Container::GeoParquet), None)
+    )]
+    #[case("dir://tracks/?split_by=track", dir("tracks/", Some(SplitBy::Track)))]
+    #[case("dir:///var/lib/acute", dir("/var/lib/acute", None))]
+    fn test_destination_from_str(#[case] s: &str, #[case] dest: Destination) {
+        assert_eq!(dest, Destination::from_str(s).unwrap());
+    }
+
+    #[test]
+    fn test_destination_postgis() {
+        let dest =
+            Destination::from_str("postgis://u:p@db/adsb?table=flights&sslmode=require").unwrap();
+        assert_eq!(
+            Destination::PostGis {
+                url: "postgres://u:p@db/adsb?sslmode=require".to_string(),
+                table: Some("flights".to_string()),
+                trajectories: false,
+            },
+            dest
+        );
+        assert_eq!("postgres://db/adsb?table=flights", dest.to_string());
+    }
+
+    #[rstest]
+    #[case("out.csv")]
+    #[case("file://")]
+    #[case("file://out.csv?colour=blue")]
+    #[case("file://out.csv?layout=rows")]
+    #[case("dir://out?split_by=colour")]
+    #[case("ftp://host/out.csv")]
+    #[case("s3://bucket/out.csv")]
+    #[case("kafka://broker/topic")]
+    fn test_destination_bad(#[case] s: &str) {
+        assert!(Destination::from_str(s).is_err());
+    }
+}
@@ -0,0 +1,418 @@
+//! This is the module handling the `diff` sub-command.
+//!
+//! Two converted datasets (CSV or Parquet) are matched on a set of key columns (`-k`, e.g.
+//! `icao24,time`) and compared record by record: records only in the second one are added, only
+//! in the first one removed and those in both with at least one different value are changed.
+//! Numbers are equal if they differ by no more than `--tolerance`.
+//!
+//! For every column present in both files, we report the number of changed values and for
+//! numbers the mean and maximum difference (second minus first), which shows drift after a
+//! converter change.  This is used to validate converters against golden outputs or to compare
+//! the coverage of the same period by two sources.
+//!
+//! Like `diff(1)`, finding differences is an error so it can be used in scripts.
+//!
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, RecordBatch};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Float64Type};
+use eyre::Result;
+use tokio::runtime::Runtime;
+use tracing::{info, trace, warn};
+
+use fetiche_common::{Listing, OutputFormat};
+
+use crate::{read_file, DiffOpts, Status};
+
+/// Batch size when reading
+const BATCH: usize = 8192;
+
+/// One value, numbers are compared with a tolerance
+///
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    /// Same value within `tolerance`
+    ///
+    fn same(&self, other: &Value, tolerance: f64) -> bool {
+        match (self, other) {
+            (Value::Num(a), Value::Num(b)) => (a - b).abs() <= tolerance || a == b,
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// A dataset indexed by its keys
+///
+#[derive(Debug, Default)]
+struct Dataset {
+    /// Non-key columns, in order
+    columns: Vec<String>,
+    /// Values of every record, same order as `columns`
+    records: BTreeMap<Vec<String>, Vec<Value>>,
+    /// Records whose keys were already seen, the last one is kept
+    duplicates: usize,
+}
+
+impl Dataset {
+    /// Index all the batches of one file.
+    ///
+    fn from_batches(batches: &[RecordBatch], keys: &[String]) -> Result<Self> {
+        let Some(first) = batches.first() else {
+            return Ok(Dataset::default());
+        };
+        let schema = first.schema();
+        for k in keys {
+            if schema.index_of(k).is_err() {
+                return Err(Status::UnknownColumn(k.clone()).into());
+            }
+        }
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .filter(|n| !keys.contains(n))
+            .collect::<Vec<_>>();
+
+        let mut ds = Dataset {
+            columns,
+            ..Dataset::default()
+        };
+        for batch in batches {
+            let kcols = keys
+                .iter()
+                .map(|k| values(batch.column_by_name(k).unwrap()))
+                .collect::<Result<Vec<_>>>()?;
+            let vcols = ds
+                .columns
+                .iter()
+                .map(|c| values(batch.column_by_name(c).unwrap()))
+                .collect::<Result<Vec<_>>>()?;
+
+            for row in 0..batch.num_rows() {
+                let key = kcols
+                    .iter()
+                    .map(|c| match &c[row] {
+                        Value::Null => String::new(),
+                        Value::Num(n) => n.to_string(),
+                        Value::Str(s) => s.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                let rec = vcols.iter().map(|c| c[row].clone()).collect();
+                if ds.records.insert(key, rec).is_some() {
+                    ds.duplicates += 1;
+                }
+            }
+        }
+        Ok(ds)
+    }
+}
+
+/// All values of a column, numbers as `f64` and everything else as strings.
+///
+fn values(col: &ArrayRef) -> Result<Vec<Value>> {
+    let numeric = col.data_type().is_numeric();
+    let col = if numeric {
+        cast(col, &DataType::Float64)?
+    } else {
+        cast(col, &DataType::Utf8)?
+    };
+    let res = (0..col.len())
+        .map(|i| {
+            if col.is_null(i) {
+                Value::Null
+            } else if numeric {
+                Value::Num(col.as_primitive::<Float64Type>().value(i))
+            } else {
+                Value::Str(col.as_string::<i32>().value(i).to_string())
+            }
+        })
+        .collect();
+    Ok(res)
+}
+
+/// Drift of one column
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnDiff {
+    /// Column name
+    pub name: String,
+    /// Number of records with a different value
+    pub changed: usize,
+    /// Number of numeric values compared
+    pub count: usize,
+    /// Sum of the differences, second minus first
+    pub sum: f64,
+    /// Largest difference in absolute value
+    pub max: f64,
+}
+
+impl ColumnDiff {
+    /// Mean difference, if we compared numbers
+    ///
+    pub fn mean(&self) -> Option<f64> {
+        (self.count != 0).then(|| self.sum / self.count as f64)
+    }
+}
+
+/// Result of the comparison
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DiffReport {
+    /// Records in both
+    pub common: usize,
+    /// Records only in the second dataset
+    pub added: usize,
+    /// Records only in the first dataset
+    pub removed: usize,
+    /// Records in both with at least one different value
+    pub changed: usize,
+    /// Duplicate keys in each dataset
+    pub duplicates: (usize, usize),
+    /// Columns only in the first dataset
+    pub only_a: Vec<String>,
+    /// Columns only in the second dataset
+    pub only_b: Vec<String>,
+    /// Drift of each common column
+    pub columns: Vec<ColumnDiff>,
+}
+
+impl DiffReport {
+    /// Any difference?
+    ///
+    pub fn is_same(&self) -> bool {
+        self.added == 0
+            && self.removed == 0
+            && self.changed == 0
+            && self.only_a.is_empty()
+            && self.only_b.is_empty()
+    }
+
+    /// Summary and per-column tables
+    ///
+    pub fn render(&self, fmt: OutputFormat) -> Result<String> {
+        let mut summary = Listing::new("Records", &[("Name", "name"), ("Value", "value")]);
+        summary
+            .push(vec!["common".to_string(), self.common.to_string()])
+            .push(vec!["added".to_string(), self.added.to_string()])
+            .push(vec!["removed".to_string(), self.removed.to_string()])
+            .push(vec!["changed".to_string(), self.changed.to_string()])
+            .push(vec![
+                "duplicates".to_string(),
+                format!("{}/{}", self.duplicates.0, self.duplicates.1),
+            ])
+            .push(vec!["only_first".to_string(), self.only_a.join(",")])
+            .push(vec!["only_second".to_string(), self.only_b.join(",")]);
+
+        let mut columns = Listing::new(
+            "Columns",
+            &[
+                ("Column", "column"),
+                ("Changed", "changed"),
+                ("Mean diff", "mean"),
+                ("Max diff", "max"),
+            ],
+        );
+        self.columns.iter().for_each(|c| {
+            columns.push(vec![
+                c.name.clone(),
+                c.changed.to_string(),
+                c.mean().map(|m| format!("{m:e}")).unwrap_or_default(),
+                if c.count != 0 {
+                    format!("{:e}", c.max)
+                } else {
+                    String::new()
+                },
+            ]);
+        });
+        Ok(format!(
+            "{}\n{}",
+            summary.render(fmt)?,
+            columns.render(fmt)?
+        ))
+    }
+}
+
+/// Compare two indexed datasets.
+///
+fn compare(a: &Dataset, b: &Dataset, tolerance: f64) -> DiffReport {
+    let mut report = DiffReport {
+        duplicates: (a.duplicates, b.duplicates),
+        only_a: a
+            .columns
+            .iter()
+            .filter(|c| !b.columns.contains(c))
+            .cloned()
+            .collect(),
+        only_b: b
+            .columns
+            .iter()
+            .filter(|c| !a.columns.contains(c))
+            .cloned()
+            .collect(),
+        ..DiffReport::default()
+    };
+
+    // Index of each common column in both datasets
+    //
+    let common = a
+        .columns
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| b.columns.iter().position(|n| n == c).map(|j| (i, j)))
+        .collect::<Vec<_>>();
+    report.columns = common
+        .iter()
+        .map(|(i, _)| ColumnDiff {
+            name: a.columns[*i].clone(),
+            ..ColumnDiff::default()
+        })
+        .collect();
+
+    for (key, ra) in a.records.iter() {
+        let Some(rb) = b.records.get(key) else {
+            report.removed += 1;
+            continue;
+        };
+        report.common += 1;
+
+        let mut changed = false;
+        for (n, (i, j)) in common.iter().enumerate() {
+            let col = &mut report.columns[n];
+            let (va, vb) = (&ra[*i], &rb[*j]);
+            if let (Value::Num(x), Value::Num(y)) = (va, vb) {
+                let d = y - x;
+                col.count += 1;
+                col.sum += d;
+                if d.abs() > col.max.abs() {
+                    col.max = d;
+                }
+            }
+            if !va.same(vb, tolerance) {
+                col.changed += 1;
+                changed = true;
+            }
+        }
+        if changed {
+            report.changed += 1;
+        }
+    }
+    report.added = b
+        .records
+        .keys()
+        .filter(|k| !a.records.contains_key(*k))
+        .count();
+    report
+}
+
+/// Read both files, compare them and display the report.
+///
+#[tracing::instrument]
+pub fn diff_datasets(dopts: &DiffOpts, fmt: OutputFormat) -> Result<()> {
+    trace!("diff_datasets");
+
+    info!(
+        "Comparing {:?} and {:?} on {:?}",
+        dopts.first, dopts.second, dopts.keys
+    );
+
+    let rt = Runtime::new()?;
+    let (a, b) = rt.block_on(async {
+        let a = load(&dopts.first, dopts).await?;
+        let b = load(&dopts.second, dopts).await?;
+        Ok::<_, eyre::Report>((a, b))
+    })?;
+
+    let report = compare(&a, &b, dopts.tolerance);
+    println!("{}", report.render(fmt)?);
+
+    if report.duplicates != (0, 0) {
+        warn!("Duplicate keys: {:?}", report.duplicates);
+    }
+    if report.is_same() {
+        Ok(())
+    } else {
+        Err(Status::DatasetsDiffer(report.added, report.removed, report.changed).into())
+    }
+}
+
+/// Read and index one file.
+///
+async fn load(fname: &Path, dopts: &DiffOpts) -> Result<Dataset> {
+    let df = read_file(fname, dopts.from, BATCH, dopts.delimiter as u8).await?;
+    let batches = df.collect().await?;
+    Dataset::from_batches(&batches, &dopts.keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    async fn dataset(csv: &str, keys: &[&str]) -> Result<Dataset> {
+        let dir = tempdir()?;
+        let fname = dir.path().join("data.csv");
+        fs::write(&fname, csv)?;
+
+        let df = read_file(&fname, None, BATCH, b',').await?;
+        let keys = keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        Dataset::from_batches(&df.collect().await?, &keys)
+    }
+
+    #[tokio::test]
+    async fn test_diff_same() -> Result<()> {
+        let csv = "icao24,time,alt\nabc,1,100.0\ndef,1,200.0\n";
+        let a = dataset(csv, &["icao24", "time"]).await?;
+        let b = dataset(csv, &["icao24", "time"]).await?;
+
+        let report = compare(&a, &b, 1e-6);
+        assert!(report.is_same());
+        assert_eq!(2, report.common);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_changes() -> Result<()> {
+        let a = dataset(
+            "icao24,time,alt,callsign\nabc,1,100.0,AAA\nabc,2,110.0,AAA\ndef,1,200.0,BBB\n",
+            &["icao24", "time"],
+        )
+        .await?;
+        let b = dataset(
+            "icao24,time,alt,callsign\nabc,1,100.0000001,AAA\nabc,2,112.5,AAA\nghi,1,300.0,CCC\n",
+            &["icao24", "time"],
+        )
+        .await?;
+
+        let report = compare(&a, &b, 1e-6);
+        assert!(!report.is_same());
+        assert_eq!(2, report.common);
+        assert_eq!(1, report.added);
+        assert_eq!(1, report.removed);
+        assert_eq!(1, report.changed);
+
+        let alt = &report.columns[0];
+        assert_eq!("alt", alt.name);
+        assert_eq!(1, alt.changed);
+        assert_eq!(2, alt.count);
+        assert!((alt.max - 2.5).abs() < 1e-9);
+        assert_eq!(0, report.columns[1].changed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_bad_key() {
+        assert!(dataset("a,b\n1,2\n", &["icao24"]).await.is_err());
+    }
+}
@@ -1,19 +1,27 @@
 //! This is the module handling the `fetch` sub-command.
 //!
+//! With `--dry-run`, the job is only described (see `Plan` in `fetiche-engine`): the site is
+//! not contacted, no job is created and nothing is written.
+//!
 
+use eyre::Result;
+use indicatif::ProgressBar;
 use std::path::Path;
 use std::str::FromStr;
 use std::time::Duration;
-use eyre::Result;
-use indicatif::ProgressBar;
 use tracing::{error, info, trace};
 
 use fetiche_common::{Container, DateOpts};
-use fetiche_engine::{Convert, Engine, Fetch, Save, Tee};
+#[cfg(feature = "postgis")]
+use fetiche_engine::PostGis;
+use fetiche_engine::{
+    Convert, Engine, Fetch, FlightPlans, Label, Layout, Plan, Qc, RawCopy, Runnable, Save, Select,
+    Split, Store, Tee, Track, POSITIONS,
+};
 use fetiche_formats::Format;
 use fetiche_sources::{Filter, Flow, Site};
 
-use crate::{FetchOpts, Status};
+use crate::{Destination, FetchOpts, Status};
 
 /// Actual fetching of data from a given site
 ///
@@ -33,6 +41,15 @@ pub fn fetch_from_site(engine: &mut Engine, fopts: &FetchOpts) -> Result<()> {
     };
 
     let filter = filter_from_opts(fopts)?;
+    let redact = match &fopts.redact {
+        Some(policy) => Some(engine.redaction(policy)?),
+        None => None,
+    };
+
+    if fopts.dry_run {
+        print!("{}", plan_from_opts(engine, fopts, filter)?);
+        return Ok(());
+    }
 
     info!("Fetching from network site {}", name);
 
@@ -40,13 +57,32 @@ pub fn fetch_from_site(engine: &mut Engine, fopts: &FetchOpts) -> Result<()> {
     //
     let mut task = Fetch::new(name, srcs);
 
-    task.site(site.name()).with(filter);
+    task.site(site.name())
+        .with(filter)
+        .tokens(engine.refresher());
+    if !fopts.topics.is_empty() {
+        task.topics(&fopts.topics, &fopts.topics_dir);
+    }
 
     let mut data = vec![];
 
     let mut job = engine.create_job("fetch_from_site");
+    job.source = Some(site.name());
+    if let Some(m) = fopts.timeout {
+        job.timeout = Some(Duration::from_secs(m * 60));
+    }
     job.add(Box::new(task));
 
+    // Keep every chunk as received, one file each, to be able to reprocess them later
+    //
+    if let Some(dir) = &fopts.raw_copy {
+        info!("Raw copy into {dir}");
+
+        let mut raw = RawCopy::new(dir, site.format());
+        raw.path(dir);
+        job.add(Box::new(raw));
+    }
+
     // Do we want a copy of the raw data (often before converting it)
     //
     if let Some(tee) = &fopts.tee {
@@ -54,35 +90,345 @@ pub fn fetch_from_site(engine: &mut Engine, fopts: &FetchOpts) -> Result<()> {
         job.add(Box::new(copy));
     }
 
-    // If a conversion is requested, insert it
-    //
-    // FIXME: DEPRECATED
+    // If a conversion, a redaction or provenance is requested, insert it
     //
-    let input = if let Some(_into) = &fopts.into {
+    let into = conversion(fopts.into);
+    if into != Format::None || redact.is_some() || fopts.provenance {
         let mut convert = Convert::new();
-        convert.from(site.format()).into(Format::Cat21);
+        convert
+            .from(site.format())
+            .into(into)
+            .strictness(engine.sources().strictness(name));
+        if let Some(redact) = redact {
+            convert.redact(redact);
+        }
+        if fopts.provenance {
+            convert.provenance(engine.provenance(name, job.id));
+        }
         job.add(Box::new(convert));
-
-        Format::Cat21
+    }
+    let input = if into == Format::None {
+        site.format()
     } else {
+        into
+    };
+
+    // Only the categories asked for
+    //
+    if !fopts.category.is_empty() {
+        let mut select = Select::new("select", input);
+        select.categories(&fopts.category);
+        job.add(Box::new(select));
+    }
+
+    // Normalise track IDs, before QC which uses them
+    //
+    if fopts.tracks {
+        let mut track = Track::new("tracks", input);
+        track
+            .max_gap(fopts.track_max_gap)
+            .max_jump(fopts.track_max_jump);
+        job.add(Box::new(track));
+    }
+
+    // Ground truth from flight plans
+    //
+    if let Some(plans) = &fopts.label {
+        let mut label = Label::new("label", input);
+        label
+            .plans(FlightPlans::load(plans)?)
+            .window(fopts.label_window)
+            .matched_only(fopts.label_matched_only);
+        job.add(Box::new(label));
+    }
+
+    // Check records before they are written
+    //
+    if let Some(summary) = &fopts.qc {
+        info!("QC summary into {summary}");
+
+        let mut qc = Qc::new(summary, input);
+        qc.path(summary)
+            .max_gap(fopts.qc_max_gap)
+            .max_climb(fopts.qc_max_climb)
+            .drop(fopts.qc_drop);
+        job.add(Box::new(qc));
+    }
+
+    let workdir = job.workdir.clone();
+    job.add(output_from_opts(fopts, input, job.id, workdir.as_deref())?);
+
+    eprintln!("Fetching from {}", site.name());
+    let bar = ProgressBar::new_spinner();
+    bar.enable_steady_tick(Duration::from_millis(100));
+
+    // Launch it now, job is removed from engine and state afterwards
+    //
+    trace!("Job({}) running.", job.id);
+    let res = engine.run_job(job, &mut data);
+
+    bar.finish();
+    res
+}
+
+/// Format asked for with `--into`, `Format::None` if none
+///
+/// FIXME: DEPRECATED
+///
+fn conversion(into: Option<Format>) -> Format {
+    match into {
+        Some(Format::Sbs1) => Format::Sbs1,
+        Some(Format::Senhive) => Format::Senhive,
+        Some(_) => Format::Cat21,
+        None => Format::None,
+    }
+}
+
+/// What `fetch_from_site()` would do, stage by stage in the same order.
+///
+#[tracing::instrument(skip(engine))]
+fn plan_from_opts(engine: &Engine, fopts: &FetchOpts, filter: Filter) -> Result<Plan> {
+    let srcs = engine.sources();
+    let site = &srcs[fopts.site.as_str()];
+
+    let mut plan = Plan::new("fetch_from_site");
+    plan.site(site, false, filter)
+        .stage(&format!("Fetch {}", site.name()));
+    if !fopts.topics.is_empty() {
+        plan.stage(&format!("Topics {}", fopts.topics.join(", ")));
+        for topic in fopts.topics.iter().filter(|t| *t != POSITIONS) {
+            let fname = Path::new(&fopts.topics_dir).join(format!("{}-{topic}.csv", site.name()));
+            plan.output(&fname.to_string_lossy());
+        }
+    }
+
+    if let Some(dir) = &fopts.raw_copy {
+        plan.stage(&format!("RawCopy into {dir}")).output(dir);
+    }
+    if let Some(tee) = &fopts.tee {
+        plan.stage(&format!("Tee into {tee}")).output(tee);
+    }
+
+    let into = conversion(fopts.into);
+    if into != Format::None || fopts.redact.is_some() || fopts.provenance {
+        let mut convert = format!("Convert {} into {}", site.format(), into);
+        if let Some(policy) = &fopts.redact {
+            convert.push_str(&format!(", redact {policy}"));
+        }
+        if fopts.provenance {
+            convert.push_str(", provenance");
+        }
+        plan.stage(&convert);
+    }
+    let input = if into == Format::None {
         site.format()
+    } else {
+        into
     };
 
+    if !fopts.category.is_empty() {
+        let cats = fopts
+            .category
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        plan.stage(&format!("Select {}", cats.join(", ")));
+    }
+    if fopts.tracks {
+        plan.stage(&format!(
+            "Track (max gap {}s, max jump {}m)",
+            fopts.track_max_gap, fopts.track_max_jump
+        ));
+    }
+    if let Some(plans) = &fopts.label {
+        plan.stage(&format!(
+            "Label from {} (window {}s{})",
+            plans.display(),
+            fopts.label_window,
+            if fopts.label_matched_only {
+                ", matched only"
+            } else {
+                ""
+            }
+        ));
+    }
+    if let Some(summary) = &fopts.qc {
+        plan.stage(&format!(
+            "Qc (max gap {}s, max climb {}m/s{})",
+            fopts.qc_max_gap,
+            fopts.qc_max_climb,
+            if fopts.qc_drop { ", drop" } else { "" }
+        ))
+        .output(summary);
+    }
+
+    let dest = destination(fopts)?;
+    match &dest {
+        #[cfg(feature = "postgis")]
+        Destination::PostGis { table, .. } => {
+            let table = table.clone().unwrap_or(fopts.table.clone());
+            plan.stage(&format!("PostGis into {table}"));
+        }
+        #[cfg(not(feature = "postgis"))]
+        Destination::PostGis { .. } => {
+            return Err(Status::UnsupportedDestination("postgres".into()).into());
+        }
+        Destination::Dir {
+            path,
+            split_by: Some(by),
+        } => {
+            plan.stage(&format!("Split by {by} into {path}"));
+        }
+        Destination::Dir {
+            path,
+            split_by: None,
+        } => {
+            plan.stage(&format!("Store into {path}/<job id>"));
+        }
+        Destination::File {
+            path,
+            write,
+            layout,
+            trajectories,
+        } => {
+            let layout = layout.unwrap_or(fopts.layout);
+            let trajectories = *trajectories || fopts.trajectories;
+            let save = save_into(Some(path), *write, layout, trajectories, input, None)?;
+            plan.stage(&format!("Save into {path} ({})", save.out));
+        }
+        Destination::Stdout => {
+            let save = save_into(None, None, fopts.layout, fopts.trajectories, input, None)?;
+            plan.stage(&format!("Save into stdout ({})", save.out));
+        }
+    }
+    plan.output(&dest.to_string());
+    Ok(plan)
+}
+
+/// Destination from `--to` or from the older `-o`, `--split-by`, `--write` and `--postgis`.
+///
+#[tracing::instrument]
+fn destination(fopts: &FetchOpts) -> Result<Destination> {
+    if let Some(to) = &fopts.to {
+        return Ok(to.clone());
+    }
+
+    #[cfg(feature = "postgis")]
+    if let Some(url) = &fopts.postgis {
+        return Ok(Destination::PostGis {
+            url: url.clone(),
+            table: Some(fopts.table.clone()),
+            trajectories: fopts.trajectories,
+        });
+    }
+
+    // Do we want one file per key?  Output is then a directory.
+    //
+    match (&fopts.output, fopts.split_by) {
+        (Some(dir), Some(by)) => Ok(Destination::Dir {
+            path: dir.clone(),
+            split_by: Some(by),
+        }),
+        (None, Some(_)) => Err(Status::NoOutputDir.into()),
+        (Some(fname), None) => Ok(Destination::File {
+            path: fname.clone(),
+            write: fopts.write,
+            layout: None,
+            trajectories: false,
+        }),
+        (None, None) => Ok(Destination::Stdout),
+    }
+}
+
+/// Select the last task: database, one file per key, hourly files or a single file.
+///
+#[tracing::instrument]
+fn output_from_opts(
+    fopts: &FetchOpts,
+    input: Format,
+    id: usize,
+    workdir: Option<&Path>,
+) -> Result<Box<dyn Runnable>> {
+    let dest = destination(fopts)?;
+    info!("Writing to {dest}");
+
+    match dest {
+        #[cfg(feature = "postgis")]
+        Destination::PostGis {
+            url,
+            table,
+            trajectories,
+        } => {
+            let table = table.unwrap_or(fopts.table.clone());
+            info!("Writing into PostGIS table {table}");
+
+            let mut pg = PostGis::new(&table, input, &url);
+            pg.table(&table)
+                .trajectories(trajectories || fopts.trajectories);
+            Ok(Box::new(pg))
+        }
+        #[cfg(not(feature = "postgis"))]
+        Destination::PostGis { .. } => {
+            Err(Status::UnsupportedDestination("postgres".into()).into())
+        }
+        Destination::Dir {
+            path,
+            split_by: Some(by),
+        } => {
+            info!("Splitting by {by} into {path}");
+
+            let mut split = Split::new(&path, input, by);
+            split.path(&path);
+            Ok(Box::new(split))
+        }
+        Destination::Dir {
+            path,
+            split_by: None,
+        } => Ok(Box::new(Store::new(&path, id)?)),
+        Destination::File {
+            path,
+            write,
+            layout,
+            trajectories,
+        } => {
+            let layout = layout.unwrap_or(fopts.layout);
+            let trajectories = trajectories || fopts.trajectories;
+            let save = save_into(Some(&path), write, layout, trajectories, input, workdir)?;
+            Ok(Box::new(save))
+        }
+        Destination::Stdout => {
+            let save = save_into(None, None, fopts.layout, fopts.trajectories, input, workdir)?;
+            Ok(Box::new(save))
+        }
+    }
+}
+
+/// Last task is `Save` into `fname` or stdout, with the container format deduced from the file
+/// name if not given.
+///
+#[tracing::instrument]
+fn save_into(
+    fname: Option<&str>,
+    write: Option<Container>,
+    layout: Layout,
+    trajectories: bool,
+    input: Format,
+    workdir: Option<&Path>,
+) -> Result<Save> {
     // Are we writing to stdout?
     //
-    let final_output = match &fopts.output {
-        Some(fname) => fname.as_str(),
-        None => "-",
-    };
+    let final_output = fname.unwrap_or("-");
 
-    // Deduce format from file name if specified, otherwise it is raw output to stdout.
+    // Use `--write` or deduce format from file name if specified, otherwise it is raw output
+    // to stdout.
     //
-    let fmt = match &fopts.output {
+    let fmt = match fname {
+        Some(_) if write.is_some() => write.unwrap(),
         Some(fname) => {
             let fname = fname.to_lowercase();
             let ext = Path::new(&fname)
                 .extension()
-                .unwrap()
+                .unwrap_or_default()
                 .to_string_lossy()
                 .to_string();
 
@@ -91,28 +437,21 @@ pub fn fetch_from_site(engine: &mut Engine, fopts: &FetchOpts) -> Result<()> {
         None => Container::default(),
     };
 
-    info!("Writing to {final_output}");
+    if trajectories && fmt != Container::GeoParquet {
+        return Err(Status::NoTrajectories.into());
+    }
+    if layout == Layout::Tracks && fmt != Container::Parquet {
+        return Err(Status::NoTrackLayout.into());
+    }
 
-    // Last task is `Save`
-    //
     let mut save = Save::new(final_output, input, fmt);
-    save.path(final_output);
-    job.add(Box::new(save));
-
-    eprintln!("Fetching {final_output}");
-    let bar = ProgressBar::new_spinner();
-    bar.enable_steady_tick(Duration::from_millis(100));
-
-    // Launch it now
-    //
-    job.run(&mut data)?;
-
-    bar.finish();
-
-    // Remove job from engine and state
-    //
-    trace!("Job({}) done, removing it.", job.id);
-    engine.remove_job(job)
+    save.path(final_output)
+        .trajectories(trajectories)
+        .layout(layout);
+    if let Some(dir) = workdir {
+        save.tmpdir(dir);
+    }
+    Ok(save)
 }
 
 /// From the CLI options
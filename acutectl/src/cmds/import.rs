@@ -0,0 +1,735 @@
+//! Import converted data (CSV or Parquet) directly into a Clickhouse table.
+//!
+//! The Arrow schema of the input file (as read by datafusion) is used to create the table if it
+//! does not exist or to evolve it if it does: new columns are added (as `Nullable`) and integer or
+//! float columns can be widened, anything else is an error.
+//!
+//! Tables are created as `ReplacingMergeTree` ordered by the deduplication keys (`-k`) so
+//! importing the same data twice does not create duplicates once parts are merged.  They can be
+//! partitioned by month or day on a date/time column (`-p`) and `--replace` drops the partitions
+//! present in the data before inserting, making re-imports idempotent.
+//!
+//! Data is sent through the Clickhouse HTTP interface in Parquet batches of `-B` rows.
+//!
+
+use std::path::Path;
+
+use clap::ValueEnum;
+use datafusion::arrow::array::{AsArray, RecordBatch};
+use datafusion::arrow::datatypes::{DataType, Schema, TimeUnit};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::prelude::{
+    CsvReadOptions, DataFrame, ParquetReadOptions, SessionConfig, SessionContext,
+};
+use eyre::Result;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::runtime::Runtime;
+use tracing::{debug, info, trace};
+
+use fetiche_common::Container;
+
+use crate::{ImportOpts, Status};
+
+/// Default Clickhouse HTTP endpoint
+const CLICKHOUSE_URL: &str = "http://localhost:8123";
+/// Default database, same as `process-data`
+const CLICKHOUSE_DB: &str = "acute";
+
+/// Partition granularity
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Granularity {
+    Day,
+    #[default]
+    Month,
+}
+
+impl Granularity {
+    /// Clickhouse function for `PARTITION BY`
+    ///
+    fn function(&self) -> &'static str {
+        match self {
+            Granularity::Day => "toYYYYMMDD",
+            Granularity::Month => "toYYYYMM",
+        }
+    }
+
+    /// `to_char()` format giving the same partition ID in datafusion
+    ///
+    fn format(&self) -> &'static str {
+        match self {
+            Granularity::Day => "%Y%m%d",
+            Granularity::Month => "%Y%m",
+        }
+    }
+}
+
+/// Schema change on an existing table
+///
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// New column with its type
+    Add(String, String),
+    /// Column type widened
+    Modify(String, String),
+}
+
+/// One column as seen in `system.columns`
+///
+#[derive(Debug, Deserialize)]
+struct ChColumn {
+    name: String,
+    #[serde(rename = "type")]
+    ctype: String,
+}
+
+/// Minimal client for the Clickhouse HTTP interface.
+///
+#[derive(Debug)]
+pub struct Clickhouse {
+    /// Endpoint
+    url: String,
+    /// Database
+    database: String,
+    /// Username, if any
+    user: Option<String>,
+    /// Password, if any
+    password: Option<String>,
+    /// HTTP client
+    client: Client,
+}
+
+impl Clickhouse {
+    /// Create a client for the given endpoint and database
+    ///
+    pub fn new(url: &str, database: &str) -> Self {
+        Clickhouse {
+            url: url.to_string(),
+            database: database.to_string(),
+            user: None,
+            password: None,
+            client: Client::new(),
+        }
+    }
+
+    /// Set the credentials
+    ///
+    pub fn login(&mut self, user: &str, password: &str) -> &mut Self {
+        self.user = Some(user.to_string());
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Run `sql` and return the raw answer
+    ///
+    #[tracing::instrument(skip(self))]
+    pub async fn execute(&self, sql: &str) -> Result<String> {
+        self.post(vec![], sql.as_bytes().to_vec()).await
+    }
+
+    /// Insert a Parquet buffer into `table`
+    ///
+    #[tracing::instrument(skip(self, data))]
+    pub async fn insert(&self, table: &str, data: Vec<u8>) -> Result<()> {
+        let query = format!("INSERT INTO {} FORMAT Parquet", quote(table));
+        self.post(vec![("query", query)], data).await?;
+        Ok(())
+    }
+
+    /// Columns of `table`, empty if it does not exist
+    ///
+    #[tracing::instrument(skip(self))]
+    pub async fn columns(&self, table: &str) -> Result<Vec<(String, String)>> {
+        let sql = format!(
+            "SELECT name, type FROM system.columns WHERE database = '{}' AND table = '{}' \
+ORDER BY position FORMAT JSONEachRow",
+            escape(&self.database),
+            escape(table)
+        );
+        let res = self.execute(&sql).await?;
+        res.lines()
+            .map(|l| {
+                let c: ChColumn = serde_json::from_str(l)?;
+                Ok((c.name, c.ctype))
+            })
+            .collect()
+    }
+
+    /// Partition key of `table` as displayed by Clickhouse, e.g. `toYYYYMM(time)`
+    ///
+    #[tracing::instrument(skip(self))]
+    pub async fn partition_key(&self, table: &str) -> Result<String> {
+        let sql = format!(
+            "SELECT partition_key FROM system.tables WHERE database = '{}' AND name = '{}'",
+            escape(&self.database),
+            escape(table)
+        );
+        Ok(self.execute(&sql).await?.trim_end().to_string())
+    }
+
+    /// Send one request, errors from Clickhouse are in the body
+    ///
+    async fn post(&self, mut params: Vec<(&str, String)>, body: Vec<u8>) -> Result<String> {
+        params.push(("database", self.database.clone()));
+
+        let mut req = self.client.post(&self.url).query(&params).body(body);
+        if let Some(user) = &self.user {
+            req = req.header("X-ClickHouse-User", user);
+        }
+        if let Some(password) = &self.password {
+            req = req.header("X-ClickHouse-Key", password);
+        }
+        let resp = req.send().await?;
+        let status = resp.status();
+        let text = resp.text().await?;
+        if !status.is_success() {
+            return Err(Status::Clickhouse(text.trim_end().to_string()).into());
+        }
+        Ok(text)
+    }
+}
+
+/// Handle `import`, connection parameters come from the options or the same environment
+/// variables as the other tools.
+///
+#[tracing::instrument]
+pub fn import_into(iopts: &ImportOpts) -> Result<()> {
+    trace!("import_into");
+
+    let url = iopts
+        .url
+        .clone()
+        .or_else(|| std::env::var("CLICKHOUSE_URL").ok())
+        .unwrap_or(CLICKHOUSE_URL.to_string());
+    let database = iopts
+        .database
+        .clone()
+        .or_else(|| std::env::var("CLICKHOUSE_DB").ok())
+        .unwrap_or(CLICKHOUSE_DB.to_string());
+
+    let mut ch = Clickhouse::new(&url, &database);
+    if let Ok(user) = std::env::var("CLICKHOUSE_USER") {
+        let password = std::env::var("CLICKHOUSE_PASSWD").unwrap_or_default();
+        ch.login(&user, &password);
+    }
+    info!("Importing into {}.{} @ {}", database, iopts.table, url);
+
+    // Create tokio runtime
+    //
+    let rt = Runtime::new()?;
+    rt.block_on(async {
+        for fname in iopts.files.iter() {
+            let rows = import_file(&ch, iopts, fname).await?;
+            eprintln!(
+                "{} rows from {:?} into {}.{}",
+                rows, fname, database, iopts.table
+            );
+        }
+        Ok(())
+    })
+}
+
+/// Import one file, returns the number of rows.
+///
+#[tracing::instrument(skip(ch))]
+async fn import_file(ch: &Clickhouse, iopts: &ImportOpts, fname: &Path) -> Result<usize> {
+    let df = read_file(fname, iopts.from, iopts.batch, b',').await?;
+    let schema = Schema::from(df.schema());
+
+    // Create or evolve the table
+    //
+    let table = &iopts.table;
+    let existing = ch.columns(table).await?;
+    let sql = if existing.is_empty() {
+        vec![create_table(
+            table,
+            &schema,
+            &iopts.keys,
+            iopts.partition.as_deref(),
+            iopts.every,
+        )?]
+    } else {
+        let wanted = schema
+            .fields()
+            .iter()
+            .map(|f| Ok((f.name().to_string(), ch_type(f.name(), f.data_type())?)))
+            .collect::<Result<Vec<_>>>()?;
+        evolve(&existing, &wanted)?
+            .iter()
+            .map(|c| alter_table(table, c))
+            .collect()
+    };
+    for q in sql.iter() {
+        info!("{}", q);
+        if iopts.dry_run {
+            println!("{};", q);
+        } else {
+            ch.execute(q).await?;
+        }
+    }
+    if iopts.dry_run {
+        return Ok(0);
+    }
+
+    // Drop what we are about to import again
+    //
+    if iopts.replace {
+        if let Some(col) = &iopts.partition {
+            let key = format!("{}({})", iopts.every.function(), col);
+            let current = ch.partition_key(table).await?;
+            if current != key {
+                return Err(Status::PartitionMismatch(current, key).into());
+            }
+            for id in partition_ids(&df, col, iopts.every).await? {
+                debug!("dropping partition {}", id);
+                let q = format!("ALTER TABLE {} DROP PARTITION ID '{}'", quote(table), id);
+                ch.execute(&q).await?;
+            }
+        }
+    }
+
+    // Insert by batches
+    //
+    let mut total = 0;
+    let mut stream = df.execute_stream().await?;
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        if batch.num_rows() == 0 {
+            continue;
+        }
+        total += batch.num_rows();
+        trace!("inserting {} rows", batch.num_rows());
+        ch.insert(table, to_parquet(&batch)?).await?;
+    }
+    Ok(total)
+}
+
+/// Open the input file with datafusion, the type comes from `--from` or the extension.  CSV
+/// files have a header line and fields separated by `delimiter`.
+///
+pub(crate) async fn read_file(
+    fname: &Path,
+    from: Option<Container>,
+    batch: usize,
+    delimiter: u8,
+) -> Result<DataFrame> {
+    let ext = fname
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let from = match from {
+        Some(from) => from,
+        None => ext
+            .parse::<Container>()
+            .map_err(|_| Status::UnknownInput(fname.to_string_lossy().to_string()))?,
+    };
+    let ext = format!(".{}", ext);
+    let path = fname.to_string_lossy();
+
+    let ctx = SessionContext::new_with_config(SessionConfig::new().with_batch_size(batch));
+    let df = match from {
+        Container::CSV => {
+            let opts = CsvReadOptions::new()
+                .has_header(true)
+                .delimiter(delimiter)
+                .file_extension(&ext);
+            ctx.read_csv(path.as_ref(), opts).await?
+        }
+        Container::Parquet => {
+            let opts = ParquetReadOptions {
+                file_extension: &ext,
+                ..Default::default()
+            };
+            ctx.read_parquet(path.as_ref(), opts).await?
+        }
+        _ => return Err(Status::UnknownInput(path.to_string()).into()),
+    };
+    Ok(df)
+}
+
+/// Distinct partition IDs in the data, formatted the way Clickhouse does
+///
+async fn partition_ids(df: &DataFrame, col: &str, every: Granularity) -> Result<Vec<String>> {
+    let ctx = SessionContext::new();
+    ctx.register_table("import", df.clone().into_view())?;
+
+    let sql = format!(
+        "SELECT DISTINCT to_char(\"{}\", '{}') FROM import",
+        col,
+        every.format()
+    );
+    let mut ids = vec![];
+    for batch in ctx.sql(&sql).await?.collect().await? {
+        let arr = batch.column(0).as_string::<i32>();
+        ids.extend(arr.iter().flatten().map(|s| s.to_string()));
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// Encode one batch as Parquet
+///
+fn to_parquet(batch: &RecordBatch) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    let mut writer = ArrowWriter::try_new(&mut buf, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(buf)
+}
+
+/// Map an Arrow type into the Clickhouse one (without `Nullable`)
+///
+pub fn ch_type(name: &str, dt: &DataType) -> Result<String> {
+    let t = match dt {
+        DataType::Boolean => "Bool".to_string(),
+        DataType::Int8 => "Int8".to_string(),
+        DataType::Int16 => "Int16".to_string(),
+        DataType::Int32 => "Int32".to_string(),
+        DataType::Int64 => "Int64".to_string(),
+        DataType::UInt8 => "UInt8".to_string(),
+        DataType::UInt16 => "UInt16".to_string(),
+        DataType::UInt32 => "UInt32".to_string(),
+        DataType::UInt64 => "UInt64".to_string(),
+        DataType::Float16 | DataType::Float32 => "Float32".to_string(),
+        DataType::Float64 => "Float64".to_string(),
+        DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Utf8View
+        | DataType::Binary
+        | DataType::LargeBinary => "String".to_string(),
+        DataType::Date32 => "Date32".to_string(),
+        DataType::Date64 => "DateTime64(3, 'UTC')".to_string(),
+        DataType::Timestamp(unit, tz) => {
+            let precision = match unit {
+                TimeUnit::Second => 0,
+                TimeUnit::Millisecond => 3,
+                TimeUnit::Microsecond => 6,
+                TimeUnit::Nanosecond => 9,
+            };
+            format!(
+                "DateTime64({}, '{}')",
+                precision,
+                tz.as_deref().unwrap_or("UTC")
+            )
+        }
+        DataType::Decimal128(p, s) | DataType::Decimal256(p, s) => format!("Decimal({}, {})", p, s),
+        DataType::Dictionary(_, v) => ch_type(name, v)?,
+        _ => return Err(Status::UnsupportedType(name.to_string(), dt.to_string()).into()),
+    };
+    Ok(t)
+}
+
+/// `CREATE TABLE` for the given schema
+///
+pub fn create_table(
+    table: &str,
+    schema: &Schema,
+    keys: &[String],
+    partition: Option<&str>,
+    every: Granularity,
+) -> Result<String> {
+    for k in keys.iter() {
+        if schema.field_with_name(k).is_err() {
+            return Err(Status::UnknownColumn(k.to_string()).into());
+        }
+    }
+
+    let cols = schema
+        .fields()
+        .iter()
+        .map(|f| {
+            let t = ch_type(f.name(), f.data_type())?;
+            // Sorting keys can not be nullable
+            //
+            let t = if f.is_nullable() && !keys.contains(f.name()) {
+                format!("Nullable({})", t)
+            } else {
+                t
+            };
+            Ok(format!("  {} {}", quote(f.name()), t))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .join(",\n");
+
+    let mut sql = format!("CREATE TABLE IF NOT EXISTS {} (\n{}\n)", quote(table), cols);
+    if keys.is_empty() {
+        sql.push_str("\nENGINE = MergeTree");
+    } else {
+        sql.push_str("\nENGINE = ReplacingMergeTree");
+    }
+    if let Some(col) = partition {
+        let field = schema
+            .field_with_name(col)
+            .map_err(|_| Status::UnknownColumn(col.to_string()))?;
+        match field.data_type() {
+            DataType::Date32 | DataType::Date64 | DataType::Timestamp(_, _) => (),
+            _ => return Err(Status::BadPartition(col.to_string()).into()),
+        }
+        sql.push_str(&format!(
+            "\nPARTITION BY {}({})",
+            every.function(),
+            quote(col)
+        ));
+    }
+    if keys.is_empty() {
+        sql.push_str("\nORDER BY tuple()");
+    } else {
+        let keys = keys.iter().map(|k| quote(k)).collect::<Vec<_>>().join(", ");
+        sql.push_str(&format!("\nORDER BY ({})", keys));
+    }
+    Ok(sql)
+}
+
+/// Changes needed for `existing` to accept `wanted`, both being lists of (name, type).
+///
+pub fn evolve(existing: &[(String, String)], wanted: &[(String, String)]) -> Result<Vec<Change>> {
+    let mut changes = vec![];
+    for (name, new) in wanted.iter() {
+        match existing.iter().find(|(n, _)| n == name) {
+            None => changes.push(Change::Add(name.clone(), format!("Nullable({})", new))),
+            Some((_, old)) => {
+                let (base, nullable) = match old.strip_prefix("Nullable(") {
+                    Some(t) => (t.trim_end_matches(')'), true),
+                    None => (old.as_str(), false),
+                };
+                if base == new {
+                    continue;
+                }
+                match wider(base, new) {
+                    Some(true) => {
+                        let t = if nullable {
+                            format!("Nullable({})", new)
+                        } else {
+                            new.clone()
+                        };
+                        changes.push(Change::Modify(name.clone(), t))
+                    }
+                    Some(false) => (),
+                    None => {
+                        return Err(
+                            Status::SchemaMismatch(name.clone(), old.clone(), new.clone()).into(),
+                        )
+                    }
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// `ALTER TABLE` for one change
+///
+fn alter_table(table: &str, change: &Change) -> String {
+    match change {
+        Change::Add(name, t) => format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+            quote(table),
+            quote(name),
+            t
+        ),
+        Change::Modify(name, t) => format!(
+            "ALTER TABLE {} MODIFY COLUMN {} {}",
+            quote(table),
+            quote(name),
+            t
+        ),
+    }
+}
+
+/// Is `new` wider than `old`?  `None` if they can not be compared.
+///
+fn wider(old: &str, new: &str) -> Option<bool> {
+    const CHAINS: [&[&str]; 3] = [
+        &["Int8", "Int16", "Int32", "Int64"],
+        &["UInt8", "UInt16", "UInt32", "UInt64"],
+        &["Float32", "Float64"],
+    ];
+
+    CHAINS.iter().find_map(|chain| {
+        let o = chain.iter().position(|t| *t == old)?;
+        let n = chain.iter().position(|t| *t == new)?;
+        Some(n > o)
+    })
+}
+
+/// Quote an identifier
+///
+fn quote(s: &str) -> String {
+    format!("`{}`", s.replace('`', "\\`"))
+}
+
+/// Escape a string literal
+///
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::datatypes::Field;
+    use httpmock::prelude::*;
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(DataType::Int32, "Int32")]
+    #[case(DataType::Float16, "Float32")]
+    #[case(DataType::Utf8, "String")]
+    #[case(
+        DataType::Timestamp(TimeUnit::Millisecond, None),
+        "DateTime64(3, 'UTC')"
+    )]
+    #[case(DataType::Timestamp(TimeUnit::Second, Some("Europe/Paris".into())), "DateTime64(0, 'Europe/Paris')")]
+    #[case(
+        DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8)),
+        "String"
+    )]
+    fn test_ch_type(#[case] dt: DataType, #[case] res: &str) {
+        assert_eq!(res, ch_type("col", &dt).unwrap());
+    }
+
+    #[test]
+    fn test_ch_type_unsupported() {
+        assert!(ch_type("col", &DataType::Null).is_err());
+    }
+
+    fn schema() -> Schema {
+        Schema::new(vec![
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("icao24", DataType::Utf8, true),
+            Field::new("alt", DataType::Float64, true),
+        ])
+    }
+
+    #[test]
+    fn test_create_table() {
+        let keys = vec!["icao24".to_string(), "time".to_string()];
+        let sql = create_table("adsb", &schema(), &keys, Some("time"), Granularity::Month).unwrap();
+        let res = r##"CREATE TABLE IF NOT EXISTS `adsb` (
+  `time` DateTime64(3, 'UTC'),
+  `icao24` String,
+  `alt` Nullable(Float64)
+)
+ENGINE = ReplacingMergeTree
+PARTITION BY toYYYYMM(`time`)
+ORDER BY (`icao24`, `time`)"##;
+        assert_eq!(res, sql);
+    }
+
+    #[test]
+    fn test_create_table_errors() {
+        let keys = vec!["nope".to_string()];
+        assert!(create_table("adsb", &schema(), &keys, None, Granularity::Day).is_err());
+        assert!(create_table("adsb", &schema(), &[], Some("alt"), Granularity::Day).is_err());
+
+        let sql = create_table("adsb", &schema(), &[], None, Granularity::Day).unwrap();
+        assert!(sql.ends_with("ENGINE = MergeTree\nORDER BY tuple()"));
+    }
+
+    #[test]
+    fn test_evolve() {
+        let pair = |n: &str, t: &str| (n.to_string(), t.to_string());
+
+        let existing = vec![
+            pair("time", "DateTime64(3, 'UTC')"),
+            pair("alt", "Nullable(Float32)"),
+            pair("squawk", "Int32"),
+        ];
+        let wanted = vec![
+            pair("time", "DateTime64(3, 'UTC')"),
+            pair("alt", "Float64"),
+            pair("squawk", "Int16"),
+            pair("callsign", "String"),
+        ];
+        let res = evolve(&existing, &wanted).unwrap();
+        assert_eq!(
+            vec![
+                Change::Modify("alt".to_string(), "Nullable(Float64)".to_string()),
+                Change::Add("callsign".to_string(), "Nullable(String)".to_string()),
+            ],
+            res
+        );
+
+        let wanted = vec![pair("squawk", "String")];
+        assert!(evolve(&existing, &wanted).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_partition_ids() -> Result<()> {
+        use std::sync::Arc;
+
+        use datafusion::arrow::array::TimestampMillisecondArray;
+
+        // 2024-10-09 23:00, 2024-10-10 01:00, 2024-11-01 00:00
+        //
+        let time = TimestampMillisecondArray::from(vec![
+            1_728_514_800_000,
+            1_728_522_000_000,
+            1_730_419_200_000,
+        ]);
+        let schema = Schema::new(vec![Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            false,
+        )]);
+        let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(time)])?;
+        let df = SessionContext::new().read_batch(batch)?;
+
+        let ids = partition_ids(&df, "time", Granularity::Month).await?;
+        assert_eq!(vec!["202410", "202411"], ids);
+        let ids = partition_ids(&df, "time", Granularity::Day).await?;
+        assert_eq!(vec!["20241009", "20241010", "20241101"], ids);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clickhouse_columns() -> Result<()> {
+        let server = MockServer::start_async().await;
+        let m = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/")
+                    .query_param("database", "acute")
+                    .header("X-ClickHouse-User", "default");
+                then.status(200).body(
+                    "{\"name\":\"time\",\"type\":\"DateTime64(3, 'UTC')\"}\n\
+{\"name\":\"alt\",\"type\":\"Nullable(Float64)\"}\n",
+                );
+            })
+            .await;
+
+        let mut ch = Clickhouse::new(&server.base_url(), "acute");
+        ch.login("default", "");
+        let cols = ch.columns("adsb").await?;
+        m.assert_async().await;
+        assert_eq!(2, cols.len());
+        assert_eq!(
+            ("alt".to_string(), "Nullable(Float64)".to_string()),
+            cols[1]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_clickhouse_error() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(POST).path("/");
+                then.status(404)
+                    .body("Code: 60. DB::Exception: Table acute.nope does not exist.\n");
+            })
+            .await;
+
+        let ch = Clickhouse::new(&server.base_url(), "acute");
+        let err = ch.execute("SELECT 1 FROM nope").await.unwrap_err();
+        assert!(err.to_string().contains("Code: 60"));
+    }
+}
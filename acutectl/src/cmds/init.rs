@@ -0,0 +1,146 @@
+//! This is the module handling the `config init` sub-command.
+//!
+//! Base directory, working directory and sites are asked for unless given on the command line,
+//! `--yes` is given or we are not talking to a terminal.  `acutectl.hcl` is written as well if
+//! missing.
+//!
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+use eyre::Result;
+use tracing::trace;
+
+use fetiche_common::config_dir;
+use fetiche_engine::Init;
+use fetiche_sources::{Sources, PLACEHOLDER};
+
+use crate::InitOpts;
+
+/// Our own configuration file
+const CONFIG: &str = "acutectl.hcl";
+
+/// Create a new configuration and tell what to do next
+///
+#[tracing::instrument]
+pub fn init_config(iopts: &InitOpts) -> Result<()> {
+    trace!("init_config");
+
+    let dir = config_dir();
+    let mut init = Init::new(&dir);
+    init.force(iopts.force);
+
+    if let Some(basedir) = &iopts.basedir {
+        init.basedir(basedir);
+    }
+    if let Some(workdir) = &iopts.workdir {
+        init.workdir(workdir);
+    }
+    init.sites(&iopts.sites);
+
+    if !iopts.yes && io::stdin().is_terminal() {
+        let mut input = io::stdin().lock();
+
+        eprintln!("Creating a new configuration in {}\n", dir.display());
+        if iopts.basedir.is_none() {
+            let def = init.basedir.to_string_lossy().to_string();
+            let basedir = ask(&mut input, "Base directory for data and tokens", &def)?;
+            init.basedir(&PathBuf::from(basedir));
+        }
+        if iopts.workdir.is_none() {
+            let def = init.basedir.join("work").to_string_lossy().to_string();
+            let workdir = ask(&mut input, "Working directory for jobs", &def)?;
+            if workdir != def {
+                init.workdir(&PathBuf::from(workdir));
+            }
+        }
+        if iopts.sites.is_empty() {
+            eprintln!("Available sites: {}", Sources::defaults()?.join(", "));
+            let sites = ask(&mut input, "Sites to configure, comma-separated", "all")?;
+            init.sites(&parse_sites(&sites));
+        }
+        eprintln!();
+    }
+
+    let mut report = init.run()?;
+
+    let fname = dir.join(CONFIG);
+    if iopts.force || !fname.exists() {
+        fs::write(&fname, include_str!("../acutectl.hcl"))?;
+        report.files.push(fname);
+    }
+
+    report
+        .dirs
+        .iter()
+        .for_each(|d| eprintln!("Created {}", d.display()));
+    report
+        .files
+        .iter()
+        .for_each(|f| eprintln!("Wrote {}", f.display()));
+
+    eprintln!("\nNext steps:");
+    if !report.credentials.is_empty() {
+        eprintln!(
+            "- replace {PLACEHOLDER} in {} for {}",
+            dir.join("sources.hcl").display(),
+            report.credentials.join(", ")
+        );
+    }
+    eprintln!("- check the sites with `acutectl list sources`");
+    if init.sites.is_empty() || init.sites.iter().any(|s| s == "simulator") {
+        eprintln!("- try it without any credentials with `acutectl fetch -o test.csv simulator`");
+    }
+    Ok(())
+}
+
+/// Ask a question, an empty answer means the default
+///
+fn ask<R: BufRead>(input: &mut R, question: &str, default: &str) -> Result<String> {
+    eprint!("{question} [{default}]: ");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(match answer.trim() {
+        "" => default.to_string(),
+        s => s.to_string(),
+    })
+}
+
+/// Comma-separated list, "all" means all of them
+///
+fn parse_sites(s: &str) -> Vec<String> {
+    match s.trim() {
+        "" | "all" => vec![],
+        s => s
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn test_ask() -> Result<()> {
+        let mut input = "\n/data \n".as_bytes();
+        assert_eq!("/var/db", ask(&mut input, "Base", "/var/db")?);
+        assert_eq!("/data", ask(&mut input, "Base", "/var/db")?);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("all", vec![])]
+    #[case("", vec![])]
+    #[case("asd, opensky,", vec!["asd", "opensky"])]
+    fn test_parse_sites(#[case] s: &str, #[case] res: Vec<&str>) {
+        assert_eq!(res, parse_sites(s));
+    }
+}
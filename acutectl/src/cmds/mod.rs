@@ -1,7 +1,31 @@
+pub use bundle::*;
 pub use convert::*;
+pub use destination::*;
+pub use diff::*;
 pub use fetch::*;
+pub use import::*;
+pub use init::*;
+pub use raw::*;
+pub use replay::*;
+pub use restart::*;
+pub use selftest::*;
 pub use stream::*;
+pub use submit::*;
+pub use top::*;
+pub use verify::*;
 
+mod bundle;
 mod convert;
+mod destination;
+mod diff;
 mod fetch;
+mod import;
+mod init;
+mod raw;
+mod replay;
+mod restart;
+mod selftest;
 mod stream;
+mod submit;
+mod top;
+mod verify;
@@ -0,0 +1,134 @@
+//! This is the module handling the `raw` sub-command.
+//!
+//! Payloads from a site are captured verbatim, with their reception time and length, into files
+//! rotated on size or age (see `RawDump` in the engine).  Fetchable sites are called once,
+//! Streamable ones are streamed like with `stream`, restarted on failure if asked to.
+//!
+
+use std::io::sink;
+use std::thread;
+use std::time::Instant;
+
+use eyre::Result;
+use tracing::{info, trace, warn};
+
+use fetiche_engine::{Engine, Fetch, Job, RawDump, Stream};
+use fetiche_sources::{Filter, Flow, Site};
+
+use crate::{Backoff, Checkpoint, RawOpts, Restart, Uptime};
+
+/// Capture everything the site sends into `ropts.output`.
+///
+#[tracing::instrument(skip(engine))]
+pub fn raw_from_site(engine: &mut Engine, ropts: &RawOpts) -> Result<()> {
+    trace!("raw_from_site({:?})", ropts.site);
+
+    let site = Site::load(&ropts.site, &engine.sources())?;
+    let mut filter = filter_from_opts(ropts, &site);
+
+    info!(
+        "Capturing raw data from {} into {}",
+        ropts.site, ropts.output
+    );
+    eprintln!("Capturing from {} into {}", site.name(), ropts.output);
+
+    // Nothing goes out of the job, everything is in the capture files
+    //
+    let mut out = Checkpoint::new(sink());
+
+    let mut backoff = Backoff::new();
+    let mut uptime = Uptime::default();
+    let res = loop {
+        let job = raw_job(engine, ropts, &site, &filter);
+
+        let start = Instant::now();
+        let res = engine.run_job(job, &mut out);
+        let up = start.elapsed();
+        uptime.up += up;
+
+        let Err(e) = res else {
+            break Ok(());
+        };
+        if !matches!(site, Flow::Streamable(_)) || !ropts.restart.allows(uptime.restarts) {
+            break Err(e);
+        }
+        let Some(next) = out.resume(&filter, uptime.up) else {
+            break Err(e);
+        };
+
+        let wait = backoff.next(up);
+        warn!("Capture failed: {}, restarting in {:?}", e, wait);
+        eprintln!("Capture failed: {e}, restarting in {}s", wait.as_secs());
+
+        let down = Instant::now();
+        thread::sleep(wait);
+        filter = next;
+        uptime.restarts += 1;
+        uptime.down += down.elapsed();
+    };
+
+    if ropts.restart != Restart::No {
+        info!("Capture ended: {}", uptime);
+        eprintln!("Capture ended: {uptime}");
+    }
+    res
+}
+
+/// Build the job, `Fetch` or `Stream` depending on the site then `RawDump`.
+///
+#[tracing::instrument(skip(engine))]
+fn raw_job(engine: &mut Engine, ropts: &RawOpts, site: &Flow, filter: &Filter) -> Job {
+    let srcs = engine.sources().clone();
+
+    let mut job = engine.create_job("raw_from_site");
+    job.source = Some(site.name());
+    match site {
+        Flow::Streamable(_) => {
+            let mut task = Stream::new(&ropts.site, srcs);
+            task.site(site.name())
+                .with(filter.clone())
+                .space(engine.space())
+                .tokens(engine.refresher());
+            job.stream = true;
+            job.add(Box::new(task));
+        }
+        _ => {
+            let mut task = Fetch::new(&ropts.site, srcs);
+            task.site(site.name())
+                .with(filter.clone())
+                .tokens(engine.refresher());
+            job.add(Box::new(task));
+        }
+    }
+
+    let mut dump = RawDump::new(&ropts.site);
+    dump.path(&ropts.output)
+        .max_size(ropts.max_size * 1024 * 1024)
+        .max_age(ropts.max_age);
+    job.add(Box::new(dump));
+
+    info!("Running job #{} with {} tasks.", job.id, job.list.len());
+    job
+}
+
+/// From the CLI options, depending on the kind of site.
+///
+#[tracing::instrument]
+fn filter_from_opts(opts: &RawOpts, site: &Flow) -> Filter {
+    trace!("filter_from_opts");
+
+    if let Some(keyword) = &opts.keyword {
+        let (name, value) = keyword.split_once(':').unwrap_or((keyword, ""));
+        return Filter::Keyword {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+    }
+    match site {
+        Flow::Streamable(_) => Filter::stream(opts.start.unwrap_or(0), opts.duration, opts.delay),
+        _ => match opts.since {
+            Some(d) => Filter::Duration(d),
+            None => Filter::default(),
+        },
+    }
+}
@@ -0,0 +1,135 @@
+//! This is the module handling the `replay` sub-command.
+//!
+//! Payloads captured by `raw` or `--raw-copy` are sent again, with their original timing sped up
+//! or not (see `Replay` in the engine), through the same stages as `stream`: conversion,
+//! categories, tracks, QC, GeoJSON and the destination or TCP clients.  Nothing is fetched, runs
+//! are reproducible which is what we want for end-to-end tests and demos of live features.
+//!
+
+use std::fs::File;
+use std::io::{stdout, Write};
+
+use eyre::Result;
+use tracing::{info, trace};
+
+use fetiche_engine::{Convert, Engine, GeoJson, Job, Qc, Replay, Select, Serve, Store, Track};
+use fetiche_formats::Format;
+
+use crate::{Destination, ReplayOpts, Status};
+
+/// Send the capture in `ropts.capture` through the pipeline.
+///
+#[tracing::instrument(skip(engine))]
+pub fn replay_capture(engine: &mut Engine, ropts: &ReplayOpts) -> Result<()> {
+    trace!("replay_capture({:?})", ropts.capture);
+
+    let dest = destination(ropts)?;
+    info!("Replaying {} at {} into {dest}", ropts.capture, ropts.speed);
+    eprintln!("Replaying {} at {}", ropts.capture, ropts.speed);
+
+    let mut out: Box<dyn Write> = match &dest {
+        Destination::File { path, .. } => Box::new(File::create(path)?),
+        _ => Box::new(stdout()),
+    };
+    let serve = match &ropts.serve {
+        Some(addr) if ropts.sse => Some(Serve::sse(addr)?),
+        Some(addr) => Some(Serve::new(addr)?),
+        None => None,
+    };
+
+    let job = replay_job(engine, ropts, &dest, serve.as_ref())?;
+    engine.run_job(job, &mut out)
+}
+
+/// Build the job, `Replay` then the same stages as `stream_job()`.
+///
+#[tracing::instrument(skip(engine))]
+fn replay_job(
+    engine: &mut Engine,
+    ropts: &ReplayOpts,
+    dest: &Destination,
+    serve: Option<&Serve>,
+) -> Result<Job> {
+    let mut task = Replay::new(&ropts.capture);
+    task.path(&ropts.capture).speed(ropts.speed);
+
+    let mut job = engine.create_job("replay_capture");
+    job.stream = true;
+    job.add(Box::new(task));
+
+    // If a conversion or a redaction is requested, insert it
+    //
+    let redact = match &ropts.redact {
+        Some(policy) => Some(engine.redaction(policy)?),
+        None => None,
+    };
+    if ropts.into != Format::None || redact.is_some() {
+        let mut convert = Convert::new();
+        convert.from(ropts.r#as).into(ropts.into);
+        if let Some(redact) = redact {
+            convert.redact(redact);
+        }
+        job.add(Box::new(convert));
+    }
+
+    let input = match ropts.into {
+        Format::None => ropts.r#as,
+        into => into,
+    };
+
+    if !ropts.category.is_empty() {
+        let mut select = Select::new("select", input);
+        select.categories(&ropts.category);
+        job.add(Box::new(select));
+    }
+    if ropts.tracks {
+        job.add(Box::new(Track::new("tracks", input)));
+    }
+    if let Some(summary) = &ropts.qc {
+        let mut qc = Qc::new(summary, input);
+        qc.path(summary).drop(ropts.qc_drop);
+        job.add(Box::new(qc));
+    }
+    if ropts.geojson {
+        job.add(Box::new(GeoJson::new("geojson", input)));
+    }
+
+    if let Destination::Dir { path: basedir, .. } = dest {
+        let store = Store::new(basedir, job.id)?;
+        job.add(Box::new(store));
+    }
+    if let Some(serve) = serve {
+        info!("Serving on {}", serve.local_addr());
+        job.add(Box::new(serve.clone()));
+    }
+    info!("Running job #{} with {} tasks.", job.id, job.list.len());
+    Ok(job)
+}
+
+/// Same destinations as `stream`: a file, stdout or hourly files in a directory.
+///
+#[tracing::instrument]
+fn destination(ropts: &ReplayOpts) -> Result<Destination> {
+    let Some(to) = &ropts.to else {
+        return Ok(match &ropts.output {
+            Some(out) => Destination::File {
+                path: out.clone(),
+                write: None,
+                layout: None,
+                trajectories: false,
+            },
+            None => Destination::Stdout,
+        });
+    };
+    match to {
+        Destination::File {
+            write: None,
+            layout: None,
+            trajectories: false,
+            ..
+        }
+        | Destination::Dir { split_by: None, .. }
+        | Destination::Stdout => Ok(to.clone()),
+        _ => Err(Status::BadDestination(to.to_string(), "not for replay".into()).into()),
+    }
+}
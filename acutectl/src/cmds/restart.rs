@@ -0,0 +1,238 @@
+//! Restarting a failed stream from the CLI side.
+//!
+//! With `acutectl stream --restart on-failure[:max]` the stream job is submitted again when it
+//! fails, at most `max` times if given.  We wait longer after each failure (1s, 2s, 4s… up to
+//! 5 min), a run lasting longer than the longest wait is considered healthy and resets it.
+//!
+//! Everything going to the output passes through a `Checkpoint` recording when data was last
+//! received, the next run resumes the stream from there and with what remains of its duration.
+//! Cumulative uptime and downtime are reported at the end.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use fetiche_sources::Filter;
+
+use crate::Status;
+
+/// First wait after a failure
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+/// Longest wait
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// Restart policy
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Restart {
+    /// Failure ends the stream
+    #[default]
+    No,
+    /// Restart on failure, at most N times if specified
+    OnFailure(Option<usize>),
+}
+
+impl Restart {
+    /// Can we restart again after `count` restarts?
+    ///
+    pub fn allows(&self, count: usize) -> bool {
+        match self {
+            Restart::No => false,
+            Restart::OnFailure(None) => true,
+            Restart::OnFailure(Some(max)) => count < *max,
+        }
+    }
+}
+
+impl FromStr for Restart {
+    type Err = Status;
+
+    /// `no`, `on-failure` or `on-failure:N`
+    ///
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "no" => Ok(Restart::No),
+            None if s == "on-failure" => Ok(Restart::OnFailure(None)),
+            Some(("on-failure", max)) => match max.parse::<usize>() {
+                Ok(max) if max > 0 => Ok(Restart::OnFailure(Some(max))),
+                _ => Err(Status::BadRestart(s.to_string())),
+            },
+            _ => Err(Status::BadRestart(s.to_string())),
+        }
+    }
+}
+
+/// Exponential backoff between restarts
+///
+#[derive(Debug)]
+pub struct Backoff {
+    wait: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Backoff { wait: BACKOFF_MIN }
+    }
+
+    /// How long to wait after a run which lasted `up`
+    ///
+    pub fn next(&mut self, up: Duration) -> Duration {
+        if up >= BACKOFF_MAX {
+            self.wait = BACKOFF_MIN;
+        }
+        let wait = self.wait;
+        self.wait = (wait * 2).min(BACKOFF_MAX);
+        wait
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wrap the output to record when data was last received, kept across restarts.
+///
+pub struct Checkpoint<W: Write> {
+    inner: W,
+    /// Timestamp of the last data written
+    pub last: Option<i64>,
+}
+
+impl<W: Write> Checkpoint<W> {
+    pub fn new(inner: W) -> Self {
+        Checkpoint { inner, last: None }
+    }
+
+    /// Resume `filter` from the checkpoint with what remains of its duration after `elapsed`.
+    /// Returns `None` if there is nothing left to stream.
+    ///
+    pub fn resume(&self, filter: &Filter, elapsed: Duration) -> Option<Filter> {
+        match filter {
+            Filter::Stream {
+                from,
+                duration,
+                delay,
+            } => {
+                let duration = if *duration == 0 {
+                    0
+                } else {
+                    match duration.saturating_sub(elapsed.as_secs() as u32) {
+                        0 => return None,
+                        left => left,
+                    }
+                };
+                Some(Filter::stream(self.last.unwrap_or(*from), duration, *delay))
+            }
+            _ => Some(filter.clone()),
+        }
+    }
+}
+
+impl<W: Write> Write for Checkpoint<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.last = Some(Utc::now().timestamp());
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Cumulative time spent streaming or waiting to restart
+///
+#[derive(Debug, Default)]
+pub struct Uptime {
+    /// Time spent running
+    pub up: Duration,
+    /// Time between a failure and the next run
+    pub down: Duration,
+    /// Number of restarts
+    pub restarts: usize,
+}
+
+impl Display for Uptime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "uptime {}s, downtime {}s, {} restart(s)",
+            self.up.as_secs(),
+            self.down.as_secs(),
+            self.restarts
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("no", Some(Restart::No))]
+    #[case("on-failure", Some(Restart::OnFailure(None)))]
+    #[case("on-failure:3", Some(Restart::OnFailure(Some(3))))]
+    #[case("on-failure:0", None)]
+    #[case("on-failure:", None)]
+    #[case("always", None)]
+    fn test_restart_from_str(#[case] s: &str, #[case] res: Option<Restart>) {
+        assert_eq!(res, Restart::from_str(s).ok());
+    }
+
+    #[test]
+    fn test_restart_allows() {
+        assert!(!Restart::No.allows(0));
+        assert!(Restart::OnFailure(None).allows(1000));
+        assert!(Restart::OnFailure(Some(2)).allows(1));
+        assert!(!Restart::OnFailure(Some(2)).allows(2));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut b = Backoff::new();
+        let short = Duration::from_secs(2);
+
+        let all: Vec<_> = (0..10).map(|_| b.next(short).as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 8, 16, 32, 64, 128, 256, 300], all);
+
+        // A healthy run resets it
+        //
+        assert_eq!(BACKOFF_MIN, b.next(BACKOFF_MAX));
+        assert_eq!(2, b.next(short).as_secs());
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let mut out = Checkpoint::new(vec![]);
+        let filter = Filter::stream(1_700_000_000, 60, 1000);
+
+        // Nothing received yet, same start
+        //
+        let res = out.resume(&filter, Duration::from_secs(20));
+        assert_eq!(Some(Filter::stream(1_700_000_000, 40, 1000)), res);
+
+        write!(out, "data").unwrap();
+        let last = out.last.unwrap();
+        let res = out.resume(&filter, Duration::from_secs(20));
+        assert_eq!(Some(Filter::stream(last, 40, 1000)), res);
+
+        // Time is up
+        //
+        assert_eq!(None, out.resume(&filter, Duration::from_secs(60)));
+
+        // No limit
+        //
+        let res = out.resume(&Filter::stream(0, 0, 1000), Duration::from_secs(3600));
+        assert_eq!(Some(Filter::stream(last, 0, 1000)), res);
+    }
+}
@@ -0,0 +1,74 @@
+//! This is the module handling the `selftest` sub-command.
+//!
+//! `selftest formats` runs every converter on the samples of its fixtures (see `Fixture` in
+//! `fetiche-formats`) and compares the result with the expected output, line by line.  The
+//! fixtures built into `acutectl` are used unless `--fixtures` gives a directory, in which case
+//! `--bless` writes the current output as the expected one (for new samples or a deliberate
+//! change of a converter).
+//!
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::channel;
+
+use eyre::Result;
+use tracing::{info, trace};
+
+use fetiche_common::{Listing, OutputFormat};
+use fetiche_engine::Convert;
+use fetiche_formats::Fixture;
+
+use crate::Status;
+
+/// Check every fixture, or bless them.
+///
+#[tracing::instrument]
+pub fn selftest_formats(fixtures: Option<&Path>, bless: bool, fmt: OutputFormat) -> Result<()> {
+    trace!("selftest_formats");
+
+    let all = match fixtures {
+        Some(dir) => Fixture::load(dir)?,
+        None => Fixture::builtin(),
+    };
+    info!("{} fixtures", all.len());
+
+    let mut list = Listing::new(
+        "Format fixtures",
+        &[("Name", "name"), ("Result", "result"), ("Detail", "detail")],
+    );
+    let mut failed = 0;
+    for f in &all {
+        let (result, detail) = match (convert(f), fixtures) {
+            (Ok(got), Some(dir)) if bless => {
+                fs::write(f.expected_path(dir), &got)?;
+                ("blessed", String::new())
+            }
+            (Ok(got), _) => match f.check(&got) {
+                None => ("ok", String::new()),
+                Some(m) => ("mismatch", m.to_string()),
+            },
+            (Err(e), _) => ("error", e.to_string()),
+        };
+        if result == "mismatch" || result == "error" {
+            failed += 1;
+        }
+        list.push(vec![f.name.clone(), result.to_string(), detail]);
+    }
+    println!("{}", list.render(fmt)?);
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(Status::SelfTestFailed(failed, all.len()).into()),
+    }
+}
+
+/// Run the converter of the fixture on its sample, like `convert` would.
+///
+fn convert(f: &Fixture) -> Result<String> {
+    let (tx, rx) = channel();
+    Convert::new()
+        .from(f.from)
+        .into(f.into)
+        .execute(f.input.clone(), tx)?;
+    Ok(rx.try_iter().collect::<Vec<_>>().concat())
+}
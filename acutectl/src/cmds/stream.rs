@@ -1,15 +1,28 @@
+//! This is the module handling the `stream` sub-command.
+//!
+//! With `--dry-run`, the job is only described (see `Plan` in `fetiche-engine`): the site is
+//! not contacted, nothing is listened on and nothing is written.
+//!
+
 use std::fs::File;
-use std::io::stdout;
+use std::io::{stdout, Write};
+use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
 
 use eyre::{eyre, Result};
-use fetiche_engine::{Convert, Engine, Store, Stream, Tee};
+use fetiche_common::Redaction;
+use fetiche_engine::{
+    Convert, Engine, FlightPlans, GeoJson, Job, Label, Plan, Qc, RawCopy, Select, Serve, Store,
+    Stream, Tee, Track,
+};
 use fetiche_formats::Format;
 use fetiche_sources::{Filter, Flow, Site};
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 
-use crate::{Status, StreamOpts};
+use crate::{Backoff, Checkpoint, Destination, Restart, Status, StreamOpts, Uptime};
 
-/// Actual fetching of data from a given site
+/// Actual fetching of data from a given site, restarted on failure if asked to.
 ///
 #[tracing::instrument]
 pub fn stream_from_site(engine: &mut Engine, sopts: &StreamOpts) -> Result<()> {
@@ -18,7 +31,6 @@ pub fn stream_from_site(engine: &mut Engine, sopts: &StreamOpts) -> Result<()> {
     check_args(sopts)?;
 
     let name = &sopts.site;
-    let srcs = engine.sources().clone();
     let site = Site::load(name, &engine.sources())?;
     match site {
         Flow::Streamable(ref s) => s,
@@ -28,19 +40,120 @@ pub fn stream_from_site(engine: &mut Engine, sopts: &StreamOpts) -> Result<()> {
         }
     };
 
-    let filter = filter_from_opts(sopts)?;
+    let mut filter = filter_from_opts(sopts)?;
+    let redact = match &sopts.redact {
+        Some(policy) => Some(engine.redaction(policy)?),
+        None => None,
+    };
+    if sopts.dry_run {
+        print!("{}", plan_from_opts(engine, sopts, filter)?);
+        return Ok(());
+    }
     info!("Streaming from network site {}", name);
 
+    // Handle output if no consumer is present at the end, it is kept across restarts.
+    //
+    let dest = destination(sopts)?;
+    info!("Writing to {dest}");
+    let out: Box<dyn Write> = match &dest {
+        Destination::File { path, .. } => Box::new(File::create(path)?),
+        _ => Box::new(stdout()),
+    };
+    let mut out = Checkpoint::new(out);
+
+    // Listen once, clients stay connected across restarts
+    //
+    let serve = match &sopts.serve {
+        Some(addr) if sopts.sse => Some(Serve::sse(addr)?),
+        Some(addr) => Some(Serve::new(addr)?),
+        None => None,
+    };
+
+    let mut backoff = Backoff::new();
+    let mut uptime = Uptime::default();
+    let res = loop {
+        let job = stream_job(
+            engine,
+            sopts,
+            &site,
+            &filter,
+            redact.clone(),
+            &dest,
+            serve.as_ref(),
+        )?;
+
+        let start = Instant::now();
+        let res = engine.run_job(job, &mut out);
+        let up = start.elapsed();
+        uptime.up += up;
+
+        let Err(e) = res else {
+            break Ok(());
+        };
+        if !sopts.restart.allows(uptime.restarts) {
+            break Err(e);
+        }
+        let Some(next) = out.resume(&filter, uptime.up) else {
+            break Err(e);
+        };
+
+        let wait = backoff.next(up);
+        warn!("Stream failed: {}, restarting in {:?}", e, wait);
+        eprintln!("Stream failed: {e}, restarting in {}s", wait.as_secs());
+
+        let down = Instant::now();
+        thread::sleep(wait);
+        filter = next;
+        uptime.restarts += 1;
+        uptime.down += down.elapsed();
+    };
+
+    if sopts.restart != Restart::No {
+        info!("Stream ended: {}", uptime);
+        eprintln!("Stream ended: {uptime}");
+    }
+    res
+}
+
+/// Build the stream job, this is done again for every restart.
+///
+#[tracing::instrument(skip(engine))]
+fn stream_job(
+    engine: &mut Engine,
+    sopts: &StreamOpts,
+    site: &Flow,
+    filter: &Filter,
+    redact: Option<Redaction>,
+    dest: &Destination,
+    serve: Option<&Serve>,
+) -> Result<Job> {
+    let srcs = engine.sources().clone();
+
     // Full json array with all point
     //
-    let mut task = Stream::new(name, srcs);
-    task.site(site.name()).with(filter);
+    let mut task = Stream::new(&sopts.site, srcs);
+    task.site(site.name())
+        .with(filter.clone())
+        .space(engine.space())
+        .tokens(engine.refresher());
 
     // Create job with first task
     //
     let mut job = engine.create_job("stream_from_site");
+    job.stream = true;
+    job.source = Some(site.name());
     job.add(Box::new(task));
 
+    // Keep every chunk as received, one file each, to be able to reprocess them later
+    //
+    if let Some(dir) = &sopts.raw_copy {
+        info!("Raw copy into {dir}");
+
+        let mut raw = RawCopy::new(dir, site.format());
+        raw.path(dir);
+        job.add(Box::new(raw));
+    }
+
     // Do we want a copy of the raw data (often before converting it)
     //
     if let Some(tee) = &sopts.tee {
@@ -48,43 +161,233 @@ pub fn stream_from_site(engine: &mut Engine, sopts: &StreamOpts) -> Result<()> {
         job.add(Box::new(copy));
     }
 
-    // If a conversion is requested, insert it
+    // If a conversion, a redaction or provenance is requested, insert it
     //
-    // FIXME: DEPRECATED
-    //
-    if let Some(_into) = &sopts.into {
+    let into = conversion(sopts.into.as_deref());
+    if into != Format::None || redact.is_some() || sopts.provenance {
         let mut convert = Convert::new();
-        convert.from(site.format()).into(Format::Cat21);
+        convert
+            .from(site.format())
+            .into(into)
+            .strictness(engine.sources().strictness(&sopts.site));
+        if let Some(redact) = redact {
+            convert.redact(redact);
+        }
+        if sopts.provenance {
+            convert.provenance(engine.provenance(&sopts.site, job.id));
+        }
         job.add(Box::new(convert));
     };
 
-    // If split is required, add a consumer for it at the end.
+    let input = if into == Format::None {
+        site.format()
+    } else {
+        into
+    };
+
+    // Only the categories asked for
     //
-    info!("Running job #{} with {} tasks.", job.id, job.list.len());
-    if sopts.split.is_some() {
-        let basedir = sopts.split.as_ref().unwrap();
+    if !sopts.category.is_empty() {
+        let mut select = Select::new("select", input);
+        select.categories(&sopts.category);
+        job.add(Box::new(select));
+    }
+
+    // Normalise track IDs, before QC which uses them
+    //
+    if sopts.tracks {
+        let mut track = Track::new("tracks", input);
+        track
+            .max_gap(sopts.track_max_gap)
+            .max_jump(sopts.track_max_jump);
+        job.add(Box::new(track));
+    }
+
+    // Ground truth from flight plans
+    //
+    if let Some(plans) = &sopts.label {
+        let mut label = Label::new("label", input);
+        label
+            .plans(FlightPlans::load(plans)?)
+            .window(sopts.label_window)
+            .matched_only(sopts.label_matched_only);
+        job.add(Box::new(label));
+    }
+
+    // Check records before they are written
+    //
+    if let Some(summary) = &sopts.qc {
+        info!("QC summary into {summary}");
 
+        let mut qc = Qc::new(summary, input);
+        qc.path(summary)
+            .max_gap(sopts.qc_max_gap)
+            .max_climb(sopts.qc_max_climb)
+            .drop(sopts.qc_drop);
+        job.add(Box::new(qc));
+    }
+
+    // Features for live maps
+    //
+    if sopts.geojson {
+        job.add(Box::new(GeoJson::new("geojson", input)));
+    }
+
+    // If split is required, add a consumer for it at the end.
+    //
+    if let Destination::Dir { path: basedir, .. } = dest {
         // Store must be the last one, it is a pure consumer
         //
         let store = Store::new(basedir, job.id)?;
         job.add(Box::new(store));
+    }
 
-        job.run(&mut stdout())?;
-    } else {
-        // Handle output if no consumer is present at the end
-        //
-        if let Some(out) = &sopts.output {
-            let mut out = File::create(out)?;
+    // Or send everything to TCP clients
+    //
+    if let Some(serve) = serve {
+        info!("Serving on {}", serve.local_addr());
+        job.add(Box::new(serve.clone()));
+    }
+    info!("Running job #{} with {} tasks.", job.id, job.list.len());
+    Ok(job)
+}
 
-            job.run(&mut out)?;
-        } else {
-            job.run(&mut stdout())?;
+/// Format asked for with `--into`, `Format::None` if none
+///
+/// FIXME: DEPRECATED
+///
+fn conversion(into: Option<&str>) -> Format {
+    match into.map(Format::from_str) {
+        Some(Ok(Format::Sbs1)) => Format::Sbs1,
+        Some(Ok(Format::Senhive)) => Format::Senhive,
+        Some(_) => Format::Cat21,
+        None => Format::None,
+    }
+}
+
+/// What `stream_from_site()` would do, stage by stage in the same order as `stream_job()`.
+///
+#[tracing::instrument(skip(engine))]
+fn plan_from_opts(engine: &Engine, sopts: &StreamOpts, filter: Filter) -> Result<Plan> {
+    let srcs = engine.sources();
+    let site = &srcs[sopts.site.as_str()];
+
+    let mut plan = Plan::new("stream_from_site");
+    plan.site(site, true, filter)
+        .stage(&format!("Stream {}", site.name()));
+
+    if let Some(dir) = &sopts.raw_copy {
+        plan.stage(&format!("RawCopy into {dir}")).output(dir);
+    }
+    if let Some(tee) = &sopts.tee {
+        plan.stage(&format!("Tee into {tee}")).output(tee);
+    }
+
+    let into = conversion(sopts.into.as_deref());
+    if into != Format::None || sopts.redact.is_some() || sopts.provenance {
+        let mut convert = format!("Convert {} into {}", site.format(), into);
+        if let Some(policy) = &sopts.redact {
+            convert.push_str(&format!(", redact {policy}"));
+        }
+        if sopts.provenance {
+            convert.push_str(", provenance");
+        }
+        plan.stage(&convert);
+    }
+
+    if !sopts.category.is_empty() {
+        let cats = sopts
+            .category
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        plan.stage(&format!("Select {}", cats.join(", ")));
+    }
+    if sopts.tracks {
+        plan.stage(&format!(
+            "Track (max gap {}s, max jump {}m)",
+            sopts.track_max_gap, sopts.track_max_jump
+        ));
+    }
+    if let Some(plans) = &sopts.label {
+        plan.stage(&format!(
+            "Label from {} (window {}s{})",
+            plans.display(),
+            sopts.label_window,
+            if sopts.label_matched_only {
+                ", matched only"
+            } else {
+                ""
+            }
+        ));
+    }
+    if let Some(summary) = &sopts.qc {
+        plan.stage(&format!(
+            "Qc (max gap {}s, max climb {}m/s{})",
+            sopts.qc_max_gap,
+            sopts.qc_max_climb,
+            if sopts.qc_drop { ", drop" } else { "" }
+        ))
+        .output(summary);
+    }
+    if sopts.geojson {
+        plan.stage("GeoJson");
+    }
+
+    let dest = destination(sopts)?;
+    if let Destination::Dir { path, .. } = &dest {
+        plan.stage(&format!("Store into {path}/<job id>"));
+    }
+    if let Some(addr) = &sopts.serve {
+        match sopts.sse {
+            true => plan.stage(&format!("Serve on {addr} (SSE)")),
+            false => plan.stage(&format!("Serve on {addr}")),
         };
+        plan.output(&format!("clients on {addr}"));
     }
+    plan.output(&dest.to_string());
+    Ok(plan)
+}
 
-    // Remove job from engine and state
-    //
-    engine.remove_job(job)
+/// Destination from `--to` or from the older `-o` and `--split`.  Streams go into a file, stdout
+/// or hourly files in a directory.
+///
+#[tracing::instrument]
+fn destination(sopts: &StreamOpts) -> Result<Destination> {
+    let Some(to) = &sopts.to else {
+        return Ok(match (&sopts.split, &sopts.output) {
+            (Some(dir), _) => Destination::Dir {
+                path: dir.clone(),
+                split_by: None,
+            },
+            (None, Some(out)) => Destination::File {
+                path: out.to_string_lossy().to_string(),
+                write: None,
+                layout: None,
+                trajectories: false,
+            },
+            (None, None) => Destination::Stdout,
+        });
+    };
+
+    let only_fetch =
+        |what: &str| Status::BadDestination(to.to_string(), format!("{what} is only for fetch"));
+    match to {
+        Destination::PostGis { .. } => {
+            Err(Status::UnsupportedDestination("postgres".into()).into())
+        }
+        Destination::Dir {
+            split_by: Some(_), ..
+        } => Err(only_fetch("split_by").into()),
+        Destination::File {
+            write: None,
+            layout: None,
+            trajectories: false,
+            ..
+        } => Ok(to.clone()),
+        Destination::File { .. } => Err(only_fetch("write, layout or trajectories").into()),
+        _ => Ok(to.clone()),
+    }
 }
 
 /// From the CLI options
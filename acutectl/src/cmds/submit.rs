@@ -0,0 +1,143 @@
+//! This is the module handling the `submit` sub-command.
+//!
+//! Jobs are read from a job file (see `JobFile` in `fetiche-engine`), checked and run in order.
+//! A job with a `schedule` is run again every `every` seconds, `count` times (or forever).
+//! Runs are scheduled on the monotonic clock (see `Ticker`), wall-clock jumps are recorded in
+//! the engine statistics.  `--timeout` overrides the wall-clock limit of every job.
+//!
+//! A scheduled job with `probe = true` waits for its source before every run, the deferred
+//! runs being recorded in the job history (see `readiness.rs` in `fetiche-engine`).
+//!
+//! Jobs named in `on_success` or `on_failure` are only run after the job naming them (see
+//! `chain.rs` in `fetiche-engine`), every run of a scheduled job running its chain again.
+//!
+//! `--dry-run` prints the plan of every job (see `plan.rs` in `fetiche-engine`) in the order
+//! they would first run, without contacting any site.
+//!
+//! `--live ADDR` serves the records of every stream job to WebSocket clients on
+//! `ws://ADDR/ws/live` (see `live.rs` in `fetiche-engine`) for as long as the jobs run.
+//!
+
+use std::collections::{BTreeSet, VecDeque};
+use std::io::stdout;
+use std::time::Duration;
+
+use eyre::Result;
+use tracing::{info, trace, warn};
+
+use fetiche_engine::{Engine, JobFile, Live, Ticker, LIVE_PATH, PROBE_RETRY};
+
+use crate::SubmitOpts;
+
+/// Load the job file and run every job in it.
+///
+#[tracing::instrument(skip(engine))]
+pub fn submit_jobs(engine: &mut Engine, sopts: &SubmitOpts) -> Result<()> {
+    trace!("submit_jobs({:?})", sopts.file);
+
+    let mut file = JobFile::load(&sopts.file)?;
+    info!("{} job(s) in {:?}", file.job.len(), sopts.file);
+
+    if sopts.check {
+        file.job.iter().for_each(|(name, spec)| {
+            let source = match spec.source.as_str() {
+                "" => "(previous job)",
+                source => source,
+            };
+            eprintln!("{}: {} -> {:?}", name, source, spec.sink);
+            if !spec.on_success.is_empty() {
+                eprintln!("  on success: {}", spec.on_success.join(", "));
+            }
+            if !spec.on_failure.is_empty() {
+                eprintln!("  on failure: {}", spec.on_failure.join(", "));
+            }
+        });
+        eprintln!("{:?} is valid.", sopts.file);
+        return Ok(());
+    }
+
+    if sopts.dry_run {
+        return plan_jobs(engine, &file);
+    }
+
+    if let Some(addr) = &sopts.live {
+        let live = Live::serve(addr.as_str())?;
+        if let Some(addr) = live.local_addr() {
+            info!("Live positions on ws://{}{}", addr, LIVE_PATH);
+        }
+        engine.live(live);
+    }
+
+    if sopts.timeout.is_some() {
+        file.job
+            .values_mut()
+            .for_each(|spec| spec.timeout = sopts.timeout);
+    }
+
+    for name in file.roots() {
+        let spec = &file.job[name];
+        match (&spec.schedule, sopts.once) {
+            (Some(schedule), false) => {
+                let mut ticker = Ticker::new(Duration::from_secs(schedule.every));
+                let mut n = 0;
+                let retry = Duration::from_secs(schedule.retry.unwrap_or(PROBE_RETRY));
+                loop {
+                    if schedule.probe {
+                        engine.wait_for_source(name, &spec.source, retry);
+                    }
+                    engine.run_chain(&file, name, &mut stdout())?;
+
+                    n += 1;
+                    if schedule.count != 0 && n >= schedule.count {
+                        break;
+                    }
+                    info!("Job {name}: run {n} done");
+                    if let Some(skew) = ticker.wait() {
+                        warn!("Job {name}: clock jumped by {}ms", skew.offset);
+                        engine.record_skew(&skew)?;
+                    }
+                }
+            }
+            _ => engine.run_chain(&file, name, &mut stdout())?,
+        }
+    }
+    Ok(())
+}
+
+/// Print the plan of every job, roots first then their chains breadth-first.
+///
+#[tracing::instrument(skip(engine, file))]
+fn plan_jobs(engine: &Engine, file: &JobFile) -> Result<()> {
+    let mut seen = BTreeSet::new();
+    let mut queue = file.roots().into_iter().collect::<VecDeque<_>>();
+    while let Some(name) = queue.pop_front() {
+        if !seen.insert(name) {
+            continue;
+        }
+        let spec = &file.job[name];
+        print!("{}", engine.plan_from(name, spec)?);
+        if let Some(schedule) = &spec.schedule {
+            match schedule.count {
+                0 => println!("  every:   {}s", schedule.every),
+                n => println!("  every:   {}s, {} times", schedule.every, n),
+            }
+            if schedule.probe {
+                let retry = schedule.retry.unwrap_or(PROBE_RETRY);
+                println!("  probe:   {}, every {}s while down", spec.source, retry);
+            }
+        }
+        if !spec.on_success.is_empty() {
+            println!("  on success: {}", spec.on_success.join(", "));
+        }
+        if !spec.on_failure.is_empty() {
+            println!("  on failure: {}", spec.on_failure.join(", "));
+        }
+        queue.extend(
+            spec.on_success
+                .iter()
+                .chain(spec.on_failure.iter())
+                .map(String::as_str),
+        );
+    }
+    Ok(())
+}
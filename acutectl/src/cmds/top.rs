@@ -0,0 +1,324 @@
+//! This is the module handling the `top` sub-command.
+//!
+//! A live dashboard of the engine in the terminal, refreshed every `--every` seconds:
+//!
+//! - the jobs in the queue, running or not, with their estimated end,
+//! - the records fetched per source (from the statistics of finished jobs) with their average
+//!   rate and the rate since the last refresh,
+//! - how many workers are busy,
+//! - the last failed jobs.
+//!
+//! By default, the local engine is used and its state file read again on every refresh so jobs
+//! run by other processes are seen.  With `--remote URL`, the JSON `StatusReport` returned by
+//! `URL` (what `acutectl status -O json` displays) is used instead, e.g. from a `fetiched`.
+//!
+//! `q` or `Esc` leaves.
+//!
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use chrono::DateTime;
+use eyre::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, Row, Table};
+use ratatui::Frame;
+use tracing::trace;
+
+use fetiche_engine::{Engine, StatusReport};
+
+use crate::TopOpts;
+
+/// Where the reports come from
+///
+enum Feed<'a> {
+    /// Embedded engine
+    Local(&'a Engine),
+    /// Remote daemon
+    Remote(reqwest::blocking::Client, String),
+}
+
+impl Feed<'_> {
+    /// Get a fresh report
+    ///
+    fn report(&self) -> Result<StatusReport> {
+        match self {
+            Feed::Local(engine) => {
+                engine.reload_state()?;
+                Ok(engine.status())
+            }
+            Feed::Remote(client, url) => Ok(client.get(url).send()?.error_for_status()?.json()?),
+        }
+    }
+}
+
+/// What is displayed
+///
+#[derive(Debug, Default)]
+struct View {
+    /// Last report
+    report: StatusReport,
+    /// Records per second per source since the previous report
+    rates: BTreeMap<String, f64>,
+    /// Error getting the last report, the previous one is kept
+    error: Option<String>,
+}
+
+impl View {
+    /// Replace the report, computing the rates from the previous one
+    ///
+    fn update(&mut self, report: StatusReport) {
+        let elapsed = (report.at - self.report.at).max(1) as f64;
+        self.rates = report
+            .stats
+            .sources
+            .iter()
+            .map(|(name, s)| {
+                let before = self
+                    .report
+                    .stats
+                    .sources
+                    .get(name)
+                    .map(|s| s.records)
+                    .unwrap_or(s.records);
+                let rate = s.records.saturating_sub(before) as f64 / elapsed;
+                (name.clone(), rate)
+            })
+            .collect();
+        self.report = report;
+        self.error = None;
+    }
+}
+
+/// Run the dashboard until the user leaves.
+///
+#[tracing::instrument(skip(engine))]
+pub fn top(engine: &Engine, topts: &TopOpts) -> Result<()> {
+    trace!("top");
+
+    let feed = match &topts.remote {
+        Some(url) => Feed::Remote(reqwest::blocking::Client::new(), url.clone()),
+        None => Feed::Local(engine),
+    };
+    let every = Duration::from_secs(topts.every.max(1));
+
+    let mut view = View::default();
+    let mut terminal = ratatui::init();
+    let res = (|| -> Result<()> {
+        loop {
+            match feed.report() {
+                Ok(report) => view.update(report),
+                Err(e) => view.error = Some(e.to_string()),
+            }
+            terminal.draw(|f| draw(f, &view))?;
+
+            let next = Instant::now() + every;
+            while let Some(wait) = next.checked_duration_since(Instant::now()) {
+                if !event::poll(wait)? {
+                    break;
+                }
+                if let Event::Key(key) = event::read()? {
+                    let ctrl_c = key.modifiers.contains(KeyModifiers::CONTROL)
+                        && key.code == KeyCode::Char('c');
+                    if key.kind == KeyEventKind::Press
+                        && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+                    {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+    res
+}
+
+/// Format a UNIX timestamp
+///
+fn time(t: i64) -> String {
+    DateTime::from_timestamp(t, 0)
+        .map(|t| t.format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Draw the whole screen
+///
+fn draw(f: &mut Frame, view: &View) {
+    let r = &view.report;
+    let [header, jobs, middle, errors] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(5),
+        Constraint::Length(8),
+        Constraint::Length(8),
+    ])
+    .areas(f.area());
+    let [sources, workers] =
+        Layout::horizontal([Constraint::Percentage(70), Constraint::Percentage(30)]).areas(middle);
+
+    // Header
+    //
+    let line = match &view.error {
+        Some(e) => Line::from(format!("{} -- {}", time(r.at), e)).red(),
+        None => Line::from(format!(
+            "{} -- {} (pid {}) -- {} queued -- q to quit",
+            time(r.at),
+            r.version,
+            r.pid,
+            r.scheduler.queued
+        )),
+    };
+    f.render_widget(line, header);
+
+    // Jobs
+    //
+    let rows = r.jobs.iter().map(|j| {
+        Row::new(vec![
+            j.id.to_string(),
+            j.name.clone(),
+            j.status.to_string(),
+            j.started.map(time).unwrap_or_default(),
+            j.eta.map(time).unwrap_or_default(),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(9),
+            Constraint::Length(9),
+        ],
+    )
+    .header(Row::new(["ID", "Name", "Status", "Started", "ETA"]).bold())
+    .block(Block::bordered().title(" Jobs "));
+    f.render_widget(table, jobs);
+
+    // Sources
+    //
+    let rows = r.stats.sources.iter().map(|(name, s)| {
+        Row::new(vec![
+            name.clone(),
+            s.records.to_string(),
+            format!("{:.1}", s.rate()),
+            format!("{:.1}", view.rates.get(name).copied().unwrap_or_default()),
+            time(s.last),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(9),
+        ],
+    )
+    .header(Row::new(["Source", "Records", "Avg/s", "Now/s", "Last"]).bold())
+    .block(Block::bordered().title(" Sources "));
+    f.render_widget(table, sources);
+
+    // Workers
+    //
+    let w = &r.workers;
+    let ratio = match w.workers {
+        0 => 0.,
+        n => (w.busy as f64 / n as f64).min(1.),
+    };
+    let gauge = Gauge::default()
+        .ratio(ratio)
+        .label(format!("{}/{} busy", w.busy, w.workers))
+        .gauge_style(Style::default().fg(Color::Green))
+        .block(Block::bordered().title(" Workers "));
+    f.render_widget(gauge, workers);
+
+    // Errors, last first
+    //
+    let items = r
+        .stats
+        .errors
+        .iter()
+        .rev()
+        .map(|e| format!("{} #{} {}: {}", time(e.at), e.id, e.name, e.error));
+    let list = List::new(items)
+        .style(Style::default().fg(Color::Red))
+        .block(Block::bordered().title(" Errors "));
+    f.render_widget(list, errors);
+}
+
+#[cfg(test)]
+mod tests {
+    use fetiche_engine::{JobError, JobStatus, QueuedJob, SourceStats};
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+
+    fn report(at: i64, records: usize) -> StatusReport {
+        let mut r = StatusReport {
+            at,
+            ..StatusReport::default()
+        };
+        r.jobs.push(QueuedJob {
+            id: 42,
+            name: "stream_from_site".to_string(),
+            status: JobStatus::Running,
+            position: 1,
+            queued: at,
+            started: Some(at),
+            eta: None,
+        });
+        r.stats.sources.insert(
+            "asd".to_string(),
+            SourceStats {
+                records,
+                seconds: 10,
+                last: at,
+            },
+        );
+        r.stats.errors.push_back(JobError {
+            id: 41,
+            name: "fetch_from_site".to_string(),
+            at,
+            error: "site is down".to_string(),
+        });
+        r.workers.workers = 2;
+        r.workers.busy = 1;
+        r
+    }
+
+    #[test]
+    fn test_view_rates() {
+        let mut view = View::default();
+        view.update(report(1000, 100));
+        assert_eq!(0., view.rates["asd"]);
+
+        view.update(report(1010, 150));
+        assert_eq!(5., view.rates["asd"]);
+    }
+
+    #[test]
+    fn test_draw() -> Result<()> {
+        let mut view = View::default();
+        view.update(report(1000, 100));
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 30))?;
+        terminal.draw(|f| draw(f, &view))?;
+
+        let screen = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|c| c.symbol())
+            .collect::<String>();
+        assert!(screen.contains("stream_from_site"));
+        assert!(screen.contains("site is down"));
+        assert!(screen.contains("1/2 busy"));
+        Ok(())
+    }
+}
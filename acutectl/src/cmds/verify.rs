@@ -0,0 +1,417 @@
+//! This is the module handling the `verify` sub-command.
+//!
+//! A file delivered by a partner is parsed record by record with the parser of its format, without
+//! converting anything.  Every row which can not be read or has a field out of range (latitude,
+//! longitude, time) is reported with its line number, and the valid ones give the time range,
+//! the bounding box and the number of unique targets.
+//!
+//! The file passes if it has at least one valid record and no more than `--max-errors` invalid
+//! ones, otherwise this is an error so it can be used in scripts.
+//!
+//! CSV is used for `aeroscope`, `asd`, `safesky` and `pandastatevector` (a header line is
+//! expected), our own Cat21 CSV files use `:`.  `asd` files ending in `.json` and `utm` are read
+//! as one JSON document per line and `sbs1` as BaseStation messages.
+//!
+
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::DateTime;
+use csv::{ReaderBuilder, StringRecord};
+use eyre::Result;
+use serde::de::DeserializeOwned;
+use tracing::{info, trace};
+
+use fetiche_common::{Listing, OutputFormat};
+use fetiche_formats::{Aeroscope, Asd, Cat21, Format, PandaStateVector, Safesky, Sbs1, Utm};
+
+use crate::{Status, VerifyOpts};
+
+/// What we keep of a valid record
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Point {
+    /// Time as UNIX timestamp
+    tm: i64,
+    /// Position, not all messages have one
+    pos: Option<(f64, f64)>,
+    /// Identifier of the target
+    target: String,
+}
+
+impl Point {
+    /// Out of range values are errors.
+    ///
+    fn check(self) -> Result<Point, String> {
+        if self.tm <= 0 {
+            return Err("time: missing or invalid".to_string());
+        }
+        if let Some((lat, lon)) = self.pos {
+            if !(-90.0..=90.0).contains(&lat) {
+                return Err(format!("latitude: {lat} out of range"));
+            }
+            if !(-180.0..=180.0).contains(&lon) {
+                return Err(format!("longitude: {lon} out of range"));
+            }
+        }
+        if self.target.trim().is_empty() {
+            return Err("target: missing identifier".to_string());
+        }
+        Ok(self)
+    }
+}
+
+impl From<&Cat21> for Point {
+    fn from(rec: &Cat21) -> Self {
+        Point {
+            tm: rec.rec_time_posix,
+            pos: Some((rec.pos_lat_deg as f64, rec.pos_long_deg as f64)),
+            target: format!("{:06X}", rec.target_addr),
+        }
+    }
+}
+
+/// One invalid row
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowError {
+    /// Line in the file, starting at 1
+    pub line: usize,
+    /// What is wrong
+    pub message: String,
+}
+
+/// Result of the verification
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Records read
+    pub rows: usize,
+    /// Invalid rows
+    pub errors: Vec<RowError>,
+    /// First and last valid times
+    pub time: Option<(i64, i64)>,
+    /// South-west and north-east corners
+    pub bbox: Option<((f64, f64), (f64, f64))>,
+    /// Unique targets
+    pub targets: BTreeSet<String>,
+}
+
+impl VerifyReport {
+    /// Account for one row.
+    ///
+    fn add(&mut self, line: usize, res: Result<Point, String>) {
+        self.rows += 1;
+        let p = match res.and_then(Point::check) {
+            Ok(p) => p,
+            Err(message) => {
+                self.errors.push(RowError { line, message });
+                return;
+            }
+        };
+        self.time = match self.time {
+            Some((first, last)) => Some((first.min(p.tm), last.max(p.tm))),
+            None => Some((p.tm, p.tm)),
+        };
+        if let Some((lat, lon)) = p.pos {
+            self.bbox = match self.bbox {
+                Some(((s, w), (n, e))) => {
+                    Some(((s.min(lat), w.min(lon)), (n.max(lat), e.max(lon))))
+                }
+                None => Some(((lat, lon), (lat, lon))),
+            };
+        }
+        self.targets.insert(p.target);
+    }
+
+    /// Valid records
+    ///
+    pub fn valid(&self) -> usize {
+        self.rows - self.errors.len()
+    }
+
+    /// Is the file acceptable?
+    ///
+    pub fn passed(&self, max_errors: usize) -> bool {
+        self.valid() != 0 && self.errors.len() <= max_errors
+    }
+
+    /// Summary and the first `show` errors
+    ///
+    pub fn render(&self, fmt: OutputFormat, max_errors: usize, show: usize) -> Result<String> {
+        let tm = |t: i64| {
+            DateTime::from_timestamp(t, 0)
+                .unwrap_or_default()
+                .to_rfc3339()
+        };
+        let (first, last) = match self.time {
+            Some((first, last)) => (tm(first), tm(last)),
+            None => (String::new(), String::new()),
+        };
+        let bbox = match self.bbox {
+            Some(((s, w), (n, e))) => format!("{s},{w},{n},{e}"),
+            None => String::new(),
+        };
+
+        let mut summary = Listing::new("Verification", &[("Name", "name"), ("Value", "value")]);
+        summary
+            .push(vec!["rows".to_string(), self.rows.to_string()])
+            .push(vec!["valid".to_string(), self.valid().to_string()])
+            .push(vec!["errors".to_string(), self.errors.len().to_string()])
+            .push(vec!["first".to_string(), first])
+            .push(vec!["last".to_string(), last])
+            .push(vec!["bbox".to_string(), bbox])
+            .push(vec!["targets".to_string(), self.targets.len().to_string()])
+            .push(vec![
+                "result".to_string(),
+                if self.passed(max_errors) {
+                    "pass".to_string()
+                } else {
+                    "fail".to_string()
+                },
+            ]);
+
+        let mut errors = Listing::new("Errors", &[("Line", "line"), ("Error", "error")]);
+        self.errors.iter().take(show).for_each(|e| {
+            errors.push(vec![e.line.to_string(), e.message.clone()]);
+        });
+
+        let mut res = summary.render(fmt)?;
+        if !self.errors.is_empty() && show != 0 {
+            res = format!("{}\n{}", res, errors.render(fmt)?);
+        }
+        Ok(res)
+    }
+}
+
+/// Parse the whole file and display the report.
+///
+#[tracing::instrument]
+pub fn verify_file(vopts: &VerifyOpts, fmt: OutputFormat) -> Result<()> {
+    trace!("verify_file");
+
+    info!("Verifying {:?} as {}", vopts.file, vopts.format);
+
+    let input = fs::read_to_string(&vopts.file)?;
+    let report = verify(vopts.format, &vopts.file, &input)?;
+    println!("{}", report.render(fmt, vopts.max_errors, vopts.show)?);
+
+    if report.passed(vopts.max_errors) {
+        Ok(())
+    } else {
+        Err(Status::VerifyFailed(report.errors.len(), report.rows).into())
+    }
+}
+
+/// Run the parser of `format` on every record of `input`.
+///
+fn verify(format: Format, fname: &Path, input: &str) -> Result<VerifyReport> {
+    let json = fname
+        .extension()
+        .is_some_and(|ext| ext == "json" || ext == "jsonl");
+
+    let report = match format {
+        Format::Aeroscope => from_csv::<Aeroscope>(input, b',', |r| Point::from(&Cat21::from(r))),
+        Format::Asd if json => from_json::<Asd>(input, asd),
+        Format::Asd => from_csv::<Asd>(input, b',', asd),
+        Format::Safesky => from_csv::<Safesky>(input, b',', |r| Point::from(&Cat21::from(r))),
+        Format::PandaStateVector => {
+            from_csv::<PandaStateVector>(input, b',', |r| Point::from(&Cat21::from(r)))
+        }
+        Format::Utm => from_json::<Utm>(input, |r| Point::from(&Cat21::from(r))),
+        Format::Cat21 => from_cat21(input),
+        Format::Sbs1 => from_sbs1(input),
+        _ => return Err(Status::UnsupportedVerify(format.to_string()).into()),
+    };
+    Ok(report)
+}
+
+/// ASD has its own time format and the drone identifier is more useful than an address.
+///
+fn asd(rec: &Asd) -> Point {
+    let tm = rec.fix_tm().map(|r| r.time.timestamp()).unwrap_or_default();
+    Point {
+        tm,
+        pos: Some((rec.latitude as f64, rec.longitude as f64)),
+        target: rec.ident.clone(),
+    }
+}
+
+/// CSV with a header line, errors from `csv` give the field.
+///
+fn from_csv<T>(input: &str, delimiter: u8, point: fn(&T) -> Point) -> VerifyReport
+where
+    T: DeserializeOwned + Debug,
+{
+    let mut report = VerifyReport::default();
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    for rec in rdr.deserialize::<T>() {
+        match rec {
+            Ok(rec) => {
+                // `csv` does not give the position of a valid record, the header is line 1
+                //
+                report.add(report.rows + 2, Ok(point(&rec)));
+            }
+            Err(e) => {
+                let line = e
+                    .position()
+                    .map(|p| p.line() as usize)
+                    .unwrap_or(report.rows + 2);
+                report.add(line, Err(e.to_string()));
+            }
+        }
+    }
+    report
+}
+
+/// One JSON document per line, blank lines are ignored.
+///
+fn from_json<T>(input: &str, point: fn(&T) -> Point) -> VerifyReport
+where
+    T: DeserializeOwned + Debug,
+{
+    let mut report = VerifyReport::default();
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .for_each(|(n, l)| {
+            let res = serde_json::from_str::<T>(l)
+                .map(|rec| point(&rec))
+                .map_err(|e| e.to_string());
+            report.add(n + 1, res);
+        });
+    report
+}
+
+/// Our Cat21 CSV files are only serialised, read the columns we need.
+///
+fn from_cat21(input: &str) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(b':')
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    let headers = rdr.headers().cloned().unwrap_or_default();
+    let col = |name: &str| headers.iter().position(|h| h == name);
+    let cols = (
+        col("REC_TIME_POSIX"),
+        col("POS_LAT_DEG"),
+        col("POS_LONG_DEG"),
+        col("TARGET_ADDR"),
+    );
+
+    fn field<T: FromStr>(rec: &StringRecord, idx: Option<usize>, name: &str) -> Result<T, String> {
+        let value = idx.and_then(|i| rec.get(i)).unwrap_or_default();
+        value
+            .trim()
+            .parse::<T>()
+            .map_err(|_| format!("{}: invalid value {:?}", name.to_lowercase(), value))
+    }
+
+    for rec in rdr.records() {
+        match rec {
+            Ok(rec) => {
+                let line = rec
+                    .position()
+                    .map(|p| p.line() as usize)
+                    .unwrap_or_default();
+                let res = (|| {
+                    Ok(Point {
+                        tm: field(&rec, cols.0, "REC_TIME_POSIX")?,
+                        pos: Some((
+                            field(&rec, cols.1, "POS_LAT_DEG")?,
+                            field(&rec, cols.2, "POS_LONG_DEG")?,
+                        )),
+                        target: format!("{:06X}", field::<u32>(&rec, cols.3, "TARGET_ADDR")?),
+                    })
+                })();
+                report.add(line, res);
+            }
+            Err(e) => {
+                let line = e.position().map(|p| p.line() as usize).unwrap_or_default();
+                report.add(line, Err(e.to_string()));
+            }
+        }
+    }
+    report
+}
+
+/// BaseStation messages, only `MSG,3` have a position.
+///
+fn from_sbs1(input: &str) -> VerifyReport {
+    let mut report = VerifyReport::default();
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .for_each(|(n, l)| {
+            let res = Sbs1::from_str(l)
+                .map(|msg| Point {
+                    tm: msg.generated.map(|t| t.timestamp()).unwrap_or_default(),
+                    pos: msg.latitude.zip(msg.longitude),
+                    target: msg.hex_ident.clone(),
+                })
+                .map_err(|e| e.to_string());
+            report.add(n + 1, res);
+        });
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const ASD: &str = "\
+journey,ident,model,source,location,timestamp,latitude,longitude,altitude,elevation,gps,rssi,home_lat,home_lon,home_height,speed,heading,station_name,station_latitude,station_longitude
+42,1581F5FJD239C00DW22E,Mavic 3,as,1,2024-05-12 10:30:15,49.611600,6.206100,120,95,,,49.61,6.20,,36.0,87.5,LUX,49.6,6.2
+42,1581F5FJD239C00DW22E,Mavic 3,as,2,2024-05-12 10:30:16,49.611700,6.206200,121,96,,,49.61,6.20,,36.0,87.5,LUX,49.6,6.2
+43,1581F5FJD239C00DW22F,Mini 4,as,1,2024-05-12 10:31:00,149.0,6.2,80,60,,,49.61,6.20,,10.0,12.0,LUX,49.6,6.2
+44,1581F5FJD239C00DW230,Mini 4,as,1,2024-05-12 10:32:00,49.6,6.2,,,,,49.61,6.20,,fast,12.0,LUX,49.6,6.2
+";
+
+    #[test]
+    fn test_verify_asd_csv() -> Result<()> {
+        let report = verify(Format::Asd, &PathBuf::from("asd.csv"), ASD)?;
+
+        assert_eq!(4, report.rows);
+        assert_eq!(2, report.valid());
+        assert_eq!(4, report.errors[0].line);
+        assert!(report.errors[0].message.starts_with("latitude"));
+        assert_eq!(5, report.errors[1].line);
+        assert_eq!(1, report.targets.len());
+        assert_eq!(Some((1715509815, 1715509816)), report.time);
+        assert!(!report.passed(0));
+        assert!(report.passed(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_sbs1() -> Result<()> {
+        let input = "\
+MSG,3,1,1,4CA2D6,1,2024/05/12,10:30:15.000,2024/05/12,10:30:15.000,,3000,,,49.5,6.25,,,0,0,0,0
+MSG,4,1,1,4CA2D6,1,2024/05/12,10:30:16.000,2024/05/12,10:30:16.000,,,120,90,,,,,,,,
+garbage
+";
+        let report = verify(Format::Sbs1, &PathBuf::from("feed.txt"), input)?;
+
+        assert_eq!(3, report.rows);
+        assert_eq!(1, report.errors.len());
+        assert_eq!(3, report.errors[0].line);
+        assert_eq!(Some(((49.5, 6.25), (49.5, 6.25))), report.bbox);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_unsupported() {
+        assert!(verify(Format::Senhive, &PathBuf::from("out.json"), "").is_err());
+    }
+}
@@ -5,14 +5,60 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Status {
+    #[error("Invalid bundle {0}")]
+    BadBundle(String),
+    #[error("Invalid destination {0}: {1}")]
+    BadDestination(String, String),
     #[error("Bad file version {0}")]
     BadFileVersion(usize),
+    #[error("Invalid restart policy {0}, use no, on-failure or on-failure:N")]
+    BadRestart(String),
+    #[error("{0} is not a date/time column")]
+    BadPartition(String),
+    #[error("Checksum mismatch for {0}")]
+    BundleChecksum(String),
+    #[error("Bundle version {0} is too recent, we support up to {1}")]
+    BundleVersion(usize, usize),
+    #[error("Datasets differ: {0} added, {1} removed, {2} changed")]
+    DatasetsDiffer(usize, usize, usize),
+    #[error("Clickhouse error: {0}")]
+    Clickhouse(String),
     #[error("Missing configuration file, use -d or create {0}")]
     MissingConfig(String),
     #[error("Error reading configuration({0})")]
     MissingConfigParameter(String),
+    #[error("--split-by needs an output directory (-o)")]
+    NoOutputDir,
+    #[error("--trajectories needs PostGIS or GeoParquet output")]
+    NoTrajectories,
+    #[error("--layout tracks needs Parquet output")]
+    NoTrackLayout,
+    #[error("Table is partitioned by {0}, not {1}")]
+    PartitionMismatch(String, String),
+    #[error("Column {0} is {1} in the table, {2} in the data")]
+    SchemaMismatch(String, String, String),
+    #[error("Self-test failed: {0} fixtures out of {1}")]
+    SelfTestFailed(usize, usize),
     #[error("Site {0} is not Fetchable!")]
     SiteNotFetchable(String),
     #[error("Site {0} is not Streamable!")]
     SiteNotStreamable(String),
+    #[error("Column {0} not found in the data")]
+    UnknownColumn(String),
+    #[error("Can not guess the format of {0}, use --from")]
+    UnknownInput(String),
+    #[error("No sink for {0}:// yet")]
+    UnsupportedDestination(String),
+    #[error("Can not select columns of {0} data, only cat21")]
+    UnsupportedColumns(String),
+    #[error("Can not sort {0} data, only cat21 and sbs1 are supported")]
+    UnsupportedSort(String),
+    #[error("Excel output can not be sorted or stabilised")]
+    UnsupportedXlsx,
+    #[error("Column {0} has an unsupported type {1}")]
+    UnsupportedType(String, String),
+    #[error("Can not verify {0} files")]
+    UnsupportedVerify(String),
+    #[error("Verification failed: {0} invalid rows out of {1}")]
+    VerifyFailed(usize, usize),
 }
@@ -11,6 +11,7 @@
 //   completion  Generate Completion stuff
 //   convert     Convert between formats
 //   fetch       Fetch data from specified site
+//   import      Import CSV or Parquet files into Clickhouse
 //   list        List information about formats and sources
 //   stream      Stream from a source
 //   version     List all package versions
@@ -20,6 +21,12 @@
 //   -c, --config <CONFIG>  configuration file
 //   -D, --debug            debug mode
 //   -o, --output <OUTPUT>  Output file
+//       --use-json         Log to stdout in JSON (for containers)
+//       --config-dir <DIR> Read all configuration files from this directory
+//       --state-dir <DIR>  Keep the engine state in this directory, no PID file
+//       --health <ADDR>    Serve /healthz and /readyz on this address
+//       --progress-json <TARGET>  Write job progress as JSON lines (fd:N, unix:PATH, tcp:HOST:PORT, file)
+//       --migrate          Upgrade engine.hcl, sources.hcl and the state file first
 //   -v, --verbose...       Verbose mode
 //   -h, --help             Print help
 //! ```
@@ -27,12 +34,18 @@
 use clap::{crate_authors, crate_description, crate_version, Parser};
 use eyre::Result;
 use serde::Deserialize;
-use tracing::{debug, trace};
-
-use acutectl::{handle_subcmd, Opts, Status};
-use fetiche_common::{close_logging, init_logging, ConfigFile, IntoConfig, Versioned};
-use fetiche_engine::Engine;
+use tracing::{debug, trace, warn};
+
+use acutectl::{
+    handle_subcmd, init_config, ConfigOpts, ConfigSubCommand, Opts, Status, SubCommand,
+};
+use fetiche_common::{
+    close_logging, config_dir, init_logging, set_config_dir, ConfigFile, Health, IntoConfig,
+    OutputFormat, Versioned,
+};
+use fetiche_engine::{Engine, Progress};
 use fetiche_macros::into_configfile;
+use fetiche_sources::Sources;
 
 /// Binary name, using a different binary name
 pub const NAME: &str = env!("CARGO_BIN_NAME");
@@ -43,6 +56,8 @@ pub const AUTHORS: &str = crate_authors!();
 
 /// Config filename
 const CONFIG: &str = "acutectl.hcl";
+/// Engine config filename
+const ENGINE_CONFIG: &str = "engine.hcl";
 /// Current version
 pub const CVERSION: usize = 2;
 
@@ -58,38 +73,106 @@ pub struct AcuteConfig {
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
-    let cfn = opts.config.or(Some(CONFIG.into()));
+    let cfn = opts.config.clone().or(Some(CONFIG.into()));
 
     // Initialise tracing.
     //
-    init_logging(NAME, opts.use_telemetry, opts.use_tree, opts.use_file)?;
+    init_logging(
+        NAME,
+        opts.use_telemetry,
+        opts.use_tree,
+        opts.use_file,
+        opts.use_json,
+    )?;
+
+    // Container mode, everything is path-driven.
+    //
+    if let Some(dir) = &opts.config_dir {
+        set_config_dir(dir);
+    }
+
+    // Nothing to load yet when creating the configuration.
+    //
+    if let SubCommand::Config(ConfigOpts {
+        subcmd: ConfigSubCommand::Init(iopts),
+    }) = &opts.subcmd
+    {
+        let res = init_config(iopts);
+        close_logging();
+        return res;
+    }
+
+    // Liveness/readiness probes
+    //
+    let health = match &opts.health {
+        Some(addr) => Some(Health::serve(addr.as_str())?),
+        None => None,
+    };
 
-    // Config only has the credentials for every source now.
+    // Upgrade older files in place before loading anything, originals are kept as `.bak`.
     //
-    let cfile = ConfigFile::<AcuteConfig>::load(cfn.as_deref())?;
-    debug!("cfile = {:?}", cfile);
+    if opts.migrate {
+        for (fname, backup) in Engine::migrate(opts.state_dir.clone())? {
+            eprintln!("Migrated {fname:?}, original saved as {backup:?}");
+        }
+    }
 
-    let cfg = cfile.inner();
-    if cfg.version() != CVERSION {
-        return Err(Status::BadFileVersion(cfg.version()).into());
+    // Nothing configured at all, we can still use the public sources built in the binary.
+    //
+    let local = opts.config.is_none() && !Sources::installed();
+    if local {
+        warn!("No sources.hcl in {:?}, local mode", config_dir());
+        eprintln!(
+            "WARNING: no configuration found in {:?}, only the built-in public sources are \
+            available and the state is kept in a temporary directory.\nRun `{NAME} config init` to create one \
+            (recommended).",
+            config_dir()
+        );
+    } else {
+        // Config only has the credentials for every source now.
+        //
+        let cfile = ConfigFile::<AcuteConfig>::load(cfn.as_deref())?;
+        debug!("cfile = {:?}", cfile);
+
+        let cfg = cfile.inner();
+        if cfg.version() != CVERSION {
+            return Err(Status::BadFileVersion(cfg.version()).into());
+        }
     }
 
-    // Banner
+    // Banner, not when we want only structured output.
     //
-    banner()?;
+    if !opts.use_json && opts.output_format == OutputFormat::Table {
+        banner()?;
+    }
 
     trace!("Engine starting.");
     // Instantiate Engine
     //
-    let mut engine = Engine::new();
+    let mut engine = match opts.state_dir {
+        _ if local => Engine::local(opts.state_dir)?,
+        Some(dir) => Engine::load_with(ENGINE_CONFIG, Some(dir))?,
+        None if opts.subcmd.shared() => Engine::load_shared(ENGINE_CONFIG)?,
+        None => Engine::load(ENGINE_CONFIG)?,
+    };
+
+    // Progress of every job for wrapping tools, human output stays on stderr.
+    //
+    if let Some(target) = &opts.progress_json {
+        engine.progress(Progress::open(target)?);
+    }
 
     trace!("Engine initialised and running.");
+    if let Some(health) = &health {
+        health.set_ready(true);
+    }
 
     let subcmd = opts.subcmd;
+    let fmt = opts.output_format;
 
     // For the moment the whole of Engine is sync so we need to block.
     //
-    let res = tokio::task::spawn_blocking(move || handle_subcmd(&mut engine, &subcmd)).await?;
+    let res = tokio::task::spawn_blocking(move || handle_subcmd(&mut engine, &subcmd, fmt)).await?;
     close_logging();
     res
 }
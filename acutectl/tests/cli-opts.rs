@@ -49,3 +49,9 @@ fn test_list_sources() {
     let mut cmd = Command::cargo_bin(BIN).unwrap();
     cmd.arg("list").arg("sources").assert().success();
 }
+
+#[test]
+fn test_submit_no_file() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("submit").assert().failure();
+}
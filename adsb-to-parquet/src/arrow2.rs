@@ -2,14 +2,21 @@
 //!
 //! This is slightly faster than `datafusion`  for small datasets and generates parquet v2 files.
 //!
+//! `read_csv()` and `write_chunk()` keep the whole file in memory, `stream_csv()` writes every
+//! batch as a row group as soon as it is read so memory use does not depend on the file size.
+//!
+//! With a profile, column types come from it instead of being inferred.  The profile timestamp
+//! is not built here, use `datafusion` for that.
+//!
 
 use std::fs::File;
+use std::io::{stderr, Write};
 use std::time::Instant;
 
 use arrow2::{
     array::Array,
     chunk::Chunk,
-    datatypes::Schema,
+    datatypes::{DataType, Field, Schema},
     io::csv::read::{
         deserialize_column, infer, infer_schema, read_rows, ByteRecord, ReaderBuilder,
     },
@@ -22,7 +29,24 @@ use parquet2::{compression::ZstdLevel, encoding::Encoding};
 use rayon::prelude::*;
 use tracing::{debug, info, trace};
 
-use crate::Options;
+use crate::{ColType, Options, Profile};
+
+/// arrow2 fields for a profile
+///
+fn fields_from(p: &Profile) -> Vec<Field> {
+    p.columns
+        .iter()
+        .map(|c| {
+            let dt = match c.ctype {
+                ColType::String => DataType::Utf8,
+                ColType::Int => DataType::Int64,
+                ColType::Float => DataType::Float64,
+                ColType::Bool => DataType::Boolean,
+            };
+            Field::new(&c.name, dt, true)
+        })
+        .collect()
+}
 
 /// Arbitrary value to get big row groups but not too big
 ///
@@ -37,8 +61,14 @@ pub fn read_csv(fname: &str, opt: Options) -> Result<(Schema, Vec<Chunk<Box<dyn
 
     trace!("fname={:?}", fname);
 
-    let mut reader = ReaderBuilder::new().delimiter(opt.delim).from_path(fname)?;
-    let (fields, _) = infer_schema(&mut reader, None, opt.header, &infer)?;
+    let mut reader = ReaderBuilder::new()
+        .delimiter(opt.delim)
+        .has_headers(opt.header)
+        .from_path(fname)?;
+    let fields = match &opt.profile {
+        Some(p) => fields_from(p),
+        None => infer_schema(&mut reader, None, opt.header, &infer)?.0,
+    };
     let schema = Schema::from(fields.clone());
 
     // Read in batches of `BATCH_SIZE` elements.
@@ -125,3 +155,79 @@ pub fn write_chunk(schema: Schema, data: Vec<Chunk<Box<dyn Array>>>, fname: &str
     let tm = Instant::now() - start;
     Ok(tm.as_millis() as u64)
 }
+
+/// Read `fname` by batches of `opt.batch` rows and write each one into `output` as a row group
+/// right away.  Types come from the profile or are inferred from the first batch.  Returns the
+/// number of rows.
+///
+/// Use parquet v2 format, ZSTD compression at level 8
+///
+#[tracing::instrument]
+pub fn stream_csv(fname: &str, output: &str, opt: Options) -> Result<u64> {
+    trace!("Stream data.");
+
+    let options = WriteOptions {
+        write_statistics: true,
+        compression: CompressionOptions::Zstd(Some(ZstdLevel::try_new(8)?)),
+        version: Version::V2,
+        data_pagesize_limit: None,
+    };
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(opt.delim)
+        .has_headers(opt.header)
+        .from_path(fname)?;
+    let fields = match &opt.profile {
+        Some(p) => fields_from(p),
+        None => infer_schema(&mut reader, Some(opt.batch), opt.header, &infer)?.0,
+    };
+    let schema = Schema::from(fields.clone());
+
+    let encodings: Vec<_> = schema
+        .fields
+        .iter()
+        .map(|f| transverse(&f.data_type, |_| Encoding::Plain))
+        .collect();
+
+    let file = File::create(output)?;
+    let mut writer = FileWriter::try_new(file, schema.clone(), options)?;
+
+    // Only one batch in memory at any time, the buffer is reused.
+    //
+    let mut rows = vec![ByteRecord::default(); opt.batch];
+    let mut total = 0;
+    loop {
+        let rows_read = read_rows(&mut reader, 0, &mut rows)?;
+        if rows_read == 0 {
+            break;
+        }
+        let batch = &rows[..rows_read];
+
+        let arrays: Vec<Box<dyn Array>> = fields
+            .par_iter()
+            .enumerate()
+            .map(|(n, field)| deserialize_column(batch, n, field.data_type.clone(), 0).unwrap())
+            .collect();
+
+        let chunk = Chunk::new(arrays);
+        let groups = RowGroupIterator::try_new(
+            vec![Ok(chunk)].into_iter(),
+            &schema,
+            options,
+            encodings.clone(),
+        )?;
+        for group in groups {
+            writer.write(group?)?;
+        }
+
+        total += rows_read as u64;
+        eprint!("\r{} lines", total);
+        stderr().flush()?;
+    }
+    eprintln!();
+
+    let size = writer.end(None)?;
+    info!("{} lines, {} bytes written.", total, size);
+
+    Ok(total)
+}
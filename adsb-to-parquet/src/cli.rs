@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use clap::{crate_authors, crate_description, crate_name, crate_version, Parser};
 
+use adsb_to_parquet::BATCH_ROWS;
+
 #[derive(Parser)]
 #[command(disable_version_flag = true)]
 #[clap(name = crate_name!(), about = crate_description!())]
@@ -14,9 +18,21 @@ pub struct Opts {
     /// Output file (default is stdout).
     #[clap(short = 'o', long)]
     pub output: Option<String>,
-    /// Delimiter for csv files.
-    #[clap(short, default_value = ",")]
-    pub delim: String,
+    /// Delimiter for csv files (default is the profile one or ",").
+    #[clap(short)]
+    pub delim: Option<String>,
+    /// Schema profile (emit, readsb, tar1090, radarcape or one from --profile-file).
+    #[clap(short = 'P', long)]
+    pub profile: Option<String>,
+    /// HCL file with more profiles.
+    #[clap(long)]
+    pub profile_file: Option<PathBuf>,
+    /// Stream the file by batches instead of loading it all in memory.
+    #[clap(short = 'S', long)]
+    pub stream: bool,
+    /// Rows per batch (and row group) when streaming.
+    #[clap(short = 'B', long, default_value_t = BATCH_ROWS)]
+    pub batch_rows: usize,
     /// Filename, can be just the basename and .csv/.parquet are implied
     pub name: String,
 }
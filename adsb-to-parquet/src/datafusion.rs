@@ -2,39 +2,95 @@
 //!
 //! This is way faster than `arrow2` (see benches/csv-to-parquet.rs) for larger datasets
 //!
+//! `stream_through_df()` pulls record batches from the CSV one at a time and closes a row group
+//! every `opts.batch` rows, memory use stays constant whatever the size of the file.
+//!
+
+use std::fs::File;
+use std::io::{stderr, Write};
 
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
 use datafusion::config::TableParquetOptions;
 use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::{Compression, ZstdLevel};
+use datafusion::parquet::file::properties::{WriterProperties, WriterVersion};
 use datafusion::prelude::*;
 use eyre::Result;
+use futures::StreamExt;
+use tracing::info;
+
+use crate::{ColType, Options, Profile, Timestamp};
+
+/// Read the CSV file, with the profile schema and timestamp if there is one.
+///
+async fn read_df(ctx: &SessionContext, fname: &str, opts: &Options) -> Result<DataFrame> {
+    let schema = opts.profile.as_ref().map(schema_from);
+
+    let mut csvopts = CsvReadOptions::default()
+        .delimiter(opts.delim)
+        .has_header(opts.header);
+    if let Some(schema) = &schema {
+        csvopts = csvopts.schema(schema);
+    }
+    let df = ctx.read_csv(fname, csvopts).await?;
 
-use crate::Options;
+    match opts.profile.as_ref().and_then(|p| p.timestamp.as_ref()) {
+        Some(ts) => Ok(df.with_column(&ts.name, timestamp_from(ts))?),
+        None => Ok(df),
+    }
+}
+
+/// Arrow schema for a profile
+///
+fn schema_from(p: &Profile) -> Schema {
+    let fields: Vec<_> = p
+        .columns
+        .iter()
+        .map(|c| {
+            let dt = match c.ctype {
+                ColType::String => DataType::Utf8,
+                ColType::Int => DataType::Int64,
+                ColType::Float => DataType::Float64,
+                ColType::Bool => DataType::Boolean,
+            };
+            Field::new(&c.name, dt, true)
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+/// Expression building the timestamp column.  Names are used as-is with `ident()` as some
+/// contain dots.
+///
+fn timestamp_from(ts: &Timestamp) -> Expr {
+    let mut cols: Vec<Expr> = ts.columns.iter().map(ident).collect();
+
+    match ts.format.as_str() {
+        "epoch" => to_timestamp(cols),
+        "epoch_ms" => to_timestamp_millis(vec![cast(cols.remove(0), DataType::Int64)]),
+        fmt => {
+            let s = if cols.len() == 1 {
+                cols.remove(0)
+            } else {
+                concat_ws(lit(" "), cols)
+            };
+            to_timestamp(vec![s, lit(fmt)])
+        }
+    }
+}
 
 /// Async reading and writing
 ///
 #[tracing::instrument]
 pub async fn parquet_through_df(fname: &str, output: &str, opts: Options) -> Result<()> {
-    // nh = no header line (default = false which means has header line).
-    //
-    let header = opts.header;
-    let delim = opts.delim;
-
     // Setup datafusion for csv files
     //
     let ctx = SessionContext::new();
 
     // Do the reading
     //
-    let df = ctx
-        .read_csv(
-            fname,
-            CsvReadOptions::default()
-                .delimiter(delim)
-                .has_header(header),
-        )
-        .await?;
-
-    let fname = "../data/test-df.parquet";
+    let df = read_df(&ctx, fname, &opts).await?;
 
     let dfopts = DataFrameWriteOptions::default().with_single_file_output(true);
 
@@ -45,7 +101,45 @@ pub async fn parquet_through_df(fname: &str, output: &str, opts: Options) -> Res
     options.global.statistics_enabled = Some("page".to_string());
     options.global.compression = Some("zstd(8)".to_string());
 
-    let _ = df.write_parquet(fname, dfopts, Some(options)).await?;
+    let _ = df.write_parquet(output, dfopts, Some(options)).await?;
 
     Ok(())
 }
+
+/// Streaming version, every record batch is written as soon as it is read.  Returns the number
+/// of rows.
+///
+#[tracing::instrument]
+pub async fn stream_through_df(fname: &str, output: &str, opts: Options) -> Result<u64> {
+    let ctx = SessionContext::new();
+
+    let df = read_df(&ctx, fname, &opts).await?;
+
+    let props = WriterProperties::builder()
+        .set_created_by(env!("CARGO_PKG_NAME").to_string())
+        .set_writer_version(WriterVersion::PARQUET_2_0)
+        .set_compression(Compression::ZSTD(ZstdLevel::try_new(8)?))
+        .set_max_row_group_size(opts.batch)
+        .build();
+
+    let schema = df.schema().inner().clone();
+    let file = File::create(output)?;
+    let mut writer = ArrowWriter::try_new(file, schema, Some(props))?;
+
+    let mut stream = df.execute_stream().await?;
+    let mut total = 0;
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        writer.write(&batch)?;
+
+        total += batch.num_rows() as u64;
+        eprint!("\r{} lines", total);
+        stderr().flush()?;
+    }
+    eprintln!();
+
+    let meta = writer.close()?;
+    info!("{} lines in {} row groups.", total, meta.row_groups.len());
+
+    Ok(total)
+}
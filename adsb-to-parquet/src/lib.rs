@@ -1,8 +1,18 @@
+pub use profile::*;
+
 pub mod arrow2;
 pub mod datafusion;
+mod profile;
+
+/// Default number of rows per batch (and row group) in streaming mode
+pub const BATCH_ROWS: usize = 500_000;
 
 #[derive(Debug)]
 pub struct Options {
     pub delim: u8,
     pub header: bool,
+    /// Rows per batch when streaming
+    pub batch: usize,
+    /// Schema profile, types are inferred if none
+    pub profile: Option<Profile>,
 }
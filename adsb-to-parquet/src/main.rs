@@ -3,15 +3,16 @@
 
 use crate::cli::Opts;
 use adsb_to_parquet::{
-    arrow2::{read_csv, write_chunk},
-    datafusion::parquet_through_df,
-    Options,
+    arrow2::{read_csv, stream_csv, write_chunk},
+    datafusion::{parquet_through_df, stream_through_df},
+    Options, Profile,
 };
 use clap::Parser;
 use eyre::Result;
 use fetiche_common::init_logging;
 use std::path::Path;
-use tracing::{debug, trace};
+use std::time::Instant;
+use tracing::{debug, trace, warn};
 
 mod cli;
 mod types;
@@ -38,11 +39,38 @@ async fn main() -> Result<()> {
 
     let output = opts.output.unwrap_or(format!("{}.parquet", base));
 
+    // Load the profile if any, command-line options win over it.
+    //
+    let profile = match &opts.profile {
+        Some(name) => Some(Profile::load(name, opts.profile_file.as_deref())?),
+        None => None,
+    };
+
     // nh = no header line (default = false which means has header line).
     //
-    let header = !opts.nh;
-    let delim = opts.delim.clone().as_bytes()[0];
-    let opt = Options { delim, header };
+    let header = if opts.nh {
+        false
+    } else {
+        profile.as_ref().and_then(|p| p.header).unwrap_or(true)
+    };
+    let delim = opts
+        .delim
+        .clone()
+        .or(profile.as_ref().and_then(|p| p.delimiter.clone()))
+        .unwrap_or(",".to_string())
+        .as_bytes()[0];
+
+    if opts.arrow2 && profile.as_ref().is_some_and(|p| p.timestamp.is_some()) {
+        warn!("arrow2 does not build the profile timestamp column");
+        eprintln!("WARNING: timestamp column is not built with -A, use datafusion.");
+    }
+
+    let opt = Options {
+        delim,
+        header,
+        batch: opts.batch_rows,
+        profile,
+    };
 
     eprintln!(
         "Reading {} with {} as delimiter",
@@ -50,9 +78,19 @@ async fn main() -> Result<()> {
         String::from_utf8(vec![opt.delim])?
     );
 
-    // arrow2 or datafusion?
+    // Streaming, memory use is bounded by the batch size
     //
-    if opts.arrow2 {
+    if opts.stream {
+        eprintln!("Streaming to {} by {} lines", output, opts.batch_rows);
+
+        let start = Instant::now();
+        let total = if opts.arrow2 {
+            stream_csv(&input, &output, opt)?
+        } else {
+            stream_through_df(&input, &output, opt).await?
+        };
+        eprintln!("{} lines done in {}ms.", total, start.elapsed().as_millis());
+    } else if opts.arrow2 {
         let (schema, data) = read_csv(&input, opt)?;
         debug!("data={:?}", data);
 
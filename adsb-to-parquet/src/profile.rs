@@ -0,0 +1,191 @@
+//! Schema profiles for the different CSV dialects.
+//!
+//! Receivers and exporters all have their own column set so instead of inferring types (or
+//! using a single hardcoded struct), a profile gives the type of every column, the delimiter,
+//! whether there is a header line and how to build a proper timestamp column.
+//!
+//! Built-in profiles are in `profiles.hcl` (`emit`, `readsb`, `tar1090` and `radarcape`), others
+//! can be defined in a file with the same syntax and loaded with `--profile-file`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use eyre::{eyre, Result};
+use serde::Deserialize;
+use tracing::trace;
+
+/// Current version of the profiles file
+const PVERSION: usize = 1;
+
+/// Type of a column
+///
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColType {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+/// One column, in file order
+///
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ctype: ColType,
+}
+
+/// How to build the timestamp column
+///
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Timestamp {
+    /// Name of the resulting column, replaced if it is one of the source columns
+    pub name: String,
+    /// Source columns, joined with a space
+    pub columns: Vec<String>,
+    /// chrono format, or "epoch" (seconds) or "epoch_ms"
+    pub format: String,
+}
+
+/// A schema profile
+///
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Profile {
+    /// Free text description
+    pub description: String,
+    /// Field delimiter
+    pub delimiter: Option<String>,
+    /// Is there a header line?
+    pub header: Option<bool>,
+    /// All columns
+    pub columns: Vec<Column>,
+    /// Optional timestamp
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Content of `profiles.hcl`
+///
+#[derive(Debug, Deserialize)]
+pub struct ProfileFile {
+    pub version: usize,
+    pub profile: BTreeMap<String, Profile>,
+}
+
+impl FromStr for ProfileFile {
+    type Err = eyre::Error;
+
+    /// Parse and check a profiles file
+    ///
+    fn from_str(s: &str) -> Result<Self> {
+        let file: ProfileFile = hcl::from_str(s)?;
+        if file.version != PVERSION {
+            return Err(eyre!(
+                "bad profiles version {}, need {}",
+                file.version,
+                PVERSION
+            ));
+        }
+        for (name, p) in file.profile.iter() {
+            p.check().map_err(|e| eyre!("profile {name}: {e}"))?;
+        }
+        Ok(file)
+    }
+}
+
+impl Profile {
+    /// Check that the timestamp refers to existing columns
+    ///
+    fn check(&self) -> Result<()> {
+        if self.columns.is_empty() {
+            return Err(eyre!("no columns"));
+        }
+        if let Some(ts) = &self.timestamp {
+            if ts.columns.is_empty() {
+                return Err(eyre!("no timestamp columns"));
+            }
+            if ts.format.starts_with("epoch") && ts.columns.len() != 1 {
+                return Err(eyre!("{} needs a single column", ts.format));
+            }
+            for c in ts.columns.iter() {
+                if !self.columns.iter().any(|col| &col.name == c) {
+                    return Err(eyre!("unknown timestamp column {c}"));
+                }
+            }
+        }
+        if let Some(d) = &self.delimiter {
+            if d.len() != 1 {
+                return Err(eyre!("delimiter must be a single character"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a profile by name, from `file` if specified or the built-in ones.
+    ///
+    #[tracing::instrument]
+    pub fn load(name: &str, file: Option<&Path>) -> Result<Self> {
+        trace!("Profile::load({name})");
+
+        let mut all = ProfileFile::from_str(include_str!("profiles.hcl"))?.profile;
+        if let Some(file) = file {
+            let data = fs::read_to_string(file)?;
+            all.extend(ProfileFile::from_str(&data)?.profile);
+        }
+        all.remove(name)
+            .ok_or_else(|| eyre!("unknown profile {name}"))
+    }
+
+    /// Names and descriptions of the built-in profiles
+    ///
+    pub fn builtin() -> Result<Vec<(String, String)>> {
+        let all = ProfileFile::from_str(include_str!("profiles.hcl"))?.profile;
+        Ok(all.into_iter().map(|(n, p)| (n, p.description)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_profiles() {
+        let all = Profile::builtin().unwrap();
+        let names: Vec<_> = all.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(vec!["emit", "radarcape", "readsb", "tar1090"], names);
+    }
+
+    #[test]
+    fn test_profile_load() {
+        let p = Profile::load("readsb", None).unwrap();
+        assert_eq!(Some(false), p.header);
+        assert_eq!(22, p.columns.len());
+        assert_eq!(ColType::Float, p.columns[14].ctype);
+
+        let ts = p.timestamp.unwrap();
+        assert_eq!(vec!["date_generated", "time_generated"], ts.columns);
+
+        assert!(Profile::load("nope", None).is_err());
+    }
+
+    #[test]
+    fn test_profile_bad_timestamp() {
+        let s = r#"
+version = 1
+profile "bad" {
+  description = "bad"
+  columns     = [{ name = "a", type = "int" }]
+  timestamp {
+    name    = "time"
+    columns = ["b"]
+    format  = "epoch"
+  }
+}
+"#;
+        assert!(ProfileFile::from_str(s).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+// Built-in schema profiles for the different CSV exports we get.
+//
+// Column types are "string", "int", "float" or "bool", columns must be listed in file order.
+// The optional timestamp block builds a `name` timestamp column from one or more columns
+// (joined with a space) using a chrono format, or "epoch"/"epoch_ms" for UNIX times.
+//
+version = 1
+
+profile "emit" {
+  description = "EMIT surveillance network, flattened Asterix Cat21"
+  delimiter   = ":"
+  header      = true
+  columns     = [
+    { name = "020.EmitterCategory", type = "int" },
+    { name = "040.GBS", type = "int" },
+    { name = "070.ModeA", type = "string" },
+    { name = "073.TimeRecPosition", type = "float" },
+    { name = "080.AircraftAddress", type = "string" },
+    { name = "131.Latitude", type = "float" },
+    { name = "131.Longitude", type = "float" },
+    { name = "140.GeometricAltitude", type = "float" },
+    { name = "145.FlightLevel", type = "float" },
+    { name = "155.BarometricVerticalRate", type = "float" },
+    { name = "157.RE", type = "string" },
+    { name = "157.GeometricVerticalRate", type = "float" },
+    { name = "160.GroundSpeed", type = "float" },
+    { name = "160.TrackAngle", type = "float" },
+    { name = "170.Callsign", type = "string" },
+    { name = "R", type = "string" },
+  ]
+}
+
+profile "readsb" {
+  description = "readsb/dump1090 SBS-1 (BaseStation) output, port 30003"
+  delimiter   = ","
+  header      = false
+  columns     = [
+    { name = "message_type", type = "string" },
+    { name = "transmission_type", type = "int" },
+    { name = "session_id", type = "int" },
+    { name = "aircraft_id", type = "int" },
+    { name = "hex_ident", type = "string" },
+    { name = "flight_id", type = "int" },
+    { name = "date_generated", type = "string" },
+    { name = "time_generated", type = "string" },
+    { name = "date_logged", type = "string" },
+    { name = "time_logged", type = "string" },
+    { name = "callsign", type = "string" },
+    { name = "altitude", type = "int" },
+    { name = "ground_speed", type = "float" },
+    { name = "track", type = "float" },
+    { name = "lat", type = "float" },
+    { name = "lon", type = "float" },
+    { name = "vertical_rate", type = "int" },
+    { name = "squawk", type = "string" },
+    { name = "alert", type = "int" },
+    { name = "emergency", type = "int" },
+    { name = "spi", type = "int" },
+    { name = "is_on_ground", type = "int" },
+  ]
+  timestamp {
+    name    = "time"
+    columns = ["date_generated", "time_generated"]
+    format  = "%Y/%m/%d %H:%M:%S%.3f"
+  }
+}
+
+profile "tar1090" {
+  description = "tar1090/readsb aircraft.json snapshots flattened to CSV"
+  delimiter   = ","
+  header      = true
+  columns     = [
+    { name = "now", type = "float" },
+    { name = "hex", type = "string" },
+    { name = "flight", type = "string" },
+    { name = "alt_baro", type = "int" },
+    { name = "alt_geom", type = "int" },
+    { name = "gs", type = "float" },
+    { name = "track", type = "float" },
+    { name = "baro_rate", type = "int" },
+    { name = "squawk", type = "string" },
+    { name = "category", type = "string" },
+    { name = "lat", type = "float" },
+    { name = "lon", type = "float" },
+    { name = "seen_pos", type = "float" },
+    { name = "rssi", type = "float" },
+  ]
+  timestamp {
+    name    = "time"
+    columns = ["now"]
+    format  = "epoch"
+  }
+}
+
+profile "radarcape" {
+  description = "Radarcape CSV log export"
+  delimiter   = ";"
+  header      = true
+  columns     = [
+    { name = "timestamp", type = "string" },
+    { name = "icao", type = "string" },
+    { name = "callsign", type = "string" },
+    { name = "squawk", type = "string" },
+    { name = "altitude", type = "int" },
+    { name = "latitude", type = "float" },
+    { name = "longitude", type = "float" },
+    { name = "speed", type = "float" },
+    { name = "heading", type = "float" },
+    { name = "vrate", type = "int" },
+    { name = "on_ground", type = "bool" },
+  ]
+  timestamp {
+    name    = "timestamp"
+    columns = ["timestamp"]
+    format  = "%Y-%m-%d %H:%M:%S%.f"
+  }
+}
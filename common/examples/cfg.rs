@@ -17,7 +17,7 @@ struct Foo {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_logging("cfg", false, false, None)?;
+    init_logging("cfg", false, false, None, false)?;
 
     let base = directories::BaseDirs::new().unwrap();
     dbg!(&base);
@@ -6,17 +6,34 @@
 //!
 //! This encapsulates the configuration file, available with `.inner()` or `.inner_mut()`.
 //!
+//! `load_layered()` implements layered configuration, each layer overriding the previous one:
+//!
+//! 1. built-in defaults (through `#[serde(default)]` on the struct),
+//! 2. the configuration file itself (e.g. `engine.hcl`),
+//! 3. an optional local file next to it (e.g. `engine.local.hcl`),
+//! 4. environment variables starting with `FETICHE_` (e.g. `FETICHE_BASEDIR=/data`), nested
+//!    keys are separated by `__` like in `FETICHE_STORAGE__LOCAL__PATH=/tmp`.
+//!
+//! The merged result is kept and can be displayed with `.effective()`.
+//!
+//! `Migrations` upgrade older files in place: every owner of a versioned file (engine, sources,
+//! etc.) registers one function per version bump and `migrate_file()` runs all the needed ones,
+//! keeping a copy of the original as `<file>.v<N>.bak`.  Files without a `version` are
+//! considered to be version 1.  Comments are not kept in the migrated HCL files.
+//!
 
 use crate::IntoConfig;
 
 use directories::BaseDirs;
 use eyre::{eyre, Result};
 use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs::read_dir;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::{env, fs};
-use tracing::{debug, error, trace};
+use tracing::{debug, error, info, trace};
 
 /// Config filename
 const CONFIG: &str = "config.hcl";
@@ -24,6 +41,81 @@ const CONFIG: &str = "config.hcl";
 /// Main name for the directory base
 const TAG: &str = "drone-utils";
 
+/// Prefix for environment variables overriding configuration values
+pub const ENV_PREFIX: &str = "FETICHE_";
+
+/// Separator for nested keys in environment variables
+const ENV_SEP: &str = "__";
+
+/// Explicit configuration directory, overriding the `$HOME`-based one.
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `dir` as the base directory for all configuration files instead of the default one
+/// derived from `$HOME` (or `%LOCALAPPDATA%`).  Must be called before any `load()` and only
+/// once, further calls are ignored.
+///
+pub fn set_config_dir(dir: &Path) {
+    if CONFIG_DIR.set(dir.to_path_buf()).is_err() {
+        error!("Configuration directory already set, ignoring {dir:?}");
+    }
+}
+
+/// Return the directory holding all configuration files, the same one used by `load()`.
+///
+pub fn config_dir() -> PathBuf {
+    base_dir(TAG)
+}
+
+/// Return the directory holding all configuration files for `tag`, either the one given to
+/// `set_config_dir()` or the default one derived from `$HOME` (or `%LOCALAPPDATA%`).
+///
+#[tracing::instrument]
+fn base_dir(tag: &str) -> PathBuf {
+    if let Some(dir) = CONFIG_DIR.get() {
+        debug!("base = {dir:?} (explicit)");
+        return dir.clone();
+    }
+
+    let base = BaseDirs::new();
+
+    match base {
+        Some(base) => {
+            #[cfg(unix)]
+            let base = base.home_dir().join(".config");
+
+            #[cfg(windows)]
+            let base = base.data_local_dir();
+
+            debug!("base = {base:?}");
+            let base = base.join(Path::new(tag));
+            base
+        }
+        None => {
+            #[cfg(unix)]
+            let homedir = std::env::var("HOME")
+                .map_err(|_| error!("No HOME variable defined, can not continue"))
+                .unwrap();
+
+            #[cfg(windows)]
+            let homedir = env::var("LOCALAPPDATA")
+                .map_err(|_| error!("No LOCALAPPDATA variable defined, can not continue"))
+                .unwrap();
+
+            debug!("base = {homedir}");
+
+            #[cfg(unix)]
+            let base = Path::new(&homedir)
+                .join(Path::new(".config"))
+                .join(Path::new(tag));
+
+            #[cfg(windows)]
+            let base = PathBuf::from(homedir).join(tag);
+
+            base
+        }
+    }
+}
+
 /// Configuration for the CLI tool, supposed to include parameters and most importantly
 /// credentials for the various sources.
 ///
@@ -34,6 +126,8 @@ pub struct ConfigFile<T: Debug + DeserializeOwned + IntoConfig> {
     /// This is the base directory for all files.
     root: PathBuf,
     inner: Option<T>,
+    /// Merged configuration when loaded through `load_layered()`
+    effective: Option<hcl::Value>,
 }
 
 impl<T> ConfigFile<T>
@@ -42,48 +136,11 @@ where
 {
     #[tracing::instrument]
     fn new(tag: &str) -> Self {
-        let base = BaseDirs::new();
-
-        let basedir: PathBuf = match base {
-            Some(base) => {
-                #[cfg(unix)]
-                let base = base.home_dir().join(".config");
-
-                #[cfg(windows)]
-                let base = base.data_local_dir();
-
-                debug!("base = {base:?}");
-                let base = base.join(Path::new(tag));
-                base
-            }
-            None => {
-                #[cfg(unix)]
-                let homedir = std::env::var("HOME")
-                    .map_err(|_| error!("No HOME variable defined, can not continue"))
-                    .unwrap();
-
-                #[cfg(windows)]
-                let homedir = env::var("LOCALAPPDATA")
-                    .map_err(|_| error!("No LOCALAPPDATA variable defined, can not continue"))
-                    .unwrap();
-
-                debug!("base = {homedir}");
-
-                #[cfg(unix)]
-                let base = Path::new(&homedir)
-                    .join(Path::new(".config"))
-                    .join(Path::new(tag));
-
-                #[cfg(windows)]
-                let base = PathBuf::from(homedir).join(tag);
-
-                base
-            }
-        };
         ConfigFile {
             tag: String::from(tag),
-            root: basedir,
+            root: base_dir(tag),
             inner: None,
+            effective: None,
         }
     }
 
@@ -153,28 +210,7 @@ where
         // FIXME: TAG is hardcoded.
         //
         let mut cfg = ConfigFile::<T>::new(TAG);
-
-        // Check is None was passed to get the default file from the default location:
-        //
-        let fname = if fname.is_none() {
-            let def = PathBuf::from(cfg.default_file()).canonicalize()?;
-            debug!("{:?}", def);
-            def
-        } else {
-            // Do we have a bare filename?
-            //
-            let fname = fname.unwrap();
-            let p = PathBuf::from(fname);
-
-            if p.file_name().unwrap() == p {
-                cfg.root.join(fname).canonicalize()?
-            } else {
-                // If it is relative or absolute, assume it exists and return its canonical form
-                //
-                PathBuf::from(fname).canonicalize()?
-            }
-        };
-        assert!(fname.is_absolute());
+        let fname = cfg.resolve(fname)?;
 
         trace!("Loading config file {fname:?} from {:?}", cfg.config_path());
 
@@ -192,6 +228,77 @@ where
         Ok(cfg)
     }
 
+    /// Load the file like `load()` then apply the local file and environment layers on top.
+    ///
+    /// For `engine.hcl`, the local file is `engine.local.hcl` in the same directory.
+    ///
+    #[tracing::instrument]
+    pub fn load_layered(fname: Option<&str>) -> Result<ConfigFile<T>> {
+        let mut cfg = ConfigFile::<T>::new(TAG);
+        let fname = cfg.resolve(fname)?;
+
+        trace!("Loading layered config file {fname:?}");
+
+        let data = fs::read_to_string(&fname)
+            .map_err(|e| eyre!("Error: failed to read config file {fname:?}: {e}"))?;
+        let mut value: hcl::Value = hcl::from_str(&data)?;
+
+        // Optional local overrides
+        //
+        let local = local_file(&fname);
+        if local.exists() {
+            debug!("Merging {local:?}");
+            let data = fs::read_to_string(&local)?;
+            merge(&mut value, hcl::from_str(&data)?);
+        }
+
+        // Environment
+        //
+        apply_env(&mut value, env::vars());
+        debug!("effective = {value:?}");
+
+        let data: T = hcl::from_value(value.clone())?;
+        cfg.inner = Some(data);
+        cfg.effective = Some(value);
+        Ok(cfg)
+    }
+
+    /// Return the merged configuration (all layers) as HCL, or `None` if not loaded through
+    /// `load_layered()`.
+    ///
+    pub fn effective(&self) -> Option<String> {
+        self.effective.as_ref().and_then(|v| hcl::to_string(v).ok())
+    }
+
+    /// Find the actual file to load.
+    ///
+    fn resolve(&self, fname: Option<&str>) -> Result<PathBuf> {
+        // Check is None was passed to get the default file from the default location:
+        //
+        let fname = match fname {
+            None => {
+                let def = PathBuf::from(self.default_file()).canonicalize()?;
+                debug!("{:?}", def);
+                def
+            }
+            Some(fname) => {
+                // Do we have a bare filename?
+                //
+                let p = PathBuf::from(fname);
+
+                if p.file_name().unwrap() == p {
+                    self.root.join(fname).canonicalize()?
+                } else {
+                    // If it is relative or absolute, assume it exists and return its canonical form
+                    //
+                    PathBuf::from(fname).canonicalize()?
+                }
+            }
+        };
+        assert!(fname.is_absolute());
+        Ok(fname)
+    }
+
     /// Return the inner configuration file
     ///
     pub fn inner(&self) -> &T {
@@ -230,13 +337,215 @@ where
     }
 }
 
+/// `engine.hcl` -> `engine.local.hcl`
+///
+fn local_file(fname: &Path) -> PathBuf {
+    let stem = fname.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = fname.extension().unwrap_or_default().to_string_lossy();
+    fname.with_file_name(format!("{stem}.local.{ext}"))
+}
+
+/// Deep merge of `other` into `base`, objects are merged key by key and everything else is
+/// replaced.
+///
+fn merge(base: &mut hcl::Value, other: hcl::Value) {
+    match (base, other) {
+        (hcl::Value::Object(base), hcl::Value::Object(other)) => {
+            for (k, v) in other {
+                match base.get_mut(&k) {
+                    Some(b) => merge(b, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// Apply all `FETICHE_*` variables on top of `value`.
+///
+fn apply_env<I>(value: &mut hcl::Value, vars: I)
+where
+    I: Iterator<Item = (String, String)>,
+{
+    for (k, v) in vars {
+        let Some(key) = k.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if key.is_empty() {
+            continue;
+        }
+        trace!("env override {k}");
+
+        let path: Vec<String> = key.split(ENV_SEP).map(|s| s.to_lowercase()).collect();
+        let mut overlay = env_value(&v);
+        for p in path.into_iter().rev() {
+            let mut map = hcl::Map::new();
+            map.insert(p, overlay);
+            overlay = hcl::Value::Object(map);
+        }
+        merge(value, overlay);
+    }
+}
+
+/// Environment values are always strings, guess the actual type.
+///
+fn env_value(v: &str) -> hcl::Value {
+    if let Ok(n) = v.parse::<i64>() {
+        hcl::Value::from(n)
+    } else if let Ok(f) = v.parse::<f64>() {
+        hcl::Value::from(f)
+    } else if let Ok(b) = v.parse::<bool>() {
+        hcl::Value::Bool(b)
+    } else {
+        hcl::Value::String(v.to_string())
+    }
+}
+
+/// Syntax of a versioned file
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Hcl,
+    Json,
+}
+
+/// One migration step from version `N` to `N + 1`, working on the parsed file.  The `version`
+/// field is updated afterwards, no need to do it in the step.
+///
+pub type Migration = fn(hcl::Value) -> Result<hcl::Value>;
+
+/// All the migrations for one kind of file.
+///
+/// Example:
+/// ```no_run
+/// use fetiche_common::{Migrations, Syntax};
+///
+/// let mut m = Migrations::new("engine.hcl", Syntax::Hcl, 3);
+/// m.step(2, |mut v| {
+///     // rename a key
+///     if let Some(obj) = v.as_object_mut() {
+///         if let Some(dir) = obj.shift_remove("datadir") {
+///             obj.insert("basedir".to_string(), dir);
+///         }
+///     }
+///     Ok(v)
+/// });
+/// m.migrate_file("/etc/fetiche/engine.hcl").unwrap();
+/// ```
+///
+#[derive(Clone, Debug)]
+pub struct Migrations {
+    /// File name, for messages
+    pub name: String,
+    /// How to read and write the file
+    pub syntax: Syntax,
+    /// Version we want to reach
+    pub current: usize,
+    /// `steps[N]` goes from version `N` to `N + 1`
+    steps: BTreeMap<usize, Migration>,
+}
+
+impl Migrations {
+    pub fn new(name: &str, syntax: Syntax, current: usize) -> Self {
+        Migrations {
+            name: name.to_string(),
+            syntax,
+            current,
+            steps: BTreeMap::new(),
+        }
+    }
+
+    /// Register the migration from version `from` to `from + 1`.
+    ///
+    pub fn step(&mut self, from: usize, f: Migration) -> &mut Self {
+        self.steps.insert(from, f);
+        self
+    }
+
+    /// Run all the steps needed to bring `value` to the current version.  Returns the original
+    /// version and the new value.
+    ///
+    #[tracing::instrument(skip(self, value))]
+    pub fn upgrade(&self, value: hcl::Value) -> Result<(usize, hcl::Value)> {
+        let orig = match value.as_object().and_then(|o| o.get("version")) {
+            Some(v) => v
+                .as_u64()
+                .ok_or_else(|| eyre!("{}: version is not a number", self.name))?
+                as usize,
+            None => 1,
+        };
+        if orig > self.current {
+            return Err(eyre!(
+                "{}: version {orig} is newer than {}, can not downgrade",
+                self.name,
+                self.current
+            ));
+        }
+
+        let mut value = value;
+        for v in orig..self.current {
+            let f = self
+                .steps
+                .get(&v)
+                .ok_or_else(|| eyre!("{}: no migration from version {v}", self.name))?;
+            debug!("{}: v{} -> v{}", self.name, v, v + 1);
+            value = f(value)?;
+            if let Some(obj) = value.as_object_mut() {
+                obj.insert("version".to_string(), hcl::Value::from(v as u64 + 1));
+            }
+        }
+        Ok((orig, value))
+    }
+
+    /// Upgrade the file in place if needed, the original being saved as `<file>.v<N>.bak`.
+    /// Returns the backup file name or `None` if the file was already up-to-date.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn migrate_file<P: AsRef<Path> + Debug>(&self, fname: P) -> Result<Option<PathBuf>> {
+        let fname = fname.as_ref();
+        let data =
+            fs::read_to_string(fname).map_err(|e| eyre!("Error: failed to read {fname:?}: {e}"))?;
+        let value: hcl::Value = match self.syntax {
+            Syntax::Hcl => hcl::from_str(&data)?,
+            Syntax::Json => serde_json::from_str(&data)?,
+        };
+
+        let (orig, value) = self.upgrade(value)?;
+        if orig == self.current {
+            trace!("{fname:?} is up-to-date");
+            return Ok(None);
+        }
+
+        let data = match self.syntax {
+            Syntax::Hcl => hcl::to_string(&value)?,
+            Syntax::Json => serde_json::to_string(&value)?,
+        };
+        let backup = backup_file(fname, orig);
+        fs::copy(fname, &backup)?;
+        fs::write(fname, data)?;
+
+        info!("{fname:?} migrated from v{orig} to v{}", self.current);
+        Ok(Some(backup))
+    }
+}
+
+/// `engine.hcl` -> `engine.hcl.v1.bak`
+///
+fn backup_file(fname: &Path, version: usize) -> PathBuf {
+    let name = fname.file_name().unwrap_or_default().to_string_lossy();
+    fname.with_file_name(format!("{name}.v{version}.bak"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{IntoConfig, Versioned};
     use fetiche_macros::into_configfile;
+    use rstest::rstest;
     use serde::{Deserialize, Serialize};
-    use std::collections::BTreeMap;
 
     /// Describe the possible ways to authenticate oneself
     ///
@@ -298,4 +607,146 @@ mod tests {
         assert_eq!(CVERSION, inner.version());
         Ok(())
     }
+
+    #[test]
+    fn test_local_file() {
+        let p = local_file(Path::new("/foo/engine.hcl"));
+        assert_eq!(PathBuf::from("/foo/engine.local.hcl"), p);
+    }
+
+    #[test]
+    fn test_merge_layers() {
+        let mut base: hcl::Value = hcl::from_str(
+            r#"
+            version = 2
+            basedir = "/var/db"
+            storage "local" {
+                path = "/tmp"
+                rotation = "1h"
+            }
+            "#,
+        )
+        .unwrap();
+        let local: hcl::Value = hcl::from_str(
+            r#"
+            storage "local" {
+                rotation = "1d"
+            }
+            "#,
+        )
+        .unwrap();
+        merge(&mut base, local);
+
+        let vars = vec![
+            ("FETICHE_BASEDIR".to_string(), "/data".to_string()),
+            ("FETICHE_WORKERS".to_string(), "8".to_string()),
+            (
+                "FETICHE_STORAGE__LOCAL__PATH".to_string(),
+                "/srv".to_string(),
+            ),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        apply_env(&mut base, vars.into_iter());
+
+        let at = |path: &[&str]| {
+            path.iter()
+                .fold(&base, |v, k| &v.as_object().unwrap()[*k])
+                .clone()
+        };
+        assert_eq!(hcl::Value::from("/data"), at(&["basedir"]));
+        assert_eq!(hcl::Value::from(8), at(&["workers"]));
+        assert_eq!(hcl::Value::from(2), at(&["version"]));
+        assert_eq!(
+            hcl::Value::from("1d"),
+            at(&["storage", "local", "rotation"])
+        );
+        assert_eq!(hcl::Value::from("/srv"), at(&["storage", "local", "path"]));
+        assert!(base.as_object().unwrap().get("home").is_none());
+    }
+
+    fn step_rename(v: hcl::Value) -> Result<hcl::Value> {
+        let mut v = v;
+        let obj = v.as_object_mut().unwrap();
+        if let Some(dir) = obj.shift_remove("datadir") {
+            obj.insert("basedir".to_string(), dir);
+        }
+        Ok(v)
+    }
+
+    fn step_workers(v: hcl::Value) -> Result<hcl::Value> {
+        let mut v = v;
+        v.as_object_mut()
+            .unwrap()
+            .insert("workers".to_string(), hcl::Value::from(4));
+        Ok(v)
+    }
+
+    #[test]
+    fn test_backup_file() {
+        let p = backup_file(Path::new("/foo/engine.hcl"), 1);
+        assert_eq!(PathBuf::from("/foo/engine.hcl.v1.bak"), p);
+    }
+
+    #[test]
+    fn test_migrations_upgrade() -> Result<()> {
+        let mut m = Migrations::new("engine.hcl", Syntax::Hcl, 3);
+        m.step(1, step_rename).step(2, step_workers);
+
+        // No version means v1
+        //
+        let v: hcl::Value = hcl::from_str(r#"datadir = "/data""#)?;
+        let (orig, v) = m.upgrade(v)?;
+        assert_eq!(1, orig);
+
+        let obj = v.as_object().unwrap();
+        assert_eq!(Some(&hcl::Value::from(3)), obj.get("version"));
+        assert_eq!(Some(&hcl::Value::from("/data")), obj.get("basedir"));
+        assert_eq!(Some(&hcl::Value::from(4)), obj.get("workers"));
+        assert!(obj.get("datadir").is_none());
+
+        // Already there
+        //
+        let v: hcl::Value = hcl::from_str("version = 3")?;
+        assert_eq!(3, m.upgrade(v)?.0);
+
+        // From the future
+        //
+        let v: hcl::Value = hcl::from_str("version = 4")?;
+        assert!(m.upgrade(v).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrations_missing_step() {
+        let mut m = Migrations::new("sources.hcl", Syntax::Hcl, 3);
+        m.step(2, step_workers);
+
+        let v: hcl::Value = hcl::from_str("version = 1").unwrap();
+        assert!(m.upgrade(v).is_err());
+    }
+
+    #[rstest]
+    #[case(Syntax::Hcl, "datadir = \"/data\"\n")]
+    #[case(Syntax::Json, r#"{"datadir": "/data"}"#)]
+    fn test_migrate_file(#[case] syntax: Syntax, #[case] data: &str) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("engine.hcl");
+        fs::write(&fname, data)?;
+
+        let mut m = Migrations::new("engine.hcl", syntax, 2);
+        m.step(1, step_rename);
+
+        let backup = m.migrate_file(&fname)?;
+        assert_eq!(Some(dir.path().join("engine.hcl.v1.bak")), backup);
+        assert_eq!(data, fs::read_to_string(backup.unwrap())?);
+
+        let migrated = fs::read_to_string(&fname)?;
+        assert!(migrated.contains("basedir"));
+        assert!(!migrated.contains("datadir"));
+
+        // Second run is a no-op
+        //
+        assert_eq!(None, m.migrate_file(&fname)?);
+        Ok(())
+    }
 }
@@ -5,7 +5,8 @@
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use strum::{EnumString, VariantNames};
-use tabled::{builder::Builder, settings::Style};
+
+use crate::{Listing, OutputFormat};
 
 /// Current `containers.hcl` version (forked from `formats.hcl`).
 ///
@@ -56,15 +57,18 @@ pub enum Container {
     CSV,
     /// Apache Parquet
     Parquet,
+    /// GeoParquet, Parquet with a WKB geometry column
+    GeoParquet,
     /// RAW Files
     #[default]
     Raw,
 }
 
 impl Container {
-    /// List all supported container formats into a string using `tabled`.
+    /// List all supported container formats.  Tables merge description, source & URL into one
+    /// column, JSON and CSV keep them as separate fields.
     ///
-    pub fn list() -> eyre::Result<String> {
+    pub fn list(fmt: OutputFormat) -> eyre::Result<String> {
         let descr = include_str!("containers.hcl");
         let fstr: ContainerFile = hcl::from_str(descr)?;
 
@@ -72,29 +76,47 @@ impl Container {
         //
         assert_eq!(fstr.version, CVERSION);
 
-        let header = vec!["Name", "Type", "Description"];
-
-        let mut builder = Builder::default();
-        builder.push_record(header);
+        let mut list = match fmt {
+            OutputFormat::Table => Listing::new(
+                "List all formats",
+                &[
+                    ("Name", "name"),
+                    ("Type", "type"),
+                    ("Description", "description"),
+                ],
+            ),
+            _ => Listing::new(
+                "List all formats",
+                &[
+                    ("Name", "name"),
+                    ("Type", "type"),
+                    ("Description", "description"),
+                    ("Source", "source"),
+                    ("URL", "url"),
+                ],
+            ),
+        };
 
         fstr.format.iter().for_each(|(name, entry)| {
-            let mut row = vec![];
-
-            let name = name.clone();
-            let dtype = entry.dtype.clone();
-            let description = entry.description.clone();
-            let source = entry.source.clone();
-            let url = entry.url.clone();
-
-            let row_text = format!("{}\nSource: {} -- URL: {}", description, source, url);
-            let dtype = dtype.to_string();
-            row.push(&name);
-            row.push(&dtype);
-            row.push(&row_text);
-            builder.push_record(row);
+            let row = match fmt {
+                OutputFormat::Table => vec![
+                    name.clone(),
+                    entry.dtype.clone(),
+                    format!(
+                        "{}\nSource: {} -- URL: {}",
+                        entry.description, entry.source, entry.url
+                    ),
+                ],
+                _ => vec![
+                    name.clone(),
+                    entry.dtype.clone(),
+                    entry.description.clone(),
+                    entry.source.clone(),
+                    entry.url.clone(),
+                ],
+            };
+            list.push(row);
         });
-        let allf = builder.build().with(Style::modern()).to_string();
-        let str = format!("List all formats:\n{allf}");
-        Ok(str)
+        list.render(fmt)
     }
 }
@@ -15,3 +15,10 @@ format "Parquet" {
   source      = "Apache"
   url         = "https://parquet.apache.org/docs/file-format/"
 }
+
+format "GeoParquet" {
+  type        = "write"
+  description = "GeoParquet 1.1 with WKB geometry (points or trajectories), bbox column and CRS."
+  source      = "OGC"
+  url         = "https://geoparquet.org/releases/v1.1.0/"
+}
@@ -1,12 +1,14 @@
 use std::ops::{Add, Sub};
+use std::str::FromStr;
 
 use chrono::{DateTime, Datelike, Days, Months, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
 use clap::Parser;
 use eyre::Report;
 use thiserror::Error;
 use tracing::trace;
 
-use crate::normalise_day;
+use crate::{normalise_day, Window};
 
 /// Enum of supported options for the date formats.
 ///
@@ -24,12 +26,18 @@ pub enum DateOpts {
     Today,
     /// Shortcut to yesterday
     Yesterday,
+    /// Sliding window like "last 15m" or "previous full hour" (UTC), see `Window`
+    Window { expr: String },
 }
 
 #[derive(Debug, Error)]
 pub enum ErrDateOpts {
     #[error("bad date: {0}")]
     BadDate(String),
+    #[error(
+        "bad window: {0}, need last <N>s|m|h|d, previous full hour|day|month, today or yesterday"
+    )]
+    BadWindow(String),
 }
 
 impl From<Report> for ErrDateOpts {
@@ -115,6 +123,10 @@ impl DateOpts {
                 trace!("begin={} end={}", begin, end);
                 (begin, end)
             }
+            DateOpts::Window { expr } => {
+                trace!("Got window {}", expr);
+                Window::from_str(&expr)?.resolve(Utc::now(), Tz::UTC)
+            }
         })
     }
 }
@@ -138,4 +150,21 @@ mod test {
         assert_eq!(dateparser::parse("2023-02-28 00:00:00 UTC").unwrap(), e);
         Ok(())
     }
+
+    #[test]
+    fn test_dateopts_window() {
+        let opt = DateOpts::Window {
+            expr: "last 15m".into(),
+        };
+        let (b, e) = DateOpts::parse(opt).unwrap();
+        assert_eq!(TimeDelta::minutes(15), e - b);
+
+        let opt = DateOpts::Window {
+            expr: "next week".into(),
+        };
+        assert!(matches!(
+            DateOpts::parse(opt),
+            Err(ErrDateOpts::BadWindow(_))
+        ));
+    }
 }
@@ -0,0 +1,232 @@
+//! Geodesy helpers shared by all crates.
+//!
+//! All positions are in degrees (latitude, longitude), distances in meters and bearings in
+//! degrees from true North, in [0, 360).
+//!
+//! - `haversine()`: great-circle distance on a sphere, fast and good to ~0.5%
+//! - `flat_distance()`: equirectangular approximation, fine for a few kilometers
+//! - `vincenty()`: distance on the WGS84 ellipsoid, iterative, sub-millimeter
+//! - `karney()`: same through [GeographicLib], always converges (even near-antipodal points)
+//! - `bearing()` and `destination()` on the sphere
+//! - `point_in_polygon()` for (lon, lat) polygons like `BB::to_polygon()`
+//!
+//! [GeographicLib]: https://geographiclib.sourceforge.io/
+//!
+
+use geographiclib_rs::{Geodesic, InverseGeodesic};
+
+/// Mean Earth radius (IUGG) in meters
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// WGS84 semi-major axis in meters
+pub const WGS84_A: f64 = 6_378_137.;
+
+/// WGS84 flattening
+pub const WGS84_F: f64 = 1. / 298.257_223_563;
+
+/// Great-circle distance between two points.
+///
+pub fn haversine(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = phi2 - phi1;
+    let dlambda = (lon2 - lon1).to_radians();
+
+    let h = (dphi / 2.).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Equirectangular distance between two close points.
+///
+pub fn flat_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let x = (lon2 - lon1).to_radians() * ((lat1 + lat2) / 2.).to_radians().cos();
+    let y = (lat2 - lat1).to_radians();
+    EARTH_RADIUS * x.hypot(y)
+}
+
+/// Initial great-circle bearing from the first point to the second.
+///
+pub fn bearing(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dlambda = (lon2 - lon1).to_radians();
+
+    let y = dlambda.sin() * phi2.cos();
+    let x = phi1.cos() * phi2.sin() - phi1.sin() * phi2.cos() * dlambda.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.)
+}
+
+/// Point reached after `dist` meters from (lat, lon) on the great circle with initial
+/// `bearing`.  Returns (lat, lon).
+///
+pub fn destination(lat: f64, lon: f64, bearing: f64, dist: f64) -> (f64, f64) {
+    let phi1 = lat.to_radians();
+    let lambda1 = lon.to_radians();
+    let theta = bearing.to_radians();
+    let delta = dist / EARTH_RADIUS;
+
+    let phi2 = (phi1.sin() * delta.cos() + phi1.cos() * delta.sin() * theta.cos()).asin();
+    let lambda2 = lambda1
+        + (theta.sin() * delta.sin() * phi1.cos()).atan2(delta.cos() - phi1.sin() * phi2.sin());
+
+    // Normalise longitude into [-180, 180)
+    //
+    let lon2 = (lambda2.to_degrees() + 540.).rem_euclid(360.) - 180.;
+    (phi2.to_degrees(), lon2)
+}
+
+/// Distance on the WGS84 ellipsoid using Vincenty's inverse formula.  Returns `None` if it does
+/// not converge, which happens for nearly antipodal points, use `karney()` there.
+///
+pub fn vincenty(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> Option<f64> {
+    let b = WGS84_A * (1. - WGS84_F);
+
+    let l = (lon2 - lon1).to_radians();
+    let u1 = ((1. - WGS84_F) * lat1.to_radians().tan()).atan();
+    let u2 = ((1. - WGS84_F) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0. {
+            // Same point
+            return Some(0.);
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos2_alpha = 1. - sin_alpha.powi(2);
+        let cos_2sigma_m = if cos2_alpha == 0. {
+            // Equatorial line
+            0.
+        } else {
+            cos_sigma - 2. * sin_u1 * sin_u2 / cos2_alpha
+        };
+        let c = WGS84_F / 16. * cos2_alpha * (4. + WGS84_F * (4. - 3. * cos2_alpha));
+
+        let prev = lambda;
+        lambda = l
+            + (1. - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1. + 2. * cos_2sigma_m.powi(2))));
+
+        if (lambda - prev).abs() < 1e-12 {
+            let u_sq = cos2_alpha * (WGS84_A.powi(2) - b.powi(2)) / b.powi(2);
+            let a = 1. + u_sq / 16384. * (4096. + u_sq * (-768. + u_sq * (320. - 175. * u_sq)));
+            let bb = u_sq / 1024. * (256. + u_sq * (-128. + u_sq * (74. - 47. * u_sq)));
+            let delta_sigma = bb
+                * sin_sigma
+                * (cos_2sigma_m
+                    + bb / 4.
+                        * (cos_sigma * (-1. + 2. * cos_2sigma_m.powi(2))
+                            - bb / 6.
+                                * cos_2sigma_m
+                                * (-3. + 4. * sin_sigma.powi(2))
+                                * (-3. + 4. * cos_2sigma_m.powi(2))));
+            return Some(b * a * (sigma - delta_sigma));
+        }
+    }
+    None
+}
+
+/// Distance on the WGS84 ellipsoid using Karney's algorithm.
+///
+pub fn karney(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    Geodesic::wgs84().inverse(lat1, lon1, lat2, lon2)
+}
+
+/// Is `(lon, lat)` inside `polygon`, a list of (lon, lat) vertices?  Uses ray casting so points
+/// exactly on an edge may go either way.
+///
+pub fn point_in_polygon(point: (f64, f64), polygon: &[(f64, f64)]) -> bool {
+    let (x, y) = point;
+    let mut inside = false;
+
+    let mut j = polygon.len().wrapping_sub(1);
+    for (i, &(xi, yi)) in polygon.iter().enumerate() {
+        let (xj, yj) = polygon[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::BB;
+
+    use super::*;
+
+    /// Degrees, minutes, seconds
+    fn dms(d: f64, m: f64, s: f64) -> f64 {
+        d.signum() * (d.abs() + m / 60. + s / 3600.)
+    }
+
+    #[rstest]
+    #[case((48.8566, 2.3522), (51.5074, -0.1278), 343_556.5)] // Paris - London
+    #[case((50.8, 4.4), (49.6, 6.2), 184_973.2)] // Brussels - Luxembourg
+    #[case((0., 0.), (0., 1.), 111_195.1)] // one degree
+    fn test_haversine(#[case] p1: (f64, f64), #[case] p2: (f64, f64), #[case] res: f64) {
+        assert!((haversine(p1.0, p1.1, p2.0, p2.1) - res).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_vincenty_karney() {
+        // Flinders Peak to Buninyong, the reference from Vincenty's paper
+        //
+        let (lat1, lon1) = (dms(-37., 57., 3.7203), dms(144., 25., 29.5244));
+        let (lat2, lon2) = (dms(-37., 39., 10.1561), dms(143., 55., 35.3839));
+
+        assert!((vincenty(lat1, lon1, lat2, lon2).unwrap() - 54_972.271).abs() < 0.001);
+        assert!((karney(lat1, lon1, lat2, lon2) - 54_972.271).abs() < 0.001);
+        assert_eq!(Some(0.), vincenty(lat1, lon1, lat1, lon1));
+    }
+
+    #[test]
+    fn test_vincenty_antipodal() {
+        assert!(vincenty(0., 0., 0.5, 179.7).is_none());
+        assert!((karney(0., 0., 0.5, 179.5) - 19_936_288.579).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_bearing() {
+        let brg = bearing(48.8566, 2.3522, 51.5074, -0.1278);
+        assert!((brg - 330.021).abs() < 0.001);
+    }
+
+    #[rstest]
+    #[case(0., 1000.)]
+    #[case(90., 1000.)]
+    #[case(225., 5000.)]
+    #[case(330., 100_000.)]
+    fn test_destination(#[case] brg: f64, #[case] dist: f64) {
+        let (lat, lon) = destination(49., 2., brg, dist);
+        assert!((haversine(49., 2., lat, lon) - dist).abs() < 0.01);
+        assert!((bearing(49., 2., lat, lon) - brg).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_flat_distance() {
+        let (lat, lon) = destination(49., 2., 45., 2000.);
+        assert!((flat_distance(49., 2., lat, lon) - 2000.).abs() < 1.);
+    }
+
+    #[rstest]
+    #[case((6.2, 49.6), true)]
+    #[case((6.2, 50.1), false)]
+    #[case((7.0, 49.6), false)]
+    fn test_point_in_polygon(#[case] p: (f64, f64), #[case] res: bool) {
+        let bb = BB::from_lat_lon(49.6, 6.2, 25).to_polygon().unwrap();
+        assert_eq!(res, point_in_polygon(p, &bb));
+    }
+}
@@ -0,0 +1,128 @@
+//! Minimal HTTP health endpoint for liveness/readiness probes (think Kubernetes).
+//!
+//! This is deliberately tiny and does not depend on any HTTP framework, it runs in its own
+//! thread and answers:
+//!
+//! - `GET /healthz` with `200` as long as the process is alive,
+//! - `GET /readyz` with `200` once `set_ready(true)` has been called, `503` otherwise.
+//!
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use eyre::Result;
+use tracing::{debug, info, trace};
+
+/// Handle on the health endpoint, cheap to clone.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Health {
+    ready: Arc<AtomicBool>,
+}
+
+impl Health {
+    /// Start listening on `addr` in a separate thread.
+    ///
+    #[tracing::instrument]
+    pub fn serve<A: ToSocketAddrs + std::fmt::Debug>(addr: A) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Health endpoint on {:?}", listener.local_addr()?);
+
+        let health = Health::default();
+        let h = health.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = h.answer(stream) {
+                    debug!("health: {}", e);
+                }
+            }
+        });
+        Ok(health)
+    }
+
+    /// Mark the process as ready (or not) to do its job.
+    ///
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    /// Are we ready?
+    ///
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Return the status line and body for a given request line.
+    ///
+    fn route(&self, request: &str) -> (&'static str, &'static str) {
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+        match path {
+            "/healthz" => ("200 OK", "ok"),
+            "/readyz" if self.is_ready() => ("200 OK", "ready"),
+            "/readyz" => ("503 Service Unavailable", "not ready"),
+            _ => ("404 Not Found", "not found"),
+        }
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> Result<()> {
+        let mut request = String::new();
+        BufReader::new(&stream).read_line(&mut request)?;
+        trace!("health: {}", request.trim_end());
+
+        let (status, body) = self.route(&request);
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        )?;
+        Ok(stream.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("GET /healthz HTTP/1.1", false, "200 OK")]
+    #[case("GET /readyz HTTP/1.1", false, "503 Service Unavailable")]
+    #[case("GET /readyz HTTP/1.1", true, "200 OK")]
+    #[case("GET / HTTP/1.1", true, "404 Not Found")]
+    fn test_health_route(#[case] req: &str, #[case] ready: bool, #[case] status: &str) {
+        let h = Health::default();
+        h.set_ready(ready);
+        assert_eq!(status, h.route(req).0);
+    }
+
+    #[test]
+    fn test_health_serve() {
+        let h = Health::serve("127.0.0.1:0");
+        assert!(h.is_ok());
+    }
+
+    #[test]
+    fn test_health_answer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        write!(client, "GET /healthz HTTP/1.1\r\n\r\n").unwrap();
+
+        let (stream, _) = listener.accept().unwrap();
+        Health::default().answer(stream).unwrap();
+
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.ends_with("ok"));
+    }
+}
@@ -8,16 +8,27 @@ pub use container::*;
 pub use dateopts::*;
 pub use daterange::*;
 use eyre::Result;
+pub use health::*;
+pub use listing::*;
 pub use location::*;
+pub use redact::*;
 pub use runtime::*;
+pub use sort::*;
+pub use window::*;
 
 mod config;
 mod container;
 mod dateopts;
 mod daterange;
+pub mod geo;
+mod health;
+mod listing;
 mod location;
 mod macros;
+mod redact;
 mod runtime;
+mod sort;
+mod window;
 
 const NAME: &str = crate_name!();
 const VERSION: &str = crate_version!();
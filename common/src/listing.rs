@@ -0,0 +1,173 @@
+//! Output of all listing and inspection commands.
+//!
+//! A `Listing` is a set of rows with a display name (for tables) and a stable field name (for
+//! JSON and CSV) for each column.  Machine-readable output does not include the title and all
+//! values are strings so that field names and types do not change with the content.
+//!
+
+use eyre::Result;
+use serde_json::{Map, Value};
+use strum::{EnumString, VariantNames};
+use tabled::{builder::Builder, settings::Style};
+
+/// How listings are displayed
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, strum::Display, EnumString, VariantNames)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum OutputFormat {
+    /// Human-readable table
+    #[default]
+    Table,
+    /// Array of objects
+    Json,
+    /// CSV with a header line
+    Csv,
+}
+
+/// A list of rows to display
+///
+#[derive(Clone, Debug, Default)]
+pub struct Listing {
+    /// Title of the table
+    title: String,
+    /// Column names for the table
+    header: Vec<String>,
+    /// Field names for JSON & CSV
+    fields: Vec<String>,
+    /// All rows, same order as the columns
+    rows: Vec<Vec<String>>,
+    /// Table style, `modern` by default
+    rounded: bool,
+}
+
+impl Listing {
+    /// Create a listing from (column name, field name) pairs
+    ///
+    pub fn new(title: &str, columns: &[(&str, &str)]) -> Self {
+        Listing {
+            title: title.to_string(),
+            header: columns.iter().map(|(h, _)| h.to_string()).collect(),
+            fields: columns.iter().map(|(_, f)| f.to_string()).collect(),
+            rows: vec![],
+            rounded: false,
+        }
+    }
+
+    /// Use the `rounded` table style
+    ///
+    pub fn rounded(&mut self) -> &mut Self {
+        self.rounded = true;
+        self
+    }
+
+    /// Add a row, missing values are empty
+    ///
+    pub fn push(&mut self, mut row: Vec<String>) -> &mut Self {
+        row.resize(self.fields.len(), String::new());
+        self.rows.push(row);
+        self
+    }
+
+    /// Number of rows
+    ///
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Check whether it is empty or not
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Render in the given format
+    ///
+    pub fn render(&self, fmt: OutputFormat) -> Result<String> {
+        match fmt {
+            OutputFormat::Table => {
+                let mut builder = Builder::default();
+                builder.push_record(self.header.clone());
+                self.rows
+                    .iter()
+                    .for_each(|r| builder.push_record(r.clone()));
+
+                let mut table = builder.build();
+                let table = if self.rounded {
+                    table.with(Style::rounded()).to_string()
+                } else {
+                    table.with(Style::modern()).to_string()
+                };
+                Ok(format!("{}:\n{}", self.title, table))
+            }
+            OutputFormat::Json => {
+                let all = self
+                    .rows
+                    .iter()
+                    .map(|r| {
+                        let obj = self
+                            .fields
+                            .iter()
+                            .zip(r.iter())
+                            .map(|(f, v)| (f.clone(), Value::String(v.clone())))
+                            .collect::<Map<_, _>>();
+                        Value::Object(obj)
+                    })
+                    .collect::<Vec<_>>();
+                Ok(serde_json::to_string_pretty(&all)?)
+            }
+            OutputFormat::Csv => {
+                let mut wtr = csv::Writer::from_writer(vec![]);
+                wtr.write_record(&self.fields)?;
+                for r in self.rows.iter() {
+                    wtr.write_record(r)?;
+                }
+                Ok(String::from_utf8(wtr.into_inner()?)?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    fn listing() -> Listing {
+        let mut l = Listing::new("List all things", &[("Name", "name"), ("Path/URL", "url")]);
+        l.push(vec!["foo".to_string(), "http://foo/".to_string()])
+            .push(vec!["bar".to_string()]);
+        l
+    }
+
+    #[rstest]
+    #[case("table", OutputFormat::Table)]
+    #[case("JSON", OutputFormat::Json)]
+    #[case("csv", OutputFormat::Csv)]
+    fn test_output_format_from_str(#[case] s: &str, #[case] fmt: OutputFormat) {
+        assert_eq!(fmt, OutputFormat::from_str(s).unwrap());
+    }
+
+    #[test]
+    fn test_listing_table() {
+        let s = listing().render(OutputFormat::Table).unwrap();
+        assert!(s.starts_with("List all things:\n┌"));
+        assert!(s.contains("Path/URL"));
+    }
+
+    #[test]
+    fn test_listing_json() {
+        let s = listing().render(OutputFormat::Json).unwrap();
+        let v: Value = serde_json::from_str(&s).unwrap();
+        assert_eq!("http://foo/", v[0]["url"]);
+        assert_eq!("", v[1]["url"]);
+    }
+
+    #[test]
+    fn test_listing_csv() {
+        let s = listing().render(OutputFormat::Csv).unwrap();
+        assert_eq!("name,url\nfoo,http://foo/\nbar,\n", s);
+    }
+}
@@ -6,15 +6,29 @@
 //!
 //! NOTE: no more history as data is now loaded from the `sites.csv` file.
 //!
+//! Besides a point and a range, an `Area` can be a circle, a polygon or a corridor (a path with
+//! a width) with an optional altitude band.  Areas are read from and written to GeoJSON
+//! `FeatureCollection`s:
+//!
+//! - a `Point` with a `radius` property (meters) is a circle,
+//! - a `Polygon` is used with its outer ring,
+//! - a `LineString` with a `width` property (meters) is a corridor,
+//! - `name`, `floor` and `ceiling` (meters) are read from the properties as well.
+//!
+//! `Area::contains()` tests a position and `Area::bb()` gives the bounding box used in queries.
+//!
 use std::collections::BTreeMap;
 use std::fs;
+use std::path::Path;
 
-use eyre::Result;
+use eyre::{eyre, Result};
 use serde::Deserialize;
-use tabled::builder::Builder;
-use tabled::settings::Style;
+use serde_json::{json, Value};
 use tracing::trace;
 
+use crate::geo::{point_in_polygon, EARTH_RADIUS};
+use crate::{Listing, OutputFormat};
+
 /// one degree is circumference of earth / 360°, convert into nautical miles
 const ONE_DEG_NM: f64 = (40_000. / 1.852) / 360.;
 
@@ -55,7 +69,24 @@ impl Default for Location {
     }
 }
 
-#[derive(Debug)]
+impl Location {
+    /// Circle of `radius` meters around the site
+    ///
+    pub fn area(&self, radius: f64) -> Area {
+        Area {
+            name: self.name.clone(),
+            shape: Shape::Circle {
+                lat: self.latitude,
+                lon: self.longitude,
+                radius,
+            },
+            floor: None,
+            ceiling: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct BB {
     /// Longitude - X0
     pub min_lon: f64,
@@ -102,6 +133,23 @@ impl BB {
         }
     }
 
+    /// Smallest BB containing both
+    ///
+    pub fn union(&self, other: &BB) -> Self {
+        Self {
+            min_lon: self.min_lon.min(other.min_lon),
+            min_lat: self.min_lat.min(other.min_lat),
+            max_lon: self.max_lon.max(other.max_lon),
+            max_lat: self.max_lat.max(other.max_lat),
+        }
+    }
+
+    /// Is (lat, lon) inside, edges included?
+    ///
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+
     /// Generate an array with the four points in a BB
     ///
     #[tracing::instrument]
@@ -115,6 +163,231 @@ impl BB {
     }
 }
 
+/// Meters in one degree of latitude
+const ONE_DEG_M: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.;
+
+/// Shape of an `Area`, points are (lon, lat) like in GeoJSON
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// `radius` meters around (lat, lon)
+    Circle { lat: f64, lon: f64, radius: f64 },
+    /// Outer ring, closed or not
+    Polygon(Vec<(f64, f64)>),
+    /// Along `path`, `width` meters wide
+    Corridor { path: Vec<(f64, f64)>, width: f64 },
+}
+
+impl Shape {
+    /// Is (lat, lon) inside?
+    ///
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        match self {
+            Shape::Circle {
+                lat: clat,
+                lon: clon,
+                radius,
+            } => crate::geo::haversine(*clat, *clon, lat, lon) <= *radius,
+            Shape::Polygon(ring) => point_in_polygon((lon, lat), ring),
+            Shape::Corridor { path, width } => match path.as_slice() {
+                [] => false,
+                [p] => crate::geo::haversine(p.1, p.0, lat, lon) <= width / 2.,
+                _ => path
+                    .windows(2)
+                    .any(|w| segment_distance((lon, lat), w[0], w[1]) <= width / 2.),
+            },
+        }
+    }
+
+    /// Bounding box
+    ///
+    pub fn bb(&self) -> BB {
+        let around = |points: &[(f64, f64)], margin: f64| {
+            let mut bb = BB {
+                min_lon: f64::MAX,
+                min_lat: f64::MAX,
+                max_lon: f64::MIN,
+                max_lat: f64::MIN,
+            };
+            for &(lon, lat) in points {
+                let dlat = margin / ONE_DEG_M;
+                let dlon = dlat / lat.to_radians().cos().max(1e-6);
+                bb = bb.union(&BB {
+                    min_lon: lon - dlon,
+                    min_lat: lat - dlat,
+                    max_lon: lon + dlon,
+                    max_lat: lat + dlat,
+                });
+            }
+            bb
+        };
+
+        match self {
+            Shape::Circle { lat, lon, radius } => around(&[(*lon, *lat)], *radius),
+            Shape::Polygon(ring) => around(ring, 0.),
+            Shape::Corridor { path, width } => around(path, width / 2.),
+        }
+    }
+}
+
+/// Distance in meters from `p` to the segment `a`-`b`, all (lon, lat), on a plane tangent at `p`
+///
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let k = p.1.to_radians().cos();
+    let xy = |q: (f64, f64)| ((q.0 - p.0) * k * ONE_DEG_M, (q.1 - p.1) * ONE_DEG_M);
+    let ((ax, ay), (bx, by)) = (xy(a), xy(b));
+
+    let (dx, dy) = (bx - ax, by - ay);
+    let len = dx * dx + dy * dy;
+    let t = if len == 0. {
+        0.
+    } else {
+        (-(ax * dx + ay * dy) / len).clamp(0., 1.)
+    };
+    (ax + t * dx).hypot(ay + t * dy)
+}
+
+/// A named area with an optional altitude band (meters)
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Area {
+    /// Name
+    pub name: String,
+    /// What it looks like on a map
+    pub shape: Shape,
+    /// Lowest altitude
+    pub floor: Option<f64>,
+    /// Highest altitude
+    pub ceiling: Option<f64>,
+}
+
+impl Area {
+    /// Is the position inside?  Without `alt`, the altitude band is ignored.
+    ///
+    pub fn contains(&self, lat: f64, lon: f64, alt: Option<f64>) -> bool {
+        if let Some(alt) = alt {
+            if self.floor.is_some_and(|f| alt < f) || self.ceiling.is_some_and(|c| alt > c) {
+                return false;
+            }
+        }
+        self.shape.contains(lat, lon)
+    }
+
+    /// Bounding box
+    ///
+    pub fn bb(&self) -> BB {
+        self.shape.bb()
+    }
+
+    /// Read one GeoJSON `Feature`
+    ///
+    pub fn from_feature(feature: &Value) -> Result<Self> {
+        let props = &feature["properties"];
+        let geometry = &feature["geometry"];
+        let number = |name: &str| props[name].as_f64();
+        let point = |v: &Value| -> Result<(f64, f64)> {
+            match (v[0].as_f64(), v[1].as_f64()) {
+                (Some(lon), Some(lat)) => Ok((lon, lat)),
+                _ => Err(eyre!("bad position {v}")),
+            }
+        };
+        let points = |v: &Value| -> Result<Vec<(f64, f64)>> {
+            v.as_array()
+                .ok_or(eyre!("bad coordinates {v}"))?
+                .iter()
+                .map(point)
+                .collect()
+        };
+
+        let coordinates = &geometry["coordinates"];
+        let shape = match geometry["type"].as_str() {
+            Some("Point") => {
+                let (lon, lat) = point(coordinates)?;
+                let radius = number("radius").ok_or(eyre!("Point without radius"))?;
+                Shape::Circle { lat, lon, radius }
+            }
+            Some("Polygon") => Shape::Polygon(points(&coordinates[0])?),
+            Some("LineString") => {
+                let width = number("width").ok_or(eyre!("LineString without width"))?;
+                Shape::Corridor {
+                    path: points(coordinates)?,
+                    width,
+                }
+            }
+            t => return Err(eyre!("unsupported geometry {t:?}")),
+        };
+        Ok(Area {
+            name: props["name"].as_str().unwrap_or_default().to_string(),
+            shape,
+            floor: number("floor"),
+            ceiling: number("ceiling"),
+        })
+    }
+
+    /// Write as a GeoJSON `Feature`
+    ///
+    pub fn to_feature(&self) -> Value {
+        let mut props = json!({"name": self.name});
+        let geometry = match &self.shape {
+            Shape::Circle { lat, lon, radius } => {
+                props["radius"] = json!(radius);
+                json!({"type": "Point", "coordinates": [lon, lat]})
+            }
+            Shape::Polygon(ring) => {
+                let mut ring = ring.clone();
+                if ring.first() != ring.last() {
+                    ring.push(ring[0]);
+                }
+                let ring = ring.iter().map(|(x, y)| [x, y]).collect::<Vec<_>>();
+                json!({"type": "Polygon", "coordinates": [ring]})
+            }
+            Shape::Corridor { path, width } => {
+                props["width"] = json!(width);
+                let path = path.iter().map(|(x, y)| [x, y]).collect::<Vec<_>>();
+                json!({"type": "LineString", "coordinates": path})
+            }
+        };
+        if let Some(floor) = self.floor {
+            props["floor"] = json!(floor);
+        }
+        if let Some(ceiling) = self.ceiling {
+            props["ceiling"] = json!(ceiling);
+        }
+        json!({"type": "Feature", "geometry": geometry, "properties": props})
+    }
+}
+
+/// Read areas from a GeoJSON `FeatureCollection` (or a single `Feature`)
+///
+pub fn areas_from_geojson(data: &str) -> Result<Vec<Area>> {
+    let data: Value = serde_json::from_str(data)?;
+    match data["type"].as_str() {
+        Some("FeatureCollection") => data["features"]
+            .as_array()
+            .ok_or(eyre!("no features"))?
+            .iter()
+            .map(Area::from_feature)
+            .collect(),
+        Some("Feature") => Ok(vec![Area::from_feature(&data)?]),
+        t => Err(eyre!("not a GeoJSON feature: {t:?}")),
+    }
+}
+
+/// Write areas as a GeoJSON `FeatureCollection`
+///
+pub fn areas_to_geojson(areas: &[Area]) -> Value {
+    let features = areas.iter().map(Area::to_feature).collect::<Vec<_>>();
+    json!({"type": "FeatureCollection", "features": features})
+}
+
+/// Load areas from a GeoJSON file.
+///
+#[tracing::instrument]
+pub fn load_areas(fname: &Path) -> Result<Vec<Area>> {
+    trace!("enter");
+    areas_from_geojson(&fs::read_to_string(fname)?)
+}
+
 /// Load all locations from the `sites.csv` file instead of a separate `locations.hcl`.
 ///
 #[tracing::instrument]
@@ -142,44 +415,39 @@ pub fn load_locations(fname: Option<String>) -> Result<BTreeMap<String, Location
 /// List loaded locations
 ///
 #[tracing::instrument]
-pub fn list_locations(data: &BTreeMap<String, Location>, dist: u32) -> Result<String> {
+pub fn list_locations(
+    data: &BTreeMap<String, Location>,
+    dist: u32,
+    fmt: OutputFormat,
+) -> Result<String> {
     trace!("enter");
-    let header = vec![
-        "Location",
-        "Plus Code",
-        "Basename",
-        "Lat/Lon",
-        "Altitude",
-        "Polygon",
-    ];
-
-    let mut builder = Builder::default();
-    builder.push_record(header);
-
-    data.keys().for_each(|name| {
-        let mut row = vec![];
-
-        let loc = data.get(name).unwrap();
-        let code = loc.code.clone();
-        let ref_alt = format!("{}", loc.ref_altitude);
-        let basename = loc.basename.clone();
+    let mut list = Listing::new(
+        &format!("List all locations ({dist} nm)"),
+        &[
+            ("Location", "name"),
+            ("Plus Code", "code"),
+            ("Basename", "basename"),
+            ("Lat/Lon", "position"),
+            ("Altitude", "altitude"),
+            ("Polygon", "polygon"),
+        ],
+    );
+
+    data.iter().for_each(|(name, loc)| {
         let poly = BB::from_location(loc, dist);
-        let point = format!("{:.5}, {:.5}", loc.latitude, loc.longitude);
-        let poly = format!(
-            "{:.2}, {:.2}, {:.2}, {:.2}",
-            poly.min_lat, poly.min_lon, poly.max_lat, poly.max_lon
-        );
-        row.push(name);
-        row.push(&code);
-        row.push(&basename);
-        row.push(&point);
-        row.push(&ref_alt);
-        row.push(&poly);
-        builder.push_record(row);
+        list.push(vec![
+            name.clone(),
+            loc.code.clone(),
+            loc.basename.clone(),
+            format!("{:.5}, {:.5}", loc.latitude, loc.longitude),
+            format!("{}", loc.ref_altitude),
+            format!(
+                "{:.2}, {:.2}, {:.2}, {:.2}",
+                poly.min_lat, poly.min_lon, poly.max_lat, poly.max_lon
+            ),
+        ]);
     });
-
-    let allf = builder.build().with(Style::modern()).to_string();
-    Ok(format!("List all locations ({dist} nm):\n{allf}"))
+    list.render(fmt)
 }
 
 #[cfg(test)]
@@ -230,6 +498,96 @@ mod tests {
         Ok(())
     }
 
+    const AREAS: &str = r#"{
+  "type": "FeatureCollection",
+  "features": [
+    {
+      "type": "Feature",
+      "geometry": {"type": "Point", "coordinates": [2.565982, 49.009909]},
+      "properties": {"name": "CDG", "radius": 5000, "ceiling": 150}
+    },
+    {
+      "type": "Feature",
+      "geometry": {"type": "Polygon", "coordinates": [[[2, 48], [3, 48], [3, 49], [2, 49], [2, 48]]]},
+      "properties": {"name": "square"}
+    },
+    {
+      "type": "Feature",
+      "geometry": {"type": "LineString", "coordinates": [[2, 48], [2, 49]]},
+      "properties": {"name": "corridor", "width": 2000}
+    }
+  ]
+}"#;
+
+    #[test]
+    fn test_areas_contains() -> Result<()> {
+        let areas = areas_from_geojson(AREAS)?;
+        assert_eq!(3, areas.len());
+
+        let cdg = &areas[0];
+        assert!(cdg.contains(49.02, 2.57, None));
+        assert!(cdg.contains(49.02, 2.57, Some(100.)));
+        assert!(!cdg.contains(49.02, 2.57, Some(200.)));
+        assert!(!cdg.contains(49.1, 2.57, None));
+
+        assert!(areas[1].contains(48.5, 2.5, None));
+        assert!(!areas[1].contains(49.5, 2.5, None));
+
+        // 1 km either side of the meridian
+        //
+        assert!(areas[2].contains(48.5, 2.01, None));
+        assert!(!areas[2].contains(48.5, 2.02, None));
+        assert!(!areas[2].contains(49.1, 2., None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_areas_bb() -> Result<()> {
+        let areas = areas_from_geojson(AREAS)?;
+
+        let bb = areas[0].bb();
+        assert_eq!(shorten(49.009909 - 5000. / ONE_DEG_M), shorten(bb.min_lat));
+        assert!(bb.contains(49.0, 2.6) && !bb.contains(49.0, 2.7));
+
+        let bb = areas[1].bb();
+        assert_eq!(
+            (2., 48., 3., 49.),
+            (bb.min_lon, bb.min_lat, bb.max_lon, bb.max_lat)
+        );
+
+        let all = areas
+            .iter()
+            .map(Area::bb)
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        assert_eq!("47.991", shorten(all.min_lat));
+        assert_eq!("3.000", shorten(all.max_lon));
+        Ok(())
+    }
+
+    #[test]
+    fn test_areas_roundtrip() -> Result<()> {
+        let areas = areas_from_geojson(AREAS)?;
+        let data = areas_to_geojson(&areas).to_string();
+        assert_eq!(areas, areas_from_geojson(&data)?);
+
+        let loc = Location {
+            name: "CDG".to_string(),
+            latitude: 49.009909,
+            longitude: 2.565982,
+            ..Default::default()
+        };
+        assert_eq!(areas[0].shape, loc.area(5000.).shape);
+        Ok(())
+    }
+
+    #[test]
+    fn test_areas_bad() {
+        assert!(areas_from_geojson("{}").is_err());
+        let point = r#"{"type": "Feature", "geometry": {"type": "Point", "coordinates": [2, 48]}}"#;
+        assert!(areas_from_geojson(point).is_err());
+    }
+
     #[test_pretty_log::test]
     fn test_to_polygon() -> Result<()> {
         let loc = Location {
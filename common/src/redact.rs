@@ -0,0 +1,297 @@
+//! Field-level redaction of exported data
+//!
+//! A `Redaction` policy says what must not leave with the data: operator identity, full serial
+//! numbers or the position of the pilot (home point).  Policies are named and defined in the
+//! configuration file of each tool:
+//!
+//! ```hcl
+//! redact "public" {
+//!   drop_operator       = true
+//!   truncate_serial     = 6
+//!   drop_pilot_location = true
+//!   drop                = ["station_name"]
+//! }
+//! ```
+//!
+//! Fields are recognised by name, whatever the format.  Dropped fields are emptied (`null` in
+//! JSON, empty in CSV) instead of removed so the layout of the output does not change.
+//!
+
+use std::collections::BTreeMap;
+
+use csv::{ReaderBuilder, WriterBuilder};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Fields identifying the operator
+const OPERATOR: &[&str] = &[
+    "operator",
+    "operator_id",
+    "operator_name",
+    "pilot_name",
+    "owner",
+    "phone",
+    "email",
+];
+
+/// Fields holding a serial number or a drone identifier
+const SERIAL: &[&str] = &[
+    "ident",
+    "drone_id",
+    "uas_id",
+    "uas_serial",
+    "serial",
+    "serial_number",
+];
+
+/// Fields giving away the position of the pilot
+const PILOT_LOCATION: &[&str] = &[
+    "home_lat",
+    "home_lon",
+    "home_height",
+    "home_location",
+    "pilot_lat",
+    "pilot_lon",
+    "pilot_position",
+    "operator_lat",
+    "operator_lon",
+    "operator_position",
+    "distance_home_m",
+];
+
+/// Named redaction policies, as read from a configuration file
+///
+pub type Redactions = BTreeMap<String, Redaction>;
+
+/// What happens to a given field
+///
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Left as-is
+    Keep,
+    /// Emptied
+    Drop,
+    /// Only the first N characters are kept
+    Truncate(usize),
+}
+
+/// One redaction policy
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Redaction {
+    /// Remove everything identifying the operator
+    #[serde(default)]
+    pub drop_operator: bool,
+    /// Keep only the first N characters of serial numbers and drone IDs
+    pub truncate_serial: Option<usize>,
+    /// Remove the position of the pilot and home point
+    #[serde(default)]
+    pub drop_pilot_location: bool,
+    /// Other fields to remove
+    #[serde(default)]
+    pub drop: Vec<String>,
+}
+
+impl Redaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn drop_operator(&mut self, drop: bool) -> &mut Self {
+        self.drop_operator = drop;
+        self
+    }
+
+    pub fn truncate_serial(&mut self, len: usize) -> &mut Self {
+        self.truncate_serial = Some(len);
+        self
+    }
+
+    pub fn drop_pilot_location(&mut self, drop: bool) -> &mut Self {
+        self.drop_pilot_location = drop;
+        self
+    }
+
+    pub fn drop_field(&mut self, field: &str) -> &mut Self {
+        self.drop.push(field.to_string());
+        self
+    }
+
+    /// Does this policy change anything?
+    ///
+    pub fn is_empty(&self) -> bool {
+        !self.drop_operator
+            && self.truncate_serial.is_none()
+            && !self.drop_pilot_location
+            && self.drop.is_empty()
+    }
+
+    /// What to do with field `name`
+    ///
+    pub fn action(&self, name: &str) -> Action {
+        let name = name.to_lowercase();
+        let name = name.as_str();
+
+        if (self.drop_operator && OPERATOR.contains(&name))
+            || (self.drop_pilot_location && PILOT_LOCATION.contains(&name))
+            || self.drop.iter().any(|f| f.eq_ignore_ascii_case(name))
+        {
+            return Action::Drop;
+        }
+        match self.truncate_serial {
+            Some(len) if SERIAL.contains(&name) => Action::Truncate(len),
+            _ => Action::Keep,
+        }
+    }
+
+    /// Apply the policy on a JSON value, nested objects and arrays included
+    ///
+    pub fn apply(&self, value: &mut Value) {
+        match value {
+            Value::Object(obj) => self.apply_object(obj),
+            Value::Array(all) => all.iter_mut().for_each(|v| self.apply(v)),
+            _ => (),
+        }
+    }
+
+    fn apply_object(&self, obj: &mut Map<String, Value>) {
+        obj.iter_mut().for_each(|(k, v)| match self.action(k) {
+            Action::Keep => self.apply(v),
+            Action::Drop => *v = Value::Null,
+            Action::Truncate(len) => {
+                if let Value::String(s) = v {
+                    *s = truncate(s, len);
+                }
+            }
+        });
+    }
+
+    /// Redact a stream of JSON documents, one per line on output
+    ///
+    pub fn json(&self, input: &str) -> Result<String> {
+        let res = serde_json::Deserializer::from_str(input)
+            .into_iter::<Value>()
+            .map(|v| {
+                let mut v = v?;
+                self.apply(&mut v);
+                Ok(serde_json::to_string(&v)?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(res.join("\n"))
+    }
+
+    /// Redact CSV data, the first line must be the header.  It is kept in the output only if
+    /// `header` is set.
+    ///
+    pub fn csv(&self, input: &str, delim: u8, header: bool) -> Result<String> {
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(delim)
+            .has_headers(true)
+            .from_reader(input.as_bytes());
+        let actions = rdr
+            .headers()?
+            .iter()
+            .map(|h| self.action(h))
+            .collect::<Vec<_>>();
+
+        let mut wtr = WriterBuilder::new().delimiter(delim).from_writer(vec![]);
+        if header {
+            wtr.write_record(rdr.headers()?)?;
+        }
+        for rec in rdr.records() {
+            let rec = rec?;
+            let rec = rec.iter().zip(&actions).map(|(f, a)| match a {
+                Action::Keep => f.to_string(),
+                Action::Drop => "".to_string(),
+                Action::Truncate(len) => truncate(f, *len),
+            });
+            wtr.write_record(rec)?;
+        }
+        Ok(String::from_utf8(wtr.into_inner()?)?)
+    }
+}
+
+/// Keep the first `len` characters
+///
+#[inline]
+fn truncate(s: &str, len: usize) -> String {
+    s.chars().take(len).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn public() -> Redaction {
+        let mut r = Redaction::new();
+        r.drop_operator(true)
+            .truncate_serial(4)
+            .drop_pilot_location(true)
+            .drop_field("station_name");
+        r
+    }
+
+    #[rstest]
+    #[case("operator_id", Action::Drop)]
+    #[case("Home_Lat", Action::Drop)]
+    #[case("station_name", Action::Drop)]
+    #[case("ident", Action::Truncate(4))]
+    #[case("latitude", Action::Keep)]
+    fn test_redaction_action(#[case] name: &str, #[case] res: Action) {
+        assert_eq!(res, public().action(name));
+    }
+
+    #[test]
+    fn test_redaction_default() {
+        let r = Redaction::default();
+        assert!(r.is_empty());
+        assert_eq!(Action::Keep, r.action("operator_id"));
+        assert!(!public().is_empty());
+    }
+
+    #[test]
+    fn test_redaction_hcl() -> Result<()> {
+        let cfg = r##"
+redact "public" {
+  drop_operator   = true
+  truncate_serial = 4
+}
+"##;
+        #[derive(Deserialize)]
+        struct Cfg {
+            redact: Redactions,
+        }
+        let cfg: Cfg = hcl::from_str(cfg)?;
+        let r = &cfg.redact["public"];
+        assert!(r.drop_operator);
+        assert_eq!(Some(4), r.truncate_serial);
+        assert!(!r.drop_pilot_location);
+        Ok(())
+    }
+
+    #[test]
+    fn test_redaction_json() -> Result<()> {
+        let input = r##"[{"ident":"1581F5FJD239C00DW22E","home_lat":49.61,"latitude":49.6116,"pilot_position":{"latitude":49.6,"longitude":6.2}}]
+{"operator_id":"FIN87astrdge12k8","telemetry":{"drone_id":"ABCDEF"}}"##;
+        let res = public().json(input)?;
+        assert_eq!(
+            r##"[{"home_lat":null,"ident":"1581","latitude":49.6116,"pilot_position":null}]
+{"operator_id":null,"telemetry":{"drone_id":"ABCD"}}"##,
+            res
+        );
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(true, "ident:latitude:home_lat\n1581:49.6116:\n")]
+    #[case(false, "1581:49.6116:\n")]
+    fn test_redaction_csv(#[case] header: bool, #[case] res: &str) -> Result<()> {
+        let input = "ident:latitude:home_lat\n1581F5FJD239C00DW22E:49.6116:49.61\n";
+        assert_eq!(res, public().csv(input, b':', header)?);
+        Ok(())
+    }
+}
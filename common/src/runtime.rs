@@ -1,26 +1,63 @@
 //! Common logging and telemetry initializer
 //!
+//! Diagnostics never go to `stdout`, which is kept for data (fetched records, listings in JSON or
+//! CSV): the tree and JSON outputs are written on `stderr`, or in a file.
+//!
+//! Levels are set by `RUST_LOG` at startup and can be changed at runtime, per module, with
+//! `set_log_filter()` (e.g. `info,fetiche_engine=trace`).  On Unix, `SIGUSR1` reads the new
+//! directives from `log-filter` in the configuration directory, one or more per line and `#` for
+//! comments, or goes back to the startup ones if the file is not there.
+//!
 //! TODO: Add code for metrics.
 
-use eyre::Result;
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+
+use chrono::Utc;
+use eyre::{eyre, Result};
 use opentelemetry::trace::TracerProvider;
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{info, warn, Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
 use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::{reload, EnvFilter, Registry};
 use tracing_tree::HierarchicalLayer;
 
+use crate::config_dir;
+
+/// File with the directives read on `SIGUSR1`, in the configuration directory
+pub const LOG_FILTER: &str = "log-filter";
+
+/// Handle on the filter of the process, to change it at runtime
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Directives given at startup
+static STARTUP: OnceLock<String> = OnceLock::new();
+
+/// `use_json` sends all logs to `stderr`, one JSON object per line, which is what most log
+/// collectors expect when running inside a container.
+///
 #[tracing::instrument]
 pub fn init_logging(
     name: &'static str,
     use_telemetry: bool,
     use_tree: bool,
     use_file: Option<String>,
+    use_json: bool,
 ) -> Result<()> {
     // Initialise logging early
     //
     // Load filters from environment
     //
     let filter = EnvFilter::from_default_env();
+    let _ = STARTUP.set(filter.to_string());
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = FILTER.set(handle);
 
     // Do we want hierarchical output?
     //
@@ -64,15 +101,86 @@ pub fn init_logging(
         None
     };
 
+    // JSON on stderr?
+    //
+    let json = if use_json {
+        Some(
+            tracing_subscriber::fmt::layer()
+                .event_format(JsonFormat)
+                .with_writer(std::io::stderr),
+        )
+    } else {
+        None
+    };
+
     // Combine filters & exporters
     //
     tracing_subscriber::registry()
-        .with(file)
         .with(filter)
-        .with(tree)
+        .with(json)
+        .with(file)
+        .with(tree.map(|t| t.with_writer(std::io::stderr)))
         .with(otlp)
         .init();
 
+    #[cfg(unix)]
+    reload_on_signal()?;
+
+    Ok(())
+}
+
+/// Replace the current log filter, `directives` having the same syntax as `RUST_LOG`.
+///
+pub fn set_log_filter(directives: &str) -> Result<()> {
+    let filter = EnvFilter::try_new(directives)?;
+    let handle = FILTER.get().ok_or(eyre!("logging not initialised"))?;
+    handle.reload(filter)?;
+    info!("log filter now {:?}", directives);
+    Ok(())
+}
+
+/// Current log filter, if logging is initialised
+///
+pub fn log_filter() -> Option<String> {
+    FILTER.get()?.with_current(|f| f.to_string()).ok()
+}
+
+/// Directives from the content of a `log-filter` file, comments and blank lines removed.
+///
+fn read_directives(text: &str) -> String {
+    text.lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Read `log-filter` again, or go back to the startup directives.
+///
+fn reload_filter() {
+    let fname = config_dir().join(LOG_FILTER);
+    let directives = match fs::read_to_string(&fname) {
+        Ok(text) => read_directives(&text),
+        Err(_) => STARTUP.get().cloned().unwrap_or_default(),
+    };
+    if let Err(e) = set_log_filter(&directives) {
+        warn!("invalid log filter in {:?}: {}", fname, e);
+    }
+}
+
+/// Reload the filter every time we get `SIGUSR1`.
+///
+#[cfg(unix)]
+fn reload_on_signal() -> Result<()> {
+    use signal_hook::consts::SIGUSR1;
+    use signal_hook::iterator::Signals;
+
+    let mut signals = Signals::new([SIGUSR1])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            reload_filter();
+        }
+    });
     Ok(())
 }
 
@@ -80,3 +188,91 @@ pub fn init_logging(
 pub fn close_logging() {
     opentelemetry::global::shutdown_tracer_provider();
 }
+
+/// Collect all fields of an event into a JSON map.
+///
+struct JsonVisitor(Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            Value::from(format!("{:?}", value)),
+        );
+    }
+}
+
+/// One JSON object per event with timestamp, level, target, current spans and fields.
+///
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+
+        let mut fields = JsonVisitor(Map::new());
+        event.record(&mut fields);
+
+        let mut obj = Map::new();
+        obj.insert("timestamp".into(), Value::from(Utc::now().to_rfc3339()));
+        obj.insert("level".into(), Value::from(meta.level().to_string()));
+        obj.insert("target".into(), Value::from(meta.target()));
+        if let Some(scope) = ctx.event_scope() {
+            let spans = scope
+                .from_root()
+                .map(|span| span.name())
+                .collect::<Vec<_>>()
+                .join(":");
+            obj.insert("spans".into(), Value::from(spans));
+        }
+        obj.insert("fields".into(), Value::Object(fields.0));
+
+        writeln!(writer, "{}", Value::Object(obj))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_directives() {
+        let text = "# more details for the engine\ninfo\n\nfetiche_engine=trace # for now\n";
+        assert_eq!("info,fetiche_engine=trace", read_directives(text));
+        assert_eq!("", read_directives("# nothing\n"));
+    }
+
+    #[test]
+    fn test_set_log_filter_invalid() {
+        assert!(set_log_filter("fetiche_engine=loud").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! External merge sort for line-oriented data larger than memory.
+//!
+//! Input is read in chunks of at most `buffer` lines, each chunk is sorted in memory and written
+//! into a temporary file, then all chunks are merged into the output.  The sort is stable: lines
+//! comparing equal keep their input order.  Lines are kept as-is, line terminators included, so
+//! `\r\n` formats like SBS-1 go through untouched.
+//!
+
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use eyre::Result;
+use tracing::{debug, trace};
+
+/// Default number of lines sorted in memory
+pub const SORT_BUFFER: usize = 1_000_000;
+
+/// Used to name temporary directories of concurrent sorts
+static SORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// External merge sort parameters
+///
+#[derive(Clone, Debug)]
+pub struct ExtSort {
+    /// Where to put the temporary chunks
+    pub tmpdir: PathBuf,
+    /// Maximum number of lines in memory
+    pub buffer: usize,
+}
+
+impl ExtSort {
+    /// Temporary chunks go into a private directory under `tmpdir`
+    ///
+    pub fn new(tmpdir: &Path) -> Self {
+        ExtSort {
+            tmpdir: tmpdir.to_path_buf(),
+            buffer: SORT_BUFFER,
+        }
+    }
+
+    pub fn buffer(&mut self, lines: usize) -> &mut Self {
+        self.buffer = lines.max(1);
+        self
+    }
+
+    /// Sort all lines of `input` into `output` using `cmp`, returns the number of lines.
+    ///
+    #[tracing::instrument(skip(self, input, output, cmp))]
+    pub fn sort<R, W, F>(&self, input: R, output: &mut W, cmp: F) -> Result<usize>
+    where
+        R: BufRead,
+        W: Write,
+        F: Fn(&str, &str) -> Ordering,
+    {
+        let n = SORTS.fetch_add(1, AtomicOrdering::Relaxed);
+        let dir = self
+            .tmpdir
+            .join(format!(".sort-{}-{}", std::process::id(), n));
+        fs::create_dir_all(&dir)?;
+        let res = self.run(input, output, &cmp, &dir);
+        let _ = fs::remove_dir_all(&dir);
+        res
+    }
+
+    fn run<R, W, F>(&self, mut input: R, output: &mut W, cmp: &F, dir: &Path) -> Result<usize>
+    where
+        R: BufRead,
+        W: Write,
+        F: Fn(&str, &str) -> Ordering,
+    {
+        // Sort each chunk in memory, a single one does not need any temporary file
+        //
+        let mut chunks = vec![];
+        let mut total = 0;
+        loop {
+            let mut lines = Vec::with_capacity(self.buffer.min(SORT_BUFFER));
+            while lines.len() < self.buffer {
+                match next_line(&mut input)? {
+                    Some(line) => lines.push(line),
+                    None => break,
+                }
+            }
+            if lines.is_empty() {
+                break;
+            }
+            total += lines.len();
+            lines.sort_by(|a, b| cmp(a, b));
+
+            if chunks.is_empty() && lines.len() < self.buffer {
+                trace!("{} lines sorted in memory", total);
+                lines
+                    .iter()
+                    .try_for_each(|l| output.write_all(l.as_bytes()))?;
+                return Ok(total);
+            }
+
+            let fname = dir.join(format!("chunk-{}", chunks.len()));
+            let mut fh = BufWriter::new(File::create(&fname)?);
+            lines.iter().try_for_each(|l| fh.write_all(l.as_bytes()))?;
+            fh.flush()?;
+            chunks.push(fname);
+        }
+        debug!("{} lines in {} chunks", total, chunks.len());
+
+        // Merge, on equal keys the earliest chunk goes first to keep the sort stable
+        //
+        let mut readers = chunks
+            .iter()
+            .map(|f| Ok(BufReader::new(File::open(f)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let mut heads = readers
+            .iter_mut()
+            .map(next_line)
+            .collect::<Result<Vec<_>>>()?;
+        loop {
+            let mut min: Option<usize> = None;
+            for (i, head) in heads.iter().enumerate() {
+                if let Some(line) = head {
+                    match min {
+                        Some(m) if cmp(line, heads[m].as_ref().unwrap()) != Ordering::Less => (),
+                        _ => min = Some(i),
+                    }
+                }
+            }
+            let Some(i) = min else {
+                break;
+            };
+            output.write_all(heads[i].as_ref().unwrap().as_bytes())?;
+            heads[i] = next_line(&mut readers[i])?;
+        }
+        Ok(total)
+    }
+}
+
+/// Next line with its terminator, the last line of the input gets one if missing
+///
+fn next_line<R: BufRead>(r: &mut R) -> Result<Option<String>> {
+    let mut line = String::new();
+    Ok(match r.read_line(&mut line)? {
+        0 => None,
+        _ => {
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            Some(line)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Sort on the first field only so we can see if the sort is stable
+    ///
+    fn by_key(a: &str, b: &str) -> Ordering {
+        a.split(',').next().cmp(&b.split(',').next())
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    #[case(3)]
+    #[case(100)]
+    fn test_ext_sort(#[case] buffer: usize) -> Result<()> {
+        let dir = tempdir()?;
+        let input = "3,a\n1,a\n2,a\n1,b\r\n3,b\n1,c";
+
+        let mut out = vec![];
+        let n = ExtSort::new(dir.path())
+            .buffer(buffer)
+            .sort(input.as_bytes(), &mut out, by_key)?;
+        assert_eq!(6, n);
+        assert_eq!("1,a\n1,b\r\n1,c\n2,a\n3,a\n3,b\n", String::from_utf8(out)?);
+        assert_eq!(0, fs::read_dir(dir.path())?.count());
+        Ok(())
+    }
+}
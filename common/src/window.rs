@@ -0,0 +1,338 @@
+//! Sliding time windows for recurring jobs.
+//!
+//! A `Window` is a relative expression like `last 15m` or `previous full hour` which is only
+//! turned into an interval when asked with `resolve()`, a job run every hour with
+//! `previous full hour` gets a different interval on every run.
+//!
+//! Supported expressions (case does not matter):
+//!
+//! - `last <N><unit>`: the N seconds (`s`), minutes (`m`), hours (`h`) or days (`d`) up to now,
+//! - `previous full hour` (or `previous hour`),
+//! - `today`: from midnight up to now,
+//! - `yesterday` (or `previous full day`),
+//! - `previous full month` (or `previous month`).
+//!
+//! Hours, days and months follow the calendar of the given timezone so `yesterday` in
+//! `Europe/Paris` is 23 or 25 hours long around DST changes.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeDelta, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use tracing::trace;
+
+use crate::ErrDateOpts;
+
+/// A time window relative to when it is resolved
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Window {
+    /// The last N seconds
+    Last(i64),
+    /// The last complete hour
+    PreviousHour,
+    /// Since midnight
+    Today,
+    /// The day before
+    Yesterday,
+    /// The last complete month
+    PreviousMonth,
+}
+
+impl Window {
+    /// Interval `[begin, end)` of the window at `now`, in the calendar of `tz`.
+    ///
+    #[tracing::instrument]
+    pub fn resolve(&self, now: DateTime<Utc>, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        let local = now.with_timezone(&tz);
+        let (begin, end) = match self {
+            Window::Last(secs) => (now - TimeDelta::seconds(*secs), now),
+            Window::PreviousHour => {
+                // Go back to the start of the local hour, which is fine even when the local hour
+                // is repeated or skipped.
+                //
+                let into = TimeDelta::seconds((local.minute() * 60 + local.second()) as i64)
+                    + TimeDelta::nanoseconds(local.nanosecond() as i64);
+                let end = now - into;
+                (end - TimeDelta::hours(1), end)
+            }
+            Window::Today => (midnight(tz, local.date_naive()), now),
+            Window::Yesterday => {
+                let today = local.date_naive();
+                (midnight(tz, today.pred_opt().unwrap()), midnight(tz, today))
+            }
+            Window::PreviousMonth => {
+                let first = local.date_naive().with_day(1).unwrap();
+                let prev = first - Months::new(1);
+                (midnight(tz, prev), midnight(tz, first))
+            }
+        };
+        trace!(
+            "{} at {} in {} gives from {} to {}",
+            self,
+            now,
+            tz,
+            begin,
+            end
+        );
+        (begin, end)
+    }
+}
+
+/// Start of `day` in `tz`, the first valid time when midnight is skipped by a DST change.
+///
+fn midnight(tz: Tz, day: NaiveDate) -> DateTime<Utc> {
+    let mut t = day.and_hms_opt(0, 0, 0).unwrap();
+    loop {
+        if let Some(t) = tz.from_local_datetime(&t).earliest() {
+            return t.with_timezone(&Utc);
+        }
+        t += TimeDelta::minutes(15);
+    }
+}
+
+impl FromStr for Window {
+    type Err = ErrDateOpts;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s
+            .split_whitespace()
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+        let bad = || ErrDateOpts::BadWindow(s.to_string());
+
+        Ok(match words.as_slice() {
+            ["last", d] => Window::Last(duration(d).ok_or_else(bad)?),
+            ["previous", "full", "hour"] | ["previous", "hour"] => Window::PreviousHour,
+            ["today"] => Window::Today,
+            ["yesterday"] | ["previous", "full", "day"] | ["previous", "day"] => Window::Yesterday,
+            ["previous", "full", "month"] | ["previous", "month"] => Window::PreviousMonth,
+            _ => return Err(bad()),
+        })
+    }
+}
+
+/// "15m" into seconds, must be positive
+///
+fn duration(s: &str) -> Option<i64> {
+    let unit = match s.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        _ => return None,
+    };
+    match s[..s.len() - 1].parse::<i64>() {
+        Ok(n) if n > 0 => n.checked_mul(unit),
+        _ => None,
+    }
+}
+
+impl Display for Window {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Window::Last(secs) => match secs {
+                s if s % 86_400 == 0 => write!(f, "last {}d", s / 86_400),
+                s if s % 3_600 == 0 => write!(f, "last {}h", s / 3_600),
+                s if s % 60 == 0 => write!(f, "last {}m", s / 60),
+                s => write!(f, "last {}s", s),
+            },
+            Window::PreviousHour => write!(f, "previous full hour"),
+            Window::Today => write!(f, "today"),
+            Window::Yesterday => write!(f, "yesterday"),
+            Window::PreviousMonth => write!(f, "previous full month"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono_tz::Europe::Paris;
+    use chrono_tz::UTC;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn t(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[rstest]
+    #[case("last 15m", Window::Last(900))]
+    #[case("Last  2h", Window::Last(7200))]
+    #[case("last 30s", Window::Last(30))]
+    #[case("last 1d", Window::Last(86400))]
+    #[case("previous full hour", Window::PreviousHour)]
+    #[case("previous hour", Window::PreviousHour)]
+    #[case("today", Window::Today)]
+    #[case("yesterday", Window::Yesterday)]
+    #[case("previous full day", Window::Yesterday)]
+    #[case("previous full month", Window::PreviousMonth)]
+    fn test_window_parse(#[case] s: &str, #[case] res: Window) {
+        assert_eq!(res, Window::from_str(s).unwrap());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("last")]
+    #[case("last 15")]
+    #[case("last 0m")]
+    #[case("last -5m")]
+    #[case("last 15w")]
+    #[case("next hour")]
+    fn test_window_parse_bad(#[case] s: &str) {
+        assert!(Window::from_str(s).is_err());
+    }
+
+    #[rstest]
+    #[case("last 15m")]
+    #[case("last 90m")]
+    #[case("last 2d")]
+    #[case("previous full hour")]
+    #[case("yesterday")]
+    #[case("previous full month")]
+    fn test_window_display(#[case] s: &str) {
+        assert_eq!(s, Window::from_str(s).unwrap().to_string());
+    }
+
+    #[rstest]
+    #[case(
+        Window::Last(900),
+        "2024-05-12T10:07:42Z",
+        "2024-05-12T09:52:42Z",
+        "2024-05-12T10:07:42Z"
+    )]
+    #[case(
+        Window::PreviousHour,
+        "2024-05-12T10:07:42Z",
+        "2024-05-12T09:00:00Z",
+        "2024-05-12T10:00:00Z"
+    )]
+    #[case(
+        Window::Today,
+        "2024-05-12T10:07:42Z",
+        "2024-05-12T00:00:00Z",
+        "2024-05-12T10:07:42Z"
+    )]
+    #[case(
+        Window::Yesterday,
+        "2024-03-01T00:30:00Z",
+        "2024-02-29T00:00:00Z",
+        "2024-03-01T00:00:00Z"
+    )]
+    #[case(
+        Window::Yesterday,
+        "2025-01-01T08:00:00Z",
+        "2024-12-31T00:00:00Z",
+        "2025-01-01T00:00:00Z"
+    )]
+    #[case(
+        Window::PreviousMonth,
+        "2024-01-15T12:00:00Z",
+        "2023-12-01T00:00:00Z",
+        "2024-01-01T00:00:00Z"
+    )]
+    #[case(
+        Window::PreviousMonth,
+        "2024-03-31T23:59:59Z",
+        "2024-02-01T00:00:00Z",
+        "2024-03-01T00:00:00Z"
+    )]
+    fn test_window_resolve_utc(
+        #[case] w: Window,
+        #[case] now: &str,
+        #[case] begin: &str,
+        #[case] end: &str,
+    ) {
+        assert_eq!((t(begin), t(end)), w.resolve(t(now), UTC));
+    }
+
+    // Europe/Paris goes from +01:00 to +02:00 on 2024-03-31 at 01:00Z and back on 2024-10-27
+    // at 01:00Z.
+    //
+    #[rstest]
+    #[case(
+        Window::PreviousHour,
+        "2024-03-31T01:10:00Z",
+        "2024-03-31T00:00:00Z",
+        "2024-03-31T01:00:00Z"
+    )]
+    #[case(
+        Window::PreviousHour,
+        "2024-10-27T01:10:00Z",
+        "2024-10-27T00:00:00Z",
+        "2024-10-27T01:00:00Z"
+    )]
+    #[case(
+        Window::PreviousHour,
+        "2024-10-27T02:10:00Z",
+        "2024-10-27T01:00:00Z",
+        "2024-10-27T02:00:00Z"
+    )]
+    #[case(
+        Window::Today,
+        "2024-03-31T10:00:00Z",
+        "2024-03-30T23:00:00Z",
+        "2024-03-31T10:00:00Z"
+    )]
+    #[case(
+        Window::Yesterday,
+        "2024-04-01T10:00:00Z",
+        "2024-03-30T23:00:00Z",
+        "2024-03-31T22:00:00Z"
+    )]
+    #[case(
+        Window::Yesterday,
+        "2024-10-28T10:00:00Z",
+        "2024-10-26T22:00:00Z",
+        "2024-10-27T23:00:00Z"
+    )]
+    #[case(
+        Window::Yesterday,
+        "2024-03-31T22:30:00Z",
+        "2024-03-30T23:00:00Z",
+        "2024-03-31T22:00:00Z"
+    )]
+    #[case(
+        Window::PreviousMonth,
+        "2024-03-31T22:30:00Z",
+        "2024-02-29T23:00:00Z",
+        "2024-03-31T22:00:00Z"
+    )]
+    #[case(
+        Window::PreviousMonth,
+        "2024-11-01T00:30:00Z",
+        "2024-09-30T22:00:00Z",
+        "2024-10-31T23:00:00Z"
+    )]
+    fn test_window_resolve_paris(
+        #[case] w: Window,
+        #[case] now: &str,
+        #[case] begin: &str,
+        #[case] end: &str,
+    ) {
+        assert_eq!((t(begin), t(end)), w.resolve(t(now), Paris));
+    }
+
+    #[test]
+    fn test_window_dst_length() {
+        let (b, e) = Window::Yesterday.resolve(t("2024-04-01T10:00:00Z"), Paris);
+        assert_eq!(TimeDelta::hours(23), e - b);
+        let (b, e) = Window::Yesterday.resolve(t("2024-10-28T10:00:00Z"), Paris);
+        assert_eq!(TimeDelta::hours(25), e - b);
+    }
+
+    #[test]
+    fn test_midnight_skipped() {
+        // America/Santiago skips from 00:00 to 01:00 on 2024-09-08
+        //
+        let day = NaiveDate::from_ymd_opt(2024, 9, 8).unwrap();
+        assert_eq!(
+            t("2024-09-08T04:00:00Z"),
+            midnight(chrono_tz::America::Santiago, day)
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use fetiche_common::init_logging;
+use fetiche_common::{init_logging, OutputFormat};
 use fetiche_engine::Engine;
 use tokio::task;
 
@@ -9,7 +9,8 @@ async fn main() -> eyre::Result<()> {
     let engine = Engine::new();
     dbg!(&engine);
 
-    let str = task::spawn_blocking(move || engine.list_tokens().unwrap()).await?;
+    let str =
+        task::spawn_blocking(move || engine.list_tokens(OutputFormat::Table).unwrap()).await?;
     eprintln!("{}", str);
     Ok(())
 }
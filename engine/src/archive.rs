@@ -0,0 +1,546 @@
+//! Archival of job artifacts.
+//!
+//! Artifacts of finished jobs (see `JobEvent::Artifacts`) older than `after` days can be moved
+//! into another storage area, the "cold" storage, with `acutectl results archive`:
+//!
+//! ```hcl
+//! archive {
+//!   after     = 30
+//!   area      = "cold"
+//!   recipient = "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p"
+//!   identity  = "/etc/acute/archive.key"
+//! }
+//! ```
+//!
+//! Files are moved into `<area>/<job id>/`, directories keeping their layout.  With a
+//! `recipient` (an [age] public key), they are encrypted on the way and get an `.age` suffix;
+//! `identity` is the matching key file, only needed to get them back.
+//!
+//! Every archived file is recorded in `archive.jsonl` in the state directory, one JSON line per
+//! file, so that `acutectl results get` can still find the artifacts of a job once they are
+//! gone from where the job wrote them and restore them, decrypted.  Unlike the history, this
+//! index is never pruned.
+//!
+//! The history is used to find finished jobs, `after` must be less than `history_days` for
+//! them to be archived at all.
+//!
+//! [age]: https://age-encryption.org/
+//!
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace, warn};
+
+use fetiche_common::{Listing, OutputFormat};
+
+use crate::{job_runs, read_history, Engine, EngineStatus, StoreArea};
+
+/// Archive index in the state directory
+pub(crate) const ARCHIVE_FILE: &str = "archive.jsonl";
+
+/// Suffix of encrypted files
+const AGE_EXT: &str = "age";
+
+/// Archival policy from `engine.hcl`
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ArchiveConfig {
+    /// Days after the end of the job
+    pub after: u64,
+    /// Storage area receiving the artifacts, a directory
+    pub area: String,
+    /// age public key to encrypt with
+    #[serde(default)]
+    pub recipient: Option<String>,
+    /// age key file to decrypt with
+    #[serde(default)]
+    pub identity: Option<PathBuf>,
+}
+
+/// One archived file
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ArchiveEntry {
+    /// Job ID
+    pub id: usize,
+    /// Job name
+    pub name: String,
+    /// Where the job wrote it
+    pub original: PathBuf,
+    /// Where it is now
+    pub archived: PathBuf,
+    /// Name under the directory of the job, subdirectories included
+    pub file: PathBuf,
+    /// Encrypted with `recipient`
+    pub encrypted: bool,
+    /// Size of the original
+    pub size: u64,
+    /// When it was archived
+    pub at: i64,
+}
+
+/// Where an artifact of a job is
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    /// Still where the job wrote it
+    Live(PathBuf),
+    /// Moved into the archive
+    Archived(ArchiveEntry),
+    /// Gone, neither there nor archived
+    Missing(PathBuf),
+}
+
+/// Read the archive index, skipping lines we can not parse
+///
+pub(crate) fn read_index(fname: &Path) -> Result<Vec<ArchiveEntry>> {
+    if !fname.exists() {
+        return Ok(vec![]);
+    }
+    let data = fs::read_to_string(fname)?;
+    Ok(data
+        .lines()
+        .filter_map(|l| serde_json::from_str::<ArchiveEntry>(l).ok())
+        .collect())
+}
+
+/// Append one entry to the archive index
+///
+fn append_index(fname: &Path, entry: &ArchiveEntry) -> Result<()> {
+    let mut fh = OpenOptions::new().create(true).append(true).open(fname)?;
+    writeln!(fh, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// All files under `path` (itself if it is a file) with their path relative to its parent
+///
+fn files(path: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut todo = vec![path.to_path_buf()];
+    let mut all = vec![];
+    while let Some(p) = todo.pop() {
+        if p.is_dir() {
+            for entry in fs::read_dir(&p)? {
+                todo.push(entry?.path());
+            }
+        } else {
+            let rel = p.strip_prefix(base).unwrap_or(&p).to_path_buf();
+            all.push((p, rel));
+        }
+    }
+    all.sort();
+    Ok(all)
+}
+
+/// Move `from` into `to`, encrypting for `recipient` if set
+///
+fn store(from: &Path, to: &Path, recipient: Option<&age::x25519::Recipient>) -> Result<()> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = PathBuf::from(format!("{}.part", to.to_string_lossy()));
+    match recipient {
+        Some(recipient) => {
+            let enc = age::Encryptor::with_recipients(iter::once(recipient as _))
+                .map_err(|e| EngineStatus::Archive(e.to_string()))?;
+            let mut out = enc.wrap_output(File::create(&tmp)?)?;
+            io::copy(&mut File::open(from)?, &mut out)?;
+            out.finish()?.sync_all()?;
+        }
+        // Different filesystems are likely, do not bother with `rename()`
+        //
+        None => {
+            fs::copy(from, &tmp)?;
+        }
+    }
+    fs::rename(&tmp, to)?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Copy the archived file of `entry` into `to`, decrypting it with `identity` if needed
+///
+fn fetch(entry: &ArchiveEntry, to: &Path, identity: Option<&Path>) -> Result<()> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if !entry.encrypted {
+        fs::copy(&entry.archived, to)?;
+        return Ok(());
+    }
+
+    let Some(identity) = identity else {
+        return Err(EngineStatus::NoArchiveIdentity.into());
+    };
+    let ids = age::IdentityFile::from_file(identity.to_string_lossy().to_string())?
+        .into_identities()
+        .map_err(|e| EngineStatus::Archive(e.to_string()))?;
+    let dec = age::Decryptor::new(BufReader::new(File::open(&entry.archived)?))
+        .map_err(|e| EngineStatus::Archive(e.to_string()))?;
+    let mut input = dec
+        .decrypt(ids.iter().map(|id| id.as_ref() as &dyn age::Identity))
+        .map_err(|e| EngineStatus::Archive(e.to_string()))?;
+    io::copy(&mut input, &mut File::create(to)?)?;
+    Ok(())
+}
+
+/// Remove the empty directories under `path`, `path` included
+///
+fn prune_dirs(path: &Path) {
+    if let Ok(dir) = fs::read_dir(path) {
+        dir.flatten()
+            .filter(|e| e.path().is_dir())
+            .for_each(|e| prune_dirs(&e.path()));
+        let _ = fs::remove_dir(path);
+    }
+}
+
+impl Engine {
+    /// Path of the archive index
+    ///
+    #[inline]
+    pub fn archive_file(&self) -> PathBuf {
+        self.state_dir.join(ARCHIVE_FILE)
+    }
+
+    /// Directory of the archive area
+    ///
+    fn archive_dir(&self, cfg: &ArchiveConfig) -> Result<PathBuf> {
+        match self.storage.iter().find(|(name, _)| *name == &cfg.area) {
+            Some((_, StoreArea::Directory { path, .. })) | Some((_, StoreArea::Hive { path })) => {
+                Ok(path.clone())
+            }
+            _ => Err(EngineStatus::BadArchiveArea(cfg.area.clone()).into()),
+        }
+    }
+
+    /// Move the artifacts of jobs finished more than `after` days ago into the archive area.
+    /// With `dry_run`, only say what would be done.  Returns the archived files.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn archive_artifacts(&self, dry_run: bool) -> Result<Vec<ArchiveEntry>> {
+        let Some(cfg) = &self.archive else {
+            return Err(EngineStatus::NoArchive.into());
+        };
+        let dir = self.archive_dir(cfg)?;
+        let recipient = match &cfg.recipient {
+            Some(r) => Some(
+                age::x25519::Recipient::from_str(r)
+                    .map_err(|e| EngineStatus::BadArchiveKey(r.clone(), e.to_string()))?,
+            ),
+            None => None,
+        };
+        if cfg.after >= self.history_days {
+            warn!(
+                "Jobs are forgotten after {} days, nothing older can be archived",
+                self.history_days
+            );
+        }
+
+        let now = Utc::now().timestamp();
+        let limit = now - (cfg.after * 86_400) as i64;
+        let runs = job_runs(&read_history(&self.history_file(), 0)?);
+
+        let mut done = vec![];
+        for run in runs
+            .iter()
+            .filter(|r| r.ok == Some(true) && r.ended.is_some_and(|t| t <= limit))
+        {
+            for artifact in run.artifacts.iter().filter(|p| p.exists()) {
+                trace!("archive #{} {:?}", run.id, artifact);
+
+                for (file, rel) in files(artifact)? {
+                    let mut archived = dir.join(run.id.to_string()).join(&rel);
+                    if recipient.is_some() {
+                        let name = archived.file_name().unwrap_or_default().to_string_lossy();
+                        archived.set_file_name(format!("{name}.{AGE_EXT}"));
+                    }
+                    let entry = ArchiveEntry {
+                        id: run.id,
+                        name: run.name.clone(),
+                        original: file.clone(),
+                        archived,
+                        file: rel,
+                        encrypted: recipient.is_some(),
+                        size: fs::metadata(&file)?.len(),
+                        at: now,
+                    };
+                    if !dry_run {
+                        store(&file, &entry.archived, recipient.as_ref())?;
+                        append_index(&self.archive_file(), &entry)?;
+                        info!("Archived {:?} as {:?}", file, entry.archived);
+                    }
+                    done.push(entry);
+                }
+                if !dry_run && artifact.is_dir() {
+                    prune_dirs(artifact);
+                }
+            }
+        }
+        Ok(done)
+    }
+
+    /// Where the artifacts of job `id` are, live or archived.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn locate(&self, id: usize) -> Result<Vec<Location>> {
+        let archived = read_index(&self.archive_file())?
+            .into_iter()
+            .filter(|e| e.id == id)
+            .collect::<Vec<_>>();
+        let artifacts = job_runs(&read_history(&self.history_file(), 0)?)
+            .into_iter()
+            .find(|r| r.id == id)
+            .map(|r| r.artifacts)
+            .unwrap_or_default();
+
+        let mut all = artifacts
+            .into_iter()
+            .filter_map(|path| match path.exists() {
+                true => Some(Location::Live(path)),
+                false if archived.iter().any(|e| e.original.starts_with(&path)) => None,
+                false => Some(Location::Missing(path)),
+            })
+            .collect::<Vec<_>>();
+        all.extend(archived.into_iter().map(Location::Archived));
+
+        match all.is_empty() {
+            true => Err(EngineStatus::NoResults(id).into()),
+            false => Ok(all),
+        }
+    }
+
+    /// Copy every artifact of job `id` into `into`, archived ones being decrypted.  Returns the
+    /// restored files.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn restore(&self, id: usize, into: &Path) -> Result<Vec<PathBuf>> {
+        let identity = self.archive.as_ref().and_then(|cfg| cfg.identity.clone());
+
+        let mut done = vec![];
+        for loc in self.locate(id)? {
+            match loc {
+                Location::Live(path) => {
+                    for (file, rel) in files(&path)? {
+                        let to = into.join(rel);
+                        if let Some(dir) = to.parent() {
+                            fs::create_dir_all(dir)?;
+                        }
+                        fs::copy(&file, &to)?;
+                        done.push(to);
+                    }
+                }
+                Location::Archived(entry) => {
+                    let to = into.join(&entry.file);
+                    fetch(&entry, &to, identity.as_deref())?;
+                    done.push(to);
+                }
+                Location::Missing(path) => warn!("{:?} is gone and was not archived", path),
+            }
+        }
+        Ok(done)
+    }
+
+    /// Display the artifacts of job `id`, see `locate()`.
+    ///
+    pub fn show_results(&self, id: usize, fmt: OutputFormat) -> Result<String> {
+        let mut list = Listing::new(
+            &format!("Results of job #{id}"),
+            &[
+                ("Path", "path"),
+                ("Status", "status"),
+                ("Archived as", "archived"),
+                ("Size", "size"),
+                ("Archived on", "at"),
+            ],
+        );
+        self.locate(id)?.iter().for_each(|loc| {
+            let row = match loc {
+                Location::Live(path) => {
+                    vec![path.to_string_lossy().to_string(), "live".to_string()]
+                }
+                Location::Archived(e) => vec![
+                    e.original.to_string_lossy().to_string(),
+                    match e.encrypted {
+                        true => "encrypted",
+                        false => "archived",
+                    }
+                    .to_string(),
+                    e.archived.to_string_lossy().to_string(),
+                    e.size.to_string(),
+                    DateTime::from_timestamp(e.at, 0)
+                        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default(),
+                ],
+                Location::Missing(path) => {
+                    vec![path.to_string_lossy().to_string(), "missing".to_string()]
+                }
+            };
+            list.push(row);
+        });
+        list.render(fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+    use tempfile::tempdir;
+
+    use crate::{append_history, HistoryEntry, JobEvent, Storage, HISTORY_FILE};
+
+    use super::*;
+
+    /// Engine with a `cold` area and job #1 finished 10 days ago, with a file and a directory
+    ///
+    fn setup(dir: &Path, cfg: ArchiveConfig) -> Result<Engine> {
+        let mut engine = Engine::local(Some(dir.join("state")))?;
+        let mut areas = Storage::register(&Default::default());
+        areas.insert(
+            "cold",
+            StoreArea::Directory {
+                path: dir.join("cold"),
+                rotation: 0,
+            },
+        );
+        engine.storage = std::sync::Arc::new(areas);
+        engine.archive = Some(cfg);
+
+        let out = dir.join("out");
+        fs::create_dir_all(out.join("tracks"))?;
+        fs::write(out.join("all.csv"), "a,b\n1,2\n")?;
+        fs::write(out.join("tracks").join("4CA2D6.csv"), "x\n")?;
+
+        let at = Utc::now().timestamp() - 10 * 86_400;
+        let fname = dir.join("state").join(HISTORY_FILE);
+        let events = [
+            JobEvent::Finished { seconds: 1 },
+            JobEvent::Artifacts {
+                path: out.join("all.csv"),
+            },
+            JobEvent::Artifacts {
+                path: out.join("tracks"),
+            },
+        ];
+        for event in events {
+            append_history(
+                &fname,
+                &HistoryEntry {
+                    id: 1,
+                    name: "daily".to_string(),
+                    at,
+                    event,
+                },
+            )?;
+        }
+        Ok(engine)
+    }
+
+    #[test]
+    fn test_archive_plain() -> Result<()> {
+        let dir = tempdir()?;
+        let cfg = ArchiveConfig {
+            after: 7,
+            area: "cold".to_string(),
+            ..ArchiveConfig::default()
+        };
+        let engine = setup(dir.path(), cfg)?;
+
+        assert_eq!(2, engine.archive_artifacts(true)?.len());
+        assert!(dir.path().join("out").join("all.csv").exists());
+
+        let done = engine.archive_artifacts(false)?;
+        assert_eq!(2, done.len());
+        assert_eq!(
+            dir.path()
+                .join("cold")
+                .join("1")
+                .join("tracks")
+                .join("4CA2D6.csv"),
+            done[1].archived
+        );
+        assert!(!dir.path().join("out").join("all.csv").exists());
+        assert!(!dir.path().join("out").join("tracks").exists());
+        assert_eq!(2, read_index(&engine.archive_file())?.len());
+
+        // Nothing left to archive
+        //
+        assert!(engine.archive_artifacts(false)?.is_empty());
+
+        let locs = engine.locate(1)?;
+        assert_eq!(2, locs.len());
+        assert!(locs.iter().all(|l| matches!(l, Location::Archived(_))));
+
+        let into = dir.path().join("restored");
+        let files = engine.restore(1, &into)?;
+        assert_eq!(2, files.len());
+        assert_eq!("a,b\n1,2\n", fs::read_to_string(into.join("all.csv"))?);
+        assert_eq!(
+            "x\n",
+            fs::read_to_string(into.join("tracks").join("4CA2D6.csv"))?
+        );
+
+        assert!(engine.locate(2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_encrypted() -> Result<()> {
+        let dir = tempdir()?;
+        let key = age::x25519::Identity::generate();
+        let identity = dir.path().join("archive.key");
+        fs::write(&identity, key.to_string().expose_secret())?;
+
+        let cfg = ArchiveConfig {
+            after: 7,
+            area: "cold".to_string(),
+            recipient: Some(key.to_public().to_string()),
+            identity: Some(identity),
+        };
+        let engine = setup(dir.path(), cfg)?;
+
+        let done = engine.archive_artifacts(false)?;
+        assert!(done.iter().all(|e| e.encrypted));
+        let archived = dir.path().join("cold").join("1").join("all.csv.age");
+        assert_eq!(archived, done[0].archived);
+        assert_ne!(b"a,b\n1,2\n".to_vec(), fs::read(&archived)?);
+
+        let into = dir.path().join("restored");
+        engine.restore(1, &into)?;
+        assert_eq!("a,b\n1,2\n", fs::read_to_string(into.join("all.csv"))?);
+        assert_eq!(
+            "x\n",
+            fs::read_to_string(into.join("tracks").join("4CA2D6.csv"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_too_young() -> Result<()> {
+        let dir = tempdir()?;
+        let cfg = ArchiveConfig {
+            after: 20,
+            area: "cold".to_string(),
+            ..ArchiveConfig::default()
+        };
+        let engine = setup(dir.path(), cfg)?;
+        assert!(engine.archive_artifacts(false)?.is_empty());
+        assert_eq!(2, engine.locate(1)?.len());
+
+        let mut engine = engine;
+        engine.archive = Some(ArchiveConfig {
+            after: 1,
+            area: "nope".to_string(),
+            ..ArchiveConfig::default()
+        });
+        assert!(engine.archive_artifacts(false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,522 @@
+//! Typed builder for `JobSpec`
+//!
+//! Jobs can be written in Rust instead of HCL, the builder follows the pipeline from the source
+//! to the sink:
+//!
+//! ```no_run
+//! use fetiche_engine::{JobSpec, Sink, SplitBy, TrackSpec};
+//! use fetiche_formats::{Cat21, VehicleCategory};
+//!
+//! let spec = JobSpec::fetch("asd")
+//!     .since(3600)
+//!     .category(VehicleCategory::Uas)
+//!     .convert::<Cat21>()
+//!     .tracks(TrackSpec::default())
+//!     .sink(Sink::split("/data/drones", SplitBy::Track))?;
+//! # Ok::<(), eyre::Report>(())
+//! ```
+//!
+//! What depends on the stages is checked by the compiler: `filter`, `limits` and `schedule`
+//! need a source (not a job reading the output of the previous one), `convert` is only
+//! possible once and `tracks`, `label`, `qc` and `columns` need records converted into
+//! `Cat21`.  Everything else (paths, containers, windows, tuning...) is checked by `sink()`
+//! with `JobSpec::check()`, the same way as a job file, returning `EngineStatus::BadJobSpec`.
+//!
+//! The result is submitted like any other job, with `Engine::create_job_from()` or sent to
+//! `fetiched` with `JobSpec::to_proto()`.
+//!
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+use eyre::Result;
+use tracing::trace;
+
+use fetiche_common::Container;
+use fetiche_formats::{Cat21, Columns, Format, Senhive, VehicleCategory};
+
+use crate::{
+    EngineStatus, ExportSpec, FilterSpec, JobSpec, LabelSpec, Layout, Limits, QcSpec, Schedule,
+    Sink, SpillSpec, SplitBy, TrackSpec, Tuning,
+};
+
+/// Job reading a site
+///
+#[derive(Debug)]
+pub struct FromSite;
+
+/// Job reading the output of the previous one
+///
+#[derive(Debug)]
+pub struct FromPrevious;
+
+/// Records left in their input format
+///
+#[derive(Debug)]
+pub struct Unconverted;
+
+/// Formats a job can convert into
+///
+pub trait Output {
+    /// Matching format
+    const FORMAT: Format;
+}
+
+impl Output for Cat21 {
+    const FORMAT: Format = Format::Cat21;
+}
+
+impl Output for Senhive {
+    const FORMAT: Format = Format::Senhive;
+}
+
+/// Builder for a `JobSpec`, `S` is where the records come from and `F` what they are
+/// converted into.
+///
+#[derive(Debug)]
+pub struct JobBuilder<S, F> {
+    /// Everything but the sink
+    spec: JobSpec,
+    /// Columns to save
+    columns: Option<Columns>,
+    /// Redaction policy of the sink
+    redact: Option<String>,
+    _stage: PhantomData<(S, F)>,
+}
+
+impl JobSpec {
+    /// Start a job reading `site`, fetched or streamed depending on the site.
+    ///
+    pub fn fetch(site: &str) -> JobBuilder<FromSite, Unconverted> {
+        trace!("JobSpec::fetch({site})");
+        JobBuilder::new(site)
+    }
+
+    /// Start a job reading the output of the previous one, see `chain.rs`.
+    ///
+    pub fn after() -> JobBuilder<FromPrevious, Unconverted> {
+        trace!("JobSpec::after()");
+        JobBuilder::new("")
+    }
+}
+
+impl<S, F> JobBuilder<S, F> {
+    fn new(source: &str) -> Self {
+        JobBuilder {
+            spec: JobSpec {
+                source: source.to_string(),
+                filter: None,
+                into: None,
+                raw_copy: None,
+                category: vec![],
+                tracks: None,
+                label: None,
+                qc: None,
+                // Replaced by `sink()`, the only way out of the builder
+                //
+                sink: Sink::store(""),
+                spill: None,
+                schedule: None,
+                limits: None,
+                timeout: None,
+                provenance: false,
+                export: None,
+                tuning: BTreeMap::new(),
+                on_success: vec![],
+                on_failure: vec![],
+            },
+            columns: None,
+            redact: None,
+            _stage: PhantomData,
+        }
+    }
+
+    /// Move on to another stage
+    ///
+    fn stage<S2, F2>(self) -> JobBuilder<S2, F2> {
+        JobBuilder {
+            spec: self.spec,
+            columns: self.columns,
+            redact: self.redact,
+            _stage: PhantomData,
+        }
+    }
+
+    /// Keep the raw data in `dir`
+    ///
+    pub fn raw_copy(mut self, dir: &str) -> Self {
+        self.spec.raw_copy = Some(dir.to_string());
+        self
+    }
+
+    /// Only keep the records of this vehicle category, can be called more than once
+    ///
+    pub fn category(mut self, category: VehicleCategory) -> Self {
+        self.spec.category.push(category.to_string());
+        self
+    }
+
+    /// Stamp every record with its source, job and ingestion time
+    ///
+    pub fn provenance(mut self) -> Self {
+        self.spec.provenance = true;
+        self
+    }
+
+    /// Spill to disk what the sink can not take yet
+    ///
+    pub fn spill(mut self, spill: SpillSpec) -> Self {
+        self.spec.spill = Some(spill);
+        self
+    }
+
+    /// Wall-clock limit in minutes
+    ///
+    pub fn timeout(mut self, minutes: u64) -> Self {
+        self.spec.timeout = Some(minutes);
+        self
+    }
+
+    /// Export artifacts once done
+    ///
+    pub fn export(mut self, export: ExportSpec) -> Self {
+        self.spec.export = Some(export);
+        self
+    }
+
+    /// Tune the stage named `stage` (`fetch`, `convert`, `save`...)
+    ///
+    pub fn tuning(mut self, stage: &str, tuning: Tuning) -> Self {
+        self.spec.tuning.insert(stage.to_string(), tuning);
+        self
+    }
+
+    /// Run the job `name` of the same file after this one succeeded
+    ///
+    pub fn on_success(mut self, name: &str) -> Self {
+        self.spec.on_success.push(name.to_string());
+        self
+    }
+
+    /// Run the job `name` of the same file after this one failed
+    ///
+    pub fn on_failure(mut self, name: &str) -> Self {
+        self.spec.on_failure.push(name.to_string());
+        self
+    }
+
+    /// Redact the records with the policy `name` from `engine.hcl` before the sink
+    ///
+    pub fn redact(mut self, name: &str) -> Self {
+        self.redact = Some(name.to_string());
+        self
+    }
+
+    /// End the job with `sink` and check the whole of it.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn sink(mut self, mut sink: Sink) -> Result<JobSpec> {
+        trace!("JobBuilder::sink({:?})", sink);
+
+        let name = match self.spec.source.as_str() {
+            "" => "after".to_string(),
+            source => source.to_string(),
+        };
+        let bad = |why: &str| EngineStatus::BadJobSpec(name.clone(), why.to_string());
+
+        if let Some(columns) = self.columns.take() {
+            match &mut sink {
+                Sink::Save { columns: c, .. } => *c = Some(columns.to_string()),
+                _ => return Err(bad("columns need the save sink").into()),
+            }
+        }
+        if let Some(policy) = self.redact.take() {
+            match &mut sink {
+                Sink::Save { redact, .. }
+                | Sink::Split { redact, .. }
+                | Sink::Store { redact, .. }
+                | Sink::Postgis { redact, .. } => *redact = Some(policy),
+            }
+        }
+        self.spec.sink = sink;
+        self.spec.check().map_err(|e| bad(&e))?;
+        Ok(self.spec)
+    }
+}
+
+impl<F> JobBuilder<FromSite, F> {
+    /// Filter what is fetched
+    ///
+    pub fn filter(mut self, filter: FilterSpec) -> Self {
+        self.spec.filter = Some(filter);
+        self
+    }
+
+    /// Shortcut for a filter going `secs` seconds back
+    ///
+    pub fn since(self, secs: i32) -> Self {
+        self.filter(FilterSpec {
+            since: Some(secs),
+            ..FilterSpec::default()
+        })
+    }
+
+    /// Stream limits
+    ///
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.spec.limits = Some(limits);
+        self
+    }
+
+    /// Run the job more than once
+    ///
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.spec.schedule = Some(schedule);
+        self
+    }
+}
+
+impl<S> JobBuilder<S, Unconverted> {
+    /// Convert the records into `T`
+    ///
+    pub fn convert<T: Output>(mut self) -> JobBuilder<S, T> {
+        self.spec.into = Some(T::FORMAT.to_string());
+        self.stage()
+    }
+}
+
+impl<S> JobBuilder<S, Cat21> {
+    /// Add a normalised `track_id` to every record
+    ///
+    pub fn tracks(mut self, tracks: TrackSpec) -> Self {
+        self.spec.tracks = Some(tracks);
+        self
+    }
+
+    /// Join the records with flight plans
+    ///
+    pub fn label(mut self, label: LabelSpec) -> Self {
+        self.spec.label = Some(label);
+        self
+    }
+
+    /// Check the records before the sink
+    ///
+    pub fn qc(mut self, qc: QcSpec) -> Self {
+        self.spec.qc = Some(qc);
+        self
+    }
+
+    /// Only save these columns, needs a CSV `Sink::save()`
+    ///
+    pub fn columns(mut self, columns: Columns) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+}
+
+impl Sink {
+    /// Single file, the container is guessed from the extension of `path` ("-" is stdout)
+    ///
+    pub fn save(path: &str) -> Self {
+        Sink::Save {
+            path: path.to_string(),
+            container: None,
+            trajectories: false,
+            layout: Layout::default(),
+            redact: None,
+            columns: None,
+        }
+    }
+
+    /// Single file in `container`
+    ///
+    pub fn save_as(path: &str, container: Container) -> Self {
+        Sink::Save {
+            path: path.to_string(),
+            container: Some(container.to_string().to_lowercase()),
+            trajectories: false,
+            layout: Layout::default(),
+            redact: None,
+            columns: None,
+        }
+    }
+
+    /// One file per key in the directory `path`
+    ///
+    pub fn split(path: &str, by: SplitBy) -> Self {
+        Sink::Split {
+            path: path.to_string(),
+            by: by.to_string(),
+            redact: None,
+        }
+    }
+
+    /// Hourly files in the directory `path`
+    ///
+    pub fn store(path: &str) -> Self {
+        Sink::Store {
+            path: path.to_string(),
+            redact: None,
+        }
+    }
+
+    /// PostGIS table, needs the `postgis` feature
+    ///
+    pub fn postgis(url: &str, table: Option<&str>) -> Self {
+        Sink::Postgis {
+            url: url.to_string(),
+            table: table.map(str::to_string),
+            trajectories: false,
+            redact: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use crate::{Engine, Live};
+
+    use super::*;
+
+    #[test]
+    fn test_builder_fetch() -> Result<()> {
+        let spec = JobSpec::fetch("asd")
+            .since(3600)
+            .raw_copy("raw")
+            .category(VehicleCategory::UasMultiRotor)
+            .convert::<Cat21>()
+            .tracks(TrackSpec::default())
+            .columns(Columns::Minimal)
+            .redact("public")
+            .sink(Sink::save("out.csv"))?;
+
+        assert_eq!("asd", spec.source);
+        assert_eq!(Some(3600), spec.filter.as_ref().and_then(|f| f.since));
+        assert_eq!(Format::Cat21, spec.conversion());
+        assert_eq!(vec![VehicleCategory::UasMultiRotor], spec.categories());
+        assert!(spec.tracks.is_some());
+        match spec.sink {
+            Sink::Save {
+                path,
+                columns,
+                redact,
+                ..
+            } => {
+                assert_eq!("out.csv", path);
+                assert_eq!(Some("minimal"), columns.as_deref());
+                assert_eq!(Some("public"), redact.as_deref());
+            }
+            _ => panic!("not a save sink"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_after() -> Result<()> {
+        let spec = JobSpec::after()
+            .convert::<Senhive>()
+            .on_success("next")
+            .sink(Sink::split("out", SplitBy::Journey))?;
+
+        assert!(spec.source.is_empty());
+        assert_eq!(Format::Senhive, spec.conversion());
+        assert_eq!(vec!["next".to_string()], spec.on_success);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(Sink::save(""), "empty save path")]
+    #[case(Sink::save_as("out.csv", Container::CSV), "")]
+    #[case(Sink::store(""), "empty store path")]
+    #[case(Sink::split("", SplitBy::Icao24), "empty split path")]
+    fn test_builder_sink(#[case] sink: Sink, #[case] why: &str) {
+        let res = JobSpec::fetch("asd").sink(sink);
+        match why {
+            "" => assert!(res.is_ok()),
+            why => assert_eq!(
+                EngineStatus::BadJobSpec("asd".to_string(), why.to_string()).to_string(),
+                res.unwrap_err().to_string()
+            ),
+        }
+    }
+
+    #[rstest]
+    #[case(Sink::store("out"), "columns need the save sink")]
+    #[case(Sink::save("out.parquet"), "columns need the csv container")]
+    fn test_builder_columns_sink(#[case] sink: Sink, #[case] why: &str) {
+        let res = JobSpec::fetch("asd")
+            .convert::<Cat21>()
+            .columns(Columns::Ids)
+            .sink(sink);
+        assert!(res.unwrap_err().to_string().contains(why));
+    }
+
+    #[test]
+    fn test_builder_checked() {
+        let res = JobSpec::fetch("asd")
+            .filter(FilterSpec {
+                window: Some("previous full hour".to_string()),
+                timezone: Some("Mars/Olympus".to_string()),
+                ..FilterSpec::default()
+            })
+            .sink(Sink::save("-"));
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("unknown timezone Mars/Olympus"));
+
+        let res = JobSpec::after()
+            .tuning("nowhere", Tuning::default())
+            .sink(Sink::save("-"));
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_builder_same_as_file() -> Result<()> {
+        let s = r#"
+version = 1
+job "j" {
+  source = "asd"
+  into   = "cat21"
+  tracks {}
+  sink "save" {
+    path      = "out.csv"
+    container = "csv"
+    columns   = "ids"
+  }
+}
+"#;
+        let file = crate::JobFile::from_str(s)?;
+        let spec = JobSpec::fetch("asd")
+            .convert::<Cat21>()
+            .tracks(TrackSpec::default())
+            .columns(Columns::Ids)
+            .sink(Sink::save_as("out.csv", Container::CSV))?;
+
+        let (a, b) = (file.job["j"].to_proto("j"), spec.to_proto("j"));
+        assert_eq!(a, b);
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_create_job() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut e = Engine::local(Some(dir.path().to_path_buf()))?;
+        e.live(Live::new());
+
+        let spec = JobSpec::fetch("simulator-live")
+            .convert::<Cat21>()
+            .columns(Columns::Minimal)
+            .sink(Sink::save("-"))?;
+        let job = e.create_job_from("j", &spec)?;
+        let names = job.list.iter().map(|t| t.name()).collect::<Vec<_>>();
+        let n = names.len();
+        assert_eq!(["Broadcast", "Project", "Save"], names[n - 3..]);
+        Ok(())
+    }
+}
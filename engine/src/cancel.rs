@@ -0,0 +1,125 @@
+//! Cancellation of running jobs.
+//!
+//! Every job has a `Cancel` token given to all its stages by `Job::run()`.  The derived `run()`
+//! (see the engine-macro crate) checks it between batches and stops the stage once it is set,
+//! tasks looping inside their `execute()` like `Replay` call `cancelled()` between records, it
+//! looks at the token of the current thread like `record_drop()` does for the counters.
+//!
+//! The token is set by the job itself when it times out and by `Engine::cancel_job()`, the job
+//! then fails with `EngineStatus::Cancelled`.
+//!
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use eyre::Result;
+use tracing::info;
+
+use crate::{Engine, EngineStatus};
+
+thread_local! {
+    /// Token of the task running in this thread
+    static CURRENT: RefCell<Option<Cancel>> = const { RefCell::new(None) };
+}
+
+/// Shared cancellation flag of one job
+///
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// New token, not set
+    ///
+    pub fn new() -> Self {
+        Cancel::default()
+    }
+
+    /// Ask every stage to stop
+    ///
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Has the job been cancelled?
+    ///
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// This thread works for this job, see `cancelled()`
+    ///
+    pub fn enter(&self) {
+        CURRENT.with(|c| *c.borrow_mut() = Some(self.clone()));
+    }
+
+    /// Token of the current thread, to be given to helper threads
+    ///
+    pub fn current() -> Option<Cancel> {
+        CURRENT.with(|c| c.borrow().clone())
+    }
+}
+
+/// Has the job of the current thread been cancelled?  Always false outside of a pipeline.
+///
+pub fn cancelled() -> bool {
+    CURRENT.with(|c| c.borrow().as_ref().is_some_and(|c| c.is_cancelled()))
+}
+
+impl Engine {
+    /// Stop job `id` if it is running in this process.  Its stages stop at the next batch and
+    /// the job fails as `Cancelled`.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn cancel_job(&self, id: usize) -> Result<()> {
+        match self.running.lock().unwrap().get(&id) {
+            Some(cancel) => {
+                info!("Cancelling job {}", id);
+                cancel.cancel();
+                Ok(())
+            }
+            None => Err(EngineStatus::NotRunning(id).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_cancel_current() {
+        assert!(!cancelled());
+
+        let cancel = Cancel::new();
+        let c = cancel.clone();
+        let h = thread::spawn(move || {
+            c.enter();
+            while !cancelled() {
+                thread::yield_now();
+            }
+            Cancel::current().is_some()
+        });
+        cancel.cancel();
+        assert!(h.join().unwrap());
+
+        // Only for the threads of the job
+        //
+        assert!(Cancel::current().is_none());
+        assert!(!cancelled());
+    }
+
+    #[test]
+    fn test_cancel_job_unknown() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let e = Engine::local(Some(dir.path().to_path_buf()))?;
+        let err = e.cancel_job(42).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::NotRunning(42))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+//! Job chaining.
+//!
+//! A job in a job file can name other jobs to run after it:
+//!
+//! - `on_success`: run when it worked, a job there without `source` reads what this one wrote,
+//! - `on_failure`: run when it failed (or could not be created), e.g. to fetch from a backup.
+//!
+//! ```hcl
+//! job "fetch" {
+//!   source = "opensky"
+//!   sink "split" {
+//!     path = "/var/lib/acute/tracks"
+//!     by   = "track"
+//!   }
+//!   on_success = ["archive"]
+//!   on_failure = ["backup"]
+//! }
+//!
+//! job "archive" {
+//!   into = "cat21"
+//!   sink "save" {
+//!     path = "tracks.csv"
+//!   }
+//! }
+//! ```
+//!
+//! Only `Save` into a text file, `Split` and `Store` leave something the next job can read.
+//! The chains are checked when the file is loaded: names must exist and there can be no cycle.
+//! Jobs named in no chain are the roots, run by `acutectl submit`.
+//!
+
+use std::collections::{BTreeSet, VecDeque};
+use std::io::Write;
+use std::path::PathBuf;
+
+use eyre::Result;
+use tracing::{info, trace, warn};
+
+use fetiche_formats::Format;
+
+use crate::{Engine, EngineStatus, JobEvent, JobFile};
+
+/// What a finished job left for the next ones
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Artifacts {
+    /// File or directory
+    pub path: PathBuf,
+    /// Format of the records in it
+    pub format: Format,
+    /// Site the records come from
+    pub source: String,
+}
+
+impl JobFile {
+    /// Jobs run by nobody else, in name order
+    ///
+    pub fn roots(&self) -> Vec<&str> {
+        let chained = self
+            .job
+            .values()
+            .flat_map(|spec| spec.on_success.iter().chain(spec.on_failure.iter()))
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>();
+        self.job
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !chained.contains(name))
+            .collect()
+    }
+
+    /// Check `on_success` and `on_failure` for every job.
+    ///
+    pub(crate) fn check_chains(&self) -> Result<()> {
+        let bad = |name: &str, why: String| EngineStatus::BadJobSpec(name.to_string(), why);
+
+        for (name, spec) in self.job.iter() {
+            for next in spec.on_success.iter().chain(spec.on_failure.iter()) {
+                if next == name {
+                    return Err(bad(name, "job can not follow itself".to_string()).into());
+                }
+                if !self.job.contains_key(next) {
+                    return Err(bad(name, format!("unknown job {next}")).into());
+                }
+            }
+        }
+
+        // A job without source needs something to read
+        //
+        for name in self.job.keys().filter(|n| self.job[*n].source.is_empty()) {
+            if self.job.values().any(|s| s.on_failure.contains(name)) {
+                return Err(bad(name, "no source to run on failure".to_string()).into());
+            }
+            let fed = self
+                .job
+                .values()
+                .filter(|s| s.on_success.contains(name))
+                .collect::<Vec<_>>();
+            if fed.is_empty() {
+                return Err(bad(name, "no source and no previous job".to_string()).into());
+            }
+            if fed.iter().any(|s| s.output_path(0).is_none()) {
+                return Err(bad(name, "previous job output can not be read".to_string()).into());
+            }
+        }
+
+        // Depth-first from every job, `path` being the current chain
+        //
+        fn visit<'a>(file: &'a JobFile, name: &'a str, path: &mut Vec<&'a str>) -> Result<()> {
+            if path.contains(&name) {
+                let cycle = format!("cycle {} -> {}", path.join(" -> "), name);
+                return Err(EngineStatus::BadJobSpec(name.to_string(), cycle).into());
+            }
+            path.push(name);
+            let spec = &file.job[name];
+            for next in spec.on_success.iter().chain(spec.on_failure.iter()) {
+                visit(file, next, path)?;
+            }
+            path.pop();
+            Ok(())
+        }
+        for name in self.job.keys() {
+            visit(self, name, &mut vec![])?;
+        }
+        Ok(())
+    }
+}
+
+impl Engine {
+    /// Run job `name` from `file` then the jobs chained to it, breadth-first.  Every job is run
+    /// even if another one failed, the first error is returned.
+    ///
+    #[tracing::instrument(skip(self, file, out))]
+    pub fn run_chain(&mut self, file: &JobFile, name: &str, out: &mut dyn Write) -> Result<()> {
+        trace!("run_chain({})", name);
+
+        let mut queue = VecDeque::from([(name.to_string(), None)]);
+        let mut first = None;
+        while let Some((name, upstream)) = queue.pop_front() {
+            let Some(spec) = file.job.get(&name) else {
+                return Err(EngineStatus::BadJobSpec(name, "unknown job".to_string()).into());
+            };
+
+            let res = self
+                .create_job_after(&name, spec, upstream.as_ref())
+                .and_then(|(job, artifacts)| {
+                    eprintln!(
+                        "Running job #{} ({}) with {} tasks.",
+                        job.id,
+                        name,
+                        job.list.len()
+                    );
+                    let (id, name) = (job.id, job.name.clone());
+                    self.run_job(job, out)?;
+                    if let Some(a) = &artifacts {
+                        let path = a.path.clone();
+                        self.record(id, &name, JobEvent::Artifacts { path });
+                    }
+                    Ok(artifacts)
+                });
+            match res {
+                Ok(artifacts) => {
+                    spec.on_success.iter().for_each(|next| {
+                        info!("Job {name} done, next is {next}");
+                        queue.push_back((next.clone(), artifacts.clone()));
+                    });
+                }
+                Err(e) => {
+                    warn!("Job {name} failed: {e}");
+                    spec.on_failure.iter().for_each(|next| {
+                        info!("Job {name} failed, next is {next}");
+                        queue.push_back((next.clone(), None));
+                    });
+                    first.get_or_insert(e);
+                }
+            }
+        }
+        match first {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    const FETCH: &str = r##"
+version = 1
+
+job "fetch" {
+  source = "opensky"
+  sink "split" {
+    path = "/tmp/tracks"
+    by   = "track"
+  }
+  on_success = ["archive"]
+  on_failure = ["backup"]
+}
+
+job "archive" {
+  into = "cat21"
+  sink "save" {
+    path = "tracks.csv"
+  }
+}
+
+job "backup" {
+  source = "adsbx"
+  sink "save" {
+    path = "-"
+  }
+}
+"##;
+
+    #[test]
+    fn test_chain_roots() -> Result<()> {
+        let file = JobFile::from_str(FETCH)?;
+        assert_eq!(vec!["fetch"], file.roots());
+        assert_eq!(
+            Some(PathBuf::from("/tmp/tracks")),
+            file.job["fetch"].output_path(1)
+        );
+        assert_eq!(
+            Some(PathBuf::from("tracks.csv")),
+            file.job["archive"].output_path(1)
+        );
+        assert_eq!(None, file.job["backup"].output_path(1));
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"on_success = ["nope"]"#, "unknown job")]
+    #[case(r#"on_success = ["backup"]"#, "follow itself")]
+    #[case(r#"on_success = ["fetch"]"#, "cycle")]
+    #[case(r#"on_failure = ["archive"]"#, "on failure")]
+    fn test_chain_bad(#[case] line: &str, #[case] why: &str) {
+        let data = FETCH.replace(
+            "source = \"adsbx\"\n",
+            &format!("source = \"adsbx\"\n  {line}\n"),
+        );
+        let e = JobFile::from_str(&data).unwrap_err();
+        assert!(e.to_string().contains(why), "{e}");
+    }
+
+    #[test]
+    fn test_chain_no_input() {
+        // Nothing to read after a `Save` on stdout
+        //
+        let data = FETCH.replace(
+            "sink \"split\" {\n    path = \"/tmp/tracks\"\n    by   = \"track\"",
+            "sink \"save\" {\n    path = \"-\"",
+        );
+        let e = JobFile::from_str(&data).unwrap_err();
+        assert!(e.to_string().contains("can not be read"), "{e}");
+
+        // Not chained at all
+        //
+        let data = FETCH.replace(r#"on_success = ["archive"]"#, "");
+        let e = JobFile::from_str(&data).unwrap_err();
+        assert!(e.to_string().contains("no previous job"), "{e}");
+    }
+}
@@ -2,6 +2,42 @@ version = 2
 
 basedir = "/var/db/acute"
 
+// Per-job working directories, default is "basedir/work", and how many directories
+// of failed jobs are kept for debugging.
+//
+// workdir     = "/var/tmp/acute"
+// keep_failed = 10
+
+// Wall-clock limit of fetches and conversions in minutes, a job still running after that is
+// cancelled and marked as timed out.  Streams are not concerned, 0 (the default) is no limit.
+//
+// job_timeout = 30
+
+// Every step of every job is appended to "history.jsonl" in the state directory, events older
+// than this number of days are removed when the engine starts.
+//
+// history_days = 30
+
+// Artifacts of jobs finished more than "after" days ago are moved into the "area" storage area by
+// `acutectl results archive`, encrypted for the age "recipient" if set.  "identity" is the key
+// file used by `acutectl results get` to decrypt them.
+//
+// archive {
+//   after     = 14
+//   area      = "cold"
+//   recipient = "age1..."
+//   identity  = "/etc/acute/archive.key"
+// }
+
+// Free space on basedir, workdir and storage areas: below "low", bulk jobs are refused and
+// below "critical", streams are paused.  Either a percentage or a size like "500M".
+//
+// space {
+//   low      = "5%"
+//   critical = "1%"
+//   interval = 30
+// }
+
 // Describe a local directory tree used to store files
 //
 storage "hourly" {
@@ -13,3 +49,33 @@ storage "daily" {
   path     = ":basedir/data"
   rotation = "1d"
 }
+
+// Number of workers running jobs, adjusted between min and max depending on the queue:
+// above "up_queue" queued jobs per worker, workers are added and with nothing queued and
+// less than "down_util" of them busy, one is removed.  "cooldown" is in seconds.  There is no
+// worker pool yet, jobs run in the process submitting them and this is not used.
+//
+// workers {
+//   min       = 1
+//   max       = 8
+//   up_queue  = 2
+//   down_util = 0.25
+//   cooldown  = 60
+// }
+
+// Authentication tokens are checked every "interval" seconds and the ones expiring in less
+// than "before" seconds are refreshed.  Both are in seconds.
+//
+// tokens {
+//   interval = 60
+//   before   = 300
+// }
+
+// Redaction policies for data leaving the engine, see `--redact` and `redact` in job sinks.
+//
+// redact "public" {
+//   drop_operator       = true
+//   truncate_serial     = 6
+//   drop_pilot_location = true
+//   drop                = ["station_name"]
+// }
@@ -12,12 +12,52 @@ pub enum TokenStatus {
 
 #[derive(Debug, Error)]
 pub enum EngineStatus {
+    #[error("Another engine is running: PID {0}, started {1}, holds {2}.  Wait for it to finish or stop it (kill {0}).")]
+    AlreadyRunning(u32, String, String),
     #[error("Bad config file version v{0}, need {1}")]
     BadConfigVersion(usize, usize),
+    #[error("Bad frame #{1} in capture file {0}")]
+    BadFrame(String, usize),
+    #[error("Nothing to replay in {0}")]
+    NoCapture(String),
+    #[error("No archive block in engine.hcl")]
+    NoArchive,
+    #[error("Archive area {0} is not a directory storage area")]
+    BadArchiveArea(String),
+    #[error("Invalid archive recipient {0}: {1}")]
+    BadArchiveKey(String, String),
+    #[error("Archived files are encrypted, set identity in the archive block")]
+    NoArchiveIdentity,
+    #[error("Archive error: {0}")]
+    Archive(String),
+    #[error("Nothing known about the results of job {0}")]
+    NoResults(usize),
+    #[error("{0} already exists, not overwriting")]
+    ConfigExists(String),
+    #[error("Can not open lock file {0}: {1}")]
+    LockFile(String, String),
     #[error("Can not create directory {0}")]
     CreateDir(String),
     #[error("Can not create link to {0} as {1}")]
     CreateLink(String, String),
+    #[error("Database error: {0}")]
+    DbError(String),
+    #[error("Invalid table name {0}")]
+    BadTableName(String),
+    #[error("Invalid duration {0}, use 1s/1m/1h/1d")]
+    BadDuration(String),
+    #[error("Invalid free space threshold {0}, use 5% or 500M")]
+    BadThreshold(String),
+    #[error("Invalid replay speed {0}, use 1x, 10x, 0.5x or max")]
+    BadSpeed(String),
+    #[error("Invalid workers bounds min={0} max={1}")]
+    BadScaling(usize, usize),
+    #[error("Unknown redaction policy {0}")]
+    UnknownRedaction(String),
+    #[error("Bad job file version v{0}, need {1}")]
+    BadJobFileVersion(usize, usize),
+    #[error("Invalid job {0}: {1}")]
+    BadJobSpec(String, String),
     #[error("Empty task list.")]
     EmptyTaskList,
     #[error("Site not found.")]
@@ -26,14 +66,98 @@ pub enum EngineStatus {
     NoFirstProducer,
     #[error("Last task must be Filter/Producer.")]
     NoLastConsumer,
+    #[error("Cat048 plots need the radar position")]
+    NoRadar,
+    #[error("Bad progress channel {0}, want fd:N, unix:PATH, tcp:HOST:PORT or a file")]
+    BadProgressTarget(String),
+    #[error("Bad flight plans in {0}: {1}")]
+    BadFlightPlans(String, String),
+    #[error("Export failed: {0}")]
+    BadExport(String),
+    #[error("Bad tuning for {0}: {1}")]
+    BadTuning(String, String),
+    #[error("Not enough free space, {0}")]
+    LowSpace(String),
+    #[error("No column {0} in input data.")]
+    NoSplitColumn(String),
+    #[error("No column {0} in input data for GeoJSON.")]
+    NoGeoJsonColumn(String),
+    #[error("No column {0} in input data for GeoParquet.")]
+    NoGeoParquetColumn(String),
+    #[error("No column {0} in input data for PostGIS.")]
+    NoPostGisColumn(String),
+    #[error("No column {0} in input data for QC.")]
+    NoQcColumn(String),
+    #[error("No column {0} in input data for tracks.")]
+    NoTrackColumn(String),
+    #[error("No column {0} in input data for categories.")]
+    NoSelectColumn(String),
+    #[error("No column {0} in input data for labels.")]
+    NoLabelColumn(String),
     #[error("No path defined for Store.")]
     NoPathDefined,
+    #[error("No profile for job {0}")]
+    NoProfile(String),
     #[error("Only Asd to Parquet for now.")]
     OnlyAsdToParquet,
     #[error("Can not remove symlink {0}")]
     RemoveLink(String),
+    #[error("Source {0} is not healthy: {1}")]
+    SourceDown(String, String),
+    #[error("Sink {0} stopped, {1} batches left in {2}")]
+    SinkStopped(String, usize, String),
+    #[error("Job {0} timed out after {1}s")]
+    TimedOut(usize, u64),
+    #[error("Job {0} cancelled")]
+    Cancelled(usize),
+    #[error("Job {0} is not running here")]
+    NotRunning(usize),
+    #[error("Task {0} failed: {1}")]
+    TaskFailed(String, String),
+    #[error("Task {0} panicked")]
+    TaskPanicked(String),
     #[error("Unknown token {0}")]
     TokenError(String),
+    #[error("Can not split by {0} with format {1}")]
+    UnsupportedSplit(String, String),
+    #[error("Format {0} can not be written as GeoJSON")]
+    UnsupportedGeoJson(String),
+    #[error("Format {0} can not be written as GeoParquet")]
+    UnsupportedGeoParquet(String),
+    #[error("Format {0} can not be written one track per row")]
+    UnsupportedTrackLayout(String),
+    #[error("Format {0} can not be written into PostGIS")]
+    UnsupportedPostGis(String),
+    #[error("Format {0} can not be checked by QC")]
+    UnsupportedQc(String),
+    #[error("Format {0} has no vehicle category")]
+    UnsupportedSelect(String),
+    #[error("Format {0} has no track rule")]
+    UnsupportedTrack(String),
+    #[error("Format {0} can not be labelled, only cat21")]
+    UnsupportedLabel(String),
+    #[error("Format {0} can not be written as Excel, only asd and cat21")]
+    UnsupportedXlsx(String),
+    #[error("Sheet {0} would have {1} rows, Excel can only hold {2}, try per-day sheets")]
+    XlsxTooManyRows(String, usize, usize),
+    #[error("Excel error: {0}")]
+    Xlsx(String),
+    #[error("Bad live filter {0}, expected source=<name> and bbox=<min_lon>,<min_lat>,<max_lon>,<max_lat>")]
+    BadLiveFilter(String),
+    #[error("Live endpoint: {0}")]
+    Live(String),
+    #[error("Bad columns: {0}")]
+    BadColumns(String),
+    #[error("Bad state archive {0}")]
+    BadStateArchive(String),
+    #[error("Checksum mismatch for {0} in the state archive")]
+    StateChecksum(String),
+    #[error("State archive v{0} is newer than v{1}")]
+    StateArchiveVersion(usize, usize),
+    #[error("Tokens in the state archive are encrypted, give the identity of this host")]
+    NoStateIdentity,
+    #[error("This engine already has jobs or a history, force the import to replace them")]
+    StateNotEmpty,
     #[error("Uninitialised Read")]
     UninitialisedRead,
 }
@@ -0,0 +1,391 @@
+//! Durable job history.
+//!
+//! The state file only knows about the jobs in the queue, once a job is gone only its profile
+//! and, if it failed, its error are left.  Every step in the life of a job is also appended to
+//! `history.jsonl` in the state directory, one JSON event per line:
+//!
+//! - `submitted` when the job is created,
+//! - `started` when it is run,
+//! - `stage` with the counters of each task (see `metrics.rs`) when it ends,
+//! - `finished` or `failed` with the error,
+//! - `artifacts` with what it left for chained jobs (see `chain.rs`),
+//! - `deferred` for a scheduled run put off because its source was down (see `readiness.rs`),
+//!   once per failed probe.
+//!
+//! Events older than `history_days` (30 by default) are pruned when the engine is loaded.
+//! `Engine::show_history()` displays one line per job (`acutectl jobs history`).  Writing the
+//! history never makes a job fail, errors are only logged.
+//!
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{trace, warn};
+
+use fetiche_common::{Listing, OutputFormat};
+
+use crate::{Engine, EngineStatus, StageStats, Storage};
+
+/// History file in the state directory
+pub(crate) const HISTORY_FILE: &str = "history.jsonl";
+
+/// Number of days of history kept by default
+pub(crate) const HISTORY_DAYS: u64 = 30;
+
+/// One step in the life of a job
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum JobEvent {
+    /// Job created
+    Submitted,
+    /// Job started
+    Started,
+    /// Counters of one task, in pipeline order
+    Stage { stage: StageStats },
+    /// Job finished successfully after `seconds`
+    Finished { seconds: u64 },
+    /// Job failed
+    Failed { error: String },
+    /// Output readable by the next jobs
+    Artifacts { path: PathBuf },
+    /// Run put off, the source is down
+    Deferred { reason: String },
+    /// One file exported after the job, see `export.rs`
+    Exported { to: String },
+    /// Export given up, the job itself is fine
+    ExportFailed { error: String },
+}
+
+/// One line of the history file
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    /// Job ID
+    pub id: usize,
+    /// Job name
+    pub name: String,
+    /// When it happened
+    pub at: i64,
+    /// What happened
+    #[serde(flatten)]
+    pub event: JobEvent,
+}
+
+/// Everything we know about one job, built from its events
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct JobRun {
+    /// Job ID
+    pub id: usize,
+    /// Job name
+    pub name: String,
+    /// When it was created
+    pub submitted: Option<i64>,
+    /// When it started
+    pub started: Option<i64>,
+    /// When it ended
+    pub ended: Option<i64>,
+    /// `None` while it is running (or if it was aborted)
+    pub ok: Option<bool>,
+    /// Error if it failed
+    pub error: Option<String>,
+    /// All stages
+    pub stages: Vec<StageStats>,
+    /// Output readable by the next jobs
+    pub artifacts: Vec<PathBuf>,
+    /// Failed probes before the run, see `readiness.rs`
+    pub deferred: usize,
+    /// Where the output was exported
+    pub exported: Vec<String>,
+    /// Error if the export failed
+    pub export_error: Option<String>,
+}
+
+impl JobRun {
+    /// Records out of the first stage, i.e. read from the source
+    ///
+    pub fn records(&self) -> usize {
+        self.stages
+            .first()
+            .map(|s| s.records_out)
+            .unwrap_or_default()
+    }
+}
+
+/// Append one event to the history file
+///
+pub(crate) fn append_history(fname: &Path, entry: &HistoryEntry) -> Result<()> {
+    let mut fh = OpenOptions::new().create(true).append(true).open(fname)?;
+    writeln!(fh, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Read all events since `since`, skipping lines we can not parse
+///
+pub(crate) fn read_history(fname: &Path, since: i64) -> Result<Vec<HistoryEntry>> {
+    if !fname.exists() {
+        return Ok(vec![]);
+    }
+    let data = fs::read_to_string(fname)?;
+    Ok(data
+        .lines()
+        .filter_map(|l| serde_json::from_str::<HistoryEntry>(l).ok())
+        .filter(|e| e.at >= since)
+        .collect())
+}
+
+/// Gather the events of each job, in job order.  Jobs submitted before `since` have only their
+/// later events.
+///
+pub fn job_runs(entries: &[HistoryEntry]) -> Vec<JobRun> {
+    let mut runs = std::collections::BTreeMap::<usize, JobRun>::new();
+    for e in entries {
+        let run = runs.entry(e.id).or_insert_with(|| JobRun {
+            id: e.id,
+            name: e.name.clone(),
+            ..JobRun::default()
+        });
+        match &e.event {
+            JobEvent::Submitted => run.submitted = Some(e.at),
+            JobEvent::Started => run.started = Some(e.at),
+            JobEvent::Stage { stage } => run.stages.push(stage.clone()),
+            JobEvent::Finished { .. } => {
+                run.ended = Some(e.at);
+                run.ok = Some(true);
+            }
+            JobEvent::Failed { error } => {
+                run.ended = Some(e.at);
+                run.ok = Some(false);
+                run.error = Some(error.clone());
+            }
+            JobEvent::Artifacts { path } => run.artifacts.push(path.clone()),
+            JobEvent::Deferred { reason } => {
+                run.ended = Some(e.at);
+                run.error = Some(reason.clone());
+                run.deferred += 1;
+            }
+            JobEvent::Exported { to } => run.exported.push(to.clone()),
+            JobEvent::ExportFailed { error } => run.export_error = Some(error.clone()),
+        }
+    }
+    runs.into_values().collect()
+}
+
+impl Engine {
+    /// Path of the history file
+    ///
+    #[inline]
+    pub fn history_file(&self) -> PathBuf {
+        self.state_dir.join(HISTORY_FILE)
+    }
+
+    /// Record one event for job `id`
+    ///
+    pub(crate) fn record(&self, id: usize, name: &str, event: JobEvent) {
+        trace!("history: #{} {:?}", id, event);
+        let entry = HistoryEntry {
+            id,
+            name: name.to_string(),
+            at: Utc::now().timestamp(),
+            event,
+        };
+        if let Err(e) = append_history(&self.history_file(), &entry) {
+            warn!("Can not write history: {}", e);
+        }
+    }
+
+    /// Forget events older than `history_days`
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn prune_history(&self) -> Result<()> {
+        let fname = self.history_file();
+        if !fname.exists() {
+            return Ok(());
+        }
+        let limit = Utc::now().timestamp() - (self.history_days * 86_400) as i64;
+        let kept = read_history(&fname, limit)?
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Write elsewhere first, other processes may be appending
+        //
+        let tmp = fname.with_extension("tmp");
+        fs::write(
+            &tmp,
+            kept.iter().map(|l| format!("{l}\n")).collect::<String>(),
+        )?;
+        fs::rename(&tmp, &fname)?;
+        Ok(())
+    }
+
+    /// Jobs with events in the last `since` (1s/1m/1h/1d), only the failed ones with `failed`
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn history(&self, since: &str, failed: bool) -> Result<Vec<JobRun>> {
+        let (_, age) = Storage::parse_rotation(since)
+            .map_err(|_| EngineStatus::BadDuration(since.to_string()))?;
+        let limit = Utc::now().timestamp() - age as i64;
+
+        let entries = read_history(&self.history_file(), limit)?;
+        Ok(job_runs(&entries)
+            .into_iter()
+            .filter(|r| !failed || r.ok == Some(false))
+            .collect())
+    }
+
+    /// Display the job history, see `history()`.
+    ///
+    pub fn show_history(&self, since: &str, failed: bool, fmt: OutputFormat) -> Result<String> {
+        let time = |t: Option<i64>| {
+            t.and_then(|t| DateTime::from_timestamp(t, 0))
+                .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default()
+        };
+
+        let mut list = Listing::new(
+            &format!("Jobs since {since}"),
+            &[
+                ("ID", "id"),
+                ("Name", "name"),
+                ("Submitted", "submitted"),
+                ("Seconds", "seconds"),
+                ("Status", "status"),
+                ("Records", "records"),
+                ("Output", "output"),
+            ],
+        );
+        self.history(since, failed)?.iter().for_each(|r| {
+            let seconds = match (r.started, r.ended) {
+                (Some(s), Some(e)) => (e - s).to_string(),
+                _ => String::new(),
+            };
+            let status = match r.ok {
+                Some(true) => "finished",
+                Some(false) => "failed",
+                None if r.started.is_some() => "running",
+                None if r.deferred > 0 => "deferred",
+                None => "queued",
+            };
+            let output = match &r.error {
+                Some(e) if r.deferred > 0 => format!("{e} ({} probes)", r.deferred),
+                Some(e) => e.clone(),
+                None => r
+                    .artifacts
+                    .iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            list.push(vec![
+                r.id.to_string(),
+                r.name.clone(),
+                time(r.submitted.or(r.started)),
+                seconds,
+                status.to_string(),
+                r.records().to_string(),
+                output,
+            ]);
+        });
+        list.render(fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn entry(id: usize, at: i64, event: JobEvent) -> HistoryEntry {
+        HistoryEntry {
+            id,
+            name: format!("job{id}"),
+            at,
+            event,
+        }
+    }
+
+    #[test]
+    fn test_history_file() -> Result<()> {
+        let dir = tempdir()?;
+        let fname = dir.path().join(HISTORY_FILE);
+        assert!(read_history(&fname, 0)?.is_empty());
+
+        let stage = StageStats {
+            name: "Fetch".to_string(),
+            records_out: 42,
+            ..StageStats::default()
+        };
+        let all = [
+            entry(1, 100, JobEvent::Submitted),
+            entry(1, 101, JobEvent::Started),
+            entry(1, 110, JobEvent::Stage { stage }),
+            entry(1, 110, JobEvent::Finished { seconds: 9 }),
+            entry(2, 200, JobEvent::Started),
+        ];
+        all.iter().try_for_each(|e| append_history(&fname, e))?;
+        fs::write(&fname, fs::read_to_string(&fname)? + "garbage\n")?;
+
+        assert_eq!(all.to_vec(), read_history(&fname, 0)?);
+        assert_eq!(1, read_history(&fname, 150)?.len());
+
+        let line = fs::read_to_string(&fname)?;
+        assert!(line.starts_with(r#"{"id":1,"name":"job1","at":100,"event":"submitted"}"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_runs() {
+        let runs = job_runs(&[
+            entry(2, 100, JobEvent::Submitted),
+            entry(1, 100, JobEvent::Started),
+            entry(
+                1,
+                110,
+                JobEvent::Failed {
+                    error: "site is down".to_string(),
+                },
+            ),
+            entry(2, 120, JobEvent::Started),
+            entry(2, 130, JobEvent::Finished { seconds: 10 }),
+            entry(
+                3,
+                140,
+                JobEvent::Deferred {
+                    reason: "opensky is down".to_string(),
+                },
+            ),
+            entry(
+                3,
+                200,
+                JobEvent::Deferred {
+                    reason: "opensky is down".to_string(),
+                },
+            ),
+            entry(
+                2,
+                130,
+                JobEvent::Artifacts {
+                    path: PathBuf::from("out.csv"),
+                },
+            ),
+        ]);
+
+        assert_eq!(3, runs.len());
+        assert_eq!(Some(false), runs[0].ok);
+        assert_eq!(Some("site is down".to_string()), runs[0].error);
+        assert_eq!(None, runs[0].submitted);
+        assert_eq!(Some(true), runs[1].ok);
+        assert_eq!(Some(130), runs[1].ended);
+        assert_eq!(vec![PathBuf::from("out.csv")], runs[1].artifacts);
+        assert_eq!(2, runs[2].deferred);
+        assert_eq!(None, runs[2].ok);
+        assert_eq!(Some(200), runs[2].ended);
+    }
+}
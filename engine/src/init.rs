@@ -0,0 +1,222 @@
+//! Create a new configuration from scratch: `engine.hcl`, `sources.hcl` and the directories
+//! they refer to.
+//!
+//! `engine.hcl` is the default one with `basedir` (and `workdir` if given) filled in, storage
+//! areas are put under `basedir`.  `sources.hcl` is the default one restricted to the selected
+//! sites, with placeholder credentials (see `Sources::template()`).  Both are parsed before
+//! being written and existing files are only overwritten if asked to.
+//!
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::Result;
+use tracing::{info, trace};
+
+use fetiche_common::Versioned;
+use fetiche_sources::Sources;
+
+use crate::{EngineConfig, EngineStatus, StorageConfig, ENGINE_CONFIG, ENGINE_VERSION};
+
+/// Default `basedir` in the shipped `engine.hcl`
+const DEF_BASEDIR: &str = "/var/db/acute";
+
+/// What we need to scaffold a configuration
+///
+#[derive(Clone, Debug)]
+pub struct Init {
+    /// Configuration directory
+    pub dir: PathBuf,
+    /// Base directory for data, tokens and storage areas
+    pub basedir: PathBuf,
+    /// Per-job working directories, `basedir/work` if not set
+    pub workdir: Option<PathBuf>,
+    /// Sites to keep, all of them if empty
+    pub sites: Vec<String>,
+    /// Overwrite existing files
+    pub force: bool,
+}
+
+/// What has been done
+///
+#[derive(Clone, Debug, Default)]
+pub struct InitReport {
+    /// Files written
+    pub files: Vec<PathBuf>,
+    /// Directories created
+    pub dirs: Vec<PathBuf>,
+    /// Sites whose credentials are placeholders
+    pub credentials: Vec<String>,
+}
+
+impl Init {
+    /// Configuration in `dir`, data in `dir/data` unless told otherwise
+    ///
+    pub fn new(dir: &Path) -> Self {
+        Init {
+            dir: dir.to_path_buf(),
+            basedir: dir.join("data"),
+            workdir: None,
+            sites: vec![],
+            force: false,
+        }
+    }
+
+    pub fn basedir(&mut self, dir: &Path) -> &mut Self {
+        self.basedir = dir.to_path_buf();
+        self
+    }
+
+    pub fn workdir(&mut self, dir: &Path) -> &mut Self {
+        self.workdir = Some(dir.to_path_buf());
+        self
+    }
+
+    pub fn sites(&mut self, sites: &[String]) -> &mut Self {
+        self.sites = sites.to_vec();
+        self
+    }
+
+    pub fn force(&mut self, force: bool) -> &mut Self {
+        self.force = force;
+        self
+    }
+
+    /// Content of `engine.hcl`
+    ///
+    fn engine(&self) -> String {
+        let quote = |p: &Path| p.to_string_lossy().replace('\\', "\\\\");
+        let basedir = quote(&self.basedir);
+
+        let content = include_str!("engine.hcl")
+            .replace(
+                &format!("basedir = \"{DEF_BASEDIR}\""),
+                &format!("basedir = \"{basedir}\""),
+            )
+            .replace("\":basedir/", &format!("\"{basedir}/"));
+        match &self.workdir {
+            Some(dir) => content.replace(
+                "// workdir     = \"/var/tmp/acute\"",
+                &format!("workdir     = \"{}\"", quote(dir)),
+            ),
+            None => content,
+        }
+    }
+
+    /// Check, write both files and create the directories
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn run(&self) -> Result<InitReport> {
+        trace!("init in {:?}", self.dir);
+
+        let engine = self.engine();
+        let cfg: EngineConfig = hcl::from_str(&engine)?;
+        if cfg.version() != ENGINE_VERSION {
+            return Err(EngineStatus::BadConfigVersion(cfg.version(), ENGINE_VERSION).into());
+        }
+
+        let sources = Sources::template(&self.sites)?;
+        let srcs = Sources::validate(&sources)?;
+
+        let files = [
+            (self.dir.join(ENGINE_CONFIG), engine),
+            (self.dir.join("sources.hcl"), sources),
+        ];
+        if !self.force {
+            if let Some((fname, _)) = files.iter().find(|(f, _)| f.exists()) {
+                return Err(EngineStatus::ConfigExists(fname.to_string_lossy().to_string()).into());
+            }
+        }
+
+        let mut report = InitReport {
+            credentials: srcs
+                .iter()
+                .filter(|(_, s)| s.auth.as_ref().is_some_and(|a| a.is_placeholder()))
+                .map(|(n, _)| n.clone())
+                .collect(),
+            ..InitReport::default()
+        };
+
+        // Everything the engine expects to find
+        //
+        let mut dirs = vec![
+            self.dir.clone(),
+            cfg.basedir.clone(),
+            cfg.basedir.join("tokens"),
+            cfg.workdir.clone().unwrap_or(cfg.basedir.join("work")),
+        ];
+        dirs.extend(cfg.storage.values().filter_map(|area| match area {
+            StorageConfig::Directory { path, .. } | StorageConfig::Hive { path } => {
+                Some(path.clone())
+            }
+            StorageConfig::Cache { .. } => None,
+        }));
+        for dir in dirs {
+            if !dir.exists() {
+                fs::create_dir_all(&dir)
+                    .map_err(|_| EngineStatus::CreateDir(dir.to_string_lossy().to_string()))?;
+                report.dirs.push(dir);
+            }
+        }
+
+        for (fname, content) in files {
+            info!("writing {:?}", fname);
+            fs::write(&fname, content)?;
+            report.files.push(fname);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_init_run() -> Result<()> {
+        let dir = tempdir()?;
+        let mut init = Init::new(dir.path());
+        init.sites(&["opensky".to_string(), "simulator".to_string()]);
+
+        let report = init.run()?;
+        assert_eq!(2, report.files.len());
+        assert_eq!(vec!["opensky".to_string()], report.credentials);
+
+        let data = dir.path().join("data");
+        for sub in ["tokens", "work", "hourly", "data"] {
+            assert!(data.join(sub).is_dir(), "{sub}");
+        }
+
+        let cfg: EngineConfig = hcl::from_str(&fs::read_to_string(&report.files[0])?)?;
+        assert_eq!(data, cfg.basedir);
+        let srcs = Sources::validate(&fs::read_to_string(&report.files[1])?)?;
+        assert_eq!(2, srcs.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_workdir() -> Result<()> {
+        let dir = tempdir()?;
+        let work = dir.path().join("work");
+        let mut init = Init::new(dir.path());
+        init.basedir(&dir.path().join("db")).workdir(&work);
+
+        let cfg: EngineConfig = hcl::from_str(&init.engine())?;
+        assert_eq!(Some(work), cfg.workdir);
+        assert!(!init.engine().contains(":basedir"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_exists() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join(ENGINE_CONFIG), "version = 2")?;
+
+        let mut init = Init::new(dir.path());
+        assert!(init.run().is_err());
+        assert!(init.force(true).run().is_ok());
+        Ok(())
+    }
+}
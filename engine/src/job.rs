@@ -5,15 +5,37 @@
 //! supposed to be collecting data (like `fetch` or `stream`) and send it along
 //! the pipe for processing.
 //!
-use std::collections::VecDeque;
+//! A job can have a wall-clock `timeout`, distinct from the duration of a stream: when it expires
+//! the job stops waiting for its tasks and fails with `EngineStatus::TimedOut`.  Threads of wedged
+//! tasks can not be killed, they end on their own as soon as they try to send anything down the
+//! now closed pipeline (sinks derived with `SinkDerive` check after every batch).
+//!
+//! Every job has a `Cancel` token (see `cancel.rs`), set when it times out or by
+//! `Engine::cancel_job()`: stages stop at their next batch and the job fails as `Cancelled`.
+//! Once the pipeline is done, all threads are joined and the job fails with the first error
+//! returned by a stage, if any.
+//!
+//! With a `Progress` channel, the job reports how far it is while it runs (see `progress.rs`).
+//!
+use std::collections::{BTreeMap, VecDeque};
 use std::io::Write;
-use std::sync::mpsc::channel;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
 use eyre::Result;
-use tracing::{info, trace};
+use tracing::{info, trace, warn};
 use tracing::{span, Level};
 
-use crate::{EngineStatus, Runnable, IO};
+use crate::{
+    records, Cancel, EngineStatus, Export, JobProfile, Metrics, Progress, ProgressEvent, Runnable,
+    StageProgress, Tuning, IO,
+};
+
+/// How often a job waiting for its pipeline checks whether it has been cancelled
+const CANCEL_POLL: Duration = Duration::from_millis(100);
 
 /// The engine is processing jobs, made of runnable tasks
 ///
@@ -25,6 +47,26 @@ pub struct Job {
     pub name: String,
     /// FIFO list of tasks
     pub list: VecDeque<Box<dyn Runnable>>,
+    /// Private working directory, set by `Engine::create_job()`
+    pub workdir: Option<PathBuf>,
+    /// Long-running stream, started even when free space is low (see `SpaceMonitor`)
+    pub stream: bool,
+    /// Wall-clock limit, the engine default is used for non-stream jobs if not set
+    pub timeout: Option<Duration>,
+    /// Counters of each task, set by `run()`
+    pub stages: Vec<Metrics>,
+    /// Site the data comes from, its records are counted in the statistics
+    pub source: Option<String>,
+    /// Where progress events go, set by `Engine::create_job()`
+    pub progress: Option<Progress>,
+    /// Expected runtime, set by `Engine::run_job()` from previous runs
+    pub expected: Option<Duration>,
+    /// Artifacts exported once the job is done, see `export.rs`
+    pub export: Option<Export>,
+    /// Tuning of the stages by name, see `tuning.rs`
+    pub tuning: BTreeMap<String, Tuning>,
+    /// Set to stop all the stages
+    pub cancel: Cancel,
 }
 
 impl Job {
@@ -40,6 +82,16 @@ impl Job {
             id: 0,
             name: name.to_owned(),
             list: VecDeque::new(),
+            workdir: None,
+            stream: false,
+            timeout: None,
+            stages: vec![],
+            source: None,
+            progress: None,
+            expected: None,
+            export: None,
+            tuning: BTreeMap::new(),
+            cancel: Cancel::new(),
         }
     }
 
@@ -53,6 +105,16 @@ impl Job {
             id,
             name: name.to_owned(),
             list: VecDeque::new(),
+            workdir: None,
+            stream: false,
+            timeout: None,
+            stages: vec![],
+            source: None,
+            progress: None,
+            expected: None,
+            export: None,
+            tuning: BTreeMap::new(),
+            cancel: Cancel::new(),
         }
     }
 
@@ -72,8 +134,8 @@ impl Job {
     ///
     /// The returned value is the last "output" channel which is the result of the pipeline run.
     ///
-    /// For now we ignore the handle for all threads, should we store them and `join()` later?  They
-    /// are launched in parallel but each one depends on the reading of the "in" pipe.
+    /// The threads are launched in parallel but each one depends on the reading of the "in" pipe,
+    /// they are joined once the last one is done.
     ///
     /// By using only channels between all threads, we should avoid any issues with passing something
     /// more complicated like we did with `out`.
@@ -123,12 +185,26 @@ impl Job {
 
         trace!("create pipeline");
 
-        // Gather results for all tasks into a single pipeline using `Iterator::fold()`
+        // Gather results for all tasks into a single pipeline using `Iterator::fold()`, each one
+        // with its own counters
         //
-        let output = self.list.iter_mut().fold(stdout, |acc, t| {
-            let (rx, _) = t.run(acc);
-            rx
-        });
+        self.stages = self.list.iter().map(|t| Metrics::new(&t.name())).collect();
+        let mut handles = vec![];
+        let output =
+            self.list
+                .iter_mut()
+                .zip(self.stages.iter())
+                .fold(stdout, |acc, (t, stage)| {
+                    let tuning = self.tuning.get(&t.name().to_lowercase());
+                    let (rx, h) = t.run(
+                        acc,
+                        stage.clone(),
+                        tuning.copied().unwrap_or_default(),
+                        self.cancel.clone(),
+                    );
+                    handles.push((t.name(), h));
+                    rx
+                });
 
         trace!("starting pipe");
 
@@ -140,25 +216,298 @@ impl Job {
         //
         drop(key);
 
-        // Wait for final output to be received and send it out
+        // Wait for final output to be received and send it out, within the time limit if any,
+        // reporting progress on the way if asked to
         //
-        for msg in output {
+        let start = Instant::now();
+        self.report(ProgressEvent::Started {
+            job: self.id,
+            name: self.name.clone(),
+            eta: self.eta(start),
+        });
+        let deadline = self.timeout.map(|t| start + t);
+        let every = self.progress.as_ref().map(|p| p.every);
+        let mut tick = every.map(|every| start + every);
+        let (mut emitted, mut bytes) = (0, 0);
+        loop {
+            // Wake up regularly to see whether we have been cancelled
+            //
+            let until = [deadline, tick, Some(Instant::now() + CANCEL_POLL)]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(start);
+            let msg = match output.recv_timeout(until.saturating_duration_since(Instant::now())) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                    let secs = self.timeout.unwrap_or_default().as_secs();
+                    warn!("Job({}) timed out after {}s", self.id, secs);
+                    self.cancel.cancel();
+                    return self.abort(start, emitted, out, EngineStatus::TimedOut(self.id, secs));
+                }
+                Err(RecvTimeoutError::Timeout) if self.cancel.is_cancelled() => {
+                    warn!("Job({}) cancelled", self.id);
+                    return self.abort(start, emitted, out, EngineStatus::Cancelled(self.id));
+                }
+                Err(RecvTimeoutError::Timeout) => String::new(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            emitted += records(&msg);
+            bytes += msg.len() as u64;
             write!(out, "{}", msg)?;
+
+            if let (Some(every), Some(at)) = (every, tick) {
+                if Instant::now() >= at {
+                    self.report_progress(start, emitted, bytes);
+                    tick = Some(Instant::now() + every);
+                }
+            }
         }
         trace!("pipe finished.");
+
+        // The last stage is done, the others may have stopped on an error.  Stages stopped by a
+        // cancellation may complain about it, this is not what failed.
+        //
+        let cancelled = self.cancel.is_cancelled();
+        let res = self.join(handles);
+        if cancelled {
+            return self.abort(start, emitted, out, EngineStatus::Cancelled(self.id));
+        }
+        if let Err(e) = res {
+            return self.abort(start, emitted, out, e);
+        }
+        self.account(emitted);
+        self.report(ProgressEvent::Finished {
+            job: self.id,
+            elapsed: start.elapsed().as_secs_f64(),
+            records: emitted,
+            bytes,
+        });
         Ok(out.flush()?)
     }
+
+    /// Wait for all the stages, the first error is returned.  Others are told to stop as soon as
+    /// one fails.
+    ///
+    fn join(&self, handles: Vec<(String, JoinHandle<Result<()>>)>) -> Result<(), EngineStatus> {
+        let mut res = Ok(());
+        for (name, h) in handles.into_iter().rev() {
+            let err = match h.join() {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => EngineStatus::TaskFailed(name, e.to_string()),
+                Err(_) => EngineStatus::TaskPanicked(name),
+            };
+            warn!("Job({}): {}", self.id, err);
+            self.cancel.cancel();
+            if res.is_ok() {
+                res = Err(err);
+            }
+        }
+        res
+    }
+
+    /// Stop waiting for the pipeline and fail with `e`
+    ///
+    fn abort(
+        &self,
+        start: Instant,
+        emitted: usize,
+        out: &mut dyn Write,
+        e: EngineStatus,
+    ) -> Result<()> {
+        self.account(emitted);
+        out.flush()?;
+        self.report(ProgressEvent::Failed {
+            job: self.id,
+            elapsed: start.elapsed().as_secs_f64(),
+            error: e.to_string(),
+        });
+        Err(e.into())
+    }
+
+    /// Records out of each stage so far
+    ///
+    fn outputs(&self, emitted: usize) -> Vec<usize> {
+        let stats = self.stages.iter().map(|m| m.get()).collect::<Vec<_>>();
+        (0..stats.len())
+            .map(|i| match stats.get(i + 1) {
+                Some(next) => next.records_in,
+                None if self.list.back().map(|t| t.cap()) == Some(IO::Consumer) => {
+                    stats[i].records_in.saturating_sub(stats[i].dropped())
+                }
+                None => emitted,
+            })
+            .collect()
+    }
+
+    /// Seconds left according to the expected runtime
+    ///
+    fn eta(&self, start: Instant) -> Option<u64> {
+        self.expected
+            .map(|t| t.saturating_sub(start.elapsed()).as_secs_f64().round() as u64)
+    }
+
+    /// Send an event on the progress channel, if any
+    ///
+    fn report(&self, ev: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.emit(&ev);
+        }
+    }
+
+    /// Where each stage stands
+    ///
+    fn report_progress(&self, start: Instant, emitted: usize, bytes: u64) {
+        let stages = self
+            .stages
+            .iter()
+            .zip(self.outputs(emitted))
+            .map(|(m, records)| {
+                let stats = m.get();
+                StageProgress {
+                    stage: stats.name.clone(),
+                    records,
+                    dropped: stats.dropped(),
+                }
+            })
+            .collect();
+        self.report(ProgressEvent::Progress {
+            job: self.id,
+            elapsed: start.elapsed().as_secs_f64(),
+            records: emitted,
+            bytes,
+            eta: self.eta(start),
+            stages,
+        });
+    }
+
+    /// Records emitted by each stage are those received by the next one, `emitted` by the last
+    /// one unless it is a consumer which keeps what it did not drop.
+    ///
+    fn account(&self, emitted: usize) {
+        for (stage, out) in self.stages.iter().zip(self.outputs(emitted)) {
+            stage.output(out);
+        }
+    }
+
+    /// Counters of the last run
+    ///
+    pub fn profile(&self) -> JobProfile {
+        JobProfile {
+            id: self.id,
+            name: self.name.clone(),
+            tm: Utc::now().timestamp(),
+            stages: self.stages.iter().map(|m| m.get()).collect(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Copy, Engine, Message, Nothing};
+    use std::sync::mpsc::Sender;
+    use std::thread;
+
+    use std::sync::{Arc, Mutex};
+
+    use fetiche_macros::{AsyncRunnableDerive, RunnableDerive, SinkDerive};
+
+    use crate::{
+        cancelled, Copy, Engine, Message, Nothing, QueueEvent, SpaceConfig, SpaceMonitor, Threshold,
+    };
 
     use super::*;
 
+    /// A producer stuck for a while, like an HTTP call without answer
+    ///
+    #[derive(Clone, Debug, RunnableDerive)]
+    struct Wedged {
+        io: IO,
+    }
+
+    impl Wedged {
+        fn execute(&mut self, _data: String, stdout: Sender<String>) -> Result<()> {
+            thread::sleep(Duration::from_secs(2));
+            let _ = stdout.send("late".to_string());
+            Ok(())
+        }
+    }
+
+    /// A producer running until cancelled, like a stream
+    ///
+    #[derive(Clone, Debug, RunnableDerive)]
+    struct Forever {
+        io: IO,
+    }
+
+    impl Forever {
+        fn execute(&mut self, _data: String, stdout: Sender<String>) -> Result<()> {
+            while !cancelled() {
+                stdout.send("tick\n".to_string())?;
+                thread::sleep(Duration::from_millis(5));
+            }
+            Ok(())
+        }
+    }
+
+    /// A filter failing on everything
+    ///
+    #[derive(Clone, Debug, RunnableDerive)]
+    struct Broken {
+        io: IO,
+    }
+
+    impl Broken {
+        fn execute(&mut self, _data: String, _stdout: Sender<String>) -> Result<()> {
+            Err(EngineStatus::EmptyTaskList.into())
+        }
+    }
+
+    /// Sink keeping what it gets
+    ///
+    #[derive(Clone, Debug, SinkDerive)]
+    struct Keep {
+        io: IO,
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Keep {
+        fn new() -> Self {
+            Keep {
+                io: IO::Consumer,
+                seen: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        fn write_batch(&mut self, data: String) -> Result<()> {
+            if data == "boom" {
+                return Err(EngineStatus::EmptyTaskList.into());
+            }
+            self.seen.lock().unwrap().push(data);
+            Ok(())
+        }
+    }
+
+    /// Filter waiting on the runtime before sending its input along
+    ///
+    #[derive(Clone, Debug, AsyncRunnableDerive)]
+    struct Later {
+        io: IO,
+    }
+
+    impl Later {
+        async fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            stdout.send(data.to_uppercase())?;
+            Ok(())
+        }
+    }
+
     #[test]
     fn test_job_run_nothing() {
-        let mut e = Engine::new();
+        // Not the default engine, it holds the lock on its home
+        //
+        let dir = tempfile::tempdir().unwrap();
+        let mut e = Engine::local(Some(dir.path().to_path_buf())).unwrap();
         let t1 = Box::new(Nothing::new());
         let t2 = Box::new(Copy::new());
 
@@ -178,7 +527,10 @@ mod tests {
 
     #[test]
     fn test_job_run_message() {
-        let mut e = Engine::new();
+        // Not the default engine, it holds the lock on its home
+        //
+        let dir = tempfile::tempdir().unwrap();
+        let mut e = Engine::local(Some(dir.path().to_path_buf())).unwrap();
         let t1 = Box::new(Message::new("hello world"));
         let t2 = Box::new(Copy::new());
 
@@ -195,4 +547,211 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!("hello world", res.unwrap())
     }
+
+    #[test]
+    fn test_job_run_local() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut e = Engine::local(Some(dir.path().to_path_buf()))?;
+        assert!(e.sources().contains_key("opensky"));
+
+        let mut j: Job = e.create_job("test");
+        j.add(Box::new(Message::new("hello world")));
+        j.add(Box::new(Copy::new()));
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+        assert_eq!("hello world", String::from_utf8(data)?);
+        assert!(dir.path().join(crate::STATE_FILE).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_run_timeout() {
+        let mut j = Job::new("wedged");
+        j.add(Box::new(Wedged { io: IO::Producer }));
+        j.add(Box::new(Copy::new()));
+        j.timeout = Some(Duration::from_millis(100));
+
+        let mut data = vec![];
+
+        let start = Instant::now();
+        let res = j.run(&mut data);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let err = res.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::TimedOut(_, 0))
+        ));
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_job_profile() -> Result<()> {
+        let mut j = Job::new("profile");
+        j.add(Box::new(Message::new("hello\nworld\n")));
+        j.add(Box::new(Copy::new()));
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+
+        let p = j.profile();
+        assert_eq!("profile", p.name);
+        assert_eq!(2, p.stages.len());
+        assert_eq!("Message", p.stages[0].name);
+        assert_eq!(0, p.stages[0].records_in);
+        assert_eq!(2, p.stages[0].records_out);
+        assert_eq!("Copy", p.stages[1].name);
+        assert_eq!(2, p.stages[1].records_in);
+        assert_eq!(2, p.stages[1].records_out);
+        assert_eq!(0, p.stages[1].dropped());
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_run_capacity() -> Result<()> {
+        let mut j = Job::new("bounded");
+        j.add(Box::new(Message::new("hello\nworld\n")));
+        j.add(Box::new(Copy::new()));
+        let bounded = Tuning {
+            capacity: Some(1),
+            ..Tuning::default()
+        };
+        j.tuning.insert("message".to_string(), bounded);
+        j.tuning.insert("copy".to_string(), bounded);
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+        assert_eq!("hello\nworld\n", String::from_utf8(data)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_run_sink() -> Result<()> {
+        let sink = Keep::new();
+        let seen = Arc::clone(&sink.seen);
+
+        let mut j = Job::new("sink");
+        j.add(Box::new(Message::new("hello world")));
+        j.add(Box::new(sink));
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+        assert!(data.is_empty());
+        assert_eq!(vec!["hello world"], *seen.lock().unwrap());
+        assert_eq!(1, j.profile().stages[1].records_in);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sink_cancelled() {
+        let mut sink = Keep::new();
+        let seen = Arc::clone(&sink.seen);
+
+        // Nobody is listening anymore, the sink stops after the first batch
+        //
+        let (tx, rx) = channel();
+        let (out, h) = sink.run(rx, Metrics::new("keep"), Tuning::default(), Cancel::new());
+        drop(out);
+        tx.send("one".to_string()).unwrap();
+        assert!(h.join().unwrap().is_ok());
+        assert!(tx.send("two".to_string()).is_err());
+        assert_eq!(vec!["one"], *seen.lock().unwrap());
+    }
+
+    #[test]
+    fn test_sink_error() {
+        let mut sink = Keep::new();
+
+        let (tx, rx) = channel();
+        let (_out, h) = sink.run(rx, Metrics::new("keep"), Tuning::default(), Cancel::new());
+        tx.send("boom".to_string()).unwrap();
+        assert!(h.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_job_run_async() -> Result<()> {
+        let mut j = Job::new("async");
+        j.add(Box::new(Message::new("hello world")));
+        j.add(Box::new(Later { io: IO::Filter }));
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+        assert_eq!("HELLO WORLD", String::from_utf8(data)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_run_cancel() {
+        let mut j = Job::new("forever");
+        j.add(Box::new(Forever { io: IO::Producer }));
+        j.add(Box::new(Copy::new()));
+
+        let cancel = j.cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+
+        let mut data = vec![];
+        let err = j.run(&mut data).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::Cancelled(0))
+        ));
+        assert!(String::from_utf8(data).unwrap().starts_with("tick\n"));
+    }
+
+    #[test]
+    fn test_job_run_task_error() {
+        let mut j = Job::new("broken");
+        j.add(Box::new(Message::new("hello world")));
+        j.add(Box::new(Broken { io: IO::Filter }));
+
+        let mut data = vec![];
+        let err = j.run(&mut data).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::TaskFailed(name, _)) if name == "Broken"
+        ));
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_engine_cancel_job() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut e = Engine::local(Some(dir.path().to_path_buf()))?;
+        let events = e.subscribe();
+
+        // Whatever the free space of the machine running the tests, the job must be accepted
+        //
+        let cfg = SpaceConfig {
+            low: Threshold::Bytes(0),
+            critical: Threshold::Bytes(0),
+            ..SpaceConfig::default()
+        };
+        let paths = [dir.path().to_path_buf()];
+        e.space = Arc::new(SpaceMonitor::new(&cfg, &paths, Arc::clone(&e.subscribers)));
+
+        let mut j: Job = e.create_job("forever");
+        j.add(Box::new(Forever { io: IO::Producer }));
+        j.add(Box::new(Copy::new()));
+        let id = j.id;
+
+        let other = e.clone();
+        thread::spawn(move || {
+            while other.cancel_job(id).is_err() {
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let mut data = vec![];
+        let err = e.run_job(j, &mut data).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::Cancelled(n)) if *n == id
+        ));
+        assert!(e.running.lock().unwrap().is_empty());
+        assert!(events.try_iter().any(|ev| ev == QueueEvent::Cancelled(id)));
+        Ok(())
+    }
 }
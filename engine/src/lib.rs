@@ -16,7 +16,7 @@
 //!
 //! // For the moment the whole of Engine is sync so we need to block.
 //! //
-//! let res = tokio::task::spawn_blocking(move || println!("{}", engine.list_tokens())).await?;
+//! let res = tokio::task::spawn_blocking(move || println!("{}", engine.list_tokens(OutputFormat::Table))).await?;
 //! # }
 //! ```
 //!
@@ -39,35 +39,87 @@ use std::fmt::Debug;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::Receiver;
-use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread::JoinHandle;
+use std::time::Duration;
 
+use chrono::DateTime;
 use eyre::Result;
 use serde::Deserialize;
 use strum::EnumString;
 use tracing::{debug, error, info, trace, warn};
 
-use fetiche_common::{ConfigFile, Container, IntoConfig, Versioned};
+use fetiche_common::{
+    ConfigFile, Container, IntoConfig, Listing, OutputFormat, Redaction, Redactions, Versioned,
+};
 use fetiche_formats::Format;
 use fetiche_macros::into_configfile;
 use fetiche_sources::Sources;
 
+pub use archive::*;
+pub use builder::*;
+pub use cancel::*;
+pub use chain::*;
 pub use error::*;
+pub use export::*;
+pub use history::*;
+pub use init::*;
 pub use job::*;
+pub use live::*;
+pub use lock::*;
+pub use metrics::*;
+pub use migrate::*;
 pub use parse::*;
+pub use plan::*;
+pub use portable::*;
+pub use progress::*;
+pub use queue::*;
+pub use readiness::*;
+pub use refresh::*;
+pub use scaler::*;
+pub use space::*;
+pub use spec::*;
 pub use state::*;
+pub use status::*;
 pub use storage::*;
 pub use task::*;
+pub use ticker::*;
 pub use tokens::*;
+pub use tuning::*;
 
+mod archive;
+mod builder;
+mod cancel;
+mod chain;
 mod error;
+mod export;
+mod history;
+mod init;
 mod job;
+mod live;
+mod lock;
+mod metrics;
+mod migrate;
 mod parse;
+mod plan;
+mod portable;
+mod progress;
+mod proto;
+mod queue;
+mod readiness;
+mod refresh;
+mod scaler;
+mod space;
+mod spec;
 mod state;
+mod status;
 mod storage;
 mod task;
+mod ticker;
 mod tokens;
+mod tuning;
+mod workdir;
 
 /// Engine signature
 ///
@@ -84,6 +136,12 @@ const ENGINE_PID: &str = "acutectl.pid";
 /// Configuration file version
 const ENGINE_VERSION: usize = 2;
 
+/// Number of failed job directories kept by default
+const KEEP_FAILED: usize = 10;
+
+/// Directory used in local mode, under the temporary directory
+const LOCAL_DIR: &str = "fetiche-local";
+
 /// Main state data file, will be created in `basedir`.
 pub(crate) const STATE_FILE: &str = "state";
 
@@ -95,6 +153,45 @@ pub struct EngineConfig {
     pub basedir: PathBuf,
     /// List of storage types
     pub storage: BTreeMap<String, StorageConfig>,
+    /// Where per-job working directories are created, `basedir/work` by default
+    #[serde(default)]
+    pub workdir: Option<PathBuf>,
+    /// Number of failed job directories kept for debugging
+    #[serde(default = "default_keep_failed")]
+    pub keep_failed: usize,
+    /// Named redaction policies for exported data
+    #[serde(default)]
+    pub redact: Redactions,
+    /// Free space thresholds
+    #[serde(default)]
+    pub space: SpaceConfig,
+    /// Default wall-clock limit of non-stream jobs in minutes, 0 is no limit
+    #[serde(default)]
+    pub job_timeout: u64,
+    /// Bounds and thresholds of worker autoscaling
+    #[serde(default)]
+    pub workers: ScalingConfig,
+    /// Background refresh of authentication tokens
+    #[serde(default)]
+    pub tokens: RefreshConfig,
+    /// Number of days of job history kept
+    #[serde(default = "default_history_days")]
+    pub history_days: u64,
+    /// Archival of old job artifacts
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+}
+
+/// Default number of failed job directories we keep
+///
+fn default_keep_failed() -> usize {
+    KEEP_FAILED
+}
+
+/// Default number of days of job history we keep
+///
+fn default_history_days() -> u64 {
+    HISTORY_DAYS
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -114,20 +211,52 @@ pub enum StorageConfig {
 pub struct Engine {
     /// Current process DI
     pub pid: u32,
+    /// Lock on `home`, held as long as one clone of the engine is alive
+    pub lock: Option<Arc<EngineLock>>,
     /// Next job ID
     pub next: Arc<AtomicUsize>,
-    /// Main area where state is saved (PID, jobs, etc.)
+    /// Main area where configuration files are
     pub home: Arc<PathBuf>,
+    /// Main area where state is saved (PID, jobs, etc.), usually the same as `home`
+    pub state_dir: Arc<PathBuf>,
     /// Sources
     pub sources: Arc<Sources>,
     /// Storage area for long-running jobs
     pub storage: Arc<Storage>,
     /// Storage are for auth tokens
     pub tokens: Arc<TokenStorage>,
+    /// Keep tokens fresh
+    pub refresher: Arc<TokenRefresher>,
+    /// Root of all per-job working directories
+    pub workdir: Arc<PathBuf>,
+    /// Number of failed job directories kept
+    pub keep_failed: usize,
+    /// Default wall-clock limit of non-stream jobs
+    pub job_timeout: Option<Duration>,
+    /// Number of days of job history kept
+    pub history_days: u64,
+    /// Archival policy
+    pub archive: Option<ArchiveConfig>,
+    /// Redaction policies
+    pub redactions: Arc<Redactions>,
+    /// Free space on all the above
+    pub space: Arc<SpaceMonitor>,
+    /// Worker autoscaling policy
+    pub scaler: Arc<Scaler>,
     /// Current state
     pub state: Arc<RwLock<State>>,
     /// Job Queue
     pub jobs: Arc<RwLock<VecDeque<usize>>>,
+    /// Effective configuration (after merging all layers)
+    pub config: Arc<String>,
+    /// Channels receiving queue changes
+    pub subscribers: Arc<Mutex<Vec<Sender<QueueEvent>>>>,
+    /// Cancellation tokens of the jobs running in this process
+    pub running: Arc<Mutex<BTreeMap<usize, Cancel>>>,
+    /// Where jobs report their progress
+    pub progress: Option<Progress>,
+    /// Where stream jobs publish their records, see `live.rs`
+    pub live: Option<Live>,
 }
 
 impl Engine {
@@ -147,13 +276,38 @@ impl Engine {
 
     /// Load configuration file for the engine.
     ///
-    /// Takes a string or anything that can be turned into a `PathBuf`.
+    /// Takes a string or anything that can be turned into a `PathBuf`.  The optional
+    /// `engine.local.hcl` and `FETICHE_*` environment variables are applied on top of it.
     ///
     #[tracing::instrument]
     pub fn load(fname: &str) -> Result<Self> {
+        Self::load_with(fname, None)
+    }
+
+    /// Same as `load()` but with the state kept in a specific directory, this is the
+    /// "container" mode used when the configuration is read-only: no PID file is written
+    /// and nothing is assumed about `$HOME`.
+    ///
+    #[tracing::instrument]
+    pub fn load_with(fname: &str, state_dir: Option<PathBuf>) -> Result<Self> {
+        Self::open(fname, state_dir, true)
+    }
+
+    /// Same as `load()` but without taking the lock, for commands only looking at what the
+    /// running engine is doing (e.g. `top` or `status`).  No PID file is written.
+    ///
+    #[tracing::instrument]
+    pub fn load_shared(fname: &str) -> Result<Self> {
+        Self::open(fname, None, false)
+    }
+
+    /// Read the configuration and create the engine, locking its home directory if `lock` is
+    /// set and the state is kept there.
+    ///
+    fn open(fname: &str, state_dir: Option<PathBuf>, lock: bool) -> Result<Self> {
         trace!("reading({:?}", fname);
 
-        let root = ConfigFile::<EngineConfig>::load(Some(fname))?;
+        let root = ConfigFile::<EngineConfig>::load_layered(Some(fname))?;
         let cfg = root.inner();
         let home = root.config_path();
         trace!("Home is in {home:?}");
@@ -165,10 +319,53 @@ impl Engine {
             return Err(EngineStatus::BadConfigVersion(cfg.version(), ENGINE_VERSION).into());
         }
 
+        cfg.workers.validate()?;
+
         trace!("load sources");
         let src = Sources::load()?;
         info!("{} sources loaded", src.len());
 
+        Self::with_config(
+            cfg,
+            home,
+            src,
+            state_dir,
+            lock,
+            root.effective().unwrap_or_default(),
+        )
+    }
+
+    /// Local mode for when there is no configuration at all: default settings, only the
+    /// built-in public sources (see `Sources::builtin()`) and everything, state included, kept
+    /// in `dir` (a temporary directory by default).  No PID file is written.
+    ///
+    #[tracing::instrument]
+    pub fn local(dir: Option<PathBuf>) -> Result<Self> {
+        let dir = dir.unwrap_or(std::env::temp_dir().join(LOCAL_DIR));
+        warn!("No configuration, local mode in {dir:?}");
+
+        let cfg = EngineConfig {
+            basedir: dir.clone(),
+            keep_failed: KEEP_FAILED,
+            history_days: HISTORY_DAYS,
+            ..EngineConfig::default()
+        };
+        let src = Sources::builtin()?;
+        info!("{} built-in sources", src.len());
+
+        Self::with_config(&cfg, dir.clone(), src, Some(dir), false, String::new())
+    }
+
+    /// Everything after reading the configuration files
+    ///
+    fn with_config(
+        cfg: &EngineConfig,
+        home: PathBuf,
+        mut src: Sources,
+        state_dir: Option<PathBuf>,
+        lock: bool,
+        config: String,
+    ) -> Result<Self> {
         // Register storage areas
         //
         trace!("load storage areas");
@@ -179,21 +376,38 @@ impl Engine {
         //
         trace!("load tokens");
         let tokens_area = cfg.basedir.join("tokens").to_string_lossy().to_string();
-        let tokens = TokenStorage::register(&tokens_area);
+        let tokens = Arc::new(TokenStorage::register(&tokens_area));
         info!("{} tokens loaded", tokens.len());
 
-        // Save PID
+        // Sites keep their tokens there too
         //
-        let pid = std::process::id();
-        let pidfile = home.join(ENGINE_PID);
-        fs::write(&pidfile, format!("{pid}"))
-            .unwrap_or_else(|_| panic!("can not write {}", pidfile.to_string_lossy()));
+        let src = Arc::new(src.tokens_in(&cfg.basedir.join("tokens")).clone());
+        let refresher = TokenRefresher::new(&cfg.tokens, Arc::clone(&tokens), Arc::clone(&src));
 
-        info!("PID {} written in {:?}", pid, pidfile);
+        // Lock the home directory with our PID, except in container mode where the orchestrator
+        // is handling this and for engines only looking at the state.
+        //
+        let pid = std::process::id();
+        let (state_dir, lock) = match state_dir {
+            Some(dir) => {
+                if !dir.exists() {
+                    fs::create_dir_all(&dir)
+                        .map_err(|_| EngineStatus::CreateDir(dir.to_string_lossy().to_string()))?;
+                }
+                info!("Using state directory {:?}, no PID file", dir);
+                (dir, None)
+            }
+            None if lock => {
+                let lock = EngineLock::acquire(&home.join(ENGINE_PID))?;
+                info!("PID {} written in {:?}", pid, lock.path);
+                (home.clone(), Some(Arc::new(lock)))
+            }
+            None => (home.clone(), None),
+        };
 
         // Load state
         //
-        let fname = home.join(STATE_FILE);
+        let fname = state_dir.join(STATE_FILE);
         let state = match State::from(fname.clone()) {
             Ok(state) => {
                 info!("State loaded from {}", fname.to_string_lossy());
@@ -208,24 +422,60 @@ impl Engine {
         trace!("state={:?}", state);
 
         let jobs = VecDeque::<usize>::new();
+        let workdir = cfg.workdir.clone().unwrap_or(cfg.basedir.join("work"));
+        trace!("workdir={:?}", workdir);
+
+        // Everywhere we write into
+        //
+        let subscribers = Arc::new(Mutex::new(vec![]));
+        let mut paths = vec![cfg.basedir.clone(), workdir.clone(), state_dir.clone()];
+        paths.extend(cfg.storage.values().filter_map(|area| match area {
+            StorageConfig::Directory { path, .. } | StorageConfig::Hive { path } => {
+                Some(path.clone())
+            }
+            StorageConfig::Cache { .. } => None,
+        }));
+        let space = SpaceMonitor::new(&cfg.space, &paths, Arc::clone(&subscribers));
 
         // Instantiate everything
         //
         let engine = Engine {
             pid,
+            lock,
             next: Arc::new(AtomicUsize::new(state.last + 1)),
             home: Arc::new(home.clone()),
-            sources: Arc::new(src.clone()),
+            state_dir: Arc::new(state_dir),
+            sources: src,
             storage: Arc::new(areas),
-            tokens: Arc::new(tokens),
+            tokens,
+            refresher: Arc::new(refresher),
+            workdir: Arc::new(workdir),
+            keep_failed: cfg.keep_failed,
+            job_timeout: match cfg.job_timeout {
+                0 => None,
+                m => Some(Duration::from_secs(m * 60)),
+            },
+            history_days: cfg.history_days,
+            archive: cfg.archive.clone(),
+            redactions: Arc::new(cfg.redact.clone()),
+            space: Arc::new(space),
+            scaler: Arc::new(Scaler::new(&cfg.workers)),
             state: Arc::new(RwLock::new(state)),
             jobs: Arc::new(RwLock::new(jobs)),
+            config: Arc::new(config),
+            subscribers,
+            running: Arc::new(Mutex::new(BTreeMap::new())),
+            progress: None,
+            live: None,
         };
         info!("New Engine loaded");
 
         // Sync immediately, ensuring state is clean
         //
         engine.sync().expect("can not sync");
+        if let Err(e) = engine.prune_history() {
+            warn!("Can not prune history: {}", e);
+        }
 
         Ok(engine)
     }
@@ -240,7 +490,7 @@ impl Engine {
 
         // Initialise job
         //
-        let job = Job::new_with_id(s, nextid);
+        let mut job = Job::new_with_id(s, nextid);
 
         // Insert into job queue
         //
@@ -251,11 +501,17 @@ impl Engine {
         //
         drop(jobs);
 
+        // Give it its own working directory
+        //
+        job.workdir = self.new_workdir(nextid);
+        job.progress = self.progress.clone();
+
         // Update state
         //
         let mut state = self.state.write().unwrap();
         state.last = nextid;
         state.queue.push_back(nextid);
+        state.queue_job(nextid, s);
 
         // Ensure lock goes away
         //
@@ -263,25 +519,45 @@ impl Engine {
 
         trace!("job {} created.", nextid);
         self.sync().expect("can not sync");
+        self.notify(QueueEvent::Queued(nextid));
+        self.record(nextid, s, JobEvent::Submitted);
 
         job
     }
 
-    /// Remove a job
+    /// Remove a job and its working directory
     ///
     #[tracing::instrument(skip(self))]
     pub fn remove_job(&mut self, job: Job) -> Result<()> {
+        self.drop_workdir(job.id);
+
         trace!("grab lock");
 
         let mut state = self.state.try_write().unwrap();
-        state.remove_job(job.id);
+        state.remove_job(job.id).end_job(job.id, true);
 
         // Prevent deadlock by dropping ownership here, must be a better way to handle this
         //
         drop(state);
 
         trace!("sync");
-        self.sync()
+        self.sync()?;
+        self.notify(QueueEvent::Finished(job.id));
+        Ok(())
+    }
+
+    /// Report the progress of all jobs created from now on into `progress`
+    ///
+    pub fn progress(&mut self, progress: Progress) -> &mut Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Publish the records of all stream jobs created from job files into `live`
+    ///
+    pub fn live(&mut self, live: Live) -> &mut Self {
+        self.live = Some(live);
+        self
     }
 
     /// Return an `Arc::clone` of the Engine sources
@@ -290,40 +566,233 @@ impl Engine {
         Arc::clone(&self.sources)
     }
 
+    /// Return an `Arc::clone` of the free space monitor
+    ///
+    pub fn space(&self) -> Arc<SpaceMonitor> {
+        Arc::clone(&self.space)
+    }
+
     /// Return an `Arc::clone` of the Engine storage areas
     ///
     pub fn storage(&self) -> Arc<Storage> {
         Arc::clone(&self.storage)
     }
 
+    /// Return the redaction policy called `name` from `engine.hcl`
+    ///
+    pub fn redaction(&self, name: &str) -> Result<Redaction> {
+        match self.redactions.get(name) {
+            Some(r) => Ok(r.clone()),
+            None => Err(EngineStatus::UnknownRedaction(name.to_string()).into()),
+        }
+    }
+
     /// Returns a list of all defined storage areas
     ///
-    pub fn list_storage(&self) -> Result<String> {
-        self.storage.list()
+    pub fn list_storage(&self, fmt: OutputFormat) -> Result<String> {
+        self.storage.list(fmt)
     }
 
     /// Return a description of all supported sources
     ///
-    pub fn list_sources(&self) -> Result<String> {
-        self.sources.list()
+    pub fn list_sources(&self, fmt: OutputFormat) -> Result<String> {
+        self.sources.list(fmt)
     }
 
     /// Return a descriptions of all supported data formats
     ///
-    pub fn list_formats(&self) -> Result<String> {
-        Format::list()
+    pub fn list_formats(&self, fmt: OutputFormat) -> Result<String> {
+        Format::list(fmt)
     }
 
     /// Return a descriptions of all supported container formats
     ///
-    pub fn list_containers(&self) -> Result<String> {
-        Container::list()
+    pub fn list_containers(&self, fmt: OutputFormat) -> Result<String> {
+        Container::list(fmt)
+    }
+
+    /// Return the list of jobs still in the queue, with their position and estimated end, and
+    /// of kept working directories
+    ///
+    pub fn list_jobs(&self, fmt: OutputFormat) -> Result<String> {
+        let queue = self.queue();
+        let state = self.state.read().unwrap();
+
+        let mut ids = state
+            .queue
+            .iter()
+            .chain(state.workdirs.keys())
+            .copied()
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+
+        let mut list = Listing::new(
+            "List all jobs",
+            &[
+                ("ID", "id"),
+                ("Name", "name"),
+                ("Status", "status"),
+                ("Position", "position"),
+                ("ETA", "eta"),
+                ("Directory", "workdir"),
+            ],
+        );
+        ids.iter().for_each(|id| {
+            let wd = state.workdirs.get(id);
+            let dir = wd
+                .map(|wd| wd.path.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let row = match queue.iter().find(|q| q.id == *id) {
+                Some(q) => vec![
+                    id.to_string(),
+                    q.name.clone(),
+                    q.status.to_string(),
+                    q.position.to_string(),
+                    q.eta
+                        .and_then(|t| DateTime::from_timestamp(t, 0))
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_default(),
+                    dir,
+                ],
+                None => vec![
+                    id.to_string(),
+                    String::new(),
+                    wd.map(|wd| wd.status.to_string())
+                        .unwrap_or("queued".to_string()),
+                    String::new(),
+                    String::new(),
+                    dir,
+                ],
+            };
+            list.push(row);
+        });
+        list.render(fmt)
+    }
+
+    /// Return the statistics kept in the state file
+    ///
+    pub fn list_stats(&self, fmt: OutputFormat) -> Result<String> {
+        let state = self.state.read().unwrap();
+
+        let mut list = Listing::new("Engine statistics", &[("Name", "name"), ("Value", "value")]);
+        let last = state
+            .stats
+            .last_skew
+            .map(|s| format!("{}ms at {}", s.offset, s.at))
+            .unwrap_or_default();
+        list.push(vec!["last_job".to_string(), state.last.to_string()])
+            .push(vec!["queued".to_string(), state.queue.len().to_string()])
+            .push(vec!["skews".to_string(), state.stats.skews.to_string()])
+            .push(vec![
+                "max_skew".to_string(),
+                state.stats.max_skew.to_string(),
+            ])
+            .push(vec!["last_skew".to_string(), last])
+            .push(vec![
+                "space_alerts".to_string(),
+                state.stats.space_alerts.to_string(),
+            ])
+            .push(vec![
+                "timeouts".to_string(),
+                state.stats.timeouts.to_string(),
+            ])
+            .push(vec![
+                "scalings".to_string(),
+                state.stats.scalings.to_string(),
+            ])
+            .push(vec![
+                "last_scaling".to_string(),
+                state
+                    .stats
+                    .last_scaling
+                    .map(|d| match DateTime::from_timestamp(d.at, 0) {
+                        Some(t) => format!("{} at {}", d, t.to_rfc3339()),
+                        None => d.to_string(),
+                    })
+                    .unwrap_or_default(),
+            ])
+            .push(vec![
+                "last_space".to_string(),
+                state
+                    .stats
+                    .last_space
+                    .as_ref()
+                    .map(|a| match DateTime::from_timestamp(a.at, 0) {
+                        Some(t) => format!("{} at {}", a, t.to_rfc3339()),
+                        None => a.to_string(),
+                    })
+                    .unwrap_or_default(),
+            ]);
+        state.stats.sources.iter().for_each(|(name, s)| {
+            list.push(vec![
+                format!("source:{}", name),
+                format!("{} records ({:.1}/s)", s.records, s.rate()),
+            ]);
+        });
+        list.push(vec![
+            "errors".to_string(),
+            state.stats.errors.len().to_string(),
+        ]);
+        state.stats.groups.iter().for_each(|(name, g)| {
+            list.push(vec![
+                format!("group:{}", name),
+                format!(
+                    "{} ({} served, {} failover(s))",
+                    g.endpoint, g.served, g.failovers
+                ),
+            ]);
+        });
+        // Sites not using the default user agent and headers
+        //
+        self.sources.iter().for_each(|(name, site)| {
+            if let Some(http) = &site.http {
+                list.push(vec![format!("http:{}", name), http.to_string()]);
+            }
+        });
+        list.render(fmt)
+    }
+
+    /// Return the configuration, either the content of `engine.hcl` or the effective one with
+    /// `engine.local.hcl` and the environment applied.
+    ///
+    pub fn show_config(&self, effective: bool) -> Result<String> {
+        if effective {
+            Ok(self.config.to_string())
+        } else {
+            Ok(fs::read_to_string(self.home.join(ENGINE_CONFIG))?)
+        }
+    }
+
+    /// Provenance stamped on the records of job `job` from `source`
+    ///
+    pub fn provenance(&self, source: &str, job: usize) -> Provenance {
+        let url = self
+            .sources
+            .get(source)
+            .map(|site| site.base_url.clone())
+            .unwrap_or_default();
+        Provenance::new(source, &url, job)
+    }
+
+    /// Return the token refresher, starting it if needed
+    ///
+    pub fn refresher(&self) -> Arc<TokenRefresher> {
+        self.refresher.start();
+        Arc::clone(&self.refresher)
+    }
+
+    /// Refresh the tokens expiring soon now, returns the sites involved
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn refresh_tokens(&self) -> Vec<String> {
+        self.refresher.scan()
     }
 
     /// Return a list of all currently available authentication tokens
     ///
-    pub fn list_tokens(&self) -> Result<String> {
-        self.tokens.list()
+    pub fn list_tokens(&self, fmt: OutputFormat) -> Result<String> {
+        self.tokens.list(fmt)
     }
 
     /// Return Engine version (and internal modules)
@@ -361,7 +830,8 @@ pub enum IO {
 /// Anything that can be `run()` is runnable.
 ///
 /// See the engine-macro crate for a proc-macro that implement the `run()`  wrapper for
-/// the `Runnable` trait.
+/// the `Runnable` trait, `AsyncRunnableDerive` does the same for an `async fn execute()` and
+/// `SinkDerive` for final stages only having a `write_batch()`.
 ///
 /// ```no_run
 /// use fetiche_engine::{IO, Runnable};
@@ -376,8 +846,19 @@ pub enum IO {
 /// }
 /// ```
 ///
+/// `run()` gets the counters of the stage, its `Tuning` and the `Cancel` token from the job, the
+/// derived one bounds its output channel with `Tuning::capacity` and stops between batches once
+/// the job is cancelled.  Errors of the stage are returned by its thread, `Job::run()` fails with
+/// the first one.
 ///
 pub trait Runnable: Debug {
     fn cap(&self) -> IO;
-    fn run(&mut self, out: Receiver<String>) -> (Receiver<String>, JoinHandle<Result<()>>);
+    fn name(&self) -> String;
+    fn run(
+        &mut self,
+        out: Receiver<String>,
+        stage: Metrics,
+        tuning: Tuning,
+        cancel: Cancel,
+    ) -> (Receiver<String>, JoinHandle<Result<()>>);
 }
@@ -0,0 +1,473 @@
+//! Live positions over WebSocket, for internal web maps without standing up Kafka.
+//!
+//! `Live` is a hub: stream jobs publish their converted records into it through the `Broadcast`
+//! task (added to every stream job from a job file once the engine has a hub, see `Engine::live()`)
+//! and it fans them out to every client, one record per text message.
+//!
+//! `Live::serve()` accepts clients on `ws://<addr>/ws/live`, with optional filters in the query:
+//!
+//! - `source=senhive` only gets records from this source (several can be given, comma-separated),
+//! - `bbox=<min_lon>,<min_lat>,<max_lon>,<max_lat>` only gets records within these bounds, records
+//!   without a position we can read (see `position()`) are not sent then.
+//!
+//! Publishing never waits for a client: each one has a queue of `LIVE_QUEUE` records, what does
+//! not fit is dropped for that client and a client dropping `LIVE_MAX_DROPS` records in a row is
+//! disconnected (close code 1008, "too slow").
+//!
+
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use eyre::Result;
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use tracing::{debug, info, trace, warn};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::{Message, WebSocket};
+
+use fetiche_formats::Format;
+
+use crate::EngineStatus;
+
+/// Path of the endpoint
+pub const LIVE_PATH: &str = "/ws/live";
+
+/// Records waiting to be sent to one client
+pub const LIVE_QUEUE: usize = 1_000;
+
+/// Records dropped in a row before a client is disconnected
+pub const LIVE_MAX_DROPS: usize = 10_000;
+
+/// How long we wait for a client to send its request
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Idle clients are pinged this often, to notice those who are gone
+const PING: Duration = Duration::from_secs(30);
+
+/// How long an idle client is given to send something (close or pong)
+const READ_POLL: Duration = Duration::from_millis(10);
+
+/// What a client wants to see
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LiveFilter {
+    /// Sources, all of them if empty
+    pub sources: Vec<String>,
+    /// `[min_lon, min_lat, max_lon, max_lat]`
+    pub bbox: Option<[f64; 4]>,
+}
+
+impl LiveFilter {
+    /// Does the client want records from `source`?
+    ///
+    pub fn wants(&self, source: &str) -> bool {
+        self.sources.is_empty() || self.sources.iter().any(|s| s.eq_ignore_ascii_case(source))
+    }
+
+    /// Is this position (if any) within the bounds?
+    ///
+    pub fn contains(&self, pos: Option<(f64, f64)>) -> bool {
+        match (self.bbox, pos) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some([min_lon, min_lat, max_lon, max_lat]), Some((lat, lon))) => {
+                (min_lat..=max_lat).contains(&lat) && (min_lon..=max_lon).contains(&lon)
+            }
+        }
+    }
+}
+
+impl FromStr for LiveFilter {
+    type Err = EngineStatus;
+
+    /// Parse the query string of the request, e.g. `source=senhive&bbox=2.2,48.7,2.6,49.1`
+    ///
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let bad = |s: &str| EngineStatus::BadLiveFilter(s.to_string());
+
+        let mut filter = LiveFilter::default();
+        for kv in query.split('&').filter(|kv| !kv.is_empty()) {
+            let (key, value) = kv.split_once('=').ok_or(bad(kv))?;
+            let value = percent_decode_str(value)
+                .decode_utf8()
+                .map_err(|_| bad(kv))?;
+            match key {
+                "source" => filter.sources.extend(
+                    value
+                        .split(',')
+                        .filter(|s| !s.is_empty())
+                        .map(|s| s.to_string()),
+                ),
+                "bbox" => {
+                    let bbox = value
+                        .split(',')
+                        .map(|v| v.trim().parse::<f64>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| bad(kv))?;
+                    match bbox[..] {
+                        [min_lon, min_lat, max_lon, max_lat]
+                            if min_lon <= max_lon && min_lat <= max_lat =>
+                        {
+                            filter.bbox = Some([min_lon, min_lat, max_lon, max_lat])
+                        }
+                        _ => return Err(bad(kv)),
+                    }
+                }
+                _ => return Err(bad(kv)),
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// One connected client
+///
+#[derive(Debug)]
+struct Client {
+    id: usize,
+    filter: LiveFilter,
+    tx: SyncSender<String>,
+    /// Records dropped in a row
+    drops: usize,
+}
+
+/// Hub between stream jobs and the clients, cheap to clone.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Live {
+    /// Address of the endpoint, if serving
+    addr: Option<SocketAddr>,
+    clients: Arc<Mutex<Vec<Client>>>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Live {
+    /// Hub without any endpoint, clients come through `subscribe()`
+    ///
+    pub fn new() -> Self {
+        Live::default()
+    }
+
+    /// Start accepting WebSocket clients on `addr` in the background.
+    ///
+    #[tracing::instrument]
+    pub fn serve<A: ToSocketAddrs + std::fmt::Debug>(addr: A) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let mut live = Live::new();
+        live.addr = Some(listener.local_addr()?);
+        info!(
+            "Live positions on ws://{}{}",
+            listener.local_addr()?,
+            LIVE_PATH
+        );
+
+        let hub = live.clone();
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                let hub = hub.clone();
+                thread::spawn(move || {
+                    if let Err(e) = hub.answer(client) {
+                        debug!("live: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(live)
+    }
+
+    /// Actual address of the endpoint, useful when binding to port 0
+    ///
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// Number of connected clients
+    ///
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// New client, records for it come out of the returned channel.  It is forgotten once the
+    /// receiver is dropped.
+    ///
+    pub fn subscribe(&self, filter: LiveFilter) -> Receiver<String> {
+        let (tx, rx) = sync_channel(LIVE_QUEUE);
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        debug!("live: client {} with {:?}", id, filter);
+        self.clients.lock().unwrap().push(Client {
+            id,
+            filter,
+            tx,
+            drops: 0,
+        });
+        rx
+    }
+
+    /// Send every record of `data` (in format `fmt`) from `source` to the clients wanting it,
+    /// never waiting for any of them.
+    ///
+    pub fn publish(&self, source: &str, fmt: Format, data: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+
+        for line in data.lines().filter(|l| !l.trim().is_empty()) {
+            let mut pos = None;
+            clients.retain_mut(|client| {
+                if !client.filter.wants(source) {
+                    return true;
+                }
+                if client.filter.bbox.is_some() {
+                    let pos = *pos.get_or_insert_with(|| position(fmt, line));
+                    if !client.filter.contains(pos) {
+                        return true;
+                    }
+                }
+                match client.tx.try_send(line.to_string()) {
+                    Ok(()) => {
+                        client.drops = 0;
+                        true
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        client.drops += 1;
+                        if client.drops < LIVE_MAX_DROPS {
+                            return true;
+                        }
+                        warn!("live: client {} too slow, disconnecting", client.id);
+                        false
+                    }
+                    Err(TrySendError::Disconnected(_)) => {
+                        debug!("live: client {} gone", client.id);
+                        false
+                    }
+                }
+            });
+        }
+    }
+
+    /// Upgrade the connection and send records until the client goes away.  The handshake
+    /// callback has to return the error response of `tungstenite` as is.
+    ///
+    #[allow(clippy::result_large_err)]
+    fn answer(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let _ = stream.set_nodelay(true);
+
+        let mut filter = None;
+        let mut ws = tungstenite::accept_hdr(stream, |req: &Request, resp: Response| {
+            trace!("live: {}", req.uri());
+            let reject = |status: StatusCode, msg: String| {
+                let mut err = ErrorResponse::new(Some(msg));
+                *err.status_mut() = status;
+                Err(err)
+            };
+            if req.uri().path() != LIVE_PATH {
+                return reject(StatusCode::NOT_FOUND, "not found".to_string());
+            }
+            match LiveFilter::from_str(req.uri().query().unwrap_or_default()) {
+                Ok(f) => {
+                    filter = Some(f);
+                    Ok(resp)
+                }
+                Err(e) => reject(StatusCode::BAD_REQUEST, e.to_string()),
+            }
+        })
+        .map_err(|e| EngineStatus::Live(e.to_string()))?;
+        ws.get_ref().set_read_timeout(Some(READ_POLL))?;
+
+        let rx = self.subscribe(filter.unwrap_or_default());
+        let res = relay(&mut ws, rx);
+        if let Err(e) = &res {
+            debug!("live: {}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Copy records to the client, pinging it when idle.  The channel is closed by the hub when the
+/// client is too slow.
+///
+fn relay(ws: &mut WebSocket<TcpStream>, rx: Receiver<String>) -> Result<()> {
+    let mut idle = Duration::ZERO;
+    loop {
+        match rx.recv_timeout(READ_POLL * 10) {
+            Ok(line) => {
+                idle = Duration::ZERO;
+                ws.send(Message::text(line))?;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                idle += READ_POLL * 10;
+                if idle >= PING {
+                    idle = Duration::ZERO;
+                    ws.send(Message::Ping(vec![]))?;
+                }
+
+                // Answer pings and notice clients closing the connection
+                //
+                match ws.read() {
+                    Ok(Message::Close(_)) => return Ok(()),
+                    Ok(_) => ws.flush()?,
+                    Err(tungstenite::Error::Io(e))
+                        if matches!(
+                            e.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                ws.close(Some(CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "too slow".into(),
+                }))?;
+                return Ok(ws.flush()?);
+            }
+        }
+    }
+}
+
+/// Position of one record as `(lat, lon)`: Cat21 CSV lines, GeoJSON features and JSON objects
+/// with `latitude`/`longitude` or `lat`/`lon`, at the top or in `position`.
+///
+pub fn position(fmt: Format, line: &str) -> Option<(f64, f64)> {
+    if fmt == Format::Cat21 {
+        // POS_LAT_DEG and POS_LONG_DEG
+        //
+        let mut fields = line.split(':').skip(3);
+        let lat = fields.next()?.trim().parse().ok()?;
+        let lon = fields.next()?.trim().parse().ok()?;
+        return Some((lat, lon));
+    }
+
+    let v: Value = serde_json::from_str(line).ok()?;
+    if let Some(coords) = v.pointer("/geometry/coordinates") {
+        return Some((coords.get(1)?.as_f64()?, coords.get(0)?.as_f64()?));
+    }
+    std::iter::once(&v).chain(v.get("position")).find_map(|o| {
+        let lat = number(o.get("latitude").or(o.get("lat"))?)?;
+        let lon = number(o.get("longitude").or(o.get("lon"))?)?;
+        Some((lat, lon))
+    })
+}
+
+/// Some sources like ASD send numbers as strings
+///
+fn number(v: &Value) -> Option<f64> {
+    match v {
+        Value::String(s) => s.parse().ok(),
+        v => v.as_f64(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+    use tungstenite::client::IntoClientRequest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("", LiveFilter::default())]
+    #[case("source=senhive", LiveFilter { sources: vec!["senhive".to_string()], bbox: None })]
+    #[case("source=asd,senhive&bbox=2.2,48.7,2.6,49.1", LiveFilter {
+        sources: vec!["asd".to_string(), "senhive".to_string()],
+        bbox: Some([2.2, 48.7, 2.6, 49.1]),
+    })]
+    #[case("bbox=2.2%2C48.7%2C2.6%2C49.1", LiveFilter { sources: vec![], bbox: Some([2.2, 48.7, 2.6, 49.1]) })]
+    fn test_live_filter(#[case] query: &str, #[case] filter: LiveFilter) {
+        assert_eq!(filter, LiveFilter::from_str(query).unwrap());
+    }
+
+    #[rstest]
+    #[case("bbox=1,2,3")]
+    #[case("bbox=3,2,1,4")]
+    #[case("bbox=a,b,c,d")]
+    #[case("site=asd")]
+    #[case("source")]
+    fn test_live_filter_bad(#[case] query: &str) {
+        assert!(LiveFilter::from_str(query).is_err());
+    }
+
+    #[rstest]
+    #[case(Format::Cat21, "8:200:393:49.6116:6.2061:393:0", Some((49.6116, 6.2061)))]
+    #[case(Format::Asd, r#"{"latitude":"49.6116","longitude":"6.2061"}"#, Some((49.6116, 6.2061)))]
+    #[case(Format::Senhive, r#"{"position":{"lat":49.6116,"lon":6.2061}}"#, Some((49.6116, 6.2061)))]
+    #[case(Format::None, r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[6.2061,49.6116]}}"#, Some((49.6116, 6.2061)))]
+    #[case(Format::Asd, r#"{"ident":"1581F5"}"#, None)]
+    #[case(Format::Sbs1, "MSG,3,1,1,4CA2D6", None)]
+    fn test_position(#[case] fmt: Format, #[case] line: &str, #[case] pos: Option<(f64, f64)>) {
+        assert_eq!(pos, position(fmt, line));
+    }
+
+    #[test]
+    fn test_live_publish() {
+        let live = Live::new();
+        let all = live.subscribe(LiveFilter::default());
+        let paris =
+            live.subscribe(LiveFilter::from_str("source=asd&bbox=2.2,48.7,2.6,49.1").unwrap());
+        let gone = live.subscribe(LiveFilter::default());
+        drop(gone);
+
+        let data =
+            "{\"latitude\":48.85,\"longitude\":2.35}\n\n{\"latitude\":49.61,\"longitude\":6.2}\n";
+        live.publish("asd", Format::Asd, data);
+        live.publish("senhive", Format::Asd, data);
+
+        assert_eq!(4, all.try_iter().count());
+        assert_eq!(
+            vec!["{\"latitude\":48.85,\"longitude\":2.35}"],
+            paris.try_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(2, live.clients());
+    }
+
+    #[test]
+    fn test_live_slow_client() {
+        let live = Live::new();
+        let slow = live.subscribe(LiveFilter::default());
+
+        let data = "{}\n".repeat(LIVE_QUEUE + LIVE_MAX_DROPS - 1);
+        live.publish("asd", Format::Asd, &data);
+        assert_eq!(1, live.clients());
+
+        // One more and it is out, with what was queued
+        //
+        live.publish("asd", Format::Asd, "{}");
+        assert_eq!(0, live.clients());
+        assert_eq!(LIVE_QUEUE, slow.iter().count());
+    }
+
+    #[test]
+    fn test_live_serve() -> Result<()> {
+        let live = Live::serve("127.0.0.1:0")?;
+        let addr = live.local_addr().unwrap();
+
+        let req = format!("ws://{addr}{LIVE_PATH}?source=senhive").into_client_request()?;
+        let (mut ws, _) = tungstenite::client(req, TcpStream::connect(addr)?)?;
+        while live.clients() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        live.publish("asd", Format::Asd, "{\"id\":1}");
+        live.publish("senhive", Format::Senhive, "{\"id\":2}\n{\"id\":3}\n");
+        assert_eq!(Message::text("{\"id\":2}"), ws.read()?);
+        assert_eq!(Message::text("{\"id\":3}"), ws.read()?);
+
+        // Wrong path or filter
+        //
+        let req = format!("ws://{addr}/ws/other").into_client_request()?;
+        assert!(tungstenite::client(req, TcpStream::connect(addr)?).is_err());
+        let req = format!("ws://{addr}{LIVE_PATH}?bbox=1").into_client_request()?;
+        assert!(tungstenite::client(req, TcpStream::connect(addr)?).is_err());
+        Ok(())
+    }
+}
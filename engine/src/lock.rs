@@ -0,0 +1,242 @@
+//! Exclusive lock on the engine home directory.
+//!
+//! The PID file (`acutectl.pid`) is also a lock file: it is kept open with an exclusive
+//! `flock(2)` for the whole life of the engine and holds our PID and start time.  The kernel
+//! releases the lock when the process goes away, even after a crash or `kill -9`, so a file
+//! left behind is never mistaken for a running engine: if we get the lock, whatever is in the
+//! file is stale and we take it over.
+//!
+//! When the lock is held by someone else, the PID and start time in the file are used to tell
+//! the user which engine is running.  Commands which only look at the state (e.g. `top`) do not
+//! take the lock, see `Engine::load_shared()`.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use eyre::Result;
+use tracing::{info, trace, warn};
+
+use crate::EngineStatus;
+
+/// Owner of the lock, as written in the file: `<pid> <start time>`
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockOwner {
+    /// Process ID
+    pub pid: u32,
+    /// When it got the lock
+    pub started: DateTime<Utc>,
+}
+
+impl LockOwner {
+    /// From the contents of the lock file, older versions only wrote the PID.
+    ///
+    fn parse(s: &str) -> Option<Self> {
+        let mut it = s.split_whitespace();
+        let pid = it.next()?.parse().ok()?;
+        let started = it
+            .next()
+            .and_then(|tm| DateTime::parse_from_rfc3339(tm).ok())
+            .map(|tm| tm.with_timezone(&Utc))
+            .unwrap_or_default();
+        Some(LockOwner { pid, started })
+    }
+}
+
+impl Display for LockOwner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.pid,
+            self.started.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )
+    }
+}
+
+/// The lock itself, released when dropped.
+///
+#[derive(Debug)]
+pub struct EngineLock {
+    /// Lock file
+    pub path: PathBuf,
+    /// Us
+    pub owner: LockOwner,
+    /// Open file holding the `flock`
+    file: File,
+}
+
+impl EngineLock {
+    /// Get the lock in `path` or explain who has it.
+    ///
+    #[tracing::instrument]
+    pub fn acquire(path: &Path) -> Result<Self> {
+        trace!("EngineLock::acquire");
+
+        let name = path.to_string_lossy().to_string();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| EngineStatus::LockFile(name.clone(), e.to_string()))?;
+
+        let mut previous = String::new();
+        file.read_to_string(&mut previous).unwrap_or_default();
+        let previous = LockOwner::parse(&previous);
+
+        if !try_lock(&file)? {
+            return Err(match previous {
+                Some(owner) => EngineStatus::AlreadyRunning(
+                    owner.pid,
+                    owner.started.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    name,
+                ),
+                None => EngineStatus::AlreadyRunning(0, "?".to_string(), name),
+            }
+            .into());
+        }
+
+        // The lock was free so the previous owner is gone, even if its PID has been reused.
+        //
+        if let Some(owner) = previous {
+            match alive(owner.pid) {
+                true => warn!(
+                    "Taking over stale lock of PID {} (now used by another process)",
+                    owner.pid
+                ),
+                false => warn!("Taking over stale lock of dead PID {}", owner.pid),
+            }
+        }
+
+        let owner = LockOwner {
+            pid: std::process::id(),
+            started: Utc::now().trunc_subsecs(0),
+        };
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{owner}")?;
+        file.flush()?;
+
+        info!("Lock {:?} taken by PID {}", path, owner.pid);
+        Ok(EngineLock {
+            path: path.to_path_buf(),
+            owner,
+            file,
+        })
+    }
+}
+
+impl Drop for EngineLock {
+    /// Empty the file but leave it there, removing it would race with another engine opening
+    /// it.  The `flock` goes away with the file descriptor.
+    ///
+    fn drop(&mut self) {
+        trace!("EngineLock::drop");
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// Non-blocking exclusive lock, `false` if someone else has it
+///
+#[cfg(unix)]
+fn try_lock(file: &File) -> Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: the descriptor is valid for the life of `file`.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(err.into()),
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> Result<bool> {
+    Ok(true)
+}
+
+/// Is there a process with this PID?
+///
+#[cfg(unix)]
+fn alive(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks for the existence of the process.
+    pid != 0
+        && (unsafe { libc::kill(pid as libc::pid_t, 0) } == 0
+            || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
+
+#[cfg(not(unix))]
+fn alive(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_lock_owner_parse() {
+        let tm = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let owner = LockOwner {
+            pid: 42,
+            started: tm,
+        };
+        assert_eq!("42 2024-01-01T12:00:00Z", owner.to_string());
+        assert_eq!(Some(owner), LockOwner::parse("42 2024-01-01T12:00:00Z\n"));
+
+        // Before locking, only the PID was written
+        //
+        assert_eq!(42, LockOwner::parse("42").unwrap().pid);
+        assert_eq!(None, LockOwner::parse(""));
+    }
+
+    #[test]
+    fn test_lock_twice() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("acutectl.pid");
+
+        let lock = EngineLock::acquire(&path).unwrap();
+        assert_eq!(std::process::id(), lock.owner.pid);
+
+        // flock is per open file, a second open in the same process conflicts too
+        //
+        let err = EngineLock::acquire(&path).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::AlreadyRunning(pid, _, _)) if *pid == std::process::id()
+        ));
+
+        drop(lock);
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+        assert!(EngineLock::acquire(&path).is_ok());
+    }
+
+    #[test]
+    fn test_lock_stale() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("acutectl.pid");
+
+        // Left behind by a crashed engine
+        //
+        fs::write(&path, "999999999 2024-01-01T12:00:00Z\n").unwrap();
+
+        let lock = EngineLock::acquire(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(Some(lock.owner.clone()), LockOwner::parse(&content));
+        assert!(!alive(999_999_999));
+    }
+}
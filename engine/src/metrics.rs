@@ -0,0 +1,281 @@
+//! Per-stage pipeline metrics.
+//!
+//! Every task of a job gets a `Metrics` handle when the pipeline is set up.  Records received
+//! are counted by the `RunnableDerive` thread, records emitted by a stage are the ones received
+//! by the next one (or by the job itself for the last one) and consumers are considered to keep
+//! everything they did not drop.  A record is a non-empty line of a chunk, JSON documents sent
+//! as a whole count as one.
+//!
+//! Tasks removing data call `record_drop()` from their `execute()` with the reason, it is
+//! accounted to the stage of the current thread (and ignored outside of a pipeline, e.g. in
+//! tests).  The profile of the last `MAX_PROFILES` jobs is kept in the state file and displayed
+//! with `Engine::show_profile()` (`acutectl jobs profile`).
+//!
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::DateTime;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use fetiche_common::{Listing, OutputFormat};
+
+use crate::{Engine, EngineStatus, State};
+
+/// Number of job profiles kept in the state file
+const MAX_PROFILES: usize = 50;
+
+thread_local! {
+    /// Stage of the task running in this thread
+    static CURRENT: RefCell<Option<Metrics>> = const { RefCell::new(None) };
+}
+
+/// Why records were dropped
+///
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize, strum::Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum DropReason {
+    /// Record could not be parsed or is missing mandatory fields
+    Parse,
+    /// Record removed on purpose (out of range, quality checks)
+    Filter,
+    /// Record already seen or older than what we have for the same track
+    Dedup,
+    /// Free space is critical
+    Space,
+}
+
+/// Counters for one stage of a job
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StageStats {
+    /// Task name
+    pub name: String,
+    /// Records received
+    pub records_in: usize,
+    /// Records emitted (or kept for consumers)
+    pub records_out: usize,
+    /// Records dropped, per reason
+    #[serde(default)]
+    pub dropped: BTreeMap<DropReason, usize>,
+}
+
+impl StageStats {
+    /// All records dropped
+    ///
+    pub fn dropped(&self) -> usize {
+        self.dropped.values().sum()
+    }
+}
+
+/// Shared handle on the counters of one stage
+///
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<StageStats>>);
+
+impl Metrics {
+    /// New stage
+    ///
+    pub fn new(name: &str) -> Self {
+        Metrics(Arc::new(Mutex::new(StageStats {
+            name: name.to_string(),
+            ..StageStats::default()
+        })))
+    }
+
+    /// This thread works for this stage, see `record_drop()`
+    ///
+    pub fn enter(&self) {
+        CURRENT.with(|c| *c.borrow_mut() = Some(self.clone()));
+    }
+
+    /// Stage of the current thread, to be given to helper threads
+    ///
+    pub fn current() -> Option<Metrics> {
+        CURRENT.with(|c| c.borrow().clone())
+    }
+
+    /// Count records received
+    ///
+    pub fn input(&self, data: &str) {
+        self.0.lock().unwrap().records_in += records(data);
+    }
+
+    /// Set records emitted
+    ///
+    pub fn output(&self, n: usize) {
+        self.0.lock().unwrap().records_out = n;
+    }
+
+    /// Count dropped records
+    ///
+    pub fn dropped(&self, reason: DropReason, n: usize) {
+        if n != 0 {
+            *self.0.lock().unwrap().dropped.entry(reason).or_default() += n;
+        }
+    }
+
+    /// Current values
+    ///
+    pub fn get(&self) -> StageStats {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Account for `n` records dropped by the task running in this thread.
+///
+pub fn record_drop(reason: DropReason, n: usize) {
+    if let Some(m) = Metrics::current() {
+        m.dropped(reason, n);
+    }
+}
+
+/// Number of records in a chunk
+///
+pub fn records(data: &str) -> usize {
+    data.lines().filter(|l| !l.trim().is_empty()).count()
+}
+
+/// All stages of one job run
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct JobProfile {
+    /// Job ID
+    pub id: usize,
+    /// Job name
+    pub name: String,
+    /// When it ended
+    pub tm: i64,
+    /// Stages in pipeline order
+    pub stages: Vec<StageStats>,
+}
+
+impl State {
+    /// Keep the profile of a job, forgetting the oldest ones
+    ///
+    pub fn add_profile(&mut self, profile: JobProfile) -> &mut Self {
+        self.profiles.push_back(profile);
+        while self.profiles.len() > MAX_PROFILES {
+            self.profiles.pop_front();
+        }
+        self
+    }
+}
+
+impl Engine {
+    /// Profile of job `id` or the last job run
+    ///
+    pub fn profile(&self, id: Option<usize>) -> Option<JobProfile> {
+        let state = self.state.read().unwrap();
+        match id {
+            Some(id) => state.profiles.iter().find(|p| p.id == id).cloned(),
+            None => state.profiles.back().cloned(),
+        }
+    }
+
+    /// Display the stages of job `id` or of the last job run.
+    ///
+    pub fn show_profile(&self, id: Option<usize>, fmt: OutputFormat) -> Result<String> {
+        let profile = self.profile(id).ok_or(EngineStatus::NoProfile(
+            id.map(|id| id.to_string()).unwrap_or("any".to_string()),
+        ))?;
+        let tm = DateTime::from_timestamp(profile.tm, 0).unwrap_or_default();
+
+        let reasons = [
+            DropReason::Parse,
+            DropReason::Filter,
+            DropReason::Dedup,
+            DropReason::Space,
+        ];
+        let mut list = Listing::new(
+            &format!(
+                "Job #{} ({}) at {}",
+                profile.id,
+                profile.name,
+                tm.to_rfc3339()
+            ),
+            &[
+                ("Stage", "stage"),
+                ("Task", "task"),
+                ("In", "in"),
+                ("Out", "out"),
+                ("Dropped", "dropped"),
+                ("Parse", "parse"),
+                ("Filter", "filter"),
+                ("Dedup", "dedup"),
+                ("Space", "space"),
+            ],
+        );
+        profile.stages.iter().enumerate().for_each(|(i, s)| {
+            let mut row = vec![
+                i.to_string(),
+                s.name.clone(),
+                s.records_in.to_string(),
+                s.records_out.to_string(),
+                s.dropped().to_string(),
+            ];
+            row.extend(
+                reasons
+                    .iter()
+                    .map(|r| s.dropped.get(r).copied().unwrap_or_default().to_string()),
+            );
+            list.push(row);
+        });
+        list.render(fmt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_records() {
+        assert_eq!(0, records(""));
+        assert_eq!(2, records("a\n\nb\n"));
+        assert_eq!(1, records(r#"{"states":[]}"#));
+    }
+
+    #[test]
+    fn test_record_drop_thread() {
+        // Outside of a stage, nothing happens
+        //
+        record_drop(DropReason::Parse, 1);
+
+        let m = Metrics::new("Convert");
+        let stage = m.clone();
+        thread::spawn(move || {
+            stage.enter();
+            stage.input("a\nb\nc\n");
+            record_drop(DropReason::Parse, 2);
+            record_drop(DropReason::Filter, 0);
+        })
+        .join()
+        .unwrap();
+
+        let s = m.get();
+        assert_eq!(3, s.records_in);
+        assert_eq!(2, s.dropped());
+        assert_eq!(1, s.dropped.len());
+    }
+
+    #[test]
+    fn test_add_profile() {
+        let mut s = State::new();
+        (0..MAX_PROFILES + 2).for_each(|id| {
+            s.add_profile(JobProfile {
+                id,
+                ..JobProfile::default()
+            });
+        });
+        assert_eq!(MAX_PROFILES, s.profiles.len());
+        assert_eq!(2, s.profiles[0].id);
+    }
+}
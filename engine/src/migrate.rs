@@ -0,0 +1,158 @@
+//! Upgrade the files used by the engine to their current version in place, see `Migrations`
+//! in `fetiche-common`:
+//!
+//! - `engine.hcl` (`ENGINE_VERSION`)
+//! - `sources.hcl` (`SOURCES_VERSION` in `fetiche-sources`)
+//! - the state file (`STATE_VERSION`)
+//!
+//! When bumping one of these versions, register the corresponding step below.
+//!
+
+use std::path::PathBuf;
+
+use eyre::Result;
+use tracing::{info, trace};
+
+use fetiche_common::{config_dir, Migrations, Syntax};
+use fetiche_sources::Sources;
+
+use crate::{Engine, ENGINE_CONFIG, ENGINE_VERSION, STATE_FILE, STATE_VERSION};
+
+/// Migrations for `engine.hcl`
+///
+pub fn engine_migrations() -> Migrations {
+    Migrations::new(ENGINE_CONFIG, Syntax::Hcl, ENGINE_VERSION)
+}
+
+/// Migrations for the state file
+///
+pub fn state_migrations() -> Migrations {
+    let mut m = Migrations::new(STATE_FILE, Syntax::Json, STATE_VERSION);
+    m.step(1, state_v1);
+    m.step(2, state_v2);
+    m.step(3, state_v3);
+    m.step(4, state_v4);
+    m.step(5, state_v5);
+    m
+}
+
+/// v1 had no `version`, nothing else changed.
+///
+fn state_v1(value: hcl::Value) -> Result<hcl::Value> {
+    Ok(value)
+}
+
+/// v2 had no `stats`.
+///
+fn state_v2(mut value: hcl::Value) -> Result<hcl::Value> {
+    if let Some(obj) = value.as_object_mut() {
+        obj.entry("stats".to_string())
+            .or_insert_with(|| hcl::Value::Object(hcl::Map::new()));
+    }
+    Ok(value)
+}
+
+/// v3 had no `workdirs`.
+///
+fn state_v3(mut value: hcl::Value) -> Result<hcl::Value> {
+    if let Some(obj) = value.as_object_mut() {
+        obj.entry("workdirs".to_string())
+            .or_insert_with(|| hcl::Value::Object(hcl::Map::new()));
+    }
+    Ok(value)
+}
+
+/// v4 had no `jobs` nor `runtimes`.
+///
+fn state_v4(mut value: hcl::Value) -> Result<hcl::Value> {
+    if let Some(obj) = value.as_object_mut() {
+        for key in ["jobs", "runtimes"] {
+            obj.entry(key.to_string())
+                .or_insert_with(|| hcl::Value::Object(hcl::Map::new()));
+        }
+    }
+    Ok(value)
+}
+
+/// v5 had no `profiles`.
+///
+fn state_v5(mut value: hcl::Value) -> Result<hcl::Value> {
+    if let Some(obj) = value.as_object_mut() {
+        obj.entry("profiles".to_string())
+            .or_insert_with(|| hcl::Value::Array(vec![]));
+    }
+    Ok(value)
+}
+
+impl Engine {
+    /// Migrate `engine.hcl`, `sources.hcl` and the state file if needed, before loading the
+    /// engine.  Returns the list of migrated files and their backup.
+    ///
+    #[tracing::instrument]
+    pub fn migrate(state_dir: Option<PathBuf>) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let home = config_dir();
+        let state_dir = state_dir.unwrap_or(home.clone());
+
+        let all = [
+            (home.clone(), engine_migrations()),
+            (home, Sources::migrations()),
+            (state_dir, state_migrations()),
+        ];
+
+        let mut done = vec![];
+        for (dir, m) in all {
+            let fname = dir.join(&m.name);
+            if !fname.exists() {
+                trace!("no {fname:?}");
+                continue;
+            }
+            if let Some(backup) = m.migrate_file(&fname)? {
+                info!("{fname:?} migrated, original in {backup:?}");
+                done.push((fname, backup));
+            }
+        }
+        Ok(done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::State;
+
+    use super::*;
+
+    #[test]
+    fn test_migrate_state_v1() -> Result<()> {
+        let dir = tempdir()?;
+        let fname = dir.path().join(STATE_FILE);
+        fs::write(&fname, r#"{"tm":1700000000,"last":42,"queue":[41,42]}"#)?;
+
+        let backup = state_migrations().migrate_file(&fname)?;
+        assert!(backup.is_some());
+
+        let state = State::from(fname)?;
+        assert_eq!(STATE_VERSION, state.version);
+        assert_eq!(42, state.last);
+        assert_eq!(2, state.queue.len());
+        assert_eq!(0, state.stats.skews);
+        assert!(state.workdirs.is_empty());
+        assert!(state.jobs.is_empty());
+        assert!(state.runtimes.is_empty());
+        assert!(state.profiles.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_engine_current() -> Result<()> {
+        let dir = tempdir()?;
+        let fname = dir.path().join(ENGINE_CONFIG);
+        fs::write(&fname, include_str!("engine.hcl"))?;
+
+        assert_eq!(None, engine_migrations().migrate_file(&fname)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+//! Dry-run plans.
+//!
+//! A `Plan` is what a job would do, resolved without running anything: the site and its
+//! format, how we would authenticate (credentials are hidden and nothing is sent), the URL and
+//! the filter passed to the site, the pipeline stages and where the records end.
+//!
+//! `acutectl fetch`, `stream` and `submit` print it with `--dry-run`, before spending quota on
+//! an expensive request:
+//!
+//! ```text
+//! Plan for fetch_from_site
+//!   source:  opensky (opensky, fetch)
+//!   auth:    Login { username: "foo", password: "HIDDEN" }
+//!   url:     https://opensky-network.org/api/states/own
+//!   filter:  -3600
+//!   stages:  Fetch opensky
+//!            Convert opensky into cat21
+//!            Save into flights.csv (csv)
+//!   outputs: flights.csv
+//! ```
+//!
+//! Job files are planned with `Engine::plan_from()`, the same checks as `create_job_from()` are
+//! done but no job is registered and no directory is created.
+//!
+
+use std::fmt::{Display, Formatter};
+
+use eyre::Result;
+use tracing::trace;
+
+use fetiche_formats::Format;
+use fetiche_sources::{Auth, Filter, Flow, Site};
+
+use crate::{
+    container_from_path, Engine, EngineStatus, JobSpec, Sink, LABEL_WINDOW, LIVE_PATH,
+    QC_MAX_CLIMB, QC_MAX_GAP, TRACK_MAX_GAP, TRACK_MAX_JUMP,
+};
+
+/// What a job would do
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan {
+    /// Job name
+    pub name: String,
+    /// Site, `None` when reading the output of the previous job
+    pub site: Option<String>,
+    /// Format of the site
+    pub format: Format,
+    /// Fetched or streamed
+    pub stream: bool,
+    /// Authentication method, credentials hidden
+    pub auth: String,
+    /// Base URL and route
+    pub url: String,
+    /// Filter sent with the request
+    pub filter: Filter,
+    /// Every task, in pipeline order
+    pub stages: Vec<String>,
+    /// Files, directories, databases and sockets written to
+    pub outputs: Vec<String>,
+}
+
+impl Plan {
+    /// Empty plan for job `name`
+    ///
+    pub fn new(name: &str) -> Self {
+        trace!("New plan {}", name);
+        Plan {
+            name: name.to_string(),
+            ..Plan::default()
+        }
+    }
+
+    /// Resolve the site without authenticating: method, URL and filter.  The `stream` route is
+    /// used for streams, `get` otherwise, falling back on the other one.
+    ///
+    pub fn site(&mut self, site: &Site, stream: bool, filter: Filter) -> &mut Self {
+        let auth = site.auth.clone().unwrap_or_default();
+        self.auth = match auth {
+            Auth::Anon => "none".to_string(),
+            _ if auth.is_placeholder() => format!("{auth} (credentials not set)"),
+            _ => auth.to_string(),
+        };
+
+        let (first, second) = if stream {
+            ("stream", "get")
+        } else {
+            ("get", "stream")
+        };
+        let route = site.route(first).or(site.route(second));
+        self.url = format!(
+            "{}{}",
+            site.base_url,
+            route.map(String::as_str).unwrap_or("")
+        );
+
+        self.site = Some(site.name());
+        self.format = site.format();
+        self.stream = stream;
+        self.filter = filter;
+        self
+    }
+
+    /// Add one stage
+    ///
+    pub fn stage(&mut self, stage: &str) -> &mut Self {
+        self.stages.push(stage.to_string());
+        self
+    }
+
+    /// Add one destination
+    ///
+    pub fn output(&mut self, output: &str) -> &mut Self {
+        self.outputs.push(output.to_string());
+        self
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let list = |v: &[String]| match v.is_empty() {
+            true => "none".to_string(),
+            false => v.join("\n           "),
+        };
+
+        writeln!(f, "Plan for {}", self.name)?;
+        match &self.site {
+            Some(site) => {
+                let how = if self.stream { "stream" } else { "fetch" };
+                writeln!(f, "  source:  {} ({}, {})", site, self.format, how)?;
+                writeln!(f, "  auth:    {}", self.auth)?;
+                let url = if self.url.is_empty() {
+                    "none"
+                } else {
+                    &self.url
+                };
+                writeln!(f, "  url:     {}", url)?;
+                writeln!(f, "  filter:  {}", self.filter)?;
+            }
+            None => writeln!(f, "  source:  output of the previous job")?,
+        }
+        writeln!(f, "  stages:  {}", list(&self.stages))?;
+        writeln!(f, "  outputs: {}", list(&self.outputs))
+    }
+}
+
+impl Engine {
+    /// Plan a job from its specification, see `create_job_from()`.  Nothing is registered and
+    /// the site is not contacted.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn plan_from(&self, name: &str, spec: &JobSpec) -> Result<Plan> {
+        spec.check()
+            .map_err(|e| EngineStatus::BadJobSpec(name.to_string(), e))?;
+
+        let mut plan = Plan::new(name);
+        let mut stream = false;
+        let fmt = if spec.source.is_empty() {
+            plan.stage("Read output of the previous job");
+            Format::None
+        } else {
+            let srcs = self.sources();
+            let flow = Site::load(&spec.source, &srcs)?;
+            stream = matches!(flow, Flow::Streamable(_));
+            let site = srcs.get(&spec.source).ok_or(EngineStatus::BadJobSpec(
+                name.to_string(),
+                format!("unknown site {}", spec.source),
+            ))?;
+            plan.site(site, stream, spec.filter(stream));
+            let task = if stream { "Stream" } else { "Fetch" };
+            plan.stage(&format!("{task} {}", spec.source));
+            flow.format()
+        };
+
+        if let Some(dir) = &spec.raw_copy {
+            plan.stage(&format!("RawCopy into {dir}")).output(dir);
+        }
+
+        let redact = match spec.sink.redact() {
+            Some(policy) => {
+                self.redaction(policy)?;
+                Some(policy)
+            }
+            None => None,
+        };
+        let into = spec.conversion();
+        if into != Format::None || redact.is_some() || spec.provenance {
+            let mut convert = match fmt {
+                Format::None => format!("Convert into {into}"),
+                _ => format!("Convert {fmt} into {into}"),
+            };
+            if let Some(policy) = redact {
+                convert.push_str(&format!(", redact {policy}"));
+            }
+            if spec.provenance {
+                convert.push_str(", provenance");
+            }
+            plan.stage(&convert);
+        }
+
+        if !spec.category.is_empty() {
+            plan.stage(&format!("Select {}", spec.category.join(", ")));
+        }
+
+        if let Some(t) = &spec.tracks {
+            plan.stage(&format!(
+                "Track (max gap {}s, max jump {}m)",
+                t.max_gap.unwrap_or(TRACK_MAX_GAP),
+                t.max_jump.unwrap_or(TRACK_MAX_JUMP)
+            ));
+        }
+
+        if let Some(l) = &spec.label {
+            plan.stage(&format!(
+                "Label from {} (window {}s{})",
+                l.plans,
+                l.window.unwrap_or(LABEL_WINDOW),
+                if l.matched_only { ", matched only" } else { "" }
+            ));
+        }
+
+        if let Some(qc) = &spec.qc {
+            plan.stage(&format!(
+                "Qc (max gap {}s, max climb {}m/s{})",
+                qc.max_gap.unwrap_or(QC_MAX_GAP),
+                qc.max_climb.unwrap_or(QC_MAX_CLIMB),
+                if qc.drop { ", drop" } else { "" }
+            ));
+            if let Some(summary) = &qc.summary {
+                plan.output(summary);
+            }
+        }
+
+        if let (true, Some(live)) = (stream, &self.live) {
+            match live.local_addr() {
+                Some(addr) => plan.stage(&format!("Broadcast to ws://{addr}{LIVE_PATH}")),
+                None => plan.stage("Broadcast to live clients"),
+            };
+        }
+
+        if let Sink::Save {
+            columns: Some(columns),
+            ..
+        } = &spec.sink
+        {
+            plan.stage(&format!("Project columns {columns}"));
+        }
+
+        if let Some(spill) = &spec.spill {
+            let size = spill.size.as_deref().unwrap_or("1G");
+            let dir = spill.path.as_deref().unwrap_or("the job directory");
+            plan.stage(&format!("Spill up to {size} into {dir}"));
+        }
+
+        match &spec.sink {
+            Sink::Save {
+                path, container, ..
+            } => {
+                let container = match container {
+                    Some(c) => c.parse()?,
+                    None => container_from_path(path),
+                };
+                let path = if path == "-" { "stdout" } else { path };
+                plan.stage(&format!("Save into {path} ({container})"))
+                    .output(path);
+            }
+            Sink::Split { path, by, .. } => {
+                plan.stage(&format!("Split by {by} into {path}"))
+                    .output(path);
+            }
+            Sink::Store { path, .. } => {
+                plan.stage(&format!("Store into {path}/<job id>"))
+                    .output(path);
+            }
+            Sink::Postgis { url, table, .. } => {
+                let host = url.rsplit_once('@').map(|(_, h)| h).unwrap_or(url);
+                let table = table.as_deref().unwrap_or("positions");
+                plan.stage(&format!("PostGis into {table}"))
+                    .output(&format!(
+                        "postgres://{}",
+                        host.trim_start_matches("postgres://")
+                    ));
+            }
+        }
+
+        for (stage, tuning) in &spec.tuning {
+            plan.stage(&format!("Tune {stage} ({tuning})"));
+        }
+
+        if let Some(export) = &spec.export {
+            plan.stage(&format!(
+                "Export {} to {}{}",
+                export.artifacts.join(", "),
+                export.to,
+                if export.remove { ", move" } else { "" }
+            ));
+        }
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_display() {
+        let site = Site {
+            name: "opensky".to_string(),
+            format: "opensky".to_string(),
+            base_url: "https://opensky-network.org/api".to_string(),
+            auth: Some(Auth::Login {
+                username: "foo".to_string(),
+                password: "secret".to_string(),
+            }),
+            routes: serde_json::from_str(r#"{"get": "/states/own"}"#).unwrap(),
+            ..Site::default()
+        };
+
+        let mut plan = Plan::new("test");
+        plan.site(&site, false, Filter::since(-3600))
+            .stage("Fetch opensky")
+            .stage("Save into out.csv (csv)")
+            .output("out.csv");
+        assert_eq!("https://opensky-network.org/api/states/own", plan.url);
+
+        let s = plan.to_string();
+        assert!(s.contains("source:  opensky (opensky, fetch)"), "{s}");
+        assert!(s.contains("filter:  -3600"), "{s}");
+        assert!(s.contains("HIDDEN"), "{s}");
+        assert!(!s.contains("secret"), "{s}");
+        assert!(s.contains("Fetch opensky\n           Save into"), "{s}");
+    }
+
+    #[test]
+    fn test_plan_placeholder() {
+        let site = Site {
+            name: "asd".to_string(),
+            format: "asd".to_string(),
+            auth: Auth::placeholder("token"),
+            ..Site::default()
+        };
+
+        let mut plan = Plan::new("test");
+        plan.site(&site, true, Filter::None);
+        assert!(plan.auth.ends_with("(credentials not set)"));
+        assert!(plan.to_string().contains("url:     none"));
+        assert!(plan.to_string().contains("outputs: none"));
+    }
+}
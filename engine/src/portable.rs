@@ -0,0 +1,554 @@
+//! Portable copy of the engine state, to move an engine to another host.
+//!
+//! `Engine::export_state()` writes a single `tar.zst` file with:
+//!
+//! - `manifest.json`, always the first entry: archive version, producer, version of the state
+//!   file and the size and SHA-256 checksum of every other entry,
+//! - `state/`: the state file (statistics, runtimes, profiles...), `history.jsonl` and
+//!   `archive.jsonl` from the state directory,
+//! - `tokens/`: the authentication tokens, encrypted for an [age] `recipient` (the key of the
+//!   new host) with an `.age` suffix; without a recipient they are left out, never written in
+//!   clear outside of the tokens directory,
+//! - `config/`: the `*.hcl` files of the engine home (`engine.hcl`, `sources.hcl`...).
+//!
+//! `Engine::import_state()` checks every checksum before writing anything, then:
+//!
+//! - replaces the state, upgrading it with `state_migrations()` if it comes from an older
+//!   engine.  Jobs queued or running on the old host and their working directories stay there,
+//! - replaces the history and the archive index,
+//! - decrypts the tokens with `identity` into the tokens directory,
+//! - copies the configuration files, except those already there with a different content which
+//!   are written as `<name>.imported` to be merged by hand (paths like `basedir` are likely to
+//!   differ between hosts).
+//!
+//! An engine with jobs in its queue or a history is not overwritten without `force`.
+//!
+//! Job files and their `schedule` blocks are not part of the engine home and must be copied
+//! separately.
+//!
+//! [age]: https://age-encryption.org/
+//!
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{info, trace, warn};
+
+use crate::{
+    state_migrations, version, Engine, EngineStatus, ARCHIVE_FILE, HISTORY_FILE, STATE_FILE,
+    STATE_VERSION,
+};
+
+/// Current version of the archive layout
+pub const STATE_ARCHIVE_VERSION: usize = 1;
+
+/// Name of the manifest inside the archive
+const MANIFEST: &str = "manifest.json";
+
+/// Suffix of encrypted tokens
+const AGE_EXT: &str = "age";
+
+/// zstd compression level
+const LEVEL: i32 = 9;
+
+/// What an entry of the archive is
+///
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum StateKind {
+    /// A file of the state directory
+    State,
+    /// An authentication token
+    Token,
+    /// A configuration file
+    Config,
+}
+
+impl StateKind {
+    /// Directory inside the archive
+    ///
+    fn dir(&self) -> &'static str {
+        match self {
+            StateKind::State => "state",
+            StateKind::Token => "tokens",
+            StateKind::Config => "config",
+        }
+    }
+}
+
+/// One entry of the archive
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StateEntry {
+    /// Path inside the archive
+    pub path: String,
+    /// What it is
+    pub kind: StateKind,
+    /// Size in bytes, as stored
+    pub size: u64,
+    /// SHA-256 in hex, as stored
+    pub sha256: String,
+    /// Encrypted with age
+    pub encrypted: bool,
+}
+
+/// Describe the content of a state archive
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StateManifest {
+    /// Archive layout version
+    pub version: usize,
+    /// Creation time
+    pub created: DateTime<Utc>,
+    /// Engine which wrote it, e.g. `fetiche-engine/0.23.0`
+    pub producer: String,
+    /// Version of the state file
+    pub state_version: usize,
+    /// All the entries but the manifest
+    pub files: Vec<StateEntry>,
+}
+
+impl StateManifest {
+    /// Number of entries of this kind
+    ///
+    pub fn count(&self, kind: StateKind) -> usize {
+        self.files.iter().filter(|f| f.kind == kind).count()
+    }
+}
+
+impl Engine {
+    /// Write the state, history, tokens and configuration into the archive `to`.  Tokens are
+    /// only exported when encrypted for `recipient`.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn export_state(&self, to: &Path, recipient: Option<&str>) -> Result<StateManifest> {
+        trace!("export_state");
+
+        let recipient = recipient
+            .map(|r| {
+                age::x25519::Recipient::from_str(r)
+                    .map_err(|e| EngineStatus::BadArchiveKey(r.to_string(), e.to_string()))
+            })
+            .transpose()?;
+
+        // Make sure what we write is current
+        //
+        self.sync()?;
+
+        let mut all = vec![];
+        for name in [STATE_FILE, HISTORY_FILE, ARCHIVE_FILE] {
+            let fname = self.state_dir.join(name);
+            if fname.exists() {
+                all.push((StateKind::State, name.to_string(), fs::read(fname)?, false));
+            }
+        }
+        let tokens = PathBuf::from(self.tokens.path());
+        match &recipient {
+            Some(recipient) => {
+                for (name, fname) in files_in(&tokens, None)? {
+                    let data = encrypt(&fs::read(fname)?, recipient)?;
+                    all.push((StateKind::Token, format!("{name}.{AGE_EXT}"), data, true));
+                }
+            }
+            None => {
+                if !files_in(&tokens, None)?.is_empty() {
+                    warn!("No recipient, tokens are not exported");
+                }
+            }
+        }
+        for (name, fname) in files_in(&self.home, Some("hcl"))? {
+            all.push((StateKind::Config, name, fs::read(fname)?, false));
+        }
+
+        let manifest = StateManifest {
+            version: STATE_ARCHIVE_VERSION,
+            created: Utc::now(),
+            producer: version(),
+            state_version: STATE_VERSION,
+            files: all
+                .iter()
+                .map(|(kind, name, data, encrypted)| StateEntry {
+                    path: format!("{}/{}", kind.dir(), name),
+                    kind: *kind,
+                    size: data.len() as u64,
+                    sha256: hex::encode(Sha256::digest(data)),
+                    encrypted: *encrypted,
+                })
+                .collect(),
+        };
+
+        let fh = File::create(to)?;
+        let enc = zstd::Encoder::new(fh, LEVEL)?;
+        let mut tar = tar::Builder::new(enc);
+
+        let json = serde_json::to_string_pretty(&manifest)?;
+        append(&mut tar, MANIFEST, json.as_bytes())?;
+        for (entry, (_, _, data, _)) in manifest.files.iter().zip(&all) {
+            info!("Adding {}", entry.path);
+            append(&mut tar, &entry.path, data)?;
+        }
+        tar.into_inner()?.finish()?.flush()?;
+        Ok(manifest)
+    }
+
+    /// Replace the state, history, tokens and configuration with those of the archive `from`,
+    /// encrypted tokens being decrypted with the age key file `identity`.  Returns the manifest
+    /// and every file written.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn import_state(
+        &self,
+        from: &Path,
+        identity: Option<&Path>,
+        force: bool,
+    ) -> Result<(StateManifest, Vec<PathBuf>)> {
+        trace!("import_state");
+
+        let (manifest, data) = read_archive(from)?;
+        if !force && self.has_state()? {
+            return Err(EngineStatus::StateNotEmpty.into());
+        }
+        let ids = match identity {
+            Some(identity) => age::IdentityFile::from_file(identity.to_string_lossy().to_string())?
+                .into_identities()
+                .map_err(|e| EngineStatus::Archive(e.to_string()))?,
+            None if manifest.files.iter().any(|f| f.encrypted) => {
+                return Err(EngineStatus::NoStateIdentity.into())
+            }
+            None => vec![],
+        };
+
+        // Decrypt everything first, a wrong key must not leave half of the state behind
+        //
+        let mut files = vec![];
+        for entry in &manifest.files {
+            let name = file_name(&entry.path)?;
+            let content = &data[&entry.path];
+            let content = match entry.encrypted {
+                true => decrypt(content, &ids)?,
+                false => content.clone(),
+            };
+            let name = match entry.encrypted {
+                true => name
+                    .strip_suffix(&format!(".{AGE_EXT}"))
+                    .unwrap_or(&name)
+                    .to_string(),
+                false => name,
+            };
+            files.push((entry.kind, name, content));
+        }
+
+        let tokens = PathBuf::from(self.tokens.path());
+        let mut written = vec![];
+        for (kind, name, content) in files {
+            let fname = match kind {
+                StateKind::State => self.state_dir.join(&name),
+                StateKind::Token => {
+                    fs::create_dir_all(&tokens)?;
+                    tokens.join(&name)
+                }
+                StateKind::Config => {
+                    let fname = self.home.join(&name);
+                    match fs::read(&fname) {
+                        Ok(old) if old != content => {
+                            warn!("{:?} differs, keeping it", fname);
+                            self.home.join(format!("{name}.imported"))
+                        }
+                        _ => fname,
+                    }
+                }
+            };
+            fs::write(&fname, content)?;
+            info!("Wrote {:?}", fname);
+            written.push(fname);
+        }
+
+        // Bring the state to our version and forget about the jobs of the old host
+        //
+        if manifest.count(StateKind::State) > 0 && self.state_file().exists() {
+            state_migrations().migrate_file(self.state_file())?;
+            self.reload_state()?;
+
+            let mut state = self.state.write().unwrap();
+            state.queue.clear();
+            state.jobs.clear();
+            state.workdirs.clear();
+            self.next.fetch_max(state.last + 1, Ordering::SeqCst);
+            drop(state);
+            self.sync()?;
+        }
+        self.tokens.reload();
+
+        Ok((manifest, written))
+    }
+
+    /// Do we have jobs in the queue or a history?
+    ///
+    fn has_state(&self) -> Result<bool> {
+        if !self.state.read().unwrap().queue.is_empty() {
+            return Ok(true);
+        }
+        match fs::metadata(self.history_file()) {
+            Ok(st) => Ok(st.len() > 0),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Read and check the whole archive: manifest first, then every entry with the right checksum.
+///
+fn read_archive(from: &Path) -> Result<(StateManifest, BTreeMap<String, Vec<u8>>)> {
+    let name = from.to_string_lossy().to_string();
+    let bad = |s: &str| EngineStatus::BadStateArchive(format!("{}: {}", name, s));
+
+    let fh = BufReader::new(File::open(from)?);
+    let mut tar = tar::Archive::new(zstd::Decoder::new(fh)?);
+    let mut entries = tar.entries()?;
+
+    let manifest: StateManifest = match entries.next() {
+        Some(entry) => {
+            let entry = entry?;
+            if entry.path()?.to_string_lossy() != MANIFEST {
+                return Err(bad("manifest is not the first entry").into());
+            }
+            serde_json::from_reader(entry).map_err(|e| bad(&e.to_string()))?
+        }
+        None => return Err(bad("empty").into()),
+    };
+    if manifest.version > STATE_ARCHIVE_VERSION {
+        return Err(
+            EngineStatus::StateArchiveVersion(manifest.version, STATE_ARCHIVE_VERSION).into(),
+        );
+    }
+
+    let mut expected = manifest
+        .files
+        .iter()
+        .map(|f| (f.path.clone(), f))
+        .collect::<BTreeMap<_, _>>();
+    let mut data = BTreeMap::new();
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let Some(file) = expected.remove(&path) else {
+            return Err(bad(&format!("{} is not in the manifest", path)).into());
+        };
+        let mut content = vec![];
+        entry.read_to_end(&mut content)?;
+        if content.len() as u64 != file.size || hex::encode(Sha256::digest(&content)) != file.sha256
+        {
+            return Err(EngineStatus::StateChecksum(path).into());
+        }
+        data.insert(path, content);
+    }
+    if let Some(path) = expected.keys().next() {
+        return Err(bad(&format!("{} is missing", path)).into());
+    }
+    Ok((manifest, data))
+}
+
+/// Name of an entry, which must be directly under its directory
+///
+fn file_name(path: &str) -> Result<String> {
+    match path.split_once('/') {
+        Some((_, name)) if !name.is_empty() && !name.contains(['/', '\\']) && name != ".." => {
+            Ok(name.to_string())
+        }
+        _ => Err(EngineStatus::BadStateArchive(format!("invalid path {}", path)).into()),
+    }
+}
+
+/// Files directly in `dir` with their name, only those with extension `ext` if set
+///
+fn files_in(dir: &Path, ext: Option<&str>) -> Result<Vec<(String, PathBuf)>> {
+    let mut all = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .filter(|p| ext.is_none() || p.extension().and_then(|e| e.to_str()) == ext)
+            .map(|p| (p.file_name().unwrap().to_string_lossy().to_string(), p))
+            .collect::<Vec<_>>(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e.into()),
+    };
+    all.sort();
+    Ok(all)
+}
+
+/// Add an in-memory file.
+///
+fn append<W: Write>(tar: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    tar.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Encrypt `data` for `recipient`
+///
+fn encrypt(data: &[u8], recipient: &age::x25519::Recipient) -> Result<Vec<u8>> {
+    let enc = age::Encryptor::with_recipients(iter::once(recipient as _))
+        .map_err(|e| EngineStatus::Archive(e.to_string()))?;
+    let mut out = vec![];
+    let mut w = enc.wrap_output(&mut out)?;
+    w.write_all(data)?;
+    w.finish()?;
+    Ok(out)
+}
+
+/// Decrypt `data` with one of `ids`
+///
+fn decrypt(data: &[u8], ids: &[Box<dyn age::Identity>]) -> Result<Vec<u8>> {
+    let dec = age::Decryptor::new(data).map_err(|e| EngineStatus::Archive(e.to_string()))?;
+    let mut input = dec
+        .decrypt(ids.iter().map(|id| id.as_ref() as &dyn age::Identity))
+        .map_err(|e| EngineStatus::Archive(e.to_string()))?;
+    let mut out = vec![];
+    input.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+    use tempfile::tempdir;
+
+    use crate::{append_history, HistoryEntry, JobEvent};
+
+    use super::*;
+
+    const TOKEN: &str = r#"{"token":"abcd","gjrt":"","expiredAt":1700000000,"roles":[],"name":"x","supervision":null,"lang":"en","status":"ok","email":"x@example.com","airspaceAdmin":null,"homepage":""}"#;
+
+    /// Old host with one finished job, a token and a configuration file
+    ///
+    fn old_host(dir: &Path) -> Result<Engine> {
+        let engine = Engine::local(Some(dir.to_path_buf()))?;
+        append_history(
+            &engine.history_file(),
+            &HistoryEntry {
+                id: 1,
+                name: "hourly".to_string(),
+                at: Utc::now().timestamp(),
+                event: JobEvent::Finished { seconds: 3 },
+            },
+        )?;
+        fs::create_dir_all(dir.join("tokens"))?;
+        fs::write(dir.join("tokens").join("asd_default_token"), TOKEN)?;
+        fs::write(dir.join("sources.hcl"), "version = 4\n")?;
+        Ok(engine)
+    }
+
+    #[test]
+    fn test_state_roundtrip() -> Result<()> {
+        let old = tempdir()?;
+        let new = tempdir()?;
+        let key = age::x25519::Identity::generate();
+        let identity = new.path().join("host.key");
+        fs::write(&identity, key.to_string().expose_secret())?;
+
+        let engine = old_host(old.path())?;
+        engine.state.write().unwrap().last = 41;
+        engine.state.write().unwrap().queue.push_back(41);
+        let archive = old.path().join("state.tar.zst");
+        let recipient = key.to_public().to_string();
+        let manifest = engine.export_state(&archive, Some(&recipient))?;
+        assert_eq!(2, manifest.count(StateKind::State));
+        assert_eq!(1, manifest.count(StateKind::Token));
+        assert_eq!(1, manifest.count(StateKind::Config));
+
+        let engine = Engine::local(Some(new.path().to_path_buf()))?;
+        let (_, written) = engine.import_state(&archive, Some(&identity), false)?;
+        assert_eq!(4, written.len());
+        assert_eq!(
+            TOKEN,
+            fs::read_to_string(new.path().join("tokens").join("asd_default_token"))?
+        );
+        assert_eq!(1, engine.tokens.len());
+        assert_eq!(1, engine.history("1d", false)?.len());
+        assert!(engine.state.read().unwrap().queue.is_empty());
+        assert!(engine.next.load(Ordering::SeqCst) > 41);
+
+        // Now we have a history
+        //
+        assert!(engine
+            .import_state(&archive, Some(&identity), false)
+            .is_err());
+        assert!(engine.import_state(&archive, Some(&identity), true).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_no_recipient() -> Result<()> {
+        let old = tempdir()?;
+        let new = tempdir()?;
+
+        let engine = old_host(old.path())?;
+        let archive = old.path().join("state.tar.zst");
+        let manifest = engine.export_state(&archive, None)?;
+        assert_eq!(0, manifest.count(StateKind::Token));
+
+        let engine = Engine::local(Some(new.path().to_path_buf()))?;
+        fs::write(new.path().join("sources.hcl"), "version = 3\n")?;
+        let (_, written) = engine.import_state(&archive, None, false)?;
+        assert!(written.contains(&new.path().join("sources.hcl.imported")));
+        assert_eq!(
+            "version = 3\n",
+            fs::read_to_string(new.path().join("sources.hcl"))?
+        );
+        assert!(engine.tokens.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_needs_identity() -> Result<()> {
+        let old = tempdir()?;
+        let new = tempdir()?;
+        let key = age::x25519::Identity::generate();
+
+        let engine = old_host(old.path())?;
+        let archive = old.path().join("state.tar.zst");
+        engine.export_state(&archive, Some(&key.to_public().to_string()))?;
+
+        let engine = Engine::local(Some(new.path().to_path_buf()))?;
+        let err = engine.import_state(&archive, None, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::NoStateIdentity)
+        ));
+        assert!(!new.path().join("history.jsonl").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_bad_recipient() -> Result<()> {
+        let dir = tempdir()?;
+        let engine = Engine::local(Some(dir.path().to_path_buf()))?;
+        assert!(engine
+            .export_state(&dir.path().join("x.tar.zst"), Some("nope"))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!("state", file_name("state/state").unwrap());
+        assert!(file_name("tokens/../x").is_err());
+        assert!(file_name("config/").is_err());
+        assert!(file_name("..").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Machine-readable progress of running jobs.
+//!
+//! When the engine has a `Progress` channel, every job created after that writes JSON lines
+//! into it while it runs: `started` (with the expected runtime), `progress` every second or
+//! so with the records seen by each stage and what has been written so far, then `finished`
+//! or `failed`.  Wrapping GUIs and CI can follow a job this way without parsing the logs.
+//!
+//! The ETA is based on the average runtime of the jobs with the same name (see `queue.rs`), it
+//! is `null` for a job never run before.
+//!
+//! The channel is opened with `Progress::open()`:
+//! - `fd:N` for an already opened file descriptor (Unix only),
+//! - `unix:PATH` for a Unix socket (Unix only),
+//! - `tcp:HOST:PORT` for a TCP socket,
+//! - anything else is a file, appended to.
+//!
+//! Writing progress never fails a job, errors are logged and the event is lost.
+//!
+
+use std::fmt::{Debug, Formatter};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use eyre::Result;
+use serde::Serialize;
+use tracing::{trace, warn};
+
+use crate::EngineStatus;
+
+/// Default interval between `progress` events
+const PROGRESS_EVERY: Duration = Duration::from_secs(1);
+
+/// Counters of one stage in a `progress` event
+///
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StageProgress {
+    /// Task name
+    pub stage: String,
+    /// Records out of this stage so far
+    pub records: usize,
+    /// Records dropped so far
+    pub dropped: usize,
+}
+
+/// One progress event, written as a JSON line
+///
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum ProgressEvent {
+    /// The job starts, `eta` in seconds if known
+    Started {
+        job: usize,
+        name: String,
+        eta: Option<u64>,
+    },
+    /// Where each stage stands, `records` and `bytes` being the output of the job so far
+    Progress {
+        job: usize,
+        elapsed: f64,
+        records: usize,
+        bytes: u64,
+        eta: Option<u64>,
+        stages: Vec<StageProgress>,
+    },
+    /// The job is done
+    Finished {
+        job: usize,
+        elapsed: f64,
+        records: usize,
+        bytes: u64,
+    },
+    /// The job stopped on an error
+    Failed {
+        job: usize,
+        elapsed: f64,
+        error: String,
+    },
+}
+
+/// Where progress events go, shared by all jobs of the engine
+///
+#[derive(Clone)]
+pub struct Progress {
+    /// Side channel
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Interval between `progress` events
+    pub every: Duration,
+}
+
+impl Progress {
+    /// Events written into `out`
+    ///
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Progress {
+            out: Arc::new(Mutex::new(out)),
+            every: PROGRESS_EVERY,
+        }
+    }
+
+    /// Open the channel described by `target` (see above)
+    ///
+    #[tracing::instrument]
+    pub fn open(target: &str) -> Result<Self> {
+        trace!("progress into {}", target);
+
+        let bad = || EngineStatus::BadProgressTarget(target.to_string());
+        let out: Box<dyn Write + Send> = if let Some(fd) = target.strip_prefix("fd:") {
+            let fd = fd.parse::<i32>().map_err(|_| bad())?;
+            from_fd(fd).ok_or_else(bad)?
+        } else if let Some(path) = target.strip_prefix("unix:") {
+            from_socket(path).ok_or_else(bad)??
+        } else if let Some(addr) = target.strip_prefix("tcp:") {
+            Box::new(TcpStream::connect(addr)?)
+        } else {
+            Box::new(OpenOptions::new().create(true).append(true).open(target)?)
+        };
+        Ok(Progress::new(out))
+    }
+
+    /// Set the interval between `progress` events
+    ///
+    pub fn every(&mut self, every: Duration) -> &mut Self {
+        self.every = every;
+        self
+    }
+
+    /// Write one event
+    ///
+    pub fn emit(&self, ev: &ProgressEvent) {
+        let line = match serde_json::to_string(ev) {
+            Ok(line) => line,
+            Err(e) => return warn!("progress: {}", e),
+        };
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+            warn!("progress: {}", e);
+        }
+    }
+}
+
+impl Debug for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress")
+            .field("every", &self.every)
+            .finish()
+    }
+}
+
+#[cfg(unix)]
+fn from_fd(fd: i32) -> Option<Box<dyn Write + Send>> {
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    // We can not check it is open without writing, a closed one gives errors on every event
+    //
+    (fd > 2).then(|| Box::new(unsafe { File::from_raw_fd(fd) }) as Box<dyn Write + Send>)
+}
+
+#[cfg(not(unix))]
+fn from_fd(_fd: i32) -> Option<Box<dyn Write + Send>> {
+    None
+}
+
+#[cfg(unix)]
+fn from_socket(path: &str) -> Option<Result<Box<dyn Write + Send>>> {
+    use std::os::unix::net::UnixStream;
+
+    Some(
+        UnixStream::connect(path)
+            .map(|s| Box::new(s) as Box<dyn Write + Send>)
+            .map_err(Into::into),
+    )
+}
+
+#[cfg(not(unix))]
+fn from_socket(_path: &str) -> Option<Result<Box<dyn Write + Send>>> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::mpsc::Sender;
+    use std::thread;
+
+    use rstest::rstest;
+
+    use fetiche_macros::RunnableDerive;
+
+    use crate::{Copy, Job, Message, Runnable, IO};
+
+    use super::*;
+
+    /// A producer taking its time
+    ///
+    #[derive(Clone, Debug, RunnableDerive)]
+    struct Slow {
+        io: IO,
+    }
+
+    impl Slow {
+        fn execute(&mut self, _data: String, stdout: Sender<String>) -> Result<()> {
+            stdout.send("one\n".to_string())?;
+            thread::sleep(Duration::from_millis(100));
+            stdout.send("two\n".to_string())?;
+            Ok(())
+        }
+    }
+
+    fn events(fname: &std::path::Path) -> Result<Vec<serde_json::Value>> {
+        Ok(fs::read_to_string(fname)?
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?)
+    }
+
+    #[rstest]
+    #[case("fd:foo")]
+    #[case("fd:1")]
+    #[case("unix:/nonexistent/progress.sock")]
+    fn test_progress_open_bad(#[case] target: &str) {
+        assert!(Progress::open(target).is_err());
+    }
+
+    #[test]
+    fn test_progress_job() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("progress.json");
+
+        let mut j = Job::new("progress");
+        j.progress = Some(Progress::open(&fname.to_string_lossy())?);
+        j.add(Box::new(Message::new("hello\nworld\n")));
+        j.add(Box::new(Copy::new()));
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+
+        let events = events(&fname)?;
+        assert_eq!("started", events[0]["event"]);
+        assert_eq!("progress", events[0]["name"]);
+        assert!(events[0]["eta"].is_null());
+
+        let last = events.last().unwrap();
+        assert_eq!("finished", last["event"]);
+        assert_eq!(2, last["records"]);
+        assert_eq!(12, last["bytes"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress_ticks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("progress.json");
+
+        let mut progress = Progress::open(&fname.to_string_lossy())?;
+        progress.every(Duration::from_millis(20));
+
+        let mut j = Job::new("slow");
+        j.progress = Some(progress);
+        j.expected = Some(Duration::from_secs(60));
+        j.add(Box::new(Slow { io: IO::Producer }));
+        j.add(Box::new(Copy::new()));
+
+        let mut data = vec![];
+        j.run(&mut data)?;
+
+        let events = events(&fname)?;
+        assert_eq!(60, events[0]["eta"]);
+        let ticks = events
+            .iter()
+            .filter(|ev| ev["event"] == "progress")
+            .collect::<Vec<_>>();
+        assert!(!ticks.is_empty());
+        assert_eq!(1, ticks[0]["records"]);
+        assert_eq!("Slow", ticks[0]["stages"][0]["stage"]);
+        assert_eq!(1, ticks[0]["stages"][1]["records"]);
+        assert_eq!(2, events.last().unwrap()["records"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+//! Conversions between the engine types and the `fetiche-proto` messages.
+//!
+//! Jobs go both ways (a job sent to the daemon is checked like one read from a job file),
+//! statistics and queued jobs are only sent.
+//!
+
+use std::str::FromStr;
+
+use chrono::DateTime;
+use eyre::Result;
+
+use fetiche_proto as proto;
+
+use crate::{
+    EngineStatus, ExportSpec, FilterSpec, JobSpec, JobStatus, LabelSpec, Layout, Limits, QcSpec,
+    QueuedJob, Schedule, Sink, SpillSpec, Stats, TrackSpec, Tuning,
+};
+
+impl JobSpec {
+    /// Message for the job `name`
+    ///
+    pub fn to_proto(&self, name: &str) -> proto::JobSpec {
+        proto::JobSpec {
+            name: name.to_string(),
+            source: self.source.clone(),
+            filter: self.filter.as_ref().map(|f| proto::Filter {
+                since: f.since,
+                begin: f.begin.map(|t| t.timestamp()),
+                end: f.end.map(|t| t.timestamp()),
+                keyword: f.keyword.clone(),
+                start: f.start,
+                window: f.window.clone(),
+                timezone: f.timezone.clone(),
+            }),
+            into: self.into.clone(),
+            raw_copy: self.raw_copy.clone(),
+            sink: Some(proto::Sink::from(&self.sink)),
+            schedule: self.schedule.as_ref().map(|s| proto::Schedule {
+                every: s.every,
+                count: s.count as u64,
+                probe: s.probe,
+                retry: s.retry,
+            }),
+            tracks: self.tracks.as_ref().map(|t| proto::Tracks {
+                max_gap: t.max_gap,
+                max_jump: t.max_jump,
+            }),
+            label: self.label.as_ref().map(|l| proto::Label {
+                plans: l.plans.clone(),
+                window: l.window,
+                matched_only: l.matched_only,
+            }),
+            qc: self.qc.as_ref().map(|q| proto::Qc {
+                summary: q.summary.clone(),
+                max_gap: q.max_gap,
+                max_climb: q.max_climb,
+                drop: q.drop,
+            }),
+            limits: self.limits.as_ref().map(|l| proto::Limits {
+                duration: l.duration,
+                delay: l.delay,
+            }),
+            timeout: self.timeout,
+            provenance: self.provenance,
+            category: self.category.clone(),
+            spill: self.spill.as_ref().map(|s| proto::Spill {
+                size: s.size.clone(),
+                path: s.path.clone(),
+            }),
+            export: self.export.as_ref().map(|e| proto::Export {
+                to: e.to.clone(),
+                artifacts: e.artifacts.clone(),
+                r#move: e.remove,
+                retries: e.retries.map(|n| n as u64),
+                region: e.region.clone(),
+                endpoint: e.endpoint.clone(),
+            }),
+            tuning: self
+                .tuning
+                .iter()
+                .map(|(name, t)| {
+                    let t = proto::Tuning {
+                        capacity: t.capacity.map(|n| n as u64),
+                        workers: t.workers.map(|n| n as u64),
+                        batch: t.batch.map(|n| n as u64),
+                    };
+                    (name.clone(), t)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Sink> for proto::Sink {
+    fn from(sink: &Sink) -> Self {
+        let kind = |k: proto::SinkKind| k as i32;
+        match sink {
+            Sink::Save {
+                path,
+                container,
+                trajectories,
+                layout,
+                redact,
+                columns,
+            } => proto::Sink {
+                kind: kind(proto::SinkKind::Save),
+                path: path.clone(),
+                container: container.clone(),
+                trajectories: *trajectories,
+                layout: Some(layout.to_string()),
+                redact: redact.clone(),
+                columns: columns.clone(),
+                ..Default::default()
+            },
+            Sink::Split { path, by, redact } => proto::Sink {
+                kind: kind(proto::SinkKind::Split),
+                path: path.clone(),
+                by: Some(by.clone()),
+                redact: redact.clone(),
+                ..Default::default()
+            },
+            Sink::Store { path, redact } => proto::Sink {
+                kind: kind(proto::SinkKind::Store),
+                path: path.clone(),
+                redact: redact.clone(),
+                ..Default::default()
+            },
+            Sink::Postgis {
+                url,
+                table,
+                trajectories,
+                redact,
+            } => proto::Sink {
+                kind: kind(proto::SinkKind::Postgis),
+                path: url.clone(),
+                table: table.clone(),
+                trajectories: *trajectories,
+                redact: redact.clone(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// A job received as a message, checked like one from a job file.
+///
+impl TryFrom<&proto::JobSpec> for JobSpec {
+    type Error = eyre::Report;
+
+    fn try_from(msg: &proto::JobSpec) -> Result<Self> {
+        let bad = |e: &str| EngineStatus::BadJobSpec(msg.name.clone(), e.to_string());
+
+        let Some(sink) = &msg.sink else {
+            return Err(bad("no sink").into());
+        };
+        let sink = match sink.kind() {
+            proto::SinkKind::Save => Sink::Save {
+                path: sink.path.clone(),
+                container: sink.container.clone(),
+                trajectories: sink.trajectories,
+                layout: match &sink.layout {
+                    Some(l) => Layout::from_str(l).map_err(|_| bad("unknown layout"))?,
+                    None => Layout::default(),
+                },
+                redact: sink.redact.clone(),
+                columns: sink.columns.clone(),
+            },
+            proto::SinkKind::Split => Sink::Split {
+                path: sink.path.clone(),
+                by: sink.by.clone().ok_or(bad("split needs by"))?,
+                redact: sink.redact.clone(),
+            },
+            proto::SinkKind::Store => Sink::Store {
+                path: sink.path.clone(),
+                redact: sink.redact.clone(),
+            },
+            proto::SinkKind::Postgis => Sink::Postgis {
+                url: sink.path.clone(),
+                table: sink.table.clone(),
+                trajectories: sink.trajectories,
+                redact: sink.redact.clone(),
+            },
+        };
+        let time = |t: Option<i64>| match t {
+            Some(t) => DateTime::from_timestamp(t, 0)
+                .map(Some)
+                .ok_or(bad("bad timestamp")),
+            None => Ok(None),
+        };
+        let filter = match &msg.filter {
+            Some(f) => Some(FilterSpec {
+                since: f.since,
+                begin: time(f.begin)?,
+                end: time(f.end)?,
+                keyword: f.keyword.clone(),
+                start: f.start,
+                window: f.window.clone(),
+                timezone: f.timezone.clone(),
+            }),
+            None => None,
+        };
+
+        let spec = JobSpec {
+            source: msg.source.clone(),
+            filter,
+            into: msg.into.clone(),
+            raw_copy: msg.raw_copy.clone(),
+            tracks: msg.tracks.as_ref().map(|t| TrackSpec {
+                max_gap: t.max_gap,
+                max_jump: t.max_jump,
+            }),
+            label: msg.label.as_ref().map(|l| LabelSpec {
+                plans: l.plans.clone(),
+                window: l.window,
+                matched_only: l.matched_only,
+            }),
+            qc: msg.qc.as_ref().map(|q| QcSpec {
+                summary: q.summary.clone(),
+                max_gap: q.max_gap,
+                max_climb: q.max_climb,
+                drop: q.drop,
+            }),
+            sink,
+            spill: msg.spill.as_ref().map(|s| SpillSpec {
+                size: s.size.clone(),
+                path: s.path.clone(),
+            }),
+            schedule: msg.schedule.as_ref().map(|s| Schedule {
+                every: s.every,
+                count: s.count as usize,
+                probe: s.probe,
+                retry: s.retry,
+            }),
+            limits: msg.limits.as_ref().map(|l| Limits {
+                duration: l.duration,
+                delay: l.delay,
+            }),
+            timeout: msg.timeout,
+            provenance: msg.provenance,
+            category: msg.category.clone(),
+            export: msg.export.as_ref().map(|e| ExportSpec {
+                to: e.to.clone(),
+                artifacts: e.artifacts.clone(),
+                remove: e.r#move,
+                retries: e.retries.map(|n| n as usize),
+                region: e.region.clone(),
+                endpoint: e.endpoint.clone(),
+            }),
+            tuning: msg
+                .tuning
+                .iter()
+                .map(|(name, t)| {
+                    let t = Tuning {
+                        capacity: t.capacity.map(|n| n as usize),
+                        workers: t.workers.map(|n| n as usize),
+                        batch: t.batch.map(|n| n as usize),
+                    };
+                    (name.clone(), t)
+                })
+                .collect(),
+            on_success: vec![],
+            on_failure: vec![],
+        };
+        spec.check().map_err(|e| bad(&e))?;
+        Ok(spec)
+    }
+}
+
+impl From<&Stats> for proto::Stats {
+    fn from(stats: &Stats) -> Self {
+        proto::Stats {
+            skews: stats.skews as u64,
+            max_skew: stats.max_skew,
+            groups: stats
+                .groups
+                .iter()
+                .map(|(name, g)| {
+                    let g = proto::GroupStats {
+                        endpoint: g.endpoint.clone(),
+                        served: g.served as u64,
+                        failovers: g.failovers as u64,
+                    };
+                    (name.clone(), g)
+                })
+                .collect(),
+            space_alerts: stats.space_alerts as u64,
+            timeouts: stats.timeouts as u64,
+        }
+    }
+}
+
+impl From<&QueuedJob> for proto::JobResult {
+    fn from(job: &QueuedJob) -> Self {
+        let status = match job.status {
+            JobStatus::Queued => proto::ResultStatus::Queued,
+            JobStatus::Running => proto::ResultStatus::Running,
+        };
+        proto::JobResult {
+            id: job.id as u64,
+            name: job.name.clone(),
+            status: status as i32,
+            queued: job.queued,
+            started: job.started,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use fetiche_proto::prost::Message;
+
+    use super::*;
+    use crate::JobFile;
+
+    const JOB: &str = r#"
+version = 1
+job "cdg" {
+  source = "asd"
+  filter {
+    begin = "2024-01-01T00:00:00Z"
+    end   = "2024-01-02T00:00:00Z"
+  }
+  into = "cat21"
+  tracks {
+    max_gap = 60
+  }
+  label {
+    plans        = "plans.csv"
+    matched_only = true
+  }
+  sink "split" {
+    path = "out"
+    by   = "journey"
+  }
+  spill {
+    size = "500M"
+  }
+  tuning "convert" {
+    workers = 4
+    batch   = 1000
+  }
+  tuning "save" {
+    capacity = 8
+  }
+  export {
+    to      = "s3://acute/{source}/{date}/{job_id}/{file}"
+    retries = 5
+  }
+  schedule {
+    every = 3600
+    count = 2
+    probe = true
+    retry = 30
+  }
+}
+"#;
+
+    #[test]
+    fn test_jobspec_roundtrip() -> Result<()> {
+        let file = JobFile::from_str(JOB)?;
+        let spec = &file.job["cdg"];
+
+        let msg = spec.to_proto("cdg");
+        let msg = proto::JobSpec::decode(msg.encode_to_vec().as_slice())?;
+        assert_eq!("cdg", msg.name);
+        assert_eq!(Some(1_704_067_200), msg.filter.as_ref().unwrap().begin);
+
+        let back = JobSpec::try_from(&msg)?;
+        assert_eq!(spec.filter.as_ref().unwrap().end, back.filter.unwrap().end);
+        assert!(matches!(back.sink, Sink::Split { by, .. } if by == "journey"));
+        let schedule = back.schedule.unwrap();
+        assert_eq!(2, schedule.count);
+        assert!(schedule.probe);
+        assert_eq!(Some(30), schedule.retry);
+        assert_eq!(Some(60), back.tracks.unwrap().max_gap);
+        assert!(back.label.unwrap().matched_only);
+        assert_eq!(Some("500M"), back.spill.unwrap().size.as_deref());
+        assert_eq!(Some(4), back.tuning["convert"].workers);
+        assert_eq!(Some(8), back.tuning["save"].capacity);
+        let export = back.export.unwrap();
+        assert_eq!(vec!["output"], export.artifacts);
+        assert_eq!(Some(5), export.retries);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jobspec_checked() {
+        let mut msg = JobFile::from_str(JOB).unwrap().job["cdg"].to_proto("cdg");
+        msg.source = "".to_string();
+        assert!(JobSpec::try_from(&msg).is_err());
+
+        msg.sink = None;
+        assert!(JobSpec::try_from(&msg).is_err());
+    }
+
+    #[test]
+    fn test_stats_to_proto() {
+        let stats = Stats {
+            skews: 2,
+            max_skew: -1500,
+            ..Default::default()
+        };
+        let msg = proto::Stats::from(&stats);
+        assert_eq!(2, msg.skews);
+        assert_eq!(-1500, msg.max_skew);
+    }
+}
@@ -0,0 +1,275 @@
+//! Scheduler queue as seen by clients.
+//!
+//! Every job created by the engine is recorded in the state file with its name, when it was
+//! queued and when it started, so `Engine::queue()` can tell where each pending or running job
+//! stands, including those of other processes sharing the same state directory.
+//!
+//! The runtime of successful jobs is kept per job name, the average is used to estimate when
+//! each job in the queue should be done.  Jobs are assumed to run one after the other, the
+//! estimate is `None` for a job whose name has never completed and for all the jobs after it.
+//!
+//! `Engine::subscribe()` returns a channel receiving a `QueueEvent` every time the queue changes
+//! or when free space goes below or back above the thresholds (see `SpaceMonitor`).
+//!
+
+use std::sync::mpsc::{channel, Receiver};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use crate::{Engine, SpaceLevel, State};
+
+/// Status of a job in the queue
+///
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting to be run
+    #[default]
+    Queued,
+    /// Being run
+    Running,
+}
+
+/// What we know about a job in the queue, kept in the state file
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct JobInfo {
+    /// Name of the job
+    pub name: String,
+    /// Current status
+    pub status: JobStatus,
+    /// When it was queued
+    pub queued: i64,
+    /// When it started
+    pub started: Option<i64>,
+}
+
+/// Past runtimes of all jobs with the same name
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Runtime {
+    /// Number of successful runs
+    pub runs: usize,
+    /// Cumulated runtime in seconds
+    pub total: i64,
+    /// Last runtime in seconds
+    pub last: i64,
+}
+
+impl Runtime {
+    /// Average runtime in seconds, if any
+    ///
+    pub fn average(&self) -> Option<i64> {
+        match self.runs {
+            0 => None,
+            n => Some(self.total / n as i64),
+        }
+    }
+}
+
+/// One entry of `Engine::queue()`
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueuedJob {
+    /// Job ID
+    pub id: usize,
+    /// Name of the job
+    pub name: String,
+    /// Current status
+    pub status: JobStatus,
+    /// Position in the queue, starting at 1
+    pub position: usize,
+    /// When it was queued
+    pub queued: i64,
+    /// When it started
+    pub started: Option<i64>,
+    /// Estimated end, if we know enough
+    pub eta: Option<i64>,
+}
+
+/// Changes in the queue
+///
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum QueueEvent {
+    /// Job created
+    Queued(usize),
+    /// Job started
+    Started(usize),
+    /// Job finished successfully
+    Finished(usize),
+    /// Job failed
+    Failed(usize),
+    /// Job cancelled after its wall-clock limit, sent after `Failed`
+    TimedOut(usize),
+    /// Job stopped by `Engine::cancel_job()`, sent after `Failed`
+    Cancelled(usize),
+    /// Free space changed level, new jobs may be refused
+    Space(SpaceLevel),
+}
+
+impl State {
+    /// Record a new job
+    ///
+    pub fn queue_job(&mut self, id: usize, name: &str) -> &mut Self {
+        let info = JobInfo {
+            name: name.to_string(),
+            queued: Utc::now().timestamp(),
+            ..Default::default()
+        };
+        self.jobs.insert(id, info);
+        self
+    }
+
+    /// Mark job `id` as running
+    ///
+    pub fn start_job(&mut self, id: usize) -> &mut Self {
+        if let Some(info) = self.jobs.get_mut(&id) {
+            info.status = JobStatus::Running;
+            info.started = Some(Utc::now().timestamp());
+        }
+        self
+    }
+
+    /// Forget job `id`, accounting for its runtime if it was successful
+    ///
+    pub fn end_job(&mut self, id: usize, ok: bool) -> &mut Self {
+        if let Some(info) = self.jobs.remove(&id) {
+            if let (true, Some(started)) = (ok, info.started) {
+                let rt = self.runtimes.entry(info.name).or_default();
+                rt.last = Utc::now().timestamp() - started;
+                rt.total += rt.last;
+                rt.runs += 1;
+            }
+        }
+        self
+    }
+
+    /// Jobs in the queue in order, with an estimated end as of `now`
+    ///
+    pub fn queue_at(&self, now: i64) -> Vec<QueuedJob> {
+        let mut cursor = Some(now);
+
+        self.queue
+            .iter()
+            .filter_map(|id| self.jobs.get(id).map(|info| (*id, info)))
+            .enumerate()
+            .map(|(i, (id, info))| {
+                let average = self.runtimes.get(&info.name).and_then(|r| r.average());
+                let eta = match (info.started, average, cursor) {
+                    (Some(started), Some(avg), Some(c)) => Some((started + avg).max(now).max(c)),
+                    (None, Some(avg), Some(c)) => Some(c + avg),
+                    _ => None,
+                };
+                cursor = eta;
+                QueuedJob {
+                    id,
+                    name: info.name.clone(),
+                    status: info.status,
+                    position: i + 1,
+                    queued: info.queued,
+                    started: info.started,
+                    eta,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Engine {
+    /// Pending and running jobs, in order, with their estimated end
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn queue(&self) -> Vec<QueuedJob> {
+        let state = self.state.read().unwrap();
+        state.queue_at(Utc::now().timestamp())
+    }
+
+    /// Receive a `QueueEvent` every time the queue changes, until the receiver is dropped
+    ///
+    pub fn subscribe(&self) -> Receiver<QueueEvent> {
+        let (tx, rx) = channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Tell all subscribers, forgetting those who are gone
+    ///
+    pub(crate) fn notify(&self, ev: QueueEvent) {
+        trace!("queue: {:?}", ev);
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(ev).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let mut s = State::new();
+        s.runtimes.insert(
+            "fetch".to_string(),
+            Runtime {
+                runs: 2,
+                total: 120,
+                last: 60,
+            },
+        );
+        [(1, "fetch"), (2, "fetch"), (3, "stream"), (4, "fetch")]
+            .iter()
+            .for_each(|(id, name)| {
+                s.queue.push_back(*id);
+                s.queue_job(*id, name);
+            });
+        s
+    }
+
+    #[test]
+    fn test_queue_at() {
+        let mut s = state();
+        let now = Utc::now().timestamp();
+        s.start_job(1);
+        s.jobs.get_mut(&1).unwrap().started = Some(now - 20);
+
+        let q = s.queue_at(now);
+        assert_eq!(4, q.len());
+        assert_eq!(JobStatus::Running, q[0].status);
+        assert_eq!(Some(now + 40), q[0].eta);
+        assert_eq!(2, q[1].position);
+        assert_eq!(Some(now + 100), q[1].eta);
+
+        // Never seen before, nothing can be said after
+        //
+        assert_eq!(None, q[2].eta);
+        assert_eq!(None, q[3].eta);
+    }
+
+    #[test]
+    fn test_queue_overrun() {
+        let mut s = state();
+        let now = Utc::now().timestamp();
+        s.start_job(1);
+        s.jobs.get_mut(&1).unwrap().started = Some(now - 600);
+
+        assert_eq!(Some(now), s.queue_at(now)[0].eta);
+    }
+
+    #[test]
+    fn test_queue_end_job() {
+        let mut s = state();
+
+        s.start_job(3).end_job(3, true);
+        assert_eq!(1, s.runtimes["stream"].runs);
+
+        // Failed or never started, no runtime
+        //
+        s.start_job(1).end_job(1, false).end_job(2, true);
+        assert_eq!(2, s.runtimes["fetch"].runs);
+        assert_eq!(1, s.jobs.len());
+    }
+}
@@ -0,0 +1,322 @@
+//! Background refresh of authentication tokens
+//!
+//! Tokens have a limited lifetime and a stream running for hours would die at the expiry
+//! boundary.  `TokenRefresher` is an actor running in its own thread, started the first time a
+//! `Fetch` or `Stream` task gets it through `Engine::refresher()`:
+//!
+//! - every `interval` seconds, it scans `TokenStorage` and gets a new token for the sites using
+//!   the ones expiring in less than `before` seconds,
+//! - when a site rejects a token (401, `AuthError::Rejected`), the task asks the actor for a new
+//!   one and runs again with it.  Several tasks hitting the same site get the same new token.
+//!
+//! Only sites authenticating with a token (`Auth::Token`, `Auth::Oauth2`) can be refreshed, the
+//! stored token is dropped (`invalidate()`) and `authenticate()` is called again.
+//!
+//! ```hcl
+//! tokens {
+//!   interval = 60
+//!   before   = 300
+//! }
+//! ```
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use eyre::{eyre, Report, Result};
+use serde::Deserialize;
+use tracing::{info, trace, warn};
+
+use fetiche_sources::{Auth, AuthError, Expirable, Flow, Site, Sources};
+
+use crate::{Ticker, TokenStorage};
+
+/// Defaults
+const DEF_INTERVAL: u64 = 60;
+const DEF_BEFORE: u64 = 300;
+
+/// A token refreshed less than this ago is given as-is to other rejected tasks
+const RECENT: Duration = Duration::from_secs(10);
+
+/// Rejected again in less than this after getting a new token, we give up
+const MIN_RUN: Duration = Duration::from_secs(60);
+
+/// `tokens` block in `engine.hcl`
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RefreshConfig {
+    /// Seconds between two scans
+    pub interval: u64,
+    /// Refresh tokens expiring in less than this many seconds
+    pub before: u64,
+}
+
+impl Default for RefreshConfig {
+    fn default() -> Self {
+        RefreshConfig {
+            interval: DEF_INTERVAL,
+            before: DEF_BEFORE,
+        }
+    }
+}
+
+/// Messages to the actor
+///
+enum Request {
+    /// The site rejected our token, a new one is sent back
+    Rejected {
+        site: String,
+        reply: Sender<Result<String>>,
+    },
+}
+
+/// Handle on the refresh actor
+///
+pub struct TokenRefresher {
+    /// Scan interval and margin
+    cfg: RefreshConfig,
+    /// Tokens we watch
+    storage: Arc<TokenStorage>,
+    /// Sites using them
+    srcs: Arc<Sources>,
+    /// Mailbox of the actor, once started
+    tx: OnceLock<Mutex<Sender<Request>>>,
+}
+
+impl Debug for TokenRefresher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenRefresher")
+            .field("cfg", &self.cfg)
+            .field("started", &self.tx.get().is_some())
+            .finish()
+    }
+}
+
+impl TokenRefresher {
+    pub fn new(cfg: &RefreshConfig, storage: Arc<TokenStorage>, srcs: Arc<Sources>) -> Self {
+        TokenRefresher {
+            cfg: cfg.clone(),
+            storage,
+            srcs,
+            tx: OnceLock::new(),
+        }
+    }
+
+    /// Start the actor if it is not running yet
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn start(&self) {
+        self.tx.get_or_init(|| {
+            trace!("start token refresher");
+
+            let (tx, rx) = channel();
+            let actor = TokenRefresher::new(&self.cfg, self.storage.clone(), self.srcs.clone());
+            thread::spawn(move || actor.run(rx));
+            Mutex::new(tx)
+        });
+    }
+
+    /// Actor loop: scan on every tick, answer rejections in between
+    ///
+    fn run(&self, rx: Receiver<Request>) {
+        let mut ticker = Ticker::new(Duration::from_secs(self.cfg.interval.max(1)));
+        let mut recent: BTreeMap<String, (Instant, String)> = BTreeMap::new();
+        loop {
+            let now = Instant::now();
+            let tick = ticker.next_tick(now);
+            match rx.recv_timeout(tick.saturating_duration_since(now)) {
+                Ok(Request::Rejected { site, reply }) => {
+                    let res = match recent.get(&site) {
+                        Some((at, token)) if at.elapsed() < RECENT => Ok(token.clone()),
+                        _ => self.refresh(&site).inspect(|token| {
+                            recent.insert(site.clone(), (Instant::now(), token.clone()));
+                        }),
+                    };
+                    let _ = reply.send(res);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.scan();
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    /// Get a new token for all sites whose token expires soon, returns their names.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn scan(&self) -> Vec<String> {
+        let limit = Utc::now().timestamp() + self.cfg.before as i64;
+        let expiring = self.storage.expiring(limit);
+        trace!("{} tokens expiring", expiring.len());
+
+        let mut done = vec![];
+        for token in expiring {
+            let key = token.key();
+            let sites = self
+                .srcs
+                .iter()
+                .filter(|(_, site)| logs_in_as(site, &key))
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>();
+            for name in sites {
+                match self.refresh(&name) {
+                    Ok(_) => done.push(name),
+                    Err(e) => warn!("Can not refresh token for {}: {}", name, e),
+                }
+            }
+        }
+        done
+    }
+
+    /// Drop the stored token of `name` and get a new one.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn refresh(&self, name: &str) -> Result<String> {
+        if !self.srcs.get(name).is_some_and(refreshable) {
+            return Err(eyre!("{}: tokens can not be refreshed", name));
+        }
+        let token = match Site::load(name, &self.srcs)? {
+            Flow::Fetchable(site) => {
+                site.invalidate()?;
+                site.authenticate()?
+            }
+            Flow::Streamable(site) => {
+                site.invalidate()?;
+                site.authenticate()?
+            }
+        };
+        self.storage.reload();
+        info!("New token for {}", name);
+        Ok(token)
+    }
+
+    /// `site` rejected our token, ask the actor for a new one.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn rejected(&self, site: &str) -> Result<String> {
+        self.start();
+
+        let (reply, rx) = channel();
+        let tx = self.tx.get().unwrap().lock().unwrap();
+        tx.send(Request::Rejected {
+            site: site.to_string(),
+            reply,
+        })
+        .map_err(|_| eyre!("token refresher is gone"))?;
+        drop(tx);
+        rx.recv()?
+    }
+}
+
+/// Only token-based authentication can be refreshed
+///
+fn refreshable(site: &Site) -> bool {
+    matches!(
+        site.auth,
+        Some(Auth::Token { .. }) | Some(Auth::Oauth2 { .. })
+    )
+}
+
+/// Does `site` get its tokens as `login`?
+///
+fn logs_in_as(site: &Site, key: &str) -> bool {
+    matches!(&site.auth, Some(Auth::Token { login, .. }) if login == key)
+}
+
+/// Is it the site rejecting our token?
+///
+pub fn is_rejected(e: &Report) -> bool {
+    matches!(e.downcast_ref::<AuthError>(), Some(AuthError::Rejected(_)))
+}
+
+/// Run `f` with `token`, again with a new one every time `site` rejects it.  We give up when
+/// the new token is rejected right away or when there is no refresher.
+///
+pub fn with_token<F>(
+    tokens: Option<&TokenRefresher>,
+    site: &str,
+    mut token: String,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<()>,
+{
+    let mut renewed = false;
+    loop {
+        let start = Instant::now();
+        match f(&token) {
+            Err(e) if is_rejected(&e) => {
+                let Some(tokens) = tokens else {
+                    return Err(e);
+                };
+                if renewed && start.elapsed() < MIN_RUN {
+                    return Err(e);
+                }
+                warn!("{}: token rejected, getting a new one", site);
+                token = tokens.rejected(site)?;
+                renewed = true;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn refresher() -> TokenRefresher {
+        TokenRefresher::new(
+            &RefreshConfig::default(),
+            Arc::new(TokenStorage::register("/nonexistent")),
+            Arc::new(Sources::default()),
+        )
+    }
+
+    #[test]
+    fn test_with_token_no_refresher() {
+        let calls = Cell::new(0);
+        let res = with_token(None, "asd", "t".to_string(), |_| {
+            calls.set(calls.get() + 1);
+            Err(AuthError::Rejected("asd".to_string()).into())
+        });
+        assert!(res.is_err());
+        assert_eq!(1, calls.get());
+
+        let res = with_token(None, "asd", "t".to_string(), |t| {
+            assert_eq!("t", t);
+            Ok(())
+        });
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_with_token_not_refreshable() {
+        let r = refresher();
+        let res = with_token(Some(&r), "nope", "t".to_string(), |_| {
+            Err(AuthError::Rejected("nope".to_string()).into())
+        });
+        assert!(res.unwrap_err().to_string().contains("nope"));
+
+        // Other errors are passed along
+        //
+        let res = with_token(Some(&r), "nope", "t".to_string(), |_| Err(eyre!("boom")));
+        assert_eq!("boom", res.unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_scan_nothing() {
+        assert!(refresher().scan().is_empty());
+        assert!(!is_rejected(&eyre!("401")));
+        assert!(is_rejected(&AuthError::Rejected("x".to_string()).into()));
+    }
+}
@@ -0,0 +1,296 @@
+//! Autoscaling of the workers running jobs
+//!
+//! Bulk backfills queue many jobs at once while quiet nights leave most workers idle, so the
+//! number of workers need not be fixed: `Engine::autoscale()` looks at the queue in the state
+//! file and asks the runner (through `WorkerFactory`) to add or remove workers.
+//!
+//! Jobs are still run by the process submitting them, there is no runner pool implementing
+//! `WorkerFactory` and nothing calls `autoscale()` yet.  This is the policy such a pool would
+//! call regularly.
+//!
+//! - when there are more than `up_queue` queued jobs per worker, we go directly to enough
+//!   workers for the queue, up to `max`,
+//! - when nothing is queued and less than `down_util` of the workers are busy, one idle worker
+//!   is removed, down to `min`.
+//!
+//! Nothing changes for `cooldown` seconds after a decision to avoid flapping.  Every decision is
+//! counted in the engine statistics, the last one being displayed with them.
+//!
+//! ```hcl
+//! workers {
+//!   min       = 1
+//!   max       = 8
+//!   up_queue  = 2
+//!   down_util = 0.25
+//!   cooldown  = 60
+//! }
+//! ```
+//!
+//! The default is a fixed single worker.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace};
+
+use crate::{Engine, EngineStatus, JobStatus};
+
+/// Defaults
+const DEF_WORKERS: usize = 1;
+const DEF_UP_QUEUE: usize = 2;
+const DEF_DOWN_UTIL: f64 = 0.25;
+const DEF_COOLDOWN: u64 = 60;
+
+/// What runs the jobs, told how many workers it should have
+///
+pub trait WorkerFactory {
+    /// Current number of workers
+    fn workers(&self) -> usize;
+    /// Start `n` more workers
+    fn add(&mut self, n: usize) -> Result<()>;
+    /// Stop `n` idle workers
+    fn remove(&mut self, n: usize) -> Result<()>;
+}
+
+/// `workers` block in `engine.hcl`
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ScalingConfig {
+    /// Never less workers than this
+    pub min: usize,
+    /// Never more workers than this
+    pub max: usize,
+    /// Queued jobs per worker above which we add workers
+    pub up_queue: usize,
+    /// Busy workers ratio below which we remove one when nothing is queued
+    pub down_util: f64,
+    /// Seconds without change after a decision
+    pub cooldown: u64,
+}
+
+impl Default for ScalingConfig {
+    fn default() -> Self {
+        ScalingConfig {
+            min: DEF_WORKERS,
+            max: DEF_WORKERS,
+            up_queue: DEF_UP_QUEUE,
+            down_util: DEF_DOWN_UTIL,
+            cooldown: DEF_COOLDOWN,
+        }
+    }
+}
+
+impl ScalingConfig {
+    /// Check bounds
+    ///
+    pub fn validate(&self) -> Result<(), EngineStatus> {
+        if self.min == 0 || self.min > self.max || self.up_queue == 0 {
+            return Err(EngineStatus::BadScaling(self.min, self.max));
+        }
+        Ok(())
+    }
+}
+
+/// One scaling decision, kept in the statistics
+///
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ScaleDecision {
+    /// When it was taken
+    pub at: i64,
+    /// Workers before
+    pub from: usize,
+    /// Workers after
+    pub to: usize,
+    /// Queued jobs at that time
+    pub queued: usize,
+    /// Running jobs at that time
+    pub busy: usize,
+}
+
+impl Display for ScaleDecision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} workers ({} queued, {} busy)",
+            self.from, self.to, self.queued, self.busy
+        )
+    }
+}
+
+/// Scaling policy
+///
+#[derive(Debug)]
+pub struct Scaler {
+    /// Bounds and thresholds
+    cfg: ScalingConfig,
+    /// Last decision
+    last: Mutex<Option<Instant>>,
+}
+
+impl Scaler {
+    pub fn new(cfg: &ScalingConfig) -> Self {
+        Scaler {
+            cfg: cfg.clone(),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Current number of workers, `min` until the first decision.
+    ///
+    pub fn workers(&self, last: Option<&ScaleDecision>) -> usize {
+        last.map(|d| d.to).unwrap_or(self.cfg.min)
+    }
+
+    /// Workers wanted for this queue, `None` if nothing has to change.
+    ///
+    pub fn decide(
+        &self,
+        workers: usize,
+        queued: usize,
+        busy: usize,
+        now: Instant,
+    ) -> Option<ScaleDecision> {
+        let cfg = &self.cfg;
+
+        // Bounds first, even during cooldown
+        //
+        let to = if workers < cfg.min || workers > cfg.max {
+            workers.clamp(cfg.min, cfg.max)
+        } else {
+            let mut last = self.last.lock().unwrap();
+            if let Some(last) = *last {
+                if now.duration_since(last) < Duration::from_secs(cfg.cooldown) {
+                    return None;
+                }
+            }
+            let to = if queued > workers * cfg.up_queue {
+                queued.div_ceil(cfg.up_queue).min(cfg.max)
+            } else if queued == 0 && (busy as f64) < cfg.down_util * workers as f64 {
+                (workers - 1).max(cfg.min).max(busy)
+            } else {
+                workers
+            };
+            if to != workers {
+                *last = Some(now);
+            }
+            to
+        };
+        (to != workers).then(|| ScaleDecision {
+            at: Utc::now().timestamp(),
+            from: workers,
+            to,
+            queued,
+            busy,
+        })
+    }
+}
+
+impl Engine {
+    /// Look at the queue and resize the pool of `factory` if needed.
+    ///
+    #[tracing::instrument(skip(self, factory))]
+    pub fn autoscale(&self, factory: &mut dyn WorkerFactory) -> Result<Option<ScaleDecision>> {
+        trace!("engine::autoscale");
+
+        let (queued, busy) = {
+            let state = self.state.read().unwrap();
+            let busy = state
+                .jobs
+                .values()
+                .filter(|j| j.status == JobStatus::Running)
+                .count();
+            (state.jobs.len() - busy, busy)
+        };
+
+        let workers = factory.workers();
+        let Some(decision) = self.scaler.decide(workers, queued, busy, Instant::now()) else {
+            return Ok(None);
+        };
+        if decision.to > workers {
+            factory.add(decision.to - workers)?;
+        } else {
+            factory.remove(workers - decision.to)?;
+        }
+        info!("Scaling: {}", decision);
+
+        let mut state = self.state.write().unwrap();
+        state.stats.add_scaling(decision);
+
+        // Ensure lock goes away
+        //
+        drop(state);
+        self.sync()?;
+        Ok(Some(decision))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaler() -> Scaler {
+        Scaler::new(&ScalingConfig {
+            min: 1,
+            max: 8,
+            cooldown: 60,
+            ..ScalingConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_scale_up_to_max() {
+        let s = scaler();
+        let now = Instant::now();
+
+        // 2 per worker is fine
+        //
+        assert_eq!(None, s.decide(2, 4, 2, now));
+
+        let d = s.decide(2, 9, 2, now).unwrap();
+        assert_eq!(5, d.to);
+
+        // Cooldown
+        //
+        assert_eq!(None, s.decide(5, 40, 5, now + Duration::from_secs(10)));
+
+        let d = s.decide(5, 40, 5, now + Duration::from_secs(61)).unwrap();
+        assert_eq!(8, d.to);
+    }
+
+    #[test]
+    fn test_scale_down_one_by_one() {
+        let s = scaler();
+        let now = Instant::now();
+
+        // Busy enough
+        //
+        assert_eq!(None, s.decide(4, 0, 1, now));
+
+        let d = s.decide(4, 0, 0, now).unwrap();
+        assert_eq!(3, d.to);
+        assert_eq!(None, s.decide(1, 0, 0, now + Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_scale_bounds() {
+        let s = scaler();
+        let now = Instant::now();
+
+        assert_eq!(1, s.decide(0, 0, 0, now).unwrap().to);
+        assert_eq!(8, s.decide(12, 0, 0, now).unwrap().to);
+
+        let bad = ScalingConfig {
+            min: 4,
+            max: 2,
+            ..ScalingConfig::default()
+        };
+        assert!(bad.validate().is_err());
+        assert!(ScalingConfig::default().validate().is_ok());
+    }
+}
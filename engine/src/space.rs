@@ -0,0 +1,417 @@
+//! Free space monitoring
+//!
+//! Streams have filled disks before, corrupting the state file along the way.  `SpaceMonitor`
+//! looks at the free space of every filesystem the engine writes to (`basedir`, `workdir`, the
+//! state directory and storage areas) and compares the lowest one with two thresholds:
+//!
+//! - below `low`, new bulk jobs (anything but streams) are refused by `Engine::run_job()`,
+//! - below `critical`, running streams are paused: what they receive is dropped instead of being
+//!   passed along until there is enough space again.
+//!
+//! Every level change is sent to `Engine::subscribe()` receivers as `QueueEvent::Space` and
+//! counted in the engine statistics.  Results are cached for `interval` seconds as streams check
+//! for every batch.
+//!
+//! Thresholds are either a percentage of the filesystem or a size, in `engine.hcl`:
+//!
+//! ```hcl
+//! space {
+//!   low      = "5%"
+//!   critical = "500M"
+//!   interval = 30
+//! }
+//! ```
+//!
+//! Free space is only known on UNIX systems, elsewhere the level is always `ok`.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace, warn};
+
+use crate::{EngineStatus, QueueEvent};
+
+/// Default thresholds and interval
+const DEF_LOW: Threshold = Threshold::Percent(5.);
+const DEF_CRITICAL: Threshold = Threshold::Percent(1.);
+const DEF_INTERVAL: u64 = 30;
+
+/// How much free space we have
+///
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+    strum::Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SpaceLevel {
+    /// Enough
+    #[default]
+    Ok,
+    /// No new bulk job
+    Low,
+    /// Streams are paused
+    Critical,
+}
+
+/// A limit on free space, either a percentage ("5%") or a size ("500M", "2G")
+///
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Threshold {
+    Percent(f64),
+    Bytes(u64),
+}
+
+impl Threshold {
+    /// Is `free` out of `total` below the limit?
+    ///
+    pub fn reached(&self, free: u64, total: u64) -> bool {
+        match self {
+            Threshold::Percent(p) => total != 0 && (free as f64) * 100. < p * total as f64,
+            Threshold::Bytes(b) => free < *b,
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = EngineStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || EngineStatus::BadThreshold(s.to_string());
+
+        let s = s.trim();
+        if let Some(p) = s.strip_suffix('%') {
+            return match p.trim().parse::<f64>() {
+                Ok(p) if (0. ..=100.).contains(&p) => Ok(Threshold::Percent(p)),
+                _ => Err(bad()),
+            };
+        }
+        let (n, unit) = match s.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c.to_ascii_uppercase()),
+            _ => (s, 'B'),
+        };
+        let mult: u64 = match unit {
+            'B' => 1,
+            'K' => 1 << 10,
+            'M' => 1 << 20,
+            'G' => 1 << 30,
+            'T' => 1 << 40,
+            _ => return Err(bad()),
+        };
+        let n = n.trim().parse::<u64>().map_err(|_| bad())?;
+        Ok(Threshold::Bytes(n.checked_mul(mult).ok_or_else(bad)?))
+    }
+}
+
+impl TryFrom<String> for Threshold {
+    type Error = EngineStatus;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Threshold::from_str(&s)
+    }
+}
+
+impl Display for Threshold {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Threshold::Percent(p) => write!(f, "{p}%"),
+            Threshold::Bytes(b) => write!(f, "{}", human(*b)),
+        }
+    }
+}
+
+/// `space` block in `engine.hcl`
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SpaceConfig {
+    /// Below this, no new bulk job
+    pub low: Threshold,
+    /// Below this, streams are paused
+    pub critical: Threshold,
+    /// Seconds between two checks
+    pub interval: u64,
+}
+
+impl Default for SpaceConfig {
+    fn default() -> Self {
+        SpaceConfig {
+            low: DEF_LOW,
+            critical: DEF_CRITICAL,
+            interval: DEF_INTERVAL,
+        }
+    }
+}
+
+/// Free space of the fullest filesystem at some point
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SpaceAlert {
+    /// When it was measured
+    pub at: i64,
+    /// One of the directories on that filesystem
+    pub path: PathBuf,
+    /// Free space in bytes
+    pub free: u64,
+    /// Size of the filesystem in bytes
+    pub total: u64,
+    /// Resulting level
+    pub level: SpaceLevel,
+}
+
+impl Display for SpaceAlert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} free on {:?}",
+            self.level,
+            human(self.free),
+            self.path
+        )
+    }
+}
+
+/// Watch free space on all the directories we write into
+///
+#[derive(Debug)]
+pub struct SpaceMonitor {
+    /// Thresholds
+    cfg: SpaceConfig,
+    /// Directories to check
+    paths: Vec<PathBuf>,
+    /// Last measure and when it was taken
+    last: Mutex<Option<(Instant, SpaceAlert)>>,
+    /// Level changes not yet accounted for in the stats
+    changes: Mutex<Vec<SpaceAlert>>,
+    /// Told about every level change
+    subscribers: Arc<Mutex<Vec<Sender<QueueEvent>>>>,
+}
+
+impl SpaceMonitor {
+    /// Watch `paths`, level changes are sent to `subscribers`
+    ///
+    pub fn new(
+        cfg: &SpaceConfig,
+        paths: &[PathBuf],
+        subscribers: Arc<Mutex<Vec<Sender<QueueEvent>>>>,
+    ) -> Self {
+        let mut paths = paths.to_vec();
+        paths.sort();
+        paths.dedup();
+        SpaceMonitor {
+            cfg: cfg.clone(),
+            paths,
+            last: Mutex::new(None),
+            changes: Mutex::new(vec![]),
+            subscribers,
+        }
+    }
+
+    /// Level for `free` bytes out of `total`
+    ///
+    pub fn classify(&self, free: u64, total: u64) -> SpaceLevel {
+        if self.cfg.critical.reached(free, total) {
+            SpaceLevel::Critical
+        } else if self.cfg.low.reached(free, total) {
+            SpaceLevel::Low
+        } else {
+            SpaceLevel::Ok
+        }
+    }
+
+    /// Last measure if recent enough, a new one otherwise
+    ///
+    pub fn check(&self) -> SpaceAlert {
+        let last = self.last.lock().unwrap().clone();
+        match last {
+            Some((when, alert)) if when.elapsed() < Duration::from_secs(self.cfg.interval) => alert,
+            _ => self.refresh(),
+        }
+    }
+
+    /// Measure now
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn refresh(&self) -> SpaceAlert {
+        trace!("space::refresh");
+
+        let alert = self
+            .paths
+            .iter()
+            .filter_map(|path| {
+                free_space(path).map(|(free, total)| SpaceAlert {
+                    at: Utc::now().timestamp(),
+                    path: path.clone(),
+                    free,
+                    total,
+                    level: self.classify(free, total),
+                })
+            })
+            .max_by(|a, b| a.level.cmp(&b.level).then(b.free.cmp(&a.free)))
+            .unwrap_or_default();
+
+        let mut last = self.last.lock().unwrap();
+        let before = last.as_ref().map(|(_, a)| a.level).unwrap_or_default();
+        *last = Some((Instant::now(), alert.clone()));
+        drop(last);
+
+        if alert.level != before {
+            match alert.level {
+                SpaceLevel::Ok => info!("Free space back to normal, {}", alert),
+                _ => warn!("Free space {}", alert),
+            }
+            self.changes.lock().unwrap().push(alert.clone());
+            self.subscribers
+                .lock()
+                .unwrap()
+                .retain(|tx| tx.send(QueueEvent::Space(alert.level)).is_ok());
+        }
+        alert
+    }
+
+    /// Level changes since the last call
+    ///
+    pub fn take(&self) -> Vec<SpaceAlert> {
+        std::mem::take(&mut *self.changes.lock().unwrap())
+    }
+}
+
+/// Free and total space of the filesystem holding `path` (or its closest existing parent)
+///
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn free_space(path: &Path) -> Option<(u64, u64)> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = path.ancestors().find(|p| p.exists())?;
+    let cpath = CString::new(path.as_os_str().as_bytes()).ok()?;
+
+    let mut st = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `cpath` is a valid C string and `st` is only read if the call succeeded.
+    if unsafe { libc::statvfs(cpath.as_ptr(), st.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let st = unsafe { st.assume_init() };
+    let bsize = st.f_frsize as u64;
+    Some((st.f_bavail as u64 * bsize, st.f_blocks as u64 * bsize))
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// Human-readable size
+///
+pub(crate) fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn monitor(low: &str, critical: &str, paths: &[PathBuf]) -> SpaceMonitor {
+        let cfg = SpaceConfig {
+            low: low.parse().unwrap(),
+            critical: critical.parse().unwrap(),
+            interval: 3_600,
+        };
+        SpaceMonitor::new(&cfg, paths, Arc::new(Mutex::new(vec![])))
+    }
+
+    #[rstest]
+    #[case("5%", Some(Threshold::Percent(5.)))]
+    #[case(" 0.5 %", Some(Threshold::Percent(0.5)))]
+    #[case("500M", Some(Threshold::Bytes(500 << 20)))]
+    #[case("2g", Some(Threshold::Bytes(2 << 30)))]
+    #[case("1024", Some(Threshold::Bytes(1024)))]
+    #[case("150%", None)]
+    #[case("12X", None)]
+    #[case("lots", None)]
+    fn test_threshold_parse(#[case] s: &str, #[case] res: Option<Threshold>) {
+        assert_eq!(res, s.parse().ok());
+    }
+
+    #[rstest]
+    #[case(60, SpaceLevel::Ok)]
+    #[case(8, SpaceLevel::Low)]
+    #[case(4, SpaceLevel::Critical)]
+    fn test_space_classify(#[case] free: u64, #[case] level: SpaceLevel) {
+        let m = monitor("10%", "5B", &[]);
+        assert_eq!(level, m.classify(free, 100));
+    }
+
+    #[test]
+    fn test_space_config_default() {
+        let cfg: SpaceConfig = hcl::from_str("low = \"1G\"").unwrap();
+        assert_eq!(Threshold::Bytes(1 << 30), cfg.low);
+        assert_eq!(DEF_CRITICAL, cfg.critical);
+    }
+
+    #[test]
+    fn test_space_monitor_changes() {
+        let dir = tempdir().unwrap();
+        let paths = [dir.path().to_path_buf(), dir.path().join("not/yet")];
+
+        let m = monitor("0%", "0B", &paths);
+        assert_eq!(SpaceLevel::Ok, m.refresh().level);
+        assert!(m.take().is_empty());
+
+        // Nobody has that much space
+        //
+        let (tx, rx) = channel();
+        let mut m = monitor("100%", "0B", &paths);
+        m.subscribers = Arc::new(Mutex::new(vec![tx]));
+        if cfg!(unix) {
+            let alert = m.check();
+            assert_eq!(SpaceLevel::Low, alert.level);
+            assert!(alert.total > 0);
+            assert_eq!(alert, m.check());
+            assert_eq!(vec![alert], m.take());
+            assert_eq!(QueueEvent::Space(SpaceLevel::Low), rx.try_recv().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_human() {
+        assert_eq!("512 B", human(512));
+        assert_eq!("1.5 KB", human(1536));
+        assert_eq!("2.0 GB", human(2 << 30));
+    }
+}
@@ -0,0 +1,1551 @@
+//! Declarative job specification
+//!
+//! Instead of building a job from command-line options, a job can be described in a file
+//! (usually `job.hcl`) and submitted with `acutectl submit -f job.hcl`:
+//!
+//! ```hcl
+//! version = 1
+//!
+//! job "cdg-drones" {
+//!   source   = "asd"
+//!   filter {
+//!     since = 3600
+//!   }
+//!   into     = "cat21"
+//!   raw_copy = "/data/raw"
+//!   sink "save" {
+//!     path = "/data/drones.csv"
+//!   }
+//!   schedule {
+//!     every = 3600
+//!   }
+//! }
+//! ```
+//!
+//! - `source` is a site from `sources.hcl`, whether it is fetched or streamed depends on the site,
+//! - `filter` is `since` (seconds), `begin`/`end` (RFC 3339), `keyword` (`name:value`) or for
+//!   streams `start` (go back N seconds).  `window` is a sliding window (`last 15m`,
+//!   `previous full hour`, `yesterday`...) resolved when the job is created, i.e. on every run
+//!   of a scheduled job, in the calendar of `timezone` (UTC by default), see `Window`,
+//! - `into` (`cat21` or `senhive`) and `raw_copy` are the same as the `fetch` options,
+//! - `tracks` adds a normalised `track_id` to every record (`max_gap`, `max_jump`), see the
+//!   `Track` task,
+//! - `category` only keeps the records of these vehicle categories (`uas`, `rotorcraft`...), see
+//!   the `Select` task,
+//! - `label` joins the records with the flight plans in `plans` (CSV or NM B2B) by callsign,
+//!   give or take `window` seconds (900 by default), adding `flight_id` and `route`, with
+//!   `matched_only = true` dropping the others, see the `Label` task,
+//! - `qc` checks the records before the sink (`summary`, `max_gap`, `max_climb`, `drop`), see
+//!   the `Qc` task,
+//! - `sink` is one of `save` (`path`, `container`, `trajectories` and `layout` = `points` or
+//!   `tracks` for Parquet, `columns` = `full`, `minimal`, `ids` or a list of names for Cat21
+//!   CSV, see the `Project` task), `split` (`path`, `by`), `store` (`path`) or
+//!   `postgis` (`url`, `table`, `trajectories`, with the `postgis` feature), all of them take
+//!   an optional `redact` naming a redaction policy from `engine.hcl`,
+//! - `spill` keeps a slow sink from holding the pipeline back: what it can not take yet is
+//!   written into `path` (`spill` in the job working directory by default), up to `size` (1G by
+//!   default), and sent when it recovers, see the `Spill` task,
+//! - `schedule` runs the job `every` N seconds, `count` times (0 means forever).  With
+//!   `probe = true` the source is checked before each run and the run is deferred while it is
+//!   down, probing again every `retry` seconds (60 by default), see `readiness.rs`,
+//! - `limits` are `duration` (seconds, 0 for no limit) and `delay` (ms between calls) for streams,
+//! - `timeout` is a wall-clock limit in minutes after which the job is cancelled and marked as
+//!   timed out, overriding `job_timeout` from `engine.hcl` (which does not apply to streams),
+//! - `provenance = true` stamps every record with its source, job and ingestion time, see
+//!   `provenance.rs`,
+//! - `tuning "<stage>"` sets the `capacity` of the channel after a stage (`fetch`, `convert`,
+//!   `save`...) and for `convert` the number of `workers` and the `batch` size, see `tuning.rs`,
+//! - `export` copies what the job wrote (`artifacts`: `output`, `qc` and/or `raw`) to `to`, an
+//!   object store or a local path with `{source}`, `{name}`, `{date}`, `{job_id}` and `{file}`
+//!   in it, once the job has succeeded (`move`, `retries`, `region`, `endpoint`), see `export.rs`,
+//! - `on_success` and `on_failure` name the jobs of the same file to run after this one, see
+//!   `chain.rs`.  A job without `source` reads the output of the job before it.
+//!
+//! A file can hold several jobs, they are run in the order of the file except those run after
+//! another one.
+//!
+//! Jobs can also be built in Rust with `JobSpec::fetch()`, see `builder.rs`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use eyre::Result;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use tracing::{info, trace};
+
+use fetiche_common::{Container, Window};
+use fetiche_formats::{Columns, Format, VehicleCategory, CAT21_COLUMNS, TRACK_ID};
+use fetiche_sources::{Filter, Flow, Site};
+
+#[cfg(feature = "postgis")]
+use crate::PostGis;
+use crate::{
+    Artifacts, Broadcast, Convert, Engine, EngineStatus, Export, Fetch, FlightPlans, Job, Label,
+    Layout, Project, Qc, RawCopy, Read, Runnable, Save, Select, Spill, Split, SplitBy, Store,
+    Stream, Threshold, Track, Tuning, EXPORT_RETRIES, EXPORT_VARS, FLIGHT_ID, LABEL_WINDOW,
+    PROVENANCE, QC_MAX_CLIMB, QC_MAX_GAP, ROUTE, SPILL_MAX, TRACK_MAX_GAP, TRACK_MAX_JUMP,
+};
+
+/// Current version of the job file format
+pub const JVERSION: usize = 1;
+
+/// Default delay between calls for streams, in ms
+const DEF_DELAY: u32 = 1_000;
+
+/// A job file, one or more jobs
+///
+#[derive(Clone, Debug, Deserialize)]
+pub struct JobFile {
+    /// Version
+    pub version: usize,
+    /// Jobs, by name in the order of the file
+    pub job: IndexMap<String, JobSpec>,
+}
+
+impl JobFile {
+    /// Read and check a job file
+    ///
+    #[tracing::instrument]
+    pub fn load(fname: &Path) -> Result<Self> {
+        trace!("JobFile::load({:?})", fname);
+
+        let data = fs::read_to_string(fname)?;
+        Self::from_str(&data)
+    }
+}
+
+impl FromStr for JobFile {
+    type Err = eyre::Error;
+
+    /// Parse and check every job
+    ///
+    fn from_str(s: &str) -> Result<Self> {
+        let file: JobFile = hcl::from_str(s)?;
+        if file.version != JVERSION {
+            return Err(EngineStatus::BadJobFileVersion(file.version, JVERSION).into());
+        }
+        for (name, spec) in file.job.iter() {
+            spec.check()
+                .map_err(|e| EngineStatus::BadJobSpec(name.clone(), e))?;
+        }
+        file.check_chains()?;
+        Ok(file)
+    }
+}
+
+/// Description of a single job
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobSpec {
+    /// Site name, empty for jobs reading the output of the previous one
+    #[serde(default)]
+    pub source: String,
+    /// Optional filter
+    pub filter: Option<FilterSpec>,
+    /// Convert into this format
+    pub into: Option<String>,
+    /// Keep the raw data in this directory
+    pub raw_copy: Option<String>,
+    /// Only keep these vehicle categories
+    #[serde(default)]
+    pub category: Vec<String>,
+    /// Add track IDs
+    pub tracks: Option<TrackSpec>,
+    /// Add flight plan labels
+    pub label: Option<LabelSpec>,
+    /// Check records before the sink
+    pub qc: Option<QcSpec>,
+    /// Where the data ends
+    pub sink: Sink,
+    /// Spill to disk what the sink can not take yet
+    pub spill: Option<SpillSpec>,
+    /// Run it more than once
+    pub schedule: Option<Schedule>,
+    /// Stream limits
+    pub limits: Option<Limits>,
+    /// Wall-clock limit in minutes
+    pub timeout: Option<u64>,
+    /// Add provenance columns
+    #[serde(default)]
+    pub provenance: bool,
+    /// Export artifacts once done
+    pub export: Option<ExportSpec>,
+    /// Tuning of the stages by name
+    #[serde(default)]
+    pub tuning: BTreeMap<String, Tuning>,
+    /// Jobs to run after this one succeeded
+    #[serde(default)]
+    pub on_success: Vec<String>,
+    /// Jobs to run after this one failed
+    #[serde(default)]
+    pub on_failure: Vec<String>,
+}
+
+/// Filter part of a job
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterSpec {
+    /// Duration in seconds (negative = back in time)
+    pub since: Option<i32>,
+    /// Start of the interval
+    pub begin: Option<DateTime<Utc>>,
+    /// End of the interval
+    pub end: Option<DateTime<Utc>>,
+    /// Keyword filter: e.g. "icao24:foobar"
+    pub keyword: Option<String>,
+    /// For streams, go back N seconds
+    pub start: Option<i64>,
+    /// Sliding window, e.g. "previous full hour"
+    pub window: Option<String>,
+    /// Timezone of the window, e.g. "Europe/Paris"
+    pub timezone: Option<String>,
+}
+
+/// Track assembly
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackSpec {
+    /// Longest gap within a track, in seconds
+    pub max_gap: Option<i64>,
+    /// Largest jump in position within a track, in meters
+    pub max_jump: Option<f64>,
+}
+
+/// Labels from flight plans
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LabelSpec {
+    /// Flight plans file
+    pub plans: String,
+    /// Slack around each plan, in seconds
+    pub window: Option<i64>,
+    /// Drop records without a plan
+    #[serde(default)]
+    pub matched_only: bool,
+}
+
+/// Quality checks
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QcSpec {
+    /// Summary file
+    pub summary: Option<String>,
+    /// Longest gap within a track, in seconds
+    pub max_gap: Option<i64>,
+    /// Fastest climb or descent, in m/s
+    pub max_climb: Option<f64>,
+    /// Remove bad records
+    #[serde(default)]
+    pub drop: bool,
+}
+
+/// Final stage of a job
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum Sink {
+    /// Single file (or stdout with "-")
+    Save {
+        path: String,
+        container: Option<String>,
+        /// GeoParquet only, also write one line per track
+        #[serde(default)]
+        trajectories: bool,
+        /// Parquet only, one row per position or per track
+        #[serde(default)]
+        layout: Layout,
+        redact: Option<String>,
+        /// Cat21 CSV only, columns to write (`full`, `minimal`, `ids` or a list)
+        columns: Option<String>,
+    },
+    /// One file per key
+    Split {
+        path: String,
+        by: String,
+        redact: Option<String>,
+    },
+    /// Hourly files in a directory
+    Store {
+        path: String,
+        redact: Option<String>,
+    },
+    /// PostGIS table
+    Postgis {
+        url: String,
+        table: Option<String>,
+        #[serde(default)]
+        trajectories: bool,
+        redact: Option<String>,
+    },
+}
+
+impl Sink {
+    /// Name of the redaction policy attached to this sink, if any
+    ///
+    pub fn redact(&self) -> Option<&str> {
+        match self {
+            Sink::Save { redact, .. }
+            | Sink::Split { redact, .. }
+            | Sink::Store { redact, .. }
+            | Sink::Postgis { redact, .. } => redact.as_deref(),
+        }
+    }
+}
+
+/// Spill in front of the sink
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpillSpec {
+    /// Largest spill ("500M", "2G"), 1G by default
+    pub size: Option<String>,
+    /// Spill directory, `spill` in the job working directory by default
+    pub path: Option<String>,
+}
+
+impl SpillSpec {
+    /// Largest spill in bytes
+    ///
+    pub(crate) fn max(&self) -> std::result::Result<u64, String> {
+        match self.size.as_deref().map(Threshold::from_str) {
+            None => Ok(SPILL_MAX),
+            Some(Ok(Threshold::Bytes(n))) if n > 0 => Ok(n),
+            Some(_) => Err(format!(
+                "bad spill size {}, need a size like 500M",
+                self.size.as_deref().unwrap_or_default()
+            )),
+        }
+    }
+}
+
+/// Export of the artifacts after the job
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportSpec {
+    /// Destination template
+    pub to: String,
+    /// What to export, `output` by default
+    #[serde(default = "def_artifacts")]
+    pub artifacts: Vec<String>,
+    /// Remove local files once exported
+    #[serde(default, rename = "move")]
+    pub remove: bool,
+    /// Retries for each file, 3 by default
+    pub retries: Option<usize>,
+    /// S3 region
+    pub region: Option<String>,
+    /// S3 endpoint
+    pub endpoint: Option<String>,
+}
+
+fn def_artifacts() -> Vec<String> {
+    vec!["output".to_string()]
+}
+
+/// When to run the job again
+///
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    /// Seconds between two runs
+    pub every: u64,
+    /// Number of runs, 0 means forever
+    #[serde(default)]
+    pub count: usize,
+    /// Check the source before every run
+    #[serde(default)]
+    pub probe: bool,
+    /// Seconds between two probes while the source is down
+    pub retry: Option<u64>,
+}
+
+/// Limits for streams
+///
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    /// Stop after N seconds, 0 is no limit
+    #[serde(default)]
+    pub duration: u32,
+    /// Delay between calls in ms
+    pub delay: Option<u32>,
+}
+
+impl JobSpec {
+    /// Check everything that does not need the engine, returns the reason if invalid.
+    ///
+    pub fn check(&self) -> std::result::Result<(), String> {
+        if self.source.is_empty() {
+            if self.filter.is_some() || self.limits.is_some() {
+                return Err("filter and limits need a source".to_string());
+            }
+            if self.schedule.is_some() {
+                return Err("jobs without a source can not be scheduled".to_string());
+            }
+        }
+
+        if let Some(f) = &self.filter {
+            match (f.begin, f.end) {
+                (Some(b), Some(e)) if b >= e => return Err("begin must be before end".to_string()),
+                (Some(_), None) | (None, Some(_)) => {
+                    return Err("need both begin and end".to_string())
+                }
+                _ => (),
+            }
+            let n = [
+                f.since.is_some(),
+                f.begin.is_some(),
+                f.keyword.is_some(),
+                f.window.is_some(),
+            ]
+            .iter()
+            .filter(|&&b| b)
+            .count();
+            if n > 1 {
+                return Err("only one of since, begin/end, keyword or window".to_string());
+            }
+            if let Some(window) = &f.window {
+                Window::from_str(window).map_err(|e| e.to_string())?;
+            }
+            match (&f.window, &f.timezone) {
+                (None, Some(_)) => return Err("timezone needs a window".to_string()),
+                (Some(_), Some(tz)) if Tz::from_str(tz).is_err() => {
+                    return Err(format!("unknown timezone {tz}"))
+                }
+                _ => (),
+            }
+            if let Some(kw) = &f.keyword {
+                if kw.split_once(':').is_none() {
+                    return Err(format!("bad keyword {kw}, need name:value"));
+                }
+            }
+        }
+
+        if let Some(into) = &self.into {
+            match Format::from_str(into) {
+                Ok(Format::Cat21) => (),
+                Ok(Format::Senhive)
+                    if self.tracks.is_none() && self.qc.is_none() && self.label.is_none() => {}
+                Ok(Format::Senhive) => return Err("tracks, label and qc need cat21".to_string()),
+                _ => {
+                    return Err(format!(
+                        "can not convert into {into}, only cat21 or senhive"
+                    ))
+                }
+            }
+        }
+
+        if let Some(bad) = self
+            .category
+            .iter()
+            .find(|c| VehicleCategory::from_str(c).is_err())
+        {
+            return Err(format!("unknown category {bad}"));
+        }
+
+        match &self.sink {
+            Sink::Save {
+                path,
+                container,
+                trajectories,
+                layout,
+                columns,
+                ..
+            } => {
+                if path.is_empty() {
+                    return Err("empty save path".to_string());
+                }
+                let container = match container {
+                    Some(c) => {
+                        Container::from_str(c).map_err(|_| format!("unknown container {c}"))?
+                    }
+                    None => container_from_path(path),
+                };
+                if *trajectories && container != Container::GeoParquet {
+                    return Err("trajectories need the geoparquet container".to_string());
+                }
+                if *layout == Layout::Tracks && container != Container::Parquet {
+                    return Err("tracks layout needs the parquet container".to_string());
+                }
+                if let Some(columns) = columns {
+                    if self.conversion() != Format::Cat21 {
+                        return Err("columns need cat21".to_string());
+                    }
+                    if !matches!(container, Container::CSV | Container::Raw) {
+                        return Err("columns need the csv container".to_string());
+                    }
+                    Columns::from_str(columns)
+                        .and_then(|c| c.indices(&self.cat21_columns()))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            Sink::Split { path, by, .. } => {
+                if path.is_empty() {
+                    return Err("empty split path".to_string());
+                }
+                SplitBy::from_str(by).map_err(|_| format!("can not split by {by}"))?;
+            }
+            Sink::Store { path, .. } => {
+                if path.is_empty() {
+                    return Err("empty store path".to_string());
+                }
+            }
+            Sink::Postgis { .. } => {
+                if !cfg!(feature = "postgis") {
+                    return Err("built without the postgis feature".to_string());
+                }
+            }
+        }
+
+        if let Some(label) = &self.label {
+            if label.plans.is_empty() {
+                return Err("empty label plans".to_string());
+            }
+            if label.window.is_some_and(|w| w < 0) {
+                return Err("label window must be >= 0".to_string());
+            }
+        }
+
+        if let Some(spill) = &self.spill {
+            spill.max()?;
+        }
+
+        if let Some(export) = &self.export {
+            self.check_export(export)?;
+        }
+
+        for (name, tuning) in &self.tuning {
+            tuning.check(name).map_err(|e| e.to_string())?;
+        }
+
+        if let Some(s) = &self.schedule {
+            if s.every == 0 {
+                return Err("schedule every must be > 0".to_string());
+            }
+            if s.retry == Some(0) {
+                return Err("schedule retry must be > 0".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Export needs a valid template and artifacts the job actually writes.  Directories (`split`,
+    /// `store` and `raw`) export several files so need `{file}` in the template.
+    ///
+    fn check_export(&self, export: &ExportSpec) -> std::result::Result<(), String> {
+        if export.to.is_empty() {
+            return Err("empty export to".to_string());
+        }
+        let mut rest = export.to.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed {{ in {}", export.to));
+            };
+            let var = &rest[start + 1..start + len];
+            if !EXPORT_VARS.contains(&var) {
+                return Err(format!("unknown variable {{{var}}} in {}", export.to));
+            }
+            rest = &rest[start + len + 1..];
+        }
+        if export.to.contains("://")
+            && !export.to.starts_with("s3://")
+            && !export.to.starts_with("file://")
+        {
+            return Err(format!("can not export to {}, only s3 or files", export.to));
+        }
+        if export.artifacts.is_empty() {
+            return Err("nothing to export".to_string());
+        }
+
+        let per_file = export.to.contains("{file}");
+        for artifact in &export.artifacts {
+            match artifact.as_str() {
+                "output" => match &self.sink {
+                    Sink::Save { path, .. } if path == "-" => {
+                        return Err("can not export stdout".to_string())
+                    }
+                    Sink::Postgis { .. } => return Err("can not export postgis".to_string()),
+                    Sink::Split { .. } | Sink::Store { .. } if !per_file => {
+                        return Err(format!("{artifact} export needs {{file}}"))
+                    }
+                    _ => (),
+                },
+                "qc" if self
+                    .qc
+                    .as_ref()
+                    .and_then(|qc| qc.summary.as_ref())
+                    .is_none() =>
+                {
+                    return Err("qc export needs a qc summary".to_string())
+                }
+                "raw" if self.raw_copy.is_none() => {
+                    return Err("raw export needs raw_copy".to_string())
+                }
+                "raw" if !per_file => return Err(format!("{artifact} export needs {{file}}")),
+                "qc" | "raw" => (),
+                _ => {
+                    return Err(format!(
+                        "unknown artifact {artifact}, need output, qc or raw"
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the filter for a fetch or a stream, windows are resolved now.
+    ///
+    pub(crate) fn filter(&self, stream: bool) -> Filter {
+        self.filter_at(stream, Utc::now())
+    }
+
+    /// Build the filter as of `now`.
+    ///
+    fn filter_at(&self, stream: bool, now: DateTime<Utc>) -> Filter {
+        let f = self.filter.clone().unwrap_or_default();
+
+        if let Some((name, value)) = f.keyword.as_deref().and_then(|kw| kw.split_once(':')) {
+            return Filter::keyword(name, value);
+        }
+        if stream {
+            let limits = self.limits.clone().unwrap_or_default();
+            return Filter::stream(
+                f.start.unwrap_or(0),
+                limits.duration,
+                limits.delay.unwrap_or(DEF_DELAY),
+            );
+        }
+        let window = f.window.as_deref().and_then(|w| Window::from_str(w).ok());
+        if let Some(window) = window {
+            let tz = f.timezone.as_deref().and_then(|tz| Tz::from_str(tz).ok());
+            let (begin, end) = window.resolve(now, tz.unwrap_or(Tz::UTC));
+            info!("window {window} is from {begin} to {end}");
+            return Filter::interval(begin, end);
+        }
+        match (f.begin, f.end, f.since) {
+            (Some(begin), Some(end), _) => Filter::interval(begin, end),
+            (_, _, Some(since)) => Filter::since(since),
+            _ => Filter::default(),
+        }
+    }
+
+    /// Conversion asked for, `Format::None` if none
+    ///
+    pub(crate) fn conversion(&self) -> Format {
+        match self.into.as_deref().map(Format::from_str) {
+            Some(Ok(Format::Senhive)) => Format::Senhive,
+            Some(_) => Format::Cat21,
+            None => Format::None,
+        }
+    }
+
+    /// Vehicle categories to keep, all of them if empty
+    ///
+    pub(crate) fn categories(&self) -> Vec<VehicleCategory> {
+        self.category
+            .iter()
+            .filter_map(|c| VehicleCategory::from_str(c).ok())
+            .collect()
+    }
+
+    /// Columns of the Cat21 records reaching the sink, with those added on the way.
+    ///
+    pub(crate) fn cat21_columns(&self) -> Vec<&'static str> {
+        let mut columns = CAT21_COLUMNS.to_vec();
+        if self.provenance {
+            columns.extend(PROVENANCE);
+        }
+        if self.tracks.is_some() {
+            columns.push(TRACK_ID);
+        }
+        if self.label.is_some() {
+            columns.extend([FLIGHT_ID, ROUTE]);
+        }
+        columns
+    }
+
+    /// Format of the records reaching the sink when reading `fmt`
+    ///
+    pub(crate) fn output_format(&self, fmt: Format) -> Format {
+        match self.conversion() {
+            Format::None => fmt,
+            into => into,
+        }
+    }
+
+    /// Where the sink of job `id` leaves records the next jobs can read, text files only.
+    ///
+    pub(crate) fn output_path(&self, id: usize) -> Option<PathBuf> {
+        match &self.sink {
+            Sink::Save {
+                path, container, ..
+            } if path != "-" => {
+                let container = match container {
+                    Some(c) => Container::from_str(c).ok()?,
+                    None => container_from_path(path),
+                };
+                matches!(container, Container::CSV | Container::Raw).then(|| PathBuf::from(path))
+            }
+            Sink::Split { path, .. } => Some(PathBuf::from(path)),
+            Sink::Store { path, .. } => Some(Path::new(path).join(id.to_string())),
+            _ => None,
+        }
+    }
+
+    /// The export of job `id` if any, see `check_export()` for the artifacts.
+    ///
+    pub(crate) fn export(&self, id: usize) -> Option<Export> {
+        let spec = self.export.as_ref()?;
+
+        let mut export = Export::new(&spec.to);
+        for artifact in &spec.artifacts {
+            let path = match (artifact.as_str(), &self.sink) {
+                ("output", Sink::Save { path, .. } | Sink::Split { path, .. }) => {
+                    PathBuf::from(path)
+                }
+                ("output", Sink::Store { path, .. }) => Path::new(path).join(id.to_string()),
+                ("qc", _) => PathBuf::from(self.qc.as_ref()?.summary.as_ref()?),
+                ("raw", _) => PathBuf::from(self.raw_copy.as_ref()?),
+                _ => continue,
+            };
+            export.artifact(&path);
+        }
+        export
+            .remove(spec.remove)
+            .retries(spec.retries.unwrap_or(EXPORT_RETRIES));
+        if let Some(region) = &spec.region {
+            export.region(region);
+        }
+        if let Some(endpoint) = &spec.endpoint {
+            export.endpoint(endpoint);
+        }
+        Some(export)
+    }
+}
+
+impl Engine {
+    /// Create a job from its specification, the job is registered like with `create_job()` and
+    /// should be run with `run_job()`.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn create_job_from(&mut self, name: &str, spec: &JobSpec) -> Result<Job> {
+        Ok(self.create_job_after(name, spec, None)?.0)
+    }
+
+    /// Same as `create_job_from()`, a job without `source` reading the output of the previous
+    /// job in `upstream`.  What the job leaves for the next ones is returned with it.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub(crate) fn create_job_after(
+        &mut self,
+        name: &str,
+        spec: &JobSpec,
+        upstream: Option<&Artifacts>,
+    ) -> Result<(Job, Option<Artifacts>)> {
+        spec.check()
+            .map_err(|e| EngineStatus::BadJobSpec(name.to_string(), e))?;
+
+        let (producer, fmt, stream, source): (Box<dyn Runnable>, _, _, _) = match upstream {
+            _ if !spec.source.is_empty() => {
+                let srcs = self.sources();
+                let site = Site::load(&spec.source, &srcs)?;
+                let fmt = site.format();
+                match site {
+                    Flow::Fetchable(_) => {
+                        let mut task = Fetch::new(&spec.source, srcs);
+                        task.site(spec.source.clone())
+                            .with(spec.filter(false))
+                            .tokens(self.refresher());
+                        (Box::new(task), fmt, false, spec.source.clone())
+                    }
+                    Flow::Streamable(_) => {
+                        let mut task = Stream::new(&spec.source, srcs);
+                        task.site(spec.source.clone())
+                            .with(spec.filter(true))
+                            .space(self.space())
+                            .tokens(self.refresher());
+                        (Box::new(task), fmt, true, spec.source.clone())
+                    }
+                }
+            }
+            Some(up) => {
+                let mut task = Read::new(name);
+                task.path(&up.path.to_string_lossy()).format(up.format);
+                (Box::new(task), up.format, false, up.source.clone())
+            }
+            None => {
+                return Err(EngineStatus::BadJobSpec(
+                    name.to_string(),
+                    "no source and no previous job".to_string(),
+                )
+                .into())
+            }
+        };
+
+        // Check the policy before creating anything
+        //
+        let redact = match spec.sink.redact() {
+            Some(policy) => Some(self.redaction(policy)?),
+            None => None,
+        };
+
+        let mut job = self.create_job(name);
+        job.stream = stream;
+        job.source = (!spec.source.is_empty()).then(|| spec.source.clone());
+        job.timeout = spec.timeout.map(|m| Duration::from_secs(m * 60));
+        job.tuning = spec.tuning.clone();
+        info!("Job #{} from spec {}", job.id, name);
+        job.add(producer);
+
+        if let Some(dir) = &spec.raw_copy {
+            let mut raw = RawCopy::new(dir, fmt);
+            raw.path(dir);
+            job.add(Box::new(raw));
+        }
+
+        // Redaction and provenance happen in `Convert`, with or without a conversion
+        //
+        let into = spec.conversion();
+        if into != Format::None || redact.is_some() || spec.provenance {
+            let mut convert = Convert::new();
+            convert
+                .from(fmt)
+                .into(into)
+                .strictness(self.sources().strictness(&source));
+            if let Some(redact) = redact {
+                convert.redact(redact);
+            }
+            if spec.provenance {
+                convert.provenance(self.provenance(&source, job.id));
+            }
+            if let Some(tuning) = spec.tuning.get("convert") {
+                convert.tuning(tuning);
+            }
+            job.add(Box::new(convert));
+        }
+        let input = spec.output_format(fmt);
+
+        let categories = spec.categories();
+        if !categories.is_empty() {
+            let mut select = Select::new(name, input);
+            select.categories(&categories);
+            job.add(Box::new(select));
+        }
+
+        if let Some(spec) = &spec.tracks {
+            let mut track = Track::new(name, input);
+            track
+                .max_gap(spec.max_gap.unwrap_or(TRACK_MAX_GAP))
+                .max_jump(spec.max_jump.unwrap_or(TRACK_MAX_JUMP));
+            job.add(Box::new(track));
+        }
+
+        if let Some(spec) = &spec.label {
+            let mut label = Label::new(name, input);
+            label
+                .plans(FlightPlans::load(Path::new(&spec.plans))?)
+                .window(spec.window.unwrap_or(LABEL_WINDOW))
+                .matched_only(spec.matched_only);
+            job.add(Box::new(label));
+        }
+
+        if let Some(spec) = &spec.qc {
+            let mut qc = Qc::new(name, input);
+            qc.max_gap(spec.max_gap.unwrap_or(QC_MAX_GAP))
+                .max_climb(spec.max_climb.unwrap_or(QC_MAX_CLIMB))
+                .drop(spec.drop);
+            if let Some(summary) = &spec.summary {
+                qc.path(summary);
+            }
+            job.add(Box::new(qc));
+        }
+
+        // Live clients get what the sink gets, see `live.rs`
+        //
+        if let (true, Some(live)) = (stream, &self.live) {
+            job.add(Box::new(Broadcast::new(live, &source, input)));
+        }
+
+        // Only now, every stage before needs the full records
+        //
+        if let Sink::Save {
+            columns: Some(columns),
+            ..
+        } = &spec.sink
+        {
+            let columns = Columns::from_str(columns)?;
+            job.add(Box::new(Project::new(
+                name,
+                &columns,
+                &spec.cat21_columns(),
+            )?));
+        }
+
+        let sink: Box<dyn Runnable> = match &spec.sink {
+            Sink::Save {
+                path,
+                container,
+                trajectories,
+                layout,
+                ..
+            } => {
+                let container = match container {
+                    Some(c) => Container::from_str(c)?,
+                    None => container_from_path(path),
+                };
+                let mut save = Save::new(path, input, container);
+                save.path(path).trajectories(*trajectories).layout(*layout);
+                if let Some(dir) = &job.workdir {
+                    save.tmpdir(dir);
+                }
+                Box::new(save)
+            }
+            Sink::Split { path, by, .. } => {
+                let mut split = Split::new(path, input, SplitBy::from_str(by)?);
+                split.path(path);
+                Box::new(split)
+            }
+            Sink::Store { path, .. } => Box::new(Store::new(path, job.id)?),
+            #[cfg(feature = "postgis")]
+            Sink::Postgis {
+                url,
+                table,
+                trajectories,
+                ..
+            } => {
+                let table = table.clone().unwrap_or(crate::POSTGIS_TABLE.to_string());
+                let mut pg = PostGis::new(&table, input, url);
+                pg.table(&table).trajectories(*trajectories);
+                Box::new(pg)
+            }
+            #[cfg(not(feature = "postgis"))]
+            Sink::Postgis { .. } => {
+                return Err(EngineStatus::BadJobSpec(
+                    name.to_string(),
+                    "built without the postgis feature".to_string(),
+                )
+                .into())
+            }
+        };
+
+        // Slow sinks do not hold the pipeline back
+        //
+        match &spec.spill {
+            Some(spill) => {
+                let dir = match (&spill.path, &job.workdir) {
+                    (Some(path), _) => PathBuf::from(path),
+                    (None, Some(dir)) => dir.join("spill"),
+                    (None, None) => std::env::temp_dir().join(format!("spill-{}", job.id)),
+                };
+                let mut task = Spill::new(sink, &dir);
+                task.max(spill.max().unwrap_or(SPILL_MAX));
+                job.add(Box::new(task));
+            }
+            None => {
+                job.add(sink);
+            }
+        }
+
+        job.export = spec.export(job.id);
+
+        let artifacts = spec.output_path(job.id).map(|path| Artifacts {
+            path,
+            format: input,
+            source,
+        });
+        Ok((job, artifacts))
+    }
+}
+
+/// Same logic as `acutectl fetch -o`: use the extension, raw for stdout or unknown ones.
+///
+pub(crate) fn container_from_path(path: &str) -> Container {
+    Path::new(&path.to_lowercase())
+        .extension()
+        .and_then(|ext| Container::from_str(&ext.to_string_lossy()).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::thread;
+
+    use rstest::rstest;
+    use tungstenite::client::IntoClientRequest;
+
+    use crate::{Live, SpaceConfig, SpaceMonitor, LIVE_PATH};
+
+    use super::*;
+
+    const JOB: &str = r##"
+version = 1
+
+job "cdg" {
+  source = "asd"
+  filter {
+    since = 3600
+  }
+  into     = "cat21"
+  raw_copy = "raw"
+  sink "split" {
+    path = "out"
+    by   = "journey"
+  }
+  schedule {
+    every = 600
+    count = 6
+    probe = true
+  }
+}
+
+job "live" {
+  source = "opensky"
+  sink "save" {
+    path   = "live.csv"
+    redact = "public"
+  }
+  limits {
+    duration = 60
+  }
+}
+"##;
+
+    #[test]
+    fn test_jobfile_parse() {
+        let f = JobFile::from_str(JOB).unwrap();
+        assert_eq!(2, f.job.len());
+
+        let cdg = &f.job["cdg"];
+        assert_eq!("asd", cdg.source);
+        assert_eq!(Some(3600), cdg.filter.as_ref().unwrap().since);
+        assert!(matches!(&cdg.sink, Sink::Split { by, .. } if by == "journey"));
+        assert_eq!(6, cdg.schedule.as_ref().unwrap().count);
+        assert!(cdg.schedule.as_ref().unwrap().probe);
+        assert!(cdg.sink.redact().is_none());
+
+        let live = &f.job["live"];
+        assert!(live.schedule.is_none());
+        assert_eq!(Some("public"), live.sink.redact());
+        assert_eq!(Filter::stream(0, 60, DEF_DELAY), live.filter(true));
+        assert_eq!(Filter::default(), live.filter(false));
+    }
+
+    #[test]
+    fn test_jobfile_order() {
+        let s = format!("{JOB}\njob \"after\" {{\n  source = \"asd\"\n  sink \"save\" {{\n    path = \"-\"\n  }}\n}}\n");
+        let f = JobFile::from_str(&s).unwrap();
+        assert_eq!(
+            vec!["cdg", "live", "after"],
+            f.job.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_jobfile_bad_version() {
+        let s = JOB.replace("version = 1", "version = 2");
+        assert!(JobFile::from_str(&s).is_err());
+    }
+
+    #[rstest]
+    #[case(r#"filter { since = 60 }"#, true)]
+    #[case(r#"filter { keyword = "icao24" }"#, false)]
+    #[case(r#"filter { begin = "2024-01-02T00:00:00Z" }"#, false)]
+    #[case(
+        r#"filter {
+        begin = "2024-01-02T00:00:00Z"
+        end   = "2024-01-01T00:00:00Z"
+    }"#,
+        false
+    )]
+    #[case(
+        r#"filter {
+        since   = 60
+        keyword = "icao24:abcdef"
+    }"#,
+        false
+    )]
+    #[case(r#"filter { window = "previous full hour" }"#, true)]
+    #[case(r#"filter { window = "next hour" }"#, false)]
+    #[case(
+        r#"filter {
+        window   = "yesterday"
+        timezone = "Europe/Paris"
+    }"#,
+        true
+    )]
+    #[case(
+        r#"filter {
+        window   = "yesterday"
+        timezone = "Mars/Olympus"
+    }"#,
+        false
+    )]
+    #[case(r#"filter { timezone = "Europe/Paris" }"#, false)]
+    #[case(
+        r#"filter {
+        since  = 60
+        window = "last 15m"
+    }"#,
+        false
+    )]
+    #[case(r#"into = "opensky""#, false)]
+    #[case(r#"into = "senhive""#, true)]
+    #[case(
+        r#"into = "senhive"
+    qc { drop = true }"#,
+        false
+    )]
+    #[case(r#"schedule { every = 0 }"#, false)]
+    #[case(
+        r#"schedule {
+        every = 60
+        probe = true
+        retry = 0
+    }"#,
+        false
+    )]
+    #[case(r#"category = ["uas", "rotorcraft"]"#, true)]
+    #[case(r#"category = ["plane"]"#, false)]
+    #[case(r#"qc { drop = true }"#, true)]
+    #[case(r#"tracks { max_jump = 2000 }"#, true)]
+    #[case(r#"qc { max_gaps = 30 }"#, false)]
+    #[case(r#"label { plans = "plans.csv" }"#, true)]
+    #[case(r#"label { window = 60 }"#, false)]
+    #[case(
+        r#"label {
+        plans  = "plans.csv"
+        window = -1
+    }"#,
+        false
+    )]
+    #[case(
+        r#"into = "senhive"
+    label { plans = "plans.csv" }"#,
+        false
+    )]
+    #[case(r#"spill { size = "500M" }"#, true)]
+    #[case(r#"spill { path = "/tmp/spill" }"#, true)]
+    #[case(r#"spill { size = "5%" }"#, false)]
+    #[case(r#"spill { size = "0" }"#, false)]
+    #[case(r#"tuning "convert" { workers = 4 }"#, true)]
+    #[case(r#"tuning "fetch" { capacity = 8 }"#, true)]
+    #[case(r#"tuning "fetch" { workers = 2 }"#, false)]
+    #[case(r#"tuning "convert" { capacity = 0 }"#, false)]
+    #[case(r#"tuning "foo" { capacity = 1 }"#, false)]
+    #[case(r#"tuning "convert" { threads = 1 }"#, false)]
+    #[case(r#"unknown = 1"#, false)]
+    fn test_jobspec_check(#[case] extra: &str, #[case] ok: bool) {
+        let s = format!(
+            "version = 1\njob \"j\" {{\n  source = \"asd\"\n  {extra}\n  sink \"save\" {{\n    path = \"-\"\n  }}\n}}\n"
+        );
+        assert_eq!(ok, JobFile::from_str(&s).is_ok());
+    }
+
+    #[test]
+    fn test_jobspec_filter_interval() {
+        let s = r#"
+version = 1
+job "j" {
+  source = "asd"
+  filter {
+    begin = "2024-01-01T00:00:00Z"
+    end   = "2024-01-02T00:00:00Z"
+  }
+  sink "store" {
+    path = "data"
+  }
+}
+"#;
+        let f = JobFile::from_str(s).unwrap();
+        let begin = DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z").unwrap();
+        let end = DateTime::parse_from_rfc3339("2024-01-02T00:00:00Z").unwrap();
+        assert_eq!(
+            Filter::interval(begin.to_utc(), end.to_utc()),
+            f.job["j"].filter(false)
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "last 15m",
+        "UTC",
+        "2024-03-01T00:10:00Z",
+        "2024-02-29T23:55:00Z",
+        "2024-03-01T00:10:00Z"
+    )]
+    #[case(
+        "yesterday",
+        "UTC",
+        "2024-03-01T00:10:00Z",
+        "2024-02-29T00:00:00Z",
+        "2024-03-01T00:00:00Z"
+    )]
+    #[case(
+        "yesterday",
+        "Europe/Paris",
+        "2024-10-28T06:00:00Z",
+        "2024-10-26T22:00:00Z",
+        "2024-10-27T23:00:00Z"
+    )]
+    #[case(
+        "previous full hour",
+        "Europe/Paris",
+        "2024-03-31T01:10:00Z",
+        "2024-03-31T00:00:00Z",
+        "2024-03-31T01:00:00Z"
+    )]
+    fn test_jobspec_filter_window(
+        #[case] window: &str,
+        #[case] tz: &str,
+        #[case] now: &str,
+        #[case] begin: &str,
+        #[case] end: &str,
+    ) {
+        let s = format!(
+            r#"
+version = 1
+job "j" {{
+  source = "asd"
+  filter {{
+    window   = "{window}"
+    timezone = "{tz}"
+  }}
+  sink "store" {{
+    path = "data"
+  }}
+}}
+"#
+        );
+        let f = JobFile::from_str(&s).unwrap();
+        let t = |s| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        assert_eq!(
+            Filter::interval(t(begin), t(end)),
+            f.job["j"].filter_at(false, t(now))
+        );
+    }
+
+    #[test]
+    fn test_jobspec_filter_window_moves() {
+        let s = r#"
+version = 1
+job "j" {
+  source = "asd"
+  filter {
+    window = "previous full hour"
+  }
+  schedule {
+    every = 3600
+  }
+  sink "store" {
+    path = "data"
+  }
+}
+"#;
+        // Every run of the schedule gets its own hour
+        //
+        let f = JobFile::from_str(s).unwrap();
+        let t = |s| DateTime::parse_from_rfc3339(s).unwrap().to_utc();
+        let first = f.job["j"].filter_at(false, t("2024-05-12T10:00:05Z"));
+        let second = f.job["j"].filter_at(false, t("2024-05-12T11:00:05Z"));
+        assert_eq!(
+            Filter::interval(t("2024-05-12T09:00:00Z"), t("2024-05-12T10:00:00Z")),
+            first
+        );
+        assert_eq!(
+            Filter::interval(t("2024-05-12T10:00:00Z"), t("2024-05-12T11:00:00Z")),
+            second
+        );
+    }
+
+    #[rstest]
+    #[case(
+        r#"path = "out.geoparquet"
+    trajectories = true"#,
+        true
+    )]
+    #[case(
+        r#"path = "out.parquet"
+    container = "geoparquet"
+    trajectories = true"#,
+        true
+    )]
+    #[case(
+        r#"path = "out.parquet"
+    trajectories = true"#,
+        false
+    )]
+    fn test_jobspec_check_trajectories(#[case] sink: &str, #[case] ok: bool) {
+        let s = format!(
+            "version = 1\njob \"j\" {{\n  source = \"asd\"\n  sink \"save\" {{\n    {sink}\n  }}\n}}\n"
+        );
+        assert_eq!(ok, JobFile::from_str(&s).is_ok());
+    }
+
+    #[rstest]
+    #[case(r#"layout = "tracks""#, "out.parquet", true)]
+    #[case(r#"layout = "points""#, "out.csv", true)]
+    #[case(r#"layout = "tracks""#, "out.geoparquet", false)]
+    #[case(r#"layout = "rows""#, "out.parquet", false)]
+    fn test_jobspec_check_layout(#[case] layout: &str, #[case] path: &str, #[case] ok: bool) {
+        let s = format!(
+            "version = 1\njob \"j\" {{\n  source = \"asd\"\n  sink \"save\" {{\n    path = \"{path}\"\n    {layout}\n  }}\n}}\n"
+        );
+        assert_eq!(ok, JobFile::from_str(&s).is_ok());
+    }
+
+    #[rstest]
+    #[case(
+        r#"sink "save" { path = "out.csv" }"#,
+        r#"to = "s3://b/{job_id}.csv""#,
+        true
+    )]
+    #[case(
+        r#"sink "save" { path = "-" }"#,
+        r#"to = "s3://b/{job_id}.csv""#,
+        false
+    )]
+    #[case(
+        r#"sink "save" { path = "out.csv" }"#,
+        r#"to = "s3://b/{day}.csv""#,
+        false
+    )]
+    #[case(
+        r#"sink "save" { path = "out.csv" }"#,
+        r#"to = "gs://b/out.csv""#,
+        false
+    )]
+    #[case(
+        r#"sink "save" { path = "out.csv" }"#,
+        r#"to = "/archive/{source}/{file}""#,
+        true
+    )]
+    #[case(
+        r#"sink "split" {
+    path = "out"
+    by   = "journey"
+  }"#,
+        r#"to = "s3://b/{date}""#,
+        false
+    )]
+    #[case(
+        r#"sink "store" { path = "out" }"#,
+        r#"to = "s3://b/{date}/{file}""#,
+        true
+    )]
+    #[case(
+        r#"sink "save" { path = "out.csv" }"#,
+        r#"to = "s3://b/{file}"
+    artifacts = ["qc"]"#,
+        false
+    )]
+    #[case(
+        r#"sink "save" { path = "out.csv" }
+  raw_copy = "raw""#,
+        r#"to = "s3://b/{file}"
+    artifacts = ["output", "raw"]
+    move = true"#,
+        true
+    )]
+    #[case(
+        r#"sink "save" { path = "out.csv" }"#,
+        r#"to = "s3://b/{file}"
+    artifacts = ["logs"]"#,
+        false
+    )]
+    fn test_jobspec_check_export(#[case] sink: &str, #[case] export: &str, #[case] ok: bool) {
+        let s = format!(
+            "version = 1\njob \"j\" {{\n  source = \"asd\"\n  {sink}\n  export {{\n    {export}\n  }}\n}}\n"
+        );
+        assert_eq!(ok, JobFile::from_str(&s).is_ok(), "{s}");
+    }
+
+    #[test]
+    fn test_jobspec_export() {
+        let s = r#"
+version = 1
+job "j" {
+  source   = "asd"
+  raw_copy = "raw"
+  sink "store" {
+    path = "data"
+  }
+  export {
+    to        = "s3://b/{file}"
+    artifacts = ["output", "raw"]
+    move      = true
+    region    = "eu-west-3"
+  }
+}
+"#;
+        let f = JobFile::from_str(s).unwrap();
+        let export = f.job["j"].export(42).unwrap();
+        assert_eq!(
+            vec![Path::new("data/42").to_path_buf(), PathBuf::from("raw")],
+            export.artifacts
+        );
+        assert!(export.remove);
+        assert_eq!(EXPORT_RETRIES, export.retries);
+        assert_eq!(Some("eu-west-3"), export.region.as_deref());
+    }
+
+    #[rstest]
+    #[case("simulator-live", true)]
+    #[case("simulator", false)]
+    fn test_create_job_broadcast(#[case] source: &str, #[case] res: bool) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut e = Engine::local(Some(dir.path().to_path_buf()))?;
+        let s = format!(
+            "version = 1\njob \"j\" {{\n  source = \"{source}\"\n  sink \"save\" {{\n    path = \"-\"\n  }}\n}}\n"
+        );
+        let f = JobFile::from_str(&s)?;
+
+        // Only with a hub
+        //
+        let job = e.create_job_from("j", &f.job["j"])?;
+        assert!(!job.list.iter().any(|t| t.name() == "Broadcast"));
+
+        e.live(Live::new());
+        let job = e.create_job_from("j", &f.job["j"])?;
+        assert_eq!(res, job.list.iter().any(|t| t.name() == "Broadcast"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_job_to_live_client() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut e = Engine::local(Some(dir.path().to_path_buf()))?;
+
+        // Streams pause when space is critical, not here
+        //
+        let cfg = SpaceConfig {
+            low: Threshold::Bytes(0),
+            critical: Threshold::Bytes(0),
+            ..SpaceConfig::default()
+        };
+        let paths = [dir.path().to_path_buf()];
+        e.space = Arc::new(SpaceMonitor::new(&cfg, &paths, Arc::clone(&e.subscribers)));
+
+        let live = Live::serve("127.0.0.1:0")?;
+        let addr = live.local_addr().unwrap();
+        e.live(live.clone());
+
+        let req = format!("ws://{addr}{LIVE_PATH}?source=simulator-live").into_client_request()?;
+        let tcp = TcpStream::connect(addr)?;
+        tcp.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let (mut ws, _) = tungstenite::client(req, tcp)?;
+        while live.clients() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let s = r#"
+version = 1
+job "j" {
+  source = "simulator-live"
+  into   = "cat21"
+  limits {
+    duration = 1
+    delay    = 100
+  }
+  sink "save" {
+    path = "-"
+  }
+}
+"#;
+        let f = JobFile::from_str(s)?;
+        let h = thread::spawn(move || {
+            let job = e.create_job_from("j", &f.job["j"])?;
+            e.run_job(job, &mut vec![])
+        });
+
+        // What the sink gets, one record per message
+        //
+        let msg = ws.read()?.into_text()?;
+        assert!(!msg.is_empty());
+        assert!(!msg.contains('\n'));
+        h.join().unwrap()?;
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r#"into = "cat21""#, r#"columns = "ids""#, true)]
+    #[case(r#"into = "cat21""#, r#"columns = "callsign, tod""#, true)]
+    #[case(r#"into = "cat21""#, r#"columns = "callsign,track_id""#, false)]
+    #[case(
+        r#"into = "cat21"
+  tracks {}"#,
+        r#"columns = "callsign,track_id""#,
+        true
+    )]
+    #[case(
+        r#"into     = "cat21"
+  provenance = true"#,
+        r#"columns = "callsign,prov_source""#,
+        true
+    )]
+    #[case("", r#"columns = "ids""#, false)]
+    #[case(r#"into = "senhive""#, r#"columns = "ids""#, false)]
+    #[case(
+        r#"into = "cat21""#,
+        r#"columns   = "ids"
+    container = "parquet""#,
+        false
+    )]
+    fn test_jobspec_check_columns(#[case] extra: &str, #[case] sink: &str, #[case] ok: bool) {
+        let s = format!(
+            "version = 1\njob \"j\" {{\n  source = \"asd\"\n  {extra}\n  sink \"save\" {{\n    path = \"out.csv\"\n    {sink}\n  }}\n}}\n"
+        );
+        assert_eq!(ok, JobFile::from_str(&s).is_ok());
+    }
+
+    #[test]
+    fn test_create_job_project() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut e = Engine::local(Some(dir.path().to_path_buf()))?;
+        e.live(Live::new());
+        let s = r#"
+version = 1
+job "j" {
+  source = "simulator-live"
+  into   = "cat21"
+  sink "save" {
+    path    = "-"
+    columns = "minimal"
+  }
+}
+"#;
+        let f = JobFile::from_str(s)?;
+
+        // Live clients still get the full records
+        //
+        let job = e.create_job_from("j", &f.job["j"])?;
+        let names = job.list.iter().map(|t| t.name()).collect::<Vec<_>>();
+        let n = names.len();
+        assert_eq!(["Broadcast", "Project", "Save"], names[n - 3..]);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("out.parquet", Container::Parquet)]
+    #[case("OUT.CSV", Container::CSV)]
+    #[case("out.geoparquet", Container::GeoParquet)]
+    #[case("-", Container::Raw)]
+    #[case("out.json", Container::Raw)]
+    fn test_container_from_path(#[case] path: &str, #[case] res: Container) {
+        assert_eq!(res, container_from_path(path));
+    }
+}
@@ -1,7 +1,7 @@
 //! Keeping state in Fetiche
 //!
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
 use std::path::PathBuf;
 
@@ -11,7 +11,17 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tracing::trace;
 
-use crate::{Engine, STATE_FILE};
+use fetiche_sources::GroupStats;
+
+use crate::{
+    Engine, JobInfo, JobProfile, Runtime, ScaleDecision, Skew, SpaceAlert, SpaceLevel, STATE_FILE,
+};
+
+/// Current version of the state file
+pub const STATE_VERSION: usize = 6;
+
+/// Number of failed jobs kept in the statistics
+const MAX_ERRORS: usize = 20;
 
 /// Register the state of the running `Engine`.
 ///
@@ -20,12 +30,185 @@ use crate::{Engine, STATE_FILE};
 ///
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct State {
+    /// File version, missing in older files
+    #[serde(default)]
+    pub version: usize,
     /// Timestamp
     pub tm: i64,
     /// Last job ID
     pub last: usize,
     /// Job Queue
     pub queue: VecDeque<usize>,
+    /// Statistics
+    #[serde(default)]
+    pub stats: Stats,
+    /// Per-job working directories still on disk
+    #[serde(default)]
+    pub workdirs: BTreeMap<usize, WorkDir>,
+    /// Name and status of the jobs in the queue
+    #[serde(default)]
+    pub jobs: BTreeMap<usize, JobInfo>,
+    /// Past runtimes per job name
+    #[serde(default)]
+    pub runtimes: BTreeMap<String, Runtime>,
+    /// Per-stage counters of the last jobs
+    #[serde(default)]
+    pub profiles: VecDeque<JobProfile>,
+}
+
+/// Status of a job working directory
+///
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum WorkStatus {
+    /// Job is running (or was aborted if the entry is old)
+    Running,
+    /// Job failed, directory kept for debugging
+    Failed,
+}
+
+/// A job working directory
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WorkDir {
+    /// Full path
+    pub path: PathBuf,
+    /// Current status
+    pub status: WorkStatus,
+    /// Last status change
+    pub tm: i64,
+}
+
+/// Engine statistics, kept across runs
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Stats {
+    /// Number of wall-clock jumps seen by the scheduler
+    pub skews: usize,
+    /// Largest one in ms
+    pub max_skew: i64,
+    /// Last one
+    pub last_skew: Option<Skew>,
+    /// Endpoints used by site groups
+    pub groups: BTreeMap<String, GroupStats>,
+    /// Number of times free space went low or critical
+    pub space_alerts: usize,
+    /// Last free space level change
+    pub last_space: Option<SpaceAlert>,
+    /// Number of jobs cancelled after their wall-clock limit
+    pub timeouts: usize,
+    /// Number of worker scaling decisions
+    pub scalings: usize,
+    /// Last one
+    pub last_scaling: Option<ScaleDecision>,
+    /// Records fetched per source
+    pub sources: BTreeMap<String, SourceStats>,
+    /// Last failed jobs, oldest first
+    pub errors: VecDeque<JobError>,
+}
+
+/// Records fetched from one source by finished jobs
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SourceStats {
+    /// Number of records
+    pub records: usize,
+    /// Time spent getting them, in seconds
+    pub seconds: u64,
+    /// Last job end
+    pub last: i64,
+}
+
+impl SourceStats {
+    /// Average records per second
+    ///
+    pub fn rate(&self) -> f64 {
+        match self.seconds {
+            0 => self.records as f64,
+            s => self.records as f64 / s as f64,
+        }
+    }
+}
+
+/// One failed job
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct JobError {
+    /// Job ID
+    pub id: usize,
+    /// Job name
+    pub name: String,
+    /// When it failed
+    pub at: i64,
+    /// What happened
+    pub error: String,
+}
+
+impl Stats {
+    /// Account for one skew event
+    ///
+    pub fn add_skew(&mut self, skew: &Skew) -> &mut Self {
+        self.skews += 1;
+        if skew.offset.abs() > self.max_skew.abs() {
+            self.max_skew = skew.offset;
+        }
+        self.last_skew = Some(*skew);
+        self
+    }
+
+    /// Account for one worker scaling decision
+    ///
+    pub fn add_scaling(&mut self, decision: ScaleDecision) -> &mut Self {
+        self.scalings += 1;
+        self.last_scaling = Some(decision);
+        self
+    }
+
+    /// Account for `records` fetched from `source` in `seconds`
+    ///
+    pub fn add_source(&mut self, source: &str, records: usize, seconds: u64) -> &mut Self {
+        let stats = self.sources.entry(source.to_string()).or_default();
+        stats.records += records;
+        stats.seconds += seconds;
+        stats.last = Utc::now().timestamp();
+        self
+    }
+
+    /// Keep a failed job, forgetting the oldest ones
+    ///
+    pub fn add_error(&mut self, error: JobError) -> &mut Self {
+        self.errors.push_back(error);
+        while self.errors.len() > MAX_ERRORS {
+            self.errors.pop_front();
+        }
+        self
+    }
+
+    /// Account for what site groups have done since the last call
+    ///
+    pub fn add_groups(&mut self, groups: BTreeMap<String, GroupStats>) -> &mut Self {
+        groups.into_iter().for_each(|(name, new)| {
+            let stats = self.groups.entry(name).or_default();
+            if new.served > 0 {
+                stats.endpoint = new.endpoint;
+            }
+            stats.served += new.served;
+            stats.failovers += new.failovers;
+        });
+        self
+    }
+
+    /// Account for free space level changes
+    ///
+    pub fn add_space(&mut self, alerts: Vec<SpaceAlert>) -> &mut Self {
+        self.space_alerts += alerts.iter().filter(|a| a.level != SpaceLevel::Ok).count();
+        if let Some(last) = alerts.into_iter().last() {
+            self.last_space = Some(last);
+        }
+        self
+    }
 }
 
 impl State {
@@ -33,9 +216,15 @@ impl State {
     ///
     pub fn new() -> Self {
         State {
+            version: STATE_VERSION,
             tm: Utc::now().timestamp(),
             last: 0,
             queue: VecDeque::<usize>::new(),
+            stats: Stats::default(),
+            workdirs: BTreeMap::new(),
+            jobs: BTreeMap::new(),
+            runtimes: BTreeMap::new(),
+            profiles: VecDeque::new(),
         }
     }
 
@@ -71,11 +260,11 @@ impl Default for State {
 }
 
 impl Engine {
-    /// Returns the path of the default state file in basedir
+    /// Returns the path of the default state file in the state directory
     ///
     #[inline]
     pub fn state_file(&self) -> PathBuf {
-        self.home.join(STATE_FILE)
+        self.state_dir.join(STATE_FILE)
     }
 
     /// Sync all state into a file
@@ -84,14 +273,46 @@ impl Engine {
     pub fn sync(&self) -> Result<()> {
         trace!("engine::sync");
         let mut data = self.state.write().unwrap();
+        data.stats.add_groups(self.sources.provenance().take());
+        data.stats.add_space(self.space.take());
         *data = State {
+            version: STATE_VERSION,
             tm: Utc::now().timestamp(),
             last: *data.queue.back().unwrap_or(&1),
             queue: data.queue.clone(),
+            stats: data.stats.clone(),
+            workdirs: data.workdirs.clone(),
+            jobs: data.jobs.clone(),
+            runtimes: data.runtimes.clone(),
+            profiles: data.profiles.clone(),
         };
         let data = json!(*data).to_string();
         Ok(fs::write(self.state_file(), data)?)
     }
+
+    /// Read the state file again to see what other processes sharing it are doing
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn reload_state(&self) -> Result<()> {
+        trace!("engine::reload_state");
+        let state = State::from(self.state_file())?;
+        *self.state.write().unwrap() = state;
+        Ok(())
+    }
+
+    /// Record a wall-clock jump seen by the scheduler
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn record_skew(&self, skew: &Skew) -> Result<()> {
+        trace!("engine::record_skew");
+        let mut state = self.state.write().unwrap();
+        state.stats.add_skew(skew);
+
+        // Ensure lock goes away
+        //
+        drop(state);
+        self.sync()
+    }
 }
 
 #[cfg(test)]
@@ -106,6 +327,60 @@ mod tests {
         assert!(s.queue.is_empty());
     }
 
+    #[test]
+    fn test_stats_add_groups() {
+        let mut s = Stats::default();
+        let run = |endpoint: &str, served, failovers| {
+            let g = GroupStats {
+                endpoint: endpoint.to_string(),
+                served,
+                failovers,
+            };
+            BTreeMap::from([("asd-any".to_string(), g)])
+        };
+
+        s.add_groups(run("https://eu", 1, 0))
+            .add_groups(run("https://us", 1, 1))
+            .add_groups(run("", 0, 1));
+        let g = &s.groups["asd-any"];
+        assert_eq!("https://us", g.endpoint);
+        assert_eq!(2, g.served);
+        assert_eq!(2, g.failovers);
+    }
+
+    #[test]
+    fn test_stats_add_space() {
+        let mut s = Stats::default();
+        let alert = |level| SpaceAlert {
+            level,
+            ..SpaceAlert::default()
+        };
+
+        s.add_space(vec![alert(SpaceLevel::Low), alert(SpaceLevel::Critical)])
+            .add_space(vec![])
+            .add_space(vec![alert(SpaceLevel::Ok)]);
+        assert_eq!(2, s.space_alerts);
+        assert_eq!(Some(alert(SpaceLevel::Ok)), s.last_space);
+    }
+
+    #[test]
+    fn test_stats_add_source_and_error() {
+        let mut s = Stats::default();
+
+        s.add_source("asd", 100, 10).add_source("asd", 50, 20);
+        assert_eq!(150, s.sources["asd"].records);
+        assert_eq!(5.0, s.sources["asd"].rate());
+
+        (0..MAX_ERRORS + 2).for_each(|id| {
+            s.add_error(JobError {
+                id,
+                ..JobError::default()
+            });
+        });
+        assert_eq!(MAX_ERRORS, s.errors.len());
+        assert_eq!(2, s.errors[0].id);
+    }
+
     #[test]
     fn test_state_remove() {
         let mut s = State::new();
@@ -118,4 +393,19 @@ mod tests {
         dbg!(&s.queue);
         assert!(s.queue.is_empty());
     }
+
+    #[test]
+    fn test_stats_add_skew() {
+        let mut s = State::new();
+        let at = Utc::now();
+
+        s.stats.add_skew(&Skew {
+            at,
+            offset: -60_000,
+        });
+        s.stats.add_skew(&Skew { at, offset: 5_500 });
+        assert_eq!(2, s.stats.skews);
+        assert_eq!(-60_000, s.stats.max_skew);
+        assert_eq!(Some(5_500), s.stats.last_skew.map(|s| s.offset));
+    }
 }
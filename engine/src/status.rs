@@ -0,0 +1,292 @@
+//! Status of the whole engine in one typed report.
+//!
+//! `Engine::status()` asks every subsystem where it stands: the job queue, the running jobs, the
+//! sources (sessions in use), the state file (last sync), the storage areas (disk usage and free
+//! space) and the statistics.  The report is what `acutectl status` displays and what a daemon
+//! would return to its clients, JSON output being the serialised `StatusReport`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+use fetiche_common::{Listing, OutputFormat};
+use fetiche_sources::Sessions;
+
+use crate::space::human;
+use crate::{Engine, JobStatus, QueuedJob, SpaceAlert, Stats, StoreArea, WorkStatus};
+
+/// Job queue
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SchedulerStatus {
+    /// Jobs waiting to be run
+    pub queued: usize,
+    /// Estimated end of the whole queue
+    pub eta: Option<i64>,
+}
+
+/// Jobs being run
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct WorkerStatus {
+    /// Workers, as of the last scaling decision
+    pub workers: usize,
+    /// Running jobs
+    pub busy: usize,
+    /// Working directories of failed jobs kept on disk
+    pub failed: usize,
+}
+
+/// Configured sources
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SourcesStatus {
+    /// Number of sites
+    pub sites: usize,
+    /// Number of site groups
+    pub groups: usize,
+    /// Sessions of the sites with a `max_concurrent` limit
+    pub sessions: BTreeMap<String, Sessions>,
+}
+
+/// State file
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StateStatus {
+    /// Last time the state was written
+    pub last_sync: i64,
+    /// Last job ID
+    pub last_job: usize,
+}
+
+/// One storage area
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct AreaStatus {
+    /// Type of area
+    pub kind: String,
+    /// Path or URL
+    pub location: String,
+    /// Bytes used by local areas
+    pub used: Option<u64>,
+}
+
+/// Everything at once
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct StatusReport {
+    /// When the report was made
+    pub at: i64,
+    /// Engine version
+    pub version: String,
+    /// Our PID
+    pub pid: u32,
+    /// Job queue
+    pub scheduler: SchedulerStatus,
+    /// Pending and running jobs, in order
+    pub jobs: Vec<QueuedJob>,
+    /// Running jobs
+    pub workers: WorkerStatus,
+    /// Sources
+    pub sources: SourcesStatus,
+    /// State file
+    pub state: StateStatus,
+    /// Storage areas by name
+    pub storage: BTreeMap<String, AreaStatus>,
+    /// Free space of the fullest filesystem
+    pub space: SpaceAlert,
+    /// Statistics
+    pub stats: Stats,
+}
+
+impl Engine {
+    /// Gather the status of all subsystems
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn status(&self) -> StatusReport {
+        trace!("engine status");
+
+        let queue = self.queue();
+        let (stats, state, failed) = {
+            let state = self.state.read().unwrap();
+            let failed = state
+                .workdirs
+                .values()
+                .filter(|wd| wd.status == WorkStatus::Failed)
+                .count();
+            let st = StateStatus {
+                last_sync: state.tm,
+                last_job: state.last,
+            };
+            (state.stats.clone(), st, failed)
+        };
+
+        let storage = self
+            .storage
+            .iter()
+            .map(|(name, area)| {
+                let status = match area {
+                    StoreArea::Cache { url } => AreaStatus {
+                        kind: area.to_string(),
+                        location: url.clone(),
+                        used: None,
+                    },
+                    StoreArea::Directory { path, .. } | StoreArea::Hive { path } => AreaStatus {
+                        kind: area.to_string(),
+                        location: path.to_string_lossy().to_string(),
+                        used: Some(usage(path)),
+                    },
+                };
+                (name.clone(), status)
+            })
+            .collect();
+
+        StatusReport {
+            at: Utc::now().timestamp(),
+            version: self.version(),
+            pid: self.pid,
+            scheduler: SchedulerStatus {
+                queued: queue
+                    .iter()
+                    .filter(|q| q.status == JobStatus::Queued)
+                    .count(),
+                eta: queue.last().and_then(|q| q.eta),
+            },
+            workers: WorkerStatus {
+                workers: self.scaler.workers(stats.last_scaling.as_ref()),
+                busy: queue
+                    .iter()
+                    .filter(|q| q.status == JobStatus::Running)
+                    .count(),
+                failed,
+            },
+            sources: SourcesStatus {
+                sites: self.sources.len(),
+                groups: self.sources.groups(),
+                sessions: self.sources.sessions(),
+            },
+            jobs: queue,
+            state,
+            storage,
+            space: self.space.check(),
+            stats,
+        }
+    }
+
+    /// Return the status of all subsystems, JSON is the whole `StatusReport`.
+    ///
+    pub fn show_status(&self, fmt: OutputFormat) -> Result<String> {
+        let report = self.status();
+        if fmt == OutputFormat::Json {
+            return Ok(serde_json::to_string_pretty(&report)?);
+        }
+
+        let time = |t: i64| {
+            DateTime::from_timestamp(t, 0)
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_default()
+        };
+        let mut list = Listing::new("Engine status", &[("Name", "name"), ("Value", "value")]);
+        let mut row = |name: &str, value: String| {
+            list.push(vec![name.to_string(), value]);
+        };
+
+        row("version", report.version.clone());
+        row("pid", report.pid.to_string());
+        row("queued", report.scheduler.queued.to_string());
+        row(
+            "queue_eta",
+            report.scheduler.eta.map(time).unwrap_or_default(),
+        );
+        row("workers", report.workers.workers.to_string());
+        row("busy", report.workers.busy.to_string());
+        row("failed_workdirs", report.workers.failed.to_string());
+        row("sites", report.sources.sites.to_string());
+        row("groups", report.sources.groups.to_string());
+        report.sources.sessions.iter().for_each(|(name, s)| {
+            row(
+                &format!("sessions:{}", name),
+                format!("{}/{}", s.used, s.max),
+            );
+        });
+        row("last_sync", time(report.state.last_sync));
+        row("last_job", report.state.last_job.to_string());
+        report.storage.iter().for_each(|(name, a)| {
+            let used = a
+                .used
+                .map(|u| format!(" ({})", human(u)))
+                .unwrap_or_default();
+            row(
+                &format!("storage:{}", name),
+                format!("{} {}{}", a.kind, a.location, used),
+            );
+        });
+        row("space", report.space.to_string());
+        row("skews", report.stats.skews.to_string());
+        row("space_alerts", report.stats.space_alerts.to_string());
+        row("timeouts", report.stats.timeouts.to_string());
+        list.render(fmt)
+    }
+}
+
+/// Bytes used by the files under `path`
+///
+fn usage(path: &Path) -> u64 {
+    let mut total = 0;
+    let mut dirs: Vec<PathBuf> = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            match entry.metadata() {
+                Ok(m) if m.is_dir() => dirs.push(entry.path()),
+                Ok(m) => total += m.len(),
+                Err(_) => (),
+            }
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("a/b"))?;
+        fs::write(dir.path().join("top"), "12345")?;
+        fs::write(dir.path().join("a/b/deep"), "123")?;
+        assert_eq!(8, usage(dir.path()));
+        assert_eq!(0, usage(&dir.path().join("missing")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_report_json() -> Result<()> {
+        let mut report = StatusReport::default();
+        report
+            .sources
+            .sessions
+            .insert("asd".to_string(), Sessions { used: 1, max: 1 });
+        let v = serde_json::to_value(&report)?;
+        assert_eq!(1, v["sources"]["sessions"]["asd"]["used"]);
+        assert!(v["scheduler"]["eta"].is_null());
+
+        // What remote clients get back
+        //
+        let back: StatusReport = serde_json::from_value(v)?;
+        assert_eq!(report, back);
+        Ok(())
+    }
+}
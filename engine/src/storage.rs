@@ -1,7 +1,9 @@
+use std::collections::btree_map::Iter;
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use eyre::Result;
+use fetiche_common::{Listing, OutputFormat};
 use nom::{
     character::complete::{i8, one_of},
     combinator::map,
@@ -9,8 +11,6 @@ use nom::{
     IResult,
 };
 use strum::EnumString;
-use tabled::builder::Builder;
-use tabled::settings::Style;
 use tracing::{debug, trace};
 
 use crate::StorageConfig;
@@ -85,34 +85,35 @@ impl Storage {
         Storage(b)
     }
 
-    pub fn list(&self) -> Result<String> {
-        let header = vec!["Name", "Path/URL", "Rotation"];
-
-        let mut builder = Builder::default();
-        builder.push_record(header);
+    pub fn list(&self, fmt: OutputFormat) -> Result<String> {
+        let mut list = Listing::new(
+            "List all storage areas",
+            &[
+                ("Name", "name"),
+                ("Path/URL", "path"),
+                ("Rotation", "rotation"),
+            ],
+        );
 
         self.0.iter().for_each(|(n, s)| {
-            let mut row = vec![];
-            let name = n.clone();
-            let area = s.clone();
-            row.push(name);
-            match area {
-                StoreArea::Cache { url } => row.push(url),
-                StoreArea::Directory { path, rotation } => {
-                    let path = path.to_string_lossy();
-                    row.push(path.to_string());
-                    row.push(format!("{}s", rotation));
-                }
-                StoreArea::Hive { path } => {
-                    let path = path.to_string_lossy();
-                    row.push(path.to_string());
-                }
+            let row = match s {
+                StoreArea::Cache { url } => vec![n.clone(), url.clone()],
+                StoreArea::Directory { path, rotation } => vec![
+                    n.clone(),
+                    path.to_string_lossy().to_string(),
+                    format!("{}s", rotation),
+                ],
+                StoreArea::Hive { path } => vec![n.clone(), path.to_string_lossy().to_string()],
             };
-            builder.push_record(row);
+            list.push(row);
         });
-        let allc = builder.build().with(Style::modern()).to_string();
-        let str = format!("List all storage areas:\n{allc}");
-        Ok(str)
+        list.render(fmt)
+    }
+
+    /// Iterate over all areas
+    ///
+    pub fn iter(&self) -> Iter<'_, String, StoreArea> {
+        self.0.iter()
     }
 
     /// Return the number of storage areas
@@ -129,7 +130,7 @@ impl Storage {
 
     /// Parse 1s/1m/1h/1d
     ///
-    fn parse_rotation(input: &str) -> IResult<&str, u32> {
+    pub(crate) fn parse_rotation(input: &str) -> IResult<&str, u32> {
         let into_s = |(n, tag): (std::primitive::i8, char)| match tag {
             's' => n as u32,
             'm' => (n as u32) * 60,
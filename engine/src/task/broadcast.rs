@@ -0,0 +1,74 @@
+//! `Broadcast` publishes everything going through it into the `Live` hub of the engine and passes
+//! it down unchanged, clients of the WebSocket endpoint get the records of every stream job.
+//!
+//! It is added by `Engine::create_job_from()` in front of the sink of stream jobs once the engine
+//! has a hub (see `Engine::live()`).
+//!
+
+use std::sync::mpsc::Sender;
+
+use eyre::Result;
+use tracing::trace;
+
+use fetiche_formats::Format;
+use fetiche_macros::RunnableDerive;
+
+use crate::{Live, Runnable, IO};
+
+/// The Broadcast task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Broadcast {
+    /// I/O capabilities
+    io: IO,
+    /// Where the records come from
+    source: String,
+    /// Format of the records
+    format: Format,
+    /// Hub of the engine
+    live: Live,
+}
+
+impl Broadcast {
+    #[tracing::instrument(skip(live))]
+    pub fn new(live: &Live, source: &str, format: Format) -> Self {
+        trace!("broadcast::new");
+        Broadcast {
+            io: IO::Filter,
+            source: source.to_string(),
+            format,
+            live: live.clone(),
+        }
+    }
+
+    /// Publish then pass the data down, slow clients never hold the pipeline back.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("broadcast::execute");
+        self.live.publish(&self.source, self.format, &data);
+        Ok(stdout.send(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use crate::LiveFilter;
+
+    use super::*;
+
+    #[test]
+    fn test_broadcast() -> Result<()> {
+        let live = Live::new();
+        let client = live.subscribe(LiveFilter::default());
+
+        let (tx, rx) = channel();
+        let data = "8:200:393:49.6116:6.2061:393\n".to_string();
+        Broadcast::new(&live, "asd", Format::Cat21).execute(data.clone(), tx)?;
+        assert_eq!(data, rx.recv()?);
+        assert_eq!("8:200:393:49.6116:6.2061:393", client.recv()?);
+        Ok(())
+    }
+}
@@ -3,6 +3,11 @@
 
 version = 1
 
+cmds "broadcast" {
+  type        = "Filter"
+  description = "Publish the records of stream jobs to the live WebSocket clients."
+}
+
 cmds "convert" {
   type        = "Filter"
   description = "Convert between the various formats into Cat21."
@@ -18,6 +23,16 @@ cmds "fetch" {
   description = "Fetch a single piece of data from a Source."
 }
 
+cmds "geojson" {
+  type        = "Filter"
+  description = "Turn records into GeoJSON features, one per line, for live maps."
+}
+
+cmds "label" {
+  type        = "Filter"
+  description = "Join records with flight plans (CSV or NM B2B) by callsign and time, adding flight_id and route."
+}
+
 cmds "message" {
   type        = "Filter"
   description = "Insert a message in the pipeline."
@@ -28,16 +43,66 @@ cmds "nothing" {
   description = "As the name implies, NOP."
 }
 
+cmds "project" {
+  type        = "Filter"
+  description = "Keep some columns of Cat21 records (full, minimal, ids or a list of names)."
+}
+
+cmds "qc" {
+  type        = "Filter"
+  description = "Check records (range, altitude spikes, time going backwards, gaps), write a summary and drop bad ones if asked."
+}
+
+cmds "rawcopy" {
+  type        = "Filter"
+  description = "Write every chunk untouched into its own timestamped file and pass it along."
+}
+
+cmds "rawdump" {
+  type        = "Consumer"
+  description = "Capture every chunk verbatim, with time, sequence and length, into files rotated on size or age."
+}
+
 cmds "read" {
   type        = "Producer"
   description = "Read a block of data from a local file."
 }
 
+cmds "replay" {
+  type        = "Producer"
+  description = "Send payloads captured by rawdump or rawcopy again, with their original timing sped up or not."
+}
+
 cmds "save" {
   type        = "Consumer"
   description = "Save into a single file, with possible a format change."
 }
 
+cmds "select" {
+  type        = "Filter"
+  description = "Keep the records of some vehicle categories (uas, rotorcraft, fixed-wing...)."
+}
+
+cmds "serve" {
+  type        = "Consumer"
+  description = "Send data to all TCP clients connected to an address, e.g. SBS-1 on port 30003."
+}
+
+cmds "postgis" {
+  type        = "Consumer"
+  description = "Write positions as PointZ (and optionally trajectories) into PostGIS (feature postgis)."
+}
+
+cmds "spill" {
+  type        = "Consumer"
+  description = "Wrap a sink, spilling batches to disk while it does not keep up and sending them when it recovers."
+}
+
+cmds "split" {
+  type        = "Consumer"
+  description = "Split the incoming data into one file per key (icao24, callsign, journey) with a manifest."
+}
+
 cmds "store" {
   type        = "Consumer"
   description = "Split the incoming data into different files in a StorageArea."
@@ -52,3 +117,8 @@ cmds "tee" {
   type        = "Filter"
   description = "Like the tee(1) commands, save a copy of incoming data into a file."
 }
+
+cmds "track" {
+  type        = "Filter"
+  description = "Add a track_id to every record, from the source or split on gaps and jumps."
+}
@@ -1,20 +1,50 @@
 //! Module handling the conversions between different formats
 //!
 //! Currently supported:
-//! - Input: Asd, Opensky
-//! - Output: Cat21
+//! - Input: Asd, Opensky, Utm, Sbs1, Flightaware, Cat048, Senhive, AvionixCube
+//! - Output: Cat21, Sbs1, Senhive
+//!
+//! Cat048 radar plots are placed with the position of the radar, see `radar()`.
+//!
+//! Senhive and Avionix messages are decoded whatever the revision of their schema, messages we
+//! cannot decode are handled according to the `strictness` of the site (see `Schema`).
+//!
+//! SBS-1 output is generated from Cat21, SBS-1 input is merged per aircraft across the whole
+//! stream as a position is spread over several messages.  Senhive fusion tracks are written
+//! straight from Asd and through Cat21 for the others.
+//!
+//! This is also where redaction policies are enforced: whatever the sink, data going through
+//! a `Convert` task with a policy is redacted, with or without a conversion (`into` left to
+//! `Format::None` means raw data is passed through).
+//!
+//! Provenance columns are added here as well, after redaction (see `provenance.rs`).
+//!
+//! Cat21 output has no header, unless `header()` is set: it is then written before the first
+//! records, for writers needing the column names like `write_xlsx()`.
+//!
+//! With more than one worker (see `Tuning`), input with one record per line (everything but
+//! SBS-1 and Cat048) is cut into batches of `batch` records converted in parallel, the results
+//! are sent in the original order.
 //!
 
+use std::fmt::Debug;
 use std::sync::mpsc::Sender;
+use std::thread;
 
 use eyre::Result;
-use serde_json::json;
+use serde::Serialize;
 use tracing::trace;
 
-use fetiche_formats::{prepare_csv, Cat21, Format, StateList};
+use fetiche_common::Redaction;
+use fetiche_formats::{
+    prepare_csv, Cat048, Cat21, Format, Radar, Sbs1, Sbs1Tracks, Senhive, StateList, Strictness,
+};
 use fetiche_macros::RunnableDerive;
 
-use crate::{Runnable, IO};
+use crate::{
+    record_drop, records, DropReason, EngineStatus, Metrics, Provenance, Runnable, Tuning,
+    DEF_BATCH, IO,
+};
 
 pub trait ConvertInto {
     fn convert(&self, into: Format) -> String;
@@ -25,6 +55,22 @@ pub struct Convert {
     io: IO,
     pub from: Format,
     pub into: Format,
+    /// Redaction policy applied on output
+    pub redact: Option<Redaction>,
+    /// Provenance stamped on output
+    pub provenance: Option<Provenance>,
+    /// Radar position and day for Cat048 input
+    pub radar: Option<Radar>,
+    /// What to do with vendor messages we cannot decode
+    pub strictness: Strictness,
+    /// Last known values for each aircraft for SBS-1 input
+    tracks: Sbs1Tracks,
+    /// Threads converting in parallel
+    pub workers: usize,
+    /// Records per batch for each worker
+    pub batch: usize,
+    /// Write the Cat21 header before the first records
+    pub header: bool,
 }
 
 impl Convert {
@@ -35,6 +81,14 @@ impl Convert {
             io: IO::Filter,
             from: Format::None,
             into: Format::None,
+            redact: None,
+            provenance: None,
+            radar: None,
+            strictness: Strictness::default(),
+            tracks: Sbs1Tracks::new(),
+            workers: 1,
+            batch: DEF_BATCH,
+            header: false,
         }
     }
 
@@ -50,6 +104,143 @@ impl Convert {
         self
     }
 
+    #[inline]
+    pub fn redact(&mut self, redact: Redaction) -> &mut Self {
+        self.redact = Some(redact);
+        self
+    }
+
+    #[inline]
+    pub fn provenance(&mut self, provenance: Provenance) -> &mut Self {
+        self.provenance = Some(provenance);
+        self
+    }
+
+    #[inline]
+    pub fn radar(&mut self, radar: Radar) -> &mut Self {
+        self.radar = Some(radar);
+        self
+    }
+
+    #[inline]
+    pub fn strictness(&mut self, strictness: Strictness) -> &mut Self {
+        self.strictness = strictness;
+        self
+    }
+
+    #[inline]
+    pub fn header(&mut self, header: bool) -> &mut Self {
+        self.header = header;
+        self
+    }
+
+    /// Workers and batch size from the stage tuning
+    ///
+    pub fn tuning(&mut self, tuning: &Tuning) -> &mut Self {
+        self.workers = tuning.workers().max(1);
+        self.batch = tuning.batch().max(1);
+        self
+    }
+
+    /// Can the input be cut into batches?  SBS-1 needs the whole stream, Cat048 is not one
+    /// record per line.
+    ///
+    fn splittable(&self) -> bool {
+        !matches!(self.from, Format::Sbs1 | Format::Cat048 | Format::None)
+    }
+
+    /// Serialise converted records, redacted and stamped if needed.  We need the header to know
+    /// which fields to redact but the next stage does not want it, unless `header` is set.
+    ///
+    fn output<T>(&self, data: Vec<T>, header: bool) -> Result<String>
+    where
+        T: Serialize + Debug,
+    {
+        if self.redact.is_none() && self.provenance.is_none() {
+            return prepare_csv(data, header);
+        }
+        let mut csv = prepare_csv(data, true)?;
+        if let Some(redact) = &self.redact {
+            csv = redact.csv(&csv, b':', header || self.provenance.is_some())?;
+        }
+        match &self.provenance {
+            Some(provenance) => provenance.csv(&csv, b':', header),
+            None => Ok(csv),
+        }
+    }
+
+    /// Convert input from the previous stage into Cat21 records.
+    ///
+    fn cat21(&mut self, data: &str) -> Result<Vec<Cat21>> {
+        let res = match self.from {
+            Format::Opensky => {
+                trace!("opensky:json to cat21: {}", data);
+
+                // One `StateList` per line when streaming
+                //
+                data.lines()
+                    .filter(|l| !l.trim().is_empty())
+                    .map(StateList::from_json)
+                    .collect::<Result<Vec<_>>>()?
+                    .iter()
+                    .flat_map(|sl| sl.to_cat21())
+                    .collect()
+            }
+            Format::Asd => {
+                trace!("asd:json to cat21: {}", data);
+
+                Cat21::from_asd(data)?
+            }
+            Format::Utm => {
+                trace!("utm:json to cat21: {}", data);
+
+                Cat21::from_utm(data)?
+            }
+            Format::NmB2b => {
+                trace!("nmb2b:json to cat21: {}", data);
+
+                Cat21::from_nmb2b(data)?
+            }
+            Format::Sbs1 => {
+                trace!("sbs1 to cat21: {}", data);
+
+                self.tracks.feed(data)
+            }
+            Format::Cat048 => {
+                trace!("cat048 to cat21: {}", data);
+
+                let Some(radar) = &self.radar else {
+                    return Err(EngineStatus::NoRadar.into());
+                };
+                radar.to_cat21(&Cat048::from_text(data)?)
+            }
+            Format::Senhive => {
+                trace!("senhive:json to cat21: {}", data);
+
+                Cat21::from_senhive(data, self.strictness)?
+            }
+            Format::AvionixCube => {
+                trace!("cube:json to cat21: {}", data);
+
+                Cat21::from_avionix_cube(data, self.strictness)?
+            }
+            #[cfg(feature = "flightaware")]
+            Format::Flightaware => {
+                trace!("flightaware:json to cat21: {}", data);
+
+                Cat21::from_flightaware(data)?
+            }
+            _ => unimplemented!(),
+        };
+        if matches!(
+            self.from,
+            Format::Asd | Format::Utm | Format::Flightaware | Format::Senhive | Format::AvionixCube
+        ) {
+            parse_drops(data, res.len());
+        }
+        Ok(res)
+    }
+
     /// This is the task here, converting between format from the previous stage
     /// of the pipeline and send it down to the next stage.
     ///
@@ -57,44 +248,352 @@ impl Convert {
     pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
         trace!("into::execute");
 
+        if self.workers > 1 && self.splittable() {
+            let batches = batches(&data, self.batch);
+            if batches.len() > 1 {
+                return self.parallel(batches, stdout);
+            }
+        }
+        let res = self.convert(data)?;
+        Ok(stdout.send(res)?)
+    }
+
+    /// Convert all batches with our workers, each one taking every `workers`-th batch.
+    ///
+    fn parallel(&mut self, batches: Vec<String>, stdout: Sender<String>) -> Result<()> {
+        let workers = self.workers.min(batches.len());
+        trace!("{} batches for {} workers", batches.len(), workers);
+
+        let stage = Metrics::current();
+        let done = thread::scope(|s| {
+            let handles = (0..workers)
+                .map(|w| {
+                    let mut conv = self.clone();
+
+                    // Batch 0 is the first one of worker 0
+                    //
+                    conv.header = self.header && w == 0;
+                    let stage = stage.clone();
+                    let mine = batches
+                        .iter()
+                        .skip(w)
+                        .step_by(workers)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    s.spawn(move || {
+                        if let Some(stage) = stage {
+                            stage.enter();
+                        }
+                        mine.into_iter()
+                            .map(|b| conv.convert(b))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("convert worker"))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        self.header = false;
+
+        // Batch `i` is the `i / workers`-th one of worker `i % workers`
+        //
+        let mut done = done.into_iter().map(|v| v.into_iter()).collect::<Vec<_>>();
+        for i in 0..batches.len() {
+            if let Some(res) = done[i % workers].next() {
+                stdout.send(res)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert one batch
+    ///
+    fn convert(&mut self, data: String) -> Result<String> {
         // Bow out early
         //
         let res = match self.into {
             Format::Cat21 => {
-                let res: Vec<_> = match self.from {
-                    Format::Opensky => {
-                        trace!("opensky:json to cat21: {}", data);
-
-                        let data: StateList = serde_json::from_str(&data)?;
-                        trace!("data={:?}", data);
-                        let data = json!(&data.states).to_string();
-                        trace!("data={}", data);
-                        Cat21::from_opensky(&data)?
-                    }
+                let res = self.cat21(&data)?;
+                let header = self.header && !res.is_empty();
+                if header {
+                    self.header = false;
+                }
+                self.output(res, header)?
+            }
+            // There is nothing to redact in SBS-1, do it on the input
+            //
+            Format::Sbs1 => {
+                let data = match (&self.redact, self.from) {
+                    (Some(redact), from) if from != Format::Sbs1 => redact.json(&data)?,
+                    _ => data,
+                };
+                let res = self.cat21(&data)?;
+                Sbs1::write(&res)
+            }
+            Format::Senhive => {
+                let data = match (&self.redact, self.from) {
+                    (Some(redact), from) if from != Format::Sbs1 => redact.json(&data)?,
+                    _ => data,
+                };
+                let res = match self.from {
                     Format::Asd => {
-                        trace!("asd:json to cat21: {}", data);
-
-                        Cat21::from_asd(&data)?
-                    }
-                    #[cfg(feature = "flightaware")]
-                    Format::Flightaware => {
-                        trace!("flightaware:json to cat21: {}", data);
-
-                        Cat21::from_flightaware(&data)?
+                        let res = Senhive::from_asd(&data)?;
+                        parse_drops(&data, res.len());
+                        res
                     }
-                    _ => unimplemented!(),
+                    from => self
+                        .cat21(&data)?
+                        .iter()
+                        .map(|rec| Senhive::from_cat21(rec, from))
+                        .collect(),
                 };
-                prepare_csv(res, false)?
+                let res = Senhive::write(&res)?;
+                match &self.provenance {
+                    Some(provenance) => provenance.json(&res)?,
+                    None => res,
+                }
+            }
+            // No conversion, only redaction of the raw data
+            //
+            Format::None => {
+                let data = match &self.redact {
+                    Some(redact) => redact.json(&data)?,
+                    None => data,
+                };
+                match &self.provenance {
+                    Some(provenance) => {
+                        let delim = match self.from {
+                            Format::Cat21 => b':',
+                            _ => b',',
+                        };
+                        provenance.raw(&data, delim)?
+                    }
+                    None => data,
+                }
             }
             _ => unimplemented!(),
         };
-
-        Ok(stdout.send(res)?)
+        Ok(res)
     }
 }
 
+/// Cut `data` into batches of `size` non-empty lines.
+///
+fn batches(data: &str, size: usize) -> Vec<String> {
+    let lines = data
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .collect::<Vec<_>>();
+    lines.chunks(size).map(|c| c.join("\n")).collect()
+}
+
+/// JSON lines which could not be parsed are silently skipped by the converters, count them.
+///
+fn parse_drops(data: &str, parsed: usize) {
+    record_drop(DropReason::Parse, records(data).saturating_sub(parsed));
+}
+
 impl Default for Convert {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    const ASD: &str = r##"{"ident":"1581F5FJD239C00DW22E","home_lat":49.61,"latitude":49.6116}"##;
+
+    #[test]
+    fn test_convert_passthrough() -> Result<()> {
+        let (tx, rx) = channel();
+        Convert::new()
+            .from(Format::Asd)
+            .execute(ASD.to_string(), tx)?;
+        assert_eq!(ASD, rx.recv()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_redact_only() -> Result<()> {
+        let mut r = Redaction::new();
+        r.truncate_serial(6).drop_pilot_location(true);
+
+        let (tx, rx) = channel();
+        Convert::new()
+            .from(Format::Asd)
+            .redact(r)
+            .execute(ASD.to_string(), tx)?;
+        assert_eq!(
+            r##"{"home_lat":null,"ident":"1581F5","latitude":49.6116}"##,
+            rx.recv()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_provenance_only() -> Result<()> {
+        let (tx, rx) = channel();
+        Convert::new()
+            .from(Format::Asd)
+            .provenance(Provenance::new("asd", "https://example.net", 3))
+            .execute(ASD.to_string(), tx)?;
+        let out: serde_json::Value = serde_json::from_str(&rx.recv()?)?;
+        assert_eq!("asd", out["prov_source"]);
+        assert_eq!(3, out["prov_job"]);
+        assert_eq!(49.6116, out["latitude"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_sbs1_roundtrip() -> Result<()> {
+        let sbs1 = "MSG,4,1,1,4CA2D6,1,2024/05/12,10:30:14.500,2024/05/12,10:30:14.500,,,420,87.5,,,,,,,,0\r
+MSG,3,1,1,4CA2D6,1,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250,,36000,,,49.61160,6.20610,,,0,0,0,0\r
+";
+        let (tx, rx) = channel();
+        let mut convert = Convert::new();
+        convert.from(Format::Sbs1).into(Format::Sbs1);
+
+        // Velocity and position in different batches are merged
+        //
+        let (first, second) = sbs1.split_at(sbs1.find("MSG,3").unwrap());
+        convert.execute(first.to_string(), tx.clone())?;
+        convert.execute(second.to_string(), tx)?;
+        assert_eq!("", rx.recv()?);
+        let out = rx.recv()?;
+        assert!(out.starts_with(
+            "MSG,4,1,1,4CA2D6,1,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250,,,420,87.5,,,,,,,,\r\n"
+        ));
+        assert_eq!(2, out.lines().count());
+        Ok(())
+    }
+
+    const ASD_FULL: &str = r##"{"journey":42,"ident":"1581F5FJD239C00DW22E","model":null,"source":"wi","location":1,"timestamp":"2024-05-12 10:30:15","latitude":"49.6116","longitude":"6.2061","altitude":120,"elevation":null,"home_lat":null,"home_lon":null,"speed":36.0,"heading":87.5,"station_latitude":null,"station_longitude":null}"##;
+
+    #[test]
+    fn test_convert_workers() -> Result<()> {
+        let data = (0..5)
+            .map(|i| ASD_FULL.replace("49.6116", &format!("49.611{i}")))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let (tx, rx) = channel();
+        Convert::new()
+            .from(Format::Asd)
+            .into(Format::Cat21)
+            .execute(data.clone(), tx)?;
+        let serial = rx.recv()?;
+
+        let (tx, rx) = channel();
+        Convert::new()
+            .from(Format::Asd)
+            .into(Format::Cat21)
+            .tuning(&Tuning {
+                workers: Some(3),
+                batch: Some(2),
+                ..Tuning::default()
+            })
+            .execute(data, tx)?;
+        let parallel = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(3, parallel.len());
+        assert_eq!(
+            serial.lines().collect::<Vec<_>>(),
+            parallel.iter().flat_map(|b| b.lines()).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_header() -> Result<()> {
+        let data = (0..5).map(|_| ASD_FULL).collect::<Vec<_>>().join("\n");
+
+        let (tx, rx) = channel();
+        let mut convert = Convert::new();
+        convert
+            .from(Format::Asd)
+            .into(Format::Cat21)
+            .header(true)
+            .tuning(&Tuning {
+                workers: Some(3),
+                batch: Some(2),
+                ..Tuning::default()
+            });
+        convert.execute(data, tx.clone())?;
+        convert.execute(ASD_FULL.to_string(), tx)?;
+
+        // Only once, before the first record
+        //
+        let out = rx.try_iter().collect::<String>();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(7, lines.len());
+        assert!(lines[0].contains("REC_TIME_POSIX"));
+        assert!(lines[1..].iter().all(|l| !l.contains("REC_TIME_POSIX")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_senhive() -> Result<()> {
+        let (tx, rx) = channel();
+        Convert::new()
+            .from(Format::Asd)
+            .into(Format::Senhive)
+            .execute(ASD_FULL.to_string(), tx)?;
+        let out = rx.recv()?;
+        assert_eq!(1, out.lines().count());
+        assert!(out.contains(r#""serialNumber":"1581F5FJD239C00DW22E""#));
+        assert!(out.contains(r#""sources":["remote_id"]"#));
+        Ok(())
+    }
+
+    /// One Mode-S plot, 10 NM east of the radar at FL100
+    ///
+    const CAT048: &str = "300022fdd4082a465000a00a0040000e0001904ca2d64994b42d882004d207773e39\n";
+
+    #[test]
+    fn test_convert_cat048() -> Result<()> {
+        let mut convert = Convert::new();
+        convert.from(Format::Cat048).into(Format::Cat21);
+
+        let (tx, _rx) = channel();
+        let err = convert.execute(CAT048.to_string(), tx).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::NoRadar)
+        ));
+
+        let (tx, rx) = channel();
+        convert
+            .radar("49.0,2.5".parse().unwrap())
+            .execute(CAT048.to_string(), tx)?;
+        let out = rx.recv()?;
+        assert_eq!(1, out.lines().count());
+        assert!(out.contains(":5022422:48:"));
+        assert!(out.contains("RYR4KX"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_senhive_strictness() -> Result<()> {
+        let input = r##"{"schema":"fusion/1.0","trackId":"4CA2D6","timestamp":"2024-05-12T10:30:15Z","position":{"latitude":49.5,"longitude":6.25}}
+{"schema":"fusion/2.0","track":{"id":"T43"}}"##;
+
+        let mut convert = Convert::new();
+        convert.from(Format::Senhive).into(Format::Cat21);
+
+        let (tx, rx) = channel();
+        convert.execute(input.to_string(), tx)?;
+        assert_eq!(1, rx.recv()?.lines().count());
+
+        let (tx, _rx) = channel();
+        assert!(convert
+            .strictness(Strictness::Fail)
+            .execute(input.to_string(), tx)
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+//! `RawDump` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This is a consumer capturing every chunk received from upstream verbatim, with a small framing
+//! header, into a series of files rotated on size or age.  It is what `acutectl raw` uses to keep
+//! a trace of what a site really sends, unparsed, for debugging or replay.
+//!
+//! Each frame is a header line followed by the payload and a newline:
+//!
+//! ```text
+//! #FRAME 2024-01-01T12:00:00.123Z 1 42
+//! <42 bytes of payload>
+//! ```
+//!
+//! with the time it was received (UTC, ms), a sequence number and the length in bytes of the
+//! payload.  Files are named after the prefix and the time they were opened, e.g.
+//! `opensky-20240101T120000Z.raw`.
+//!
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use eyre::Result;
+use tracing::{debug, trace};
+
+use fetiche_macros::RunnableDerive;
+
+use crate::{EngineStatus, Runnable, IO};
+
+/// Marker at the start of every frame header
+const FRAME: &str = "#FRAME";
+
+/// Default size before rotating, 64 MB
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Default age before rotating, one hour
+const MAX_AGE: i64 = 3_600;
+
+/// The RawDump task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct RawDump {
+    /// I/O capabilities
+    io: IO,
+    /// Prefix of every file, usually the site name
+    pub name: String,
+    /// Output directory
+    pub path: PathBuf,
+    /// Rotate when the current file is larger than this, in bytes (0 means never)
+    pub max_size: u64,
+    /// Rotate when the current file is older than this, in seconds (0 means never)
+    pub max_age: i64,
+    /// Number of chunks written so far
+    pub seq: usize,
+    /// Current file, its size and when it was opened
+    current: Option<(PathBuf, u64, DateTime<Utc>)>,
+}
+
+/// One captured chunk, as read back by `read_frames()`.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// When it was received
+    pub time: DateTime<Utc>,
+    /// Sequence number
+    pub seq: usize,
+    /// Untouched payload
+    pub data: String,
+}
+
+impl RawDump {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str) -> Self {
+        trace!("New RawDump {}", name);
+        RawDump {
+            io: IO::Consumer,
+            name: name.to_owned(),
+            path: PathBuf::from("."),
+            max_size: MAX_SIZE,
+            max_age: MAX_AGE,
+            seq: 0,
+            current: None,
+        }
+    }
+
+    /// Set the output directory
+    ///
+    pub fn path(&mut self, name: &str) -> &mut Self {
+        trace!("Add path: {}", name);
+        self.path = PathBuf::from(name);
+        self
+    }
+
+    /// Set the size in bytes after which we rotate
+    ///
+    pub fn max_size(&mut self, size: u64) -> &mut Self {
+        self.max_size = size;
+        self
+    }
+
+    /// Set the age in seconds after which we rotate
+    ///
+    pub fn max_age(&mut self, age: i64) -> &mut Self {
+        self.max_age = age;
+        self
+    }
+
+    /// Write the chunk as one frame, rotating first if needed.
+    ///
+    #[tracing::instrument(skip(self, data, _stdout))]
+    pub fn execute(&mut self, data: String, _stdout: Sender<String>) -> Result<()> {
+        trace!("RawDump::execute()");
+
+        self.write_frame(Utc::now(), &data)
+    }
+
+    /// Append one frame received at `tm` into the current file.
+    ///
+    fn write_frame(&mut self, tm: DateTime<Utc>, data: &str) -> Result<()> {
+        if !self.path.exists() {
+            fs::create_dir_all(&self.path)
+                .map_err(|_| EngineStatus::CreateDir(self.path.to_string_lossy().to_string()))?;
+        }
+
+        let (fname, size, opened) = match self.current.take() {
+            Some((fname, size, opened)) if !self.must_rotate(size, opened, tm) => {
+                (fname, size, opened)
+            }
+            _ => {
+                let fname = self.path.join(dump_name(&self.name, tm));
+                debug!("Rotating into {:?}", fname);
+                (fname, 0, tm)
+            }
+        };
+
+        self.seq += 1;
+        let frame = frame(tm, self.seq, data);
+        let mut fh = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&fname)?;
+        fh.write_all(frame.as_bytes())?;
+
+        self.current = Some((fname, size + frame.len() as u64, opened));
+        Ok(())
+    }
+
+    /// Is the current file full or too old?
+    ///
+    fn must_rotate(&self, size: u64, opened: DateTime<Utc>, tm: DateTime<Utc>) -> bool {
+        (self.max_size != 0 && size >= self.max_size)
+            || (self.max_age != 0 && (tm - opened).num_seconds() >= self.max_age)
+    }
+}
+
+impl Default for RawDump {
+    fn default() -> Self {
+        RawDump::new("default")
+    }
+}
+
+/// Name of a new capture file.
+///
+fn dump_name(prefix: &str, tm: DateTime<Utc>) -> String {
+    format!("{}-{}.raw", prefix, tm.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// Header, payload and trailing newline.
+///
+fn frame(tm: DateTime<Utc>, seq: usize, data: &str) -> String {
+    format!(
+        "{} {} {} {}\n{}\n",
+        FRAME,
+        tm.to_rfc3339_opts(SecondsFormat::Millis, true),
+        seq,
+        data.len(),
+        data
+    )
+}
+
+/// Read back all the frames of a capture file, in order.
+///
+#[tracing::instrument]
+pub fn read_frames(fname: &Path) -> Result<Vec<Frame>> {
+    trace!("read_frames");
+
+    let name = fname.to_string_lossy().to_string();
+    let buf = fs::read_to_string(fname)?;
+
+    let mut frames = vec![];
+    let mut rest = buf.as_str();
+    while !rest.is_empty() {
+        let bad = || EngineStatus::BadFrame(name.clone(), frames.len() + 1);
+
+        let (header, body) = rest.split_once('\n').ok_or_else(bad)?;
+        let v: Vec<_> = header.split(' ').collect();
+        let [FRAME, time, seq, len] = v[..] else {
+            return Err(bad().into());
+        };
+        let time = DateTime::parse_from_rfc3339(time)
+            .map_err(|_| bad())?
+            .with_timezone(&Utc);
+        let seq = seq.parse::<usize>().map_err(|_| bad())?;
+        let len = len.parse::<usize>().map_err(|_| bad())?;
+
+        // Payload is followed by a newline
+        //
+        if body.len() < len + 1 || !body.is_char_boundary(len) || &body[len..len + 1] != "\n" {
+            return Err(bad().into());
+        }
+        frames.push(Frame {
+            time,
+            seq,
+            data: body[..len].to_string(),
+        });
+        rest = &body[len + 1..];
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use chrono::{Duration, TimeZone};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn files(dir: &Path) -> Vec<PathBuf> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_frame() {
+        let tm = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            "#FRAME 2024-01-01T12:00:00.000Z 3 5\nhello\n",
+            frame(tm, 3, "hello")
+        );
+        assert_eq!("opensky-20240101T120000Z.raw", dump_name("opensky", tm));
+    }
+
+    #[test]
+    fn test_rawdump_roundtrip() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("raw");
+        let (tx, _rx) = channel();
+
+        // Payloads with newlines and what looks like a header are kept as-is
+        //
+        let data = ["{\"a\":1}\n{\"a\":2}", "", "#FRAME x 1 2\nfoo"];
+
+        let mut dump = RawDump::new("opensky");
+        dump.path(&out.to_string_lossy());
+        for d in data {
+            dump.execute(d.to_string(), tx.clone()).unwrap();
+        }
+
+        let files = files(&out);
+        assert_eq!(1, files.len());
+        let frames = read_frames(&files[0]).unwrap();
+        assert_eq!(3, frames.len());
+        for (i, f) in frames.iter().enumerate() {
+            assert_eq!(i + 1, f.seq);
+            assert_eq!(data[i], f.data);
+        }
+    }
+
+    #[test]
+    fn test_rawdump_rotate() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("raw");
+        let tm = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        let mut dump = RawDump::new("asd");
+        dump.path(&out.to_string_lossy()).max_size(60).max_age(10);
+
+        // First file gets full after two frames
+        //
+        dump.write_frame(tm, "0123456789").unwrap();
+        dump.write_frame(tm + Duration::seconds(1), "0123456789")
+            .unwrap();
+        dump.write_frame(tm + Duration::seconds(2), "x").unwrap();
+        // Then too old
+        //
+        dump.write_frame(tm + Duration::seconds(12), "y").unwrap();
+
+        let files = files(&out);
+        assert_eq!(3, files.len());
+        assert!(files[0].ends_with("asd-20240101T120000Z.raw"));
+        assert!(files[1].ends_with("asd-20240101T120002Z.raw"));
+        assert!(files[2].ends_with("asd-20240101T120012Z.raw"));
+        assert_eq!(2, read_frames(&files[0]).unwrap().len());
+        assert_eq!(3, read_frames(&files[1]).unwrap()[0].seq);
+    }
+
+    #[test]
+    fn test_read_frames_truncated() {
+        let dir = tempdir().unwrap();
+        let fname = dir.path().join("bad.raw");
+        fs::write(&fname, "#FRAME 2024-01-01T12:00:00.000Z 1 10\nshort\n").unwrap();
+
+        assert!(read_frames(&fname).is_err());
+    }
+}
@@ -1,16 +1,22 @@
 //! `Fetch` is a `Runnable` task as defined in the `engine`  crate.
 //!
+//! Sites exporting more than one topic (ASD) can fetch several of them for the same filter in
+//! one go with `topics()`: positions go down the pipe as usual and every other topic is written
+//! into its own `<site>-<topic>.csv` file.
+//!
 
-use std::sync::mpsc::Sender;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
 
 use eyre::Result;
-use tracing::trace;
+use tracing::{debug, info, trace};
 
 use fetiche_macros::RunnableDerive;
 use fetiche_sources::{AuthError, Filter, Flow, Site, Sources};
 
-use crate::{EngineStatus, Runnable, IO};
+use crate::{cancelled, with_token, EngineStatus, Runnable, TokenRefresher, IO};
 
 /// The Fetch task
 ///
@@ -26,8 +32,17 @@ pub struct Fetch {
     pub site: Option<String>,
     /// Optional arguments (usually json-encoded string)
     pub args: String,
+    /// Get a new token when the site rejects ours
+    pub tokens: Option<Arc<TokenRefresher>>,
+    /// Topics to fetch, only positions if empty
+    pub topics: Vec<String>,
+    /// Where the other topics are written
+    pub dir: PathBuf,
 }
 
+/// The topic sent down the pipe
+pub const POSITIONS: &str = "positions";
+
 impl Fetch {
     #[tracing::instrument(skip(srcs))]
     pub fn new(s: &str, srcs: Arc<Sources>) -> Self {
@@ -37,6 +52,9 @@ impl Fetch {
             args: String::new(),
             site: None,
             srcs: srcs.clone(),
+            tokens: None,
+            topics: vec![],
+            dir: PathBuf::from("."),
         }
     }
     /// Copy the site's data
@@ -55,6 +73,29 @@ impl Fetch {
         self
     }
 
+    /// Refresh the token when rejected
+    ///
+    pub fn tokens(&mut self, tokens: Arc<TokenRefresher>) -> &mut Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Fetch these topics, the ones other than positions go into `dir`
+    ///
+    pub fn topics(&mut self, topics: &[String], dir: &str) -> &mut Self {
+        trace!("Add topics {:?} into {}", topics, dir);
+        self.topics = topics.to_vec();
+        self.dir = PathBuf::from(dir);
+        self
+    }
+
+    /// Name of the file for a given topic
+    ///
+    pub fn artifact(&self, topic: &str) -> PathBuf {
+        let site = self.site.as_deref().unwrap_or(&self.name);
+        self.dir.join(format!("{site}-{topic}.csv"))
+    }
+
     /// The heart of the matter: fetch data
     ///
     #[tracing::instrument(skip(self))]
@@ -65,6 +106,15 @@ impl Fetch {
         //
         match &self.site {
             Some(site) => {
+                // Wait for our turn if the site limits concurrent sessions, the permit is
+                // released at the end of this block.  A cancelled or timed out job stops waiting.
+                //
+                let Some(_permit) = self.srcs.acquire(site, cancelled) else {
+                    debug!("fetch cancelled while waiting for {}", site);
+                    return Ok(());
+                };
+
+                let name = site;
                 let site = Site::load(site, &self.srcs)?;
                 if let Flow::Fetchable(site) = site {
                     let token = site.authenticate();
@@ -79,7 +129,44 @@ impl Fetch {
                         },
                         Ok(token) => token,
                     };
-                    site.fetch(stdout, &token, &self.args)?;
+                    if self.topics.is_empty() {
+                        with_token(self.tokens.as_deref(), name, token, |token| {
+                            site.fetch(stdout.clone(), token, &self.args)
+                        })?;
+                        return Ok(());
+                    }
+
+                    // Everything is kept until all topics are there, a retry with a new token
+                    // would duplicate the positions already sent otherwise.
+                    //
+                    let mut received = vec![];
+                    with_token(self.tokens.as_deref(), name, token, |token| {
+                        let (outs, rxs): (Vec<_>, Vec<_>) = self
+                            .topics
+                            .iter()
+                            .map(|t| {
+                                let (tx, rx) = channel();
+                                ((t.clone(), tx), rx)
+                            })
+                            .unzip();
+                        site.fetch_topics(outs, token, &self.args)?;
+                        received = rxs;
+                        Ok(())
+                    })?;
+
+                    if !self.topics.iter().all(|t| t == POSITIONS) {
+                        fs::create_dir_all(&self.dir)?;
+                    }
+                    for (topic, rx) in self.topics.iter().zip(received) {
+                        let data = rx.try_iter().collect::<Vec<_>>().concat();
+                        if topic == POSITIONS {
+                            stdout.send(data)?;
+                        } else {
+                            let fname = self.artifact(topic);
+                            info!("{} written into {:?}", topic, fname);
+                            fs::write(fname, data)?;
+                        }
+                    }
                 }
             }
             None => return Err(EngineStatus::NoSiteDefined.into()),
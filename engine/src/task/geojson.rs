@@ -0,0 +1,174 @@
+//! `GeoJson` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This filter turns `Asd` or `Cat21` CSV records into GeoJSON features, one per line
+//! (newline-delimited GeoJSON), for live maps:
+//!
+//! - `geometry` is a `Point` with longitude, latitude and altitude if there is one,
+//! - `id` is the track (see `read_tracks()` in `layout.rs` for which column is used),
+//! - `properties` has all the columns of the record, empty ones being left out.
+//!
+//! Records without a position are dropped.  With a `Serve` in SSE mode after it, a Leaflet page
+//! can follow the tracks directly.
+//!
+
+use std::sync::mpsc::Sender;
+
+use csv::ReaderBuilder;
+use eyre::Result;
+use serde_json::{json, Map, Value};
+use tracing::{debug, trace};
+
+use fetiche_formats::{Format, TRACK_ID};
+use fetiche_macros::RunnableDerive;
+
+use crate::{EngineStatus, Runnable, IO};
+
+/// The GeoJson task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct GeoJson {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Input file format
+    pub inp: Format,
+}
+
+impl GeoJson {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format) -> Self {
+        trace!("New GeoJson {}", name);
+        GeoJson {
+            io: IO::Filter,
+            name: name.to_owned(),
+            inp,
+        }
+    }
+
+    /// Send one feature per line for every record with a position.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("GeoJson::execute()");
+
+        let rule = self
+            .inp
+            .track_rule()
+            .ok_or(EngineStatus::UnsupportedGeoJson(self.inp.to_string()))?;
+
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(rule.delimiter)
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let header = rdr.headers()?.clone();
+        let idx = |name: &str| header.iter().position(|h| h == name);
+        let col = |name: &str| idx(name).ok_or(EngineStatus::NoGeoJsonColumn(name.to_string()));
+
+        let vehicle = col(rule.vehicle)?;
+        let track = idx(TRACK_ID)
+            .or(rule.track.and_then(idx))
+            .unwrap_or(vehicle);
+        let (lat, lon) = (col(rule.latitude)?, col(rule.longitude)?);
+        let alt = rule.altitude.and_then(idx);
+
+        let mut features = vec![];
+        for rec in rdr.records() {
+            let rec = rec?;
+            let get = |i: usize| rec.get(i).unwrap_or_default().trim();
+            let num = |i: usize| get(i).parse::<f64>().ok();
+
+            let (Some(lat), Some(lon)) = (num(lat), num(lon)) else {
+                continue;
+            };
+            let coordinates = match alt.and_then(num) {
+                Some(alt) => json!([lon, lat, alt]),
+                None => json!([lon, lat]),
+            };
+            let properties = header
+                .iter()
+                .zip(rec.iter())
+                .filter(|(_, v)| !v.trim().is_empty())
+                .map(|(k, v)| (k.to_string(), Value::from(v.trim())))
+                .collect::<Map<_, _>>();
+            let feature = json!({
+                "type": "Feature",
+                "id": get(track),
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": coordinates,
+                },
+                "properties": properties,
+            });
+            features.push(serde_json::to_string(&feature)?);
+        }
+        debug!("{} features", features.len());
+
+        if features.is_empty() {
+            return Ok(());
+        }
+        features.push(String::new());
+        Ok(stdout.send(features.join("\n"))?)
+    }
+}
+
+impl Default for GeoJson {
+    fn default() -> Self {
+        GeoJson::new("default", Format::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    fn run(data: &str, fmt: Format) -> Result<Vec<Value>> {
+        let (tx, rx) = channel();
+        GeoJson::new("test", fmt).execute(data.to_string(), tx)?;
+        Ok(rx
+            .try_recv()
+            .unwrap_or_default()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn test_geojson_asd() -> Result<()> {
+        let data = "journey,ident,timestamp,latitude,longitude,altitude,model
+12,ABC,2024-05-12 10:00:00,49.0,2.0,120,
+13,DEF,2024-05-12 10:00:01,,2.1,100,Mavic
+14,GHI,2024-05-12 10:00:02,49.2,2.2,,Mini
+";
+        let res = run(data, Format::Asd)?;
+        assert_eq!(2, res.len());
+
+        assert_eq!("Feature", res[0]["type"]);
+        assert_eq!("12", res[0]["id"]);
+        assert_eq!(json!([2.0, 49.0, 120.0]), res[0]["geometry"]["coordinates"]);
+        assert_eq!("ABC", res[0]["properties"]["ident"]);
+        assert!(res[0]["properties"].get("model").is_none());
+
+        assert_eq!(json!([2.2, 49.2]), res[1]["geometry"]["coordinates"]);
+        assert_eq!("Mini", res[1]["properties"]["model"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_geojson_track_id_and_empty() -> Result<()> {
+        let data = "TARGET_ADDR:REC_TIME_POSIX:POS_LAT_DEG:POS_LONG_DEG:track_id
+4CA2D6:1000:49.0:2.0:4CA2D6-1000
+";
+        let res = run(data, Format::Cat21)?;
+        assert_eq!("4CA2D6-1000", res[0]["id"]);
+
+        let header = "TARGET_ADDR:REC_TIME_POSIX:POS_LAT_DEG:POS_LONG_DEG\n";
+        assert!(run(header, Format::Cat21)?.is_empty());
+        assert!(run("a,b\n", Format::None).is_err());
+        Ok(())
+    }
+}
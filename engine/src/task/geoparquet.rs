@@ -0,0 +1,451 @@
+//! GeoParquet 1.1 output for `Save`.
+//!
+//! Positions keep all their original columns and get two more:
+//!
+//! - `geometry`, the position as a WKB `Point` (longitude first),
+//! - `bbox`, the `xmin`/`ymin`/`xmax`/`ymax` covering column used by readers to skip row groups.
+//!
+//! Trajectories are one WKB `LineString` per track, ordered by time, with the track ID, vehicle,
+//! first and last times and number of points.  The track is the `track_id` column added by the
+//! `Track` task if there is one, then the format's own identifier (ASD `journey`) and finally the
+//! vehicle (see `read_tracks()` in `layout.rs`).  Provenance columns, if any, are those of the
+//! first record.
+//!
+//! The `geo` metadata in the footer describes the geometry column with an explicit `OGC:CRS84`
+//! CRS and the bounding box of the whole file, so GDAL, GeoPandas or QGIS open these files as-is.
+//!
+//! See <https://geoparquet.org/releases/v1.1.0/>.
+//!
+
+use std::fs::File;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{
+    ArrayRef, AsArray, BinaryBuilder, Float64Builder, RecordBatch, StringArray, StructArray,
+    TimestampSecondArray, UInt32Array,
+};
+use datafusion::arrow::buffer::NullBuffer;
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Float64Type, Schema, TimeUnit};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::{Compression, ZstdLevel};
+use datafusion::parquet::file::metadata::KeyValue;
+use datafusion::parquet::file::properties::WriterProperties;
+use datafusion::prelude::{CsvReadOptions, SessionContext};
+use eyre::Result;
+use serde_json::{json, Value};
+use tracing::{debug, trace};
+
+use fetiche_formats::{TrackRule, TRACK_ID};
+
+use super::layout::read_tracks;
+use super::provenance::stamp_columns;
+use crate::EngineStatus;
+
+/// GeoParquet specification we follow
+const GEOPARQUET_VERSION: &str = "1.1.0";
+/// Name of the geometry column
+const GEOMETRY: &str = "geometry";
+/// Name of the covering column
+const BBOX: &str = "bbox";
+/// WKB little-endian marker
+const WKB_LE: u8 = 1;
+/// WKB geometry types
+const WKB_POINT: u32 = 1;
+const WKB_LINESTRING: u32 = 2;
+
+/// Read the CSV file `from` and write its records into `to` with a `Point` each.
+///
+#[tracing::instrument(skip(rule))]
+pub(crate) async fn write_points(from: &str, to: &str, rule: &TrackRule) -> Result<()> {
+    let ctx = SessionContext::new();
+    let df = ctx
+        .read_csv(
+            from,
+            CsvReadOptions::default()
+                .has_header(true)
+                .delimiter(rule.delimiter),
+        )
+        .await?;
+    let schema = df.schema().as_arrow().clone();
+    let lat = schema
+        .index_of(rule.latitude)
+        .map_err(|_| EngineStatus::NoGeoParquetColumn(rule.latitude.to_string()))?;
+    let lon = schema
+        .index_of(rule.longitude)
+        .map_err(|_| EngineStatus::NoGeoParquetColumn(rule.longitude.to_string()))?;
+
+    let mut fields = schema.fields().to_vec();
+    fields.push(Arc::new(Field::new(GEOMETRY, DataType::Binary, true)));
+    fields.push(Arc::new(Field::new(BBOX, bbox_type(), true)));
+    let schema = Arc::new(Schema::new_with_metadata(fields, schema.metadata().clone()));
+
+    let mut total = Bounds::default();
+    let mut batches = vec![];
+    for batch in df.collect().await? {
+        let lat = cast(batch.column(lat), &DataType::Float64)?;
+        let lon = cast(batch.column(lon), &DataType::Float64)?;
+        let (lat, lon) = (
+            lat.as_primitive::<Float64Type>(),
+            lon.as_primitive::<Float64Type>(),
+        );
+
+        let mut geometry = BinaryBuilder::new();
+        let mut bbox = Covering::default();
+        for (lat, lon) in lat.iter().zip(lon.iter()) {
+            match (lat, lon) {
+                (Some(lat), Some(lon)) => {
+                    geometry.append_value(wkb_point(lon, lat));
+                    let b = Bounds::from_points(&[(lon, lat)]);
+                    bbox.push(&b);
+                    total.extend(&b);
+                }
+                _ => {
+                    geometry.append_null();
+                    bbox.push(&Bounds::default());
+                }
+            }
+        }
+
+        let mut columns = batch.columns().to_vec();
+        columns.push(Arc::new(geometry.finish()));
+        columns.push(Arc::new(bbox.finish()?));
+        batches.push(RecordBatch::try_new(schema.clone(), columns)?);
+    }
+    debug!("{} batches, bbox {:?}", batches.len(), total);
+
+    write_geoparquet(to, schema, &batches, "Point", &total)
+}
+
+/// Build one `LineString` per track from the CSV `data` and write them into `to`.
+///
+#[tracing::instrument(skip(data, rule))]
+pub(crate) fn write_trajectories(data: &str, to: &str, rule: &TrackRule) -> Result<()> {
+    let tracks = read_tracks(data, rule)?;
+
+    let (mut ids, mut vehicles, mut starts, mut ends, mut counts) =
+        (vec![], vec![], vec![], vec![], vec![]);
+    let mut geometry = BinaryBuilder::new();
+    let mut bbox = Covering::default();
+    let mut total = Bounds::default();
+    for (id, (v, points)) in tracks {
+        // A line needs two points
+        //
+        if points.len() < 2 {
+            continue;
+        }
+
+        let line = points.iter().map(|p| (p.lon, p.lat)).collect::<Vec<_>>();
+        let b = Bounds::from_points(&line);
+        geometry.append_value(wkb_linestring(&line));
+        bbox.push(&b);
+        total.extend(&b);
+
+        ids.push(id);
+        vehicles.push(v);
+        starts.push(points[0].tm);
+        ends.push(points[points.len() - 1].tm);
+        counts.push(points.len() as u32);
+    }
+    trace!("{} trajectories", ids.len());
+    let rows = ids.len();
+
+    let time = DataType::Timestamp(TimeUnit::Second, Some("UTC".into()));
+    let mut fields = vec![
+        Field::new(TRACK_ID, DataType::Utf8, false),
+        Field::new("vehicle", DataType::Utf8, false),
+        Field::new("start_time", time.clone(), false),
+        Field::new("end_time", time, false),
+        Field::new("points", DataType::UInt32, false),
+        Field::new(GEOMETRY, DataType::Binary, true),
+        Field::new(BBOX, bbox_type(), true),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(ids)),
+        Arc::new(StringArray::from(vehicles)),
+        Arc::new(TimestampSecondArray::from(starts).with_timezone("UTC")),
+        Arc::new(TimestampSecondArray::from(ends).with_timezone("UTC")),
+        Arc::new(UInt32Array::from(counts)),
+        Arc::new(geometry.finish()),
+        Arc::new(bbox.finish()?),
+    ];
+    let (f, c) = stamp_columns(data, rule.delimiter, rows);
+    fields.extend(f);
+    columns.extend(c);
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    write_geoparquet(to, schema, &[batch], "LineString", &total)
+}
+
+/// Write all batches with the `geo` metadata.
+///
+fn write_geoparquet(
+    to: &str,
+    schema: Arc<Schema>,
+    batches: &[RecordBatch],
+    gtype: &str,
+    bbox: &Bounds,
+) -> Result<()> {
+    let props = WriterProperties::builder()
+        .set_created_by("acutectl/save".to_string())
+        .set_compression(Compression::ZSTD(ZstdLevel::try_new(8)?))
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            "geo".to_string(),
+            geo_metadata(gtype, bbox).to_string(),
+        )]))
+        .build();
+
+    let mut writer = ArrowWriter::try_new(File::create(to)?, schema, Some(props))?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// The `geo` file metadata, see the GeoParquet specification.
+///
+fn geo_metadata(gtype: &str, bbox: &Bounds) -> Value {
+    let crs: Value = serde_json::from_str(include_str!("crs84.json")).unwrap_or_default();
+    let mut column = json!({
+        "encoding": "WKB",
+        "geometry_types": [gtype],
+        "crs": crs,
+        "covering": {
+            "bbox": {
+                "xmin": [BBOX, "xmin"],
+                "ymin": [BBOX, "ymin"],
+                "xmax": [BBOX, "xmax"],
+                "ymax": [BBOX, "ymax"],
+            }
+        }
+    });
+    if let Some(b) = bbox.0 {
+        column["bbox"] = json!(b);
+    }
+    json!({
+        "version": GEOPARQUET_VERSION,
+        "primary_column": GEOMETRY,
+        "columns": { GEOMETRY: column },
+    })
+}
+
+/// Type of the `bbox` column
+///
+fn bbox_type() -> DataType {
+    DataType::Struct(bbox_fields())
+}
+
+fn bbox_fields() -> Fields {
+    ["xmin", "ymin", "xmax", "ymax"]
+        .into_iter()
+        .map(|n| Field::new(n, DataType::Float64, false))
+        .collect()
+}
+
+/// `[xmin, ymin, xmax, ymax]` if there is anything inside
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Bounds(Option<[f64; 4]>);
+
+impl Bounds {
+    fn from_points(points: &[(f64, f64)]) -> Self {
+        let mut b = Bounds::default();
+        points
+            .iter()
+            .for_each(|&(x, y)| b.extend(&Bounds(Some([x, y, x, y]))));
+        b
+    }
+
+    fn extend(&mut self, other: &Bounds) {
+        self.0 = match (self.0, other.0) {
+            (Some(a), Some(b)) => Some([
+                a[0].min(b[0]),
+                a[1].min(b[1]),
+                a[2].max(b[2]),
+                a[3].max(b[3]),
+            ]),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// Builder for the `bbox` column, one `Bounds` per row.
+///
+#[derive(Debug, Default)]
+struct Covering {
+    coords: [Float64Builder; 4],
+    valid: Vec<bool>,
+}
+
+impl Covering {
+    fn push(&mut self, b: &Bounds) {
+        let v = b.0.unwrap_or_default();
+        self.coords
+            .iter_mut()
+            .zip(v)
+            .for_each(|(c, v)| c.append_value(v));
+        self.valid.push(b.0.is_some());
+    }
+
+    fn finish(mut self) -> Result<StructArray> {
+        let arrays = self
+            .coords
+            .iter_mut()
+            .map(|c| Arc::new(c.finish()) as ArrayRef)
+            .collect::<Vec<_>>();
+        let nulls = NullBuffer::from(self.valid);
+        let nulls = (nulls.null_count() > 0).then_some(nulls);
+        Ok(StructArray::try_new(bbox_fields(), arrays, nulls)?)
+    }
+}
+
+/// WKB `Point`
+///
+fn wkb_point(x: f64, y: f64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(21);
+    buf.push(WKB_LE);
+    buf.extend(WKB_POINT.to_le_bytes());
+    buf.extend(x.to_le_bytes());
+    buf.extend(y.to_le_bytes());
+    buf
+}
+
+/// WKB `LineString`
+///
+fn wkb_linestring(points: &[(f64, f64)]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(9 + 16 * points.len());
+    buf.push(WKB_LE);
+    buf.extend(WKB_LINESTRING.to_le_bytes());
+    buf.extend((points.len() as u32).to_le_bytes());
+    for (x, y) in points {
+        buf.extend(x.to_le_bytes());
+        buf.extend(y.to_le_bytes());
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+    use tempfile::tempdir;
+
+    use fetiche_formats::Format;
+
+    use super::*;
+
+    /// `geo` metadata of a Parquet file
+    ///
+    fn geo_of(path: &std::path::Path) -> Result<Value> {
+        let rdr = SerializedFileReader::new(File::open(path)?)?;
+        let kv = rdr
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .cloned()
+            .unwrap_or_default();
+        let geo = kv.iter().find(|kv| kv.key == "geo").unwrap();
+        Ok(serde_json::from_str(geo.value.as_deref().unwrap())?)
+    }
+
+    #[test]
+    fn test_wkb() {
+        let p = wkb_point(2.0, 49.0);
+        assert_eq!(21, p.len());
+        assert_eq!(&[1, 1, 0, 0, 0], &p[..5]);
+        assert_eq!(2.0f64.to_le_bytes(), p[5..13]);
+
+        let l = wkb_linestring(&[(2.0, 49.0), (2.1, 49.1)]);
+        assert_eq!(9 + 32, l.len());
+        assert_eq!(&[1, 2, 0, 0, 0, 2, 0, 0, 0], &l[..9]);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut b = Bounds::from_points(&[(2.0, 49.0), (2.5, 48.0)]);
+        assert_eq!(Some([2.0, 48.0, 2.5, 49.0]), b.0);
+        b.extend(&Bounds::default());
+        assert_eq!(Some([2.0, 48.0, 2.5, 49.0]), b.0);
+        assert_eq!(None, Bounds::from_points(&[]).0);
+    }
+
+    #[test]
+    fn test_geo_metadata() {
+        let v = geo_metadata("Point", &Bounds(Some([1., 2., 3., 4.])));
+        assert_eq!("1.1.0", v["version"]);
+        assert_eq!("geometry", v["primary_column"]);
+        let col = &v["columns"]["geometry"];
+        assert_eq!("WKB", col["encoding"]);
+        assert_eq!("CRS84", col["crs"]["id"]["code"]);
+        assert_eq!(json!([1., 2., 3., 4.]), col["bbox"]);
+        assert_eq!(json!(["bbox", "xmin"]), col["covering"]["bbox"]["xmin"]);
+        assert!(geo_metadata("Point", &Bounds::default())["columns"]["geometry"]["bbox"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_write_points() -> Result<()> {
+        let dir = tempdir()?;
+        let csv = dir.path().join("in.csv");
+        let out = dir.path().join("out.parquet");
+        std::fs::write(
+            &csv,
+            "journey,ident,timestamp,latitude,longitude
+1,A,2024-05-12 10:00:00,49.0,2.0
+1,A,2024-05-12 10:00:01,,2.0
+2,B,2024-05-12 10:00:02,48.5,2.5
+",
+        )?;
+        let rule = Format::Asd.track_rule().unwrap();
+        write_points(&csv.to_string_lossy(), &out.to_string_lossy(), &rule).await?;
+
+        let geo = geo_of(&out)?;
+        assert_eq!(
+            json!(["Point"]),
+            geo["columns"]["geometry"]["geometry_types"]
+        );
+        assert_eq!(
+            json!([2.0, 48.5, 2.5, 49.0]),
+            geo["columns"]["geometry"]["bbox"]
+        );
+
+        let rdr = SerializedFileReader::new(File::open(&out)?)?;
+        assert_eq!(3, rdr.metadata().file_metadata().num_rows());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_trajectories() -> Result<()> {
+        let dir = tempdir()?;
+        let out = dir.path().join("tracks.parquet");
+        let data = "TARGET_ADDR:REC_TIME_POSIX:POS_LAT_DEG:POS_LONG_DEG:track_id
+4CA2D6:1010:49.1:2.1:4CA2D6-1000
+4CA2D6:1000:49.0:2.0:4CA2D6-1000
+4CA2D6:2000:50.0:2.0:4CA2D6-2000
+";
+        let rule = Format::Cat21.track_rule().unwrap();
+        write_trajectories(data, &out.to_string_lossy(), &rule)?;
+
+        let geo = geo_of(&out)?;
+        assert_eq!(
+            json!(["LineString"]),
+            geo["columns"]["geometry"]["geometry_types"]
+        );
+        assert_eq!(
+            json!([2.0, 49.0, 2.1, 49.1]),
+            geo["columns"]["geometry"]["bbox"]
+        );
+
+        // Single point track is dropped
+        //
+        let rdr = SerializedFileReader::new(File::open(&out)?)?;
+        assert_eq!(1, rdr.metadata().file_metadata().num_rows());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_trajectories_no_column() {
+        let rule = Format::Asd.track_rule().unwrap();
+        assert!(write_trajectories("journey,ident\n1,A\n", "/nonexistent", &rule).is_err());
+    }
+}
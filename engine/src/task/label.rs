@@ -0,0 +1,443 @@
+//! `Label` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This filter joins records with flight plans, the ground truth used to label data for
+//! training: every record gets a `flight_id` and `route` column (or has them filled if already
+//! there) from the plan with the same callsign active at the time of the record, give or take
+//! `window` seconds.  Records without a plan are passed along with empty labels or dropped with
+//! `matched_only`.
+//!
+//! Flight plans are read once from a file (see `FlightPlans::load()`):
+//! - CSV with a header and the `callsign`, `flight_id`, `start`, `end` and `route` columns, times
+//!   being UNIX timestamps or RFC 3339,
+//! - a NM B2B flight list, either the SOAP reply itself (`.xml`) or the flights as passed along by
+//!   the `nmb2b` format, one JSON object per line.  A plan runs from its EOBT (or its first point)
+//!   to its last point, the route being the aerodromes and named points.
+//!
+//! Only `Cat21` is supported, the callsign being in `CALLSIGN`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use chrono::DateTime;
+use csv::{ReaderBuilder, WriterBuilder};
+use eyre::Result;
+use serde::Deserialize;
+use tracing::{debug, trace};
+
+use fetiche_formats::{Format, NmFlight};
+use fetiche_macros::RunnableDerive;
+
+use super::qc::parse_time;
+use crate::{record_drop, DropReason, EngineStatus, Runnable, IO};
+
+/// Default slack around flight plans, in seconds
+pub const LABEL_WINDOW: i64 = 900;
+/// Column of the matched flight
+pub const FLIGHT_ID: &str = "flight_id";
+/// Column of the route of the matched flight
+pub const ROUTE: &str = "route";
+
+/// One flight plan, times are UNIX timestamps
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct FlightPlan {
+    /// Callsign, what records are matched on
+    pub callsign: String,
+    /// Flight identifier
+    pub flight_id: String,
+    /// Start of the flight
+    #[serde(deserialize_with = "plan_time")]
+    pub start: i64,
+    /// End of the flight
+    #[serde(deserialize_with = "plan_time")]
+    pub end: i64,
+    /// Route, e.g. "LFPG PON ... EGLL"
+    #[serde(default)]
+    pub route: String,
+}
+
+fn plan_time<'de, D>(d: D) -> std::result::Result<i64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(d)?;
+    parse_time(s.trim())
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(s.trim())
+                .ok()
+                .map(|t| t.timestamp())
+        })
+        .ok_or_else(|| serde::de::Error::custom(format!("bad time {s}")))
+}
+
+impl FlightPlan {
+    /// From EOBT (or the first point) to the last point, `None` without any time.
+    ///
+    pub fn from_nm(f: &NmFlight) -> Option<Self> {
+        let first = f.points.first().map(|p| p.time.timestamp());
+        let start = f.eobt.map(|t| t.timestamp()).or(first)?;
+        let end = f.points.last().map(|p| p.time.timestamp()).unwrap_or(start);
+        let route = [f.departure.as_str()]
+            .into_iter()
+            .chain(f.points.iter().filter_map(|p| p.name.as_deref()))
+            .chain([f.destination.as_str()])
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let flight_id = match f.ifpl_id.as_str() {
+            "" => format!("{}-{}", f.aircraft_id, start),
+            id => id.to_string(),
+        };
+        Some(FlightPlan {
+            callsign: f.aircraft_id.clone(),
+            flight_id,
+            start,
+            end: end.max(start),
+            route,
+        })
+    }
+}
+
+/// All flight plans, by callsign
+///
+#[derive(Clone, Debug, Default)]
+pub struct FlightPlans {
+    plans: BTreeMap<String, Vec<FlightPlan>>,
+}
+
+impl FlightPlans {
+    /// Read a file of flight plans, the kind depending on the extension: `.csv`, `.xml` for a
+    /// NM B2B reply and anything else for NM flights, one per line.
+    ///
+    #[tracing::instrument]
+    pub fn load(fname: &Path) -> Result<Self> {
+        trace!("FlightPlans::load({:?})", fname);
+
+        let data = fs::read_to_string(fname)?;
+        let bad = |e: String| EngineStatus::BadFlightPlans(fname.to_string_lossy().to_string(), e);
+        let ext = fname
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let plans = match ext.as_str() {
+            "csv" => Self::from_csv(&data).map_err(|e| bad(e.to_string()))?,
+            "xml" => {
+                let flights = NmFlight::from_reply(&data).map_err(|e| bad(e.to_string()))?;
+                Self::from_nm(&flights)
+            }
+            _ => {
+                let flights = data
+                    .lines()
+                    .filter(|l| !l.trim().is_empty())
+                    .map(serde_json::from_str::<NmFlight>)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| bad(e.to_string()))?;
+                Self::from_nm(&flights)
+            }
+        };
+        debug!("{} flight plans", plans.len());
+        Ok(plans)
+    }
+
+    /// CSV with a header
+    ///
+    pub fn from_csv(data: &str) -> Result<Self> {
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes());
+        let plans = rdr
+            .deserialize::<FlightPlan>()
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(plans.into_iter().collect())
+    }
+
+    /// NM B2B flights, those without any time are ignored
+    ///
+    pub fn from_nm(flights: &[NmFlight]) -> Self {
+        flights.iter().filter_map(FlightPlan::from_nm).collect()
+    }
+
+    /// Number of plans
+    ///
+    pub fn len(&self) -> usize {
+        self.plans.values().map(|v| v.len()).sum()
+    }
+
+    /// No plans at all
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
+    }
+
+    /// Plan of `callsign` at `time`, `window` seconds before or after it counting too.  The
+    /// closest one wins, the last one started if several are active.
+    ///
+    pub fn find(&self, callsign: &str, time: i64, window: i64) -> Option<&FlightPlan> {
+        self.plans
+            .get(&callsign.trim().to_uppercase())?
+            .iter()
+            .filter(|p| p.start - window <= time && time <= p.end + window)
+            .min_by_key(|p| {
+                let off = (p.start - time).max(time - p.end).max(0);
+                (off, -p.start)
+            })
+    }
+}
+
+impl FromIterator<FlightPlan> for FlightPlans {
+    fn from_iter<T: IntoIterator<Item = FlightPlan>>(iter: T) -> Self {
+        let mut plans = BTreeMap::<String, Vec<FlightPlan>>::new();
+        for p in iter.into_iter().filter(|p| !p.callsign.trim().is_empty()) {
+            plans
+                .entry(p.callsign.trim().to_uppercase())
+                .or_default()
+                .push(p);
+        }
+        FlightPlans { plans }
+    }
+}
+
+/// The Label task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Label {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Input file format
+    pub inp: Format,
+    /// Flight plans, shared by the clones
+    pub plans: Arc<FlightPlans>,
+    /// Slack around each plan, in seconds
+    pub window: i64,
+    /// Drop records without a plan
+    pub matched_only: bool,
+}
+
+impl Label {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format) -> Self {
+        trace!("New Label {}", name);
+        Label {
+            io: IO::Filter,
+            name: name.to_owned(),
+            inp,
+            plans: Arc::new(FlightPlans::default()),
+            window: LABEL_WINDOW,
+            matched_only: false,
+        }
+    }
+
+    /// Set the flight plans
+    ///
+    pub fn plans(&mut self, plans: FlightPlans) -> &mut Self {
+        self.plans = Arc::new(plans);
+        self
+    }
+
+    /// Set the slack around plans
+    ///
+    pub fn window(&mut self, secs: i64) -> &mut Self {
+        self.window = secs;
+        self
+    }
+
+    /// Drop records without a plan
+    ///
+    pub fn matched_only(&mut self, matched_only: bool) -> &mut Self {
+        self.matched_only = matched_only;
+        self
+    }
+
+    /// Add the labels to every record and pass them along.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("Label::execute()");
+
+        if self.inp != Format::Cat21 {
+            return Err(EngineStatus::UnsupportedLabel(self.inp.to_string()).into());
+        }
+
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(b':')
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let mut header = rdr.headers()?.clone();
+        let idx = |name: &str| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or(EngineStatus::NoLabelColumn(name.to_string()))
+        };
+        let (callsign, time) = (idx("CALLSIGN")?, idx("REC_TIME_POSIX")?);
+
+        // Already there if the data went through here before
+        //
+        let mut column = |name: &str| match header.iter().position(|h| h == name) {
+            Some(pos) => pos,
+            None => {
+                header.push_field(name);
+                header.len() - 1
+            }
+        };
+        let pos = [column(FLIGHT_ID), column(ROUTE)];
+
+        let mut wtr = WriterBuilder::new().delimiter(b':').from_writer(vec![]);
+        wtr.write_record(&header)?;
+        let (mut matched, mut dropped) = (0, 0);
+        for rec in rdr.records() {
+            let rec = rec?;
+            let plan = parse_time(rec.get(time).unwrap_or_default().trim()).and_then(|t| {
+                self.plans
+                    .find(rec.get(callsign).unwrap_or_default(), t, self.window)
+            });
+            if plan.is_some() {
+                matched += 1;
+            } else if self.matched_only {
+                dropped += 1;
+                continue;
+            }
+            let labels = plan
+                .map(|p| [p.flight_id.as_str(), p.route.as_str()])
+                .unwrap_or_default();
+            let rec = (0..header.len())
+                .map(|i| match pos.iter().position(|p| *p == i) {
+                    Some(n) => labels[n],
+                    None => rec.get(i).unwrap_or_default(),
+                })
+                .collect::<Vec<_>>();
+            wtr.write_record(&rec)?;
+        }
+        debug!("{} records matched a flight plan", matched);
+        if dropped > 0 {
+            debug!("{} records dropped", dropped);
+            record_drop(DropReason::Filter, dropped);
+        }
+
+        let data = String::from_utf8(wtr.into_inner()?)?;
+        Ok(stdout.send(data)?)
+    }
+}
+
+impl Default for Label {
+    fn default() -> Self {
+        Label::new("default", Format::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    const PLANS: &str = "callsign,flight_id,start,end,route
+AFR123,AA001,1000,2000,LFPG PON EGLL
+AFR123,AA002,5000,6000,EGLL LFPG
+EZY45,2024-05-12T10:00:00Z-1,2024-05-12T10:00:00Z,2024-05-12T11:00:00Z,
+";
+
+    fn plans() -> FlightPlans {
+        FlightPlans::from_csv(PLANS).unwrap()
+    }
+
+    #[rstest]
+    #[case("AFR123", 1500, Some("AA001"))]
+    #[case("afr123 ", 2500, Some("AA001"))]
+    #[case("AFR123", 3500, None)]
+    #[case("AFR123", 4500, Some("AA002"))]
+    #[case("EZY45", 1_715_508_000, Some("2024-05-12T10:00:00Z-1"))]
+    #[case("BAW1", 1500, None)]
+    fn test_label_find(#[case] callsign: &str, #[case] time: i64, #[case] id: Option<&str>) {
+        let plans = plans();
+        assert_eq!(3, plans.len());
+        assert_eq!(
+            id,
+            plans
+                .find(callsign, time, LABEL_WINDOW)
+                .map(|p| p.flight_id.as_str())
+        );
+    }
+
+    #[test]
+    fn test_label_nm() {
+        let line = r#"{"ifpl_id":"","aircraft_id":"AFR123","departure":"LFPG","destination":"EGLL","eobt":"2024-05-12T10:00:00Z","points":[{"time":"2024-05-12T10:20:00Z","name":"PON"},{"time":"2024-05-12T11:00:00Z","latitude":51.47,"longitude":-0.45}]}"#;
+        let f = serde_json::from_str::<NmFlight>(line).unwrap();
+        let plans = FlightPlans::from_nm(&[f, NmFlight::default()]);
+        assert_eq!(1, plans.len());
+
+        let p = plans.find("AFR123", 1_715_510_000, 0).unwrap();
+        assert_eq!("AFR123-1715508000", p.flight_id);
+        assert_eq!("LFPG PON EGLL", p.route);
+        assert_eq!(1_715_511_600, p.end);
+    }
+
+    #[test]
+    fn test_label_execute() -> Result<()> {
+        let data = "TARGET_ADDR:CALLSIGN:REC_TIME_POSIX
+4CA2D6:AFR123:1500
+4CA2D6:AFR123:3500
+39C4A3::1500
+";
+        let mut t = Label::new("test", Format::Cat21);
+        t.plans(plans());
+
+        let (tx, rx) = channel();
+        t.execute(data.to_string(), tx)?;
+        let out = rx.recv()?;
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            "TARGET_ADDR:CALLSIGN:REC_TIME_POSIX:flight_id:route",
+            lines[0]
+        );
+        assert_eq!("4CA2D6:AFR123:1500:AA001:LFPG PON EGLL", lines[1]);
+        assert_eq!("4CA2D6:AFR123:3500::", lines[2]);
+
+        // Labels are filled again, unmatched records dropped
+        //
+        t.matched_only(true);
+        let (tx, rx) = channel();
+        t.execute(out, tx)?;
+        let out = rx.recv()?;
+        assert_eq!(2, out.lines().count());
+        assert_eq!(1, out.lines().next().unwrap().matches(FLIGHT_ID).count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_unsupported() {
+        let (tx, _rx) = channel();
+        let mut t = Label::new("test", Format::Asd);
+        assert!(t.execute("a,b\n".to_string(), tx).is_err());
+
+        let (tx, _rx) = channel();
+        let mut t = Label::new("test", Format::Cat21);
+        assert!(t
+            .execute("TARGET_ADDR:REC_TIME_POSIX\n".to_string(), tx)
+            .is_err());
+    }
+
+    #[test]
+    fn test_label_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("plans.csv");
+        fs::write(&fname, PLANS)?;
+        assert_eq!(3, FlightPlans::load(&fname)?.len());
+
+        let fname = dir.path().join("plans.json");
+        fs::write(&fname, "garbage\n")?;
+        assert!(FlightPlans::load(&fname).is_err());
+        Ok(())
+    }
+}
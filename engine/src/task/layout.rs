@@ -0,0 +1,303 @@
+//! Per-track layout for Parquet output.
+//!
+//! By default, `Save` writes one row per position.  With `Layout::Tracks`, records are grouped
+//! per track and each row is one trajectory with summary columns and its points, ordered by
+//! time, in a nested `path` column:
+//!
+//! - `track_id`, `vehicle`,
+//! - `start_time`, `end_time` and `duration` (seconds),
+//! - `points`, `min_lat`, `min_lon`, `max_lat`, `max_lon`,
+//! - `max_altitude`, in the unit of the input format (meters for ASD, feet for Cat21),
+//! - `path`, a list of `(time, latitude, longitude, altitude)`.
+//!
+//! The track is found like for GeoParquet trajectories (see `geoparquet.rs`): the `track_id`
+//! column added by the `Track` task if there is one, then the format's own identifier and
+//! finally the vehicle.  Records without a time or a position are ignored.  Provenance columns
+//! are kept, with the values of the first record.
+//!
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::sync::Arc;
+
+use csv::ReaderBuilder;
+use datafusion::arrow::array::{
+    Array, ArrayRef, Float64Array, Float64Builder, Int64Array, ListBuilder, RecordBatch,
+    StringArray, StructBuilder, TimestampSecondArray, TimestampSecondBuilder, UInt32Array,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Schema, TimeUnit};
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::basic::{Compression, ZstdLevel};
+use datafusion::parquet::file::properties::WriterProperties;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+use tracing::trace;
+
+use fetiche_formats::{TrackRule, TRACK_ID};
+
+use super::provenance::stamp_columns;
+use super::qc::parse_time;
+use crate::EngineStatus;
+
+/// How records are laid out in Parquet files
+///
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, EnumString, PartialEq, Serialize, strum::Display,
+)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// One row per position
+    #[default]
+    Points,
+    /// One row per track with its points
+    Tracks,
+}
+
+/// One point of a track
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct TrackPoint {
+    pub tm: i64,
+    pub lon: f64,
+    pub lat: f64,
+    pub alt: Option<f64>,
+}
+
+/// Vehicle and points of a track
+///
+pub(crate) type TrackPoints = (String, Vec<TrackPoint>);
+
+/// Group the records of the CSV `data` per track, points are sorted by time.
+///
+pub(crate) fn read_tracks(data: &str, rule: &TrackRule) -> Result<BTreeMap<String, TrackPoints>> {
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(rule.delimiter)
+        .has_headers(true)
+        .from_reader(data.as_bytes());
+    let header = rdr.headers()?.clone();
+    let idx = |name: &str| {
+        header
+            .iter()
+            .position(|h| h == name)
+            .ok_or(EngineStatus::NoGeoParquetColumn(name.to_string()))
+    };
+    let vehicle = idx(rule.vehicle)?;
+    let track = idx(TRACK_ID)
+        .ok()
+        .or(rule.track.and_then(|t| idx(t).ok()))
+        .unwrap_or(vehicle);
+    let (time, lat, lon) = (idx(rule.time)?, idx(rule.latitude)?, idx(rule.longitude)?);
+    let alt = rule.altitude.and_then(|a| idx(a).ok());
+
+    let mut tracks: BTreeMap<String, TrackPoints> = BTreeMap::new();
+    for rec in rdr.records() {
+        let rec = rec?;
+        let get = |i: usize| rec.get(i).unwrap_or_default().trim();
+        let num = |i: usize| get(i).parse::<f64>().ok();
+
+        let (Some(tm), Some(lat), Some(lon)) = (parse_time(get(time)), num(lat), num(lon)) else {
+            continue;
+        };
+        tracks
+            .entry(get(track).to_string())
+            .or_insert_with(|| (get(vehicle).to_string(), vec![]))
+            .1
+            .push(TrackPoint {
+                tm,
+                lon,
+                lat,
+                alt: alt.and_then(num),
+            });
+    }
+    tracks
+        .values_mut()
+        .for_each(|(_, points)| points.sort_by_key(|p| p.tm));
+    Ok(tracks)
+}
+
+/// Fields of one point in `path`
+///
+fn point_fields() -> Fields {
+    Fields::from(vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Second, Some("UTC".into())),
+            false,
+        ),
+        Field::new("latitude", DataType::Float64, false),
+        Field::new("longitude", DataType::Float64, false),
+        Field::new("altitude", DataType::Float64, true),
+    ])
+}
+
+/// Write one row per track from the CSV `data` into `to`.
+///
+#[tracing::instrument(skip(data, rule))]
+pub(crate) fn write_tracks(data: &str, to: &str, rule: &TrackRule) -> Result<()> {
+    let tracks = read_tracks(data, rule)?;
+    trace!("{} tracks", tracks.len());
+
+    let (mut ids, mut vehicles, mut starts, mut ends, mut durations, mut counts) =
+        (vec![], vec![], vec![], vec![], vec![], vec![]);
+    let (mut min_lat, mut min_lon, mut max_lat, mut max_lon, mut max_alt) =
+        (vec![], vec![], vec![], vec![], vec![]);
+    let mut path = ListBuilder::new(StructBuilder::new(
+        point_fields(),
+        vec![
+            Box::new(TimestampSecondBuilder::new().with_timezone("UTC")),
+            Box::new(Float64Builder::new()),
+            Box::new(Float64Builder::new()),
+            Box::new(Float64Builder::new()),
+        ],
+    ));
+
+    for (id, (v, points)) in tracks {
+        let (first, last) = (points[0].tm, points[points.len() - 1].tm);
+        ids.push(id);
+        vehicles.push(v);
+        starts.push(first);
+        ends.push(last);
+        durations.push(last - first);
+        counts.push(points.len() as u32);
+        min_lat.push(points.iter().map(|p| p.lat).fold(f64::INFINITY, f64::min));
+        min_lon.push(points.iter().map(|p| p.lon).fold(f64::INFINITY, f64::min));
+        max_lat.push(
+            points
+                .iter()
+                .map(|p| p.lat)
+                .fold(f64::NEG_INFINITY, f64::max),
+        );
+        max_lon.push(
+            points
+                .iter()
+                .map(|p| p.lon)
+                .fold(f64::NEG_INFINITY, f64::max),
+        );
+        max_alt.push(points.iter().filter_map(|p| p.alt).reduce(f64::max));
+
+        let sb = path.values();
+        for p in &points {
+            sb.field_builder::<TimestampSecondBuilder>(0)
+                .unwrap()
+                .append_value(p.tm);
+            sb.field_builder::<Float64Builder>(1)
+                .unwrap()
+                .append_value(p.lat);
+            sb.field_builder::<Float64Builder>(2)
+                .unwrap()
+                .append_value(p.lon);
+            sb.field_builder::<Float64Builder>(3)
+                .unwrap()
+                .append_option(p.alt);
+            sb.append(true);
+        }
+        path.append(true);
+    }
+    let path = path.finish();
+    let rows = ids.len();
+
+    let time = DataType::Timestamp(TimeUnit::Second, Some("UTC".into()));
+    let mut fields = vec![
+        Field::new(TRACK_ID, DataType::Utf8, false),
+        Field::new("vehicle", DataType::Utf8, false),
+        Field::new("start_time", time.clone(), false),
+        Field::new("end_time", time, false),
+        Field::new("duration", DataType::Int64, false),
+        Field::new("points", DataType::UInt32, false),
+        Field::new("min_lat", DataType::Float64, false),
+        Field::new("min_lon", DataType::Float64, false),
+        Field::new("max_lat", DataType::Float64, false),
+        Field::new("max_lon", DataType::Float64, false),
+        Field::new("max_altitude", DataType::Float64, true),
+        Field::new("path", path.data_type().clone(), false),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(ids)),
+        Arc::new(StringArray::from(vehicles)),
+        Arc::new(TimestampSecondArray::from(starts).with_timezone("UTC")),
+        Arc::new(TimestampSecondArray::from(ends).with_timezone("UTC")),
+        Arc::new(Int64Array::from(durations)),
+        Arc::new(UInt32Array::from(counts)),
+        Arc::new(Float64Array::from(min_lat)),
+        Arc::new(Float64Array::from(min_lon)),
+        Arc::new(Float64Array::from(max_lat)),
+        Arc::new(Float64Array::from(max_lon)),
+        Arc::new(Float64Array::from(max_alt)),
+        Arc::new(path),
+    ];
+    let (f, c) = stamp_columns(data, rule.delimiter, rows);
+    fields.extend(f);
+    columns.extend(c);
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    let props = WriterProperties::builder()
+        .set_created_by("acutectl/save".to_string())
+        .set_compression(Compression::ZSTD(ZstdLevel::try_new(8)?))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(to)?, schema, Some(props))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::array::{AsArray, StructArray};
+    use datafusion::arrow::datatypes::{Float64Type, Int64Type, UInt32Type};
+    use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use tempfile::tempdir;
+
+    use fetiche_formats::Format;
+
+    use super::*;
+
+    #[test]
+    fn test_write_tracks() -> Result<()> {
+        let dir = tempdir()?;
+        let out = dir.path().join("tracks.parquet");
+        let data = "journey,ident,timestamp,latitude,longitude,altitude
+1,A,2024-05-12 10:00:10,49.1,2.1,120
+1,A,2024-05-12 10:00:00,49.0,2.0,100
+1,A,2024-05-12 10:00:20,,2.0,100
+2,B,2024-05-12 11:00:00,48.5,2.5,
+";
+        let rule = Format::Asd.track_rule().unwrap();
+        write_tracks(data, &out.to_string_lossy(), &rule)?;
+
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&out)?)?
+            .build()?
+            .next()
+            .unwrap()?;
+        assert_eq!(2, batch.num_rows());
+
+        let col = |name: &str| batch.column_by_name(name).unwrap().clone();
+        assert_eq!(10, col("duration").as_primitive::<Int64Type>().value(0));
+        assert_eq!(2, col("points").as_primitive::<UInt32Type>().value(0));
+        assert_eq!(49.1, col("max_lat").as_primitive::<Float64Type>().value(0));
+        assert_eq!(
+            120.0,
+            col("max_altitude").as_primitive::<Float64Type>().value(0)
+        );
+        assert!(col("max_altitude").is_null(1));
+
+        // Points are in time order
+        //
+        let path = col("path");
+        let first = path.as_list::<i32>().value(0);
+        let first: &StructArray = first.as_struct();
+        assert_eq!(49.0, first.column(1).as_primitive::<Float64Type>().value(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_layout_from_str() {
+        use std::str::FromStr;
+
+        assert_eq!(Layout::Tracks, Layout::from_str("tracks").unwrap());
+        assert_eq!(Layout::Points, Layout::default());
+        assert!(Layout::from_str("rows").is_err());
+    }
+}
@@ -4,54 +4,119 @@
 use std::collections::BTreeMap;
 
 use eyre::Result;
+use fetiche_common::{Listing, OutputFormat};
 use serde::Deserialize;
 use strum::EnumIter;
-use tabled::{builder::Builder, settings::Style};
 use tracing::trace;
 
+pub use broadcast::*;
 pub use common::*;
 pub use convert::*;
+pub use dump::*;
 pub use fetch::*;
+pub use geojson::*;
+pub use label::*;
+pub use layout::*;
+#[cfg(feature = "postgis")]
+pub use postgis::*;
+pub use project::*;
+pub use provenance::*;
+pub use qc::*;
+pub use raw::*;
 pub use read::*;
+pub use replay::*;
 pub use save::*;
+pub use select::*;
+pub use serve::*;
+pub use spill::*;
+pub use split::*;
 pub use store::*;
 pub use stream::*;
 pub use tee::*;
+pub use track::*;
+pub use xlsx::*;
 
 use crate::{Engine, IO};
 
+mod broadcast;
 mod common;
 mod convert;
+mod dump;
 mod fetch;
+mod geojson;
+mod geoparquet;
+mod label;
+mod layout;
+#[cfg(feature = "postgis")]
+mod postgis;
+mod project;
+mod provenance;
+mod qc;
+mod raw;
 mod read;
+mod replay;
 mod save;
+mod select;
+mod serve;
+mod spill;
+mod split;
 mod store;
 mod stream;
 mod tee;
+mod track;
+mod xlsx;
 
 #[derive(Debug, strum::Display, strum::VariantNames, EnumIter, PartialEq)]
 #[strum(serialize_all = "PascalCase")]
 pub enum Cmds {
+    /// Publish records to the live WebSocket clients
+    Broadcast,
     /// Convert into Cat21 data
     Convert,
     /// Basic raw copy
     Copy,
     /// Fetch a single dataset
     Fetch,
+    /// Turn records into newline-delimited GeoJSON features
+    GeoJson,
+    /// Label records with their flight plan
+    Label,
     /// Display a message
     Message,
     /// NOP
     Nothing,
+    /// Write positions (and trajectories) into PostGIS
+    PostGis,
+    /// Keep some columns of Cat21 records
+    Project,
+    /// Check records for impossible values, time going backwards and gaps
+    Qc,
+    /// Keep every raw chunk in its own timestamped file
+    RawCopy,
+    /// Capture every raw chunk with framing into rotating files
+    RawDump,
     /// Read a single file
     Read,
+    /// Send captured payloads again with their timing
+    Replay,
     /// Save a single dataset
     Save,
+    /// Keep the records of some vehicle categories
+    Select,
+    /// Send data to all connected TCP clients
+    Serve,
+    /// Spill to disk what a slow sink can not take yet
+    Spill,
+    /// Save a dataset split by key into a directory
+    Split,
     /// Store datasets into a organised directory
     Store,
     /// Fetch a stream of data
     Stream,
     /// Copy data and pass it along
     Tee,
+    /// Add a normalised track ID to every record
+    Track,
 }
 
 /// For each format, we define a set of key attributes that will get displayed.
@@ -82,7 +147,7 @@ impl Engine {
     /// Returns the content of the `cmds.hcl` file as a table.
     ///
     #[tracing::instrument]
-    pub fn list_commands(&self) -> Result<String> {
+    pub fn list_commands(&self, fmt: OutputFormat) -> Result<String> {
         trace!("list all commands");
 
         let allcmds_s = include_str!("cmds.hcl");
@@ -92,29 +157,25 @@ impl Engine {
         //
         assert_eq!(allcmds.version, CVERSION);
 
-        let header = vec!["Name", "Type", "Description"];
-
-        let mut builder = Builder::default();
-        builder.push_record(header);
+        let mut list = Listing::new(
+            "List all commands",
+            &[
+                ("Name", "name"),
+                ("Type", "type"),
+                ("Description", "description"),
+            ],
+        );
 
         allcmds
             .cmds
             .iter()
             .for_each(|(cmd, cmd_desc): (&String, &CmdsDescr)| {
-                let mut row = vec![];
-
-                let name = cmd.clone();
-                let ctype = cmd_desc.ctype.clone().to_string();
-                let descr = cmd_desc.description.clone();
-                row.push(name);
-                row.push(ctype);
-                row.push(descr);
-                builder.push_record(row);
+                list.push(vec![
+                    cmd.clone(),
+                    cmd_desc.ctype.to_string(),
+                    cmd_desc.description.clone(),
+                ]);
             });
-
-        let allc = builder.build().with(Style::modern()).to_string();
-        let str = format!("List all commands:\n{allc}");
-
-        Ok(str)
+        list.render(fmt)
     }
 }
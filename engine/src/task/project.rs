@@ -0,0 +1,115 @@
+//! `Project` is a `Runnable` task as defined in the `engine` crate.
+//!
+//! This filter only keeps some columns of `Cat21` records, in the order given by a `Columns`
+//! profile (see `fetiche_formats::Columns`), for tools rejecting files with columns they do not
+//! know.  It is added by `Engine::create_job_from()` right before a `save` sink with `columns`,
+//! after every stage needing the full records (`Track`, `Qc`, `Broadcast`...).
+//!
+//! The columns reaching it are known in advance: those of `Cat21`, then the ones added by the
+//! other stages, see `Project::new()`.  A header line, if any, is projected like the records.
+//!
+
+use std::sync::mpsc::Sender;
+
+use eyre::Result;
+use tracing::trace;
+
+use fetiche_formats::{select_columns, Columns};
+use fetiche_macros::RunnableDerive;
+
+use crate::{EngineStatus, Runnable, IO};
+
+/// The Project task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Project {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Profile
+    pub columns: Columns,
+    /// Position of the columns we keep in the input
+    indices: Vec<usize>,
+}
+
+impl Project {
+    /// Keep `columns` of records made of the `input` columns, fails on unknown ones.
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, columns: &Columns, input: &[&str]) -> Result<Self> {
+        trace!("New Project {}", name);
+        let indices = columns
+            .indices(input)
+            .map_err(|e| EngineStatus::BadColumns(e.to_string()))?;
+        Ok(Project {
+            io: IO::Filter,
+            name: name.to_owned(),
+            columns: columns.clone(),
+            indices,
+        })
+    }
+
+    /// Pass along the columns we keep.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("Project::execute()");
+
+        Ok(stdout.send(select_columns(&data, b':', &self.indices)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::sync::mpsc::channel;
+
+    use fetiche_formats::CAT21_COLUMNS;
+
+    use super::*;
+
+    #[test]
+    fn test_project() -> Result<()> {
+        let input = ["SAC", "SIC", "CALLSIGN", "track_id"];
+        let columns = Columns::from_str("track_id,sac")?;
+        let mut p = Project::new("p", &columns, &input)?;
+
+        let (tx, rx) = channel();
+        p.execute("8:200:AFR123:a1b2c3-1\n".to_string(), tx.clone())?;
+        assert_eq!("a1b2c3-1:8\n", rx.recv()?);
+
+        // With a header, as written by `Track`
+        //
+        p.execute(
+            "SAC:SIC:CALLSIGN:track_id\n8:200:AFR123:x\n".to_string(),
+            tx,
+        )?;
+        assert_eq!("track_id:SAC\nx:8\n", rx.recv()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_ids() -> Result<()> {
+        let mut p = Project::new("p", &Columns::Ids, &CAT21_COLUMNS)?;
+        let line = (0..CAT21_COLUMNS.len())
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let (tx, rx) = channel();
+        p.execute(line, tx)?;
+        assert_eq!("0:1:2:3:4:5:6:7:8:9:24:30:31:32\n", rx.recv()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_unknown() {
+        let columns = Columns::from_str("sac,nope").unwrap();
+        let err = Project::new("p", &columns, &CAT21_COLUMNS).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::BadColumns(_))
+        ));
+    }
+}
@@ -0,0 +1,432 @@
+//! `Qc` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This is a filter checking converted records before they are saved:
+//!
+//! - positions out of range (latitude outside ±90°, longitude outside ±180°),
+//! - altitude spikes, i.e. climbing or descending faster than `max_climb` m/s within a track,
+//! - time going backwards within a track,
+//! - gaps longer than `max_gap` seconds within a track.
+//!
+//! Records failing one of the first three checks are bad and removed if `drop` is set, gaps are
+//! only reported.  Tracks are `track_id` if present (see `Track`), drone journeys or aircraft
+//! addresses otherwise.  A summary is (re)written as
+//! JSON after each batch of data if a path is given, this is what the data-quality dashboard reads.
+//!
+//! Only CSV-based formats are supported (`Asd` and `Cat21`), like `Split`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use chrono::{NaiveDateTime, Utc};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use eyre::Result;
+use serde::Serialize;
+use tracing::{debug, trace};
+
+use fetiche_formats::{Format, TRACK_ID};
+use fetiche_macros::RunnableDerive;
+
+use crate::{record_drop, DropReason, EngineStatus, Runnable, IO};
+
+/// Default longest gap within a track, in seconds
+pub const QC_MAX_GAP: i64 = 60;
+/// Default fastest climb or descent, in m/s
+pub const QC_MAX_CLIMB: f64 = 100.;
+/// Only the first gaps are listed in the summary
+const MAX_GAPS: usize = 1_000;
+
+/// Name of the columns we need for each format
+///
+#[derive(Clone, Debug)]
+struct Columns {
+    track: &'static str,
+    time: &'static str,
+    latitude: &'static str,
+    longitude: &'static str,
+    altitude: &'static str,
+    /// To get the altitude in metres
+    scale: f64,
+}
+
+impl Columns {
+    fn from_format(fmt: Format) -> Option<Self> {
+        match fmt {
+            Format::Asd => Some(Columns {
+                track: "journey",
+                time: "timestamp",
+                latitude: "latitude",
+                longitude: "longitude",
+                altitude: "altitude",
+                scale: 1.,
+            }),
+            Format::Cat21 => Some(Columns {
+                track: "TARGET_ADDR",
+                time: "REC_TIME_POSIX",
+                latitude: "POS_LAT_DEG",
+                longitude: "POS_LONG_DEG",
+                altitude: "ALT_GEO_FT",
+                scale: 0.3048,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Why a record is bad
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Check {
+    /// Missing or unparsable position or time
+    Invalid,
+    /// Position out of range
+    OutOfRange,
+    /// Altitude changing too fast
+    Spike,
+    /// Time going backwards
+    Backwards,
+}
+
+impl Check {
+    /// Drop reason in the job profile, records already seen for a track being duplicates
+    ///
+    fn reason(&self) -> DropReason {
+        match self {
+            Check::Invalid => DropReason::Parse,
+            Check::OutOfRange | Check::Spike => DropReason::Filter,
+            Check::Backwards => DropReason::Dedup,
+        }
+    }
+}
+
+/// A gap within a track
+///
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Gap {
+    /// Track (journey or address)
+    pub track: String,
+    /// Last record before
+    pub from: i64,
+    /// First record after
+    pub to: i64,
+}
+
+/// Content of the QC summary
+///
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct QcSummary {
+    /// Input format
+    pub format: String,
+    /// Last update
+    pub updated_at: String,
+    /// Records seen
+    pub records: usize,
+    /// Bad records (removed if `dropped` is set)
+    pub bad: usize,
+    /// Were bad records removed?
+    pub dropped: bool,
+    /// Missing or unparsable position or time
+    pub invalid: usize,
+    /// Position out of range
+    pub out_of_range: usize,
+    /// Altitude spikes
+    pub altitude_spikes: usize,
+    /// Time going backwards
+    pub time_backwards: usize,
+    /// Number of gaps longer than `max_gap`
+    pub gaps: usize,
+    /// Longest gap, in seconds
+    pub longest_gap: i64,
+    /// First gaps
+    pub gap_list: Vec<Gap>,
+    /// Number of tracks seen
+    pub tracks: usize,
+}
+
+impl QcSummary {
+    fn add(&mut self, check: Check) {
+        self.bad += 1;
+        match check {
+            Check::Invalid => self.invalid += 1,
+            Check::OutOfRange => self.out_of_range += 1,
+            Check::Spike => self.altitude_spikes += 1,
+            Check::Backwards => self.time_backwards += 1,
+        }
+    }
+}
+
+/// The Qc task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Qc {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Summary file, if any
+    pub path: Option<PathBuf>,
+    /// Input file format
+    pub inp: Format,
+    /// Longest gap within a track, in seconds
+    pub max_gap: i64,
+    /// Fastest climb or descent, in m/s
+    pub max_climb: f64,
+    /// Remove bad records?
+    pub drop: bool,
+    /// What we have seen so far
+    pub summary: QcSummary,
+    /// Time and altitude of the last good record of each track
+    last: BTreeMap<String, (i64, f64)>,
+}
+
+impl Qc {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format) -> Self {
+        trace!("New Qc {}", name);
+        Qc {
+            io: IO::Filter,
+            name: name.to_owned(),
+            path: None,
+            inp,
+            max_gap: QC_MAX_GAP,
+            max_climb: QC_MAX_CLIMB,
+            drop: false,
+            summary: QcSummary::default(),
+            last: BTreeMap::new(),
+        }
+    }
+
+    /// Set the summary file
+    ///
+    pub fn path(&mut self, name: &str) -> &mut Self {
+        trace!("Add path: {}", name);
+        self.path = Some(PathBuf::from(name));
+        self
+    }
+
+    /// Set the longest gap
+    ///
+    pub fn max_gap(&mut self, secs: i64) -> &mut Self {
+        self.max_gap = secs;
+        self
+    }
+
+    /// Set the fastest climb or descent
+    ///
+    pub fn max_climb(&mut self, rate: f64) -> &mut Self {
+        self.max_climb = rate;
+        self
+    }
+
+    /// Remove bad records
+    ///
+    pub fn drop(&mut self, drop: bool) -> &mut Self {
+        self.drop = drop;
+        self
+    }
+
+    /// Check every record and pass them along, without the bad ones if asked to.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("Qc::execute()");
+
+        let cols = Columns::from_format(self.inp)
+            .ok_or(EngineStatus::UnsupportedQc(self.inp.to_string()))?;
+        let delim = match self.inp {
+            Format::Cat21 => b':',
+            _ => b',',
+        };
+
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(delim)
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let header = rdr.headers()?.clone();
+        let idx = |name: &str| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or(EngineStatus::NoQcColumn(name.to_string()))
+        };
+        // Normalised tracks are better than addresses
+        //
+        let track = match header.iter().any(|h| h == TRACK_ID) {
+            true => TRACK_ID,
+            false => cols.track,
+        };
+        let idx = [
+            idx(track)?,
+            idx(cols.time)?,
+            idx(cols.latitude)?,
+            idx(cols.longitude)?,
+            idx(cols.altitude)?,
+        ];
+
+        let mut wtr = WriterBuilder::new().delimiter(delim).from_writer(vec![]);
+        wtr.write_record(&header)?;
+        for rec in rdr.records() {
+            let rec = rec?;
+            self.summary.records += 1;
+            match self.check(&rec, &idx, cols.scale) {
+                Some(check) => {
+                    debug!("{:?}: {:?}", check, rec);
+                    self.summary.add(check);
+                    if self.drop {
+                        record_drop(check.reason(), 1);
+                    } else {
+                        wtr.write_record(&rec)?;
+                    }
+                }
+                None => wtr.write_record(&rec)?,
+            }
+        }
+        self.summary.tracks = self.last.len();
+        self.write_summary()?;
+
+        let data = String::from_utf8(wtr.into_inner()?)?;
+        Ok(stdout.send(data)?)
+    }
+
+    /// Check one record, `idx` being the track, time, latitude, longitude and altitude columns.
+    ///
+    fn check(&mut self, rec: &StringRecord, idx: &[usize; 5], scale: f64) -> Option<Check> {
+        let get = |i: usize| rec.get(i).unwrap_or_default().trim();
+        let num = |i: usize| get(i).parse::<f64>().ok();
+
+        let (Some(tm), Some(lat), Some(lon)) = (parse_time(get(idx[1])), num(idx[2]), num(idx[3]))
+        else {
+            return Some(Check::Invalid);
+        };
+        if !(-90. ..=90.).contains(&lat) || !(-180. ..=180.).contains(&lon) {
+            return Some(Check::OutOfRange);
+        }
+        let alt = num(idx[4]).unwrap_or_default() * scale;
+
+        let track = get(idx[0]).to_string();
+        if let Some(&(last, last_alt)) = self.last.get(&track) {
+            if tm < last {
+                return Some(Check::Backwards);
+            }
+            let dt = tm - last;
+            if (alt - last_alt).abs() / dt.max(1) as f64 > self.max_climb {
+                return Some(Check::Spike);
+            }
+            if dt > self.max_gap {
+                self.summary.gaps += 1;
+                self.summary.longest_gap = self.summary.longest_gap.max(dt);
+                if self.summary.gap_list.len() < MAX_GAPS {
+                    self.summary.gap_list.push(Gap {
+                        track: track.clone(),
+                        from: last,
+                        to: tm,
+                    });
+                }
+            }
+        }
+        self.last.insert(track, (tm, alt));
+        None
+    }
+
+    /// (Re)write the summary if needed
+    ///
+    fn write_summary(&mut self) -> Result<()> {
+        if let Some(path) = &self.path {
+            self.summary.format = self.inp.to_string();
+            self.summary.updated_at = Utc::now().to_rfc3339();
+            self.summary.dropped = self.drop;
+            let str = serde_json::to_string_pretty(&self.summary)?;
+            fs::write(path, str)?;
+        }
+        Ok(())
+    }
+}
+
+/// Time is either a UNIX timestamp or the ASD one
+///
+pub(crate) fn parse_time(s: &str) -> Option<i64> {
+    match s.parse::<i64>() {
+        Ok(t) => Some(t),
+        Err(_) => NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .map(|t| t.and_utc().timestamp()),
+    }
+}
+
+impl Default for Qc {
+    fn default() -> Self {
+        Qc::new("default", Format::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const DATA: &str = "journey,timestamp,latitude,longitude,altitude
+1,2024-10-09 10:00:00,49.0,2.5,100
+1,2024-10-09 10:00:01,49.0,2.5,2000
+1,2024-10-09 10:00:02,49.0,2.5,102
+2,2024-10-09 10:00:00,95.0,2.5,100
+2,2024-10-09 10:00:05,49.1,2.5,100
+2,2024-10-09 10:00:03,49.1,2.5,100
+2,2024-10-09 10:05:00,49.1,2.5,100
+3,,49.1,2.5,100
+";
+
+    #[rstest]
+    #[case("1728468000", Some(1728468000))]
+    #[case("2024-10-09 10:00:00", Some(1728468000))]
+    #[case("yesterday", None)]
+    fn test_qc_parse_time(#[case] s: &str, #[case] res: Option<i64>) {
+        assert_eq!(res, parse_time(s));
+    }
+
+    #[rstest]
+    #[case(false, 9)]
+    #[case(true, 5)]
+    fn test_qc_execute(#[case] drop: bool, #[case] lines: usize) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("qc.json");
+        let (tx, rx) = channel();
+
+        let mut t = Qc::new("foo", Format::Asd);
+        t.path(&path.to_string_lossy()).drop(drop);
+        t.execute(DATA.to_string(), tx).unwrap();
+
+        let out = rx.recv().unwrap();
+        assert_eq!(lines, out.lines().count());
+
+        let s = &t.summary;
+        assert_eq!(8, s.records);
+        assert_eq!(4, s.bad);
+        assert_eq!(1, s.invalid);
+        assert_eq!(1, s.out_of_range);
+        assert_eq!(1, s.altitude_spikes);
+        assert_eq!(1, s.time_backwards);
+        assert_eq!(1, s.gaps);
+        assert_eq!(295, s.longest_gap);
+        assert_eq!(2, s.tracks);
+
+        let summary = fs::read_to_string(path).unwrap();
+        assert!(summary.contains("\"altitude_spikes\": 1"));
+        assert!(summary.contains("\"track\": \"2\""));
+    }
+
+    #[test]
+    fn test_qc_unsupported() {
+        let (tx, _rx) = channel();
+
+        let mut t = Qc::new("foo", Format::Opensky);
+        assert!(t.execute("{}".to_string(), tx).is_err());
+    }
+}
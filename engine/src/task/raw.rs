@@ -0,0 +1,152 @@
+//! `RawCopy` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! Like `Tee`, this is a filter passing data down unchanged but every chunk received is also
+//! written untouched into its own file inside a directory, named after the time it was received,
+//! a sequence number and the format of the site (e.g. `20240101T120000.123Z-000001.asd`).
+//!
+//! Put it right after the producer so that the originals are kept before any conversion and can
+//! be reprocessed later with `acutectl convert`.
+//!
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use eyre::Result;
+use tracing::{debug, trace};
+
+use fetiche_formats::Format;
+use fetiche_macros::RunnableDerive;
+
+use crate::{EngineStatus, Runnable, IO};
+
+/// The RawCopy task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct RawCopy {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Output directory
+    pub path: PathBuf,
+    /// Format of the raw data, used as extension
+    pub inp: Format,
+    /// Number of chunks written so far
+    pub seq: usize,
+}
+
+impl RawCopy {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format) -> Self {
+        trace!("New RawCopy {}", name);
+        RawCopy {
+            io: IO::Filter,
+            name: name.to_owned(),
+            path: PathBuf::from(name),
+            inp,
+            seq: 0,
+        }
+    }
+
+    /// Set the output directory
+    ///
+    pub fn path(&mut self, name: &str) -> &mut Self {
+        trace!("Add path: {}", name);
+        self.path = PathBuf::from(name);
+        self
+    }
+
+    /// Write the chunk in a new file then pass it down.
+    ///
+    #[tracing::instrument(skip(self, data))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("RawCopy::execute()");
+
+        if !self.path.exists() {
+            fs::create_dir_all(&self.path)
+                .map_err(|_| EngineStatus::CreateDir(self.path.to_string_lossy().to_string()))?;
+        }
+
+        self.seq += 1;
+        let fname = self.path.join(chunk_name(Utc::now(), self.seq, self.inp));
+        debug!("{} bytes into {:?}", data.len(), fname);
+        fs::write(&fname, &data)?;
+
+        Ok(stdout.send(data)?)
+    }
+}
+
+/// Name of the file for a given chunk.
+///
+fn chunk_name(tm: DateTime<Utc>, seq: usize, fmt: Format) -> String {
+    let ext = match fmt {
+        Format::None => "raw".to_string(),
+        _ => fmt.to_string(),
+    };
+    format!("{}-{:06}.{}", tm.format("%Y%m%dT%H%M%S%.3fZ"), seq, ext)
+}
+
+/// Reception time and sequence number of a chunk from its file name, see `chunk_name()`.
+///
+pub(crate) fn parse_chunk_name(fname: &str) -> Option<(DateTime<Utc>, usize)> {
+    let (stem, _ext) = fname.rsplit_once('.')?;
+    let (tm, seq) = stem.split_once('-')?;
+    let tm = NaiveDateTime::parse_from_str(tm, "%Y%m%dT%H%M%S%.3fZ").ok()?;
+    Some((tm.and_utc(), seq.parse().ok()?))
+}
+
+impl Default for RawCopy {
+    fn default() -> Self {
+        RawCopy::new("default", Format::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use chrono::TimeZone;
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Format::Asd, "20240101T120000.000Z-000001.asd")]
+    #[case(Format::Opensky, "20240101T120000.000Z-000001.opensky")]
+    #[case(Format::None, "20240101T120000.000Z-000001.raw")]
+    fn test_chunk_name(#[case] fmt: Format, #[case] res: &str) {
+        let tm = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(res, chunk_name(tm, 1, fmt));
+        assert_eq!(Some((tm, 1)), parse_chunk_name(res));
+    }
+
+    #[test]
+    fn test_rawcopy_execute() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("raw");
+        let (tx, rx) = channel();
+
+        let mut raw = RawCopy::new("raw", Format::Asd);
+        raw.path(&out.to_string_lossy());
+        raw.execute("first".to_string(), tx.clone()).unwrap();
+        raw.execute("second".to_string(), tx).unwrap();
+
+        assert_eq!("first", rx.recv().unwrap());
+        assert_eq!("second", rx.recv().unwrap());
+
+        let mut files = fs::read_dir(&out)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(2, files.len());
+        assert!(files[0].to_string_lossy().ends_with("-000001.asd"));
+        assert_eq!("first", fs::read_to_string(&files[0]).unwrap());
+        assert_eq!("second", fs::read_to_string(&files[1]).unwrap());
+    }
+}
@@ -1,19 +1,24 @@
 //! `Read` is a `Runnable` task as defined in the `engine`  crate.
 //!
+//! Every file is sent as one block, like a `Fetch` does with a payload.  If the path is a
+//! directory, all the files in it are sent in name order, except the `Split` manifest: this is
+//! how chained jobs read the output of the job before them (see `chain.rs`).
+//!
+//! Binary Asterix Cat048 recordings are sent as text, one hex-encoded block per line.
+//!
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs;
 use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 
 use eyre::Result;
 use tracing::trace;
 
-use fetiche_formats::Format;
+use fetiche_formats::{Cat048, Format};
 use fetiche_macros::RunnableDerive;
 use fetiche_sources::Filter;
 
-use crate::{EngineStatus, Runnable, IO};
+use crate::{EngineStatus, Runnable, IO, MANIFEST};
 
 /// The Read task
 ///
@@ -75,19 +80,32 @@ impl Read {
     #[tracing::instrument]
     pub fn execute(&mut self, _data: String, stdout: Sender<String>) -> Result<()> {
         trace!("Read::transform()");
-        if self.path.is_none() || self.format == Format::None {
-            Err(EngineStatus::UninitialisedRead.into())
+        let (Some(p), false) = (&self.path, self.format == Format::None) else {
+            return Err(EngineStatus::UninitialisedRead.into());
+        };
+
+        let files = if p.is_dir() {
+            let mut files = fs::read_dir(p)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|f| f.is_file() && !f.ends_with(MANIFEST))
+                .collect::<Vec<_>>();
+            files.sort();
+            files
         } else {
-            let p = self.path.clone().unwrap();
-            let fh = File::open(p)?;
-            let bfh = BufReader::new(fh);
-
-            // Now send each line down the pipe
-            //
-            bfh.lines().for_each(|l| stdout.send(l.unwrap()).unwrap());
-
-            Ok(())
+            vec![p.clone()]
+        };
+
+        // Now send each file down the pipe
+        //
+        for f in files {
+            trace!("Read {:?}", f);
+            let data = match self.format {
+                Format::Cat048 => Cat048::to_text(&fs::read(f)?)?,
+                _ => fs::read_to_string(f)?,
+            };
+            stdout.send(data)?;
         }
+        Ok(())
     }
 }
 
@@ -130,4 +148,20 @@ mod tests {
         assert_eq!(Format::Asd, t.format);
         assert_eq!(PathBuf::from("../Cargo.toml"), t.path.unwrap());
     }
+
+    #[test]
+    fn test_read_dir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("b.csv"), "a\n2\n")?;
+        fs::write(dir.path().join("a.csv"), "a\n1\n")?;
+        fs::write(dir.path().join(MANIFEST), "{}")?;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut t = Read::new("foo");
+        t.path(&dir.path().to_string_lossy()).format(Format::Cat21);
+        t.execute(String::new(), tx)?;
+
+        assert_eq!(vec!["a\n1\n", "a\n2\n"], rx.iter().collect::<Vec<_>>());
+        Ok(())
+    }
 }
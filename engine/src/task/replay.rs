@@ -0,0 +1,264 @@
+//! `Replay` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This is a producer sending again payloads captured earlier, either by `RawDump` (files of
+//! framed chunks, see `read_frames()`) or by `RawCopy` (one file per chunk, named after the time
+//! it was received).  Both can be mixed in the same directory, every chunk is sent in the order
+//! it was received.
+//!
+//! The original timing is simulated by waiting between chunks as long as between their
+//! reception, divided by the speed: `10x` is ten times faster, `max` does not wait at all.  This
+//! gives reproducible end-to-end runs of a live pipeline, from conversion down to its sinks.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use eyre::Result;
+use tracing::{debug, trace};
+
+use fetiche_macros::RunnableDerive;
+
+use crate::{cancelled, parse_chunk_name, read_frames, EngineStatus, Frame, Runnable, IO};
+
+/// How fast we replay compared to the original capture
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Speed {
+    /// Same timing as the capture
+    #[default]
+    Real,
+    /// That many times faster (or slower if below 1)
+    Times(f64),
+    /// No waiting at all
+    Max,
+}
+
+impl Speed {
+    /// How long to wait for a gap of `gap` in the capture
+    ///
+    pub fn wait(&self, gap: chrono::Duration) -> std::time::Duration {
+        let gap = gap.to_std().unwrap_or_default();
+        match self {
+            Speed::Real => gap,
+            Speed::Times(x) => gap.div_f64(*x),
+            Speed::Max => std::time::Duration::ZERO,
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = EngineStatus;
+
+    /// `max`, `10x`, `0.5x` or just `10`
+    ///
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "max" {
+            return Ok(Speed::Max);
+        }
+        match s.strip_suffix('x').unwrap_or(s).parse::<f64>() {
+            Ok(1.) => Ok(Speed::Real),
+            Ok(x) if x.is_finite() && x > 0. => Ok(Speed::Times(x)),
+            _ => Err(EngineStatus::BadSpeed(s.to_string())),
+        }
+    }
+}
+
+impl Display for Speed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Speed::Real => write!(f, "1x"),
+            Speed::Times(x) => write!(f, "{x}x"),
+            Speed::Max => write!(f, "max"),
+        }
+    }
+}
+
+/// The Replay task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Replay {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Capture file or directory
+    pub path: PathBuf,
+    /// Replay speed
+    pub speed: Speed,
+}
+
+impl Replay {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str) -> Self {
+        trace!("New Replay {}", name);
+        Replay {
+            io: IO::Producer,
+            name: name.to_owned(),
+            path: PathBuf::from(name),
+            speed: Speed::default(),
+        }
+    }
+
+    /// Set the capture file or directory
+    ///
+    pub fn path(&mut self, name: &str) -> &mut Self {
+        trace!("Add path: {}", name);
+        self.path = PathBuf::from(name);
+        self
+    }
+
+    /// Set the replay speed
+    ///
+    pub fn speed(&mut self, speed: Speed) -> &mut Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Send every captured chunk down the pipe, waiting between them.
+    ///
+    #[tracing::instrument(skip(self, _data, stdout))]
+    pub fn execute(&mut self, _data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("Replay::execute()");
+
+        let frames = load_capture(&self.path)?;
+        debug!("{} chunks to replay at {}", frames.len(), self.speed);
+
+        let mut last = None;
+        for f in frames {
+            if let Some(last) = last {
+                thread::sleep(self.speed.wait(f.time - last));
+            }
+            if cancelled() {
+                debug!("replay cancelled");
+                break;
+            }
+            last = Some(f.time);
+            stdout.send(f.data)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Replay::new("default")
+    }
+}
+
+/// All the chunks of a capture file or directory, in the order they were received.
+///
+pub fn load_capture(path: &Path) -> Result<Vec<Frame>> {
+    let files = if path.is_dir() {
+        fs::read_dir(path)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|f| f.is_file())
+            .collect::<Vec<_>>()
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let mut frames = vec![];
+    for f in files {
+        let fname = f.file_name().unwrap_or_default().to_string_lossy();
+        match parse_chunk_name(&fname) {
+            Some((time, seq)) => frames.push(Frame {
+                time,
+                seq,
+                data: fs::read_to_string(&f)?,
+            }),
+            None => frames.extend(read_frames(&f)?),
+        }
+    }
+    if frames.is_empty() {
+        return Err(EngineStatus::NoCapture(path.to_string_lossy().to_string()).into());
+    }
+    frames.sort_by_key(|f| (f.time, f.seq));
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[rstest]
+    #[case("max", Speed::Max)]
+    #[case("1x", Speed::Real)]
+    #[case("10x", Speed::Times(10.))]
+    #[case("0.5x", Speed::Times(0.5))]
+    #[case("4", Speed::Times(4.))]
+    fn test_speed(#[case] s: &str, #[case] speed: Speed) {
+        assert_eq!(speed, s.parse::<Speed>().unwrap());
+        assert_eq!(speed, speed.to_string().parse::<Speed>().unwrap());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("0x")]
+    #[case("-2x")]
+    #[case("fast")]
+    fn test_speed_bad(#[case] s: &str) {
+        assert!(s.parse::<Speed>().is_err());
+    }
+
+    #[test]
+    fn test_speed_wait() {
+        let gap = chrono::Duration::seconds(10);
+        assert_eq!(Duration::from_secs(10), Speed::Real.wait(gap));
+        assert_eq!(Duration::from_secs(1), Speed::Times(10.).wait(gap));
+        assert_eq!(Duration::ZERO, Speed::Max.wait(gap));
+        assert_eq!(Duration::ZERO, Speed::Real.wait(-gap));
+    }
+
+    #[test]
+    fn test_replay_mixed() {
+        let dir = tempdir().unwrap();
+        let tm = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        // Chunks 1 and 3 from RawDump, 2 from RawCopy
+        //
+        fs::write(
+            dir.path().join("asd-20240101T120000Z.raw"),
+            "#FRAME 2024-01-01T12:00:00.000Z 1 5\nfirst\n#FRAME 2024-01-01T12:00:02.000Z 3 5\nthird\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("20240101T120001.000Z-000002.asd"), "second").unwrap();
+
+        let frames = load_capture(dir.path()).unwrap();
+        assert_eq!(3, frames.len());
+        assert_eq!(tm, frames[0].time);
+        assert_eq!(2, frames[1].seq);
+
+        let (tx, rx) = channel();
+        let mut t = Replay::new("replay");
+        t.path(&dir.path().to_string_lossy()).speed(Speed::Max);
+        t.execute(String::new(), tx).unwrap();
+        assert_eq!(
+            vec!["first", "second", "third"],
+            rx.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_replay_empty() {
+        let dir = tempdir().unwrap();
+        let (tx, _rx) = channel();
+
+        let mut t = Replay::new("replay");
+        t.path(&dir.path().to_string_lossy());
+        assert!(t.execute(String::new(), tx).is_err());
+    }
+}
@@ -1,18 +1,21 @@
 //! `Save` is a `Runnable` task as defined in the `engine`  crate.
 //!
-//! This is for saving data into a specific (or not) format like plain file (None) or Parquet.
+//! This is for saving data into a specific (or not) format like plain file (None), Parquet or
+//! GeoParquet (see `geoparquet.rs`), the latter optionally with a `<name>_trajectories` file next
+//! to it holding one line per track.  Parquet files can also have one row per track with its
+//! points (see `Layout` in `layout.rs`).
 //!
 
 use std::fs;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 
 use datafusion::config::TableParquetOptions;
 use datafusion::dataframe::DataFrameWriteOptions;
 use datafusion::prelude::{CsvReadOptions, SessionContext};
 use eyre::Result;
-use tempfile::Builder;
+use tempfile::{Builder, NamedTempFile};
 use tokio::runtime::Runtime;
 use tracing::{info, trace};
 
@@ -20,7 +23,9 @@ use fetiche_common::Container;
 use fetiche_formats::Format;
 use fetiche_macros::RunnableDerive;
 
-use crate::{EngineStatus, Runnable, IO};
+use super::geoparquet::{write_points, write_trajectories};
+use super::layout::write_tracks;
+use crate::{EngineStatus, Layout, Runnable, IO};
 
 /// The Save task
 ///
@@ -38,6 +43,12 @@ pub struct Save {
     pub out: Container,
     /// Optional arguments (usually json-encoded string)
     pub args: String,
+    /// Where to put temporary files, the job directory if set
+    pub tmpdir: Option<PathBuf>,
+    /// GeoParquet: also write one line per track
+    pub trajectories: bool,
+    /// Parquet: one row per position or per track
+    pub layout: Layout,
 }
 
 impl Save {
@@ -53,6 +64,9 @@ impl Save {
             inp,
             out,
             args: "".to_string(),
+            tmpdir: None,
+            trajectories: false,
+            layout: Layout::default(),
         }
     }
 
@@ -67,6 +81,39 @@ impl Save {
         self
     }
 
+    /// Use this directory for temporary files
+    ///
+    pub fn tmpdir(&mut self, dir: &Path) -> &mut Self {
+        trace!("Add tmpdir: {:?}", dir);
+        self.tmpdir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// GeoParquet: also write one line per track
+    ///
+    pub fn trajectories(&mut self, yes: bool) -> &mut Self {
+        self.trajectories = yes;
+        self
+    }
+
+    /// Parquet: one row per position or per track
+    ///
+    pub fn layout(&mut self, layout: Layout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Copy data into a temporary CSV file for datafusion.
+    ///
+    fn tmpfile(&self, data: &str) -> Result<NamedTempFile> {
+        let mut tmpf = match &self.tmpdir {
+            Some(dir) => Builder::new().suffix(".csv").tempfile_in(dir)?,
+            None => Builder::new().suffix(".csv").tempfile()?,
+        };
+        let _ = tmpf.write(data.as_bytes())?;
+        Ok(tmpf)
+    }
+
     /// The heart of the matter: save data
     ///
     #[tracing::instrument(skip(data))]
@@ -84,14 +131,22 @@ impl Save {
             match self.out {
                 // There we handle the combination of input & output formats
                 //
+                Container::Parquet if self.layout == Layout::Tracks => {
+                    let rule = self
+                        .inp
+                        .track_rule()
+                        .ok_or(EngineStatus::UnsupportedTrackLayout(self.inp.to_string()))?;
+                    trace!("from {}(csv) to parquet, one row per track", self.inp);
+
+                    write_tracks(&data, p, &rule)?;
+                }
                 Container::Parquet => match self.inp {
                     Format::Asd => {
                         trace!("from asd(csv) to parquet");
 
                         // Write into temporary file.
                         //
-                        let mut tmpf = Builder::new().suffix(".csv").tempfile()?;
-                        let _ = tmpf.write(data.as_bytes())?;
+                        let tmpf = self.tmpfile(&data)?;
 
                         let fname = tmpf.path().to_string_lossy().to_string();
                         info!("fname={}, p={}", fname, p);
@@ -106,6 +161,26 @@ impl Save {
                     }
                     _ => return Err(EngineStatus::OnlyAsdToParquet.into()),
                 },
+                Container::GeoParquet => {
+                    let rule = self
+                        .inp
+                        .track_rule()
+                        .ok_or(EngineStatus::UnsupportedGeoParquet(self.inp.to_string()))?;
+                    trace!("from {}(csv) to geoparquet", self.inp);
+
+                    let tmpf = self.tmpfile(&data)?;
+                    let fname = tmpf.path().to_string_lossy().to_string();
+                    info!("fname={}, p={}", fname, p);
+
+                    let rt = Runtime::new()?;
+                    rt.block_on(write_points(&fname, p, &rule))?;
+
+                    if self.trajectories {
+                        let t = trajectories_path(p);
+                        info!("trajectories into {}", t);
+                        write_trajectories(&data, &t, &rule)?;
+                    }
+                }
                 _ => {
                     trace!("raw data");
                     fs::write(PathBuf::from(p), &data)?
@@ -137,6 +212,18 @@ async fn write_parquet(from: &str, to: &str) -> Result<()> {
     Ok(())
 }
 
+/// `dir/name.ext` becomes `dir/name_trajectories.ext`
+///
+fn trajectories_path(p: &str) -> String {
+    let path = Path::new(p);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_trajectories.{}", stem, ext.to_string_lossy()),
+        None => format!("{}_trajectories", stem),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
 impl Default for Save {
     fn default() -> Self {
         Save::new("default", Format::None, Container::default())
@@ -145,6 +232,8 @@ impl Default for Save {
 
 #[cfg(test)]
 mod tests {
+    use rstest::rstest;
+
     use super::*;
 
     #[test]
@@ -172,4 +261,20 @@ mod tests {
         assert_eq!("foo", t.name);
         assert_eq!("../Cargo.toml", t.path.unwrap());
     }
+
+    #[rstest]
+    #[case("out.parquet", "out_trajectories.parquet")]
+    #[case("/data/drones.geoparquet", "/data/drones_trajectories.geoparquet")]
+    #[case("drones", "drones_trajectories")]
+    fn test_trajectories_path(#[case] p: &str, #[case] res: &str) {
+        assert_eq!(res, trajectories_path(p));
+    }
+
+    #[test]
+    fn test_write_geoparquet_unsupported() {
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let mut t = Save::new("foo", Format::Opensky, Container::GeoParquet);
+        t.path("/nonexistent");
+        assert!(t.execute("{}".to_string(), tx).is_err());
+    }
 }
@@ -0,0 +1,210 @@
+//! `Select` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This filter only keeps the records of some vehicle categories (see
+//! `fetiche_formats::VehicleCategory`), e.g. `uas` for every kind of drone.  The category comes
+//! from the emitter category for `Cat21`, from the drone model for `Asd` and `Senhive`.  The
+//! other records are counted as dropped by the filter.
+//!
+//! Like `Qc`, CSV-based formats are supported (`Asd` and `Cat21`), plus `Senhive` (one JSON
+//! message per line).
+//!
+
+use std::sync::mpsc::Sender;
+
+use csv::{ReaderBuilder, WriterBuilder};
+use eyre::Result;
+use tracing::{debug, trace};
+
+use fetiche_formats::{Format, Senhive, VehicleCategory};
+use fetiche_macros::RunnableDerive;
+
+use crate::{record_drop, DropReason, EngineStatus, Runnable, IO};
+
+/// The Select task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Select {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Input file format
+    pub inp: Format,
+    /// Categories we keep
+    pub categories: Vec<VehicleCategory>,
+}
+
+impl Select {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format) -> Self {
+        trace!("New Select {}", name);
+        Select {
+            io: IO::Filter,
+            name: name.to_owned(),
+            inp,
+            categories: vec![],
+        }
+    }
+
+    /// Set the categories we keep
+    ///
+    pub fn categories(&mut self, categories: &[VehicleCategory]) -> &mut Self {
+        self.categories = categories.to_vec();
+        self
+    }
+
+    /// Do we want this one?
+    ///
+    fn wanted(&self, cat: VehicleCategory) -> bool {
+        self.categories.iter().any(|w| cat.matches(*w))
+    }
+
+    /// Pass along the records of the wanted categories.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("Select::execute()");
+
+        let (data, dropped) = match self.inp {
+            Format::Asd | Format::Cat21 => self.select_csv(&data)?,
+            Format::Senhive => self.select_lines(&data),
+            _ => return Err(EngineStatus::UnsupportedSelect(self.inp.to_string()).into()),
+        };
+        if dropped > 0 {
+            debug!("{} records dropped", dropped);
+            record_drop(DropReason::Filter, dropped);
+        }
+        Ok(stdout.send(data)?)
+    }
+
+    /// CSV with a header, the category is in the model or emitter category column.
+    ///
+    fn select_csv(&self, data: &str) -> Result<(String, usize)> {
+        let (delim, column) = match self.inp {
+            Format::Cat21 => (b':', "EMITTER_CATEGORY"),
+            _ => (b',', "model"),
+        };
+
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(delim)
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let header = rdr.headers()?.clone();
+        let idx = header
+            .iter()
+            .position(|h| h == column)
+            .ok_or(EngineStatus::NoSelectColumn(column.to_string()))?;
+
+        let mut wtr = WriterBuilder::new().delimiter(delim).from_writer(vec![]);
+        wtr.write_record(&header)?;
+        let mut dropped = 0;
+        for rec in rdr.records() {
+            let rec = rec?;
+            let value = rec.get(idx).unwrap_or_default().trim();
+            let cat = match self.inp {
+                Format::Cat21 => VehicleCategory::from_emitter(value.parse().unwrap_or_default()),
+                _ => VehicleCategory::from_drone_model((!value.is_empty()).then_some(value)),
+            };
+            if self.wanted(cat) {
+                wtr.write_record(&rec)?;
+            } else {
+                dropped += 1;
+            }
+        }
+        Ok((String::from_utf8(wtr.into_inner()?)?, dropped))
+    }
+
+    /// One JSON message per line, lines we can not parse are dropped.
+    ///
+    fn select_lines(&self, data: &str) -> (String, usize) {
+        let mut dropped = 0;
+        let out = data
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .filter(|l| {
+                let keep = serde_json::from_str::<Senhive>(l)
+                    .map(|r| self.wanted(r.category()))
+                    .unwrap_or(false);
+                if !keep {
+                    dropped += 1;
+                }
+                keep
+            })
+            .map(|l| format!("{l}\n"))
+            .collect::<String>();
+        (out, dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    const ASD: &str = "journey,model,latitude,longitude
+1,Mavic 3,49.0,2.5
+2,eBee X,49.0,2.5
+3,,49.0,2.5
+";
+
+    const CAT21: &str = "TARGET_ADDR:EMITTER_CATEGORY:CALLSIGN
+39AC47:3:AFR123
+39AC48:10:FGHEL
+39AC49:13:
+";
+
+    #[rstest]
+    #[case(Format::Asd, ASD, &[VehicleCategory::Uas], 4)]
+    #[case(Format::Asd, ASD, &[VehicleCategory::UasFixedWing], 2)]
+    #[case(Format::Asd, ASD, &[VehicleCategory::FixedWing], 1)]
+    #[case(Format::Cat21, CAT21, &[VehicleCategory::Uas], 2)]
+    #[case(Format::Cat21, CAT21, &[VehicleCategory::FixedWing, VehicleCategory::Rotorcraft], 3)]
+    fn test_select_csv(
+        #[case] fmt: Format,
+        #[case] data: &str,
+        #[case] cats: &[VehicleCategory],
+        #[case] lines: usize,
+    ) {
+        let (tx, rx) = channel();
+        let mut t = Select::new("foo", fmt);
+        t.categories(cats);
+        t.execute(data.to_string(), tx).unwrap();
+        assert_eq!(lines, rx.recv().unwrap().lines().count());
+    }
+
+    #[test]
+    fn test_select_senhive() {
+        let line = |model: &str| {
+            format!(
+                r#"{{"schema":"fusion/1.0","trackId":"1","timestamp":"2024-01-01T00:00:00Z","sources":[],"drone":{{"model":"{model}"}},"position":{{"latitude":49.0,"longitude":2.5}}}}"#
+            )
+        };
+        let data = [line("Mavic 3"), line("eBee X"), "garbage".to_string()].join("\n");
+
+        let (tx, rx) = channel();
+        let mut t = Select::new("foo", Format::Senhive);
+        t.categories(&[VehicleCategory::UasMultiRotor]);
+        t.execute(data, tx).unwrap();
+        let out = rx.recv().unwrap();
+        assert_eq!(1, out.lines().count());
+        assert!(out.contains("Mavic"));
+    }
+
+    #[test]
+    fn test_select_unsupported() {
+        let (tx, _rx) = channel();
+        let mut t = Select::new("foo", Format::Opensky);
+        assert!(t.execute(String::new(), tx).is_err());
+
+        let (tx, _rx) = channel();
+        let mut t = Select::new("foo", Format::Asd);
+        assert!(t
+            .execute("journey,latitude\n1,49.0\n".to_string(), tx)
+            .is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! `Serve` is a consumer sending everything it receives to all TCP clients connected to a given
+//! address, like `dump1090` does with SBS-1 messages on port 30003.
+//!
+//! Put a `Convert` into `Sbs1` before it and tools like Virtual Radar Server can use fetiche as
+//! a BaseStation feed.  Clients connecting late only get data from then on, clients going away
+//! are forgotten.  The listener is kept until the program exits.
+//!
+//! In SSE mode (`Serve::sse()`), it is a minimal HTTP server instead:
+//!
+//! - `GET /events` is a Server-Sent Events stream with one event per line of data,
+//! - `GET /` is a Leaflet page displaying the `GeoJson` features received on `/events`.
+//!
+//! This module is data-agnostic and does not care whether it is JSON, CSV or SBS-1.
+//!
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use eyre::Result;
+use tracing::{debug, info, trace};
+
+use fetiche_macros::RunnableDerive;
+
+use crate::{Runnable, IO};
+
+/// Live map served on `/` in SSE mode
+const MAP: &str = include_str!("map.html");
+
+/// How long we wait for an HTTP client to send its request
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The Serve task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Serve {
+    /// I/O capabilities
+    io: IO,
+    /// Address we are listening on
+    addr: SocketAddr,
+    /// Server-Sent Events over HTTP instead of raw TCP
+    sse: bool,
+    /// Connected clients
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Serve {
+    /// Start listening on `addr` (like `0.0.0.0:30003`) and accepting clients in the background.
+    ///
+    #[tracing::instrument]
+    pub fn new(addr: &str) -> Result<Self> {
+        trace!("serve::new");
+        Self::listen(addr, false)
+    }
+
+    /// Same as `new()` but as an HTTP server with Server-Sent Events on `/events`.
+    ///
+    #[tracing::instrument]
+    pub fn sse(addr: &str) -> Result<Self> {
+        trace!("serve::sse");
+        Self::listen(addr, true)
+    }
+
+    fn listen(addr: &str, sse: bool) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        info!("Serving on {}", addr);
+
+        let clients = Arc::new(Mutex::new(vec![]));
+        let list = clients.clone();
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                debug!("new client {:?}", client.peer_addr());
+                let _ = client.set_nodelay(true);
+                if !sse {
+                    list.lock().unwrap().push(client);
+                    continue;
+                }
+                match answer(client) {
+                    Ok(Some(client)) => list.lock().unwrap().push(client),
+                    Ok(None) => (),
+                    Err(e) => debug!("serve: {}", e),
+                }
+            }
+        });
+
+        Ok(Serve {
+            io: IO::Consumer,
+            addr,
+            sse,
+            clients,
+        })
+    }
+
+    /// Actual address, useful when binding to port 0
+    ///
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send data to every client, forgetting those who are gone
+    ///
+    #[tracing::instrument(skip(self, data, _stdout))]
+    pub fn execute(&mut self, data: String, _stdout: Sender<String>) -> Result<()> {
+        trace!("serve::execute");
+
+        let data = if self.sse { events(&data) } else { data };
+        self.clients.lock().unwrap().retain_mut(|client| {
+            let ok = client.write_all(data.as_bytes()).is_ok();
+            if !ok {
+                debug!("client {:?} gone", client.peer_addr());
+            }
+            ok
+        });
+        Ok(())
+    }
+}
+
+/// One SSE event per non-empty line
+///
+fn events(data: &str) -> String {
+    data.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| format!("data: {}\n\n", l))
+        .collect()
+}
+
+/// Answer an HTTP client, returning it if it wants the events.
+///
+fn answer(mut client: TcpStream) -> Result<Option<TcpStream>> {
+    client.set_read_timeout(Some(HTTP_TIMEOUT))?;
+
+    let mut rdr = BufReader::new(&client);
+    let mut request = String::new();
+    rdr.read_line(&mut request)?;
+    trace!("serve: {}", request.trim_end());
+
+    // Skip headers
+    //
+    let mut line = String::new();
+    while rdr.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, ctype, body) = match path {
+        "/events" => {
+            write!(
+                client,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nAccess-Control-Allow-Origin: *\r\n\r\n"
+            )?;
+            client.flush()?;
+            client.set_read_timeout(None)?;
+            return Ok(Some(client));
+        }
+        "/" | "/index.html" => ("200 OK", "text/html", MAP),
+        _ => ("404 Not Found", "text/plain", "not found"),
+    };
+    write!(
+        client,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        ctype,
+        body.len(),
+        body
+    )?;
+    client.flush()?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    #[test]
+    fn test_serve_clients() -> Result<()> {
+        let mut serve = Serve::new("127.0.0.1:0")?;
+        let addr = serve.local_addr();
+
+        let one = TcpStream::connect(addr)?;
+        let two = TcpStream::connect(addr)?;
+        while serve.clients.lock().unwrap().len() < 2 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(two);
+
+        let (tx, _rx) = channel();
+        serve.execute("MSG,1\r\n".to_string(), tx.clone())?;
+        serve.execute("MSG,3\r\n".to_string(), tx)?;
+
+        let mut lines = BufReader::new(one).lines();
+        assert_eq!("MSG,1", lines.next().unwrap()?);
+        assert_eq!("MSG,3", lines.next().unwrap()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_sse() -> Result<()> {
+        let mut serve = Serve::sse("127.0.0.1:0")?;
+        let addr = serve.local_addr();
+
+        let mut page = TcpStream::connect(addr)?;
+        write!(page, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        let mut resp = String::new();
+        page.read_to_string(&mut resp)?;
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.contains("EventSource"));
+
+        let mut client = TcpStream::connect(addr)?;
+        write!(client, "GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+        while serve.clients.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (tx, _rx) = channel();
+        serve.execute("{\"id\":1}\n\n{\"id\":2}\n".to_string(), tx)?;
+
+        let lines = BufReader::new(client)
+            .lines()
+            .map_while(|l| l.ok())
+            .skip_while(|l| !l.is_empty())
+            .filter(|l| !l.is_empty())
+            .take(2)
+            .collect::<Vec<_>>();
+        assert_eq!(["data: {\"id\":1}", "data: {\"id\":2}"], lines[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_bad_addr() {
+        assert!(Serve::new("nowhere:30003").is_err());
+    }
+}
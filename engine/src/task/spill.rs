@@ -0,0 +1,444 @@
+//! `Spill` wraps any sink so that a stalled sink (a slow database, a network peer) does not hold
+//! the whole pipeline back and keep growing in memory.
+//!
+//! Up to `depth` batches wait in memory for the sink.  When it does not keep up, the next ones
+//! are written into the spill directory, one file per batch, and sent in order as soon as the
+//! sink takes data again.  The spill is bounded by `max` bytes: once full, we wait for the sink
+//! like without a spill.  Nothing is ever dropped.
+//!
+//! This is not a write-ahead log: while nothing is spilled, batches go straight to the sink and
+//! those waiting in memory are lost if the process dies.  Spilled batches stay on disk until the
+//! sink has taken them, those left by a previous run (the sink died or the job was killed) are
+//! sent first.
+//!
+//! The oldest spilled batch is read once and kept in memory until the sink takes it, a stalled
+//! sink does not make us read it again for every batch coming in.
+//!
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use eyre::Result;
+use tracing::{error, info, trace, warn};
+
+use crate::space::human;
+use crate::{Cancel, EngineStatus, Metrics, Runnable, Tuning, IO};
+
+/// Largest spill by default, 1 GB
+pub const SPILL_MAX: u64 = 1 << 30;
+
+/// Batches waiting in memory before we spill
+const SPILL_DEPTH: usize = 4;
+
+/// How often we try to send spilled batches while nothing comes in
+const SPILL_RETRY: Duration = Duration::from_millis(200);
+
+/// Extension of spilled batches
+const SPILL_EXT: &str = "spill";
+
+/// The Spill wrapper
+///
+#[derive(Debug)]
+pub struct Spill {
+    /// The wrapped sink
+    inner: Box<dyn Runnable>,
+    /// Where batches are spilled
+    pub dir: PathBuf,
+    /// Largest spill in bytes
+    pub max: u64,
+    /// Batches waiting in memory for the sink
+    pub depth: usize,
+}
+
+impl Spill {
+    /// Wrap `inner`, spilling into `dir`
+    ///
+    #[tracing::instrument]
+    pub fn new(inner: Box<dyn Runnable>, dir: &Path) -> Self {
+        trace!("New Spill for {}", inner.name());
+        Spill {
+            inner,
+            dir: dir.to_path_buf(),
+            max: SPILL_MAX,
+            depth: SPILL_DEPTH,
+        }
+    }
+
+    /// Set the largest spill in bytes
+    ///
+    pub fn max(&mut self, max: u64) -> &mut Self {
+        self.max = max;
+        self
+    }
+
+    /// Set the number of batches waiting in memory
+    ///
+    pub fn depth(&mut self, depth: usize) -> &mut Self {
+        self.depth = depth.max(1);
+        self
+    }
+}
+
+/// Not derived, the sink runs in its own thread and we feed it from ours.  An error of the sink
+/// is returned rather than ours, it is why we stopped.
+///
+impl Runnable for Spill {
+    fn cap(&self) -> IO {
+        self.inner.cap()
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn run(
+        &mut self,
+        input: Receiver<String>,
+        stage: Metrics,
+        tuning: Tuning,
+        cancel: Cancel,
+    ) -> (Receiver<String>, JoinHandle<Result<()>>) {
+        let (tx, rx) = sync_channel::<String>(self.depth);
+        let (out, sink) = self.inner.run(rx, stage.clone(), tuning, cancel.clone());
+
+        let name = self.inner.name();
+        let (dir, max) = (self.dir.clone(), self.max);
+        let h = thread::spawn(move || {
+            trace!("Runnable(Spill) for {}", name);
+            stage.enter();
+            cancel.enter();
+
+            let res = SpillQueue::open(&name, &dir, max).and_then(|mut q| q.feed(input, tx));
+            if let Err(e) = &res {
+                error!("Spill: {}", e);
+            }
+            match sink.join() {
+                Ok(Ok(())) => res,
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(EngineStatus::TaskPanicked(name).into()),
+            }
+        });
+        (out, h)
+    }
+}
+
+/// Batches on disk, oldest first
+///
+#[derive(Debug)]
+struct SpillQueue {
+    /// Name of the sink
+    name: String,
+    /// Spill directory
+    dir: PathBuf,
+    /// Largest spill in bytes
+    max: u64,
+    /// Current size
+    size: u64,
+    /// Sequence number of the next batch
+    next: u64,
+    /// Spilled batches and their size
+    files: VecDeque<(PathBuf, u64)>,
+    /// Content of the oldest spilled batch once read
+    head: Option<String>,
+    /// Number of batches spilled since the sink last caught up
+    spilled: usize,
+}
+
+impl SpillQueue {
+    /// Create the directory or pick up what is left in it
+    ///
+    fn open(name: &str, dir: &Path, max: u64) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .map_err(|_| EngineStatus::CreateDir(dir.to_string_lossy().to_string()))?;
+
+        let mut left = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter_map(|p| {
+                let seq = p
+                    .extension()
+                    .filter(|ext| *ext == SPILL_EXT)
+                    .and_then(|_| p.file_stem()?.to_str()?.parse::<u64>().ok())?;
+                let size = p.metadata().ok()?.len();
+                Some((seq, p, size))
+            })
+            .collect::<Vec<_>>();
+        left.sort_by_key(|(seq, ..)| *seq);
+
+        let q = SpillQueue {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            max,
+            size: left.iter().map(|(.., size)| size).sum(),
+            next: left.last().map(|(seq, ..)| seq + 1).unwrap_or(0),
+            spilled: left.len(),
+            files: left.into_iter().map(|(_, p, size)| (p, size)).collect(),
+            head: None,
+        };
+        if !q.files.is_empty() {
+            info!(
+                "{}: {} batches left in {:?} sent first",
+                name,
+                q.files.len(),
+                dir
+            );
+        }
+        Ok(q)
+    }
+
+    /// Pass everything from `input` to the sink behind `tx`, spilling when it is full.
+    ///
+    fn feed(&mut self, input: Receiver<String>, tx: SyncSender<String>) -> Result<()> {
+        loop {
+            self.drain(&tx)?;
+
+            let msg = if self.files.is_empty() {
+                input.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                input.recv_timeout(SPILL_RETRY)
+            };
+            match msg {
+                Ok(data) if self.files.is_empty() => match tx.try_send(data) {
+                    Ok(()) => (),
+                    Err(TrySendError::Full(data)) => {
+                        info!(
+                            "{} does not keep up, spilling into {:?}",
+                            self.name, self.dir
+                        );
+                        self.spill(data, &tx)?
+                    }
+                    Err(TrySendError::Disconnected(_)) => return Err(self.stopped()),
+                },
+                Ok(data) => self.spill(data, &tx)?,
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        // Nothing more is coming, wait for the sink to take what is left
+        //
+        while !self.files.is_empty() {
+            self.send_first(&tx)?;
+        }
+        Ok(())
+    }
+
+    /// Send spilled batches while the sink takes them
+    ///
+    fn drain(&mut self, tx: &SyncSender<String>) -> Result<()> {
+        while let Some(data) = self.first()? {
+            match tx.try_send(data) {
+                Ok(()) => self.pop()?,
+                Err(TrySendError::Full(data)) => {
+                    self.head = Some(data);
+                    return Ok(());
+                }
+                Err(TrySendError::Disconnected(_)) => return Err(self.stopped()),
+            }
+        }
+        if self.spilled > 0 {
+            info!(
+                "{} caught up, {} batches were spilled",
+                self.name, self.spilled
+            );
+            self.spilled = 0;
+        }
+        Ok(())
+    }
+
+    /// Write `data` after the other spilled batches, waiting for the sink to make room if the
+    /// spill is full.
+    ///
+    fn spill(&mut self, data: String, tx: &SyncSender<String>) -> Result<()> {
+        let len = data.len() as u64;
+        if self.size + len > self.max {
+            warn!("Spill of {} full ({}), waiting", self.name, human(self.max));
+        }
+        while self.size + len > self.max && !self.files.is_empty() {
+            self.send_first(tx)?;
+        }
+        if self.files.is_empty() && len > self.max {
+            return tx.send(data).map_err(|_| self.stopped());
+        }
+
+        let path = self.dir.join(format!("{:012}.{SPILL_EXT}", self.next));
+        fs::write(&path, data)?;
+        self.next += 1;
+        self.size += len;
+        self.spilled += 1;
+        self.files.push_back((path, len));
+        Ok(())
+    }
+
+    /// Wait for the sink to take the oldest spilled batch
+    ///
+    fn send_first(&mut self, tx: &SyncSender<String>) -> Result<()> {
+        if let Some(data) = self.first()? {
+            tx.send(data).map_err(|_| self.stopped())?;
+            self.pop()?;
+        }
+        Ok(())
+    }
+
+    /// Oldest spilled batch, read from disk only the first time
+    ///
+    fn first(&mut self) -> Result<Option<String>> {
+        match (self.head.take(), self.files.front()) {
+            (Some(data), _) => Ok(Some(data)),
+            (None, Some((path, _))) => Ok(Some(fs::read_to_string(path)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// The oldest batch has been taken
+    ///
+    fn pop(&mut self) -> Result<()> {
+        self.head = None;
+        if let Some((path, len)) = self.files.pop_front() {
+            fs::remove_file(path)?;
+            self.size -= len;
+        }
+        Ok(())
+    }
+
+    /// The sink is gone, spilled batches stay where they are
+    ///
+    fn stopped(&self) -> eyre::Report {
+        EngineStatus::SinkStopped(
+            self.name.clone(),
+            self.files.len(),
+            self.dir.to_string_lossy().to_string(),
+        )
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::{Arc, Mutex};
+
+    use fetiche_macros::RunnableDerive;
+
+    use super::*;
+
+    /// Sink taking its time
+    ///
+    #[derive(Clone, Debug, RunnableDerive)]
+    struct Slow {
+        io: IO,
+        delay: Duration,
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Slow {
+        fn new(delay: u64) -> Self {
+            Slow {
+                io: IO::Consumer,
+                delay: Duration::from_millis(delay),
+                seen: Arc::new(Mutex::new(vec![])),
+            }
+        }
+
+        fn execute(&mut self, data: String, _stdout: Sender<String>) -> Result<()> {
+            thread::sleep(self.delay);
+            self.seen.lock().unwrap().push(data);
+            Ok(())
+        }
+    }
+
+    fn batches(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("batch {i}\n")).collect()
+    }
+
+    /// Send `data` through `spill`, returns once everything has been taken
+    ///
+    fn run(spill: &mut Spill, data: &[String]) -> Result<()> {
+        let (tx, rx) = channel();
+        let (out, h) = spill.run(rx, Metrics::new("test"), Tuning::default(), Cancel::new());
+        data.iter().for_each(|d| tx.send(d.clone()).unwrap());
+        drop(tx);
+        let res = h.join().unwrap();
+        for _ in out {}
+        res
+    }
+
+    #[test]
+    fn test_spill_slow_sink() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sink = Slow::new(10);
+        let seen = Arc::clone(&sink.seen);
+
+        let mut spill = Spill::new(Box::new(sink), dir.path());
+        spill.depth(1);
+        let data = batches(20);
+        run(&mut spill, &data)?;
+
+        assert_eq!(data, *seen.lock().unwrap());
+        assert_eq!(0, fs::read_dir(dir.path())?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_full() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let sink = Slow::new(5);
+        let seen = Arc::clone(&sink.seen);
+
+        let mut spill = Spill::new(Box::new(sink), dir.path());
+        spill.depth(1).max(20);
+        let data = batches(10);
+        run(&mut spill, &data)?;
+
+        assert_eq!(data, *seen.lock().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_drain_reads_once() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("000000000000.spill");
+        fs::write(&path, "old 0\n")?;
+
+        let mut q = SpillQueue::open("test", dir.path(), SPILL_MAX)?;
+        let (tx, rx) = sync_channel(1);
+        tx.send("busy\n".to_string())?;
+
+        // The sink is stalled, the batch is kept once read
+        //
+        q.drain(&tx)?;
+        fs::write(&path, "changed\n")?;
+        q.drain(&tx)?;
+
+        assert_eq!("busy\n", rx.recv()?);
+        q.drain(&tx)?;
+        assert_eq!("old 0\n", rx.recv()?);
+        assert!(q.files.is_empty());
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_leftovers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("000000000001.spill"), "old 1\n")?;
+        fs::write(dir.path().join("000000000000.spill"), "old 0\n")?;
+        fs::write(dir.path().join("README"), "not a batch")?;
+
+        let sink = Slow::new(0);
+        let seen = Arc::clone(&sink.seen);
+        let mut spill = Spill::new(Box::new(sink), dir.path());
+        run(&mut spill, &batches(1))?;
+
+        assert_eq!(
+            vec!["old 0\n", "old 1\n", "batch 0\n"],
+            *seen.lock().unwrap()
+        );
+        assert!(dir.path().join("README").exists());
+        Ok(())
+    }
+}
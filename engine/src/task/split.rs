@@ -0,0 +1,344 @@
+//! `Split` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This is a final stage like `Save` but instead of writing everything into a single file, it
+//! demultiplexes the records into one CSV file per key (icao24, callsign, journey or track) inside
+//! an output directory.  A `manifest.json` file is (re)written after each batch of data with
+//! the list of files, by key, and the number of records in each.
+//!
+//! Keys are sanitised into file names, two keys giving the same name (`A/B` and `A_B`) get
+//! different files with a `-N` suffix.  Records without a key are listed under the empty key,
+//! in `unknown.csv` unless a real `unknown` key got it first.
+//!
+//! Only CSV-based formats are supported (`Asd` and `Cat21`), use `--into` to convert other
+//! formats first.
+//!
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+
+use chrono::Utc;
+use csv::{ReaderBuilder, WriterBuilder};
+use eyre::Result;
+use serde::Serialize;
+use strum::EnumString;
+use tracing::{debug, trace};
+
+use fetiche_formats::{Format, TRACK_ID};
+use fetiche_macros::SinkDerive;
+
+use crate::{EngineStatus, Runnable, IO};
+
+/// Name of the file describing the content of the output directory.
+pub const MANIFEST: &str = "manifest.json";
+
+/// Which key do we use to split records.
+///
+#[derive(Clone, Copy, Debug, Default, EnumString, PartialEq, Serialize, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SplitBy {
+    /// Aircraft address (or drone identifier)
+    #[default]
+    Icao24,
+    /// Callsign
+    Callsign,
+    /// Drone journey
+    Journey,
+    /// Normalised track, see `Track`
+    Track,
+}
+
+impl SplitBy {
+    /// Return the name of the CSV column holding the key for the given format, if any.
+    ///
+    pub fn column(&self, fmt: Format) -> Option<&'static str> {
+        match (fmt, self) {
+            (Format::Asd, SplitBy::Icao24) => Some("ident"),
+            (Format::Asd, SplitBy::Journey) => Some("journey"),
+            (Format::Cat21, SplitBy::Icao24) => Some("TARGET_ADDR"),
+            (Format::Cat21, SplitBy::Callsign) => Some("CALLSIGN"),
+            (Format::Asd | Format::Cat21, SplitBy::Track) => Some(TRACK_ID),
+            _ => None,
+        }
+    }
+}
+
+/// One entry per output file in the manifest.
+///
+#[derive(Clone, Debug, Serialize)]
+pub struct SplitEntry {
+    /// File name, relative to the output directory
+    pub file: String,
+    /// Number of records
+    pub records: usize,
+}
+
+/// Content of `manifest.json`
+///
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    split_by: SplitBy,
+    format: String,
+    created_at: String,
+    files: &'a BTreeMap<String, SplitEntry>,
+}
+
+/// The Split task
+///
+#[derive(Clone, Debug, SinkDerive)]
+pub struct Split {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Output directory
+    pub path: PathBuf,
+    /// Input file format
+    pub inp: Format,
+    /// Splitting key
+    pub by: SplitBy,
+    /// What we have written so far
+    pub files: BTreeMap<String, SplitEntry>,
+}
+
+impl Split {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format, by: SplitBy) -> Self {
+        trace!("New Split {}", name);
+        Split {
+            io: IO::Consumer,
+            name: name.to_owned(),
+            path: PathBuf::from(name),
+            inp,
+            by,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Set the output directory
+    ///
+    pub fn path(&mut self, name: &str) -> &mut Self {
+        trace!("Add path: {}", name);
+        self.path = PathBuf::from(name);
+        self
+    }
+
+    /// Demultiplex the incoming data into the per-key files.
+    ///
+    #[tracing::instrument(skip(self, data))]
+    pub fn write_batch(&mut self, data: String) -> Result<()> {
+        trace!("Split::write_batch()");
+
+        let column = match self.by.column(self.inp) {
+            Some(column) => column,
+            None => {
+                return Err(EngineStatus::UnsupportedSplit(
+                    self.by.to_string(),
+                    self.inp.to_string(),
+                )
+                .into())
+            }
+        };
+        let delim = match self.inp {
+            Format::Cat21 => b':',
+            _ => b',',
+        };
+
+        if !self.path.exists() {
+            fs::create_dir_all(&self.path)
+                .map_err(|_| EngineStatus::CreateDir(self.path.to_string_lossy().to_string()))?;
+        }
+
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(delim)
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let header = rdr.headers()?.clone();
+        let idx = header
+            .iter()
+            .position(|h| h == column)
+            .ok_or(EngineStatus::NoSplitColumn(column.to_string()))?;
+
+        // Group records first, this way we open each file only once per batch.
+        //
+        let mut groups = BTreeMap::<String, Vec<csv::StringRecord>>::new();
+        for rec in rdr.records() {
+            let rec = rec?;
+            let key = rec.get(idx).unwrap_or_default().trim().to_string();
+            groups.entry(key).or_default().push(rec);
+        }
+
+        for (key, recs) in groups {
+            let file = match self.files.get(&key) {
+                Some(entry) => entry.file.clone(),
+                None => self.file_for(&key),
+            };
+            let fname = self.path.join(&file);
+            let new = !fname.exists();
+
+            debug!("{} records into {:?}", recs.len(), fname);
+            let fh = OpenOptions::new().create(true).append(true).open(&fname)?;
+            let mut wtr = WriterBuilder::new().delimiter(delim).from_writer(fh);
+            if new {
+                wtr.write_record(&header)?;
+            }
+            for rec in recs.iter() {
+                wtr.write_record(rec)?;
+            }
+            wtr.flush()?;
+
+            self.files
+                .entry(key)
+                .or_insert(SplitEntry { file, records: 0 })
+                .records += recs.len();
+        }
+        self.write_manifest()
+    }
+
+    /// File for a new key, its sanitised name made unique among the files already used.
+    ///
+    fn file_for(&self, key: &str) -> String {
+        let base = match key {
+            "" => "unknown".to_string(),
+            key => sanitise(key),
+        };
+        let used = |file: &str| self.files.values().any(|e| e.file == file);
+
+        let mut file = format!("{base}.csv");
+        let mut n = 1;
+        while used(&file) {
+            file = format!("{base}-{n}.csv");
+            n += 1;
+        }
+        file
+    }
+
+    /// (Re)write the manifest
+    ///
+    fn write_manifest(&self) -> Result<()> {
+        let manifest = Manifest {
+            split_by: self.by,
+            format: self.inp.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            files: &self.files,
+        };
+        let str = serde_json::to_string_pretty(&manifest)?;
+        fs::write(self.path.join(MANIFEST), str)?;
+        Ok(())
+    }
+}
+
+/// Keys end up as filenames so keep them sane.
+///
+fn sanitise(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        Split::new("default", Format::None, SplitBy::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[rstest]
+    #[case("icao24", SplitBy::Icao24)]
+    #[case("callsign", SplitBy::Callsign)]
+    #[case("journey", SplitBy::Journey)]
+    #[case("track", SplitBy::Track)]
+    fn test_splitby_from_str(#[case] s: &str, #[case] by: SplitBy) {
+        assert_eq!(by, SplitBy::from_str(s).unwrap());
+    }
+
+    #[rstest]
+    #[case(Format::Asd, SplitBy::Journey, Some("journey"))]
+    #[case(Format::Asd, SplitBy::Callsign, None)]
+    #[case(Format::Cat21, SplitBy::Callsign, Some("CALLSIGN"))]
+    #[case(Format::Cat21, SplitBy::Track, Some("track_id"))]
+    #[case(Format::Opensky, SplitBy::Icao24, None)]
+    fn test_splitby_column(#[case] fmt: Format, #[case] by: SplitBy, #[case] res: Option<&str>) {
+        assert_eq!(res, by.column(fmt));
+    }
+
+    #[test]
+    fn test_sanitise() {
+        assert_eq!("AFR_123", sanitise("AFR 123"));
+        assert_eq!("__etc_passwd", sanitise("/.etc/passwd"));
+    }
+
+    #[test]
+    fn test_split_write_batch() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out");
+        let data = "journey,ident,latitude\n1,A,1.0\n2,B,2.0\n1,A,3.0\n".to_string();
+
+        let mut t = Split::new("foo", Format::Asd, SplitBy::Journey);
+        t.path(&path.to_string_lossy());
+        t.write_batch(data.clone()).unwrap();
+        t.write_batch(data).unwrap();
+
+        let one = fs::read_to_string(path.join("1.csv")).unwrap();
+        assert_eq!(5, one.lines().count());
+        assert!(one.starts_with("journey,ident,latitude\n"));
+        assert_eq!(4, t.files["1"].records);
+        assert_eq!(2, t.files["2"].records);
+
+        let manifest = fs::read_to_string(path.join(MANIFEST)).unwrap();
+        assert!(manifest.contains("\"split_by\": \"journey\""));
+        assert!(manifest.contains("\"2.csv\""));
+    }
+
+    #[test]
+    fn test_split_collisions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("out");
+        let data = "journey,ident\nA/B,1\nA_B,2\nunknown,3\n,4\nA/B,5\n".to_string();
+
+        let mut t = Split::new("foo", Format::Asd, SplitBy::Journey);
+        t.path(&path.to_string_lossy());
+        t.write_batch(data).unwrap();
+        t.write_batch("journey,ident\nA_B,6\n".to_string()).unwrap();
+
+        let files = t
+            .files
+            .iter()
+            .map(|(k, e)| (k.as_str(), e.file.as_str(), e.records))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("", "unknown.csv", 1),
+                ("A/B", "A_B.csv", 2),
+                ("A_B", "A_B-1.csv", 2),
+                ("unknown", "unknown-1.csv", 1),
+            ],
+            files
+        );
+        let ab = fs::read_to_string(path.join("A_B-1.csv")).unwrap();
+        assert_eq!("journey,ident\nA_B,2\nA_B,6\n", ab);
+    }
+
+    #[test]
+    fn test_split_unsupported() {
+        let dir = tempdir().unwrap();
+
+        let mut t = Split::new("foo", Format::Opensky, SplitBy::Icao24);
+        t.path(&dir.path().to_string_lossy());
+        assert!(t.write_batch("{}".to_string()).is_err());
+    }
+}
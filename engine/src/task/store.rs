@@ -11,13 +11,12 @@
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
-use std::sync::mpsc::Sender;
 
 use chrono::{Datelike, Timelike, Utc};
 use eyre::Result;
 use tracing::{error, trace};
 
-use fetiche_macros::RunnableDerive;
+use fetiche_macros::SinkDerive;
 
 use crate::{EngineStatus, Runnable, IO};
 
@@ -26,7 +25,7 @@ use crate::{EngineStatus, Runnable, IO};
 /// We currently do not cache the open file for the current output, we might
 /// do that in the future but the cost is 2 more syscalls but simplified code.
 ///
-#[derive(Clone, Debug, RunnableDerive)]
+#[derive(Clone, Debug, SinkDerive)]
 pub struct Store {
     /// IO Capability
     io: IO,
@@ -122,9 +121,9 @@ impl Store {
     /// Store and rotate every hour for now.  We open/create and write every packet without
     /// trying to open first.  More syscalls but these are cheap.
     ///
-    #[tracing::instrument(skip(self, _stdout))]
-    pub fn execute(&mut self, data: String, _stdout: Sender<String>) -> Result<()> {
-        trace!("store::execute");
+    #[tracing::instrument(skip(self, data))]
+    pub fn write_batch(&mut self, data: String) -> Result<()> {
+        trace!("store::write_batch");
 
         let tm = Utc::now();
 
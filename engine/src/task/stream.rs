@@ -1,17 +1,33 @@
 //! `Stream` is a `Runnable` task as defined in the `engine`  crate.
 //!
+//! With a `SpaceMonitor`, what is received while free space is critical is dropped instead of
+//! being passed along, the stream itself keeps running and resumes as soon as there is enough
+//! space again.
+//!
+//! Sites stream until nobody takes what they send, what they send goes through a relay which
+//! stops as soon as the job is cancelled (see `Cancel`).
+//!
+//! The relay also compares every JSON batch with the schema of the site format (see `Drift` in
+//! `fetiche-formats`) and warns as soon as a provider sends unknown fields, drops expected ones
+//! or changes their type, then again whenever the drift changes.
+//!
 
 use std::fmt::{Debug, Formatter};
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{channel, Sender};
 use std::sync::Arc;
+use std::thread;
 
 use eyre::Result;
-use tracing::trace;
+use tracing::{debug, info, trace, warn};
 
+use fetiche_formats::DriftWatch;
 use fetiche_macros::RunnableDerive;
 use fetiche_sources::{Filter, Flow, Site, Sources};
 
-use crate::{EngineStatus, Runnable, IO};
+use crate::{
+    cancelled, records, with_token, Cancel, DropReason, EngineStatus, Metrics, Runnable,
+    SpaceLevel, SpaceMonitor, TokenRefresher, IO,
+};
 
 /// The Stream task
 ///
@@ -29,6 +45,10 @@ pub struct Stream {
     pub every: usize,
     /// Optional arguments (usually json-encoded string)
     pub args: String,
+    /// Pause when free space is critical
+    pub space: Option<Arc<SpaceMonitor>>,
+    /// Get a new token when the site rejects ours
+    pub tokens: Option<Arc<TokenRefresher>>,
 }
 
 impl Debug for Stream {
@@ -40,6 +60,8 @@ impl Debug for Stream {
             .field("srcs", &self.srcs)
             .field("every", &self.every)
             .field("args", &self.args)
+            .field("space", &self.space.is_some())
+            .field("tokens", &self.tokens.is_some())
             .finish()
     }
 }
@@ -57,6 +79,8 @@ impl Stream {
             srcs: Arc::clone(&srcs),
             args: "".to_string(),
             every: 0,
+            space: None,
+            tokens: None,
         }
     }
 
@@ -84,6 +108,21 @@ impl Stream {
         self
     }
 
+    /// Watch free space
+    ///
+    pub fn space(&mut self, space: Arc<SpaceMonitor>) -> &mut Self {
+        trace!("Watch free space");
+        self.space = Some(space);
+        self
+    }
+
+    /// Refresh the token when rejected, so long streams survive expiration
+    ///
+    pub fn tokens(&mut self, tokens: Arc<TokenRefresher>) -> &mut Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
     /// The heart of the matter: fetch data
     ///
     #[tracing::instrument]
@@ -94,12 +133,24 @@ impl Stream {
         //
         match &self.site {
             Some(site) => {
+                // Held for the whole duration of the stream
+                //
+                let Some(_permit) = self.srcs.acquire(site, cancelled) else {
+                    debug!("stream cancelled while waiting for {}", site);
+                    return Ok(());
+                };
+
+                let name = site;
                 let site = Site::load(site, &self.srcs)?;
                 if let Flow::Streamable(site) = site {
                     let token = site.authenticate()?;
 
+                    let watch = DriftWatch::new(site.format());
+                    let out = relay(self.space.clone(), Some(watch), stdout);
                     let args = self.args.clone();
-                    site.stream(stdout, &token, &args).unwrap();
+                    with_token(self.tokens.as_deref(), name, token, |token| {
+                        site.stream(out.clone(), token, &args)
+                    })?;
                 }
             }
             None => return Err(EngineStatus::NoSiteDefined.into()),
@@ -108,5 +159,114 @@ impl Stream {
     }
 }
 
+/// Pass data along unless free space is critical, until the job is cancelled, reporting schema
+/// drifts if `watch` is set.  The stream ends when `stdout` is closed.
+///
+fn relay(
+    space: Option<Arc<SpaceMonitor>>,
+    mut watch: Option<DriftWatch>,
+    stdout: Sender<String>,
+) -> Sender<String> {
+    let (tx, rx) = channel::<String>();
+    let stage = Metrics::current();
+    let cancel = Cancel::current();
+
+    thread::spawn(move || {
+        let mut dropped = 0;
+        for data in rx {
+            if cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                info!("Stream cancelled");
+                break;
+            }
+            if space
+                .as_ref()
+                .is_some_and(|s| s.check().level == SpaceLevel::Critical)
+            {
+                if dropped == 0 {
+                    warn!("Free space critical, pausing stream");
+                }
+                if let Some(stage) = &stage {
+                    stage.dropped(DropReason::Space, records(&data));
+                }
+                dropped += data.len();
+                continue;
+            }
+            if dropped != 0 {
+                info!("Resuming stream, {} bytes dropped", dropped);
+                dropped = 0;
+            }
+            match watch.as_mut().and_then(|w| w.check(&data)) {
+                Some(drift) if drift.is_empty() => info!("Stream back to its schema"),
+                Some(drift) => warn!("Schema drift: {}", drift),
+                None => (),
+            }
+            if stdout.send(data).is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use fetiche_formats::Format;
+
+    use crate::SpaceConfig;
+
+    use super::*;
+
+    fn pass(low: &str, critical: &str) -> Vec<String> {
+        let cfg = SpaceConfig {
+            low: low.parse().unwrap(),
+            critical: critical.parse().unwrap(),
+            interval: 0,
+        };
+        let paths = [PathBuf::from(".")];
+        let space = SpaceMonitor::new(&cfg, &paths, Arc::new(Mutex::new(vec![])));
+
+        let (stdout, rx) = channel();
+        let tx = relay(Some(Arc::new(space)), None, stdout);
+        tx.send("one".to_string()).unwrap();
+        tx.send("two".to_string()).unwrap();
+        drop(tx);
+        rx.iter().collect()
+    }
+
+    #[test]
+    fn test_stream_pause_when_full() {
+        assert_eq!(vec!["one", "two"], pass("100%", "0B"));
+        if cfg!(unix) {
+            assert!(pass("100%", "100%").is_empty());
+        }
+    }
+
+    #[test]
+    fn test_stream_drift_passes() {
+        let (stdout, rx) = channel();
+        let tx = relay(None, Some(DriftWatch::new(Format::Asd)), stdout);
+        tx.send(r##"{"foo":1}"##.to_string()).unwrap();
+        tx.send("MSG,1".to_string()).unwrap();
+        drop(tx);
+        assert_eq!(2, rx.iter().count());
+    }
+
+    #[test]
+    fn test_stream_cancelled() {
+        let cancel = Cancel::new();
+        cancel.enter();
+
+        let (stdout, rx) = channel();
+        let tx = relay(None, None, stdout);
+        tx.send("one".to_string()).unwrap();
+        while rx.try_recv().is_err() {
+            thread::yield_now();
+        }
+        cancel.cancel();
+        let _ = tx.send("two".to_string());
+        assert!(rx.recv().is_err());
+    }
+}
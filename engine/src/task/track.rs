@@ -0,0 +1,295 @@
+//! `Track` is a `Runnable` task as defined in the `engine`  crate.
+//!
+//! This filter appends a normalised `track_id` column to every record (or fills it if already
+//! there), see `fetiche_formats::TrackRule` for where each format keeps its identifiers:
+//!
+//! - if the source has a track identifier (like ASD `journey`), it is used as-is,
+//! - otherwise records are grouped by vehicle (like the Cat21 address) and a new track starts
+//!   when there is no record for more than `max_gap` seconds or when the position jumps by more
+//!   than `max_jump` meters.  These tracks are named `<vehicle>-<start>`, `start` being the
+//!   timestamp of the first record, so they do not change from one batch to the next.
+//!
+//! Only CSV-based formats are supported (`Asd` and `Cat21`), like `Qc`.
+//!
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::Sender;
+
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use eyre::Result;
+use tracing::{debug, trace};
+
+use fetiche_common::geo::haversine;
+use fetiche_formats::{track_id, Format, TRACK_ID};
+use fetiche_macros::RunnableDerive;
+
+use super::qc::parse_time;
+use crate::{EngineStatus, Runnable, IO};
+
+/// Default longest gap within a track, in seconds
+pub const TRACK_MAX_GAP: i64 = 300;
+/// Default largest jump in position within a track, in meters
+pub const TRACK_MAX_JUMP: f64 = 5_000.;
+/// Vehicle name when there is none
+const UNKNOWN: &str = "unknown";
+
+/// Current track of a vehicle
+///
+#[derive(Clone, Debug)]
+struct Segment {
+    /// Track ID
+    id: String,
+    /// Time of the last record
+    time: i64,
+    /// Last known position
+    pos: Option<(f64, f64)>,
+}
+
+/// The Track task
+///
+#[derive(Clone, Debug, RunnableDerive)]
+pub struct Track {
+    /// I/O capabilities
+    io: IO,
+    /// name for the task
+    pub name: String,
+    /// Input file format
+    pub inp: Format,
+    /// Longest gap within a track, in seconds
+    pub max_gap: i64,
+    /// Largest jump in position within a track, in meters
+    pub max_jump: f64,
+    /// Current track of each vehicle
+    last: BTreeMap<String, Segment>,
+}
+
+impl Track {
+    /// Initialise our environment
+    ///
+    #[tracing::instrument]
+    pub fn new(name: &str, inp: Format) -> Self {
+        trace!("New Track {}", name);
+        Track {
+            io: IO::Filter,
+            name: name.to_owned(),
+            inp,
+            max_gap: TRACK_MAX_GAP,
+            max_jump: TRACK_MAX_JUMP,
+            last: BTreeMap::new(),
+        }
+    }
+
+    /// Set the longest gap
+    ///
+    pub fn max_gap(&mut self, secs: i64) -> &mut Self {
+        self.max_gap = secs;
+        self
+    }
+
+    /// Set the largest jump
+    ///
+    pub fn max_jump(&mut self, meters: f64) -> &mut Self {
+        self.max_jump = meters;
+        self
+    }
+
+    /// Add the track ID to every record and pass them along.
+    ///
+    #[tracing::instrument(skip(self, data, stdout))]
+    pub fn execute(&mut self, data: String, stdout: Sender<String>) -> Result<()> {
+        trace!("Track::execute()");
+
+        let rule = self
+            .inp
+            .track_rule()
+            .ok_or(EngineStatus::UnsupportedTrack(self.inp.to_string()))?;
+
+        let mut rdr = ReaderBuilder::new()
+            .delimiter(rule.delimiter)
+            .has_headers(true)
+            .from_reader(data.as_bytes());
+        let mut header = rdr.headers()?.clone();
+        let idx = |name: &str| {
+            header
+                .iter()
+                .position(|h| h == name)
+                .ok_or(EngineStatus::NoTrackColumn(name.to_string()))
+        };
+        let track = match rule.track {
+            Some(name) => Some(idx(name)?),
+            None => None,
+        };
+        let idx = [
+            idx(rule.vehicle)?,
+            idx(rule.time)?,
+            idx(rule.latitude)?,
+            idx(rule.longitude)?,
+        ];
+
+        // Already there if the data went through here before
+        //
+        let pos = header.iter().position(|h| h == TRACK_ID);
+        if pos.is_none() {
+            header.push_field(TRACK_ID);
+        }
+
+        let mut wtr = WriterBuilder::new()
+            .delimiter(rule.delimiter)
+            .from_writer(vec![]);
+        wtr.write_record(&header)?;
+        for rec in rdr.records() {
+            let mut rec = rec?;
+            let id = self.track_of(&rec, track, &idx);
+            match pos {
+                Some(pos) => {
+                    rec = rec
+                        .iter()
+                        .enumerate()
+                        .map(|(i, f)| if i == pos { id.as_str() } else { f })
+                        .collect();
+                }
+                None => rec.push_field(&id),
+            }
+            wtr.write_record(&rec)?;
+        }
+        debug!("{} vehicles", self.last.len());
+
+        let data = String::from_utf8(wtr.into_inner()?)?;
+        Ok(stdout.send(data)?)
+    }
+
+    /// Track of one record, `idx` being the vehicle, time, latitude and longitude columns.
+    ///
+    fn track_of(&mut self, rec: &StringRecord, track: Option<usize>, idx: &[usize; 4]) -> String {
+        let get = |i: usize| rec.get(i).unwrap_or_default().trim();
+
+        if let Some(id) = track.map(get).filter(|id| !id.is_empty()) {
+            return id.to_string();
+        }
+
+        let vehicle = match get(idx[0]) {
+            "" => UNKNOWN,
+            v => v,
+        };
+        let last = self.last.get(vehicle);
+
+        // Without time, stay on the current track
+        //
+        let Some(tm) = parse_time(get(idx[1])) else {
+            return last.map(|s| s.id.clone()).unwrap_or(track_id(vehicle, 0));
+        };
+        let pos = match (get(idx[2]).parse::<f64>(), get(idx[3]).parse::<f64>()) {
+            (Ok(lat), Ok(lon)) => Some((lat, lon)),
+            _ => None,
+        };
+
+        let id = match last {
+            Some(s) if !self.is_new(s, tm, pos) => s.id.clone(),
+            _ => track_id(vehicle, tm),
+        };
+        let pos = pos.or(last.and_then(|s| s.pos));
+        self.last.insert(
+            vehicle.to_string(),
+            Segment {
+                id: id.clone(),
+                time: tm,
+                pos,
+            },
+        );
+        id
+    }
+
+    /// Does a record at `tm` and `pos` start a new track after `last`?
+    ///
+    fn is_new(&self, last: &Segment, tm: i64, pos: Option<(f64, f64)>) -> bool {
+        if (tm - last.time).abs() > self.max_gap {
+            return true;
+        }
+        match (last.pos, pos) {
+            (Some((lat1, lon1)), Some((lat2, lon2))) => {
+                haversine(lat1, lon1, lat2, lon2) > self.max_jump
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Track::new("default", Format::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    fn run(t: &mut Track, data: &str) -> Result<Vec<String>> {
+        let (tx, rx) = channel();
+        t.execute(data.to_string(), tx)?;
+        Ok(rx.recv()?.lines().map(|l| l.to_string()).collect())
+    }
+
+    #[test]
+    fn test_track_journey() -> Result<()> {
+        let data = "journey,ident,timestamp,latitude,longitude
+12,ABC,2024-05-12 10:00:00,49.0,2.0
+13,DEF,2024-05-12 10:00:01,49.1,2.1
+";
+        let mut t = Track::new("test", Format::Asd);
+        let out = run(&mut t, data)?;
+        assert_eq!(
+            "journey,ident,timestamp,latitude,longitude,track_id",
+            out[0]
+        );
+        assert!(out[1].ends_with(",12"));
+        assert!(out[2].ends_with(",13"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_segments() -> Result<()> {
+        let data = "TARGET_ADDR:REC_TIME_POSIX:POS_LAT_DEG:POS_LONG_DEG
+4CA2D6:1000:49.0:2.0
+4CA2D6:1010:49.01:2.0
+4CA2D6:2000:49.02:2.0
+4CA2D6:2010:50.0:2.0
+39C4A3:1005:48.0:2.0
+";
+        let mut t = Track::new("test", Format::Cat21);
+        let ids = run(&mut t, data)?
+            .iter()
+            .skip(1)
+            .map(|l| l.rsplit(':').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "4CA2D6-1000",
+                "4CA2D6-1000",
+                "4CA2D6-2000",
+                "4CA2D6-2010",
+                "39C4A3-1005"
+            ],
+            ids
+        );
+
+        // Same track across batches, existing column is filled again
+        //
+        let more = "TARGET_ADDR:REC_TIME_POSIX:POS_LAT_DEG:POS_LONG_DEG:track_id
+4CA2D6:2020:50.001:2.0:
+";
+        let out = run(&mut t, more)?;
+        assert_eq!(1, out[0].matches(TRACK_ID).count());
+        assert_eq!("4CA2D6:2020:50.001:2.0:4CA2D6-2010", out[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_track_unsupported() {
+        let (tx, _rx) = channel();
+        let mut t = Track::new("test", Format::Opensky);
+        assert!(t.execute("a,b\n".to_string(), tx).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Excel workbooks, for people who do not do CSV.
+//!
+//! `write_xlsx()` turns CSV records with a header (`Asd` and `Cat21`, see `Format::track_rule()`)
+//! into an `.xlsx` file.  Columns are typed from what they hold: numbers, booleans and the time
+//! of the record as a date (from a UNIX timestamp or `YYYY-MM-DD HH:MM:SS`), everything else is
+//! text.  The header is in bold and frozen on every sheet.
+//!
+//! Everything goes into one sheet named after the format or, with `per_day`, into one sheet per
+//! UTC day, records without a valid time going into `undated`.  Excel can not hold more than
+//! `XLSX_MAX_ROWS` records in a sheet, nothing is written if one of them would be larger.
+//!
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime};
+use csv::{ReaderBuilder, StringRecord};
+use eyre::Result;
+use rust_xlsxwriter::{Format as CellFormat, Workbook, XlsxError};
+use tracing::{debug, trace};
+
+use fetiche_formats::Format;
+
+use crate::EngineStatus;
+
+/// Largest number of records in a sheet, the header takes one of Excel's 1,048,576 rows
+pub const XLSX_MAX_ROWS: usize = 1_048_575;
+
+/// Sheet for records without a valid time
+const UNDATED: &str = "undated";
+
+/// Integers above this lose precision as Excel numbers are doubles
+const MAX_SAFE_INT: i64 = 1 << 53;
+
+/// Type of a column
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Integer,
+    Float,
+    Bool,
+    Time,
+    Text,
+}
+
+/// Write the CSV records in `input` into the workbook `to`, returns the number of records.
+///
+#[tracing::instrument(skip(input))]
+pub fn write_xlsx(input: &str, to: &Path, fmt: Format, per_day: bool) -> Result<usize> {
+    trace!("write_xlsx");
+
+    let rule = fmt
+        .track_rule()
+        .ok_or(EngineStatus::UnsupportedXlsx(fmt.to_string()))?;
+
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(rule.delimiter)
+        .has_headers(true)
+        .from_reader(input.as_bytes());
+    let header = rdr.headers()?.clone();
+    let records = rdr.records().collect::<Result<Vec<_>, _>>()?;
+
+    let time = header.iter().position(|h| h == rule.time);
+    let kinds = (0..header.len())
+        .map(|i| kind(&records, i, time == Some(i)))
+        .collect::<Vec<_>>();
+    debug!("columns: {:?}", kinds);
+
+    // Split into sheets and check their size before writing anything
+    //
+    let mut sheets = BTreeMap::<String, Vec<&StringRecord>>::new();
+    for rec in &records {
+        let name = match per_day {
+            true => time
+                .and_then(|i| parse_time(field(rec, i)))
+                .map(|t| t.format("%Y-%m-%d").to_string())
+                .unwrap_or(UNDATED.to_string()),
+            false => fmt.to_string(),
+        };
+        sheets.entry(name).or_default().push(rec);
+    }
+    if sheets.is_empty() {
+        sheets.insert(fmt.to_string(), vec![]);
+    }
+    check_rows(&sheets, XLSX_MAX_ROWS)?;
+
+    let bold = CellFormat::new().set_bold();
+    let date = CellFormat::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+
+    let mut wb = Workbook::new();
+    for (name, recs) in &sheets {
+        let ws = wb.add_worksheet();
+        ws.set_name(name).map_err(xlsx)?;
+        ws.write_row_with_format(0, 0, header.iter(), &bold)
+            .map_err(xlsx)?;
+        ws.set_freeze_panes(1, 0).map_err(xlsx)?;
+
+        for (r, rec) in recs.iter().enumerate() {
+            let row = r as u32 + 1;
+            for (c, kind) in kinds.iter().enumerate() {
+                let col = c as u16;
+                let value = field(rec, c);
+                if value.is_empty() {
+                    continue;
+                }
+                match kind {
+                    Kind::Integer | Kind::Float => ws.write_number(row, col, value.parse::<f64>()?),
+                    Kind::Bool => ws.write_boolean(row, col, value == "true"),
+                    Kind::Time => match parse_time(value) {
+                        Some(t) => ws.write_datetime_with_format(row, col, t, &date),
+                        None => ws.write_string(row, col, value),
+                    },
+                    Kind::Text => ws.write_string(row, col, value),
+                }
+                .map_err(xlsx)?;
+            }
+        }
+        ws.autofit();
+    }
+    wb.save(to).map_err(xlsx)?;
+    Ok(records.len())
+}
+
+/// Refuse sheets with more than `max` records
+///
+fn check_rows(sheets: &BTreeMap<String, Vec<&StringRecord>>, max: usize) -> Result<()> {
+    match sheets.iter().find(|(_, recs)| recs.len() > max) {
+        Some((name, recs)) => {
+            Err(EngineStatus::XlsxTooManyRows(name.clone(), recs.len(), max).into())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Field `i` of `rec`, trimmed
+///
+fn field(rec: &StringRecord, i: usize) -> &str {
+    rec.get(i).unwrap_or_default().trim()
+}
+
+/// Type of column `i`: the narrowest one holding all its non-empty values.
+///
+fn kind(records: &[StringRecord], i: usize, time: bool) -> Kind {
+    let values = records
+        .iter()
+        .map(|rec| field(rec, i))
+        .filter(|v| !v.is_empty())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return Kind::Text;
+    }
+
+    let all = |f: fn(&str) -> bool| values.iter().all(|v| f(v));
+    if time && all(|v| parse_time(v).is_some()) {
+        Kind::Time
+    } else if all(is_integer) {
+        Kind::Integer
+    } else if all(is_float) {
+        Kind::Float
+    } else if all(|v| v == "true" || v == "false") {
+        Kind::Bool
+    } else {
+        Kind::Text
+    }
+}
+
+/// Identifiers like "0042" are text, not numbers
+///
+fn leading_zero(v: &str) -> bool {
+    let digits = v.strip_prefix('-').unwrap_or(v).as_bytes();
+    digits.len() > 1 && digits[0] == b'0' && digits[1].is_ascii_digit()
+}
+
+/// Integer Excel can hold exactly
+///
+fn is_integer(v: &str) -> bool {
+    !leading_zero(v) && v.parse::<i64>().is_ok_and(|n| n.abs() <= MAX_SAFE_INT)
+}
+
+/// Finite number, no "NaN" or "inf"
+///
+fn is_float(v: &str) -> bool {
+    !leading_zero(v) && v.parse::<f64>().is_ok_and(|f| f.is_finite())
+}
+
+/// Times are either UNIX timestamps (Cat21) or "%Y-%m-%d %H:%M:%S" (ASD)
+///
+fn parse_time(s: &str) -> Option<NaiveDateTime> {
+    match s.parse::<i64>() {
+        Ok(t) => DateTime::from_timestamp(t, 0).map(|t| t.naive_utc()),
+        Err(_) => NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok(),
+    }
+}
+
+fn xlsx(e: XlsxError) -> EngineStatus {
+    EngineStatus::Xlsx(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    const ASD: &str = "journey,ident,timestamp,latitude,altitude,model,home
+1,0042,2024-05-12 10:30:15,49.5,80,Mavic 3,true
+1,0042,2024-05-12 10:30:16,49.6,85,Mavic 3,false
+2,0107,2024-05-13 08:00:00,50.1,,Mini 2,true
+";
+
+    #[rstest]
+    #[case(0, Kind::Integer)]
+    #[case(1, Kind::Text)]
+    #[case(2, Kind::Time)]
+    #[case(3, Kind::Float)]
+    #[case(4, Kind::Integer)]
+    #[case(5, Kind::Text)]
+    #[case(6, Kind::Bool)]
+    fn test_kind(#[case] i: usize, #[case] res: Kind) {
+        let recs = ReaderBuilder::new()
+            .from_reader(ASD.as_bytes())
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(res, kind(&recs, i, i == 2));
+    }
+
+    #[rstest]
+    #[case("42", true)]
+    #[case("-7", true)]
+    #[case("0", true)]
+    #[case("0042", false)]
+    #[case("1.5", false)]
+    #[case("9007199254740993", false)]
+    fn test_is_integer(#[case] v: &str, #[case] res: bool) {
+        assert_eq!(res, is_integer(v));
+    }
+
+    #[rstest]
+    #[case("49.5", true)]
+    #[case("0.5", true)]
+    #[case("-0.5", true)]
+    #[case("0042", false)]
+    #[case("NaN", false)]
+    #[case("inf", false)]
+    fn test_is_float(#[case] v: &str, #[case] res: bool) {
+        assert_eq!(res, is_float(v));
+    }
+
+    #[test]
+    fn test_write_xlsx() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let to = dir.path().join("out.xlsx");
+
+        assert_eq!(3, write_xlsx(ASD, &to, Format::Asd, true)?);
+        let data = fs::read(&to)?;
+        assert!(data.starts_with(b"PK"));
+
+        // One sheet per day
+        //
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.contains("xl/worksheets/sheet2.xml"));
+        assert!(!text.contains("xl/worksheets/sheet3.xml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_xlsx_empty() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let to = dir.path().join("out.xlsx");
+
+        let header = ASD.lines().next().unwrap();
+        assert_eq!(0, write_xlsx(header, &to, Format::Asd, true)?);
+        assert!(String::from_utf8_lossy(&fs::read(&to)?).contains("xl/worksheets/sheet1.xml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_rows() {
+        let rec = StringRecord::from(vec!["1"]);
+        let mut sheets = BTreeMap::new();
+        sheets.insert("2024-05-12".to_string(), vec![&rec, &rec]);
+        sheets.insert("2024-05-13".to_string(), vec![&rec, &rec, &rec]);
+
+        assert!(check_rows(&sheets, 3).is_ok());
+        let err = check_rows(&sheets, 2).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EngineStatus>(),
+            Some(EngineStatus::XlsxTooManyRows(name, 3, 2)) if name == "2024-05-13"
+        ));
+    }
+
+    #[test]
+    fn test_write_xlsx_unsupported() {
+        let dir = tempfile::tempdir().unwrap();
+        let res = write_xlsx("{}", &dir.path().join("out.xlsx"), Format::Opensky, false);
+        assert!(res.is_err());
+    }
+}
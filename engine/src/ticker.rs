@@ -0,0 +1,197 @@
+//! Monotonic scheduling for recurring jobs
+//!
+//! Waiting is done on the monotonic clock so a change of the system time does not make a job
+//! run twice or not at all.  Ticks are computed from a fixed anchor (`anchor + n * period`)
+//! instead of "now + period" so they do not drift, and ticks missed because a run was too long
+//! are skipped instead of being run in a burst.
+//!
+//! The wall clock is still watched: when it moves away from the monotonic one by more than
+//! `threshold` (NTP step, VM snapshot restored, etc.), a `Skew` is reported and the schedule
+//! is re-anchored.  If the wall clock jumped forward (the usual case after a restore as the
+//! monotonic clock does not count the time the VM was stopped), the next run happens right
+//! away to catch up once; if it jumped back, the current schedule is kept.
+//!
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// Default difference between both clocks we accept
+const THRESHOLD: Duration = Duration::from_secs(5);
+
+/// How often we look at the wall clock while waiting
+const RESOLUTION: Duration = Duration::from_secs(1);
+
+/// A wall-clock jump
+///
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Skew {
+    /// When it was detected (wall clock)
+    pub at: DateTime<Utc>,
+    /// Jump in ms, positive if the wall clock moved forward
+    pub offset: i64,
+}
+
+/// Schedule ticks every `period` on the monotonic clock.
+///
+#[derive(Clone, Debug)]
+pub struct Ticker {
+    /// Time between two ticks
+    period: Duration,
+    /// Maximum accepted skew
+    threshold: Duration,
+    /// Sleep at most this long before checking the clocks again
+    resolution: Duration,
+    /// Start of the schedule
+    anchor: Instant,
+    /// Number of ticks since `anchor`
+    ticks: u32,
+    /// Last time we looked at both clocks
+    last: (Instant, DateTime<Utc>),
+}
+
+impl Ticker {
+    /// Start a new schedule now
+    ///
+    pub fn new(period: Duration) -> Self {
+        let now = Instant::now();
+        Ticker {
+            period,
+            threshold: THRESHOLD,
+            resolution: RESOLUTION,
+            anchor: now,
+            ticks: 0,
+            last: (now, Utc::now()),
+        }
+    }
+
+    /// Change the accepted skew
+    ///
+    pub fn threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Next tick after `now`, skipping the ones already passed.
+    ///
+    pub fn next_tick(&mut self, now: Instant) -> Instant {
+        let elapsed = now.saturating_duration_since(self.anchor);
+        let due = (elapsed.as_nanos() / self.period.as_nanos().max(1)) as u32 + 1;
+        if due > self.ticks + 1 {
+            debug!("{} tick(s) missed", due - self.ticks - 1);
+        }
+        self.ticks = due;
+        self.anchor + self.period * due
+    }
+
+    /// Compare how both clocks moved since the last check, returns the skew if it is over
+    /// the threshold.
+    ///
+    pub fn check(&mut self, mono: Instant, wall: DateTime<Utc>) -> Option<Skew> {
+        let (last_mono, last_wall) = self.last;
+        self.last = (mono, wall);
+
+        let expected = last_wall
+            + chrono::Duration::from_std(mono.saturating_duration_since(last_mono)).ok()?;
+        let offset = (wall - expected).num_milliseconds();
+        if offset.unsigned_abs() as u128 > self.threshold.as_millis() {
+            warn!("wall clock jumped by {}ms", offset);
+            Some(Skew { at: wall, offset })
+        } else {
+            None
+        }
+    }
+
+    /// Restart the schedule at `now`
+    ///
+    fn reanchor(&mut self, now: Instant) {
+        self.anchor = now;
+        self.ticks = 0;
+    }
+
+    /// Sleep until the next tick.  Returns early after a forward jump of the wall clock,
+    /// reports any skew seen while waiting.
+    ///
+    pub fn wait(&mut self) -> Option<Skew> {
+        let mut skew = None;
+        let tick = self.next_tick(Instant::now());
+        loop {
+            let now = Instant::now();
+            if let Some(s) = self.check(now, Utc::now()) {
+                skew = Some(s);
+                // Catch up now, a jump back does not change the monotonic schedule
+                //
+                if s.offset > 0 {
+                    self.reanchor(now);
+                    return skew;
+                }
+            }
+            if now >= tick {
+                return skew;
+            }
+            thread::sleep((tick - now).min(self.resolution));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticker_next_tick() {
+        let mut t = Ticker::new(Duration::from_secs(10));
+        let start = t.anchor;
+
+        assert_eq!(start + Duration::from_secs(10), t.next_tick(start));
+        assert_eq!(
+            start + Duration::from_secs(20),
+            t.next_tick(start + Duration::from_secs(12))
+        );
+
+        // Missed ticks are skipped, not run in a burst
+        //
+        assert_eq!(
+            start + Duration::from_secs(60),
+            t.next_tick(start + Duration::from_secs(55))
+        );
+        assert_eq!(6, t.ticks);
+    }
+
+    #[test]
+    fn test_ticker_check() {
+        let mut t = Ticker::new(Duration::from_secs(10));
+        let (mono, wall) = t.last;
+
+        // Both clocks in sync
+        //
+        let mono = mono + Duration::from_secs(10);
+        let wall = wall + chrono::Duration::seconds(10);
+        assert!(t.check(mono, wall).is_none());
+
+        // VM restored after one hour
+        //
+        let mono = mono + Duration::from_secs(10);
+        let wall = wall + chrono::Duration::seconds(3610);
+        let skew = t.check(mono, wall).unwrap();
+        assert_eq!(3_600_000, skew.offset);
+
+        // Clock set back
+        //
+        let mono = mono + Duration::from_secs(1);
+        let wall = wall - chrono::Duration::seconds(59);
+        assert_eq!(-60_000, t.check(mono, wall).unwrap().offset);
+    }
+
+    #[test]
+    fn test_ticker_wait() {
+        let mut t = Ticker::new(Duration::from_millis(50));
+        let start = Instant::now();
+        assert!(t.wait().is_none());
+        assert!(t.wait().is_none());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -3,13 +3,13 @@ use std::fmt::Debug;
 use std::fs;
 use std::fs::read_dir;
 use std::path::Path;
+use std::sync::RwLock;
 use std::time::UNIX_EPOCH;
 
 use chrono::{DateTime, Utc};
 use eyre::Result;
-use fetiche_sources::{AsdToken, TokenType};
-use tabled::builder::Builder;
-use tabled::settings::Style;
+use fetiche_common::{Listing, OutputFormat};
+use fetiche_sources::{AsdToken, Expirable, TokenType};
 use tracing::trace;
 
 use crate::TokenStatus;
@@ -19,52 +19,50 @@ pub struct TokenStorage {
     /// `path` is relative to `root`.
     path: String,
     /// Btree of (key, AuthToken)
-    list: BTreeMap<String, TokenType>,
+    list: RwLock<BTreeMap<String, TokenType>>,
 }
 
 impl TokenStorage {
     /// Read the directory and return all tokens (one per file)
     ///
     pub fn register(path: &str) -> Self {
-        let mut db = BTreeMap::<String, TokenType>::new();
-        if let Ok(dir) = read_dir(path) {
-            dir.into_iter().for_each(|entry| {
-                if let Ok(p) = entry {
-                    let f = p.file_name().to_str().unwrap().to_string();
-                    let full = Path::new(path).join(f.as_str());
-                    let raw = fs::read_to_string(full).unwrap();
-
-                    if f.starts_with("asd_") {
-                        let data: AsdToken = serde_json::from_str(&raw).unwrap();
-                        db.insert(
-                            p.file_name().to_string_lossy().to_string(),
-                            TokenType::AsdToken(data),
-                        );
-                    } else {
-                        unimplemented!()
-                    }
-                }
-            });
-        }
         TokenStorage {
             path: path.into(),
-            list: db,
+            list: RwLock::new(read_tokens(path)),
         }
     }
 
+    /// Read the directory again, after tokens have been refreshed
+    ///
+    pub fn reload(&self) {
+        *self.list.write().unwrap() = read_tokens(&self.path);
+    }
+
     #[inline]
-    pub fn store(&mut self, key: &str, data: TokenType) -> Result<()> {
-        self.list.insert(key.into(), data);
+    pub fn store(&self, key: &str, data: TokenType) -> Result<()> {
+        self.list.write().unwrap().insert(key.into(), data);
         Ok(())
     }
 
     pub fn load(&self, key: &str) -> Result<TokenType> {
-        match self.list.get(key) {
+        match self.list.read().unwrap().get(key) {
             Some(t) => Ok(t.clone()),
             None => Err(TokenStatus::NotFound(key.to_string()).into()),
         }
     }
 
+    /// Tokens expiring before `limit` (UNIX timestamp)
+    ///
+    pub fn expiring(&self, limit: i64) -> Vec<TokenType> {
+        self.list
+            .read()
+            .unwrap()
+            .values()
+            .filter(|t| t.expires_at() <= limit)
+            .cloned()
+            .collect()
+    }
+
     #[inline]
     pub fn path(&self) -> String {
         self.path.clone()
@@ -72,12 +70,12 @@ impl TokenStorage {
 
     #[inline]
     pub fn len(&self) -> usize {
-        self.list.len()
+        self.list.read().unwrap().len()
     }
 
     #[inline]
     pub fn is_empty(&self) -> bool {
-        self.list.is_empty()
+        self.list.read().unwrap().is_empty()
     }
 
     /// List tokens
@@ -86,13 +84,18 @@ impl TokenStorage {
     ///       we do not know which kind of token each one is.
     ///
     #[tracing::instrument(skip(self))]
-    pub fn list(&self) -> Result<String> {
+    pub fn list(&self, fmt: OutputFormat) -> Result<String> {
         trace!("listing tokens");
 
-        let header = vec!["Path", "Producer", "Created at"];
-
-        let mut builder = Builder::default();
-        builder.push_record(header);
+        let mut list = Listing::new(
+            "Listing all tokens",
+            &[
+                ("Path", "path"),
+                ("Producer", "producer"),
+                ("Created at", "created_at"),
+            ],
+        );
+        list.rounded();
 
         let p = self.path.as_str();
         if let Ok(dir) = fs::read_dir(p) {
@@ -121,11 +124,35 @@ impl TokenStorage {
                     let origin = format!("{}", DateTime::<Utc>::from(UNIX_EPOCH));
                     row.push(origin);
                 }
-                builder.push_record(row);
+                list.push(row);
             }
         }
-        let table = builder.build().with(Style::rounded()).to_string();
-        let table = format!("Listing all tokens:\n{}", table);
-        Ok(table)
+        list.render(fmt)
+    }
+}
+
+/// One token per file, the kind being given by the prefix
+///
+fn read_tokens(path: &str) -> BTreeMap<String, TokenType> {
+    let mut db = BTreeMap::<String, TokenType>::new();
+    if let Ok(dir) = read_dir(path) {
+        dir.into_iter().for_each(|entry| {
+            if let Ok(p) = entry {
+                let f = p.file_name().to_str().unwrap().to_string();
+                let full = Path::new(path).join(f.as_str());
+                let raw = fs::read_to_string(full).unwrap();
+
+                if f.starts_with("asd_") {
+                    let data: AsdToken = serde_json::from_str(&raw).unwrap();
+                    db.insert(
+                        p.file_name().to_string_lossy().to_string(),
+                        TokenType::AsdToken(data),
+                    );
+                } else {
+                    unimplemented!()
+                }
+            }
+        });
     }
+    db
 }
@@ -0,0 +1,143 @@
+//! Per-stage tuning
+//!
+//! Defaults are fine for most jobs but throughput depends a lot on the hardware, a job spec can
+//! tune each stage by its name (the task name in lowercase: `fetch`, `convert`, `save`...):
+//!
+//! ```hcl
+//! tuning "convert" {
+//!   capacity = 16
+//!   workers  = 4
+//!   batch    = 5000
+//! }
+//! ```
+//!
+//! - `capacity` bounds the number of batches waiting between the stage and the next one, the
+//!   stage waits before taking more input when the next one does not keep up (unbounded by
+//!   default, the capacity of a producer only limits what is passed along),
+//! - `workers` is the number of threads converting in parallel (`convert` only, 1 by default),
+//! - `batch` is the number of records given to each worker at once (`DEF_BATCH` by default).
+//!
+//! Workers only help with inputs having one record per line, see `Convert`.
+//!
+
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+
+use crate::{Cmds, EngineStatus};
+
+/// Records per batch given to each worker
+pub const DEF_BATCH: usize = 10_000;
+
+/// Stages able to use more than one worker
+const PARALLEL: &[&str] = &["convert"];
+
+/// Tuning of one stage
+///
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tuning {
+    /// Batches waiting for the next stage, unbounded if not set
+    pub capacity: Option<usize>,
+    /// Worker threads
+    pub workers: Option<usize>,
+    /// Records per batch for the workers
+    pub batch: Option<usize>,
+}
+
+impl Tuning {
+    /// Worker threads, 1 by default
+    ///
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or(1)
+    }
+
+    /// Records per batch, `DEF_BATCH` by default
+    ///
+    pub fn batch(&self) -> usize {
+        self.batch.unwrap_or(DEF_BATCH)
+    }
+
+    /// Check the values for stage `name`
+    ///
+    pub fn check(&self, name: &str) -> Result<(), EngineStatus> {
+        let bad = |why: &str| Err(EngineStatus::BadTuning(name.to_string(), why.to_string()));
+
+        if !Cmds::iter().any(|c| c.to_string().to_lowercase() == name) {
+            return bad("unknown stage");
+        }
+        if self.capacity == Some(0) {
+            return bad("capacity must be at least 1");
+        }
+        if self.workers == Some(0) || self.batch == Some(0) {
+            return bad("workers and batch must be at least 1");
+        }
+        if (self.workers.is_some() || self.batch.is_some()) && !PARALLEL.contains(&name) {
+            return bad("only convert has workers");
+        }
+        Ok(())
+    }
+}
+
+impl Display for Tuning {
+    /// `capacity 16, workers 4, batch 5000` with only what is set
+    ///
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let list = [
+            ("capacity", self.capacity),
+            ("workers", self.workers),
+            ("batch", self.batch),
+        ]
+        .iter()
+        .filter_map(|(name, v)| v.map(|v| format!("{name} {v}")))
+        .collect::<Vec<_>>();
+        match list.is_empty() {
+            true => write!(f, "defaults"),
+            false => write!(f, "{}", list.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("convert", Some(16), Some(4), Some(1000), true)]
+    #[case("save", Some(4), None, None, true)]
+    #[case("fetch", Some(0), None, None, false)]
+    #[case("convert", None, Some(0), None, false)]
+    #[case("convert", None, None, Some(0), false)]
+    #[case("track", None, Some(2), None, false)]
+    #[case("foo", Some(1), None, None, false)]
+    fn test_tuning_check(
+        #[case] name: &str,
+        #[case] capacity: Option<usize>,
+        #[case] workers: Option<usize>,
+        #[case] batch: Option<usize>,
+        #[case] ok: bool,
+    ) {
+        let t = Tuning {
+            capacity,
+            workers,
+            batch,
+        };
+        assert_eq!(ok, t.check(name).is_ok());
+    }
+
+    #[test]
+    fn test_tuning_display() {
+        let t = Tuning {
+            capacity: Some(16),
+            batch: Some(500),
+            ..Tuning::default()
+        };
+        assert_eq!("capacity 16, batch 500", t.to_string());
+        assert_eq!("defaults", Tuning::default().to_string());
+        assert_eq!(1, t.workers());
+        assert_eq!(500, t.batch());
+    }
+}
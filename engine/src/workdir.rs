@@ -0,0 +1,355 @@
+//! Per-job working directories
+//!
+//! Every job created through `Engine::create_job()` gets its own `job-<id>` directory under the
+//! engine `workdir` (`basedir/work` by default) and it is recorded in the state file.  Tasks
+//! needing temporary files use it instead of a shared location.
+//!
+//! `Engine::run_job()` removes it when the job succeeds.  On failure it is kept for debugging
+//! but only the last `keep_failed` ones are retained.  Directories left behind by aborted runs
+//! (process killed, machine rebooted, etc.) are reclaimed by `Engine::gc_workdirs()`, see
+//! `acutectl jobs gc`.
+//!
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Utc;
+use eyre::Result;
+use tracing::{info, trace, warn};
+
+use crate::{
+    Engine, EngineStatus, Job, JobError, JobEvent, QueueEvent, SpaceLevel, State, Storage, WorkDir,
+    WorkStatus,
+};
+
+/// Prefix of every job directory
+const PREFIX: &str = "job-";
+
+impl State {
+    /// Record a new working directory for job `id`
+    ///
+    pub fn add_workdir(&mut self, id: usize, path: &Path) -> &mut Self {
+        self.workdirs.insert(
+            id,
+            WorkDir {
+                path: path.to_path_buf(),
+                status: WorkStatus::Running,
+                tm: Utc::now().timestamp(),
+            },
+        );
+        self
+    }
+
+    /// Mark the directory of job `id` as failed then forget the oldest failed ones beyond
+    /// `keep`.  Returns the paths to remove.
+    ///
+    pub fn fail_workdir(&mut self, id: usize, keep: usize) -> Vec<PathBuf> {
+        if let Some(wd) = self.workdirs.get_mut(&id) {
+            wd.status = WorkStatus::Failed;
+            wd.tm = Utc::now().timestamp();
+        }
+
+        // IDs are always incrementing so the oldest come first
+        //
+        let failed = self
+            .workdirs
+            .iter()
+            .filter(|(_, wd)| wd.status == WorkStatus::Failed)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let extra = failed.len().saturating_sub(keep);
+        failed[..extra]
+            .iter()
+            .filter_map(|id| self.workdirs.remove(id))
+            .map(|wd| wd.path)
+            .collect()
+    }
+
+    /// Forget directories of aborted jobs (still running and last changed before `limit`) and
+    /// failed ones if `failed` is set, jobs in `ours` are never touched.  Returns the paths to
+    /// remove.
+    ///
+    pub fn stale_workdirs(
+        &mut self,
+        limit: i64,
+        failed: bool,
+        ours: &VecDeque<usize>,
+    ) -> Vec<PathBuf> {
+        let stale = self
+            .workdirs
+            .iter()
+            .filter(|(id, wd)| {
+                !ours.contains(id)
+                    && match wd.status {
+                        WorkStatus::Running => wd.tm <= limit,
+                        WorkStatus::Failed => failed,
+                    }
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        stale
+            .into_iter()
+            .filter_map(|id| {
+                self.remove_job(id);
+                self.workdirs.remove(&id)
+            })
+            .map(|wd| wd.path)
+            .collect()
+    }
+}
+
+impl Engine {
+    /// Create the working directory for job `id` and record it.  Jobs can run without one so
+    /// errors are only logged.
+    ///
+    pub(crate) fn new_workdir(&self, id: usize) -> Option<PathBuf> {
+        let path = self.workdir.join(format!("{PREFIX}{id}"));
+        if let Err(e) = fs::create_dir_all(&path) {
+            warn!("Can not create {:?}: {}", path, e);
+            return None;
+        }
+        trace!("job {} in {:?}", id, path);
+
+        let mut state = self.state.write().unwrap();
+        state.add_workdir(id, &path);
+        Some(path)
+    }
+
+    /// Remove the working directory of job `id` if any, the caller is syncing the state.
+    ///
+    pub(crate) fn drop_workdir(&self, id: usize) {
+        let mut state = self.state.write().unwrap();
+        if let Some(wd) = state.workdirs.remove(&id) {
+            remove(&wd.path);
+        }
+    }
+
+    /// Run a job then remove it.  If the job fails, its working directory is kept and the
+    /// error returned.  Bulk jobs are refused when free space is low, streams are paused by
+    /// `Stream` itself when it is critical.  Bulk jobs without their own timeout get
+    /// `job_timeout`, a job running longer is cancelled as `TimedOut`.  While it runs, the job
+    /// can be stopped with `Engine::cancel_job()`.  The per-stage counters
+    /// are kept in the state either way, along with the records fetched from the job's source
+    /// and the error if it failed.  Every step is recorded in the job history.  A successful job
+    /// with an `export` has it run before being removed.
+    ///
+    #[tracing::instrument(skip(self, job, out))]
+    pub fn run_job(&mut self, mut job: Job, out: &mut dyn Write) -> Result<()> {
+        let space = self.space.refresh();
+        if !job.stream && space.level != SpaceLevel::Ok {
+            warn!("Job {} refused, {}", job.id, space);
+            let e = EngineStatus::LowSpace(space.to_string());
+            let error = e.to_string();
+            self.record(job.id, &job.name, JobEvent::Failed { error });
+            self.fail_job(job)?;
+            return Err(e.into());
+        }
+
+        let mut state = self.state.write().unwrap();
+        state.start_job(job.id);
+        job.expected = state
+            .runtimes
+            .get(&job.name)
+            .and_then(|r| r.average())
+            .map(|t| Duration::from_secs(t as u64));
+        drop(state);
+        self.sync()?;
+        self.notify(QueueEvent::Started(job.id));
+        self.record(job.id, &job.name, JobEvent::Started);
+
+        if job.timeout.is_none() && !job.stream {
+            job.timeout = self.job_timeout;
+        }
+
+        let start = Instant::now();
+        self.running
+            .lock()
+            .unwrap()
+            .insert(job.id, job.cancel.clone());
+        let res = job.run(out);
+        self.running.lock().unwrap().remove(&job.id);
+        let profile = job.profile();
+        let mut state = self.state.write().unwrap();
+        if let (Some(source), Some(first)) = (&job.source, profile.stages.first()) {
+            state
+                .stats
+                .add_source(source, first.records_out, start.elapsed().as_secs());
+        }
+        drop(state);
+        profile.stages.iter().for_each(|stage| {
+            let stage = stage.clone();
+            self.record(job.id, &job.name, JobEvent::Stage { stage });
+        });
+        self.state.write().unwrap().add_profile(profile);
+        match res {
+            Ok(()) => {
+                let seconds = start.elapsed().as_secs();
+                self.record(job.id, &job.name, JobEvent::Finished { seconds });
+                self.export(&job);
+                self.remove_job(job)
+            }
+            Err(e) => {
+                let error = e.to_string();
+                self.record(job.id, &job.name, JobEvent::Failed { error });
+                let id = job.id;
+                let error = JobError {
+                    id,
+                    name: job.name.clone(),
+                    at: Utc::now().timestamp(),
+                    error: e.to_string(),
+                };
+                self.state.write().unwrap().stats.add_error(error);
+                self.fail_job(job)?;
+                match e.downcast_ref::<EngineStatus>() {
+                    Some(EngineStatus::TimedOut(..)) => {
+                        self.state.write().unwrap().stats.timeouts += 1;
+                        self.sync()?;
+                        self.notify(QueueEvent::TimedOut(id));
+                    }
+                    Some(EngineStatus::Cancelled(..)) => self.notify(QueueEvent::Cancelled(id)),
+                    _ => (),
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Remove a failed job, keeping its working directory.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn fail_job(&mut self, job: Job) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        state.remove_job(job.id).end_job(job.id, false);
+        if let Some(dir) = &job.workdir {
+            warn!("Job {} failed, keeping {:?}", job.id, dir);
+        }
+        let pruned = state.fail_workdir(job.id, self.keep_failed);
+
+        // Ensure lock goes away
+        //
+        drop(state);
+
+        pruned.iter().for_each(|p| remove(p));
+        self.sync()?;
+        self.notify(QueueEvent::Failed(job.id));
+        Ok(())
+    }
+
+    /// Reclaim job directories left behind: aborted jobs and unknown directories older than
+    /// `older_than` (1s/1m/1h/1d) and all failed ones if `failed` is set.  Jobs of the current
+    /// process are never touched.  Returns the removed directories.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn gc_workdirs(&mut self, older_than: &str, failed: bool) -> Result<Vec<PathBuf>> {
+        let (_, age) = Storage::parse_rotation(older_than)
+            .map_err(|_| EngineStatus::BadDuration(older_than.to_string()))?;
+        let limit = Utc::now().timestamp() - age as i64;
+        let ours = self.jobs.read().unwrap().clone();
+
+        let mut state = self.state.write().unwrap();
+        let mut all = state.stale_workdirs(limit, failed, &ours);
+
+        // Now the directories the state does not know about
+        //
+        if let Ok(dir) = fs::read_dir(self.workdir.as_ref()) {
+            for entry in dir.flatten() {
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !path.is_dir()
+                    || !name.starts_with(PREFIX)
+                    || state.workdirs.values().any(|wd| wd.path == path)
+                {
+                    continue;
+                }
+                let mtime = entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .ok()
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or_default();
+                if mtime <= limit {
+                    all.push(path);
+                }
+            }
+        }
+
+        // Ensure lock goes away
+        //
+        drop(state);
+
+        all.iter().for_each(|p| {
+            info!("Removing {:?}", p);
+            remove(p)
+        });
+        self.sync()?;
+        Ok(all)
+    }
+}
+
+/// Remove a directory and everything inside, only logging errors
+///
+fn remove(path: &Path) {
+    if let Err(e) = fs::remove_dir_all(path) {
+        if path.exists() {
+            warn!("Can not remove {:?}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(n: usize) -> State {
+        let mut s = State::new();
+        (1..=n).for_each(|id| {
+            s.queue.push_back(id);
+            s.add_workdir(id, &PathBuf::from(format!("/work/{PREFIX}{id}")));
+        });
+        s
+    }
+
+    #[test]
+    fn test_state_fail_workdir() {
+        let mut s = state(4);
+
+        assert!(s.fail_workdir(1, 2).is_empty());
+        assert!(s.fail_workdir(2, 2).is_empty());
+        assert_eq!(WorkStatus::Failed, s.workdirs[&2].status);
+
+        // Oldest failed one goes away
+        //
+        let pruned = s.fail_workdir(3, 2);
+        assert_eq!(vec![PathBuf::from("/work/job-1")], pruned);
+        assert_eq!(vec![&2, &3, &4], s.workdirs.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_state_stale_workdirs() {
+        let mut s = state(3);
+        s.fail_workdir(1, 10);
+        let now = Utc::now().timestamp();
+
+        // Nothing is old enough
+        //
+        assert!(s
+            .stale_workdirs(now - 3600, false, &VecDeque::new())
+            .is_empty());
+
+        // Job 3 is ours, job 1 failed
+        //
+        let ours = VecDeque::from([3]);
+        let all = s.stale_workdirs(now, false, &ours);
+        assert_eq!(vec![PathBuf::from("/work/job-2")], all);
+        assert_eq!(vec![1, 3], s.queue.iter().copied().collect::<Vec<_>>());
+
+        let all = s.stale_workdirs(now, true, &ours);
+        assert_eq!(vec![PathBuf::from("/work/job-1")], all);
+        assert_eq!(1, s.workdirs.len());
+    }
+}
@@ -19,6 +19,18 @@ pub struct Opts {
     pub config: Option<PathBuf>,
     #[clap(short = 'w', long)]
     pub workdir: Option<PathBuf>,
+    /// Read all configuration files from this directory (container mode).
+    #[clap(long)]
+    pub config_dir: Option<PathBuf>,
+    /// Keep state in this directory, no PID file and no detaching (container mode).
+    #[clap(long)]
+    pub state_dir: Option<PathBuf>,
+    /// Log to stdout in JSON.
+    #[clap(long)]
+    pub use_json: bool,
+    /// Serve /healthz and /readyz on this address, e.g. "0.0.0.0:8080".
+    #[clap(long)]
+    pub health: Option<String>,
     /// debug mode (no fork & detach).
     #[clap(short = 'D', long = "debug", default_value = "true")]
     pub debug: bool,
@@ -32,7 +32,7 @@ use serde_json::json;
 use strum::EnumString;
 use tracing::{debug, info, trace, warn};
 
-use fetiche_common::makepath;
+use fetiche_common::{makepath, OutputFormat};
 use fetiche_formats::Format;
 pub use fetiche_sources::{Auth, Fetchable, Filter, Flow, Site, Sources, Streamable};
 pub use job::*;
@@ -305,13 +305,13 @@ impl Engine {
     /// Return a description of all supported sources
     ///
     pub fn list_sources(&self) -> Result<String> {
-        self.sources.list()
+        self.sources.list(OutputFormat::Table)
     }
 
     /// Return a descriptions of all supported data formats
     ///
     pub fn list_formats(&self) -> Result<String> {
-        Format::list()
+        Format::list(OutputFormat::Table)
     }
 
     /// Return a list of all currently available authentication tokens
@@ -22,6 +22,7 @@ use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 use tracing_tree::HierarchicalLayer;
 
+use fetiche_common::{init_logging, set_config_dir, Health};
 use fetiched::{
     Bus, ConfigActor, ConfigKeys, ConfigList, ConfigSet, EngineActor, GetStatus, GetVersion, Param,
     StateActor, StorageActor, Submit, Sync,
@@ -43,53 +44,39 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 async fn main() -> Result<()> {
     let opts: Opts = Opts::parse();
 
-    // Initialise logging early
+    // Container mode: JSON logs on stdout and nothing else
     //
-    let tree = HierarchicalLayer::new(2)
-        .with_targets(true)
-        .with_bracketed_fields(true);
+    if opts.use_json {
+        init_logging(NAME, false, false, None, true)?;
+    } else {
+        init_tracing()?;
+    }
+    trace!("Logging initialised.");
 
-    // Setup Open Telemetry with Jaeger
-    //
-    let tree = HierarchicalLayer::new(2)
-        .with_ansi(true)
-        .with_span_retrace(true)
-        .with_span_modes(true)
-        .with_targets(true)
-        .with_verbose_entry(true)
-        .with_verbose_exit(true)
-        .with_bracketed_fields(true);
+    info!("This is {} starting up…", version());
 
-    // Setup Open Telemetry with OTLP
-    //
-    let exporter = opentelemetry_otlp::new_exporter().tonic();
-    let tracer = opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_exporter(exporter)
-        .install_simple()?;
-    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+    if let Some(dir) = &opts.config_dir {
+        set_config_dir(dir);
+    }
 
-    // Load filters from environment
-    //
-    let filter = EnvFilter::from_default_env();
+    let health = match &opts.health {
+        Some(addr) => Some(Health::serve(addr.as_str())?),
+        None => None,
+    };
 
-    // Combine filter & specific format
+    // With an explicit state directory, the orchestrator is in charge: no PID file and no
+    // detaching from the terminal.
     //
-    tracing_subscriber::registry()
-        .with(filter)
-        .with(tree)
-        .with(telemetry)
-        .init();
-    trace!("Logging initialised.");
-
-    info!("This is {} starting up…", version());
-
-    let workdir = opts.workdir.unwrap_or(default_workdir()?);
+    let container = opts.state_dir.is_some();
+    let workdir = match opts.state_dir.or(opts.workdir) {
+        Some(dir) => dir,
+        None => default_workdir()?,
+    };
     let pid_file = workdir.join(Path::new("fetiched.pid"));
 
     trace!("Working directory is {:?}", workdir);
 
-    if pid_file.exists() {
+    if !container && pid_file.exists() {
         info!("PID exist");
         let pid = fs::read_to_string(&pid_file)
             .await?
@@ -107,7 +94,8 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    if opts.debug {
+    let debug = opts.debug || container;
+    if debug {
         info!("Debug mode, no detaching, PID={}", std::process::id());
     } else {
         #[cfg(unix)]
@@ -141,6 +129,9 @@ async fn main() -> Result<()> {
     };
 
     trace!("Init done, serving.");
+    if let Some(health) = &health {
+        health.set_ready(true);
+    }
 
     // Main agent
 
@@ -207,13 +198,50 @@ async fn main() -> Result<()> {
     trace!("Finished.");
     state.do_send(Sync);
 
-    if !opts.debug {
+    if !debug {
         let _ = fs::remove_file(&pid_file).await;
     }
     System::current().stop();
     Ok(())
 }
 
+/// Default logging setup: tree-like output and OTLP telemetry
+///
+fn init_tracing() -> Result<()> {
+    // Setup Open Telemetry with Jaeger
+    //
+    let tree = HierarchicalLayer::new(2)
+        .with_ansi(true)
+        .with_span_retrace(true)
+        .with_span_modes(true)
+        .with_targets(true)
+        .with_verbose_entry(true)
+        .with_verbose_exit(true)
+        .with_bracketed_fields(true);
+
+    // Setup Open Telemetry with OTLP
+    //
+    let exporter = opentelemetry_otlp::new_exporter().tonic();
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .install_simple()?;
+    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+
+    // Load filters from environment
+    //
+    let filter = EnvFilter::from_default_env();
+
+    // Combine filter & specific format
+    //
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tree)
+        .with(telemetry)
+        .init();
+    Ok(())
+}
+
 /// UNIX-specific detach from terminal if -D/--debug is not specified
 ///
 #[cfg(unix)]
@@ -0,0 +1,166 @@
+//! Throughput of the main conversions and writers.
+//!
+//! Run with `cargo bench -p fetiche-formats`, results are given in elements (records) per second
+//! so they can be compared between runs to spot regressions.
+//!
+
+use std::fs;
+
+use chrono::{TimeZone, Utc};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use datafusion::config::TableParquetOptions;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::prelude::*;
+use serde_json::json;
+use tempfile::tempdir;
+
+use fetiche_formats::{prepare_csv, Asd, Cat21, StateList};
+
+/// Record counts we bench with
+const SIZES: [usize; 2] = [100, 10_000];
+
+/// Generate `n` ASD records, a drone flying in circle around CDG.
+///
+fn asd_fixture(n: usize) -> Vec<Asd> {
+    (0..n)
+        .map(|i| {
+            let a = (i as f32 / 60.).to_radians();
+            let tm = Utc.timestamp_opt(1_700_000_000 + i as i64, 0).unwrap();
+            Asd {
+                time: tm,
+                journey: (i / 1000) as u32,
+                ident: format!("1581F{:08}", i / 1000),
+                model: Some("Mavic 3".to_string()),
+                source: "as".to_string(),
+                location: i as u32,
+                timestamp: tm.format("%Y-%m-%d %H:%M:%S").to_string(),
+                latitude: 49.0097 + 0.01 * a.sin(),
+                longitude: 2.5479 + 0.01 * a.cos(),
+                altitude: Some(80),
+                elevation: Some(80),
+                gps: Some(12),
+                rssi: Some(-60),
+                home_lat: Some(49.0097),
+                home_lon: Some(2.5479),
+                home_height: Some(0.),
+                speed: 8.,
+                heading: (i % 360) as f32,
+                station_name: Some("CDG".to_string()),
+                station_latitude: Some(49.0097),
+                station_longitude: Some(2.5479),
+            }
+        })
+        .collect()
+}
+
+/// Take the real Opensky payload and repeat its state vectors until we have `n` of them.
+///
+fn opensky_fixture(n: usize) -> String {
+    let data = fs::read_to_string("../data/202306042003.json").unwrap();
+    let data: serde_json::Value = serde_json::from_str(&data).unwrap();
+    let states = data["states"].as_array().unwrap();
+    let states: Vec<_> = states.iter().cycle().take(n).cloned().collect();
+
+    json!({"time": data["time"], "states": states}).to_string()
+}
+
+/// Opensky JSON payload to Cat21, same path as the `Convert` task.
+///
+fn opensky_to_cat21(c: &mut Criterion) {
+    let mut group = c.benchmark_group("opensky to cat21");
+    for n in SIZES {
+        let data = opensky_fixture(n);
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &data, |b, data| {
+            b.iter(|| {
+                let sl: StateList = serde_json::from_str(data).unwrap();
+                sl.to_cat21()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// ASD JSON stream to Cat21
+///
+fn asd_to_cat21(c: &mut Criterion) {
+    let mut group = c.benchmark_group("asd to cat21");
+    for n in SIZES {
+        let data = asd_fixture(n)
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &data, |b, data| {
+            b.iter(|| Cat21::from_asd(data).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// CSV writer used for the final output
+///
+fn csv_writer(c: &mut Criterion) {
+    let mut group = c.benchmark_group("csv writer");
+    for n in SIZES {
+        let data = asd_fixture(n);
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &data, |b, data| {
+            b.iter_batched(
+                || data.iter().map(Cat21::from).collect::<Vec<_>>(),
+                |data| prepare_csv(data, true).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Parquet writer, same path as the `Save` task (CSV read and written through datafusion).
+///
+fn parquet_writer(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let dir = tempdir().unwrap();
+
+    let mut group = c.benchmark_group("parquet writer");
+    group.sample_size(10);
+    for n in SIZES {
+        let csv = dir.path().join(format!("asd-{}.csv", n));
+        let mut wtr = csv::Writer::from_path(&csv).unwrap();
+        asd_fixture(n)
+            .iter()
+            .for_each(|r| wtr.serialize(r).unwrap());
+        wtr.flush().unwrap();
+
+        let csv = csv.to_string_lossy().to_string();
+        let out = dir.path().join(format!("asd-{}.parquet", n));
+        let out = out.to_string_lossy().to_string();
+
+        group.throughput(Throughput::Elements(n as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(n), &n, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                let ctx = SessionContext::new();
+                let df = ctx.read_csv(&csv, CsvReadOptions::default()).await.unwrap();
+                let dfopts = DataFrameWriteOptions::default().with_single_file_output(true);
+                let mut options = TableParquetOptions::default();
+                options.global.compression = Some("zstd(8)".to_string());
+                df.write_parquet(&out, dfopts, Some(options)).await.unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    opensky_to_cat21,
+    asd_to_cat21,
+    csv_writer,
+    parquet_writer
+);
+
+criterion_main!(benches);
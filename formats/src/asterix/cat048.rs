@@ -0,0 +1,570 @@
+//! Asterix Category 048, monoradar target reports (PSR/SSR plots).
+//!
+//! Recordings are raw Asterix data blocks one after the other (CAT, LEN then records), blocks of
+//! other categories are skipped.  Records are read with the standard UAP of CAT048, only the
+//! items we need are decoded:
+//! - I048/010 data source (SAC/SIC),
+//! - I048/140 time of day,
+//! - I048/020 target report descriptor (type of detection, SPI, simulated),
+//! - I048/040 measured position in polar coordinates,
+//! - I048/070 Mode-3/A code and I048/090 flight level,
+//! - I048/220 aircraft address and I048/240 identification for Mode-S,
+//! - I048/161 track number and I048/200 calculated velocity.
+//!
+//! The pipeline carries text so `Cat048::to_text()` turns a recording into one hex-encoded block
+//! per line, this is what `Read` sends for this format.
+//!
+//! Plots are relative to the radar and only have the time of day, the `Radar` position and day
+//! of the recording are needed to get `Cat21` records out of them.
+//!
+
+use std::str::FromStr;
+
+use chrono::{NaiveDate, Utc};
+use eyre::{eyre, Result};
+use serde::Serialize;
+use tracing::{trace, warn};
+
+use fetiche_common::geo::destination;
+
+use crate::{Bool, Cat21, TodCalculated};
+
+/// Asterix category of radar plots
+pub const CAT048: u8 = 48;
+
+/// One nautical mile in meters
+const NM: f64 = 1852.;
+
+/// Layout of an item of the UAP, to skip the ones we do not decode
+///
+#[derive(Clone, Copy, Debug)]
+enum Item {
+    /// Fixed length
+    Fixed(usize),
+    /// Extended with the FX bit
+    Extended,
+    /// Repetition factor then n times this length
+    Repetitive(usize),
+    /// I048/130, one byte per subfield
+    Compound130,
+    /// I048/120, CAL then repetitive RDS
+    Compound120,
+    /// Length in the first byte, SP and RE
+    Explicit,
+}
+
+/// UAP of CAT048, FRN 1 to 28
+const UAP: [Item; 28] = [
+    Item::Fixed(2),      // 010
+    Item::Fixed(3),      // 140
+    Item::Extended,      // 020
+    Item::Fixed(4),      // 040
+    Item::Fixed(2),      // 070
+    Item::Fixed(2),      // 090
+    Item::Compound130,   // 130
+    Item::Fixed(3),      // 220
+    Item::Fixed(6),      // 240
+    Item::Repetitive(8), // 250
+    Item::Fixed(2),      // 161
+    Item::Fixed(4),      // 042
+    Item::Fixed(4),      // 200
+    Item::Extended,      // 170
+    Item::Fixed(4),      // 210
+    Item::Extended,      // 030
+    Item::Fixed(2),      // 080
+    Item::Fixed(4),      // 100
+    Item::Fixed(2),      // 110
+    Item::Compound120,   // 120
+    Item::Fixed(2),      // 230
+    Item::Fixed(7),      // 260
+    Item::Fixed(1),      // 055
+    Item::Fixed(2),      // 050
+    Item::Fixed(1),      // 065
+    Item::Fixed(2),      // 060
+    Item::Explicit,      // SP
+    Item::Explicit,      // RE
+];
+
+/// One radar plot
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Cat048 {
+    /// System Area Code
+    pub sac: u8,
+    /// System Identification Code
+    pub sic: u8,
+    /// Seconds since midnight UTC
+    pub tod: f64,
+    /// Type of detection (I048/020 TYP): 1 PSR, 2 SSR, 3 SSR+PSR, 4-7 Mode-S
+    pub typ: u8,
+    /// Special Position Identification
+    pub spi: bool,
+    /// Simulated target
+    pub simulated: bool,
+    /// Slant range (NM) and azimuth (degrees) from the radar
+    pub polar: Option<(f64, f64)>,
+    /// Mode-3/A code
+    pub mode_3a: Option<u16>,
+    /// Flight level, in hundreds of feet
+    pub flight_level: Option<f64>,
+    /// Mode-S address
+    pub address: Option<u32>,
+    /// Mode-S identification
+    pub callsign: Option<String>,
+    /// Track number
+    pub track_number: Option<u16>,
+    /// Ground speed in knots
+    pub ground_speed: Option<f64>,
+    /// Heading in degrees
+    pub heading: Option<f64>,
+}
+
+impl Cat048 {
+    /// Decode every plot of a recording
+    ///
+    #[tracing::instrument(skip(data))]
+    pub fn decode(data: &[u8]) -> Result<Vec<Cat048>> {
+        let mut res = vec![];
+        for block in blocks(data)? {
+            if block[0] != CAT048 {
+                trace!("skip cat{:03} block", block[0]);
+                continue;
+            }
+            let mut r = Reader::new(&block[3..]);
+            while !r.is_empty() {
+                res.push(Cat048::record(&mut r)?);
+            }
+        }
+        Ok(res)
+    }
+
+    /// One hex-encoded CAT048 block per line
+    ///
+    pub fn to_text(data: &[u8]) -> Result<String> {
+        Ok(blocks(data)?
+            .into_iter()
+            .filter(|b| b[0] == CAT048)
+            .map(|b| hex::encode(b) + "\n")
+            .collect())
+    }
+
+    /// Decode the output of `to_text()`, invalid lines are skipped.
+    ///
+    pub fn from_text(input: &str) -> Result<Vec<Cat048>> {
+        Ok(input
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .filter_map(|l| {
+                hex::decode(l.trim())
+                    .map_err(|e| eyre!("cat048: {}", e))
+                    .and_then(|b| Cat048::decode(&b))
+                    .map_err(|e| warn!("{}", e))
+                    .ok()
+            })
+            .flatten()
+            .collect())
+    }
+
+    /// Decode one record
+    ///
+    fn record(r: &mut Reader) -> Result<Cat048> {
+        let fspec = r.extended()?;
+        let frns = fspec.iter().enumerate().flat_map(|(i, b)| {
+            (0..7)
+                .filter(move |bit| b & (0x80 >> bit) != 0)
+                .map(move |bit| i * 7 + bit + 1)
+        });
+
+        let mut plot = Cat048::default();
+        for frn in frns {
+            let Some(item) = UAP.get(frn - 1) else {
+                return Err(eyre!("cat048: unknown FRN {}", frn));
+            };
+            let data = r.item(*item)?;
+            match frn {
+                1 => (plot.sac, plot.sic) = (data[0], data[1]),
+                2 => plot.tod = u24(data) as f64 / 128.,
+                3 => {
+                    plot.typ = data[0] >> 5;
+                    plot.simulated = data[0] & 0x10 != 0;
+                    plot.spi = data[0] & 0x04 != 0;
+                }
+                4 => {
+                    let rho = u16(data) as f64 / 256.;
+                    let theta = u16(&data[2..]) as f64 * 360. / 65536.;
+                    plot.polar = Some((rho, theta));
+                }
+                5 => plot.mode_3a = Some(u16(data) & 0x0fff),
+                6 => {
+                    // 14-bit two's complement, in 1/4 FL
+                    //
+                    let fl = ((u16(data) << 2) as i16) >> 2;
+                    plot.flight_level = Some(fl as f64 / 4.);
+                }
+                8 => plot.address = Some(u24(data)),
+                9 => plot.callsign = Some(callsign(data)),
+                11 => plot.track_number = Some(u16(data) & 0x0fff),
+                13 => {
+                    // 2^-14 NM/s
+                    //
+                    plot.ground_speed = Some(u16(data) as f64 * 3600. / 16384.);
+                    plot.heading = Some(u16(&data[2..]) as f64 * 360. / 65536.);
+                }
+                _ => (),
+            }
+        }
+        Ok(plot)
+    }
+}
+
+/// Split a recording into data blocks
+///
+fn blocks(data: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut res = vec![];
+    let mut data = data;
+    while !data.is_empty() {
+        if data.len() < 3 {
+            return Err(eyre!("cat048: truncated block header"));
+        }
+        let len = u16(&data[1..]) as usize;
+        if len < 3 || len > data.len() {
+            return Err(eyre!(
+                "cat048: bad block length {} ({} left)",
+                len,
+                data.len()
+            ));
+        }
+        res.push(&data[..len]);
+        data = &data[len..];
+    }
+    Ok(res)
+}
+
+#[inline]
+fn u16(b: &[u8]) -> u16 {
+    u16::from_be_bytes([b[0], b[1]])
+}
+
+#[inline]
+fn u24(b: &[u8]) -> u32 {
+    u32::from_be_bytes([0, b[0], b[1], b[2]])
+}
+
+/// Mode-S identification, eight 6-bit IA-5 characters
+///
+fn callsign(b: &[u8]) -> String {
+    let bits = b.iter().fold(0u64, |acc, b| acc << 8 | *b as u64);
+    (0..8)
+        .map(|i| match (bits >> (42 - 6 * i)) & 0x3f {
+            c @ 1..=26 => (b'A' + c as u8 - 1) as char,
+            c @ 48..=57 => c as u8 as char,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Cursor over the records of a block
+///
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos + n;
+        if end > self.buf.len() {
+            return Err(eyre!("cat048: truncated record"));
+        }
+        let res = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    /// Bytes up to the first one without FX
+    ///
+    fn extended(&mut self) -> Result<&'a [u8]> {
+        let start = self.pos;
+        while self.take(1)?[0] & 0x01 != 0 {}
+        Ok(&self.buf[start..self.pos])
+    }
+
+    /// Raw bytes of an item
+    ///
+    fn item(&mut self, item: Item) -> Result<&'a [u8]> {
+        let start = self.pos;
+        match item {
+            Item::Fixed(n) => {
+                self.take(n)?;
+            }
+            Item::Extended => {
+                self.extended()?;
+            }
+            Item::Repetitive(n) => {
+                let rep = self.take(1)?[0] as usize;
+                self.take(rep * n)?;
+            }
+            Item::Compound130 => {
+                let n = self
+                    .extended()?
+                    .iter()
+                    .map(|b| (b & 0xfe).count_ones() as usize)
+                    .sum();
+                self.take(n)?;
+            }
+            Item::Compound120 => {
+                let primary = self.extended()?[0];
+                if primary & 0x80 != 0 {
+                    self.take(2)?;
+                }
+                if primary & 0x40 != 0 {
+                    let rep = self.take(1)?[0] as usize;
+                    self.take(rep * 6)?;
+                }
+            }
+            Item::Explicit => {
+                let len = self.take(1)?[0] as usize;
+                self.take(len.saturating_sub(1))?;
+            }
+        }
+        Ok(&self.buf[start..self.pos])
+    }
+}
+
+/// Where the radar is and when the recording was made
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Radar {
+    /// Latitude in degrees
+    pub latitude: f64,
+    /// Longitude in degrees
+    pub longitude: f64,
+    /// Antenna altitude in meters
+    pub altitude: f64,
+    /// Day of the first plot, UTC
+    pub day: NaiveDate,
+}
+
+impl Radar {
+    /// Radar at this position, recording of today
+    ///
+    pub fn new(latitude: f64, longitude: f64, altitude: f64) -> Self {
+        Radar {
+            latitude,
+            longitude,
+            altitude,
+            day: Utc::now().date_naive(),
+        }
+    }
+
+    /// Set the day of the recording
+    ///
+    pub fn day(&mut self, day: NaiveDate) -> &mut Self {
+        self.day = day;
+        self
+    }
+
+    /// Geographic position of the plots, the ones without a measured position are skipped.
+    ///
+    /// The slant range is projected on the ground with the flight level (or as if on the ground
+    /// without one), time of day going backward by more than 12h means we are past midnight.
+    ///
+    pub fn to_cat21(&self, plots: &[Cat048]) -> Vec<Cat21> {
+        let midnight = self.day.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+
+        let mut days = 0;
+        let mut last = 0.;
+        plots
+            .iter()
+            .filter_map(|p| {
+                if p.tod < last - 43200. {
+                    days += 1;
+                }
+                last = p.tod;
+
+                let (rho, theta) = p.polar?;
+                let alt_ft = p.flight_level.unwrap_or_default().max(0.) * 100.;
+                let height = alt_ft * 0.3048 - self.altitude;
+                let range = ((rho * NM).powi(2) - height.powi(2)).max(0.).sqrt();
+                let (lat, lon) = destination(self.latitude, self.longitude, theta, range);
+
+                let tm = midnight + days * 86400 + p.tod as i64;
+                let yes_no = |b| if b { Bool::Y } else { Bool::N };
+                Some(Cat21 {
+                    sac: p.sac as usize,
+                    sic: p.sic as usize,
+                    alt_geo_ft: alt_ft as u32,
+                    pos_lat_deg: lat as f32,
+                    pos_long_deg: lon as f32,
+                    alt_baro_ft: alt_ft as u32,
+                    tod: (p.tod * 128.) as i64,
+                    rec_time_posix: tm,
+                    rec_time_ms: (p.tod.fract() * 1000.) as u32,
+                    simulated_target: yes_no(p.simulated),
+                    spi: yes_no(p.spi),
+                    target_addr: p.address.unwrap_or_default(),
+                    cat: CAT048 as usize,
+                    line_id: 1,
+                    report_type: 3,
+                    tod_calculated: TodCalculated::N,
+                    callsign: p.callsign.clone().unwrap_or_default(),
+                    groundspeed_kt: p.ground_speed.unwrap_or_default() as f32,
+                    track_angle_deg: p.heading.unwrap_or_default() as f32,
+                    rec_num: 1,
+                    ..Cat21::default()
+                })
+            })
+            .collect()
+    }
+}
+
+/// "LAT,LON" or "LAT,LON,ALT" (meters), for today.
+///
+impl FromStr for Radar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let v = s
+            .split(',')
+            .map(|f| f.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("bad radar position {}: {}", s, e))?;
+        match v[..] {
+            [lat, lon] => Ok(Radar::new(lat, lon, 0.)),
+            [lat, lon, alt] => Ok(Radar::new(lat, lon, alt)),
+            _ => Err(format!("bad radar position {}, want LAT,LON[,ALT]", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// One plot from a Mode-S roll-call: 010, 140, 020, 040, 070, 090, 220, 240, 161 and 200.
+    ///
+    const PLOT: [u8; 34] = [
+        0x30, 0x00, 0x22, // CAT048, 34 bytes
+        0xfd, 0xd4, // FSPEC
+        0x08, 0x2a, // SAC 8, SIC 42
+        0x46, 0x50, 0x00, // 10:00:00
+        0xa0, // roll-call
+        0x0a, 0x00, 0x40, 0x00, // 10 NM, 90°
+        0x0e, 0x00, // 7000
+        0x01, 0x90, // FL100
+        0x4c, 0xa2, 0xd6, // address
+        0x49, 0x94, 0xb4, 0x2d, 0x88, 0x20, // RYR4KX
+        0x04, 0xd2, // track 1234
+        0x07, 0x77, 0x3e, 0x39, // 420 kt, 87.5°
+    ];
+
+    /// A CAT034 north marker, to be skipped
+    ///
+    const NORTH: [u8; 6] = [0x22, 0x00, 0x06, 0x80, 0x08, 0x2a];
+
+    #[test]
+    fn test_cat048_decode() -> Result<()> {
+        let data = [&NORTH[..], &PLOT[..]].concat();
+        let res = Cat048::decode(&data)?;
+        assert_eq!(1, res.len());
+
+        let p = &res[0];
+        assert_eq!((8, 42), (p.sac, p.sic));
+        assert_eq!(36000., p.tod);
+        assert_eq!(5, p.typ);
+        assert_eq!(Some((10., 90.)), p.polar);
+        assert_eq!(Some(0o7000), p.mode_3a);
+        assert_eq!(Some(100.), p.flight_level);
+        assert_eq!(Some(0x4ca2d6), p.address);
+        assert_eq!(Some("RYR4KX".to_string()), p.callsign);
+        assert_eq!(Some(1234), p.track_number);
+        assert!((p.ground_speed.unwrap() - 420.).abs() < 0.5);
+        assert!((p.heading.unwrap() - 87.5).abs() < 0.01);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(&PLOT[..2])]
+    #[case(&PLOT[..20])]
+    #[case(&[0x30, 0x00, 0x02])]
+    fn test_cat048_truncated(#[case] data: &[u8]) {
+        assert!(Cat048::decode(data).is_err());
+    }
+
+    #[test]
+    fn test_cat048_negative_fl() -> Result<()> {
+        let data = [0x30, 0x00, 0x06, 0x04, 0x3f, 0xfc];
+        let res = Cat048::decode(&data)?;
+        assert_eq!(Some(-1.), res[0].flight_level);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat048_text() -> Result<()> {
+        let data = [&PLOT[..], &NORTH[..], &PLOT[..]].concat();
+        let text = Cat048::to_text(&data)?;
+        assert_eq!(2, text.lines().count());
+
+        let text = format!("{}not hex\n", text);
+        assert_eq!(2, Cat048::from_text(&text)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat048_to_cat21() -> Result<()> {
+        let mut radar: Radar = "49.0,2.5".parse().unwrap();
+        radar.day(NaiveDate::from_ymd_opt(2024, 5, 12).unwrap());
+
+        let mut plots = Cat048::decode(&PLOT)?;
+        plots.push(Cat048 {
+            tod: 86000.,
+            polar: Some((1., 0.)),
+            ..Cat048::default()
+        });
+        plots.push(Cat048 {
+            tod: 10.,
+            polar: Some((1., 0.)),
+            ..Cat048::default()
+        });
+        plots.push(Cat048::default());
+        let res = radar.to_cat21(&plots);
+        assert_eq!(3, res.len());
+
+        let r = &res[0];
+        assert!((r.pos_lat_deg - 49.0).abs() < 0.01);
+        assert!((r.pos_long_deg - 2.75).abs() < 0.01);
+        assert_eq!(10000, r.alt_baro_ft);
+        assert_eq!(1715508000, r.rec_time_posix);
+        assert_eq!(0x4ca2d6, r.target_addr);
+        assert_eq!("RYR4KX", r.callsign);
+        assert_eq!(48, r.cat);
+
+        // Past midnight
+        //
+        assert_eq!(1715558000, res[1].rec_time_posix);
+        assert_eq!(1715558410, res[2].rec_time_posix);
+        assert!(res[2].pos_lat_deg > 49.0);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("49.0,2.5", Some(0.))]
+    #[case("49.0, 2.5, 120", Some(120.))]
+    #[case("49.0", None)]
+    #[case("north,2.5", None)]
+    fn test_radar_from_str(#[case] s: &str, #[case] alt: Option<f64>) {
+        assert_eq!(alt, s.parse::<Radar>().ok().map(|r| r.altitude));
+    }
+}
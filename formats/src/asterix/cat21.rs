@@ -2,6 +2,44 @@ use crate::{Bool, TodCalculated, DEF_SAC, DEF_SIC};
 
 use serde::Serialize;
 
+/// Columns of the Cat21 output, in order
+pub const CAT21_COLUMNS: [&str; 34] = [
+    "SAC",
+    "SIC",
+    "ALT_GEO_FT",
+    "POS_LAT_DEG",
+    "POS_LONG_DEG",
+    "ALT_BARO_FT",
+    "TOD",
+    "REC_TIME_POSIX",
+    "REC_TIME_MS",
+    "EMITTER_CATEGORY",
+    "DIFFERENTIAL_CORRECTION",
+    "GROUND_BIT",
+    "SIMULATED_TARGET",
+    "TEST_TARGET",
+    "FROM_FT",
+    "SELECTED_ALT_CAPABILITY",
+    "SPI",
+    "LINK_TECHNOLOGY_CDDI",
+    "LINK_TECHNOLOGY_MDS",
+    "LINK_TECHNOLOGY_UAT",
+    "LINK_TECHNOLOGY_VDL",
+    "LINK_TECHNOLOGY_OTHER",
+    "DESCRIPTOR_ATP",
+    "ALT_REPORTING_CAPABILITY_FT",
+    "TARGET_ADDR",
+    "CAT",
+    "LINE_ID",
+    "DS_ID",
+    "REPORT_TYPE",
+    "TOD_CALCULATED",
+    "CALLSIGN",
+    "GROUNDSPEED_KT",
+    "TRACK_ANGLE_DEG",
+    "REC_NUM",
+];
+
 /// Our pseudo cat21 csv output, we add the mapping from the awk script in comment
 ///
 /// SAC:SIC:ALT_GEO_FT:POS_LAT_DEG:POS_LONG_DEG:ALT_BARO_FT:TOD:REC_TIME_POSIX:REC_TIME_MS:
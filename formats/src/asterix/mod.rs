@@ -4,10 +4,12 @@
 //!
 
 mod adsb;
+mod cat048;
 mod cat129;
 mod cat21;
 
 pub use adsb::*;
+pub use cat048::*;
 pub use cat129::*;
 pub use cat21::*;
 
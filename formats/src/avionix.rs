@@ -2,11 +2,19 @@
 //!
 //! URL: http://www.avionix.pl
 //!
+//! CUBE messages are decoded through `Schema` as the Aero Network API has already renamed some
+//! fields: the first revision (`cube/1`) had `icao` and `call` instead of `hex` and `fli`.  The
+//! fields documented as optional (marked with `*`) can be missing.
+//!
 
 use chrono::{DateTime, Utc};
+use eyre::Result;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use strum::EnumString;
+use tracing::debug;
+
+use crate::{decode, Cat21, Revision, Schema, Strictness, TodCalculated};
 
 /// Avionix CUBE drone antenna output format
 ///
@@ -41,6 +49,7 @@ pub struct AvionixCube {
     /// - altg  Geometric altitude in feet -- Integer -- 5400
     pub altg: u32,
     /// - hgt   Difference between barometric and geometric altitude in ft* -- Integer -- -225
+    #[serde(default)]
     pub hgt: i32,
     /// - spd   Ground speed in knots -- Integer -- 49
     pub spd: u32,
@@ -62,21 +71,97 @@ pub struct AvionixCube {
     /// - dbm   Signal strentgh of last received message -- Integer -- -91
     pub dbm: i32,
     /// - shd   Selected heading* -- Integer -- 293
+    #[serde(default)]
     pub shd: u32,
     /// - org   ICAO code airport of origin* -- String “EDDK”
+    #[serde(default)]
     pub org: String,
     /// - dst   ICAO code airport of destination* -- String -- “EPKK”
+    #[serde(default)]
     pub dst: String,
     /// - opr   Operator* -- String -- “GWI”
+    #[serde(default)]
     pub opr: String,
     /// - typ   Aircraft type* -- String “A319”
+    #[serde(default)]
     pub typ: String,
     /// - reg   Registration* -- String “D-AKNM”
+    #[serde(default)]
     pub reg: String,
     /// - cou   Country* -- String -- “Germany”
+    #[serde(default)]
     pub cou: String,
 }
 
+impl Schema for AvionixCube {
+    const NAME: &'static str = "cube";
+    const REVISIONS: &'static [Revision] = &[
+        Revision {
+            version: "cube/2",
+            keys: &["uti", "hex", "lat", "lon"],
+            renames: &[],
+        },
+        Revision {
+            version: "cube/1",
+            keys: &["uti", "icao", "lat", "lon"],
+            renames: &[("icao", "hex"), ("call", "fli")],
+        },
+    ];
+}
+
+impl Cat21 {
+    /// Decode CUBE messages, whatever their revision, into Cat21 records.
+    ///
+    #[tracing::instrument(skip(input))]
+    pub fn from_avionix_cube(input: &str, strictness: Strictness) -> Result<Vec<Cat21>> {
+        let res = decode::<AvionixCube>(input, strictness)?;
+        debug!(
+            "{} messages, {} upgraded, {} dropped",
+            res.records.len(),
+            res.upgraded,
+            res.dropped
+        );
+        Ok(res.records.iter().map(Cat21::from).collect())
+    }
+}
+
+impl From<&AvionixCube> for Cat21 {
+    /// Altitudes are already in feet and speed in knots.
+    ///
+    /// The following fields are **lost**:
+    /// - gda, src, cat
+    /// - squ, vrt, mop
+    /// - lla, tru, dbm
+    /// - shd, org, dst, opr, typ, reg, cou
+    ///
+    #[tracing::instrument]
+    fn from(line: &AvionixCube) -> Self {
+        let tod = line.time as i64;
+        Cat21 {
+            alt_geo_ft: line.altg,
+            pos_lat_deg: line.lat as f32,
+            pos_long_deg: line.lon as f32,
+            alt_baro_ft: line.alt,
+            tod: 128 * (tod % 86400),
+            rec_time_posix: tod,
+            emitter_category: 13,
+            descriptor_atp: 1,
+            alt_reporting_capability_ft: 0,
+            target_addr: u32::from_str_radix(&line.hex, 16).unwrap_or_default(),
+            cat: 21,
+            line_id: 1,
+            ds_id: 18,
+            report_type: 3,
+            tod_calculated: TodCalculated::N,
+            callsign: line.fli.trim().to_string(),
+            groundspeed_kt: line.spd as f32,
+            track_angle_deg: line.trk as f32,
+            rec_num: 1,
+            ..Cat21::default()
+        }
+    }
+}
+
 // -----
 
 /// Avionix pseudo-Cat21 coming from the ADS-B receiver.
@@ -219,3 +304,30 @@ enum Category {
     /// Ground Vehicule
     O14,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = r##"{"uti":1715509815,"dat":"2024-05-12 10:30:15.250000000","hex":"4ca2d6","tim":"10:30:15.25","fli":"DRONE1 ","lat":49.6116,"lon":6.2061,"gda":"A","src":"RID","alt":400,"altg":390,"spd":20,"cat":"O13","squ":"","vrt":0,"trk":87.5,"mop":0,"lla":0,"tru":12,"dbm":-80}
+{"uti":1715509816,"dat":"2024-05-12 10:30:16.250000000","icao":"4ca2d6","tim":"10:30:16.25","call":"DRONE1","lat":49.6117,"lon":6.2062,"gda":"A","src":"RID","alt":400,"altg":390,"spd":20,"cat":"O13","squ":"","vrt":0,"trk":87.5,"mop":0,"lla":0,"tru":13,"dbm":-80}"##;
+
+    #[test]
+    fn test_avionix_cube_into_cat21() -> Result<()> {
+        let res = Cat21::from_avionix_cube(CUBE, Strictness::Fail)?;
+        assert_eq!(2, res.len());
+
+        let line = &res[0];
+        assert_eq!(0x4CA2D6, line.target_addr);
+        assert_eq!("DRONE1", line.callsign);
+        assert_eq!(390, line.alt_geo_ft);
+        assert_eq!(20.0, line.groundspeed_kt);
+        assert_eq!(1715509815, line.rec_time_posix);
+
+        // `cube/1` message
+        //
+        assert_eq!(0x4CA2D6, res[1].target_addr);
+        assert_eq!("DRONE1", res[1].callsign);
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Unified vehicle category.
+//!
+//! Every source tells what a target is in its own way: the ADS-B emitter category (Asterix
+//! I021/020, also used by Opensky and in our `Cat21`), the drone model for Aeroscope and Remote ID
+//! (ASD, Senhive), the aircraft type for Safesky.  `VehicleCategory` is the common taxonomy, each
+//! source having its mapping table here:
+//!
+//! | Category         | Emitter category | Safesky                          | Drone model          |
+//! |------------------|------------------|----------------------------------|----------------------|
+//! | `fixed-wing`     | 1-6, 15          | `MOTORPLANE`, `JET`, `ULM`, ...  |                      |
+//! | `rotorcraft`     | 10               | `HELICOPTER`, `GYROCOPTER`       |                      |
+//! | `glider`         | 11               | `GLIDER`, `PARA_GLIDER`, ...     |                      |
+//! | `lighter-than-air` | 12             | `BALLOON`, `AIRSHIP`             |                      |
+//! | `parachutist`    | 16               | `PARACHUTE`                      |                      |
+//! | `uas`            | 13               | `UAV`, `DRONE`                   | unknown model        |
+//! | `uas-multirotor` |                  |                                  | Mavic, Matrice, ...  |
+//! | `uas-fixed-wing` |                  |                                  | eBee, Wingtra, ...   |
+//! | `ground`         | 20, 21           |                                  |                      |
+//! | `obstacle`       | 22-24            | `GROUND_OBSTACLE`, ...           |                      |
+//! | `unknown`        | anything else    | anything else                    |                      |
+//!
+//! `uas` used as a filter matches every kind of UAS, see `matches()`.
+//!
+
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+
+use crate::{Aeroscope, Asd, Cat21, Category, Safesky, Senhive};
+
+/// What a target is, whatever the source
+///
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Serialize,
+    strum::Display,
+    EnumString,
+    strum::VariantNames,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum VehicleCategory {
+    /// Nothing or nothing useful
+    #[default]
+    Unknown,
+    /// Aeroplanes, from ultralights to heavies
+    FixedWing,
+    /// Helicopters and gyrocopters
+    Rotorcraft,
+    /// Gliders, paragliders and hang-gliders
+    Glider,
+    /// Balloons and airships
+    LighterThanAir,
+    /// Parachutists
+    Parachutist,
+    /// Unmanned, type unknown
+    Uas,
+    /// Unmanned with rotors
+    #[serde(rename = "uas-multirotor")]
+    #[strum(serialize = "uas-multirotor")]
+    UasMultiRotor,
+    /// Unmanned with wings, including VTOL ones
+    UasFixedWing,
+    /// Surface vehicles
+    Ground,
+    /// Fixed obstacles
+    Obstacle,
+}
+
+/// Drone models, lowercase, the first one contained in the model wins
+const DRONE_MODELS: &[(&str, VehicleCategory)] = &[
+    ("ebee", VehicleCategory::UasFixedWing),
+    ("wingtra", VehicleCategory::UasFixedWing),
+    ("skywalker", VehicleCategory::UasFixedWing),
+    ("trinity", VehicleCategory::UasFixedWing),
+    ("c-astral", VehicleCategory::UasFixedWing),
+    ("mavic", VehicleCategory::UasMultiRotor),
+    ("phantom", VehicleCategory::UasMultiRotor),
+    ("inspire", VehicleCategory::UasMultiRotor),
+    ("matrice", VehicleCategory::UasMultiRotor),
+    ("agras", VehicleCategory::UasMultiRotor),
+    ("avata", VehicleCategory::UasMultiRotor),
+    ("spark", VehicleCategory::UasMultiRotor),
+    ("mini", VehicleCategory::UasMultiRotor),
+    ("air 2", VehicleCategory::UasMultiRotor),
+    ("air 3", VehicleCategory::UasMultiRotor),
+    ("fpv", VehicleCategory::UasMultiRotor),
+    ("tello", VehicleCategory::UasMultiRotor),
+    ("anafi", VehicleCategory::UasMultiRotor),
+    ("evo", VehicleCategory::UasMultiRotor),
+    ("skydio", VehicleCategory::UasMultiRotor),
+];
+
+/// Safesky aircraft types
+const SAFESKY_TYPES: &[(&str, VehicleCategory)] = &[
+    ("MOTORPLANE", VehicleCategory::FixedWing),
+    ("JET", VehicleCategory::FixedWing),
+    ("MILITARY", VehicleCategory::FixedWing),
+    ("THREE_AXES_ULM", VehicleCategory::FixedWing),
+    ("FLEX_WING_TRIKES", VehicleCategory::FixedWing),
+    ("PARA_MOTOR", VehicleCategory::Glider),
+    ("HELICOPTER", VehicleCategory::Rotorcraft),
+    ("GYROCOPTER", VehicleCategory::Rotorcraft),
+    ("GLIDER", VehicleCategory::Glider),
+    ("PARA_GLIDER", VehicleCategory::Glider),
+    ("HAND_GLIDER", VehicleCategory::Glider),
+    ("BALLOON", VehicleCategory::LighterThanAir),
+    ("AIRSHIP", VehicleCategory::LighterThanAir),
+    ("PARACHUTE", VehicleCategory::Parachutist),
+    ("UAV", VehicleCategory::Uas),
+    ("DRONE", VehicleCategory::Uas),
+    ("GROUND_OBSTACLE", VehicleCategory::Obstacle),
+    ("STATIC_OBJECT", VehicleCategory::Obstacle),
+];
+
+impl VehicleCategory {
+    /// From the Asterix I021/020 emitter category
+    ///
+    pub fn from_emitter(ecat: usize) -> Self {
+        match ecat {
+            1..=6 | 15 => VehicleCategory::FixedWing,
+            10 => VehicleCategory::Rotorcraft,
+            11 => VehicleCategory::Glider,
+            12 => VehicleCategory::LighterThanAir,
+            13 => VehicleCategory::Uas,
+            16 => VehicleCategory::Parachutist,
+            20 | 21 => VehicleCategory::Ground,
+            22..=24 => VehicleCategory::Obstacle,
+            _ => VehicleCategory::Unknown,
+        }
+    }
+
+    /// Closest Asterix I021/020 emitter category, 0 being "no information"
+    ///
+    pub fn ecat(&self) -> usize {
+        match self {
+            VehicleCategory::Unknown => 0,
+            VehicleCategory::FixedWing => 1,
+            VehicleCategory::Rotorcraft => 10,
+            VehicleCategory::Glider => 11,
+            VehicleCategory::LighterThanAir => 12,
+            VehicleCategory::Uas
+            | VehicleCategory::UasMultiRotor
+            | VehicleCategory::UasFixedWing => 13,
+            VehicleCategory::Parachutist => 16,
+            VehicleCategory::Ground => 21,
+            VehicleCategory::Obstacle => 22,
+        }
+    }
+
+    /// From a drone model, every target of a drone source being a UAS
+    ///
+    pub fn from_drone_model(model: Option<&str>) -> Self {
+        let Some(model) = model else {
+            return VehicleCategory::Uas;
+        };
+        let model = model.to_lowercase();
+        DRONE_MODELS
+            .iter()
+            .find(|(name, _)| model.contains(name))
+            .map(|(_, cat)| *cat)
+            .unwrap_or(VehicleCategory::Uas)
+    }
+
+    /// From a Safesky aircraft type
+    ///
+    pub fn from_safesky(atype: &str) -> Self {
+        SAFESKY_TYPES
+            .iter()
+            .find(|(name, _)| atype.eq_ignore_ascii_case(name))
+            .map(|(_, cat)| *cat)
+            .unwrap_or_default()
+    }
+
+    /// Any kind of UAS
+    ///
+    pub fn is_uas(&self) -> bool {
+        matches!(
+            self,
+            VehicleCategory::Uas | VehicleCategory::UasMultiRotor | VehicleCategory::UasFixedWing
+        )
+    }
+
+    /// Is this wanted by a filter on `wanted`?  `uas` matches every kind of UAS.
+    ///
+    pub fn matches(&self, wanted: VehicleCategory) -> bool {
+        *self == wanted || (wanted == VehicleCategory::Uas && self.is_uas())
+    }
+}
+
+impl From<Category> for VehicleCategory {
+    fn from(c: Category) -> Self {
+        VehicleCategory::from_emitter(c.ecat())
+    }
+}
+
+impl Cat21 {
+    /// Category from the emitter category
+    ///
+    pub fn category(&self) -> VehicleCategory {
+        VehicleCategory::from_emitter(self.emitter_category)
+    }
+}
+
+impl Asd {
+    /// Category from the drone model
+    ///
+    pub fn category(&self) -> VehicleCategory {
+        VehicleCategory::from_drone_model(self.model.as_deref())
+    }
+}
+
+impl Aeroscope {
+    /// Category from the drone type
+    ///
+    pub fn category(&self) -> VehicleCategory {
+        VehicleCategory::from_drone_model(Some(&self.drone_type))
+    }
+}
+
+impl Senhive {
+    /// Category from the drone model
+    ///
+    pub fn category(&self) -> VehicleCategory {
+        VehicleCategory::from_drone_model(self.drone.model.as_deref())
+    }
+}
+
+impl Safesky {
+    /// Category from the aircraft type
+    ///
+    pub fn category(&self) -> VehicleCategory {
+        VehicleCategory::from_safesky(&self.aircraft_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(0, VehicleCategory::Unknown)]
+    #[case(3, VehicleCategory::FixedWing)]
+    #[case(10, VehicleCategory::Rotorcraft)]
+    #[case(13, VehicleCategory::Uas)]
+    #[case(15, VehicleCategory::FixedWing)]
+    #[case(21, VehicleCategory::Ground)]
+    #[case(24, VehicleCategory::Obstacle)]
+    #[case(42, VehicleCategory::Unknown)]
+    fn test_from_emitter(#[case] ecat: usize, #[case] cat: VehicleCategory) {
+        assert_eq!(cat, VehicleCategory::from_emitter(ecat));
+    }
+
+    #[rstest]
+    #[case(Some("DJI Mavic 3"), VehicleCategory::UasMultiRotor)]
+    #[case(Some("MATRICE 300 RTK"), VehicleCategory::UasMultiRotor)]
+    #[case(Some("senseFly eBee X"), VehicleCategory::UasFixedWing)]
+    #[case(Some("Homebuilt"), VehicleCategory::Uas)]
+    #[case(None, VehicleCategory::Uas)]
+    fn test_from_drone_model(#[case] model: Option<&str>, #[case] cat: VehicleCategory) {
+        assert_eq!(cat, VehicleCategory::from_drone_model(model));
+    }
+
+    #[rstest]
+    #[case("HELICOPTER", VehicleCategory::Rotorcraft)]
+    #[case("para_glider", VehicleCategory::Glider)]
+    #[case("UAV", VehicleCategory::Uas)]
+    #[case("SPACESHIP", VehicleCategory::Unknown)]
+    fn test_from_safesky(#[case] atype: &str, #[case] cat: VehicleCategory) {
+        assert_eq!(cat, VehicleCategory::from_safesky(atype));
+    }
+
+    #[test]
+    fn test_category_names() {
+        assert_eq!(
+            VehicleCategory::UasMultiRotor,
+            VehicleCategory::from_str("uas-multirotor").unwrap()
+        );
+        assert_eq!(
+            VehicleCategory::UasFixedWing,
+            VehicleCategory::from_str("UAS-Fixed-Wing").unwrap()
+        );
+        assert_eq!(
+            "lighter-than-air",
+            VehicleCategory::LighterThanAir.to_string()
+        );
+        assert!(VehicleCategory::from_str("plane").is_err());
+    }
+
+    #[test]
+    fn test_category_matches() {
+        assert!(VehicleCategory::UasMultiRotor.matches(VehicleCategory::Uas));
+        assert!(VehicleCategory::Uas.matches(VehicleCategory::Uas));
+        assert!(!VehicleCategory::Uas.matches(VehicleCategory::UasMultiRotor));
+        assert!(!VehicleCategory::Rotorcraft.matches(VehicleCategory::Uas));
+        assert_eq!(
+            VehicleCategory::Rotorcraft,
+            VehicleCategory::from(Category::RotorCraft)
+        );
+        for cat in [
+            VehicleCategory::Glider,
+            VehicleCategory::Uas,
+            VehicleCategory::Ground,
+        ] {
+            assert_eq!(cat, VehicleCategory::from_emitter(cat.ecat()));
+        }
+    }
+}
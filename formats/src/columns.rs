@@ -0,0 +1,219 @@
+//! Column selection for the Cat21 output
+//!
+//! Our pseudo-Cat21 CSV has all the columns of `CAT21_COLUMNS`, some tools want less and reject
+//! files with columns they do not know.  `Columns` selects which ones are written:
+//!
+//! - `full`: everything, the default,
+//! - `minimal`: time, address, callsign, position, altitude, speed and heading,
+//! - `ids`: the position report as read by the IDS tool, without the flags and counters we make
+//!   up (`CAT21_IDS`),
+//! - or a comma-separated list of column names, in the order they are to be written.
+//!
+//! Names are matched without regard to case.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use eyre::{eyre, Result};
+
+/// Columns of the `minimal` profile
+pub const CAT21_MINIMAL: [&str; 8] = [
+    "REC_TIME_POSIX",
+    "TARGET_ADDR",
+    "CALLSIGN",
+    "POS_LAT_DEG",
+    "POS_LONG_DEG",
+    "ALT_GEO_FT",
+    "GROUNDSPEED_KT",
+    "TRACK_ANGLE_DEG",
+];
+
+/// Columns of the `ids` profile
+pub const CAT21_IDS: [&str; 14] = [
+    "SAC",
+    "SIC",
+    "ALT_GEO_FT",
+    "POS_LAT_DEG",
+    "POS_LONG_DEG",
+    "ALT_BARO_FT",
+    "TOD",
+    "REC_TIME_POSIX",
+    "REC_TIME_MS",
+    "EMITTER_CATEGORY",
+    "TARGET_ADDR",
+    "CALLSIGN",
+    "GROUNDSPEED_KT",
+    "TRACK_ANGLE_DEG",
+];
+
+/// Which columns to write
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Columns {
+    /// All of them
+    #[default]
+    Full,
+    /// `CAT21_MINIMAL`
+    Minimal,
+    /// `CAT21_IDS`
+    Ids,
+    /// These, in this order
+    Custom(Vec<String>),
+}
+
+impl Columns {
+    /// Position in `header` of every column we want, in output order.
+    ///
+    pub fn indices(&self, header: &[&str]) -> Result<Vec<usize>> {
+        let find = |name: &str| {
+            header
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(name))
+                .ok_or(eyre!("unknown column {name}"))
+        };
+        match self {
+            Columns::Full => Ok((0..header.len()).collect()),
+            Columns::Minimal => CAT21_MINIMAL.iter().map(|c| find(c)).collect(),
+            Columns::Ids => CAT21_IDS.iter().map(|c| find(c)).collect(),
+            Columns::Custom(list) => list.iter().map(|c| find(c)).collect(),
+        }
+    }
+}
+
+/// Keep the columns at `indices` of every CSV line of `input`, header or not.
+///
+pub fn select_columns(input: &str, delim: u8, indices: &[usize]) -> Result<String> {
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(delim)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    let mut wtr = WriterBuilder::new().delimiter(delim).from_writer(vec![]);
+    for rec in rdr.records() {
+        let rec = rec?;
+        let out = indices
+            .iter()
+            .map(|&i| rec.get(i).unwrap_or_default())
+            .collect::<StringRecord>();
+        wtr.write_record(&out)?;
+    }
+    Ok(String::from_utf8(wtr.into_inner()?)?)
+}
+
+impl FromStr for Columns {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "full" => Columns::Full,
+            "minimal" => Columns::Minimal,
+            "ids" => Columns::Ids,
+            _ => {
+                let list = s
+                    .split(',')
+                    .map(|c| c.trim().to_uppercase())
+                    .collect::<Vec<_>>();
+                if list.iter().any(|c| c.is_empty()) {
+                    return Err(eyre!("bad column list {s}"));
+                }
+                Columns::Custom(list)
+            }
+        })
+    }
+}
+
+impl Display for Columns {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Columns::Full => write!(f, "full"),
+            Columns::Minimal => write!(f, "minimal"),
+            Columns::Ids => write!(f, "ids"),
+            Columns::Custom(list) => write!(f, "{}", list.join(",")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{prepare_csv, prepare_csv_with, Cat21, CAT21_COLUMNS};
+
+    use super::*;
+
+    #[rstest]
+    #[case("full", Columns::Full)]
+    #[case("Minimal", Columns::Minimal)]
+    #[case("ids", Columns::Ids)]
+    #[case("callsign, pos_lat_deg", Columns::Custom(vec!["CALLSIGN".to_string(), "POS_LAT_DEG".to_string()]))]
+    fn test_columns_from_str(#[case] s: &str, #[case] res: Columns) {
+        assert_eq!(res, Columns::from_str(s).unwrap());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("callsign,,tod")]
+    fn test_columns_from_str_bad(#[case] s: &str) {
+        assert!(Columns::from_str(s).is_err());
+    }
+
+    #[test]
+    fn test_cat21_columns() {
+        let csv = prepare_csv(vec![Cat21::default()], true).unwrap();
+        let header = csv.lines().next().unwrap().split(':').collect::<Vec<_>>();
+        assert_eq!(CAT21_COLUMNS.to_vec(), header);
+    }
+
+    #[rstest]
+    #[case(Columns::Full, CAT21_COLUMNS.len())]
+    #[case(Columns::Minimal, 8)]
+    #[case(Columns::Ids, 14)]
+    #[case(Columns::from_str("tod,sac").unwrap(), 2)]
+    fn test_columns_indices(#[case] columns: Columns, #[case] len: usize) {
+        assert_eq!(len, columns.indices(&CAT21_COLUMNS).unwrap().len());
+    }
+
+    #[test]
+    fn test_columns_indices_unknown() {
+        let columns = Columns::from_str("tod,foo").unwrap();
+        let err = columns.indices(&CAT21_COLUMNS).unwrap_err();
+        assert_eq!("unknown column FOO", err.to_string());
+    }
+
+    #[test]
+    fn test_prepare_csv_with() {
+        let data = || vec![Cat21::default(), Cat21::default()];
+        let columns = Columns::from_str("callsign,sac").unwrap();
+
+        let csv = prepare_csv_with(data(), true, &columns).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(3, lines.len());
+        assert_eq!("CALLSIGN:SAC", lines[0]);
+        assert!(lines[1].ends_with(":8"));
+
+        let csv = prepare_csv_with(data(), false, &Columns::Ids).unwrap();
+        assert_eq!(2, csv.lines().count());
+        assert!(csv.lines().all(|l| l.split(':').count() == CAT21_IDS.len()));
+
+        assert_eq!(
+            prepare_csv(data(), false).unwrap(),
+            prepare_csv_with(data(), false, &Columns::Full).unwrap()
+        );
+        assert_eq!(
+            "",
+            prepare_csv_with(Vec::<Cat21>::new(), true, &columns).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_select_columns() {
+        let input = "SAC:SIC:TOD\n8:200:1200\n8:201:1201\n";
+        assert_eq!(
+            "TOD:SAC\n1200:8\n1201:8\n",
+            select_columns(input, b':', &[2, 0]).unwrap()
+        );
+        assert_eq!("", select_columns("", b':', &[2, 0]).unwrap());
+    }
+}
@@ -0,0 +1,421 @@
+//! Schema drift of JSON payloads.
+//!
+//! Providers sometimes deploy a new API overnight: fields are renamed, disappear or change type
+//! and converters start dropping records or failing.  A `Profile` gathers per-field statistics
+//! from a batch of messages: in how many messages each field is present, how many times it is
+//! `null` and which JSON types its other values have.  Nested objects are flattened with dots
+//! like in `Schema` (`position.latitude`), arrays are not looked into.
+//!
+//! Comparing the profile of a batch with the registered one of its format (`Format::schema()`,
+//! learned from the sample of the built-in fixture) gives a `Drift`:
+//!
+//! - unknown fields, with the number of messages having them,
+//! - missing fields: present in every message of the schema but not in the batch,
+//! - type changes: values of a type never seen for the field, `null` included for fields which
+//!   are never `null` in the schema.
+//!
+//! Fields only ever seen as `null` in the schema may take any type.
+//!
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+use eyre::Result;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::trace;
+
+use crate::{Fixture, Format};
+
+/// JSON type of a value
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Kind {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl From<&Value> for Kind {
+    fn from(v: &Value) -> Self {
+        match v {
+            Value::Null => Kind::Null,
+            Value::Bool(_) => Kind::Bool,
+            Value::Number(_) => Kind::Number,
+            Value::String(_) => Kind::String,
+            Value::Array(_) => Kind::Array,
+            Value::Object(_) => Kind::Object,
+        }
+    }
+}
+
+/// Statistics of one field
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct FieldStats {
+    /// Messages with the field, `null` or not
+    pub present: usize,
+    /// Messages with the field set to `null`
+    pub nulls: usize,
+    /// Number of values of each other type
+    pub kinds: BTreeMap<Kind, usize>,
+}
+
+/// Statistics of all fields of a batch
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Profile {
+    /// Number of messages
+    pub records: usize,
+    /// Per field, flattened
+    pub fields: BTreeMap<String, FieldStats>,
+}
+
+impl Profile {
+    /// Profile JSON objects, one after the other or in arrays.  Fails on anything else.
+    ///
+    #[tracing::instrument(skip(input))]
+    pub fn from_json(input: &str) -> Result<Self> {
+        let mut profile = Profile::default();
+        for msg in serde_json::Deserializer::from_str(input).into_iter::<Value>() {
+            match msg? {
+                Value::Array(all) => all.iter().for_each(|m| profile.add(m)),
+                msg => profile.add(&msg),
+            }
+        }
+        trace!(
+            "{} records, {} fields",
+            profile.records,
+            profile.fields.len()
+        );
+        Ok(profile)
+    }
+
+    /// Count one message, something else than an object is a message without fields
+    ///
+    pub fn add(&mut self, msg: &Value) {
+        self.records += 1;
+        if let Value::Object(msg) = msg {
+            self.add_fields("", msg);
+        }
+    }
+
+    fn add_fields(&mut self, prefix: &str, msg: &Map<String, Value>) {
+        for (key, v) in msg {
+            let name = format!("{prefix}{key}");
+            let stats = self.fields.entry(name.clone()).or_default();
+            stats.present += 1;
+            match Kind::from(v) {
+                Kind::Null => stats.nulls += 1,
+                kind => *stats.kinds.entry(kind).or_default() += 1,
+            }
+            if let Value::Object(inner) = v {
+                self.add_fields(&format!("{name}."), inner);
+            }
+        }
+    }
+
+    /// Is `field` in every message, never `null`?
+    ///
+    pub fn required(&self, field: &str) -> bool {
+        self.fields
+            .get(field)
+            .is_some_and(|s| s.present == self.records && s.nulls == 0)
+    }
+
+    /// Differences between this batch and `schema`.
+    ///
+    pub fn drift(&self, schema: &Profile) -> Drift {
+        let mut drift = Drift {
+            records: self.records,
+            ..Drift::default()
+        };
+        for (name, stats) in &self.fields {
+            let Some(expected) = schema.fields.get(name) else {
+                drift.unknown.insert(name.clone(), stats.present);
+                continue;
+            };
+            if stats.nulls > 0 && expected.nulls == 0 && !expected.kinds.is_empty() {
+                drift.changed.push(TypeChange {
+                    field: name.clone(),
+                    expected: expected.kinds.keys().copied().collect(),
+                    found: Kind::Null,
+                    count: stats.nulls,
+                });
+            }
+            if expected.kinds.is_empty() {
+                continue;
+            }
+            for (kind, count) in &stats.kinds {
+                if !expected.kinds.contains_key(kind) {
+                    drift.changed.push(TypeChange {
+                        field: name.clone(),
+                        expected: expected.kinds.keys().copied().collect(),
+                        found: *kind,
+                        count: *count,
+                    });
+                }
+            }
+        }
+        for (name, expected) in &schema.fields {
+            if expected.present != schema.records {
+                continue;
+            }
+            let present = self.fields.get(name).map(|s| s.present).unwrap_or(0);
+            if present < self.records {
+                drift.missing.insert(name.clone(), self.records - present);
+            }
+        }
+        drift
+    }
+}
+
+/// A field with values of an unexpected type
+///
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TypeChange {
+    /// Field name, flattened
+    pub field: String,
+    /// Types in the schema
+    pub expected: BTreeSet<Kind>,
+    /// Type found
+    pub found: Kind,
+    /// Number of values of that type
+    pub count: usize,
+}
+
+/// Differences between a batch and its schema
+///
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Drift {
+    /// Messages in the batch
+    pub records: usize,
+    /// Fields not in the schema, with the number of messages having them
+    pub unknown: BTreeMap<String, usize>,
+    /// Required fields, with the number of messages without them
+    pub missing: BTreeMap<String, usize>,
+    /// Values of a new type
+    pub changed: Vec<TypeChange>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.unknown.is_empty() && self.missing.is_empty() && self.changed.is_empty()
+    }
+
+    /// Fields involved, to tell a new drift from the same one batch after batch
+    ///
+    pub fn fields(&self) -> BTreeSet<String> {
+        self.unknown
+            .keys()
+            .chain(self.missing.keys())
+            .chain(self.changed.iter().map(|c| &c.field))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Display for Drift {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut all = vec![];
+        if !self.unknown.is_empty() {
+            let list = self
+                .unknown
+                .iter()
+                .map(|(k, n)| format!("{k} ({n})"))
+                .collect::<Vec<_>>();
+            all.push(format!("unknown fields {}", list.join(", ")));
+        }
+        if !self.missing.is_empty() {
+            let list = self
+                .missing
+                .iter()
+                .map(|(k, n)| format!("{k} ({n})"))
+                .collect::<Vec<_>>();
+            all.push(format!("missing fields {}", list.join(", ")));
+        }
+        for c in &self.changed {
+            let expected = c.expected.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+            all.push(format!(
+                "{} is {} instead of {} ({})",
+                c.field,
+                c.found,
+                expected.join("/"),
+                c.count
+            ));
+        }
+        match all.is_empty() {
+            true => write!(f, "no drift in {} records", self.records),
+            false => write!(f, "{} in {} records", all.join("; "), self.records),
+        }
+    }
+}
+
+impl Format {
+    /// Registered schema of the format: the profile of its built-in sample, if it is JSON.
+    ///
+    pub fn schema(&self) -> Option<Profile> {
+        let sample = Fixture::builtin().into_iter().find(|f| f.from == *self)?;
+        Profile::from_json(&sample.input)
+            .ok()
+            .filter(|p| !p.fields.is_empty())
+    }
+}
+
+/// Compare batches with the schema of their format, only reporting drifts as they change.
+///
+#[derive(Clone, Debug)]
+pub struct DriftWatch {
+    /// Expected profile, the first JSON batch when the format has none
+    schema: Option<Profile>,
+    /// Fields of the last drift reported
+    last: BTreeSet<String>,
+}
+
+impl DriftWatch {
+    pub fn new(fmt: Format) -> Self {
+        DriftWatch {
+            schema: fmt.schema(),
+            last: BTreeSet::new(),
+        }
+    }
+
+    /// Drift of `batch`, unless it is the same as the one of the previous batch.  Batches which
+    /// are not JSON are ignored.
+    ///
+    pub fn check(&mut self, batch: &str) -> Option<Drift> {
+        let profile = Profile::from_json(batch).ok()?;
+        if profile.records == 0 {
+            return None;
+        }
+        let Some(schema) = &self.schema else {
+            trace!("schema registered from the first batch");
+            self.schema = Some(profile);
+            return None;
+        };
+        let drift = profile.drift(schema);
+        let fields = drift.fields();
+        if fields == self.last {
+            return None;
+        }
+        self.last = fields;
+        Some(drift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const SCHEMA: &str = r##"{"id":1,"name":"a","alt":null,"pos":{"lat":49.5,"lon":6.2}}
+{"id":2,"name":"b","alt":100,"pos":{"lat":49.6,"lon":6.3},"note":"x"}"##;
+
+    #[test]
+    fn test_profile_stats() -> Result<()> {
+        let p = Profile::from_json(SCHEMA)?;
+        assert_eq!(2, p.records);
+        assert_eq!(
+            FieldStats {
+                present: 2,
+                nulls: 1,
+                kinds: BTreeMap::from([(Kind::Number, 1)]),
+            },
+            p.fields["alt"]
+        );
+        assert_eq!(1, p.fields["note"].present);
+        assert_eq!(2, p.fields["pos.lat"].present);
+        assert_eq!(Some(&2), p.fields["pos"].kinds.get(&Kind::Object));
+        assert!(p.required("id"));
+        assert!(!p.required("alt"));
+        assert!(!p.required("note"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_arrays() -> Result<()> {
+        let p = Profile::from_json(r##"[{"a":1},{"a":2}] {"a":[1,2]}"##)?;
+        assert_eq!(3, p.records);
+        assert_eq!(Some(&1), p.fields["a"].kinds.get(&Kind::Array));
+        assert!(Profile::from_json("MSG,1,2").is_err());
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(r##"{"id":3,"name":"c","alt":5,"pos":{"lat":1,"lon":2}}"##, 0, 0, 0)]
+    #[case(
+        r##"{"id":3,"name":"c","alt":null,"pos":{"lat":1,"lon":2},"speed":3}"##,
+        1,
+        0,
+        0
+    )]
+    #[case(r##"{"id":3,"alt":5,"pos":{"lat":1,"lon":2}}"##, 0, 1, 0)]
+    #[case(r##"{"id":"3","name":"c","alt":5,"pos":{"lat":1,"lon":2}}"##, 0, 0, 1)]
+    #[case(r##"{"id":3,"name":null,"alt":5,"pos":{"lat":1,"lon":2}}"##, 0, 0, 1)]
+    #[case(
+        r##"{"id":3,"name":"c","alt":5,"pos":{"latitude":1,"lon":2}}"##,
+        1,
+        1,
+        0
+    )]
+    fn test_drift(
+        #[case] batch: &str,
+        #[case] unknown: usize,
+        #[case] missing: usize,
+        #[case] changed: usize,
+    ) -> Result<()> {
+        let schema = Profile::from_json(SCHEMA)?;
+        let drift = Profile::from_json(batch)?.drift(&schema);
+        assert_eq!(unknown, drift.unknown.len());
+        assert_eq!(missing, drift.missing.len());
+        assert_eq!(changed, drift.changed.len());
+        assert_eq!(drift.is_empty(), unknown + missing + changed == 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drift_display() -> Result<()> {
+        let schema = Profile::from_json(SCHEMA)?;
+        let batch = r##"{"id":"3","alt":5,"pos":{"lat":1,"lon":2},"speed":3}
+{"id":4,"alt":5,"pos":{"lat":1,"lon":2},"speed":4}"##;
+        let drift = Profile::from_json(batch)?.drift(&schema);
+        assert_eq!(
+            "unknown fields speed (2); missing fields name (2); id is string instead of number (1) in 2 records",
+            drift.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_schema() {
+        let asd = Format::Asd.schema().unwrap();
+        assert_eq!(
+            Some(&Kind::String),
+            asd.fields["latitude"].kinds.keys().next()
+        );
+        assert!(Format::Opensky.schema().is_some());
+        assert!(Format::Sbs1.schema().is_none());
+    }
+
+    #[test]
+    fn test_drift_watch() {
+        let mut w = DriftWatch::new(Format::None);
+        assert!(w.check(SCHEMA).is_none());
+        assert!(w.check("not json").is_none());
+
+        let bad = r##"{"id":3,"name":"c","alt":5,"pos":{"lat":1,"lon":2},"speed":3}"##;
+        assert_eq!(1, w.check(bad).unwrap().unknown.len());
+        assert!(w.check(bad).is_none());
+
+        // Back to normal is reported too
+        //
+        let good = r##"{"id":3,"name":"c","alt":5,"pos":{"lat":1,"lon":2}}"##;
+        assert!(w.check(good).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Golden files for the converters.
+//!
+//! Every fixture is a pair of files named after the formats, a sample of what a provider sends
+//! (`<from>-<into>.input`) and what we expect once converted (`<from>-<into>.expected`).  The
+//! ones in `fixtures/` are built into the crate so `acutectl selftest formats` can check every
+//! converter anywhere, another directory can be given to test new samples.
+//!
+//! A mismatch is reported with the first line which differs, which is usually enough to find
+//! which field a provider changed.
+//!
+
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use eyre::{eyre, Result};
+use tracing::trace;
+
+use crate::Format;
+
+/// Extension of the sample
+pub const INPUT: &str = "input";
+/// Extension of the expected output
+pub const EXPECTED: &str = "expected";
+
+/// Sample and expected output of a fixture in `fixtures/`
+///
+macro_rules! fixture {
+    ($name:literal) => {
+        (
+            $name,
+            include_str!(concat!("../fixtures/", $name, ".input")),
+            include_str!(concat!("../fixtures/", $name, ".expected")),
+        )
+    };
+}
+
+/// Fixtures shipped with the crate, `(name, input, expected)`
+///
+const BUILTIN: &[(&str, &str, &str)] = &[
+    fixture!("asd-cat21"),
+    fixture!("asd-senhive"),
+    fixture!("opensky-cat21"),
+    fixture!("sbs1-cat21"),
+    fixture!("sbs1-sbs1"),
+    fixture!("utm-cat21"),
+];
+
+/// One sample and its expected conversion
+///
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    /// `<from>-<into>`
+    pub name: String,
+    /// Input format
+    pub from: Format,
+    /// Output format
+    pub into: Format,
+    /// Raw sample
+    pub input: String,
+    /// Converted sample
+    pub expected: String,
+}
+
+/// First difference between the expected and actual outputs
+///
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    /// Line number, starting at 1
+    pub line: usize,
+    /// Expected line, `None` if the output is longer
+    pub expected: Option<String>,
+    /// Actual line, `None` if the output is shorter
+    pub got: Option<String>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let show = |l: &Option<String>| l.clone().unwrap_or_else(|| "<nothing>".to_string());
+        write!(
+            f,
+            "line {}: expected {}, got {}",
+            self.line,
+            show(&self.expected),
+            show(&self.got)
+        )
+    }
+}
+
+impl Fixture {
+    /// From the name and both contents
+    ///
+    pub fn new(name: &str, input: &str, expected: &str) -> Result<Self> {
+        let (from, into) = name
+            .split_once('-')
+            .ok_or_else(|| eyre!("bad fixture name {name}, want <from>-<into>"))?;
+        Ok(Fixture {
+            name: name.to_string(),
+            from: Format::from_str(from)?,
+            into: Format::from_str(into)?,
+            input: input.to_string(),
+            expected: expected.to_string(),
+        })
+    }
+
+    /// All the fixtures shipped with the crate
+    ///
+    pub fn builtin() -> Vec<Fixture> {
+        BUILTIN
+            .iter()
+            .map(|(name, input, expected)| Fixture::new(name, input, expected).unwrap())
+            .collect()
+    }
+
+    /// All the fixtures in `dir`, in name order.  A sample without expected output gets an
+    /// empty one, to be filled in with `acutectl selftest formats --bless`.
+    ///
+    #[tracing::instrument]
+    pub fn load(dir: &Path) -> Result<Vec<Fixture>> {
+        let mut inputs = fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|f| f.extension().is_some_and(|ext| ext == INPUT))
+            .collect::<Vec<_>>();
+        inputs.sort();
+
+        inputs
+            .iter()
+            .map(|f| {
+                trace!("fixture {:?}", f);
+                let name = f.file_stem().unwrap_or_default().to_string_lossy();
+                let expected = fs::read_to_string(f.with_extension(EXPECTED)).unwrap_or_default();
+                Fixture::new(&name, &fs::read_to_string(f)?, &expected)
+            })
+            .collect()
+    }
+
+    /// Where the expected output of this fixture lives in `dir`
+    ///
+    pub fn expected_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.{}", self.name, EXPECTED))
+    }
+
+    /// Compare `got` with what we expect, line by line
+    ///
+    pub fn check(&self, got: &str) -> Option<Mismatch> {
+        let mut expected = self.expected.lines();
+        let mut got = got.lines();
+        let mut line = 0;
+        loop {
+            line += 1;
+            match (expected.next(), got.next()) {
+                (None, None) => return None,
+                (e, g) if e == g => continue,
+                (e, g) => {
+                    return Some(Mismatch {
+                        line,
+                        expected: e.map(str::to_string),
+                        got: g.map(str::to_string),
+                    })
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_builtin() {
+        let all = Fixture::builtin();
+        assert_eq!(BUILTIN.len(), all.len());
+        assert!(all
+            .iter()
+            .all(|f| !f.input.is_empty() && !f.expected.is_empty()));
+        assert_eq!(Format::Sbs1, all[3].from);
+        assert_eq!(Format::Cat21, all[3].into);
+    }
+
+    #[test]
+    fn test_fixture_bad_name() {
+        assert!(Fixture::new("asd", "", "").is_err());
+        assert!(Fixture::new("asd-foo", "", "").is_err());
+    }
+
+    #[test]
+    fn test_fixture_check() -> Result<()> {
+        let f = Fixture::new("asd-cat21", "", "a\nb\n")?;
+
+        assert_eq!(None, f.check("a\nb"));
+        assert_eq!(
+            Some(Mismatch {
+                line: 2,
+                expected: Some("b".to_string()),
+                got: Some("c".to_string()),
+            }),
+            f.check("a\nc\n")
+        );
+        let m = f.check("a\n").unwrap();
+        assert_eq!("line 2: expected b, got <nothing>", m.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixture_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("utm-cat21.input"), "{}")?;
+        fs::write(dir.path().join("asd-cat21.input"), "{}")?;
+        fs::write(dir.path().join("asd-cat21.expected"), "x\n")?;
+        fs::write(dir.path().join("README"), "")?;
+
+        let all = Fixture::load(dir.path())?;
+        assert_eq!(2, all.len());
+        assert_eq!("asd-cat21", all[0].name);
+        assert_eq!("x\n", all[0].expected);
+        assert_eq!("", all[1].expected);
+        assert_eq!(
+            dir.path().join("utm-cat21.expected"),
+            all[1].expected_path(dir.path())
+        );
+        Ok(())
+    }
+}
@@ -37,6 +37,13 @@ format "cat21" {
   url         = "https://www.eurocontrol.int/asterix/"
 }
 
+format "cat048" {
+  type        = "adsb"
+  description = "Binary ASTERIX Cat048 recordings of PSR/SSR radar plots."
+  source      = "ECTL"
+  url         = "https://www.eurocontrol.int/asterix/"
+}
+
 format "cat129" {
   type        = "drone"
   description = "Flattened ASTERIX Cat129 data for Drone data."
@@ -71,3 +78,38 @@ format "flightaware" {
   source      = "Flightaware"
   url         = "https://flightaware.com/commercial/firehose/documentation/summary"
 }
+
+format "utm" {
+  type        = "drone"
+  description = "ASTM F3548 UTM telemetry exchanged between U-space service providers."
+  source      = "USSP"
+  url         = "https://github.com/astm-utm/Protocol"
+}
+
+format "nmb2b" {
+  type        = "adsb"
+  description = "Flight plans and planned trajectories from the EUROCONTROL NM B2B Flight services."
+  source      = "EUROCONTROL"
+  url         = "https://www.eurocontrol.int/service/network-manager-business-business-b2b-web-services"
+}
+
+format "sbs1" {
+  type        = "adsb"
+  description = "SBS-1 BaseStation CSV messages as sent by dump1090 on port 30003."
+  source      = "dump1090"
+  url         = "http://woodair.net/sbs/article/barebones42_socket_data.htm"
+}
+
+format "senhive" {
+  type        = "write"
+  description = "Senhive fusion tracks, one JSON message per line, read into Cat21 or written from ASD or Cat21 data."
+  source      = "Senhive"
+  url         = "https://www.senhive.com/"
+}
+
+format "xlsx" {
+  type        = "write"
+  description = "Excel workbook with typed columns, written from ASD or Cat21 data."
+  source      = "Microsoft"
+  url         = "https://learn.microsoft.com/en-us/openspecs/office_standards/ms-xlsx/"
+}
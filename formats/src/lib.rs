@@ -13,9 +13,9 @@ use std::io::Read;
 
 use csv::{Reader, WriterBuilder};
 use eyre::Result;
+use fetiche_common::{Listing, OutputFormat};
 use serde::{Deserialize, Serialize};
 use strum::EnumString;
-use tabled::{builder::Builder, settings::Style};
 use tracing::{debug, trace};
 
 // Re-export for convenience
@@ -24,19 +24,41 @@ pub use aeroscope::*;
 pub use asd::*;
 pub use asterix::*;
 pub use avionix::*;
+pub use category::*;
+pub use columns::*;
+pub use drift::*;
+pub use fixture::*;
 #[cfg(feature = "flightaware")]
 pub use flightaware::*;
+pub use nmb2b::*;
 pub use opensky::*;
 pub use safesky::*;
+pub use sbs1::*;
+pub use schema::*;
+pub use senhive::*;
+pub use sort::*;
+pub use track::*;
+pub use utm::*;
 
 mod aeroscope;
 mod asd;
 mod asterix;
 mod avionix;
+mod category;
+mod columns;
+mod drift;
+mod fixture;
 #[cfg(feature = "flightaware")]
 mod flightaware;
+mod nmb2b;
 mod opensky;
 mod safesky;
+mod sbs1;
+mod schema;
+mod senhive;
+mod sort;
+mod track;
+mod utm;
 
 /// Current formats.hcl version
 ///
@@ -107,16 +129,28 @@ pub enum Format {
     AvionixCat21,
     /// ECTL Asterix Cat21 flattened CSV
     Cat21,
+    /// Asterix Cat048 radar plots, binary recordings
+    Cat048,
     /// ECTL Drone specific Asterix Cat129
     Cat129,
     /// Flightaware API v4 Position data
     Flightaware,
+    /// Flight plans and trajectories from the EUROCONTROL NM B2B services
+    NmB2b,
     /// ADS-B data from the Opensky API
     Opensky,
     /// Opensky data from the Impala historical DB
     PandaStateVector,
     /// ADS-B data  from the Safesky API
     Safesky,
+    /// SBS-1 BaseStation messages, from dump1090 port 30003
+    Sbs1,
+    /// Senhive fusion tracks, output only
+    Senhive,
+    /// ASTM F3548 UTM telemetry from U-space service providers
+    Utm,
+    /// Excel workbook, output only
+    Xlsx,
 }
 
 /// This is the special hex string for ICAO codes
@@ -228,9 +262,10 @@ impl Format {
 }
 
 impl Format {
-    /// List all supported formats into a string using `tabled`.
+    /// List all supported formats.  Tables merge description, source & URL into one column,
+    /// JSON and CSV keep them as separate fields.
     ///
-    pub fn list() -> Result<String> {
+    pub fn list(fmt: OutputFormat) -> Result<String> {
         let descr = include_str!("formats.hcl");
         let fstr: FormatFile = hcl::from_str(descr)?;
 
@@ -238,30 +273,48 @@ impl Format {
         //
         assert_eq!(fstr.version, FVERSION);
 
-        let header = vec!["Name", "Type", "Description"];
-
-        let mut builder = Builder::default();
-        builder.push_record(header);
+        let mut list = match fmt {
+            OutputFormat::Table => Listing::new(
+                "List all formats",
+                &[
+                    ("Name", "name"),
+                    ("Type", "type"),
+                    ("Description", "description"),
+                ],
+            ),
+            _ => Listing::new(
+                "List all formats",
+                &[
+                    ("Name", "name"),
+                    ("Type", "type"),
+                    ("Description", "description"),
+                    ("Source", "source"),
+                    ("URL", "url"),
+                ],
+            ),
+        };
 
         fstr.format.iter().for_each(|(name, entry)| {
-            let mut row = vec![];
-
-            let name = name.clone();
-            let dtype = entry.dtype.clone();
-            let description = entry.description.clone();
-            let source = entry.source.clone();
-            let url = entry.url.clone();
-
-            let row_text = format!("{}\nSource: {} -- URL: {}", description, source, url);
-            let dtype = dtype.to_string();
-            row.push(&name);
-            row.push(&dtype);
-            row.push(&row_text);
-            builder.push_record(row);
+            let row = match fmt {
+                OutputFormat::Table => vec![
+                    name.clone(),
+                    entry.dtype.clone(),
+                    format!(
+                        "{}\nSource: {} -- URL: {}",
+                        entry.description, entry.source, entry.url
+                    ),
+                ],
+                _ => vec![
+                    name.clone(),
+                    entry.dtype.clone(),
+                    entry.description.clone(),
+                    entry.source.clone(),
+                    entry.url.clone(),
+                ],
+            };
+            list.push(row);
         });
-        let allf = builder.build().with(Style::modern()).to_string();
-        let str = format!("List all formats:\n{allf}");
-        Ok(str)
+        list.render(fmt)
     }
 
     /// List all supported formats into a string
@@ -318,7 +371,7 @@ pub enum TodCalculated {
     R,
 }
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, PartialEq, Serialize)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum Bool {
     Y,
@@ -367,6 +420,27 @@ where
     Ok(data)
 }
 
+/// Same as `prepare_csv()` with only some of the columns, see `Columns`
+///
+#[tracing::instrument]
+pub fn prepare_csv_with<T>(data: Vec<T>, header: bool, columns: &Columns) -> Result<String>
+where
+    T: Serialize + Debug,
+{
+    if *columns == Columns::Full {
+        return prepare_csv(data, header);
+    }
+    let csv = prepare_csv(data, true)?;
+    let Some((first, rest)) = csv.split_once('\n') else {
+        return Ok(csv);
+    };
+    let indices = columns.indices(&first.split(':').collect::<Vec<_>>())?;
+    match header {
+        true => select_columns(&csv, b':', &indices),
+        false => select_columns(rest, b':', &indices),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,453 @@
+//! Module to load flight plans and their trajectories from the EUROCONTROL Network Manager
+//! B2B web services and map them into our own Cat21 format.
+//!
+//! The NM B2B Flight services answer SOAP requests with XML replies, `FlightListByAirspaceReply`
+//! or `FlightListByAerodromeReply` for the list of flights crossing an airspace or using an
+//! aerodrome during a traffic window.  Each flight has its keys (callsign, aerodromes of departure
+//! and destination, EOBT), aircraft type and address and the planned trajectory as a point
+//! profile (`ctfmPointProfile`).
+//!
+//! `NmFlight::from_reply()` reads a whole SOAP reply, flights are then passed along as one JSON
+//! object per line and `Cat21::from_nmb2b()` gives one record per point of the profile, which
+//! can be joined with sensor data on the aircraft address or the callsign.
+//!
+//! Only the basics are kept.  Points are either published (a name only, without position in the
+//! reply) or geographic ones (`nonPublishedPoint-GeoPoint`), only the latter have coordinates and
+//! are converted.  Flight levels are in hundreds of feet, times are UTC.
+//!
+//! See: <https://www.eurocontrol.int/service/network-manager-business-business-b2b-web-services>
+//!
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{Cat21, TodCalculated};
+
+/// Default aircraft address if there is none in the flight plan
+const DEF_ADDR: u32 = 623615;
+
+/// One flight with its planned trajectory
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct NmFlight {
+    /// IFPS identifier
+    pub ifpl_id: String,
+    /// Callsign
+    pub aircraft_id: String,
+    /// ICAO code of the aerodrome of departure
+    pub departure: String,
+    /// ICAO code of the aerodrome of destination
+    pub destination: String,
+    /// Estimated off-block time
+    pub eobt: Option<DateTime<Utc>>,
+    /// ICAO aircraft type
+    pub aircraft_type: Option<String>,
+    /// ICAO 24-bit address, in hex
+    pub aircraft_address: Option<String>,
+    /// Planned trajectory
+    #[serde(default)]
+    pub points: Vec<NmPoint>,
+}
+
+/// One point of the trajectory
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct NmPoint {
+    /// When the flight is over the point
+    pub time: DateTime<Utc>,
+    /// Name of the published point, if any
+    pub name: Option<String>,
+    /// Latitude in degrees, only for geographic points
+    pub latitude: Option<f64>,
+    /// Longitude in degrees, only for geographic points
+    pub longitude: Option<f64>,
+    /// Altitude in feet
+    pub altitude: Option<u32>,
+}
+
+// ----- XML reply, only what we need
+
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    #[serde(rename = "Body")]
+    body: Body,
+}
+
+#[derive(Debug, Deserialize)]
+struct Body {
+    #[serde(rename = "$value")]
+    reply: Reply,
+}
+
+#[derive(Debug, Deserialize)]
+struct Reply {
+    status: String,
+    reason: Option<String>,
+    data: Option<ReplyData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplyData {
+    #[serde(default)]
+    flights: Vec<FlightOrPlan>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlightOrPlan {
+    flight: Option<Flight>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Flight {
+    flight_id: FlightId,
+    aircraft_type: Option<String>,
+    aircraft_address: Option<String>,
+    #[serde(default)]
+    ctfm_point_profile: Vec<FlightPoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlightId {
+    id: Option<String>,
+    keys: FlightKeys,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FlightKeys {
+    aircraft_id: String,
+    aerodrome_of_departure: Option<String>,
+    aerodrome_of_destination: Option<String>,
+    estimated_off_block_time: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FlightPoint {
+    time_over: String,
+    flight_level: Option<FlightLevel>,
+    point: Option<Point>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlightLevel {
+    unit: String,
+    level: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Point {
+    #[serde(rename = "pointId")]
+    point_id: Option<String>,
+    #[serde(rename = "nonPublishedPoint-GeoPoint")]
+    geo: Option<GeoPoint>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeoPoint {
+    position: Position,
+}
+
+#[derive(Debug, Deserialize)]
+struct Position {
+    latitude: Angle,
+    longitude: Angle,
+}
+
+#[derive(Debug, Deserialize)]
+struct Angle {
+    angle: String,
+    side: String,
+}
+
+impl Angle {
+    /// `DD[MM[SS[.ss]]]` (`DDD` for longitudes) with a side into signed degrees.
+    ///
+    fn degrees(&self, width: usize) -> Option<f64> {
+        let a = self.angle.trim();
+        let num = |s: &str| -> Option<f64> {
+            if s.is_empty() {
+                Some(0.)
+            } else {
+                s.parse::<f64>().ok()
+            }
+        };
+        let deg = num(a.get(..width)?)?;
+        let min = num(a.get(width..(width + 2).min(a.len()))?)?;
+        let sec = num(a.get((width + 2).min(a.len())..)?)?;
+        let value = deg + min / 60. + sec / 3600.;
+        match self.side.as_str() {
+            "NORTH" | "EAST" => Some(value),
+            "SOUTH" | "WEST" => Some(-value),
+            _ => None,
+        }
+    }
+}
+
+/// Times are either `yyyy-MM-dd HH:mm:ss` or `yyyy-MM-dd HH:mm`, always UTC.
+///
+fn nm_time(s: &str) -> Option<DateTime<Utc>> {
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s.trim(), f).ok())
+        .map(|t| t.and_utc())
+}
+
+impl From<FlightLevel> for u32 {
+    /// Flight levels and altitudes are in hundreds of feet, metric ones in tens of meters.
+    ///
+    fn from(fl: FlightLevel) -> Self {
+        match fl.unit.as_str() {
+            "S" | "M" => (fl.level as f32 * 10. * 3.28084) as u32,
+            _ => fl.level * 100,
+        }
+    }
+}
+
+impl From<Flight> for NmFlight {
+    fn from(f: Flight) -> Self {
+        let keys = f.flight_id.keys;
+        let points = f
+            .ctfm_point_profile
+            .into_iter()
+            .filter_map(|p| {
+                let time = nm_time(&p.time_over)?;
+                let (name, pos) = match p.point {
+                    Some(pt) => (
+                        pt.point_id,
+                        pt.geo.and_then(|g| {
+                            Some((
+                                g.position.latitude.degrees(2)?,
+                                g.position.longitude.degrees(3)?,
+                            ))
+                        }),
+                    ),
+                    None => (None, None),
+                };
+                Some(NmPoint {
+                    time,
+                    name,
+                    latitude: pos.map(|p| p.0),
+                    longitude: pos.map(|p| p.1),
+                    altitude: p.flight_level.map(u32::from),
+                })
+            })
+            .collect();
+        NmFlight {
+            ifpl_id: f.flight_id.id.unwrap_or_default(),
+            aircraft_id: keys.aircraft_id,
+            departure: keys.aerodrome_of_departure.unwrap_or_default(),
+            destination: keys.aerodrome_of_destination.unwrap_or_default(),
+            eobt: keys.estimated_off_block_time.as_deref().and_then(nm_time),
+            aircraft_type: f.aircraft_type,
+            aircraft_address: f.aircraft_address,
+            points,
+        }
+    }
+}
+
+impl NmFlight {
+    /// Read all flights from a SOAP reply, failing if NM did not answer `OK`.
+    ///
+    #[tracing::instrument(skip(input))]
+    pub fn from_reply(input: &str) -> Result<Vec<NmFlight>> {
+        let env: Envelope = quick_xml::de::from_str(input)?;
+        let reply = env.body.reply;
+        if reply.status != "OK" {
+            return Err(eyre!(
+                "NM B2B: {} {}",
+                reply.status,
+                reply.reason.unwrap_or_default()
+            ));
+        }
+        let all = reply
+            .data
+            .map(|d| d.flights)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|f| f.flight.map(NmFlight::from))
+            .collect::<Vec<_>>();
+        debug!("{} flights", all.len());
+        Ok(all)
+    }
+
+    /// One JSON object per line
+    ///
+    pub fn to_lines(all: &[NmFlight]) -> Result<String> {
+        let lines = all
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(lines.join("\n"))
+    }
+
+    /// Aircraft address as a number, if valid
+    ///
+    fn address(&self) -> Option<u32> {
+        self.aircraft_address
+            .as_deref()
+            .and_then(|a| u32::from_str_radix(a.trim(), 16).ok())
+    }
+
+    /// One `Cat21` record per point with a position.
+    ///
+    /// The following fields are **lost**:
+    /// - aerodromes, EOBT and aircraft type
+    /// - published points without coordinates
+    ///
+    pub fn to_cat21(&self) -> Vec<Cat21> {
+        let addr = self.address().unwrap_or(DEF_ADDR);
+        self.points
+            .iter()
+            .filter_map(|p| {
+                let (lat, lon) = (p.latitude?, p.longitude?);
+                let tod = p.time.timestamp();
+                let alt = p.altitude.unwrap_or_default();
+                Some(Cat21 {
+                    alt_geo_ft: alt,
+                    pos_lat_deg: lat as f32,
+                    pos_long_deg: lon as f32,
+                    alt_baro_ft: alt,
+                    tod: 128 * (tod % 86400),
+                    rec_time_posix: tod,
+                    emitter_category: 3,
+                    descriptor_atp: 1,
+                    alt_reporting_capability_ft: 0,
+                    target_addr: addr,
+                    cat: 21,
+                    line_id: 1,
+                    ds_id: 18,
+                    report_type: 3,
+                    tod_calculated: TodCalculated::N,
+                    callsign: self.aircraft_id.clone(),
+                    rec_num: 1,
+                    ..Cat21::default()
+                })
+            })
+            .collect()
+    }
+}
+
+impl Cat21 {
+    /// Flatten the trajectories of flights sent as JSON lines, see `NmFlight`.
+    ///
+    #[tracing::instrument(skip(input))]
+    pub fn from_nmb2b(input: &str) -> Result<Vec<Cat21>> {
+        let res = serde_json::Deserializer::from_str(input)
+            .into_iter::<NmFlight>()
+            .filter_map(|f| f.ok())
+            .flat_map(|f| f.to_cat21())
+            .collect::<Vec<_>>();
+        debug!("{} records", res.len());
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLY: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<S:Envelope xmlns:S="http://schemas.xmlsoap.org/soap/envelope/">
+  <S:Body>
+    <fw:FlightListByAirspaceReply xmlns:fw="eurocontrol/cfmu/b2b/FlightServices">
+      <requestReceptionTime>2024-05-12 10:30:15</requestReceptionTime>
+      <requestId>B2B_CUR:1234</requestId>
+      <sendTime>2024-05-12 10:30:16</sendTime>
+      <status>OK</status>
+      <data>
+        <flights>
+          <flight>
+            <flightId>
+              <id>AA12345678</id>
+              <keys>
+                <aircraftId>RYR123</aircraftId>
+                <aerodromeOfDeparture>ELLX</aerodromeOfDeparture>
+                <nonICAOAerodromeOfDeparture>false</nonICAOAerodromeOfDeparture>
+                <airFiled>false</airFiled>
+                <aerodromeOfDestination>EGSS</aerodromeOfDestination>
+                <nonICAOAerodromeOfDestination>false</nonICAOAerodromeOfDestination>
+                <estimatedOffBlockTime>2024-05-12 10:00</estimatedOffBlockTime>
+              </keys>
+            </flightId>
+            <aircraftType>B738</aircraftType>
+            <aircraftAddress>4CA2D6</aircraftAddress>
+            <ctfmPointProfile>
+              <timeOver>2024-05-12 10:30:00</timeOver>
+              <coveredDistance>120</coveredDistance>
+              <flightLevel><unit>F</unit><level>350</level></flightLevel>
+              <point><pointId>ABNUR</pointId></point>
+            </ctfmPointProfile>
+            <ctfmPointProfile>
+              <timeOver>2024-05-12 10:35:30</timeOver>
+              <coveredDistance>160</coveredDistance>
+              <flightLevel><unit>F</unit><level>370</level></flightLevel>
+              <point>
+                <nonPublishedPoint-GeoPoint>
+                  <position>
+                    <latitude><angle>4930</angle><side>NORTH</side></latitude>
+                    <longitude><angle>0061500</angle><side>WEST</side></longitude>
+                  </position>
+                </nonPublishedPoint-GeoPoint>
+              </point>
+            </ctfmPointProfile>
+          </flight>
+        </flights>
+        <flights>
+          <flightPlan/>
+        </flights>
+      </data>
+    </fw:FlightListByAirspaceReply>
+  </S:Body>
+</S:Envelope>
+"##;
+
+    #[test]
+    fn test_nmb2b_from_reply() -> Result<()> {
+        let all = NmFlight::from_reply(REPLY)?;
+        assert_eq!(1, all.len());
+
+        let f = &all[0];
+        assert_eq!("AA12345678", f.ifpl_id);
+        assert_eq!("RYR123", f.aircraft_id);
+        assert_eq!("ELLX", f.departure);
+        assert_eq!("EGSS", f.destination);
+        assert_eq!(Some("B738".to_string()), f.aircraft_type);
+        assert_eq!("2024-05-12T10:00:00+00:00", f.eobt.unwrap().to_rfc3339());
+        assert_eq!(2, f.points.len());
+        assert_eq!(Some("ABNUR".to_string()), f.points[0].name);
+        assert!(f.points[0].latitude.is_none());
+        assert_eq!(Some(37000), f.points[1].altitude);
+        assert_eq!(Some(49.5), f.points[1].latitude);
+        assert_eq!(Some(-6.25), f.points[1].longitude);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nmb2b_error() {
+        let reply = r##"<S:Envelope xmlns:S="http://schemas.xmlsoap.org/soap/envelope/"><S:Body>
+<fw:FlightListByAirspaceReply xmlns:fw="eurocontrol/cfmu/b2b/FlightServices">
+<status>INVALID_INPUT</status><reason>INVALID_VALUE</reason>
+</fw:FlightListByAirspaceReply></S:Body></S:Envelope>"##;
+        let err = NmFlight::from_reply(reply).unwrap_err();
+        assert!(err.to_string().contains("INVALID_INPUT"));
+    }
+
+    #[test]
+    fn test_nmb2b_into_cat21() -> Result<()> {
+        let lines = NmFlight::to_lines(&NmFlight::from_reply(REPLY)?)?;
+        let res = Cat21::from_nmb2b(&lines)?;
+        assert_eq!(1, res.len());
+
+        let line = &res[0];
+        assert_eq!("RYR123", line.callsign);
+        assert_eq!(0x4CA2D6, line.target_addr);
+        assert_eq!(37000, line.alt_baro_ft);
+        assert_eq!(1715510130, line.rec_time_posix);
+        assert_eq!(-6.25, line.pos_long_deg);
+        Ok(())
+    }
+}
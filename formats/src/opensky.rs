@@ -6,6 +6,11 @@
 //!
 //! XXX Due to this, I'm not sure converting these state vectors into our Cat21 makes any sense.
 //!
+//! Each array is decoded in order into a `StateVector` (serde accepts sequences for structs),
+//! every field the API may return as `null` being an `Option`.  `category` is only sent with
+//! `extended=1`.  The ICAO address, ground bit, SPI and category are carried into `Cat21`,
+//! the squawk has no column there.
+//!
 //! Documentation is taken from [The Opensky site](https://opensky-network.github.io/opensky-api/rest.html)
 //!
 //! [Impala]: https://opensky-network.org/data/impala/
@@ -16,7 +21,7 @@ use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use tracing::{debug, trace};
 
-use crate::{convert_to, to_feet, to_knots, Cat21, TodCalculated};
+use crate::{convert_to, to_feet, to_knots, Bool, Cat21, TodCalculated};
 
 /// Origin of state's position
 ///
@@ -51,6 +56,7 @@ pub enum Category {
     Skydiver,
     UltraLight,
     Reserved,
+    Uav,
     Space,
     SurfaceEmergencyVehicule,
     SurfaceServiceVehicule,
@@ -59,6 +65,34 @@ pub enum Category {
     LineObstacle,
 }
 
+impl Category {
+    /// Asterix I021/020 emitter category
+    ///
+    pub fn ecat(&self) -> usize {
+        match self {
+            Category::NoInfo | Category::NoAdsBEmitterCategoryInfo | Category::Reserved => 0,
+            Category::Light => 1,
+            Category::Small => 2,
+            Category::Large => 3,
+            Category::HighVortexLarge => 4,
+            Category::Heavy => 5,
+            Category::HighPerformance => 6,
+            Category::RotorCraft => 10,
+            Category::Glider => 11,
+            Category::Lighter => 12,
+            Category::Uav => 13,
+            Category::Space => 14,
+            Category::UltraLight => 15,
+            Category::Skydiver => 16,
+            Category::SurfaceEmergencyVehicule => 20,
+            Category::SurfaceServiceVehicule => 21,
+            Category::PointObstacle => 22,
+            Category::ClusterObstacle => 23,
+            Category::LineObstacle => 24,
+        }
+    }
+}
+
 // Public structs
 
 /// This is the main container for packets sent by the API.
@@ -94,42 +128,11 @@ impl StateList {
     pub fn from_json(input: &str) -> Result<Self> {
         trace!("statelist::from_json");
 
-        let data: Payload = serde_json::from_str(input)?;
-
-        let states: Vec<StateVector> = data
-            .states
-            .iter()
-            .map(|r| StateVector {
-                icao24: r.0.clone(),
-                callsign: Some(r.1.clone()),
-                origin_country: r.2.clone(),
-                time_position: Some(r.3),
-                last_contact: r.4,
-                longitude: Some(r.5),
-                latitude: Some(r.6),
-                baro_altitude: Some(r.7),
-                on_ground: r.8,
-                velocity: Some(r.9),
-                true_track: Some(r.10),
-                vertical_rate: Some(r.11),
-                sensors: Some(r.12.clone()),
-                geo_altitude: Some(r.13),
-                squawk: Some(r.14.clone()),
-                spi: r.15,
-                position_source: r.16,
-                //category: r.17,
-            })
-            .collect();
-
-        trace!("{} points", states.len());
-
-        // Prepare final data
-        //
-        let data: StateList = StateList {
-            time: data.time,
-            states: Some(states),
-        };
-
+        let data: StateList = serde_json::from_str(input)?;
+        trace!(
+            "{} points",
+            data.states.as_ref().map(|s| s.len()).unwrap_or_default()
+        );
         Ok(data)
     }
 }
@@ -144,24 +147,35 @@ pub struct StateVector {
     pub callsign: Option<String>,
     /// Origin Country
     pub origin_country: String,
+    /// Last position update
     pub time_position: Option<i32>,
+    /// Last update of any kind
     pub last_contact: i32,
     /// Position
     pub longitude: Option<f32>,
     pub latitude: Option<f32>,
+    /// Meters
     pub baro_altitude: Option<f32>,
     pub on_ground: bool,
+    /// m/s
     pub velocity: Option<f32>,
+    /// Degrees from North
     pub true_track: Option<f32>,
+    /// m/s
     pub vertical_rate: Option<f32>,
+    /// Receivers which contributed
     pub sensors: Option<Vec<i32>>,
+    /// Meters
     pub geo_altitude: Option<f32>,
+    /// Mode A code
     pub squawk: Option<String>,
+    /// Special purpose indicator
     pub spi: bool,
     /// Position source
     pub position_source: Source,
-    // /// Aircraft category XXX BUG
-    // pub category: Category,
+    /// Aircraft category, only with `extended=1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
 }
 
 convert_to!(from_opensky, StateVector, Cat21);
@@ -236,55 +250,15 @@ impl From<&PandaStateVector> for Cat21 {
     }
 }
 
-// Private structs
-
-/// Struct returned by the Opensky API
-///
-#[derive(Debug, Deserialize)]
-struct Payload {
-    /// UNIX timestamp
-    pub time: i32,
-    /// State vectors
-    pub states: Vec<Rawdata>,
-}
-
-/// Opensky sends out tuples we need to match with real field names.
-/// cf. [StateVector]
-///
-/// XXX This is a terrible way to return named data
-///
-/// [StateVector]: https://openskynetwork.github.io/opensky-api/rest.html#own-states
-///
-#[derive(Debug, Deserialize)]
-struct Rawdata(
-    String,
-    String,
-    String,
-    i32,
-    i32,
-    f32,
-    f32,
-    f32,
-    bool,
-    f32,
-    f32,
-    f32,
-    Vec<i32>,
-    f32,
-    String,
-    bool,
-    Source,
-    //Category,
-);
-
 convert_to!(from_vectors, StateVector, Cat21);
 
 impl From<&StateVector> for Cat21 {
     /// Generate a `Cat21` struct from `StateList`
     ///
     fn from(line: &StateVector) -> Self {
-        let tod: i64 = line.time_position.unwrap_or(0) as i64;
+        let tod: i64 = line.time_position.unwrap_or(line.last_contact) as i64;
         let callsign = line.callsign.clone().unwrap_or("".to_string());
+        let flag = |b: bool| if b { Bool::Y } else { Bool::N };
 
         Cat21 {
             alt_geo_ft: to_feet(line.geo_altitude.unwrap_or(0.0)),
@@ -294,16 +268,18 @@ impl From<&StateVector> for Cat21 {
             tod: 128 * (tod % 86400),
             rec_time_posix: tod,
             rec_time_ms: 0,
-            emitter_category: 13,
+            emitter_category: line.category.map(|c| c.ecat()).unwrap_or(13),
+            ground_bit: flag(line.on_ground),
+            spi: flag(line.spi),
             descriptor_atp: 1,
             alt_reporting_capability_ft: 0,
-            target_addr: 623615,
+            target_addr: u32::from_str_radix(line.icao24.trim(), 16).unwrap_or(623615),
             cat: 21,
             line_id: 1,
             ds_id: 18,
             report_type: 3,
             tod_calculated: TodCalculated::N,
-            callsign,
+            callsign: callsign.trim().to_string(),
             groundspeed_kt: to_knots(line.velocity.unwrap_or(0.0)),
             track_angle_deg: line.true_track.unwrap_or(0.0),
             rec_num: 1,
@@ -311,3 +287,95 @@ impl From<&StateVector> for Cat21 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    /// Shaped like `/states/all`, with `null` in every position where the API may send one
+    ///
+    const STATES: &str = r#"{
+  "time": 1715508000,
+  "states": [
+    ["4ca2d6", "RYR5GH  ", "Ireland", 1715507999, 1715507999, 2.3488, 48.8534, 10668.0, false, 231.5, 178.2, -0.33, null, 10972.8, "2312", false, 0],
+    ["39de4f", null, "France", null, 1715507990, null, null, null, true, null, null, null, null, null, null, false, 0],
+    ["3c6444", "DLH9U   ", "Germany", 1715507998, 1715507998, 8.5706, 50.0333, null, false, 80.2, 250.0, 5.2, [1234, 5678], null, "1000", true, 2, 6]
+  ]
+}"#;
+
+    #[test]
+    fn test_statelist_nulls() -> Result<()> {
+        let sl = StateList::from_json(STATES)?;
+        let states = sl.states.unwrap();
+        assert_eq!(3, states.len());
+
+        let s = &states[0];
+        assert_eq!(Some("2312".to_string()), s.squawk);
+        assert_eq!(None, s.sensors);
+        assert_eq!(None, s.category);
+
+        let s = &states[1];
+        assert_eq!(None, s.callsign);
+        assert_eq!(None, s.time_position);
+        assert_eq!(None, s.latitude);
+        assert_eq!(None, s.squawk);
+        assert!(s.on_ground);
+
+        let s = &states[2];
+        assert_eq!(Some(vec![1234, 5678]), s.sensors);
+        assert_eq!(Source::MLAT, s.position_source);
+        assert_eq!(Some(Category::Heavy), s.category);
+        assert!(s.spi);
+        Ok(())
+    }
+
+    #[test]
+    fn test_statelist_api_response() -> Result<()> {
+        // Saved from the API, also used by the benchmarks
+        //
+        let sl = StateList::from_json(include_str!("../../data/202306042003.json"))?;
+        assert_eq!(1685901853, sl.time);
+
+        let cat21 = sl.to_cat21();
+        assert_eq!(24, cat21.len());
+        assert_eq!(0x3c4b34, cat21[0].target_addr);
+        assert_eq!("DLH402", cat21[0].callsign);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(0, 0x4ca2d6, 13, Bool::N, Bool::N, 1715507999)]
+    #[case(1, 0x39de4f, 13, Bool::Y, Bool::N, 1715507990)]
+    #[case(2, 0x3c6444, 5, Bool::N, Bool::Y, 1715507998)]
+    fn test_statelist_to_cat21(
+        #[case] n: usize,
+        #[case] addr: u32,
+        #[case] ecat: usize,
+        #[case] ground: Bool,
+        #[case] spi: Bool,
+        #[case] tm: i64,
+    ) -> Result<()> {
+        let cat21 = StateList::from_json(STATES)?.to_cat21();
+        let c = &cat21[n];
+        assert_eq!(addr, c.target_addr);
+        assert_eq!(ecat, c.emitter_category);
+        assert_eq!(ground, c.ground_bit);
+        assert_eq!(spi, c.spi);
+        assert_eq!(tm, c.rec_time_posix);
+        Ok(())
+    }
+
+    #[test]
+    fn test_statevector_roundtrip() -> Result<()> {
+        // What `Convert` does: objects this time
+        //
+        let sl = StateList::from_json(STATES)?;
+        let data = serde_json::to_string(&sl.states.unwrap()[2])?;
+        assert!(data.contains(r#""category":6"#));
+        let cat21 = Cat21::from_opensky(&data)?;
+        assert_eq!("DLH9U", cat21[0].callsign);
+        Ok(())
+    }
+}
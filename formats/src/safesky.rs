@@ -61,7 +61,7 @@ impl From<&Safesky> for Cat21 {
             tod: 128 * (tod % 86400),
             rec_time_posix: tod,
             rec_time_ms: 0,
-            emitter_category: 13,
+            emitter_category: line.category().ecat(),
             differential_correction: Bool::N,
             ground_bit: Bool::N,
             simulated_target: Bool::N,
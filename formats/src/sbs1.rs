@@ -0,0 +1,460 @@
+//! Module to read and write the SBS-1 "BaseStation" format, the CSV text messages sent by
+//! `dump1090` and its forks on port 30003 and understood by most ADS-B tools (Virtual Radar
+//! Server, etc.).
+//!
+//! Each line is one message with 22 comma-separated fields, only `MSG` lines carry data.  Fields
+//! are filled depending on the transmission type:
+//! - `MSG,1`: callsign
+//! - `MSG,2`: surface position
+//! - `MSG,3`: airborne position and altitude
+//! - `MSG,4`: ground speed, track and vertical rate
+//! - `MSG,5` to `MSG,8`: altitude, squawk and flags
+//!
+//! A position is thus spread over several messages, `Sbs1Tracks` remembers the last known values
+//! for each aircraft and generates a `Cat21` record for every position.
+//!
+//! Altitudes are in feet, speeds in knots and vertical rates in ft/min.  Dates are written and
+//! read as UTC.
+//!
+//! See: <http://woodair.net/sbs/article/barebones42_socket_data.htm>
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{Bool, Cat21, TodCalculated};
+
+/// Number of fields in a `MSG` line
+const SBS1_FIELDS: usize = 22;
+
+/// One SBS-1 message
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Sbs1 {
+    /// `MSG`, `SEL`, `ID`, `AIR`, `STA` or `CLK`
+    pub message_type: String,
+    /// 1 to 8 for `MSG`
+    pub transmission_type: Option<u8>,
+    /// Database session ID
+    pub session_id: String,
+    /// Database aircraft ID
+    pub aircraft_id: String,
+    /// ICAO 24-bit address in hex
+    pub hex_ident: String,
+    /// Database flight ID
+    pub flight_id: String,
+    /// When the message was generated
+    pub generated: Option<DateTime<Utc>>,
+    /// When the message was logged
+    pub logged: Option<DateTime<Utc>>,
+    /// Flight number or registration
+    pub callsign: Option<String>,
+    /// Mode C altitude in feet
+    pub altitude: Option<i32>,
+    /// Ground speed in knots
+    pub ground_speed: Option<f32>,
+    /// Track in degrees
+    pub track: Option<f32>,
+    /// Latitude in degrees
+    pub latitude: Option<f64>,
+    /// Longitude in degrees
+    pub longitude: Option<f64>,
+    /// Vertical rate in ft/min
+    pub vertical_rate: Option<i32>,
+    /// Mode A squawk code
+    pub squawk: Option<String>,
+    /// Squawk has changed
+    pub alert: Option<bool>,
+    /// Emergency squawk
+    pub emergency: Option<bool>,
+    /// Transponder ident activated
+    pub spi: Option<bool>,
+    /// Squat switch
+    pub is_on_ground: Option<bool>,
+}
+
+/// Empty fields are `None`
+///
+fn field<T: FromStr>(s: &str) -> Option<T> {
+    match s.trim() {
+        "" => None,
+        s => s.parse().ok(),
+    }
+}
+
+/// Flags are `-1` (or `1`) when set and `0` when not
+///
+fn flag(s: &str) -> Option<bool> {
+    match s.trim() {
+        "-1" | "1" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Date is `YYYY/MM/DD`, time is `HH:MM:SS.mmm`
+///
+fn time(date: &str, time: &str) -> Option<DateTime<Utc>> {
+    let s = format!("{} {}", date.trim(), time.trim());
+    NaiveDateTime::parse_from_str(&s, "%Y/%m/%d %H:%M:%S%.f")
+        .ok()
+        .map(|t| Utc.from_utc_datetime(&t))
+}
+
+fn write_opt<T: Display>(v: &Option<T>) -> String {
+    v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Five decimals like `dump1090`
+///
+fn write_deg(v: &Option<f64>) -> String {
+    v.map(|v| format!("{:.5}", v)).unwrap_or_default()
+}
+
+fn write_flag(v: &Option<bool>) -> &'static str {
+    match v {
+        Some(true) => "-1",
+        Some(false) => "0",
+        None => "",
+    }
+}
+
+fn write_time(v: &Option<DateTime<Utc>>) -> String {
+    match v {
+        Some(t) => t.format("%Y/%m/%d,%H:%M:%S%.3f").to_string(),
+        None => ",".to_string(),
+    }
+}
+
+impl FromStr for Sbs1 {
+    type Err = eyre::Report;
+
+    /// Parse one line, trailing `\r\n` included.  Non-`MSG` lines are shorter.
+    ///
+    fn from_str(s: &str) -> Result<Self> {
+        let f = s.trim_end().split(',').collect::<Vec<_>>();
+        if f.len() < 10 {
+            return Err(eyre!("sbs1: short line ({} fields)", f.len()));
+        }
+        let get = |i: usize| f.get(i).copied().unwrap_or_default();
+
+        let message_type = get(0).trim().to_string();
+        if message_type == "MSG" && f.len() < SBS1_FIELDS {
+            return Err(eyre!("sbs1: short MSG line ({} fields)", f.len()));
+        }
+        Ok(Sbs1 {
+            message_type,
+            transmission_type: field(get(1)),
+            session_id: get(2).trim().to_string(),
+            aircraft_id: get(3).trim().to_string(),
+            hex_ident: get(4).trim().to_uppercase(),
+            flight_id: get(5).trim().to_string(),
+            generated: time(get(6), get(7)),
+            logged: time(get(8), get(9)),
+            callsign: field::<String>(get(10)).map(|c| c.trim().to_string()),
+            altitude: field(get(11)),
+            ground_speed: field(get(12)),
+            track: field(get(13)),
+            latitude: field(get(14)),
+            longitude: field(get(15)),
+            vertical_rate: field(get(16)),
+            squawk: field(get(17)),
+            alert: flag(get(18)),
+            emergency: flag(get(19)),
+            spi: flag(get(20)),
+            is_on_ground: flag(get(21)),
+        })
+    }
+}
+
+impl Display for Sbs1 {
+    /// Always write the 22 fields, without line ending
+    ///
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.message_type,
+            write_opt(&self.transmission_type),
+            self.session_id,
+            self.aircraft_id,
+            self.hex_ident,
+            self.flight_id,
+            write_time(&self.generated),
+            write_time(&self.logged),
+            write_opt(&self.callsign),
+            write_opt(&self.altitude),
+            write_opt(&self.ground_speed),
+            write_opt(&self.track),
+            write_deg(&self.latitude),
+            write_deg(&self.longitude),
+            write_opt(&self.vertical_rate),
+            write_opt(&self.squawk),
+            write_flag(&self.alert),
+            write_flag(&self.emergency),
+            write_flag(&self.spi),
+            write_flag(&self.is_on_ground),
+        )
+    }
+}
+
+impl Sbs1 {
+    /// Empty `MSG` of the given transmission type for `hex`, like `dump1090` we do not have
+    /// any database IDs.
+    ///
+    fn msg(ttype: u8, hex: &str, tm: Option<DateTime<Utc>>) -> Self {
+        Sbs1 {
+            message_type: "MSG".to_string(),
+            transmission_type: Some(ttype),
+            session_id: "1".to_string(),
+            aircraft_id: "1".to_string(),
+            hex_ident: hex.to_string(),
+            flight_id: "1".to_string(),
+            generated: tm,
+            logged: tm,
+            ..Sbs1::default()
+        }
+    }
+
+    /// Generate the messages for one `Cat21` record: callsign (`MSG,1`) if known, velocity
+    /// (`MSG,4`) and position (`MSG,3`) last so that readers merging messages get everything.
+    ///
+    pub fn from_cat21(rec: &Cat21) -> Vec<Sbs1> {
+        let hex = format!("{:06X}", rec.target_addr);
+        let tm = Utc
+            .timestamp_opt(rec.rec_time_posix, rec.rec_time_ms * 1_000_000)
+            .single();
+        let ground = Some(matches!(rec.ground_bit, Bool::Y));
+
+        let mut res = vec![];
+        let callsign = rec.callsign.trim();
+        if !callsign.is_empty() {
+            res.push(Sbs1 {
+                callsign: Some(callsign.to_string()),
+                is_on_ground: ground,
+                ..Sbs1::msg(1, &hex, tm)
+            });
+        }
+        res.push(Sbs1 {
+            ground_speed: Some(rec.groundspeed_kt),
+            track: Some(rec.track_angle_deg),
+            ..Sbs1::msg(4, &hex, tm)
+        });
+        res.push(Sbs1 {
+            altitude: Some(rec.alt_baro_ft as i32),
+            latitude: Some(rec.pos_lat_deg as f64),
+            longitude: Some(rec.pos_long_deg as f64),
+            alert: Some(false),
+            emergency: Some(false),
+            spi: Some(matches!(rec.spi, Bool::Y)),
+            is_on_ground: ground,
+            ..Sbs1::msg(3, &hex, tm)
+        });
+        res
+    }
+
+    /// Write a batch of records as SBS-1 lines, each ended by `\r\n` like `dump1090` does.
+    ///
+    pub fn write(data: &[Cat21]) -> String {
+        data.iter()
+            .flat_map(Sbs1::from_cat21)
+            .map(|m| format!("{}\r\n", m))
+            .collect()
+    }
+}
+
+/// Last known values for one aircraft
+///
+#[derive(Clone, Debug, Default)]
+struct Aircraft {
+    callsign: Option<String>,
+    altitude: Option<i32>,
+    ground_speed: Option<f32>,
+    track: Option<f32>,
+    spi: Option<bool>,
+    is_on_ground: Option<bool>,
+}
+
+/// Merge the partial messages of each aircraft, to be kept between batches of a stream.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Sbs1Tracks {
+    aircraft: BTreeMap<String, Aircraft>,
+}
+
+impl Sbs1Tracks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merge one message, return a record if it has a position.
+    ///
+    pub fn update(&mut self, msg: &Sbs1) -> Option<Cat21> {
+        if msg.message_type != "MSG" || msg.hex_ident.is_empty() {
+            return None;
+        }
+
+        let ac = self.aircraft.entry(msg.hex_ident.clone()).or_default();
+        if msg.callsign.is_some() {
+            ac.callsign.clone_from(&msg.callsign);
+        }
+        ac.altitude = msg.altitude.or(ac.altitude);
+        ac.ground_speed = msg.ground_speed.or(ac.ground_speed);
+        ac.track = msg.track.or(ac.track);
+        ac.spi = msg.spi.or(ac.spi);
+        ac.is_on_ground = msg.is_on_ground.or(ac.is_on_ground);
+
+        let (Some(lat), Some(lon)) = (msg.latitude, msg.longitude) else {
+            return None;
+        };
+        let tm = msg.generated.or(msg.logged).unwrap_or_else(Utc::now);
+        let tod = tm.timestamp();
+        let alt = ac.altitude.unwrap_or_default().max(0) as u32;
+        let yes_no = |b: Option<bool>| if b == Some(true) { Bool::Y } else { Bool::N };
+
+        Some(Cat21 {
+            alt_geo_ft: alt,
+            pos_lat_deg: lat as f32,
+            pos_long_deg: lon as f32,
+            alt_baro_ft: alt,
+            tod: 128 * (tod % 86400),
+            rec_time_posix: tod,
+            rec_time_ms: tm.timestamp_subsec_millis(),
+            emitter_category: 13,
+            ground_bit: yes_no(ac.is_on_ground),
+            spi: yes_no(ac.spi),
+            descriptor_atp: 1,
+            alt_reporting_capability_ft: 0,
+            target_addr: u32::from_str_radix(&msg.hex_ident, 16).unwrap_or_default(),
+            cat: 21,
+            line_id: 1,
+            ds_id: 18,
+            report_type: 3,
+            tod_calculated: TodCalculated::N,
+            callsign: ac.callsign.clone().unwrap_or_default(),
+            groundspeed_kt: ac.ground_speed.unwrap_or_default(),
+            track_angle_deg: ac.track.unwrap_or_default(),
+            rec_num: 1,
+            ..Cat21::default()
+        })
+    }
+
+    /// Parse a batch of lines, invalid ones are skipped.
+    ///
+    pub fn feed(&mut self, input: &str) -> Vec<Cat21> {
+        input
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .filter_map(|l| match Sbs1::from_str(l) {
+                Ok(msg) => Some(msg),
+                Err(e) => {
+                    debug!("{}: {}", e, l);
+                    None
+                }
+            })
+            .filter_map(|msg| self.update(&msg))
+            .collect()
+    }
+}
+
+impl Cat21 {
+    /// Convert a batch of SBS-1 lines, aircraft with no position in the batch are ignored.
+    ///
+    #[tracing::instrument]
+    pub fn from_sbs1(input: &str) -> Result<Vec<Cat21>> {
+        Ok(Sbs1Tracks::new().feed(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    const FEED: &str = "MSG,1,111,11111,4CA2D6,111111,2024/05/12,10:30:14.000,2024/05/12,10:30:14.000,RYR4KX  ,,,,,,,,,,,0\r
+MSG,4,111,11111,4CA2D6,111111,2024/05/12,10:30:14.500,2024/05/12,10:30:14.500,,,420,87.5,,,-64,,,,,0\r
+MSG,3,111,11111,4CA2D6,111111,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250,,36000,,,49.61160,6.20610,,,0,0,0,0\r
+STA,,5,179,4CA2D6,10103,2024/05/12,10:30:16.000,2024/05/12,10:30:16.000,RM\r
+MSG,3,111,11111,4CA2D6,111111,2024/05/12,10:30:16.250,2024/05/12,10:30:16.250,,36025,,,49.61200,6.20900,,,0,0,0,0\r
+";
+
+    #[test]
+    fn test_sbs1_parse() -> Result<()> {
+        let line = FEED.lines().nth(2).unwrap();
+        let msg = Sbs1::from_str(line)?;
+
+        assert_eq!(Some(3), msg.transmission_type);
+        assert_eq!("4CA2D6", msg.hex_ident);
+        assert_eq!(Some(36000), msg.altitude);
+        assert_eq!(Some(49.6116), msg.latitude);
+        assert_eq!(Some(false), msg.is_on_ground);
+        assert_eq!(1715509815, msg.generated.unwrap().timestamp());
+        assert_eq!(None, msg.callsign);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("MSG,3,111")]
+    #[case("MSG,3,111,11111,4CA2D6,111111,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250")]
+    fn test_sbs1_parse_short(#[case] line: &str) {
+        assert!(Sbs1::from_str(line).is_err());
+    }
+
+    #[test]
+    fn test_sbs1_into_cat21() -> Result<()> {
+        let res = Cat21::from_sbs1(FEED)?;
+        assert_eq!(2, res.len());
+
+        let line = &res[0];
+        assert_eq!(0x4CA2D6, line.target_addr);
+        assert_eq!("RYR4KX", line.callsign);
+        assert_eq!(36000, line.alt_baro_ft);
+        assert_eq!(420.0, line.groundspeed_kt);
+        assert_eq!(87.5, line.track_angle_deg);
+        assert_eq!(1715509815, line.rec_time_posix);
+        assert_eq!(250, line.rec_time_ms);
+        assert_eq!(36025, res[1].alt_baro_ft);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sbs1_tracks_across_batches() {
+        let mut tracks = Sbs1Tracks::new();
+        let (first, second) = FEED.split_at(FEED.find("MSG,3").unwrap());
+
+        assert!(tracks.feed(first).is_empty());
+        let res = tracks.feed(second);
+        assert_eq!(2, res.len());
+        assert_eq!("RYR4KX", res[0].callsign);
+    }
+
+    #[test]
+    fn test_sbs1_write() -> Result<()> {
+        let res = Cat21::from_sbs1(FEED)?;
+        let out = Sbs1::write(&res[..1]);
+
+        let lines = out.split_terminator("\r\n").collect::<Vec<_>>();
+        assert_eq!(3, lines.len());
+        assert_eq!(
+            "MSG,3,1,1,4CA2D6,1,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250,,36000,,,49.61160,6.20610,,,0,0,0,0",
+            lines[2]
+        );
+        assert!(lines.iter().all(|l| l.split(',').count() == SBS1_FIELDS));
+
+        // And back
+        //
+        let back = Cat21::from_sbs1(&out)?;
+        assert_eq!(1, back.len());
+        assert_eq!(res[0].callsign, back[0].callsign);
+        assert_eq!(res[0].groundspeed_kt, back[0].groundspeed_kt);
+        Ok(())
+    }
+}
@@ -0,0 +1,328 @@
+//! Versioned decoding of vendor JSON messages.
+//!
+//! Some vendors (Senhive, Avionix) change their JSON schema without notice, renaming or moving
+//! fields.  Every message type implementing `Schema` lists the revisions we know about, current
+//! one first, with the keys identifying each of them and how their fields map onto the current
+//! ones.  Messages are looked at one by one as a stream can mix revisions while a vendor rolls
+//! out an upgrade:
+//!
+//! 1. the revision is the one named in the message when the schema has a version key, otherwise
+//!    the first one whose keys are all present;
+//! 2. messages in an older revision are upgraded by moving the renamed fields (`a.b` being the
+//!    field `b` of the object `a`);
+//! 3. the result is decoded as the current revision.
+//!
+//! What happens to messages in an unknown revision or failing to decode depends on the
+//! `Strictness`, set per site in `sources.hcl`.
+//!
+
+use eyre::{eyre, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use strum::EnumString;
+use tracing::{debug, trace, warn};
+
+/// What to do with messages we cannot decode
+///
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, EnumString, Eq, PartialEq, Serialize, strum::Display,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Strictness {
+    /// Stop at the first bad message
+    Fail,
+    /// Try unknown revisions as the current one, log and skip what does not decode
+    #[default]
+    Warn,
+    /// Skip unknown revisions and bad messages without a word
+    Drop,
+}
+
+/// One revision of a schema
+///
+#[derive(Clone, Copy, Debug)]
+pub struct Revision {
+    /// Name of the revision
+    pub version: &'static str,
+    /// Keys which must all be present in a message of this revision
+    pub keys: &'static [&'static str],
+    /// Fields moved since, `(old, current)`
+    pub renames: &'static [(&'static str, &'static str)],
+}
+
+/// Message types with several known revisions
+///
+pub trait Schema: DeserializeOwned {
+    /// Name of the message type, for logs
+    const NAME: &'static str;
+    /// Key holding the revision, if the messages carry it
+    const VERSION_KEY: Option<&'static str> = None;
+    /// Known revisions, the current one first
+    const REVISIONS: &'static [Revision];
+
+    /// Find the revision of `msg`
+    ///
+    fn revision(msg: &Map<String, Value>) -> Option<&'static Revision> {
+        if let Some(Value::String(version)) = Self::VERSION_KEY.and_then(|k| msg.get(k)) {
+            return Self::REVISIONS.iter().find(|r| r.version == version);
+        }
+        Self::REVISIONS
+            .iter()
+            .find(|r| r.keys.iter().all(|k| lookup(msg, k).is_some()))
+    }
+}
+
+/// Result of a decoding
+///
+#[derive(Debug)]
+pub struct Decoded<T> {
+    /// Good messages, in the current revision
+    pub records: Vec<T>,
+    /// Messages upgraded from an older revision
+    pub upgraded: usize,
+    /// Messages in a revision we do not know
+    pub unknown: usize,
+    /// Messages skipped
+    pub dropped: usize,
+}
+
+impl<T> Default for Decoded<T> {
+    fn default() -> Self {
+        Decoded {
+            records: vec![],
+            upgraded: 0,
+            unknown: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl<T> Decoded<T> {
+    /// Skip a bad message or fail, depending on `strictness`
+    ///
+    fn skip(&mut self, strictness: Strictness, why: String) -> Result<()> {
+        match strictness {
+            Strictness::Fail => return Err(eyre!(why)),
+            Strictness::Warn => warn!("{why}, skipped"),
+            Strictness::Drop => debug!("{why}, skipped"),
+        }
+        self.dropped += 1;
+        Ok(())
+    }
+}
+
+/// Decode every message in `input`, either JSON objects one after the other or arrays of them.
+///
+#[tracing::instrument(skip(input))]
+pub fn decode<T: Schema>(input: &str, strictness: Strictness) -> Result<Decoded<T>> {
+    trace!("decode {}", T::NAME);
+
+    let mut res = Decoded::default();
+    for msg in serde_json::Deserializer::from_str(input).into_iter::<Value>() {
+        match msg {
+            Ok(Value::Array(all)) => {
+                for msg in all {
+                    decode_one(msg, strictness, &mut res)?;
+                }
+            }
+            Ok(msg) => decode_one(msg, strictness, &mut res)?,
+            // Nothing sensible can be read after a syntax error
+            //
+            Err(e) => {
+                res.skip(strictness, format!("{}: {e}", T::NAME))?;
+                break;
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Bring one message to the current revision and decode it
+///
+fn decode_one<T: Schema>(msg: Value, strictness: Strictness, res: &mut Decoded<T>) -> Result<()> {
+    let Value::Object(mut msg) = msg else {
+        return res.skip(strictness, format!("{}: not an object", T::NAME));
+    };
+
+    let current = &T::REVISIONS[0];
+    match T::revision(&msg) {
+        Some(rev) => {
+            if rev.version != current.version {
+                trace!("{} {} -> {}", T::NAME, rev.version, current.version);
+                upgrade(&mut msg, rev);
+                res.upgraded += 1;
+            }
+            if let Some(key) = T::VERSION_KEY {
+                msg.insert(key.to_string(), Value::from(current.version));
+            }
+        }
+        None => {
+            res.unknown += 1;
+            let version = T::VERSION_KEY
+                .and_then(|k| msg.get(k))
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| "?".to_string());
+            let why = format!("{}: unknown revision {version}", T::NAME);
+            match strictness {
+                Strictness::Warn => warn!("{why}, decoded as {}", current.version),
+                _ => return res.skip(strictness, why),
+            }
+        }
+    }
+
+    match serde_json::from_value::<T>(Value::Object(msg)) {
+        Ok(rec) => res.records.push(rec),
+        Err(e) => res.skip(strictness, format!("{}: {e}", T::NAME))?,
+    }
+    Ok(())
+}
+
+/// Move the fields renamed since `rev`
+///
+fn upgrade(msg: &mut Map<String, Value>, rev: &Revision) {
+    for (old, new) in rev.renames {
+        if let Some(v) = take(msg, old) {
+            insert(msg, new, v);
+        }
+    }
+}
+
+/// Value at `path`
+///
+fn lookup<'a>(msg: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    match path.split_once('.') {
+        Some((key, rest)) => lookup(msg.get(key)?.as_object()?, rest),
+        None => msg.get(path),
+    }
+}
+
+/// Remove the value at `path`
+///
+fn take(msg: &mut Map<String, Value>, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        Some((key, rest)) => take(msg.get_mut(key)?.as_object_mut()?, rest),
+        None => msg.remove(path),
+    }
+}
+
+/// Set the value at `path`, creating the objects on the way
+///
+fn insert(msg: &mut Map<String, Value>, path: &str, v: Value) {
+    match path.split_once('.') {
+        Some((key, rest)) => {
+            let inner = msg.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            if !inner.is_object() {
+                *inner = Value::Object(Map::new());
+            }
+            if let Value::Object(inner) = inner {
+                insert(inner, rest, v);
+            }
+        }
+        None => {
+            msg.insert(path.to_string(), v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Msg {
+        v: String,
+        id: u32,
+        pos: Pos,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Pos {
+        lat: f64,
+    }
+
+    impl Schema for Msg {
+        const NAME: &'static str = "msg";
+        const VERSION_KEY: Option<&'static str> = Some("v");
+        const REVISIONS: &'static [Revision] = &[
+            Revision {
+                version: "2",
+                keys: &["id", "pos.lat"],
+                renames: &[],
+            },
+            Revision {
+                version: "1",
+                keys: &["ident", "lat"],
+                renames: &[("ident", "id"), ("lat", "pos.lat")],
+            },
+        ];
+    }
+
+    #[test]
+    fn test_decode_revisions() -> Result<()> {
+        let input = r##"{"v":"2","id":1,"pos":{"lat":49.5}}
+{"ident":2,"lat":50.5}
+[{"id":3,"pos":{"lat":51.5}}]"##;
+        let res = decode::<Msg>(input, Strictness::Fail)?;
+        assert_eq!(3, res.records.len());
+        assert_eq!(1, res.upgraded);
+        assert_eq!(0, res.dropped);
+        assert_eq!(
+            Msg {
+                v: "2".to_string(),
+                id: 2,
+                pos: Pos { lat: 50.5 }
+            },
+            res.records[1]
+        );
+        assert_eq!("2", res.records[2].v);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(Strictness::Fail, None)]
+    #[case(Strictness::Warn, Some((2, 1)))]
+    #[case(Strictness::Drop, Some((1, 2)))]
+    fn test_decode_strictness(#[case] s: Strictness, #[case] want: Option<(usize, usize)>) {
+        // A newer revision only adding a field, then something else entirely
+        //
+        let input = r##"{"v":"2","id":1,"pos":{"lat":49.5}}
+{"v":"3","id":2,"pos":{"lat":50.5},"alt":100}
+{"v":"3","foo":3}"##;
+        let res = decode::<Msg>(input, s);
+        match want {
+            None => assert!(res.is_err()),
+            Some((good, dropped)) => {
+                let res = res.unwrap();
+                assert_eq!(good, res.records.len());
+                assert_eq!(dropped, res.dropped);
+                assert_eq!(2, res.unknown);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_garbage() -> Result<()> {
+        let res = decode::<Msg>(
+            r##"{"v":"2","id":1,"pos":{"lat":49.5}} 42 {"v":"##,
+            Strictness::Warn,
+        )?;
+        assert_eq!(1, res.records.len());
+        assert_eq!(2, res.dropped);
+        assert!(decode::<Msg>("42", Strictness::Fail).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_strictness_from_str() {
+        assert_eq!(Strictness::Drop, Strictness::from_str("drop").unwrap());
+        assert_eq!(Strictness::Fail, Strictness::from_str("FAIL").unwrap());
+        assert_eq!(Strictness::Warn, Strictness::default());
+        assert!(Strictness::from_str("maybe").is_err());
+    }
+}
@@ -0,0 +1,410 @@
+//! Module to read and write Senhive "fusion" tracks.
+//!
+//! Senhive publishes the tracks of its counter-UAS fusion engine as JSON messages, one per track
+//! update.  Partners consuming these can get data from our other sources (Aeroscope or Remote ID
+//! through ASD, ADS-B, UTM telemetry) in the same schema, written one message per line so that
+//! each line can be sent as-is on a message bus or saved into a file.
+//!
+//! `Asd` records are converted directly as they carry the drone serial, model, home position
+//! and the detection technology; everything else goes through `Cat21`.
+//!
+//! Units are the ones of the schema: degrees, meters (MSL altitude, height above ground) and m/s.
+//!
+//! Tracks received from Senhive are decoded through `Schema`, the revision being given by the
+//! `schema` key.  Before it appeared (`fusion/0.9`), positions and identification were flat.
+//!
+
+use chrono::{DateTime, TimeZone, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    convert_to, decode, to_feet, to_knots, Asd, Cat21, Format, Revision, Schema, Strictness,
+    TodCalculated,
+};
+
+/// Version of the fusion schema we write
+const SCHEMA: &str = "fusion/1.0";
+
+/// Aircraft address for drones without one
+const DEF_ADDR: u32 = 623615;
+
+/// One fusion track update
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Senhive {
+    /// Schema name and version
+    pub schema: String,
+    /// Stable identifier of the track
+    pub track_id: String,
+    /// Time of the position
+    pub timestamp: DateTime<Utc>,
+    /// Detection technologies which contributed to this update
+    #[serde(default)]
+    pub sources: Vec<DetectionSource>,
+    /// What we know about the drone
+    #[serde(default)]
+    pub drone: SenhiveDrone,
+    /// Current position
+    pub position: SenhivePosition,
+    /// Ground velocity, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<SenhiveVelocity>,
+    /// Take-off position, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub home_position: Option<SenhivePoint>,
+}
+
+/// Detection technologies of the schema
+///
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum DetectionSource {
+    /// ADS-B
+    Adsb,
+    /// DJI Aeroscope
+    Aeroscope,
+    /// Network or broadcast Remote ID
+    RemoteId,
+    /// U-space telemetry
+    Utm,
+    /// Anything else
+    Other,
+}
+
+/// Drone identification
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenhiveDrone {
+    /// Serial number or aircraft address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    /// Model, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Callsign, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callsign: Option<String>,
+}
+
+/// Position with altitudes
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenhivePosition {
+    /// Latitude in degrees
+    pub latitude: f64,
+    /// Longitude in degrees
+    pub longitude: f64,
+    /// Altitude above mean sea level in meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altitude_msl: Option<f64>,
+    /// Height above ground in meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height_agl: Option<f64>,
+}
+
+/// Ground velocity
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenhiveVelocity {
+    /// Ground speed in m/s
+    pub ground_speed: f64,
+    /// True heading in degrees
+    pub heading: f64,
+}
+
+/// Plain point
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SenhivePoint {
+    /// Latitude in degrees
+    pub latitude: f64,
+    /// Longitude in degrees
+    pub longitude: f64,
+}
+
+impl Schema for Senhive {
+    const NAME: &'static str = "senhive";
+    const VERSION_KEY: Option<&'static str> = Some("schema");
+    const REVISIONS: &'static [Revision] = &[
+        Revision {
+            version: SCHEMA,
+            keys: &["trackId", "timestamp", "position"],
+            renames: &[],
+        },
+        Revision {
+            version: "fusion/0.9",
+            keys: &["trackId", "time", "lat", "lon"],
+            renames: &[
+                ("time", "timestamp"),
+                ("lat", "position.latitude"),
+                ("lon", "position.longitude"),
+                ("alt", "position.altitudeMsl"),
+                ("serial", "drone.serialNumber"),
+                ("model", "drone.model"),
+            ],
+        },
+    ];
+}
+
+convert_to!(from_asd, Asd, Senhive);
+
+impl Cat21 {
+    /// Decode Senhive tracks, whatever their revision, into Cat21 records.
+    ///
+    #[tracing::instrument(skip(input))]
+    pub fn from_senhive(input: &str, strictness: Strictness) -> Result<Vec<Cat21>> {
+        let res = decode::<Senhive>(input, strictness)?;
+        debug!(
+            "{} tracks, {} upgraded, {} dropped",
+            res.records.len(),
+            res.upgraded,
+            res.dropped
+        );
+        Ok(res.records.iter().map(Cat21::from).collect())
+    }
+}
+
+impl From<&Senhive> for Cat21 {
+    /// The track identifier is used as aircraft address when it is one (we write it that way
+    /// from Cat21), the serial number is the callsign if there is none.
+    ///
+    /// The following fields are **lost**:
+    /// - sources
+    /// - model
+    /// - height_agl
+    /// - home_position
+    ///
+    #[tracing::instrument]
+    fn from(line: &Senhive) -> Self {
+        let tod = line.timestamp.timestamp();
+        let alt_geo_ft = to_feet(line.position.altitude_msl.unwrap_or_default() as f32);
+        let (speed, heading) = line
+            .velocity
+            .as_ref()
+            .map(|v| (v.ground_speed as f32 * 3.6, v.heading as f32))
+            .unwrap_or_default();
+        let target_addr = match line.track_id.len() {
+            6 => u32::from_str_radix(&line.track_id, 16).unwrap_or(DEF_ADDR),
+            _ => DEF_ADDR,
+        };
+        let callsign = line
+            .drone
+            .callsign
+            .clone()
+            .or(line.drone.serial_number.clone())
+            .unwrap_or(line.track_id.clone());
+        Cat21 {
+            alt_geo_ft,
+            pos_lat_deg: line.position.latitude as f32,
+            pos_long_deg: line.position.longitude as f32,
+            alt_baro_ft: alt_geo_ft,
+            tod: 128 * (tod % 86400),
+            rec_time_posix: tod,
+            rec_time_ms: line.timestamp.timestamp_subsec_millis(),
+            emitter_category: 13,
+            descriptor_atp: 1,
+            alt_reporting_capability_ft: 0,
+            target_addr,
+            cat: 21,
+            line_id: 1,
+            ds_id: 18,
+            report_type: 3,
+            tod_calculated: TodCalculated::N,
+            callsign,
+            groundspeed_kt: to_knots(speed),
+            track_angle_deg: heading,
+            rec_num: 1,
+            ..Cat21::default()
+        }
+    }
+}
+
+impl From<&Asd> for Senhive {
+    /// ASD gives the technology in `source` ("as" for Aeroscope, "wi" for Remote ID through
+    /// InfoDrone and "ab" for ADS-B), speed is in km/h.
+    ///
+    #[tracing::instrument]
+    fn from(line: &Asd) -> Self {
+        let timestamp = line.fix_tm().map(|r| r.time).unwrap_or(line.time);
+        let source = match line.source.as_str() {
+            "ab" => DetectionSource::Adsb,
+            "as" => DetectionSource::Aeroscope,
+            "wi" => DetectionSource::RemoteId,
+            _ => DetectionSource::Other,
+        };
+        let home_position = match (line.home_lat, line.home_lon) {
+            (Some(lat), Some(lon)) => Some(SenhivePoint {
+                latitude: lat as f64,
+                longitude: lon as f64,
+            }),
+            _ => None,
+        };
+        Senhive {
+            schema: SCHEMA.to_string(),
+            track_id: line.journey.to_string(),
+            timestamp,
+            sources: vec![source],
+            drone: SenhiveDrone {
+                serial_number: Some(line.ident.clone()),
+                model: line.model.clone(),
+                callsign: None,
+            },
+            position: SenhivePosition {
+                latitude: line.latitude as f64,
+                longitude: line.longitude as f64,
+                altitude_msl: line.altitude.map(|a| a as f64),
+                height_agl: line.elevation.map(|e| e as f64),
+            },
+            velocity: Some(SenhiveVelocity {
+                ground_speed: line.speed as f64 / 3.6,
+                heading: line.heading as f64,
+            }),
+            home_position,
+        }
+    }
+}
+
+impl Senhive {
+    /// From a `Cat21` record converted from `from`, the track being the aircraft address.
+    ///
+    pub fn from_cat21(rec: &Cat21, from: Format) -> Self {
+        let source = match from {
+            Format::Aeroscope => DetectionSource::Aeroscope,
+            Format::Utm => DetectionSource::Utm,
+            Format::Opensky | Format::Sbs1 | Format::Flightaware | Format::Safesky => {
+                DetectionSource::Adsb
+            }
+            _ => DetectionSource::Other,
+        };
+        let timestamp = Utc
+            .timestamp_opt(rec.rec_time_posix, rec.rec_time_ms * 1_000_000)
+            .single()
+            .unwrap_or_default();
+        let addr = format!("{:06X}", rec.target_addr);
+        let callsign = rec.callsign.trim();
+        Senhive {
+            schema: SCHEMA.to_string(),
+            track_id: addr.clone(),
+            timestamp,
+            sources: vec![source],
+            drone: SenhiveDrone {
+                serial_number: Some(addr),
+                model: None,
+                callsign: (!callsign.is_empty()).then(|| callsign.to_string()),
+            },
+            position: SenhivePosition {
+                latitude: rec.pos_lat_deg as f64,
+                longitude: rec.pos_long_deg as f64,
+                altitude_msl: Some(rec.alt_geo_ft as f64 * 0.3048),
+                height_agl: None,
+            },
+            velocity: Some(SenhiveVelocity {
+                ground_speed: rec.groundspeed_kt as f64 * 1852.0 / 3600.0,
+                heading: rec.track_angle_deg as f64,
+            }),
+            home_position: None,
+        }
+    }
+
+    /// One JSON message per line.
+    ///
+    pub fn write(data: &[Senhive]) -> Result<String> {
+        let mut out = String::new();
+        for rec in data {
+            out.push_str(&serde_json::to_string(rec)?);
+            out.push('\n');
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    const ASD: &str = r##"{"journey":42,"ident":"1581F5FJD239C00DW22E","model":"Mavic 3","source":"as","location":1,"timestamp":"2024-05-12 10:30:15","latitude":"49.6116","longitude":"6.2061","altitude":120,"elevation":95,"home_lat":"49.61","home_lon":"6.20","speed":36.0,"heading":87.5,"station_latitude":null,"station_longitude":null}"##;
+
+    #[test]
+    fn test_senhive_from_asd() -> Result<()> {
+        let res = Senhive::from_asd(ASD)?;
+        assert_eq!(1, res.len());
+
+        let t = &res[0];
+        assert_eq!("42", t.track_id);
+        assert_eq!(
+            "2024-05-12T10:30:15Z",
+            t.timestamp
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        );
+        assert_eq!(vec![DetectionSource::Aeroscope], t.sources);
+        assert_eq!(Some("Mavic 3".to_string()), t.drone.model);
+        assert_eq!(Some(120.0), t.position.altitude_msl);
+        assert_eq!(Some(95.0), t.position.height_agl);
+        assert_eq!(10.0, t.velocity.as_ref().unwrap().ground_speed);
+        assert!(t.home_position.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_senhive_into_cat21() -> Result<()> {
+        let input = r##"{"schema":"fusion/1.0","trackId":"4CA2D6","timestamp":"2024-05-12T10:30:15.250Z","sources":["adsb"],"drone":{"callsign":"RYR123"},"position":{"latitude":49.5,"longitude":6.25,"altitudeMsl":305.0},"velocity":{"groundSpeed":10.0,"heading":90.0}}
+{"trackId":"T42","time":"2024-05-12T10:30:16Z","lat":49.6,"lon":6.3,"alt":120.0,"serial":"1581F5FJD239C00DW22E"}
+{"schema":"fusion/2.0","track":{"id":"T43"}}"##;
+        assert!(Cat21::from_senhive(input, Strictness::Fail).is_err());
+
+        let res = Cat21::from_senhive(input, Strictness::Warn)?;
+        assert_eq!(2, res.len());
+
+        let line = &res[0];
+        assert_eq!(0x4CA2D6, line.target_addr);
+        assert_eq!("RYR123", line.callsign);
+        assert_eq!(1000, line.alt_geo_ft);
+        assert_eq!(250, line.rec_time_ms);
+        assert_eq!(19.44, line.groundspeed_kt);
+
+        // `fusion/0.9`, before the `schema` key
+        //
+        let line = &res[1];
+        assert_eq!(DEF_ADDR, line.target_addr);
+        assert_eq!("1581F5FJD239C00DW22E", line.callsign);
+        assert_eq!(49.6, line.pos_lat_deg);
+        assert_eq!(393, line.alt_geo_ft);
+        Ok(())
+    }
+
+    #[test]
+    fn test_senhive_write() -> Result<()> {
+        let rec = Cat21 {
+            pos_lat_deg: 49.5,
+            pos_long_deg: 6.25,
+            alt_geo_ft: 1000,
+            rec_time_posix: 1715509815,
+            target_addr: 0x4CA2D6,
+            callsign: "RYR123 ".to_string(),
+            groundspeed_kt: 100.0,
+            ..Cat21::default()
+        };
+        let out = Senhive::write(&[Senhive::from_cat21(&rec, Format::Opensky)])?;
+        assert_eq!(1, out.lines().count());
+
+        let v: Value = serde_json::from_str(out.trim_end())?;
+        assert_eq!("fusion/1.0", v["schema"]);
+        assert_eq!("4CA2D6", v["trackId"]);
+        assert_eq!("2024-05-12T10:30:15Z", v["timestamp"]);
+        assert_eq!("adsb", v["sources"][0]);
+        assert_eq!("RYR123", v["drone"]["callsign"]);
+        assert_eq!(304.8, v["position"]["altitudeMsl"]);
+        assert!(v["position"].get("heightAgl").is_none());
+        assert!(v.get("homePosition").is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Sort keys and stable output
+//!
+//! Converted data is written in the order it comes from the source, which is not always the same
+//! from one run to the next.  `SortRule` tells, for the headerless output of each format, which
+//! columns hold the time and the aircraft address so the output can be sorted on them.
+//!
+//! `stable_line()` normalises a line so the same data is always written the same way: floats get
+//! a fixed number of decimals and JSON objects have their keys sorted.
+//!
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use strum::EnumString;
+
+use crate::Format;
+
+/// Number of decimals of floats in stable output
+pub const STABLE_PRECISION: usize = 6;
+
+/// What we can sort on
+///
+#[derive(Clone, Copy, Debug, Deserialize, EnumString, PartialEq, Serialize, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum SortKey {
+    /// Time of the record
+    Time,
+    /// ICAO 24-bit address (or any vehicle identifier)
+    Icao24,
+}
+
+/// Where to find the sort keys in the output of a format
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SortRule {
+    /// Columns of the time, most significant first
+    pub time: &'static [usize],
+    /// Columns of the address
+    pub icao24: &'static [usize],
+    /// CSV delimiter
+    pub delimiter: u8,
+}
+
+impl Format {
+    /// Sort rule for the output of `Convert`, CSV is written without header.
+    ///
+    pub fn sort_rule(&self) -> Option<SortRule> {
+        match self {
+            // REC_TIME_POSIX, REC_TIME_MS and TARGET_ADDR
+            //
+            Format::Cat21 => Some(SortRule {
+                time: &[7, 8],
+                icao24: &[24],
+                delimiter: b':',
+            }),
+            // Date and time generated, then hex ident
+            //
+            Format::Sbs1 => Some(SortRule {
+                time: &[6, 7],
+                icao24: &[4],
+                delimiter: b',',
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl SortRule {
+    /// Columns for a key
+    ///
+    pub fn columns(&self, key: SortKey) -> &'static [usize] {
+        match key {
+            SortKey::Time => self.time,
+            SortKey::Icao24 => self.icao24,
+        }
+    }
+
+    /// Compare two lines on `keys`, numerically if both fields are numbers.
+    ///
+    pub fn compare(&self, a: &str, b: &str, keys: &[SortKey]) -> Ordering {
+        let delim = self.delimiter as char;
+        let a = a.trim_end().split(delim).collect::<Vec<_>>();
+        let b = b.trim_end().split(delim).collect::<Vec<_>>();
+
+        keys.iter()
+            .flat_map(|&k| self.columns(k))
+            .map(|&i| {
+                let fa = a.get(i).copied().unwrap_or_default().trim();
+                let fb = b.get(i).copied().unwrap_or_default().trim();
+                match (fa.parse::<f64>(), fb.parse::<f64>()) {
+                    (Ok(x), Ok(y)) => x.total_cmp(&y),
+                    _ => fa.cmp(fb),
+                }
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Write a line the same way whatever the source: JSON objects get their keys sorted, floats in
+/// delimited lines get `STABLE_PRECISION` decimals.  The line terminator is kept.
+///
+pub fn stable_line(line: &str, delimiter: u8) -> String {
+    let body = line.trim_end_matches(['\r', '\n']);
+    let eol = &line[body.len()..];
+
+    if body.starts_with('{') {
+        if let Ok(v) = serde_json::from_str::<serde_json::Value>(body) {
+            return format!("{}{}", v, eol);
+        }
+    }
+
+    let delim = delimiter as char;
+    let body = body
+        .split(delim)
+        .map(|f| match f.parse::<f64>() {
+            Ok(v) if f.contains(['.', 'e', 'E']) && v.is_finite() => {
+                format!("{:.*}", STABLE_PRECISION, v)
+            }
+            _ => f.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(&delim.to_string());
+    format!("{}{}", body, eol)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[test]
+    fn test_sort_key() {
+        assert_eq!(SortKey::Icao24, SortKey::from_str("icao24").unwrap());
+        assert!(SortKey::from_str("speed").is_err());
+    }
+
+    #[rstest]
+    #[case(&[SortKey::Time], Ordering::Greater)]
+    #[case(&[SortKey::Icao24], Ordering::Less)]
+    #[case(&[SortKey::Icao24, SortKey::Time], Ordering::Less)]
+    fn test_sort_rule_sbs1(#[case] keys: &[SortKey], #[case] res: Ordering) {
+        let a = "MSG,3,1,1,39C4A3,1,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250,,36000\r\n";
+        let b = "MSG,3,1,1,4CA2D6,1,2024/05/12,10:30:14.500,2024/05/12,10:30:14.500,,36000\r\n";
+        let rule = Format::Sbs1.sort_rule().unwrap();
+        assert_eq!(res, rule.compare(a, b, keys));
+    }
+
+    #[test]
+    fn test_sort_rule_cat21_numeric() {
+        let line = |tm: &str| format!("8:51:0:49.0:2.0:0:0:{tm}:0{}\n", ":0".repeat(16));
+        let rule = Format::Cat21.sort_rule().unwrap();
+        assert_eq!(
+            Ordering::Less,
+            rule.compare(&line("999"), &line("1000"), &[SortKey::Time])
+        );
+        assert!(Format::Asd.sort_rule().is_none());
+    }
+
+    #[rstest]
+    #[case("8:51:49.61:2.5e1:10\n", b':', "8:51:49.610000:25.000000:10\n")]
+    #[case(
+        "MSG,3,,2024/05/12,10:30:15.250,49.61160\r\n",
+        b',',
+        "MSG,3,,2024/05/12,10:30:15.250,49.611600\r\n"
+    )]
+    #[case(r#"{"b":1.5,"a":"x"}"#, b',', r#"{"a":"x","b":1.5}"#)]
+    fn test_stable_line(#[case] line: &str, #[case] delim: u8, #[case] res: &str) {
+        assert_eq!(res, stable_line(line, delim));
+    }
+}
@@ -0,0 +1,90 @@
+//! Track identifiers
+//!
+//! Sources do not identify tracks the same way: ASD has a `journey` per drone flight while ADS-B
+//! formats like `Cat21` only have the aircraft address, which stays the same for all its flights.
+//! `TrackRule` tells, for the CSV form of each format, which column is a real track identifier
+//! (if any), which one identifies the vehicle and where time and position are.
+//!
+//! Tracks are then normalised into a `track_id` column appended to each record: the source
+//! identifier if there is one, otherwise one synthesised from the vehicle and the start of the
+//! track, a new track starting after a gap or a jump in position (see the engine `Track` task).
+//!
+
+use crate::Format;
+
+/// Name of the normalised column
+pub const TRACK_ID: &str = "track_id";
+
+/// Where to find track-related data in the CSV form of a format
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackRule {
+    /// Column with a track identifier, if the source has one
+    pub track: Option<&'static str>,
+    /// Column identifying the vehicle
+    pub vehicle: &'static str,
+    /// Time, either a UNIX timestamp or `YYYY-MM-DD HH:MM:SS`
+    pub time: &'static str,
+    /// Latitude in degrees
+    pub latitude: &'static str,
+    /// Longitude in degrees
+    pub longitude: &'static str,
+    /// Altitude, in the unit of the format
+    pub altitude: Option<&'static str>,
+    /// CSV delimiter
+    pub delimiter: u8,
+}
+
+impl Format {
+    /// Extraction rule for track identifiers, only CSV-based formats are supported.
+    ///
+    pub fn track_rule(&self) -> Option<TrackRule> {
+        match self {
+            Format::Asd => Some(TrackRule {
+                track: Some("journey"),
+                vehicle: "ident",
+                time: "timestamp",
+                latitude: "latitude",
+                longitude: "longitude",
+                altitude: Some("altitude"),
+                delimiter: b',',
+            }),
+            Format::Cat21 => Some(TrackRule {
+                track: None,
+                vehicle: "TARGET_ADDR",
+                time: "REC_TIME_POSIX",
+                latitude: "POS_LAT_DEG",
+                longitude: "POS_LONG_DEG",
+                altitude: Some("ALT_GEO_FT"),
+                delimiter: b':',
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Synthesised identifier for a track of `vehicle` starting at `start`
+///
+pub fn track_id(vehicle: &str, start: i64) -> String {
+    format!("{}-{}", vehicle.trim(), start)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(Format::Asd, Some(Some("journey")))]
+    #[case(Format::Cat21, Some(None))]
+    #[case(Format::Opensky, None)]
+    fn test_track_rule(#[case] fmt: Format, #[case] track: Option<Option<&str>>) {
+        assert_eq!(track, fmt.track_rule().map(|r| r.track));
+    }
+
+    #[test]
+    fn test_track_id() {
+        assert_eq!("4CA2D6-1715509814", track_id(" 4CA2D6", 1715509814));
+    }
+}
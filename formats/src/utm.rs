@@ -0,0 +1,250 @@
+//! Module to load the flight telemetry exchanged between USSPs (U-space service providers) and
+//! map it into our own Cat21 and Cat129 formats.
+//!
+//! This is the `GetOperationalIntentTelemetryResponse` object of the ASTM F3548-21 USS to USS
+//! API, as returned by `/uss/v1/operational_intents/{entityid}/telemetry` by the sandboxes we
+//! interoperate with.
+//!
+//! Altitudes are in meters above the WGS84 ellipsoid and speeds in m/s, these are the only units
+//! defined by the standard.
+//!
+//! See: <https://github.com/astm-utm/Protocol/blob/master/utm.yaml>
+//!
+
+use chrono::{DateTime, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{convert_to, to_feet, to_knots, Cat129, Cat21, Position, TodCalculated};
+
+/// Telemetry of one operational intent, the only data record of this format
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Utm {
+    /// UUID of the operational intent
+    pub operational_intent_id: String,
+    /// Last known position, missing if the flight has no telemetry (yet)
+    pub telemetry: Option<VehicleTelemetry>,
+    /// When new telemetry is expected
+    pub next_telemetry_opportunity: Option<UtmTime>,
+}
+
+/// Position and velocity at a given time
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VehicleTelemetry {
+    /// When it was measured
+    pub time_measured: UtmTime,
+    /// Position, including altitude
+    pub position: Option<UtmPosition>,
+    /// Ground velocity
+    pub velocity: Option<Velocity>,
+}
+
+/// Time is an object to carry its format, always `RFC3339`
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UtmTime {
+    /// Actual time
+    pub value: DateTime<Utc>,
+    /// Always "RFC3339"
+    pub format: String,
+}
+
+/// Position of the aircraft
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UtmPosition {
+    /// Longitude in degrees
+    pub longitude: f64,
+    /// Latitude in degrees
+    pub latitude: f64,
+    /// Horizontal accuracy category (`HAUnknown`, `HA10NMPlus`, … `HA1mMinus`)
+    pub accuracy_h: Option<String>,
+    /// Vertical accuracy category (`VAUnknown`, `VA150mPlus`, … `VA1mMinus`)
+    pub accuracy_v: Option<String>,
+    /// Whether the position was extrapolated instead of measured
+    #[serde(default)]
+    pub extrapolated: bool,
+    /// Altitude
+    pub altitude: Option<Altitude>,
+}
+
+/// Altitude, only meters above the WGS84 ellipsoid are defined
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Altitude {
+    /// Altitude value
+    pub value: f64,
+    /// Always "W84"
+    pub reference: String,
+    /// Always "M"
+    pub units: String,
+}
+
+/// Ground velocity
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Velocity {
+    /// Ground speed
+    pub speed: f32,
+    /// Always "MetersPerSecond"
+    pub units_speed: String,
+    /// True track in degrees
+    pub track: Option<f32>,
+}
+
+convert_to!(from_utm, Utm, Cat21);
+
+impl Utm {
+    /// Time of the measurement, `None` without telemetry
+    ///
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        self.telemetry.as_ref().map(|t| t.time_measured.value)
+    }
+
+    /// Altitude in meters (WGS84) if any
+    ///
+    fn altitude(&self) -> f32 {
+        self.telemetry
+            .as_ref()
+            .and_then(|t| t.position.as_ref())
+            .and_then(|p| p.altitude.as_ref())
+            .map(|a| a.value as f32)
+            .unwrap_or_default()
+    }
+
+    /// Ground speed in km/h and track in degrees
+    ///
+    fn velocity(&self) -> (f32, f32) {
+        self.telemetry
+            .as_ref()
+            .and_then(|t| t.velocity.as_ref())
+            .map(|v| (v.speed * 3.6, v.track.unwrap_or_default()))
+            .unwrap_or_default()
+    }
+
+    /// Latitude and longitude
+    ///
+    fn position(&self) -> Position {
+        self.telemetry
+            .as_ref()
+            .and_then(|t| t.position.as_ref())
+            .map(|p| Position {
+                latitude: p.latitude as f32,
+                longitude: p.longitude as f32,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Short identifier from the intent UUID, like other drone sources
+    ///
+    fn ident(&self) -> String {
+        self.operational_intent_id.chars().take(8).collect()
+    }
+}
+
+impl From<&Utm> for Cat21 {
+    /// Makes the loading and transformations
+    ///
+    /// The following fields are **lost**:
+    /// - accuracy_h/v
+    /// - extrapolated
+    /// - next_telemetry_opportunity
+    ///
+    #[tracing::instrument]
+    fn from(line: &Utm) -> Self {
+        let tod = line.time().map(|t| t.timestamp()).unwrap_or_default();
+        let pos = line.position();
+        let (speed, track) = line.velocity();
+        Cat21 {
+            alt_geo_ft: to_feet(line.altitude()),
+            pos_lat_deg: pos.latitude,
+            pos_long_deg: pos.longitude,
+            alt_baro_ft: to_feet(line.altitude()),
+            tod: 128 * (tod % 86400),
+            rec_time_posix: tod,
+            emitter_category: 13,
+            descriptor_atp: 1,
+            alt_reporting_capability_ft: 0,
+            target_addr: 623615,
+            cat: 21,
+            line_id: 1,
+            ds_id: 18,
+            report_type: 3,
+            tod_calculated: TodCalculated::N,
+            callsign: line.ident(),
+            groundspeed_kt: to_knots(speed),
+            track_angle_deg: track,
+            rec_num: 1,
+            ..Cat21::default()
+        }
+    }
+}
+
+impl From<&Utm> for Cat129 {
+    /// Load and transform into Cat129, there is no manufacturer information in telemetry.
+    ///
+    #[tracing::instrument]
+    fn from(line: &Utm) -> Self {
+        let (speed, _) = line.velocity();
+        Cat129 {
+            uas_serial: line.ident(),
+            tod: line.time().map(|t| t.timestamp()).unwrap_or_default(),
+            position: line.position(),
+            alt_sea_lvl: line.altitude(),
+            ground_speed: to_knots(speed),
+            ..Cat129::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TELEMETRY: &str = r##"{
+  "operational_intent_id": "2f8343be-6482-4d1b-a474-16847cf5b5b6",
+  "telemetry": {
+    "time_measured": {"value": "2024-05-12T10:30:15.250Z", "format": "RFC3339"},
+    "position": {
+      "longitude": 6.2061,
+      "latitude": 49.6116,
+      "accuracy_h": "HA3mMinus",
+      "accuracy_v": "VA10mMinus",
+      "extrapolated": false,
+      "altitude": {"value": 400.0, "reference": "W84", "units": "M"}
+    },
+    "velocity": {"speed": 10.0, "units_speed": "MetersPerSecond", "track": 270.5}
+  },
+  "next_telemetry_opportunity": {"value": "2024-05-12T10:30:16.250Z", "format": "RFC3339"}
+}"##;
+
+    #[test]
+    fn test_utm_into_cat21() -> Result<()> {
+        let res = Cat21::from_utm(TELEMETRY)?;
+        assert_eq!(1, res.len());
+
+        let line = &res[0];
+        assert_eq!("2f8343be", line.callsign);
+        assert_eq!(49.6116, line.pos_lat_deg);
+        assert_eq!(1312, line.alt_geo_ft);
+        assert_eq!(1715509815, line.rec_time_posix);
+        assert!((line.groundspeed_kt - 19.44).abs() < 0.01);
+        assert_eq!(270.5, line.track_angle_deg);
+        Ok(())
+    }
+
+    #[test]
+    fn test_utm_no_telemetry() -> Result<()> {
+        let data = r##"{"operational_intent_id": "2f8343be-6482-4d1b-a474-16847cf5b5b6"}"##;
+        let utm: Utm = serde_json::from_str(data)?;
+        assert!(utm.time().is_none());
+
+        let line = Cat129::from(&utm);
+        assert_eq!("2f8343be", line.uas_serial);
+        assert_eq!(0, line.tod);
+        Ok(())
+    }
+}
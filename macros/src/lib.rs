@@ -10,8 +10,80 @@ use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitInt};
 /// `execute()` takes whatever was sent from the previous stage and process is, knowing that
 /// any input should be sent directly to the stdout channel.
 ///
+/// An error from `execute()` stops the stage and is returned by its thread, the job stops
+/// waiting for it and fails.  The job `Cancel` token is checked before every batch.
+///
 #[proc_macro_derive(RunnableDerive)]
 pub fn runnable(input: TokenStream) -> TokenStream {
+    let klass = parse_macro_input!(input as DeriveInput);
+    let klass = klass.ident;
+    let call = quote!(
+        // Do something (or not) with the input data, stop there if there is an error
+        //
+        if let Err(e) = src.execute(data, stdout.clone()) {
+            ::tracing::error!("{}: {}", stringify!(#klass), e);
+            return Err(e);
+        }
+    );
+    runnable_impl(&klass, quote!(), call).into()
+}
+
+/// Same as `RunnableDerive` for tasks with an `async fn execute()`, like the ones talking to
+/// a database.
+///
+/// Every stage gets its own single-threaded runtime for as long as it runs instead of one per
+/// batch.  Errors and cancellation are handled the same way.
+///
+#[proc_macro_derive(AsyncRunnableDerive)]
+pub fn async_runnable(input: TokenStream) -> TokenStream {
+    let klass = parse_macro_input!(input as DeriveInput);
+    let klass = klass.ident;
+    let setup = quote!(
+        let rt = ::tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+    );
+    let call = quote!(
+        if let Err(e) = rt.block_on(src.execute(data, stdout.clone())) {
+            ::tracing::error!("{}: {}", stringify!(#klass), e);
+            return Err(e);
+        }
+
+        // Consumers send nothing, see whether the job is still waiting for us
+        //
+        if src.io == IO::Consumer && stdout.send(::std::string::String::new()).is_err() {
+            ::tracing::trace!("{} cancelled", stringify!(#klass));
+            break;
+        }
+    );
+    runnable_impl(&klass, setup, call).into()
+}
+
+/// Implement `Runnable` for final stages: `write_batch()` gets every batch and nothing is sent
+/// to the next stage.
+///
+/// Records received are counted like for the other stages.  As sinks never send anything,
+/// they could not notice the job giving up on them (see `Job::timeout`), after each batch an
+/// empty string is sent down the pipeline and the sink stops when nobody is listening anymore
+/// or when the job is cancelled.  An error from `write_batch()` stops the stage and is returned
+/// by its thread.
+///
+/// ```ignore
+/// #[derive(Clone, Debug, SinkDerive)]
+/// pub struct Store {
+///     io: IO,
+///     path: PathBuf,
+/// }
+///
+/// impl Store {
+///     pub fn write_batch(&mut self, data: String) -> Result<()> {
+///         ...
+///     }
+/// }
+/// ```
+///
+#[proc_macro_derive(SinkDerive)]
+pub fn sink(input: TokenStream) -> TokenStream {
     let klass = parse_macro_input!(input as DeriveInput);
     let klass = klass.ident;
     let outer = quote!(
@@ -20,30 +92,140 @@ pub fn runnable(input: TokenStream) -> TokenStream {
                 self.io.clone()
             }
 
+            fn name(&self) -> ::std::string::String {
+                stringify!(#klass).to_string()
+            }
+
             fn run(
                 &mut self,
                 input: ::std::sync::mpsc::Receiver<::std::string::String>,
+                stage: crate::Metrics,
+                _tuning: crate::Tuning,
+                cancel: crate::Cancel,
             ) -> (::std::sync::mpsc::Receiver<String>, ::std::thread::JoinHandle<Result<()>>) {
                 let (stdout, stdin) = ::std::sync::mpsc::channel::<::std::string::String>();
 
+                let mut src = self.clone();
+                let h = ::std::thread::spawn(move || {
+                    ::tracing::trace!("Sink({})", stringify!(#klass));
+
+                    stage.enter();
+                    cancel.enter();
+                    for data in input {
+                        if cancel.is_cancelled() {
+                            ::tracing::trace!("{} cancelled", stringify!(#klass));
+                            break;
+                        }
+                        stage.input(&data);
+                        if let Err(e) = src.write_batch(data) {
+                            ::tracing::error!("{}: {}", stringify!(#klass), e);
+                            return Err(e);
+                        }
+
+                        if stdout.send(::std::string::String::new()).is_err() {
+                            ::tracing::trace!("{} cancelled", stringify!(#klass));
+                            break;
+                        }
+                    }
+                    Ok(())
+                });
+                (stdin, h)
+            }
+        }
+    );
+    outer.into()
+}
+
+/// `Runnable` implementation shared by `RunnableDerive` and `AsyncRunnableDerive`, `setup` is
+/// run once in the thread of the stage and `call` for every batch in `data`.
+///
+fn runnable_impl(
+    klass: &Ident,
+    setup: proc_macro2::TokenStream,
+    call: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    quote!(
+        impl Runnable for #klass {
+            fn cap(&self) -> IO {
+                self.io.clone()
+            }
+
+            fn name(&self) -> ::std::string::String {
+                stringify!(#klass).to_string()
+            }
+
+            fn run(
+                &mut self,
+                input: ::std::sync::mpsc::Receiver<::std::string::String>,
+                stage: crate::Metrics,
+                tuning: crate::Tuning,
+                cancel: crate::Cancel,
+            ) -> (::std::sync::mpsc::Receiver<String>, ::std::thread::JoinHandle<Result<()>>) {
+                let (stdout, stdin) = ::std::sync::mpsc::channel::<::std::string::String>();
+
+                // With a capacity, what we produce goes through a bounded channel.  Producers may
+                // never return from `execute()` so a separate thread does it for them, the other
+                // ones pass their output along after each batch and wait when the next stage
+                // does not keep up.
+                //
+                let (output, stdin, relay) = match tuning.capacity {
+                    None => (stdin, None, None),
+                    Some(cap) => {
+                        let (tx, rx) = ::std::sync::mpsc::sync_channel::<::std::string::String>(cap);
+                        if self.io == IO::Producer {
+                            ::std::thread::spawn(move || {
+                                for data in stdin {
+                                    if tx.send(data).is_err() {
+                                        break;
+                                    }
+                                }
+                            });
+                            (rx, None, None)
+                        } else {
+                            (rx, Some(stdin), Some(tx))
+                        }
+                    }
+                };
+
                 let mut src = self.clone();
                 let h = ::std::thread::spawn(move || {
                     ::tracing::trace!("Runnable({})", stringify!(#klass));
 
+                    // Everything dropped in this thread is for this stage, and stops with the job
+                    //
+                    stage.enter();
+                    cancel.enter();
+
+                    #setup
+
                     // Add our message
                     //
                     for data in input {
-                        // Do something (or not) with the input data if there is an error
+                        if cancel.is_cancelled() {
+                            ::tracing::trace!("{} cancelled", stringify!(#klass));
+                            break;
+                        }
+
+                        // The first one is only a trigger
                         //
-                        src.execute(data, stdout.clone()).unwrap();
+                        if src.io != IO::Producer {
+                            stage.input(&data);
+                        }
+
+                        #call
+
+                        if let (Some(relay), Some(stdin)) = (&relay, &stdin) {
+                            for data in stdin.try_iter() {
+                                relay.send(data)?;
+                            }
+                        }
                     }
                     Ok(())
                 });
-                (stdin, h)
+                (output, h)
             }
         }
-    );
-    outer.into()
+    )
 }
 
 /// Add a `version(usize)` with to any given `struct` and implement the `Versioned`trait for it
@@ -0,0 +1,194 @@
+//! Chunked retrieval of long time ranges.
+//!
+//! Every 1h segment (see `extract_segments()`) is queried separately and its result written right
+//! away as a part file in `<output>.parts/`.  Completed segments are recorded in the manifest
+//! (`manifest.json` in the same directory) so that a failed run can be restarted and only fetch
+//! the missing ones.  Once everything is there, parts are merged into the final output.
+//!
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace};
+
+use crate::cli::Otype;
+use crate::output::{merge_parts, write_output};
+use crate::segment::ONE_HOUR;
+use crate::trino::{history_query, to_csv, Trino};
+
+/// Name of the manifest file in the parts directory
+pub const MANIFEST: &str = "manifest.json";
+
+/// Record of the query and the segments already retrieved
+///
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub start: i32,
+    pub end: i32,
+    pub bb: [f64; 4],
+    pub icao: Option<String>,
+    /// Completed segments
+    pub done: BTreeSet<i32>,
+}
+
+impl Manifest {
+    pub fn new(start: i32, end: i32, bb: [f64; 4], icao: Option<&str>) -> Self {
+        Manifest {
+            start,
+            end,
+            bb,
+            icao: icao.map(|s| s.to_string()),
+            done: BTreeSet::new(),
+        }
+    }
+
+    /// Load the manifest in `dir` if there is one and check it is for the same query, otherwise
+    /// start from `self`.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub fn resume(self, dir: &Path) -> Result<Self> {
+        let fname = dir.join(MANIFEST);
+        if !fname.exists() {
+            return Ok(self);
+        }
+
+        let old: Manifest = serde_json::from_str(&fs::read_to_string(&fname)?)?;
+        if old.start != self.start
+            || old.end != self.end
+            || old.bb != self.bb
+            || old.icao != self.icao
+        {
+            return Err(eyre!(
+                "{:?} is for a different query, remove {:?} first",
+                fname,
+                dir
+            ));
+        }
+        info!("Resuming, {} segments already done", old.done.len());
+        Ok(old)
+    }
+
+    /// Save the manifest, through a temporary file so that it is never half-written.
+    ///
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{MANIFEST}.tmp"));
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, dir.join(MANIFEST))?;
+        Ok(())
+    }
+}
+
+/// Directory for the part files of `output`
+///
+pub fn parts_dir(output: &str) -> PathBuf {
+    PathBuf::from(format!("{output}.parts"))
+}
+
+/// Fetch all segments not in the manifest, writing one part file per segment, then merge them
+/// into `output`.  The parts directory is removed afterwards unless `keep` is set.
+///
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(skip(trino, segments))]
+pub async fn fetch_chunks(
+    trino: &Trino,
+    start: i32,
+    end: i32,
+    segments: &[i32],
+    bb: [f64; 4],
+    icao: Option<&str>,
+    output: &str,
+    otype: &Otype,
+    keep: bool,
+) -> Result<()> {
+    trace!("fetch_chunks");
+
+    let dir = parts_dir(output);
+    fs::create_dir_all(&dir)?;
+
+    let mut manifest = Manifest::new(start, end, bb, icao).resume(&dir)?;
+
+    let todo: Vec<_> = segments
+        .iter()
+        .filter(|h| !manifest.done.contains(h))
+        .collect();
+
+    let bar = ProgressBar::new(segments.len() as u64);
+    bar.set_style(ProgressStyle::with_template(
+        "[{elapsed_precise}] {bar:40} {pos}/{len} {msg}",
+    )?);
+    bar.set_position(manifest.done.len() as u64);
+
+    for &hour in todo {
+        bar.set_message(format!("segment {hour}"));
+
+        // Only the part of the interval inside this segment
+        //
+        let from = start.max(hour);
+        let to = end.min(hour + ONE_HOUR - 1);
+        let sql = history_query(from, to, &[hour], bb, icao);
+
+        let (columns, rows) = trino.query(&sql).await?;
+        if !rows.is_empty() {
+            let part = dir.join(format!("part-{hour}.{otype}"));
+            let data = to_csv(&columns, &rows);
+            write_output(&data, true, &part.to_string_lossy(), otype).await?;
+        }
+
+        manifest.done.insert(hour);
+        manifest.save(&dir)?;
+        bar.inc(1);
+    }
+    bar.finish_with_message("done");
+
+    // Now merge everything
+    //
+    let parts = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with("part-"))
+        .count();
+    if parts == 0 {
+        eprintln!("No data found.");
+    } else {
+        info!("Merging {} parts into {}", parts, output);
+        merge_parts(&dir, output, otype).await?;
+    }
+
+    if !keep {
+        fs::remove_dir_all(&dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_manifest_resume() -> Result<()> {
+        let dir = tempdir()?;
+        let bb = [4.0, 50.0, 5.0, 51.0];
+
+        let mut m = Manifest::new(3600, 11000, bb, None).resume(dir.path())?;
+        assert!(m.done.is_empty());
+
+        m.done.insert(3600);
+        m.save(dir.path())?;
+
+        let m = Manifest::new(3600, 11000, bb, None).resume(dir.path())?;
+        assert_eq!(1, m.done.len());
+        assert!(m.done.contains(&3600));
+
+        // Different query
+        //
+        assert!(Manifest::new(3600, 7200, bb, None)
+            .resume(dir.path())
+            .is_err());
+        Ok(())
+    }
+}
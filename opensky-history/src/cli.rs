@@ -18,6 +18,9 @@ pub struct Opts {
     /// Location file path
     #[clap(short = 'C', long)]
     pub config: Option<String>,
+    /// GeoJSON file with the area(s) to query instead of a location.
+    #[clap(short = 'A', long)]
+    pub area: Option<String>,
     /// ICAO code for searches
     #[clap(short = 'I', long)]
     pub icao: Option<String>,
@@ -35,7 +38,14 @@ pub struct Opts {
     /// End date (YYYY-MM-DD).
     #[clap(short = 'E', long)]
     pub end: Option<String>,
-    /// Location name (if in `locations.hcl`).
+    /// Keep the part files and manifest after merging them.
+    #[clap(long)]
+    pub keep_parts: bool,
+    /// Use the embedded python (pyopensky) instead of Trino.
+    #[cfg(feature = "python")]
+    #[clap(long)]
+    pub python: bool,
+    /// Location name (if in `locations.hcl`) or area name with `--area`.
     pub name: Option<String>,
 }
 
@@ -2,38 +2,49 @@
 //!
 //! Author: Ollivier Robert <ollivier.robert@eurocontrol.int> for the ACUTE project.
 //!
-//! It uses an embedded python script through the [inline-python] crate.
-//! The script use [pyopensky] to connect to the [Impala Shell] at Opensky.
+//! It talks directly to the Opensky [Trino] endpoint (see `trino.rs`), credentials are taken
+//! from `OPENSKY_USERNAME` and `OPENSKY_PASSWORD`.
 //!
-//! XXX: "nightly" toolchain is mandatory for this.
+//! The original embedded python version (through [pyopensky]) is still available with the
+//! `python` feature and `--python`, it needs the "nightly" toolchain.
 //!
-//! [inline-python]: https://crates.io/crates/inline-python
+//! Long intervals are retrieved one 1h segment at a time and can be resumed, see `chunk.rs`.
+//!
+//! The query covers the bounding box of a location and its range or, with `--area`, of the
+//! areas in a GeoJSON file (see `Area` in `fetiche-common`).
+//!
+//! [Trino]: https://opensky-network.org/data/trino
 //! [pyopensky]: https://pypi.org/project/pyopensky/
-//! [Impala Shell]: https://opensky-network.org/data/impala
 //!
 
 use std::collections::BTreeMap;
-use std::io::Write;
+use std::path::Path;
 
 use chrono::prelude::*;
 use clap::{crate_authors, crate_version, Parser};
-use datafusion::config::{CsvOptions, TableParquetOptions};
-use datafusion::dataframe::DataFrameWriteOptions;
-use datafusion::prelude::*;
 use eyre::{eyre, Result};
-use inline_python::{Context, python};
-use tempfile::Builder;
 use tracing::{info, trace};
 
-use fetiche_common::{BB, list_locations, load_locations, Location};
+use fetiche_common::{
+    BB, list_locations, load_areas, load_locations, Area, Location, OutputFormat,
+};
 
-use crate::cli::{banner, Opts, Otype, version};
+use crate::chunk::fetch_chunks;
+use crate::cli::{banner, Opts, version};
 use crate::init::init_runtime;
+#[cfg(feature = "python")]
+use crate::output::write_output;
 use crate::segment::extract_segments;
+use crate::trino::{Trino, AUTH_URL, TRINO_URL};
 
+mod chunk;
 mod cli;
 mod init;
+mod output;
+#[cfg(feature = "python")]
+mod python;
 mod segment;
+mod trino;
 
 /// Binary name, using a different binary name
 pub const NAME: &str = env!("CARGO_BIN_NAME");
@@ -69,11 +80,12 @@ async fn main() -> Result<()> {
 
     // List loaded locations if nothing is specified, neither name nor location
     //
-    let site = match opts.name {
-        Some(name) => name,
-        None => {
+    let site = match (opts.name, &opts.area) {
+        (Some(name), _) => name,
+        (None, Some(_)) => String::new(),
+        (None, None) => {
             let dist = opts.range;
-            let str = list_locations(&loc, dist)?;
+            let str = list_locations(&loc, dist, OutputFormat::Table)?;
             eprintln!("{}", str);
             return Ok(());
         }
@@ -111,75 +123,67 @@ async fn main() -> Result<()> {
     info!("{} segments", v.len());
     trace!("{:?}", v);
 
-    let bb = match loc.get(&site) {
-        Some(loc) => loc,
-        None => return Err(eyre!("You must specify a location")),
-    };
-
-    // If the --icao option is specified, add the parameter to the query string.
-    //
-    let _icao = if opts.icao.is_some() {
-        format!(" AND CALLSIGN = '{}'", opts.icao.unwrap())
-    } else {
-        String::new()
+    let bb = match &opts.area {
+        // All areas in the file, or only the one named `site`
+        //
+        Some(fname) => load_areas(Path::new(fname))?
+            .iter()
+            .filter(|a| site.is_empty() || a.name == site)
+            .map(Area::bb)
+            .reduce(|a, b| a.union(&b))
+            .ok_or(eyre!("No area {} in {}", site, fname))?,
+        None => {
+            let loc = match loc.get(&site) {
+                Some(loc) => loc,
+                None => return Err(eyre!("You must specify a location")),
+            };
+
+            // Default range is 25 nm
+            //
+            BB::from_location(loc, opts.range)
+        }
     };
-
-    // Default range is 25 nm
-    //
-    let bb = BB::from_location(bb, opts.range);
     let bb = [bb.min_lon, bb.min_lat, bb.max_lon, bb.max_lat];
     trace!("BB={:?}", bb);
-    // Initialise our embedded Python environment
-    //
-    trace!("initialise python");
-
-    let v1 = v.clone();
-    let ctx: Context = python! {
-        from pyopensky.impala import Impala
 
-        impala = Impala()
-
-        print("From: ", 'start, "To: ", 'end, "BB=", 'bb)
-        print("Segments: ", len('v1))
-        start = 'start
-        end = 'end
-        bb = 'bb
+    let output = opts.output;
 
-        df = impala.history(start, end, bounds=bb)
-        if df is None:
-            data = ""
-        else:
-            data = df.to_csv()
-    };
-    let data = ctx.get::<String>("data");
+    #[cfg(feature = "python")]
+    if opts.python {
+        let data = python::fetch_python(start, end, &v, bb);
+        trace!("data={}", &data);
 
-    // End of the Python part thanks $DEITY! (and @m_ou_se on Twitter)
-    //
-    trace!("data={}", &data);
+        return write_output(&data, false, &output, &opts.otype).await;
+    }
 
-    // Write into temporary file.
+    // Query every segment separately, resuming if a previous run failed.
     //
-    let mut tmpf = Builder::new().suffix(".csv").tempfile()?;
-    let _ = tmpf.write(data.as_bytes())?;
-
-    let ctx = SessionContext::new();
-    let fname = tmpf.path().to_string_lossy().to_string();
-    let df = ctx.read_csv(fname, CsvReadOptions::default().has_header(false)).await?;
-    let dfopts = DataFrameWriteOptions::default().with_single_file_output(true);
-
-    let output = opts.output;
+    let trino = connect().await?;
+    fetch_chunks(
+        &trino,
+        start,
+        end,
+        &v,
+        bb,
+        opts.icao.as_deref(),
+        &output,
+        &opts.otype,
+        opts.keep_parts,
+    )
+    .await
+}
 
-    if opts.otype == Otype::Parquet {
-        let mut options = TableParquetOptions::default();
-        options.global.created_by = "acutectl/save".to_string();
-        options.global.encoding = Some("plain".to_string());
-        options.global.statistics_enabled = Some("page".to_string());
-        options.global.compression = Some("zstd(8)".to_string());
-
-        let _ = df.write_parquet(&output, dfopts, Some(options)).await?;
-    } else {
-        let props = CsvOptions::default();
-        let _ = df.write_csv(&output, dfopts, Some(props)).await?;
-    }
-    Ok(())
+/// Log into the Opensky Trino endpoint.
+///
+#[tracing::instrument]
+async fn connect() -> Result<Trino> {
+    let user = std::env::var("OPENSKY_USERNAME")
+        .map_err(|_| eyre!("OPENSKY_USERNAME must be set"))?;
+    let password = std::env::var("OPENSKY_PASSWORD")
+        .map_err(|_| eyre!("OPENSKY_PASSWORD must be set"))?;
+
+    let mut trino = Trino::new(TRINO_URL, AUTH_URL);
+    trino.login(&user, &password);
+    trino.authenticate().await?;
+    Ok(trino)
 }
@@ -0,0 +1,73 @@
+//! Writing the results, either directly from CSV data or by merging the part files written by
+//! the chunked queries.
+//!
+
+use std::io::Write;
+use std::path::Path;
+
+use datafusion::config::{CsvOptions, TableParquetOptions};
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::prelude::*;
+use eyre::Result;
+use tempfile::Builder;
+use tracing::trace;
+
+use crate::cli::Otype;
+
+/// Write a dataframe into `output` as a single CSV or Parquet file.
+///
+async fn write_df(df: DataFrame, output: &str, otype: &Otype) -> Result<()> {
+    let dfopts = DataFrameWriteOptions::default().with_single_file_output(true);
+
+    if *otype == Otype::Parquet {
+        let mut options = TableParquetOptions::default();
+        options.global.created_by = "acutectl/save".to_string();
+        options.global.encoding = Some("plain".to_string());
+        options.global.statistics_enabled = Some("page".to_string());
+        options.global.compression = Some("zstd(8)".to_string());
+
+        let _ = df.write_parquet(output, dfopts, Some(options)).await?;
+    } else {
+        let props = CsvOptions::default();
+        let _ = df.write_csv(output, dfopts, Some(props)).await?;
+    }
+    Ok(())
+}
+
+/// Write CSV `data` into `output` converting it if needed.
+///
+#[tracing::instrument(skip(data))]
+pub async fn write_output(data: &str, header: bool, output: &str, otype: &Otype) -> Result<()> {
+    trace!("write_output");
+
+    // Write into temporary file.
+    //
+    let mut tmpf = Builder::new().suffix(".csv").tempfile()?;
+    let _ = tmpf.write(data.as_bytes())?;
+
+    let ctx = SessionContext::new();
+    let fname = tmpf.path().to_string_lossy().to_string();
+    let df = ctx
+        .read_csv(fname, CsvReadOptions::default().has_header(header))
+        .await?;
+
+    write_df(df, output, otype).await
+}
+
+/// Merge all the part files in `dir` into `output`.
+///
+#[tracing::instrument]
+pub async fn merge_parts(dir: &Path, output: &str, otype: &Otype) -> Result<()> {
+    trace!("merge_parts");
+
+    let ctx = SessionContext::new();
+    let dir = dir.to_string_lossy().to_string();
+    let df = if *otype == Otype::Parquet {
+        ctx.read_parquet(dir, ParquetReadOptions::default()).await?
+    } else {
+        ctx.read_csv(dir, CsvReadOptions::default().has_header(true))
+            .await?
+    };
+
+    write_df(df, output, otype).await
+}
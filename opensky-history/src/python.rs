@@ -0,0 +1,44 @@
+//! Original implementation using an embedded python script through the [inline-python] crate.
+//! The script uses [pyopensky] to connect to the [Impala Shell] at Opensky.
+//!
+//! Only available with the `python` feature, which needs the "nightly" toolchain.
+//!
+//! [inline-python]: https://crates.io/crates/inline-python
+//! [pyopensky]: https://pypi.org/project/pyopensky/
+//! [Impala Shell]: https://opensky-network.org/data/impala
+//!
+
+use inline_python::{python, Context};
+use tracing::trace;
+
+/// Run the query through `pyopensky` and return the data as CSV.
+///
+#[tracing::instrument(skip(v))]
+pub fn fetch_python(start: i32, end: i32, v: &[i32], bb: [f64; 4]) -> String {
+    // Initialise our embedded Python environment
+    //
+    trace!("initialise python");
+
+    let v1 = v.to_vec();
+    let ctx: Context = python! {
+        from pyopensky.impala import Impala
+
+        impala = Impala()
+
+        print("From: ", 'start, "To: ", 'end, "BB=", 'bb)
+        print("Segments: ", len('v1))
+        start = 'start
+        end = 'end
+        bb = 'bb
+
+        df = impala.history(start, end, bounds=bb)
+        if df is None:
+            data = ""
+        else:
+            data = df.to_csv()
+    };
+
+    // End of the Python part thanks $DEITY! (and @m_ou_se on Twitter)
+    //
+    ctx.get::<String>("data")
+}
@@ -1,6 +1,6 @@
 use tracing::trace;
 
-const ONE_HOUR: i32 = 3_600;
+pub const ONE_HOUR: i32 = 3_600;
 
 /// Calculate the list of 1h segments necessary for a given time interval
 ///
@@ -0,0 +1,359 @@
+//! Minimal client for the Opensky [Trino] endpoint using the REST protocol.
+//!
+//! A query is `POST`ed to `/v1/statement` and the results are retrieved by following `nextUri`
+//! until there is none left.  Authentication is through an OpenID token obtained from the
+//! Opensky Keycloak server with the user credentials.
+//!
+//! [Trino]: https://trino.io/docs/current/develop/client-protocol.html
+//!
+
+use std::time::Duration;
+
+use eyre::{eyre, Result};
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{debug, trace};
+
+/// Opensky Trino endpoint
+pub const TRINO_URL: &str = "https://trino.opensky-network.org";
+/// Where to get a token
+pub const AUTH_URL: &str =
+    "https://auth.opensky-network.org/auth/realms/opensky-network/protocol/openid-connect/token";
+
+/// Catalog & schema for the historical data
+const CATALOG: &str = "minio";
+const SCHEMA: &str = "osky";
+
+/// Columns we retrieve from `state_vectors_data4`, same as `pyopensky`.
+pub const COLUMNS: [&str; 17] = [
+    "time",
+    "icao24",
+    "lat",
+    "lon",
+    "velocity",
+    "heading",
+    "vertrate",
+    "callsign",
+    "onground",
+    "alert",
+    "spi",
+    "squawk",
+    "baroaltitude",
+    "geoaltitude",
+    "lastposupdate",
+    "lastcontact",
+    "hour",
+];
+
+/// Token returned by the auth server, we only need the access token.
+///
+#[derive(Debug, Deserialize)]
+struct Token {
+    access_token: String,
+}
+
+/// One column description
+///
+#[derive(Debug, Deserialize)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ctype: String,
+}
+
+/// Error reported by the server
+///
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryError {
+    message: String,
+    error_name: String,
+}
+
+/// Every answer to `/v1/statement` and its `nextUri`
+///
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryResults {
+    id: String,
+    next_uri: Option<String>,
+    columns: Option<Vec<Column>>,
+    data: Option<Vec<Vec<Value>>>,
+    error: Option<QueryError>,
+}
+
+/// Trino client
+///
+#[derive(Debug)]
+pub struct Trino {
+    /// Base URL for queries
+    url: String,
+    /// Token URL
+    auth: String,
+    /// Opensky username
+    user: String,
+    /// Opensky password
+    password: String,
+    /// Bearer token once logged in
+    token: Option<String>,
+    /// HTTP client
+    client: Client,
+}
+
+impl Trino {
+    /// Create a client for the given endpoints
+    ///
+    pub fn new(url: &str, auth: &str) -> Self {
+        Trino {
+            url: url.to_string(),
+            auth: auth.to_string(),
+            user: String::new(),
+            password: String::new(),
+            token: None,
+            client: Client::new(),
+        }
+    }
+
+    /// Set the credentials
+    ///
+    pub fn login(&mut self, user: &str, password: &str) -> &mut Self {
+        self.user = user.to_string();
+        self.password = password.to_string();
+        self
+    }
+
+    /// Get the bearer token from the auth server
+    ///
+    #[tracing::instrument(skip(self))]
+    pub async fn authenticate(&mut self) -> Result<()> {
+        trace!("authenticate({})", self.user);
+
+        let form = [
+            ("client_id", "trino-client"),
+            ("grant_type", "password"),
+            ("username", self.user.as_str()),
+            ("password", self.password.as_str()),
+        ];
+        let resp = self.client.post(&self.auth).form(&form).send().await?;
+        if !resp.status().is_success() {
+            return Err(eyre!("authentication failed: {}", resp.status()));
+        }
+        let token: Token = resp.json().await?;
+        self.token = Some(token.access_token);
+        Ok(())
+    }
+
+    /// Run `sql` and return the columns and all the rows.
+    ///
+    #[tracing::instrument(skip(self))]
+    pub async fn query(&self, sql: &str) -> Result<(Vec<Column>, Vec<Vec<Value>>)> {
+        trace!("query");
+
+        let req = self
+            .client
+            .post(format!("{}/v1/statement", self.url))
+            .header("X-Trino-User", &self.user)
+            .header("X-Trino-Catalog", CATALOG)
+            .header("X-Trino-Schema", SCHEMA)
+            .body(sql.to_string());
+        let req = match &self.token {
+            Some(token) => req.bearer_auth(token),
+            None => req,
+        };
+        let mut res: QueryResults = req.send().await?.error_for_status()?.json().await?;
+
+        let mut columns = vec![];
+        let mut rows = vec![];
+        loop {
+            if let Some(err) = res.error {
+                return Err(eyre!(
+                    "query {} failed: {}: {}",
+                    res.id,
+                    err.error_name,
+                    err.message
+                ));
+            }
+            if let Some(cols) = res.columns.take() {
+                columns = cols;
+            }
+            if let Some(mut data) = res.data.take() {
+                debug!("{} rows", data.len());
+                rows.append(&mut data);
+            }
+            match res.next_uri {
+                Some(next) => res = self.next(&next).await?,
+                None => break,
+            }
+        }
+        Ok((columns, rows))
+    }
+
+    /// Fetch the next part, retrying while the server is busy.
+    ///
+    async fn next(&self, uri: &str) -> Result<QueryResults> {
+        let mut wait = Duration::from_millis(50);
+        loop {
+            let req = self.client.get(uri);
+            let req = match &self.token {
+                Some(token) => req.bearer_auth(token),
+                None => req,
+            };
+            let resp = req.send().await?;
+            match resp.status() {
+                StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT => {
+                    trace!("busy, waiting {}ms", wait.as_millis());
+                    tokio::time::sleep(wait).await;
+                    wait = (wait * 2).min(Duration::from_secs(5));
+                }
+                _ => return Ok(resp.error_for_status()?.json().await?),
+            }
+        }
+    }
+}
+
+/// Build the history query for the bounding box `bb` (min lon, min lat, max lon, max lat) limited
+/// to the hour segments computed by `extract_segments()`.
+///
+pub fn history_query(
+    start: i32,
+    end: i32,
+    segments: &[i32],
+    bb: [f64; 4],
+    callsign: Option<&str>,
+) -> String {
+    let hours = segments
+        .iter()
+        .map(|h| h.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let callsign = match callsign {
+        Some(cs) => format!(" AND trim(callsign) = '{}'", cs.replace('\'', "''")),
+        None => String::new(),
+    };
+    format!(
+        "SELECT {} FROM state_vectors_data4 WHERE hour IN ({}) AND time >= {} AND time <= {} \
+AND lon >= {} AND lat >= {} AND lon <= {} AND lat <= {}{}",
+        COLUMNS.join(", "),
+        hours,
+        start,
+        end,
+        bb[0],
+        bb[1],
+        bb[2],
+        bb[3],
+        callsign
+    )
+}
+
+/// Convert the result into CSV with a header line.
+///
+pub fn to_csv(columns: &[Column], rows: &[Vec<Value>]) -> String {
+    let header = columns
+        .iter()
+        .map(|c| c.name.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+    let lines = rows.iter().map(|row| {
+        row.iter()
+            .map(|v| match v {
+                Value::Null => String::new(),
+                Value::String(s) if s.contains([',', '"', '\n']) => {
+                    format!("\"{}\"", s.replace('"', "\"\""))
+                }
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    std::iter::once(header)
+        .chain(lines)
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::prelude::*;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_history_query() {
+        let q = history_query(
+            3610,
+            7230,
+            &[3600, 7200],
+            [4.0, 50.0, 5.0, 51.0],
+            Some("AFR1"),
+        );
+        assert!(q.starts_with("SELECT time, icao24, lat,"));
+        assert!(q.contains("WHERE hour IN (3600, 7200) AND time >= 3610 AND time <= 7230"));
+        assert!(q.contains("AND lon >= 4 AND lat >= 50 AND lon <= 5 AND lat <= 51"));
+        assert!(q.ends_with(" AND trim(callsign) = 'AFR1'"));
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let cols = vec![
+            Column {
+                name: "time".to_string(),
+                ctype: "integer".to_string(),
+            },
+            Column {
+                name: "callsign".to_string(),
+                ctype: "varchar".to_string(),
+            },
+        ];
+        let rows = vec![vec![json!(1), json!("AFR1")], vec![json!(2), Value::Null]];
+        assert_eq!("time,callsign\n1,AFR1\n2,\n", to_csv(&cols, &rows));
+    }
+
+    #[tokio::test]
+    async fn test_trino_query() -> Result<()> {
+        let server = MockServer::start_async().await;
+        let next = server.url("/v1/statement/queued/q1/1");
+
+        let _auth = server
+            .mock_async(|when, then| {
+                when.method(POST).path("/token");
+                then.status(200).json_body(json!({"access_token": "TOKEN"}));
+            })
+            .await;
+        let _first = server
+            .mock_async(|when, then| {
+                when.method(POST)
+                    .path("/v1/statement")
+                    .header("authorization", "Bearer TOKEN")
+                    .header("X-Trino-Catalog", CATALOG);
+                then.status(200)
+                    .json_body(json!({"id": "q1", "nextUri": next}));
+            })
+            .await;
+        let _last = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v1/statement/queued/q1/1");
+                then.status(200).json_body(json!({
+                    "id": "q1",
+                    "columns": [{"name": "time", "type": "integer"}],
+                    "data": [[1], [2]],
+                }));
+            })
+            .await;
+
+        let mut trino = Trino::new(&server.base_url(), &server.url("/token"));
+        trino.login("user", "pass");
+        trino.authenticate().await?;
+
+        let (cols, rows) = trino.query("SELECT time FROM t").await?;
+        assert_eq!("time", cols[0].name);
+        assert_eq!(2, rows.len());
+        Ok(())
+    }
+}
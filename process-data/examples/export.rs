@@ -52,7 +52,7 @@ async fn main() -> Result<()> {
     let pass = std::env::var("CLICKHOUSE_PASSWD")?;
     let endpoint = std::env::var("KLICKHOUSE_URL")?;
 
-    init_logging("export-encounters", false, true, None)?;
+    init_logging("export-encounters", false, true, None, false)?;
 
     eprintln!("Create connection.");
     let client = Client::connect(
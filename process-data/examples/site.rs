@@ -15,7 +15,7 @@ async fn main() -> Result<()> {
     let pass = std::env::var("CLICKHOUSE_PASSWD")?;
     let endpoint = std::env::var("KLICKHOUSE_URL")?;
 
-    init_logging("site", false, false, None)?;
+    init_logging("site", false, false, None, false)?;
 
     let client = Client::connect(
         endpoint,
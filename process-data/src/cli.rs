@@ -1,7 +1,9 @@
 use clap::{crate_authors, crate_description, crate_name, crate_version, Parser};
 use clap_complete::Shell;
 
-use crate::cmds::{AcuteOpts, DistOpts, ExportOpts, SetupOpts};
+use crate::cmds::{
+    AcuteOpts, AnalyzeOpts, DistOpts, ExportOpts, ReportOpts, SetupOpts, SummaryOpts,
+};
 
 /// Global (aka non-command-related) options.
 ///
@@ -46,6 +48,9 @@ pub struct Opts {
 pub enum SubCommand {
     /// Display data about Acute sites, etc.
     Acute(AcuteOpts),
+    /// Analyses over the stored data.
+    #[clap(visible_alias = "an")]
+    Analyze(AnalyzeOpts),
     /// Build the ACUTE env. from the ground up.
     #[clap(visible_alias = "boot", visible_alias = "restart")]
     Bootstrap,
@@ -58,6 +63,12 @@ pub enum SubCommand {
     /// Import into a CH instance.
     //#[clap(visible_alias = "imp")]
     //Import(ImportOpts),
+    /// HTML/PDF report for one site and one day.
+    #[clap(visible_alias = "rep")]
+    Report(ReportOpts),
+    /// (Re)build the daily summary tables used by dashboards.
+    #[clap(visible_alias = "refresh")]
+    RefreshSummaries(SummaryOpts),
     /// Remove macros and other stuff
     #[clap(visible_alias = "clean", visible_alias = "cls")]
     Cleanup(SetupOpts),
@@ -86,7 +86,7 @@ pub async fn run_acute_cmd(ctx: &Context, opts: &AcuteOpts) -> Result<()> {
                 .query_collect::<Antenna>("SELECT * FROM antennas")
                 .await?;
 
-            println!("Listing all antennas:");
+            eprintln!("Listing all antennas:");
             let res = json!(&res).to_string();
             println!("{res}");
         }
@@ -120,7 +120,7 @@ FROM
 ORDER BY start_at
         "##;
 
-            println!("Listing all installations:");
+            eprintln!("Listing all installations:");
             let res = dbh.query_collect::<Install>(r).await?;
             let res = json!(&res).to_string();
             println!("{res}");
@@ -159,7 +159,7 @@ ORDER BY
             let q = QueryBuilder::new(r).arg(home.y).arg(home.x);
             let res = dbh.query_collect::<Site>(q).await?;
 
-            println!("Listing all sites:");
+            eprintln!("Listing all sites:");
             let res = json!(&res).to_string();
             println!("{res}");
         }
@@ -0,0 +1,527 @@
+//! `analyze hotspots` sub-module.
+//!
+//! Drone detections around a site are sampled from the `drones` table (one point per journey
+//! every `--every` seconds), split into time-of-day slots (UTC) and clustered with DBSCAN: a
+//! point with at least `--min-points` others within `--eps` meters is the core of a cluster,
+//! clusters grow from core to core and what is left is noise.  Distances are computed on a
+//! local flat projection around the site, good enough within the usual 70 nm.
+//!
+//! Each cluster seen on at least `--min-days` different days is an operating area, exported as
+//! a GeoJSON `Polygon` (its convex hull) with the site, slot, points, journeys, days and the
+//! first and last detection as properties.
+//!
+//! >NOTE: THIS IS CLICKHOUSE-SPECIFIC
+//!
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+
+use chrono::{DateTime, Days, Timelike, Utc};
+use clap::Parser;
+use eyre::Result;
+use geo::{ConvexHull, MultiPoint};
+use klickhouse::{Client, QueryBuilder, Row};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, info, trace};
+
+use fetiche_common::expand_interval;
+use fetiche_common::geo::EARTH_RADIUS;
+
+use crate::cmds::{enumerate_sites, find_site, parse_day, Site, ONE_DEG};
+use crate::config::Context;
+use crate::error::Status;
+
+/// Options for `analyze hotspots`
+///
+#[derive(Debug, Parser)]
+pub struct HotspotOpts {
+    /// First day, "today", "yesterday" or any date.
+    #[clap(short = 'D', long, default_value = "yesterday")]
+    pub date: String,
+    /// Last day (default is `--date`).
+    #[clap(short = 'U', long)]
+    pub until: Option<String>,
+    /// Only this site (default is every site with an installation on one of the days).
+    #[clap(short = 's', long)]
+    pub site: Option<String>,
+    /// Distance around the site in Nautical Miles.
+    #[clap(long, default_value = "70.")]
+    pub distance: f64,
+    /// Neighbourhood radius in meters.
+    #[clap(long, default_value = "250.")]
+    pub eps: f64,
+    /// Neighbours needed for a point to be the core of a cluster.
+    #[clap(long, default_value = "10")]
+    pub min_points: usize,
+    /// Keep only clusters seen on at least this many days.
+    #[clap(long, default_value = "1")]
+    pub min_days: usize,
+    /// Length of the time-of-day slots in hours, must divide 24.
+    #[clap(long, default_value = "6")]
+    pub slot: u32,
+    /// One point per journey every N seconds.
+    #[clap(long, default_value = "10")]
+    pub every: u32,
+    /// Output file (default is hotspots-<site|all>-<YYYYMMDD>.geojson).
+    #[clap(short = 'o', long)]
+    pub output: Option<String>,
+}
+
+/// One sampled drone position
+///
+#[derive(Clone, Debug, Default, Deserialize, Row, Serialize)]
+struct Detection {
+    journey: i32,
+    time: u32,
+    lon: f64,
+    lat: f64,
+}
+
+/// One operating area
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hotspot {
+    /// Site name
+    pub site: String,
+    /// Time-of-day slot, e.g. "06-12"
+    pub slot: String,
+    /// Rank within the site and slot, by number of points
+    pub rank: usize,
+    /// Number of detections
+    pub points: usize,
+    /// Distinct journeys
+    pub journeys: usize,
+    /// Distinct days
+    pub days: usize,
+    /// First detection
+    pub first: DateTime<Utc>,
+    /// Last detection
+    pub last: DateTime<Utc>,
+    /// Average position (lon, lat)
+    pub centre: (f64, f64),
+    /// Convex hull (lon, lat), closed
+    pub hull: Vec<(f64, f64)>,
+}
+
+/// Label of the slot holding `time`, with slots of `slot` hours.
+///
+fn slot_of(time: u32, slot: u32) -> String {
+    let hour = DateTime::from_timestamp(time as i64, 0)
+        .unwrap_or_default()
+        .hour();
+    let start = hour - hour % slot;
+    format!("{:02}-{:02}", start, start + slot)
+}
+
+/// Position in meters from the site, on a flat projection.
+///
+fn project(site: &Site, lon: f64, lat: f64) -> (f64, f64) {
+    let (lon0, lat0) = (site.longitude as f64, site.latitude as f64);
+    let x = (lon - lon0).to_radians() * lat0.to_radians().cos() * EARTH_RADIUS;
+    let y = (lat - lat0).to_radians() * EARTH_RADIUS;
+    (x, y)
+}
+
+/// Coordinate rounded to 6 decimals (about 10cm)
+///
+fn coord(x: f64) -> f64 {
+    (x * 1e6).round() / 1e6
+}
+
+/// DBSCAN over `points` (in meters), returns the cluster of every point, `None` for noise.
+///
+/// Points are put in a grid of `eps` cells so only the 9 cells around a point are searched for
+/// its neighbours.  A point counts as its own neighbour.
+///
+pub fn dbscan(points: &[(f64, f64)], eps: f64, min_points: usize) -> Vec<Option<usize>> {
+    let cell = |(x, y): (f64, f64)| ((x / eps).floor() as i64, (y / eps).floor() as i64);
+
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    points
+        .iter()
+        .enumerate()
+        .for_each(|(i, &p)| grid.entry(cell(p)).or_default().push(i));
+
+    let neighbours = |i: usize| {
+        let (cx, cy) = cell(points[i]);
+        let (x, y) = points[i];
+        let mut found = vec![];
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if let Some(list) = grid.get(&(cx + dx, cy + dy)) {
+                    found.extend(list.iter().copied().filter(|&j| {
+                        let (x2, y2) = points[j];
+                        (x - x2).hypot(y - y2) <= eps
+                    }));
+                }
+            }
+        }
+        found
+    };
+
+    let mut labels = vec![None; points.len()];
+    let mut visited = vec![false; points.len()];
+    let mut next = 0;
+    for i in 0..points.len() {
+        if visited[i] {
+            continue;
+        }
+        visited[i] = true;
+
+        // Noise for now, might be the border of a later cluster
+        //
+        let seeds = neighbours(i);
+        if seeds.len() < min_points {
+            continue;
+        }
+
+        labels[i] = Some(next);
+        let mut queue = seeds;
+        while let Some(j) = queue.pop() {
+            if labels[j].is_none() {
+                labels[j] = Some(next);
+            }
+            if visited[j] {
+                continue;
+            }
+            visited[j] = true;
+            let more = neighbours(j);
+            if more.len() >= min_points {
+                queue.extend(more);
+            }
+        }
+        next += 1;
+    }
+    labels
+}
+
+/// Build the hotspot from the detections of one cluster
+///
+fn hotspot(site: &Site, slot: &str, points: &[&Detection]) -> Hotspot {
+    let journeys = points.iter().map(|d| d.journey).collect::<BTreeSet<_>>();
+    let days = points
+        .iter()
+        .map(|d| d.time / 86_400)
+        .collect::<BTreeSet<_>>();
+    let time = |t: Option<u32>| DateTime::from_timestamp(t.unwrap_or_default() as i64, 0);
+
+    let n = points.len() as f64;
+    let centre = (
+        coord(points.iter().map(|d| d.lon).sum::<f64>() / n),
+        coord(points.iter().map(|d| d.lat).sum::<f64>() / n),
+    );
+    let hull = MultiPoint::from(points.iter().map(|d| (d.lon, d.lat)).collect::<Vec<_>>())
+        .convex_hull()
+        .exterior()
+        .coords()
+        .map(|c| (coord(c.x), coord(c.y)))
+        .collect();
+
+    Hotspot {
+        site: site.name.clone(),
+        slot: slot.to_string(),
+        rank: 0,
+        points: points.len(),
+        journeys: journeys.len(),
+        days: days.len(),
+        first: time(points.iter().map(|d| d.time).min()).unwrap_or_default(),
+        last: time(points.iter().map(|d| d.time).max()).unwrap_or_default(),
+        centre,
+        hull,
+    }
+}
+
+/// Cluster the detections of one site, slot by slot, biggest hotspots first.
+///
+#[tracing::instrument(skip(data))]
+fn find_site_hotspots(site: &Site, data: &[Detection], opts: &HotspotOpts) -> Vec<Hotspot> {
+    let mut slots: BTreeMap<String, Vec<&Detection>> = BTreeMap::new();
+    data.iter()
+        .for_each(|d| slots.entry(slot_of(d.time, opts.slot)).or_default().push(d));
+
+    let mut found = vec![];
+    for (slot, list) in slots {
+        let points = list
+            .iter()
+            .map(|d| project(site, d.lon, d.lat))
+            .collect::<Vec<_>>();
+        let labels = dbscan(&points, opts.eps, opts.min_points);
+
+        let mut clusters: BTreeMap<usize, Vec<&Detection>> = BTreeMap::new();
+        labels
+            .iter()
+            .zip(list.iter())
+            .filter_map(|(l, d)| l.map(|l| (l, *d)))
+            .for_each(|(l, d)| clusters.entry(l).or_default().push(d));
+        debug!("{} {slot}: {} clusters", site.name, clusters.len());
+
+        let mut spots = clusters
+            .values()
+            .map(|c| hotspot(site, &slot, c))
+            .filter(|h| h.days >= opts.min_days)
+            .collect::<Vec<_>>();
+        spots.sort_by_key(|h| Reverse(h.points));
+        spots
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, h)| h.rank = i + 1);
+        found.extend(spots);
+    }
+    found
+}
+
+/// Build the GeoJSON `FeatureCollection` of the hotspots
+///
+pub fn hotspots_geojson(list: &[Hotspot]) -> Value {
+    let features = list
+        .iter()
+        .map(|h| {
+            let ring = h
+                .hull
+                .iter()
+                .map(|(x, y)| json!([x, y]))
+                .collect::<Vec<_>>();
+            json!({
+                "type": "Feature",
+                "id": format!("{}-{}-{}", h.site, h.slot, h.rank),
+                "geometry": { "type": "Polygon", "coordinates": [ring] },
+                "properties": {
+                    "site": h.site,
+                    "slot": h.slot,
+                    "rank": h.rank,
+                    "points": h.points,
+                    "journeys": h.journeys,
+                    "days": h.days,
+                    "first": h.first.to_rfc3339(),
+                    "last": h.last.to_rfc3339(),
+                    "centre": [h.centre.0, h.centre.1],
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Sample the detections around one site between `begin` and `end` (excluded).
+///
+#[tracing::instrument(skip(dbh))]
+async fn retrieve_detections(
+    dbh: &Client,
+    site: &Site,
+    begin: &str,
+    end: &str,
+    opts: &HotspotOpts,
+) -> Result<Vec<Detection>> {
+    let r = r##"
+SELECT
+  journey,
+  toUInt32(min(timestamp)) AS time,
+  argMin(longitude, timestamp) AS lon,
+  argMin(latitude, timestamp) AS lat
+FROM drones
+WHERE
+  timestamp >= toDateTime($1) AND timestamp < toDateTime($2) AND
+  pointInEllipses(longitude, latitude, $3, $4, $5, $5)
+GROUP BY journey, intDiv(toUInt32(timestamp), $6)
+"##;
+    let dist = opts.distance * 1.852 / ONE_DEG;
+    let q = QueryBuilder::new(r)
+        .arg(begin)
+        .arg(end)
+        .arg(site.longitude as f64)
+        .arg(site.latitude as f64)
+        .arg(dist)
+        .arg(opts.every.max(1));
+    Ok(dbh.query_collect::<Detection>(q).await?)
+}
+
+/// Handle `analyze hotspots`, returns the number of hotspots found.
+///
+#[tracing::instrument(skip(ctx))]
+pub async fn find_hotspots(ctx: &Context, opts: &HotspotOpts) -> Result<usize> {
+    if opts.slot == 0 || 24 % opts.slot != 0 {
+        return Err(Status::BadSlot(opts.slot).into());
+    }
+
+    let begin = parse_day(&opts.date)?;
+    let end = match &opts.until {
+        Some(until) => parse_day(until)?,
+        None => begin,
+    } + Days::new(1);
+
+    let sites = match &opts.site {
+        Some(name) => vec![find_site(ctx, name).await?],
+        None => {
+            let mut all = BTreeMap::new();
+            for d in expand_interval(begin, end)?.iter() {
+                for site in enumerate_sites(ctx, *d).await? {
+                    all.insert(site.id, site);
+                }
+            }
+            all.into_values().collect()
+        }
+    };
+
+    let day = |d: &DateTime<Utc>| d.format("%Y-%m-%d %H:%M:%S").to_string();
+    let dbh = ctx.db().await;
+
+    let mut list = vec![];
+    for site in sites.iter() {
+        let data = retrieve_detections(&dbh, site, &day(&begin), &day(&end), opts).await?;
+        info!("{}: {} detections", site.name, data.len());
+
+        let found = find_site_hotspots(site, &data, opts);
+        for h in found.iter() {
+            eprintln!(
+                "{} {} #{}: {} points, {} journeys, {} days around ({}, {})",
+                h.site, h.slot, h.rank, h.points, h.journeys, h.days, h.centre.1, h.centre.0
+            );
+        }
+        list.extend(found);
+    }
+
+    let output = match &opts.output {
+        Some(output) => output.clone(),
+        None => format!(
+            "hotspots-{}-{}.geojson",
+            opts.site.as_deref().unwrap_or("all"),
+            begin.format("%Y%m%d")
+        ),
+    };
+    fs::write(&output, serde_json::to_string(&hotspots_geojson(&list))?)?;
+    trace!("{} hotspots written", list.len());
+    eprintln!("Hotspots in {output}");
+
+    Ok(list.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn site() -> Site {
+        Site {
+            id: 1,
+            name: "LUX".to_string(),
+            code: "ELLX".to_string(),
+            basename: "Luxembourg".to_string(),
+            latitude: 49.6,
+            longitude: 6.2,
+            ref_alt: 376.,
+        }
+    }
+
+    fn opts(args: &[&str]) -> HotspotOpts {
+        HotspotOpts::parse_from([&["hotspots"], args].concat())
+    }
+
+    /// `n` detections of `journey` spread over a few meters around (lon, lat) on day `day`
+    ///
+    fn blob(journey: i32, day: u32, hour: u32, lon: f64, lat: f64, n: usize) -> Vec<Detection> {
+        (0..n)
+            .map(|i| Detection {
+                journey,
+                time: day * 86_400 + hour * 3_600 + i as u32 * 10,
+                lon: lon + (i % 5) as f64 * 1e-5,
+                lat: lat + (i / 5) as f64 * 1e-5,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dbscan() {
+        let mut points = (0..20)
+            .map(|i| ((i % 5) as f64 * 10., (i / 5) as f64 * 10.))
+            .collect::<Vec<_>>();
+        points.extend((0..20).map(|i| (1_000. + (i % 5) as f64 * 10., (i / 5) as f64 * 10.)));
+        points.push((500., 500.));
+
+        let labels = dbscan(&points, 25., 5);
+        assert!(labels[..20].iter().all(|l| *l == Some(0)));
+        assert!(labels[20..40].iter().all(|l| *l == Some(1)));
+        assert_eq!(None, labels[40]);
+    }
+
+    #[rstest]
+    #[case(1, 2)]
+    #[case(2, 1)]
+    #[case(3, 0)]
+    fn test_dbscan_min_points(#[case] min_points: usize, #[case] clusters: usize) {
+        // Two points close to each other, one alone
+        //
+        let points = [(0., 0.), (10., 0.), (1_000., 1_000.)];
+        let labels = dbscan(&points, 200., min_points);
+        let found = labels.iter().flatten().collect::<BTreeSet<_>>();
+        assert_eq!(clusters, found.len());
+    }
+
+    #[rstest]
+    #[case(0, 6, "00-06")]
+    #[case(3_600 * 7, 6, "06-12")]
+    #[case(3_600 * 23 + 59, 6, "18-24")]
+    #[case(3_600 * 13, 1, "13-14")]
+    #[case(86_400 + 3_600 * 13, 24, "00-24")]
+    fn test_slot_of(#[case] time: u32, #[case] slot: u32, #[case] label: &str) {
+        assert_eq!(label, slot_of(time, slot));
+    }
+
+    #[test]
+    fn test_find_site_hotspots() {
+        let site = site();
+        let mut data = vec![];
+        data.extend(blob(1, 100, 8, 6.25, 49.62, 20));
+        data.extend(blob(2, 101, 9, 6.25, 49.62, 20));
+        data.extend(blob(3, 100, 8, 6.10, 49.55, 15));
+        data.extend(blob(4, 100, 20, 6.10, 49.55, 15));
+
+        let all = opts(&["--eps", "50", "--min-points", "5"]);
+        let found = find_site_hotspots(&site, &data, &all);
+        assert_eq!(3, found.len());
+
+        let first = &found[0];
+        assert_eq!(
+            ("06-12", 1, 40, 2, 2),
+            (
+                first.slot.as_str(),
+                first.rank,
+                first.points,
+                first.journeys,
+                first.days
+            )
+        );
+        assert_eq!(first.hull.first(), first.hull.last());
+        assert!((first.centre.0 - 6.25002).abs() < 1e-4);
+        assert_eq!(2, found[1].rank);
+        assert_eq!("18-24", found[2].slot);
+
+        // Only recurring
+        //
+        let recurring = opts(&["--eps", "50", "--min-points", "5", "--min-days", "2"]);
+        let found = find_site_hotspots(&site, &data, &recurring);
+        assert_eq!(1, found.len());
+        assert_eq!(40, found[0].points);
+    }
+
+    #[test]
+    fn test_hotspots_geojson() {
+        let site = site();
+        let data = blob(1, 100, 8, 6.25, 49.62, 20);
+        let found = find_site_hotspots(&site, &data, &opts(&["--min-points", "5"]));
+
+        let geo = hotspots_geojson(&found);
+        assert_eq!("FeatureCollection", geo["type"]);
+        let f = &geo["features"][0];
+        assert_eq!("LUX-06-12-1", f["id"]);
+        assert_eq!("Polygon", f["geometry"]["type"]);
+        let ring = f["geometry"]["coordinates"][0].as_array().unwrap();
+        assert_eq!(ring.first(), ring.last());
+        assert!(ring.contains(&json!([6.25, 49.62])));
+        assert_eq!(20, f["properties"]["points"]);
+        assert_eq!("1970-04-11T08:00:00+00:00", f["properties"]["first"]);
+    }
+}
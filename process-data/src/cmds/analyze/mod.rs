@@ -0,0 +1,21 @@
+//! This is the `analyze` command module
+//!
+
+use clap::Parser;
+
+pub use hotspots::*;
+
+mod hotspots;
+
+#[derive(Debug, Parser)]
+pub struct AnalyzeOpts {
+    #[clap(subcommand)]
+    pub subcmd: AnalyzeSubCommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum AnalyzeSubCommand {
+    /// Cluster drone detections into recurring operating areas
+    #[clap(visible_alias = "hot")]
+    Hotspots(HotspotOpts),
+}
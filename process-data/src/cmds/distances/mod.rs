@@ -3,8 +3,10 @@ use std::fmt::Debug;
 use clap::Parser;
 
 pub use planes::*;
+pub use score::*;
 
 mod planes;
+mod score;
 
 #[derive(Debug, Parser)]
 pub(crate) struct DistOpts {
@@ -20,6 +22,8 @@ pub(crate) struct DistOpts {
 pub(crate) enum DistSubcommand {
     /// drone to planes distance
     Planes(PlanesOpts),
+    /// (re)classify encounters into severity bands
+    Score(ScoreOpts),
 }
 
 // -----
@@ -2,7 +2,9 @@
 //!
 //! XXX CH does not have the SQL sequences so we need to generate the en_id field ourselves
 //!
-use crate::cmds::{Calculate, PlaneDistance, PlanesStats, Stats, TempTables, ONE_DEG};
+use crate::cmds::{
+    score_encounters, Calculate, PlaneDistance, PlanesStats, Stats, TempTables, ONE_DEG,
+};
 use eyre::Result;
 use futures::future::try_join_all;
 use klickhouse::{Client, QueryBuilder, RawRow, Row};
@@ -408,7 +410,8 @@ CREATE OR REPLACE TABLE ids{tag} (
       CEIL(dist_drone_plane) AS distance_slant_m,
       any_value(CEIL(dist2d)) AS distance_hor_m,
       any_value(CEIL(ABS(palt - dalt))) AS distance_vert_m,
-      any_value(CEIL(hdist2d)) as distance_home_m
+      any_value(CEIL(hdist2d)) as distance_home_m,
+      '' AS severity
     FROM today_close{tag} AS tc JOIN ids{tag} AS id
       ON id.journey = tc.journey AND id.callsign = tc.callsign
     WHERE
@@ -575,6 +578,13 @@ impl Calculate for PlaneDistance {
         bar.message(format!("{} encounters.", c_encounters));
         sleep(Duration::from_millis(self.wait)).await;
 
+        // Classify today's encounters
+        //
+        bar.message("Score encounters.");
+        let pattern = format!("{}-{}-%", self.site.name, self.date.format("%Y%m%d"));
+        let c_scored = score_encounters(dbh, &self.rubric, &pattern).await?;
+        info!("{} encounters classified.", c_scored);
+
         info!("Stats for {}\n{}", self.date, stats);
         bar.message("Done.");
         bar.finish();
@@ -15,7 +15,9 @@ use tracing::{info, trace};
 
 use fetiche_common::{expand_interval, normalise_day, DateOpts};
 
-use crate::cmds::{enumerate_sites, find_site, Calculate, PlanesStats, Site, Stats};
+use crate::cmds::{
+    add_severity_column, enumerate_sites, find_site, Calculate, PlanesStats, Rubric, Site, Stats,
+};
 use crate::config::Context;
 use crate::error::Status;
 
@@ -36,6 +38,9 @@ pub struct PlanesOpts {
     /// Proximity in Meters.
     #[clap(short = 'p', long, default_value = "5500.")]
     pub separation: f64,
+    /// Severity rubric file (default is the built-in one).
+    #[clap(short = 'r', long)]
+    pub rubric: Option<String>,
 }
 
 // -----
@@ -62,6 +67,9 @@ pub struct PlaneDistance {
     /// Lon of antenna
     #[builder]
     pub lon: f64,
+    /// Rubric used to classify encounters
+    #[builder(default)]
+    pub rubric: Rubric,
     /// List of temporary tables created along the way, for cleanup.
     #[builder(default = "vec![]")]
     state: Vec<TempTables>,
@@ -109,6 +117,11 @@ pub async fn planes_calculation(ctx: &Context, opts: &PlanesOpts) -> Result<Stat
         }
     };
 
+    // Load the rubric once, it is checked before any calculation.
+    //
+    let rubric = Rubric::load(opts.rubric.as_deref())?;
+    add_severity_column(&ctx.db().await).await?;
+
     let dates = expand_interval(begin, end)?;
     eprintln!("{} days to process, from {begin} to {end}", dates.len());
 
@@ -169,19 +182,22 @@ pub async fn planes_calculation(ctx: &Context, opts: &PlanesOpts) -> Result<Stat
     for batch in &work_list.into_iter().chunks(ctx.pool_size) {
         let stats: Vec<_> = batch
             .into_iter()
-            .map(|(day, site)| async move {
-                trace!("Calculate for site {site} on day {day}");
-                let site = site.clone();
-                let ctx = ctx.clone();
-
-                let r = tokio::spawn(async move {
-                    calculate_one_day_on_site(&ctx, &site, &day, distance, separation)
-                        .await
-                        .unwrap()
-                })
-                .await
-                .unwrap();
-                r
+            .map(|(day, site)| {
+                let rubric = rubric.clone();
+                async move {
+                    trace!("Calculate for site {site} on day {day}");
+                    let site = site.clone();
+                    let ctx = ctx.clone();
+
+                    let r = tokio::spawn(async move {
+                        calculate_one_day_on_site(&ctx, &site, &day, distance, separation, rubric)
+                            .await
+                            .unwrap()
+                    })
+                    .await
+                    .unwrap();
+                    r
+                }
             })
             .collect();
         let stats: Vec<_> = join_all(stats).await;
@@ -207,6 +223,7 @@ async fn calculate_one_day_on_site(
     day: &DateTime<Utc>,
     distance: f64,
     separation: f64,
+    rubric: Rubric,
 ) -> Result<Stats> {
     let dbh = ctx
         .dbh
@@ -223,6 +240,7 @@ async fn calculate_one_day_on_site(
         .distance(distance)
         .date(day)
         .separation(separation)
+        .rubric(rubric)
         .wait(ctx.wait)
         .build()?;
 
@@ -0,0 +1,35 @@
+// Default severity rubric for drone/plane encounters.
+//
+// Bands are checked in name order and the first one whose conditions all hold is used, an
+// encounter matching none is left unclassified.  All conditions are optional:
+//
+// - max_hor_m      horizontal separation at the closest point is at most this (m)
+// - max_vert_m     vertical separation at the closest point is at most this (m)
+// - min_closure_ms closure rate before the closest point is at least this (m/s)
+// - min_duration_s the encounter lasted at least this (s)
+//
+version = 1
+
+band "A" {
+  description = "Serious risk of collision"
+  max_hor_m   = 150
+  max_vert_m  = 30
+}
+
+band "B" {
+  description = "Safety not assured"
+  max_hor_m   = 500
+  max_vert_m  = 100
+}
+
+band "C" {
+  description = "Close proximity, fast closure or lasting"
+  max_hor_m      = 1000
+  max_vert_m     = 300
+  min_closure_ms = 20
+}
+
+band "D" {
+  description = "No risk of collision"
+  max_hor_m   = 1852
+}
@@ -0,0 +1,257 @@
+//! Scoring of the encounters found by the `planes` calculation.
+//!
+//! Each encounter (all the points of an `en_id` in `airplane_prox`) is summarised into a few
+//! metrics (separation at the closest point, closure rate and duration) which are classified
+//! into severity bands using a rubric.  The band name is stored in the `severity` column.
+//!
+//! The default rubric is in `rubric.hcl`, another one can be given with `--rubric`.
+//!
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::Parser;
+use eyre::Result;
+use klickhouse::{Client, QueryBuilder, Row};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace};
+
+use crate::config::Context;
+use crate::error::Status;
+
+/// Current version of the rubric file
+const RVERSION: usize = 1;
+
+/// Options for `distances score`
+///
+#[derive(Clone, Debug, Parser)]
+pub struct ScoreOpts {
+    /// Rubric file (default is the built-in one).
+    #[clap(short = 'r', long)]
+    pub rubric: Option<String>,
+    /// Only this day (YYYYMMDD), default is all encounters.
+    #[clap(short = 'D', long)]
+    pub day: Option<String>,
+}
+
+/// One severity band, every condition present must hold.
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Band {
+    pub description: String,
+    pub max_hor_m: Option<i32>,
+    pub max_vert_m: Option<i32>,
+    pub min_closure_ms: Option<f64>,
+    pub min_duration_s: Option<i64>,
+}
+
+impl Band {
+    /// Does this encounter fall into this band?
+    ///
+    pub fn matches(&self, m: &Metrics) -> bool {
+        self.max_hor_m.is_none_or(|v| m.hor <= v)
+            && self.max_vert_m.is_none_or(|v| m.vert <= v)
+            && self.min_closure_ms.is_none_or(|v| m.closure_rate() >= v)
+            && self.min_duration_s.is_none_or(|v| m.duration >= v)
+    }
+}
+
+/// The rubric itself, bands are checked in name order.
+///
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rubric {
+    pub version: usize,
+    pub band: BTreeMap<String, Band>,
+}
+
+impl Default for Rubric {
+    /// Built-in rubric
+    ///
+    fn default() -> Self {
+        Rubric::from_str(include_str!("rubric.hcl")).unwrap()
+    }
+}
+
+impl FromStr for Rubric {
+    type Err = eyre::Error;
+
+    /// Parse and check a rubric.
+    ///
+    fn from_str(s: &str) -> Result<Self> {
+        let r: Rubric = hcl::from_str(s).map_err(|e| Status::BadRubric(e.to_string()))?;
+        if r.version != RVERSION {
+            return Err(Status::BadFileVersion(r.version).into());
+        }
+        if r.band.is_empty() {
+            return Err(Status::BadRubric("no band defined".to_string()).into());
+        }
+        Ok(r)
+    }
+}
+
+impl Rubric {
+    /// Load the rubric from `fname` or use the built-in one.
+    ///
+    #[tracing::instrument]
+    pub fn load(fname: Option<&str>) -> Result<Self> {
+        match fname {
+            Some(fname) => Rubric::from_str(&fs::read_to_string(Path::new(fname))?),
+            None => Ok(Rubric::default()),
+        }
+    }
+
+    /// Return the name of the first matching band, if any.
+    ///
+    pub fn classify(&self, m: &Metrics) -> Option<&str> {
+        self.band
+            .iter()
+            .find(|(_, b)| b.matches(m))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Summary of one encounter.
+///
+#[derive(Clone, Debug, Default, Deserialize, Row, Serialize)]
+pub struct Metrics {
+    pub en_id: String,
+    /// Horizontal separation at the closest point (m)
+    pub hor: i32,
+    /// Vertical separation at the closest point (m)
+    pub vert: i32,
+    /// Slant distance at the closest point (m)
+    pub slant: i32,
+    /// Slant distance at the first point (m)
+    pub first_dist: i32,
+    /// Time from the first to the closest point (s)
+    pub to_cpa: i64,
+    /// Duration of the encounter (s)
+    pub duration: i64,
+}
+
+impl Metrics {
+    /// Closure rate in m/s between the first point and the closest one.
+    ///
+    pub fn closure_rate(&self) -> f64 {
+        if self.to_cpa <= 0 {
+            return 0.;
+        }
+        (self.first_dist - self.slant) as f64 / self.to_cpa as f64
+    }
+}
+
+/// Add the `severity` column to tables created before it existed.
+///
+#[tracing::instrument(skip(dbh))]
+pub async fn add_severity_column(dbh: &Client) -> Result<()> {
+    let r = "ALTER TABLE airplane_prox ADD COLUMN IF NOT EXISTS severity VARCHAR DEFAULT ''";
+    Ok(dbh.execute(r).await?)
+}
+
+/// Compute the metrics of all encounters whose `en_id` matches `pattern`, classify them and store
+/// the severity.  Returns the number of classified encounters.
+///
+#[tracing::instrument(skip(dbh, rubric))]
+pub async fn score_encounters(dbh: &Client, rubric: &Rubric, pattern: &str) -> Result<usize> {
+    trace!("score_encounters");
+
+    let r = r##"
+SELECT
+  en_id,
+  argMin(distance_hor_m, distance_slant_m) AS hor,
+  argMin(distance_vert_m, distance_slant_m) AS vert,
+  min(distance_slant_m) AS slant,
+  argMin(distance_slant_m, time) AS first_dist,
+  dateDiff('second', min(time), argMin(time, distance_slant_m)) AS to_cpa,
+  dateDiff('second', min(time), max(time)) AS duration
+FROM airplane_prox
+WHERE en_id LIKE $1
+GROUP BY en_id
+"##;
+    let q = QueryBuilder::new(r).arg(pattern);
+    let all = dbh.query_collect::<Metrics>(q).await?;
+    debug!("{} encounters to score", all.len());
+
+    // Group by band to have one update per band.
+    //
+    let mut bands: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for m in all.iter() {
+        if let Some(band) = rubric.classify(m) {
+            bands.entry(band).or_default().push(m.en_id.clone());
+        }
+    }
+
+    let mut count = 0;
+    for (band, ids) in bands.iter() {
+        info!("{} encounters in band {}", ids.len(), band);
+        count += ids.len();
+
+        let q = QueryBuilder::new(
+            "ALTER TABLE airplane_prox UPDATE severity = $1 WHERE has($2, en_id)",
+        )
+        .arg(band.to_string())
+        .arg(ids.clone());
+        dbh.execute(q).await?;
+    }
+    Ok(count)
+}
+
+/// Handle `distances score`, (re)classify existing encounters.
+///
+#[tracing::instrument(skip(ctx))]
+pub async fn score_calculation(ctx: &Context, opts: &ScoreOpts) -> Result<usize> {
+    let rubric = Rubric::load(opts.rubric.as_deref())?;
+    let dbh = ctx.db().await;
+
+    let pattern = match &opts.day {
+        Some(day) => format!("%-{day}-%"),
+        None => "%".to_string(),
+    };
+
+    add_severity_column(&dbh).await?;
+    score_encounters(&dbh, &rubric, &pattern).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn metrics(hor: i32, vert: i32, first: i32, to_cpa: i64) -> Metrics {
+        Metrics {
+            hor,
+            vert,
+            slant: hor.max(vert),
+            first_dist: first,
+            to_cpa,
+            duration: to_cpa,
+            ..Metrics::default()
+        }
+    }
+
+    #[test]
+    fn test_default_rubric() {
+        let r = Rubric::default();
+        assert_eq!(4, r.band.len());
+        assert_eq!("Serious risk of collision", r.band["A"].description);
+    }
+
+    #[rstest]
+    #[case(metrics(100, 20, 100, 0), Some("A"))]
+    #[case(metrics(100, 80, 100, 0), Some("B"))]
+    #[case(metrics(800, 200, 1800, 10), Some("C"))]
+    #[case(metrics(800, 200, 900, 10), Some("D"))]
+    #[case(metrics(1900, 200, 1900, 10), None)]
+    fn test_classify(#[case] m: Metrics, #[case] band: Option<&str>) {
+        assert_eq!(band, Rubric::default().classify(&m));
+    }
+
+    #[test]
+    fn test_bad_rubric() {
+        assert!(Rubric::from_str("version = 1\n").is_err());
+        assert!(Rubric::from_str("version = 2\nband \"A\" {\n  description = \"x\"\n}\n").is_err());
+    }
+}
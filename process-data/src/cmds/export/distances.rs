@@ -1,6 +1,12 @@
 //! Export the distances calculated by the `distances` module.
 //!
+//! Encounters can be exported as flat CSV (one line per drone point and plane point), Parquet
+//! with the same columns or GeoJSON: one `FeatureCollection` with, for every encounter, the
+//! drone and plane paths as `LineString` and the closest point as a `Point` carrying all the
+//! columns of that record.
+//!
 
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::fs;
 
@@ -10,11 +16,14 @@ use datafusion::config::TableParquetOptions;
 use datafusion::dataframe::DataFrameWriteOptions;
 use datafusion::prelude::{CsvReadOptions, SessionContext};
 use eyre::Result;
-use klickhouse::{Client, DateTime, Row};
+use klickhouse::{Client, DateTime, QueryBuilder, Row};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tempfile::Builder;
 use tracing::{debug, info, trace};
 
+use fetiche_common::Redaction;
+
 use crate::cmds::Format;
 use crate::config::Context;
 use crate::error::Status;
@@ -30,11 +39,72 @@ pub struct ExpDistOpts {
     /// Output file
     #[clap(short = 'o', long)]
     pub output: Option<String>,
+    /// Only these severity bands (comma-separated, e.g. "A,B").
+    #[clap(short = 's', long, value_delimiter = ',')]
+    pub severity: Vec<String>,
+    /// Sort by severity then distance instead of time.
+    #[clap(short = 'R', long)]
+    pub ranked: bool,
+    /// Redact the output with this policy (see `redact` in process-data.hcl)
+    #[clap(long)]
+    pub redact: Option<String>,
+}
+
+/// Selection, ordering and redaction of the exported encounters
+///
+#[derive(Debug)]
+struct Selection {
+    /// Severity bands to keep, all if empty
+    severity: Vec<String>,
+    /// Ranked list?
+    ranked: bool,
+    /// Redaction policy
+    redact: Option<Redaction>,
+}
+
+impl Selection {
+    /// `WHERE` condition on the `severity` column, using `$1` for the list.
+    ///
+    fn condition(&self) -> &str {
+        if self.severity.is_empty() {
+            "1 = 1"
+        } else {
+            "has($1, severity)"
+        }
+    }
+
+    /// `ORDER BY` clause
+    ///
+    fn order(&self) -> &str {
+        if self.ranked {
+            "severity = '', severity, distance_slant_m"
+        } else {
+            "time"
+        }
+    }
+
+    /// Final CSV, redacted if needed
+    ///
+    fn output(&self, data: String) -> Result<String> {
+        match &self.redact {
+            Some(redact) => redact.csv(&data, b',', true),
+            None => Ok(data),
+        }
+    }
+
+    /// Final GeoJSON, redacted if needed
+    ///
+    fn output_json(&self, mut data: Value) -> Result<String> {
+        if let Some(redact) = &self.redact {
+            redact.apply(&mut data);
+        }
+        Ok(serde_json::to_string(&data)?)
+    }
 }
 
 /// Private struct for extracting data
 ///
-#[derive(Debug, Deserialize, Row, Serialize)]
+#[derive(Debug, Default, Deserialize, Row, Serialize)]
 struct Encounter {
     site: i32,
     en_id: String,
@@ -55,15 +125,17 @@ struct Encounter {
     distance_hor_m: i32,
     distance_vert_m: i32,
     distance_home_m: i32,
+    severity: String,
 }
 
 /// Retrieve all the records in `airplane_prox` table.
 ///
 #[tracing::instrument(skip(client))]
-async fn retrieve_all_encounters(client: &Client) -> Result<Vec<Encounter>> {
+async fn retrieve_all_encounters(client: &Client, sel: &Selection) -> Result<Vec<Encounter>> {
     trace!("retrieving records from airplane_prox");
 
-    let r = r##"
+    let r = format!(
+        r##"
   SELECT
     site,
     en_id,
@@ -84,11 +156,17 @@ async fn retrieve_all_encounters(client: &Client) -> Result<Vec<Encounter>> {
     distance_hor_m,
     distance_vert_m,
     distance_home_m,
+    severity,
   FROM airplane_prox
-  ORDER BY time
-        "##;
-
-    let res = client.query_collect::<Encounter>(r).await?;
+  WHERE {}
+  ORDER BY {}
+        "##,
+        sel.condition(),
+        sel.order()
+    );
+
+    let q = QueryBuilder::new(&r).arg(sel.severity.clone());
+    let res = client.query_collect::<Encounter>(q).await?;
     debug!("retrieved encounters: {:?}", res);
 
     Ok(res)
@@ -97,7 +175,7 @@ async fn retrieve_all_encounters(client: &Client) -> Result<Vec<Encounter>> {
 /// Retrieve the subset summary of all encounters from `airplane_prox`
 ///
 #[tracing::instrument(skip(client))]
-async fn retrieve_summary_encounters(client: &Client) -> Result<Vec<Encounter>> {
+async fn retrieve_summary_encounters(client: &Client, sel: &Selection) -> Result<Vec<Encounter>> {
     trace!("retrieving summary records from airplane_prox");
 
     let r = r##"
@@ -119,7 +197,8 @@ AS (
 
     // Match with airprox_summary for export
     //
-    let r1 = r##"
+    let r1 = format!(
+        r##"
   SELECT *
   FROM
     airplane_prox AS a JOIN airprox_summary AS s
@@ -128,10 +207,15 @@ AS (
         s.journey = a.journey AND
         s.drone_id = a.drone_id
   WHERE
-    a.distance_slant_m = s.distance_slant_m
-  ORDER BY time
-    "##;
-    let summ = client.query_collect::<Encounter>(r1).await?;
+    a.distance_slant_m = s.distance_slant_m AND
+    {}
+  ORDER BY {}
+    "##,
+        sel.condition(),
+        sel.order()
+    );
+    let q = QueryBuilder::new(&r1).arg(sel.severity.clone());
+    let summ = client.query_collect::<Encounter>(q).await?;
     trace!("Summary encounters: {:?}", summ);
     Ok(summ)
 }
@@ -139,10 +223,10 @@ AS (
 /// Write the output of `retrieve_all_encounters()` as a CSV file
 ///
 #[tracing::instrument(skip(client))]
-async fn export_all_encounters_csv(client: &Client, fname: &str) -> Result<()> {
+async fn export_all_encounters_csv(client: &Client, fname: &str, sel: &Selection) -> Result<()> {
     trace!("Exporting all encounters from airplane_prox");
 
-    let data = retrieve_all_encounters(client).await?;
+    let data = retrieve_all_encounters(client, sel).await?;
     let len = data.len();
 
     // Prepare the writer
@@ -159,25 +243,132 @@ async fn export_all_encounters_csv(client: &Client, fname: &str) -> Result<()> {
     // Output final csv
     //
     let data = String::from_utf8(wtr.into_inner()?)?;
-    fs::write(fname, data)?;
+    fs::write(fname, sel.output(data)?)?;
     trace!("Exported {} encounters", len);
 
     Ok(())
 }
 
+/// Coordinate as the `f64` printed like the `f32`, i.e. 49.6 and not 49.599998474121094
+///
+fn coord(x: f32) -> f64 {
+    x.to_string().parse().unwrap_or_default()
+}
+
+/// Build the GeoJSON `FeatureCollection` of `data`, encounters are in `en_id` order.
+///
+/// Paths are ordered by time and only output when they have at least two points (summaries have
+/// only the closest one).  The `kind` property says what each feature is: `drone`, `plane` or
+/// `encounter`.
+///
+fn encounters_geojson(data: &[Encounter]) -> Result<Value> {
+    let mut encounters: BTreeMap<&str, Vec<&Encounter>> = BTreeMap::new();
+    data.iter()
+        .for_each(|rec| encounters.entry(&rec.en_id).or_default().push(rec));
+
+    let mut features = vec![];
+    for (en_id, mut points) in encounters {
+        points.sort_by_key(|rec| rec.time.1);
+
+        if points.len() > 1 {
+            let drone = points
+                .iter()
+                .map(|r| json!([coord(r.drone_lon), coord(r.drone_lat), coord(r.drone_alt_m)]))
+                .collect::<Vec<_>>();
+            let plane = points
+                .iter()
+                .map(|r| json!([coord(r.prox_lon), coord(r.prox_lat), coord(r.prox_alt_m)]))
+                .collect::<Vec<_>>();
+            let first = points[0];
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": drone },
+                "properties": {
+                    "kind": "drone",
+                    "en_id": en_id,
+                    "journey": first.journey,
+                    "drone_id": first.drone_id,
+                    "model": first.model,
+                },
+            }));
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": plane },
+                "properties": {
+                    "kind": "plane",
+                    "en_id": en_id,
+                    "prox_callsign": first.prox_callsign,
+                    "prox_id": first.prox_id,
+                },
+            }));
+        }
+
+        // Closest point, with all columns.  Going through the JSON text keeps the `f32` as is.
+        //
+        let closest = points
+            .iter()
+            .min_by_key(|r| r.distance_slant_m)
+            .expect("encounter without points");
+        let mut properties: Value = serde_json::from_str(&serde_json::to_string(closest)?)?;
+        properties["kind"] = json!("encounter");
+        features.push(json!({
+            "type": "Feature",
+            "id": en_id,
+            "geometry": {
+                "type": "Point",
+                "coordinates": [
+                    coord(closest.drone_lon),
+                    coord(closest.drone_lat),
+                    coord(closest.drone_alt_m),
+                ],
+            },
+            "properties": properties,
+        }));
+    }
+    Ok(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+/// Write the encounters (all or only the summary) as a GeoJSON file
+///
+#[tracing::instrument(skip(client))]
+async fn export_encounters_geojson(
+    client: &Client,
+    fname: &str,
+    sel: &Selection,
+    summary: bool,
+) -> Result<()> {
+    trace!("Exporting encounters as GeoJSON");
+
+    let data = if summary {
+        retrieve_summary_encounters(client, sel).await?
+    } else {
+        retrieve_all_encounters(client, sel).await?
+    };
+    let len = data.len();
+
+    fs::write(fname, sel.output_json(encounters_geojson(&data)?)?)?;
+    trace!("Exported {} encounter points", len);
+
+    Ok(())
+}
+
 /// For each considered drone point, export the list of encounters i.e. planes around 1 nm radius
 /// Same as previous but export as a Parquet file.  Due to the way DataFrames are handled in
 /// Datafusion, it is easier to generate the CSV and use it to generate a parquet file.
 ///
 #[tracing::instrument(skip(client))]
-async fn export_all_encounters_parquet(client: &Client, fname: &str) -> Result<()> {
+async fn export_all_encounters_parquet(
+    client: &Client,
+    fname: &str,
+    sel: &Selection,
+) -> Result<()> {
     let csv = Builder::new().suffix(".csv").tempfile()?;
     let tmpname = csv.path().to_string_lossy().to_string();
     trace!("Creating and saving CSV into {tmpname}");
 
     // Generate the csv file as `tmpname`
     //
-    export_all_encounters_csv(client, &tmpname).await?;
+    export_all_encounters_csv(client, &tmpname, sel).await?;
 
     let ctx = SessionContext::new();
     let df = ctx
@@ -203,10 +394,14 @@ async fn export_all_encounters_parquet(client: &Client, fname: &str) -> Result<(
 /// This is for extracting a summary of encounters for a single day for any given site.
 ///
 #[tracing::instrument(skip(dbh))]
-async fn export_all_encounters_summary_csv(dbh: &Client, fname: &str) -> eyre::Result<()> {
+async fn export_all_encounters_summary_csv(
+    dbh: &Client,
+    fname: &str,
+    sel: &Selection,
+) -> eyre::Result<()> {
     // Create a temp file with all min distances
     //
-    let data = retrieve_summary_encounters(dbh).await?;
+    let data = retrieve_summary_encounters(dbh, sel).await?;
     let len = data.len();
 
     // Prepare the writer
@@ -222,7 +417,7 @@ async fn export_all_encounters_summary_csv(dbh: &Client, fname: &str) -> eyre::R
     // Output final csv
     //
     let data = String::from_utf8(wtr.into_inner()?)?;
-    fs::write(fname, data)?;
+    fs::write(fname, sel.output(data)?)?;
     trace!("Exported {} encounters", len);
 
     Ok(())
@@ -234,20 +429,40 @@ async fn export_all_encounters_summary_csv(dbh: &Client, fname: &str) -> eyre::R
 pub async fn export_results(ctx: &Context, opts: &ExpDistOpts) -> eyre::Result<()> {
     let client = ctx.db().await;
 
+    let redact = match &opts.redact {
+        Some(policy) => Some(ctx.redaction(policy)?),
+        None => None,
+    };
+    let sel = Selection {
+        severity: opts.severity.clone(),
+        ranked: opts.ranked,
+        redact,
+    };
+
     // Do we export as a csv the "encounters of the day"?
     //
     match &opts.output {
         Some(fname) => {
             if opts.summary {
-                export_all_encounters_summary_csv(&client, fname).await?
+                match opts.format {
+                    Format::Geojson => {
+                        export_encounters_geojson(&client, fname, &sel, true).await?
+                    }
+                    _ => export_all_encounters_summary_csv(&client, fname, &sel).await?,
+                }
             } else {
                 match opts.format {
-                    Format::Csv => export_all_encounters_csv(&client, fname).await?,
-                    Format::Parquet => export_all_encounters_parquet(&client, fname).await?,
-                    _ => return {
-                        eprintln!("Unknown format specified.");
-                        Err(Status::UnknownFormat(opts.format.to_string()).into())
-                    },
+                    Format::Csv => export_all_encounters_csv(&client, fname, &sel).await?,
+                    Format::Geojson => {
+                        export_encounters_geojson(&client, fname, &sel, false).await?
+                    }
+                    Format::Parquet => export_all_encounters_parquet(&client, fname, &sel).await?,
+                    _ => {
+                        return {
+                            eprintln!("Unknown format specified.");
+                            Err(Status::UnknownFormat(opts.format.to_string()).into())
+                        }
+                    }
                 }
             };
         }
@@ -260,3 +475,67 @@ pub async fn export_results(ctx: &Context, opts: &ExpDistOpts) -> eyre::Result<(
     info!("Done.");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use klickhouse::Tz;
+
+    use super::*;
+
+    fn point(en_id: &str, time: u32, lat: f32, distance: i32) -> Encounter {
+        Encounter {
+            en_id: en_id.to_string(),
+            time: DateTime(Tz::UTC, time),
+            drone_id: "D1".to_string(),
+            drone_lat: lat,
+            drone_lon: 6.2,
+            drone_alt_m: 120.,
+            prox_callsign: "LGL1".to_string(),
+            prox_lat: lat + 0.01,
+            prox_lon: 6.21,
+            prox_alt_m: 300.,
+            distance_slant_m: distance,
+            severity: "B".to_string(),
+            ..Encounter::default()
+        }
+    }
+
+    #[test]
+    fn test_encounters_geojson() -> Result<()> {
+        let data = vec![
+            point("LUX-1", 20, 49.62, 250),
+            point("LUX-1", 10, 49.6, 180),
+            point("LUX-2", 30, 49.7, 900),
+        ];
+        let geo = encounters_geojson(&data)?;
+        assert_eq!("FeatureCollection", geo["type"]);
+
+        let features = geo["features"].as_array().unwrap();
+        assert_eq!(4, features.len());
+
+        // Paths in time order
+        //
+        assert_eq!("drone", features[0]["properties"]["kind"]);
+        assert_eq!(
+            json!([6.2, 49.6, 120.0]),
+            features[0]["geometry"]["coordinates"][0]
+        );
+        assert_eq!("plane", features[1]["properties"]["kind"]);
+        assert_eq!("LGL1", features[1]["properties"]["prox_callsign"]);
+
+        // Closest point
+        //
+        assert_eq!("LUX-1", features[2]["id"]);
+        assert_eq!("Point", features[2]["geometry"]["type"]);
+        assert_eq!(180, features[2]["properties"]["distance_slant_m"]);
+        assert_eq!(
+            "1970-01-01T00:00:10+00:00",
+            features[2]["properties"]["time"]
+        );
+
+        // Single point, no path
+        //
+        assert_eq!("LUX-2", features[3]["id"]);
+        Ok(())
+    }
+}
@@ -15,6 +15,8 @@ mod drones;
 pub(crate) enum Format {
     /// Classic CSV.
     Csv,
+    /// GeoJSON `FeatureCollection`.
+    Geojson,
     /// Parquet compressed format.
     Parquet,
     /// Text for stdout
@@ -6,23 +6,27 @@ use std::fmt::Debug;
 use tracing::info;
 
 pub use acute::*;
+pub use analyze::*;
 pub use distances::*;
 pub use export::*;
-//pub use import::*;
+pub use report::*;
 pub use setup::*;
 pub use site::*;
 pub use stats::*;
+pub use summaries::*;
 
 use crate::cli::{Opts, SubCommand};
 use crate::config::Context;
 
 mod acute;
+mod analyze;
 mod distances;
 mod export;
-//mod import;
+mod report;
 mod setup;
 mod site;
 mod stats;
+mod summaries;
 
 /// One degree in *kilometers*
 const ONE_DEG: f64 = 40_000. / 360.;
@@ -45,6 +49,20 @@ pub async fn handle_cmds(ctx: &Context, opts: &Opts) -> eyre::Result<()> {
                 let stats = planes_calculation(ctx, popts).await?;
                 eprintln!("Stats:\n{:?}", stats);
             }
+            DistSubcommand::Score(sopts) => {
+                eprintln!("Classify encounters into severity bands.\n");
+
+                let count = score_calculation(ctx, sopts).await?;
+                eprintln!("{} encounters classified.", count);
+            }
+        },
+        SubCommand::Analyze(aopts) => match &aopts.subcmd {
+            AnalyzeSubCommand::Hotspots(hopts) => {
+                eprintln!("Cluster drone detections into operating areas.\n");
+
+                let count = find_hotspots(ctx, hopts).await?;
+                eprintln!("{} hotspots found.", count);
+            }
         },
         SubCommand::Export(eopts) => match &eopts.subcmd {
             ExportSubCommand::Distances(opts) => {
@@ -58,6 +76,11 @@ pub async fn handle_cmds(ctx: &Context, opts: &Opts) -> eyre::Result<()> {
                 export_drone_stats(ctx, opts).await?;
             }
         },
+        SubCommand::Report(ropts) => {
+            eprintln!("Generating report for {}.\n", ropts.site);
+
+            generate_report(ctx, ropts).await?;
+        }
         SubCommand::Setup(sopts) => {
             eprintln!("Setup ACUTE environment in {}.\n", ctx.config["datalake"]);
             setup_acute_environment(ctx, sopts).await?;
@@ -66,6 +89,12 @@ pub async fn handle_cmds(ctx: &Context, opts: &Opts) -> eyre::Result<()> {
             eprintln!("Remove ACUTE environement in {}.\n", ctx.config["datalake"]);
             cleanup_environment(ctx, copts).await?;
         }
+        SubCommand::RefreshSummaries(sopts) => {
+            eprintln!("Refresh summary tables.\n");
+
+            let count = refresh_summaries(ctx, sopts).await?;
+            eprintln!("{} summaries rebuilt.", count);
+        }
         SubCommand::Acute(aopts) => {
             eprintln!("ACUTE specific commands.\n");
             run_acute_cmd(ctx, aopts).await?;
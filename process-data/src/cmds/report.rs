@@ -0,0 +1,370 @@
+//! This is the `report` command module.
+//!
+//! Generate a self-contained HTML report for one site and one day from the encounters stored in
+//! `airplane_prox`: summary tables, the ranked list of encounters and a map (inline SVG) of the
+//! closest points.  With `--pdf`, the HTML file is converted through `wkhtmltopdf`.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use chrono::{DateTime, Days, Utc};
+use clap::Parser;
+use eyre::{eyre, Result};
+use klickhouse::{QueryBuilder, Row};
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace};
+
+use fetiche_common::geo::{bearing, haversine};
+use fetiche_common::normalise_day;
+
+use crate::cmds::{find_site, Site};
+use crate::config::Context;
+
+/// Size of the map in pixels
+const MAP_SIZE: f64 = 600.;
+
+/// One nautical mile in meters
+const ONE_NM: f64 = 1852.;
+
+#[derive(Debug, Parser)]
+pub struct ReportOpts {
+    /// Site name (e.g. "LUX").
+    #[clap(short = 's', long)]
+    pub site: String,
+    /// Day to report on, "today", "yesterday" or any date.
+    #[clap(short = 'D', long, default_value = "yesterday")]
+    pub date: String,
+    /// Output file (default is report-<site>-<YYYYMMDD>.html).
+    #[clap(short = 'o', long)]
+    pub output: Option<String>,
+    /// Also generate a PDF file (needs `wkhtmltopdf`).
+    #[clap(long)]
+    pub pdf: bool,
+}
+
+/// Closest point of one encounter
+///
+#[derive(Clone, Debug, Default, Deserialize, Row, Serialize)]
+pub struct Closest {
+    en_id: String,
+    time: String,
+    drone_id: String,
+    model: String,
+    drone_lat: f32,
+    drone_lon: f32,
+    drone_height_m: f32,
+    prox_callsign: String,
+    prox_id: String,
+    prox_lat: f32,
+    prox_lon: f32,
+    distance_slant_m: i32,
+    distance_hor_m: i32,
+    distance_vert_m: i32,
+    severity: String,
+}
+
+/// Parse the `--date` argument into the start of the day.
+///
+pub(crate) fn parse_day(date: &str) -> Result<DateTime<Utc>> {
+    let now = Utc::now();
+    let day = match date {
+        "today" => now,
+        "yesterday" => now - Days::new(1),
+        _ => dateparser::parse(date).map_err(|e| eyre!("bad date {date}: {e}"))?,
+    };
+    normalise_day(day)
+}
+
+/// Retrieve the closest point of every encounter, ranked by severity then distance.
+///
+#[tracing::instrument(skip(ctx))]
+async fn retrieve_closest(ctx: &Context, pattern: &str) -> Result<Vec<Closest>> {
+    let dbh = ctx.db().await;
+
+    let r = r##"
+SELECT
+  en_id,
+  toString(argMin(time, distance_slant_m)) AS time,
+  any(drone_id) AS drone_id,
+  any(model) AS model,
+  argMin(drone_lat, distance_slant_m) AS drone_lat,
+  argMin(drone_lon, distance_slant_m) AS drone_lon,
+  argMin(drone_height_m, distance_slant_m) AS drone_height_m,
+  any(prox_callsign) AS prox_callsign,
+  any(prox_id) AS prox_id,
+  argMin(prox_lat, distance_slant_m) AS prox_lat,
+  argMin(prox_lon, distance_slant_m) AS prox_lon,
+  min(distance_slant_m) AS distance_slant_m,
+  argMin(distance_hor_m, distance_slant_m) AS distance_hor_m,
+  argMin(distance_vert_m, distance_slant_m) AS distance_vert_m,
+  any(severity) AS severity
+FROM airplane_prox
+WHERE en_id LIKE $1
+GROUP BY en_id
+ORDER BY severity = '', severity, distance_slant_m
+"##;
+    let q = QueryBuilder::new(r).arg(pattern);
+    Ok(dbh.query_collect::<Closest>(q).await?)
+}
+
+/// Escape text for HTML
+///
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Draw the closest points around the site as an SVG map, using an azimuthal equidistant
+/// projection centered on the site.  Circles are drawn every nautical mile.
+///
+pub fn svg_map(site: &Site, list: &[Closest]) -> String {
+    let lat0 = site.latitude as f64;
+    let lon0 = site.longitude as f64;
+
+    // Position in meters relative to the site
+    //
+    let xy = |lat: f32, lon: f32| {
+        let (lat, lon) = (lat as f64, lon as f64);
+        let d = haversine(lat0, lon0, lat, lon);
+        let b = bearing(lat0, lon0, lat, lon).to_radians();
+        (d * b.sin(), d * b.cos())
+    };
+
+    // Scale to fit everything, at least 1 nm
+    //
+    let extent = list
+        .iter()
+        .flat_map(|c| [xy(c.drone_lat, c.drone_lon), xy(c.prox_lat, c.prox_lon)])
+        .map(|(x, y)| x.abs().max(y.abs()))
+        .fold(ONE_NM, f64::max)
+        * 1.1;
+    let half = MAP_SIZE / 2.;
+    let scale = half / extent;
+    let px = |(x, y): (f64, f64)| (half + x * scale, half - y * scale);
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{MAP_SIZE}" height="{MAP_SIZE}" viewBox="0 0 {MAP_SIZE} {MAP_SIZE}">
+<rect width="100%" height="100%" fill="#f4f6f8"/>
+"##
+    );
+    let rings = (extent / ONE_NM).floor() as usize;
+    for n in 1..=rings {
+        let _ = writeln!(
+            svg,
+            r##"<circle cx="{half}" cy="{half}" r="{:.1}" fill="none" stroke="#c0c8d0"/><text x="{half}" y="{:.1}" font-size="10" fill="#8090a0">{n} nm</text>"##,
+            n as f64 * ONE_NM * scale,
+            half - n as f64 * ONE_NM * scale - 2.,
+        );
+    }
+    let _ = writeln!(
+        svg,
+        r##"<rect x="{:.1}" y="{:.1}" width="8" height="8" fill="#333"><title>{}</title></rect>"##,
+        half - 4.,
+        half - 4.,
+        escape(&site.name),
+    );
+    for (i, c) in list.iter().enumerate() {
+        let (dx, dy) = px(xy(c.drone_lat, c.drone_lon));
+        let (ax, ay) = px(xy(c.prox_lat, c.prox_lon));
+        let _ = writeln!(
+            svg,
+            r##"<line x1="{dx:.1}" y1="{dy:.1}" x2="{ax:.1}" y2="{ay:.1}" stroke="#999" stroke-dasharray="3,2"/>
+<circle cx="{dx:.1}" cy="{dy:.1}" r="4" fill="#1f77b4"><title>{} {}</title></circle>
+<circle cx="{ax:.1}" cy="{ay:.1}" r="4" fill="#d62728"><title>{} {}</title></circle>
+<text x="{:.1}" y="{:.1}" font-size="10">{}</text>"##,
+            escape(&c.drone_id),
+            escape(&c.model),
+            escape(&c.prox_callsign),
+            escape(&c.prox_id),
+            dx + 5.,
+            dy - 5.,
+            i + 1,
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Generate the whole HTML page.
+///
+pub fn render_html(site: &Site, day: &DateTime<Utc>, list: &[Closest]) -> String {
+    let day = day.format("%Y-%m-%d");
+
+    // Summary
+    //
+    let mut drones = list.iter().map(|c| &c.drone_id).collect::<Vec<_>>();
+    drones.sort();
+    drones.dedup();
+    let mut planes = list.iter().map(|c| &c.prox_id).collect::<Vec<_>>();
+    planes.sort();
+    planes.dedup();
+    let min = list.iter().map(|c| c.distance_slant_m).min();
+
+    let mut bands: BTreeMap<&str, usize> = BTreeMap::new();
+    list.iter().for_each(|c| {
+        let band = if c.severity.is_empty() {
+            "-"
+        } else {
+            c.severity.as_str()
+        };
+        *bands.entry(band).or_default() += 1;
+    });
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Encounters for {name} on {day}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+table {{ border-collapse: collapse; margin-bottom: 2em; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: right; }}
+th {{ background: #eef; }}
+td.l {{ text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Encounters for {name} ({code}) on {day}</h1>
+<h2>Summary</h2>
+<table>
+<tr><th>Encounters</th><td>{}</td></tr>
+<tr><th>Drones</th><td>{}</td></tr>
+<tr><th>Planes</th><td>{}</td></tr>
+<tr><th>Closest (m)</th><td>{}</td></tr>
+</table>
+<h2>By severity</h2>
+<table>
+<tr><th>Severity</th><th>Encounters</th></tr>
+"##,
+        list.len(),
+        drones.len(),
+        planes.len(),
+        min.map_or("-".to_string(), |m| m.to_string()),
+        name = escape(&site.name),
+        code = escape(&site.code),
+    );
+    for (band, n) in bands.iter() {
+        let _ = writeln!(html, "<tr><td class=\"l\">{band}</td><td>{n}</td></tr>");
+    }
+    html.push_str(
+        r##"</table>
+<h2>Encounters</h2>
+<table>
+<tr><th>#</th><th>Severity</th><th>Time</th><th>Drone</th><th>Model</th><th>Height (m)</th><th>Callsign</th><th>Address</th><th>Slant (m)</th><th>Horizontal (m)</th><th>Vertical (m)</th></tr>
+"##,
+    );
+    for (i, c) in list.iter().enumerate() {
+        let _ = writeln!(
+            html,
+            r##"<tr><td>{}</td><td class="l">{}</td><td class="l">{}</td><td class="l">{}</td><td class="l">{}</td><td>{:.0}</td><td class="l">{}</td><td class="l">{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"##,
+            i + 1,
+            escape(&c.severity),
+            escape(&c.time),
+            escape(&c.drone_id),
+            escape(&c.model),
+            c.drone_height_m,
+            escape(&c.prox_callsign),
+            escape(&c.prox_id),
+            c.distance_slant_m,
+            c.distance_hor_m,
+            c.distance_vert_m,
+        );
+    }
+    html.push_str("</table>\n<h2>Map</h2>\n");
+    html.push_str(&svg_map(site, list));
+    let _ = write!(
+        html,
+        "<p>Drones in blue, planes in red, at the closest point.  Generated by {} v{}.</p>\n</body>\n</html>\n",
+        crate::NAME,
+        crate::VERSION
+    );
+    html
+}
+
+/// Handle `report`.
+///
+#[tracing::instrument(skip(ctx))]
+pub async fn generate_report(ctx: &Context, opts: &ReportOpts) -> Result<()> {
+    let site = find_site(ctx, &opts.site).await?;
+    let day = parse_day(&opts.date)?;
+    trace!("report for {} on {}", site.name, day);
+
+    let pattern = format!("{}-{}-%", site.name, day.format("%Y%m%d"));
+    let list = retrieve_closest(ctx, &pattern).await?;
+    info!("{} encounters", list.len());
+
+    let output = match &opts.output {
+        Some(output) => output.clone(),
+        None => format!("report-{}-{}.html", site.name, day.format("%Y%m%d")),
+    };
+    fs::write(&output, render_html(&site, &day, &list))?;
+    eprintln!("Report in {output}");
+
+    if opts.pdf {
+        let pdf = Path::new(&output).with_extension("pdf");
+        let status = Command::new("wkhtmltopdf")
+            .arg("--quiet")
+            .arg(&output)
+            .arg(&pdf)
+            .status()
+            .map_err(|e| eyre!("can not run wkhtmltopdf: {e}"))?;
+        if !status.success() {
+            return Err(eyre!("wkhtmltopdf failed: {status}"));
+        }
+        eprintln!("PDF in {}", pdf.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn site() -> Site {
+        Site {
+            id: 1,
+            name: "LUX".to_string(),
+            code: "ELLX".to_string(),
+            basename: "Luxembourg".to_string(),
+            latitude: 49.6,
+            longitude: 6.2,
+            ref_alt: 300.,
+        }
+    }
+
+    #[test]
+    fn test_render_html() {
+        let list = vec![Closest {
+            en_id: "LUX-20241010-1-0".to_string(),
+            drone_id: "D<1>".to_string(),
+            drone_lat: 49.601,
+            drone_lon: 6.201,
+            prox_callsign: "LGL1".to_string(),
+            prox_lat: 49.602,
+            prox_lon: 6.202,
+            distance_slant_m: 180,
+            severity: "B".to_string(),
+            ..Closest::default()
+        }];
+        let day = Utc.with_ymd_and_hms(2024, 10, 10, 0, 0, 0).unwrap();
+        let html = render_html(&site(), &day, &list);
+
+        assert!(html.contains("<h1>Encounters for LUX (ELLX) on 2024-10-10</h1>"));
+        assert!(html.contains("<tr><th>Closest (m)</th><td>180</td></tr>"));
+        assert!(html.contains("D&lt;1&gt;"));
+        assert!(html.contains("<svg"));
+    }
+}
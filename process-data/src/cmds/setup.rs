@@ -98,6 +98,7 @@ OR REPLACE TABLE acute.airplane_prox (
   distance_hor_m   INT,
   distance_vert_m  INT,
   distance_home_m  INT,
+  severity         VARCHAR DEFAULT '',
 )
     ENGINE = ReplacingMergeTree PRIMARY KEY (time, journey)
     COMMENT 'Store all plane-drone encounters with less then 1nm distance.';
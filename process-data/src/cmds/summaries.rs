@@ -0,0 +1,426 @@
+//! This is the `refresh-summaries` command module.
+//!
+//! Dashboards read small per-day and per-site aggregate tables instead of scanning the raw data:
+//!
+//! - `summary_flights`: flights (aircraft address and callsign), aircraft and positions from
+//!   `airplanes`,
+//! - `summary_drones`: journeys, drones and positions from `drones` around the site
+//!   (`--distance`, 70 nm by default like `distances planes`),
+//! - `summary_encounters`: encounters, by severity band, from `airplane_prox`.
+//!
+//! Every row built is recorded in `summary_sources` with the number of source rows it was built
+//! from.  On the next run, a summary is only rebuilt for a day and a site when that number has
+//! changed, i.e. when data arrived late or was re-imported (or with `--force`).  Tables are
+//! `ReplacingMergeTree`, a rebuilt row replaces the old one, use `FINAL` to read them.
+//!
+//! >NOTE: THIS IS CLICKHOUSE-SPECIFIC
+//!
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Days, Utc};
+use clap::Parser;
+use eyre::Result;
+use klickhouse::{Client, QueryBuilder, RawRow, Row};
+use serde::{Deserialize, Serialize};
+use strum::{EnumIter, EnumString, IntoEnumIterator, VariantNames};
+use tracing::{debug, info, trace};
+
+use fetiche_common::expand_interval;
+
+use crate::cmds::{enumerate_sites, find_site, parse_day, Site, ONE_DEG};
+use crate::config::Context;
+
+/// Options for `refresh-summaries`
+///
+#[derive(Debug, Parser)]
+pub struct SummaryOpts {
+    /// First day, "today", "yesterday" or any date.
+    #[clap(short = 'D', long, default_value = "yesterday")]
+    pub date: String,
+    /// Last day (default is `--date`).
+    #[clap(short = 'U', long)]
+    pub until: Option<String>,
+    /// Only this site (default is every site with an installation that day).
+    #[clap(short = 's', long)]
+    pub site: Option<String>,
+    /// Only these summaries (comma-separated: flights, drones, encounters), default is all.
+    #[clap(short = 'S', long, value_delimiter = ',')]
+    pub only: Vec<Summary>,
+    /// Rebuild even if the sources did not change.
+    #[clap(short = 'f', long)]
+    pub force: bool,
+    /// Distance around the site in Nautical Miles, for drones.
+    #[clap(long, default_value = "70.")]
+    pub distance: f64,
+}
+
+/// The aggregate tables
+///
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    EnumIter,
+    EnumString,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    VariantNames,
+    strum::Display,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum Summary {
+    /// Flights, aircraft and positions
+    Flights,
+    /// Journeys, drones and positions
+    Drones,
+    /// Encounters by severity
+    Encounters,
+}
+
+/// Arguments of all the queries below:
+///
+/// - $1 = day (YYYY-MM-DD)
+/// - $2 = site ID
+/// - $3 = lon of site
+/// - $4 = lat of site
+/// - $5 = distance in degrees
+/// - $6 = site name (`en_id` starts with it)
+///
+impl Summary {
+    /// Table name
+    ///
+    pub fn table(&self) -> String {
+        format!("summary_{self}")
+    }
+
+    /// Create the table if needed
+    ///
+    fn create(&self) -> String {
+        let columns = match self {
+            Summary::Flights => {
+                r##"
+  flights      UInt32,
+  aircraft     UInt32,
+  points       UInt64,"##
+            }
+            Summary::Drones => {
+                r##"
+  journeys     UInt32,
+  drones       UInt32,
+  points       UInt64,"##
+            }
+            Summary::Encounters => {
+                r##"
+  encounters   UInt32,
+  severity     Map(String, UInt32),"##
+            }
+        };
+        format!(
+            r##"
+CREATE TABLE IF NOT EXISTS acute.{} (
+  day          Date,
+  site         INT,{columns}
+  refreshed_at DateTime
+)
+    ENGINE = ReplacingMergeTree(refreshed_at)
+    PARTITION BY toYYYYMM(day)
+    ORDER BY (day, site)
+    COMMENT 'Daily {self} per site, see process-data refresh-summaries.'
+"##,
+            self.table()
+        )
+    }
+
+    /// Number of source rows for one day and one site
+    ///
+    fn sources(&self) -> &'static str {
+        match self {
+            Summary::Flights => {
+                r##"
+SELECT count()
+FROM airplanes
+WHERE
+  site = $2 AND
+  toStartOfInterval(time, toIntervalDay(1)) = toDateTime($1)
+"##
+            }
+            Summary::Drones => {
+                r##"
+SELECT count()
+FROM drones
+WHERE
+  toStartOfInterval(timestamp, toIntervalDay(1)) = toDateTime($1) AND
+  pointInEllipses(longitude, latitude, $3, $4, $5, $5)
+"##
+            }
+            Summary::Encounters => {
+                r##"
+SELECT count()
+FROM airplane_prox
+WHERE
+  en_id LIKE concat($6, '-', formatDateTime(toDate($1), '%Y%m%d'), '-%')
+"##
+            }
+        }
+    }
+
+    /// Build the row for one day and one site
+    ///
+    fn refresh(&self) -> String {
+        let select = match self {
+            Summary::Flights => {
+                r##"
+  uniqExact(prox_id, prox_callsign),
+  uniqExact(prox_id),
+  count(),
+  now()
+FROM airplanes
+WHERE
+  site = $2 AND
+  toStartOfInterval(time, toIntervalDay(1)) = toDateTime($1)
+"##
+            }
+            Summary::Drones => {
+                r##"
+  uniqExact(journey),
+  uniqExact(ident),
+  count(),
+  now()
+FROM drones
+WHERE
+  toStartOfInterval(timestamp, toIntervalDay(1)) = toDateTime($1) AND
+  pointInEllipses(longitude, latitude, $3, $4, $5, $5)
+"##
+            }
+            Summary::Encounters => {
+                r##"
+  count(),
+  sumMap(map(severity, toUInt32(1))),
+  now()
+FROM
+(
+  SELECT en_id, any(severity) AS severity
+  FROM airplane_prox
+  WHERE
+    en_id LIKE concat($6, '-', formatDateTime(toDate($1), '%Y%m%d'), '-%')
+  GROUP BY en_id
+)
+"##
+            }
+        };
+        format!(
+            "INSERT INTO acute.{}\nSELECT\n  toDate($1),\n  $2,{select}",
+            self.table()
+        )
+    }
+}
+
+/// Table recording what every summary row was built from
+///
+const SOURCES_TABLE: &str = r##"
+CREATE TABLE IF NOT EXISTS acute.summary_sources (
+  summary      VARCHAR,
+  day          Date,
+  site         INT,
+  source_rows  UInt64,
+  refreshed_at DateTime
+)
+    ENGINE = ReplacingMergeTree(refreshed_at)
+    ORDER BY (summary, day, site)
+    COMMENT 'Number of source rows behind every summary row.'
+"##;
+
+/// One line of `summary_sources`
+///
+#[derive(Clone, Debug, Default, Deserialize, Row, Serialize)]
+struct Source {
+    summary: String,
+    day: String,
+    site: i32,
+    source_rows: u64,
+}
+
+/// Source rows recorded for each summary, day and site
+///
+type Recorded = BTreeMap<(String, String, i32), u64>;
+
+/// Does this summary need to be rebuilt?  Yes if never built or if the number of source rows
+/// changed since.
+///
+pub fn is_stale(recorded: Option<u64>, current: u64) -> bool {
+    recorded != Some(current)
+}
+
+/// Bind the arguments common to all summary queries (see `Summary`).
+///
+fn query<'a>(r: &'a str, day: &str, site: &Site, dist: f64) -> QueryBuilder<'a> {
+    QueryBuilder::new(r)
+        .arg(day)
+        .arg(site.id)
+        .arg(site.longitude as f64)
+        .arg(site.latitude as f64)
+        .arg(dist)
+        .arg(site.name.as_str())
+}
+
+/// Create all tables, existing ones are left alone.
+///
+#[tracing::instrument(skip(dbh))]
+async fn create_tables(dbh: &Client) -> Result<()> {
+    for summary in Summary::iter() {
+        dbh.execute(summary.create()).await?;
+    }
+    Ok(dbh.execute(SOURCES_TABLE).await?)
+}
+
+/// Load what was recorded between `begin` and `end` (both YYYY-MM-DD).
+///
+#[tracing::instrument(skip(dbh))]
+async fn load_recorded(dbh: &Client, begin: &str, end: &str) -> Result<Recorded> {
+    let r = r##"
+SELECT summary, toString(day) AS day, site, source_rows
+FROM acute.summary_sources FINAL
+WHERE day BETWEEN toDate($1) AND toDate($2)
+"##;
+    let q = QueryBuilder::new(r).arg(begin).arg(end);
+    let all = dbh.query_collect::<Source>(q).await?;
+    Ok(all
+        .into_iter()
+        .map(|s| ((s.summary, s.day, s.site), s.source_rows))
+        .collect())
+}
+
+/// Rebuild what is stale for one day and one site, returns the summaries rebuilt.
+///
+#[tracing::instrument(skip(ctx, dbh, recorded))]
+async fn refresh_one_day_on_site(
+    ctx: &Context,
+    dbh: &Client,
+    opts: &SummaryOpts,
+    recorded: &Recorded,
+    summaries: &[Summary],
+    day: &str,
+    site: &Site,
+) -> Result<Vec<Summary>> {
+    let dist = opts.distance * 1.852 / ONE_DEG;
+
+    let mut done = vec![];
+    for summary in summaries {
+        let mut row = dbh
+            .query_one::<RawRow>(query(summary.sources(), day, site, dist))
+            .await?;
+        let current: u64 = row.get(0);
+
+        let key = (summary.to_string(), day.to_string(), site.id);
+        let previous = recorded.get(&key).copied();
+        if !opts.force && !is_stale(previous, current) {
+            trace!("{summary} for {} on {day} is up to date", site.name);
+            continue;
+        }
+        info!(
+            "{summary} for {} on {day}: {:?} -> {current} source rows",
+            site.name, previous
+        );
+        done.push(*summary);
+        if ctx.dry_run {
+            continue;
+        }
+
+        dbh.execute(query(&summary.refresh(), day, site, dist))
+            .await?;
+        let r = "INSERT INTO acute.summary_sources VALUES ($1, toDate($2), $3, $4, now())";
+        let q = QueryBuilder::new(r)
+            .arg(summary.to_string())
+            .arg(day)
+            .arg(site.id)
+            .arg(current);
+        dbh.execute(q).await?;
+    }
+    Ok(done)
+}
+
+/// Handle `refresh-summaries`, returns the number of rows rebuilt.
+///
+#[tracing::instrument(skip(ctx))]
+pub async fn refresh_summaries(ctx: &Context, opts: &SummaryOpts) -> Result<usize> {
+    let begin = parse_day(&opts.date)?;
+    let end = match &opts.until {
+        Some(until) => parse_day(until)?,
+        None => begin,
+    };
+    let days: Vec<DateTime<Utc>> = expand_interval(begin, end + Days::new(1))?;
+    let summaries = match opts.only.is_empty() {
+        true => Summary::iter().collect::<Vec<_>>(),
+        false => opts.only.clone(),
+    };
+    eprintln!(
+        "{} days to check for {}",
+        days.len(),
+        summaries
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let dbh = ctx.db().await;
+    create_tables(&dbh).await?;
+
+    let day = |d: &DateTime<Utc>| d.format("%Y-%m-%d").to_string();
+    let recorded = load_recorded(&dbh, &day(&begin), &day(&end)).await?;
+    debug!("{} rows recorded", recorded.len());
+
+    let mut count = 0;
+    for d in days.iter() {
+        let sites = match &opts.site {
+            Some(name) => vec![find_site(ctx, name).await?],
+            None => enumerate_sites(ctx, *d).await?,
+        };
+        for site in sites.iter() {
+            let done =
+                refresh_one_day_on_site(ctx, &dbh, opts, &recorded, &summaries, &day(d), site)
+                    .await?;
+            if !done.is_empty() {
+                let done = done.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+                eprintln!("{} {}: {}", day(d), site.name, done.join(", "));
+            }
+            count += done.len();
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(None, 0, true)]
+    #[case(None, 42, true)]
+    #[case(Some(42), 42, false)]
+    #[case(Some(42), 50, true)]
+    #[case(Some(42), 0, true)]
+    fn test_is_stale(#[case] recorded: Option<u64>, #[case] current: u64, #[case] res: bool) {
+        assert_eq!(res, is_stale(recorded, current));
+    }
+
+    #[test]
+    fn test_summary_queries() {
+        assert_eq!(Summary::Drones, Summary::from_str("drones").unwrap());
+        for s in Summary::iter() {
+            let table = s.table();
+            assert!(s.create().contains(&format!("acute.{table} (")));
+            assert!(s
+                .refresh()
+                .starts_with(&format!("INSERT INTO acute.{table}")));
+        }
+        assert!(Summary::Encounters.refresh().contains("sumMap"));
+    }
+}
@@ -19,7 +19,9 @@ use klickhouse::{bb8, Client, ClientOptions, ConnectionManager};
 use serde::{Deserialize, Serialize};
 use tracing::{error, info, trace};
 
-use fetiche_common::{close_logging, init_logging, ConfigFile, IntoConfig, Versioned};
+use fetiche_common::{
+    close_logging, init_logging, ConfigFile, IntoConfig, Redaction, Redactions, Versioned,
+};
 use fetiche_macros::into_configfile;
 
 use crate::cli::Opts;
@@ -47,6 +49,9 @@ pub struct ProcessConfig {
     pub user: Option<String>,
     /// Corresponding password
     pub password: Option<String>,
+    /// Named redaction policies for exports
+    #[serde(default)]
+    pub redact: Redactions,
 }
 
 /// This holds our context, meaning common stuff
@@ -63,6 +68,8 @@ pub struct Context {
     pub wait: u64,
     /// Dry run
     pub dry_run: bool,
+    /// Redaction policies
+    pub redactions: Arc<Redactions>,
 }
 
 impl Context {
@@ -77,6 +84,15 @@ impl Context {
         client.clone()
     }
 
+    /// Return the redaction policy called `name`
+    ///
+    pub fn redaction(&self, name: &str) -> Result<Redaction> {
+        match self.redactions.get(name) {
+            Some(r) => Ok(r.clone()),
+            None => Err(Status::UnknownRedaction(name.to_string()).into()),
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn finish(&self) -> Result<()> {
         Ok(())
@@ -90,6 +106,7 @@ impl Debug for Context {
             .field("dbh", &String::from("Clickhouse client"))
             .field("wait", &self.wait)
             .field("dry_run", &self.dry_run)
+            .field("redactions", &self.redactions)
             .finish()
     }
 }
@@ -105,6 +122,7 @@ pub async fn init_runtime(opts: &Opts) -> Result<Context> {
         opts.use_telemetry,
         opts.use_tree,
         opts.use_file.clone(),
+        false,
     )?;
     trace!("Logging initialised.");
 
@@ -196,6 +214,7 @@ pub async fn init_runtime(opts: &Opts) -> Result<Context> {
         pool_size,
         wait: opts.wait,
         dry_run: opts.dry_run,
+        redactions: Arc::new(cfg.redact.clone()),
     };
     Ok(ctx)
 }
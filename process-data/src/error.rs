@@ -23,4 +23,10 @@ pub enum Status {
     NoOutputFile,
     #[error("Unknown output format, aborting.")]
     UnknownFormat(String),
+    #[error("Invalid rubric: {0}")]
+    BadRubric(String),
+    #[error("Unknown redaction policy {0}")]
+    UnknownRedaction(String),
+    #[error("Bad time-of-day slot of {0}h, must divide 24")]
+    BadSlot(u32),
 }
@@ -0,0 +1,214 @@
+//! Compact on-disk artifact format.
+//!
+//! An artifact is the `FTPB` magic followed by a `Header` and any number of `Record`, every
+//! message being prefixed by its length as a varint (like `writeDelimitedTo()` in the Java
+//! protobuf library).  Records are read one at a time so files larger than memory are fine.
+//!
+
+use std::io::{ErrorKind, Read, Write};
+
+use chrono::Utc;
+use eyre::Result;
+use prost::Message;
+use tracing::trace;
+
+use crate::{Header, ProtoStatus, Record};
+
+/// Magic bytes at the start of every artifact
+pub const MAGIC: &[u8; 4] = b"FTPB";
+/// Current artifact version, v2 added the category of `DronePoint`
+pub const ARTIFACT_VERSION: u32 = 2;
+/// Oldest version we can read
+pub const MIN_ARTIFACT_VERSION: u32 = 1;
+
+/// Write records into an artifact.
+///
+#[derive(Debug)]
+pub struct ArtifactWriter<W: Write> {
+    inner: W,
+    /// Records written so far
+    count: usize,
+}
+
+impl<W: Write> ArtifactWriter<W> {
+    /// Write the magic & header, `format` being the name of the records' format.
+    ///
+    pub fn new(mut inner: W, format: &str, producer: &str) -> Result<Self> {
+        let header = Header {
+            version: ARTIFACT_VERSION,
+            format: format.to_string(),
+            created: Utc::now().timestamp(),
+            producer: producer.to_string(),
+        };
+        inner.write_all(MAGIC)?;
+        inner.write_all(&header.encode_length_delimited_to_vec())?;
+        Ok(ArtifactWriter { inner, count: 0 })
+    }
+
+    /// Append one record
+    ///
+    pub fn write(&mut self, rec: &Record) -> Result<&mut Self> {
+        self.inner
+            .write_all(&rec.encode_length_delimited_to_vec())?;
+        self.count += 1;
+        Ok(self)
+    }
+
+    /// Number of records written
+    ///
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Flush everything and give back the writer
+    ///
+    pub fn finish(mut self) -> Result<W> {
+        trace!("{} records", self.count);
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Read records from an artifact, this is an iterator over `Result<Record>`.
+///
+#[derive(Debug)]
+pub struct ArtifactReader<R: Read> {
+    inner: R,
+    header: Header,
+}
+
+impl<R: Read> ArtifactReader<R> {
+    /// Check the magic and read the header
+    ///
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        inner
+            .read_exact(&mut magic)
+            .map_err(|_| ProtoStatus::BadMagic)?;
+        if &magic != MAGIC {
+            return Err(ProtoStatus::BadMagic.into());
+        }
+        let Some(buf) = read_delimited(&mut inner)? else {
+            return Err(ProtoStatus::Truncated.into());
+        };
+        let header = Header::decode(buf.as_slice())?;
+        if !(MIN_ARTIFACT_VERSION..=ARTIFACT_VERSION).contains(&header.version) {
+            return Err(ProtoStatus::BadVersion(header.version, ARTIFACT_VERSION).into());
+        }
+        Ok(ArtifactReader { inner, header })
+    }
+
+    /// Header of the artifact
+    ///
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+}
+
+impl<R: Read> Iterator for ArtifactReader<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match read_delimited(&mut self.inner) {
+            Ok(Some(buf)) => Some(Record::decode(buf.as_slice()).map_err(Into::into)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Read one length-prefixed message, `None` at the end of the file.
+///
+fn read_delimited<R: Read>(r: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut len: u64 = 0;
+    let mut byte = [0u8; 1];
+    for i in 0..10 {
+        match r.read_exact(&mut byte) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && i == 0 => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(ProtoStatus::Truncated.into())
+            }
+            Err(e) => return Err(e.into()),
+        }
+        len |= ((byte[0] & 0x7f) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            let mut buf = vec![0u8; len as usize];
+            r.read_exact(&mut buf).map_err(|_| ProtoStatus::Truncated)?;
+            return Ok(Some(buf));
+        }
+    }
+    Err(ProtoStatus::Truncated.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{Cat21, DronePoint, Header};
+
+    use super::*;
+
+    fn sample() -> Result<Vec<u8>> {
+        let mut w = ArtifactWriter::new(vec![], "cat21", "test")?;
+        for i in 0..300 {
+            w.write(&Record::from(Cat21 {
+                rec_num: i,
+                callsign: "A".repeat(i as usize),
+                ..Default::default()
+            }))?;
+        }
+        w.write(&Record::from(DronePoint::default()))?;
+        assert_eq!(301, w.count());
+        w.finish()
+    }
+
+    #[test]
+    fn test_artifact_roundtrip() -> Result<()> {
+        let buf = sample()?;
+        assert_eq!(MAGIC, &buf[..4]);
+
+        let r = ArtifactReader::new(buf.as_slice())?;
+        assert_eq!("cat21", r.header().format);
+        assert_eq!(ARTIFACT_VERSION, r.header().version);
+
+        let recs = r.collect::<Result<Vec<_>>>()?;
+        assert_eq!(301, recs.len());
+        // Records longer than 127 bytes need a two-byte length
+        //
+        assert!(matches!(&recs[200].record, Some(crate::Data::Cat21(c)) if c.rec_num == 200));
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(b"NOPE".to_vec())]
+    #[case(b"FT".to_vec())]
+    #[case(b"FTPB".to_vec())]
+    fn test_artifact_bad_start(#[case] buf: Vec<u8>) {
+        assert!(ArtifactReader::new(buf.as_slice()).is_err());
+    }
+
+    #[rstest]
+    #[case(1, true)]
+    #[case(ARTIFACT_VERSION, true)]
+    #[case(ARTIFACT_VERSION + 1, false)]
+    fn test_artifact_version(#[case] version: u32, #[case] ok: bool) {
+        let header = Header {
+            version,
+            ..Default::default()
+        };
+        let mut buf = MAGIC.to_vec();
+        buf.extend(header.encode_length_delimited_to_vec());
+        assert_eq!(ok, ArtifactReader::new(buf.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_artifact_truncated() -> Result<()> {
+        let mut buf = sample()?;
+        buf.truncate(buf.len() - 1);
+        let r = ArtifactReader::new(buf.as_slice())?;
+        let recs = r.collect::<Vec<_>>();
+        assert!(recs.last().unwrap().is_err());
+        Ok(())
+    }
+}
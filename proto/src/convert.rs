@@ -0,0 +1,259 @@
+//! Conversions between the `fetiche-formats` records and their messages.
+//!
+
+use fetiche_formats::{Asd, Bool};
+
+use crate::{Cat21, Data, DronePoint, Record, TodCalculated, VehicleCategory};
+
+impl From<&Asd> for DronePoint {
+    fn from(r: &Asd) -> Self {
+        DronePoint {
+            time: r.time.timestamp(),
+            journey: r.journey.to_string(),
+            ident: r.ident.clone(),
+            model: r.model.clone(),
+            latitude: r.latitude as f64,
+            longitude: r.longitude as f64,
+            altitude: r.altitude.map(f64::from),
+            elevation: r.elevation.map(f64::from),
+            home_lat: r.home_lat.map(f64::from),
+            home_lon: r.home_lon.map(f64::from),
+            speed: r.speed,
+            heading: r.heading,
+            station: r.station_name.clone(),
+            category: VehicleCategory::from(r.category()) as i32,
+        }
+    }
+}
+
+impl From<fetiche_formats::VehicleCategory> for VehicleCategory {
+    fn from(c: fetiche_formats::VehicleCategory) -> Self {
+        use fetiche_formats::VehicleCategory as V;
+
+        match c {
+            V::Unknown => VehicleCategory::Unknown,
+            V::FixedWing => VehicleCategory::FixedWing,
+            V::Rotorcraft => VehicleCategory::Rotorcraft,
+            V::Glider => VehicleCategory::Glider,
+            V::LighterThanAir => VehicleCategory::LighterThanAir,
+            V::Parachutist => VehicleCategory::Parachutist,
+            V::Uas => VehicleCategory::Uas,
+            V::UasMultiRotor => VehicleCategory::UasMultiRotor,
+            V::UasFixedWing => VehicleCategory::UasFixedWing,
+            V::Ground => VehicleCategory::Ground,
+            V::Obstacle => VehicleCategory::Obstacle,
+        }
+    }
+}
+
+impl From<VehicleCategory> for fetiche_formats::VehicleCategory {
+    fn from(c: VehicleCategory) -> Self {
+        use fetiche_formats::VehicleCategory as V;
+
+        match c {
+            VehicleCategory::Unknown => V::Unknown,
+            VehicleCategory::FixedWing => V::FixedWing,
+            VehicleCategory::Rotorcraft => V::Rotorcraft,
+            VehicleCategory::Glider => V::Glider,
+            VehicleCategory::LighterThanAir => V::LighterThanAir,
+            VehicleCategory::Parachutist => V::Parachutist,
+            VehicleCategory::Uas => V::Uas,
+            VehicleCategory::UasMultiRotor => V::UasMultiRotor,
+            VehicleCategory::UasFixedWing => V::UasFixedWing,
+            VehicleCategory::Ground => V::Ground,
+            VehicleCategory::Obstacle => V::Obstacle,
+        }
+    }
+}
+
+/// `Y`/`N`
+///
+#[inline]
+fn yes(b: &Bool) -> bool {
+    matches!(b, Bool::Y)
+}
+
+#[inline]
+fn flag(b: bool) -> Bool {
+    if b {
+        Bool::Y
+    } else {
+        Bool::N
+    }
+}
+
+impl From<&fetiche_formats::TodCalculated> for TodCalculated {
+    fn from(t: &fetiche_formats::TodCalculated) -> Self {
+        match t {
+            fetiche_formats::TodCalculated::C => TodCalculated::C,
+            fetiche_formats::TodCalculated::L => TodCalculated::L,
+            fetiche_formats::TodCalculated::N => TodCalculated::N,
+            fetiche_formats::TodCalculated::R => TodCalculated::R,
+        }
+    }
+}
+
+impl From<TodCalculated> for fetiche_formats::TodCalculated {
+    fn from(t: TodCalculated) -> Self {
+        match t {
+            TodCalculated::C => fetiche_formats::TodCalculated::C,
+            TodCalculated::L => fetiche_formats::TodCalculated::L,
+            TodCalculated::N => fetiche_formats::TodCalculated::N,
+            TodCalculated::R => fetiche_formats::TodCalculated::R,
+        }
+    }
+}
+
+impl From<&fetiche_formats::Cat21> for Cat21 {
+    fn from(r: &fetiche_formats::Cat21) -> Self {
+        Cat21 {
+            sac: r.sac as u32,
+            sic: r.sic as u32,
+            alt_geo_ft: r.alt_geo_ft,
+            pos_lat_deg: r.pos_lat_deg,
+            pos_long_deg: r.pos_long_deg,
+            alt_baro_ft: r.alt_baro_ft,
+            tod: r.tod,
+            rec_time_posix: r.rec_time_posix,
+            rec_time_ms: r.rec_time_ms,
+            emitter_category: r.emitter_category as u32,
+            differential_correction: yes(&r.differential_correction),
+            ground_bit: yes(&r.ground_bit),
+            simulated_target: yes(&r.simulated_target),
+            test_target: yes(&r.test_target),
+            from_ft: yes(&r.from_ft),
+            selected_alt_capability: yes(&r.selected_alt_capability),
+            spi: yes(&r.spi),
+            link_technology_cddi: yes(&r.link_technology_cddi),
+            link_technology_mds: yes(&r.link_technology_mds),
+            link_technology_uat: yes(&r.link_technology_uat),
+            link_technology_vdl: yes(&r.link_technology_vdl),
+            link_technology_other: yes(&r.link_technology_other),
+            descriptor_atp: r.descriptor_atp as u32,
+            alt_reporting_capability_ft: r.alt_reporting_capability_ft as u32,
+            target_addr: r.target_addr,
+            cat: r.cat as u32,
+            line_id: r.line_id as u32,
+            ds_id: r.ds_id as u32,
+            report_type: r.report_type as u32,
+            tod_calculated: TodCalculated::from(&r.tod_calculated) as i32,
+            callsign: r.callsign.clone(),
+            groundspeed_kt: r.groundspeed_kt,
+            track_angle_deg: r.track_angle_deg,
+            rec_num: r.rec_num as u64,
+        }
+    }
+}
+
+impl From<&Cat21> for fetiche_formats::Cat21 {
+    fn from(r: &Cat21) -> Self {
+        fetiche_formats::Cat21 {
+            sac: r.sac as usize,
+            sic: r.sic as usize,
+            alt_geo_ft: r.alt_geo_ft,
+            pos_lat_deg: r.pos_lat_deg,
+            pos_long_deg: r.pos_long_deg,
+            alt_baro_ft: r.alt_baro_ft,
+            tod: r.tod,
+            rec_time_posix: r.rec_time_posix,
+            rec_time_ms: r.rec_time_ms,
+            emitter_category: r.emitter_category as usize,
+            differential_correction: flag(r.differential_correction),
+            ground_bit: flag(r.ground_bit),
+            simulated_target: flag(r.simulated_target),
+            test_target: flag(r.test_target),
+            from_ft: flag(r.from_ft),
+            selected_alt_capability: flag(r.selected_alt_capability),
+            spi: flag(r.spi),
+            link_technology_cddi: flag(r.link_technology_cddi),
+            link_technology_mds: flag(r.link_technology_mds),
+            link_technology_uat: flag(r.link_technology_uat),
+            link_technology_vdl: flag(r.link_technology_vdl),
+            link_technology_other: flag(r.link_technology_other),
+            descriptor_atp: r.descriptor_atp as usize,
+            alt_reporting_capability_ft: r.alt_reporting_capability_ft as usize,
+            target_addr: r.target_addr,
+            cat: r.cat as usize,
+            line_id: r.line_id as usize,
+            ds_id: r.ds_id as usize,
+            report_type: r.report_type as usize,
+            tod_calculated: r.tod_calculated().into(),
+            callsign: r.callsign.clone(),
+            groundspeed_kt: r.groundspeed_kt,
+            track_angle_deg: r.track_angle_deg,
+            rec_num: r.rec_num as usize,
+        }
+    }
+}
+
+impl From<DronePoint> for Record {
+    fn from(r: DronePoint) -> Self {
+        Record {
+            record: Some(Data::Drone(r)),
+        }
+    }
+}
+
+impl From<Cat21> for Record {
+    fn from(r: Cat21) -> Self {
+        Record {
+            record: Some(Data::Cat21(r)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cat21_roundtrip() {
+        let orig = fetiche_formats::Cat21 {
+            pos_lat_deg: 49.5,
+            pos_long_deg: 2.25,
+            rec_time_posix: 1_700_000_000,
+            ground_bit: Bool::Y,
+            target_addr: 0x4CA2D6,
+            tod_calculated: fetiche_formats::TodCalculated::C,
+            callsign: "AFR123".to_string(),
+            ..Default::default()
+        };
+        let msg = Cat21::from(&orig);
+        assert!(msg.ground_bit);
+        assert!(!msg.spi);
+        assert_eq!(TodCalculated::C, msg.tod_calculated());
+
+        let back = fetiche_formats::Cat21::from(&msg);
+        assert_eq!(
+            serde_json::to_string(&orig).unwrap(),
+            serde_json::to_string(&back).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_drone_category() {
+        let asd: Asd = serde_json::from_str(
+            r#"{"journey":12,"ident":"1581F","model":"Mavic 3","source":"as","location":1,
+            "timestamp":"2024-01-01 00:00:00","latitude":"49.0","longitude":"2.5",
+            "home_lat":null,"home_lon":null,"speed":10.0,"heading":90.0,
+            "station_latitude":null,"station_longitude":null}"#,
+        )
+        .unwrap();
+        let msg = DronePoint::from(&asd);
+        assert_eq!(VehicleCategory::UasMultiRotor, msg.category());
+        assert_eq!(
+            fetiche_formats::VehicleCategory::UasMultiRotor,
+            msg.category().into()
+        );
+
+        // Messages from v1 artifacts
+        //
+        assert_eq!(VehicleCategory::Unknown, DronePoint::default().category());
+    }
+
+    #[test]
+    fn test_record_from() {
+        let r = Record::from(DronePoint::default());
+        assert!(matches!(r.record, Some(Data::Drone(_))));
+    }
+}
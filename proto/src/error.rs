@@ -0,0 +1,14 @@
+//! Error module
+//!
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProtoStatus {
+    #[error("Not an artifact file")]
+    BadMagic,
+    #[error("Bad artifact version {0}, expected {1}")]
+    BadVersion(u32, u32),
+    #[error("Truncated message")]
+    Truncated,
+}
@@ -0,0 +1,25 @@
+//! Protobuf messages shared by the Fetiche components.
+//!
+//! Instead of passing JSON strings around, jobs (`JobSpec`), records (`DronePoint` and `Cat21`
+//! wrapped into `Record`), statistics (`Stats`) and job outcomes (`JobResult`) have a single
+//! binary definition in `fetiche.proto`, used by the daemon API, its clients and the on-disk
+//! artifact format (see `ArtifactWriter` and `ArtifactReader`).
+//!
+//! The messages are defined with the `prost` derive macros so no `protoc` is needed to build,
+//! conversions from the `fetiche-formats` records are here, the ones for the engine types are in
+//! `fetiche-engine`.  `DronePoint` and `Cat21` can also be converted into each other, with a
+//! report of what was lost (see `Loss`).
+//!
+
+pub use prost;
+
+pub use artifact::*;
+pub use error::*;
+pub use messages::*;
+pub use model::*;
+
+mod artifact;
+mod convert;
+mod error;
+mod messages;
+mod model;
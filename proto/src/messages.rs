@@ -0,0 +1,530 @@
+//! Messages from `fetiche.proto`, written with the `prost` derive macros.
+//!
+//! Tags and types must match the `.proto` file, the tests check a few known encodings.
+//!
+
+use std::collections::BTreeMap;
+
+// ----- Jobs
+
+/// Description of a single job
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobSpec {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    /// Site name
+    #[prost(string, tag = "2")]
+    pub source: String,
+    #[prost(message, optional, tag = "3")]
+    pub filter: Option<Filter>,
+    /// Convert into this format
+    #[prost(string, optional, tag = "4")]
+    pub into: Option<String>,
+    /// Keep the raw data in this directory
+    #[prost(string, optional, tag = "5")]
+    pub raw_copy: Option<String>,
+    #[prost(message, optional, tag = "6")]
+    pub sink: Option<Sink>,
+    #[prost(message, optional, tag = "7")]
+    pub schedule: Option<Schedule>,
+    #[prost(message, optional, tag = "8")]
+    pub tracks: Option<Tracks>,
+    #[prost(message, optional, tag = "9")]
+    pub qc: Option<Qc>,
+    #[prost(message, optional, tag = "10")]
+    pub limits: Option<Limits>,
+    /// Wall-clock limit in minutes
+    #[prost(uint64, optional, tag = "11")]
+    pub timeout: Option<u64>,
+    /// Add provenance columns
+    #[prost(bool, tag = "12")]
+    pub provenance: bool,
+    /// Only keep these vehicle categories
+    #[prost(string, repeated, tag = "13")]
+    pub category: Vec<String>,
+    #[prost(message, optional, tag = "14")]
+    pub spill: Option<Spill>,
+    #[prost(message, optional, tag = "15")]
+    pub export: Option<Export>,
+    #[prost(message, optional, tag = "16")]
+    pub label: Option<Label>,
+    /// Tuning of the stages by name
+    #[prost(btree_map = "string, message", tag = "17")]
+    pub tuning: BTreeMap<String, Tuning>,
+}
+
+/// Filter part of a job
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Filter {
+    /// Duration in seconds (negative = back in time)
+    #[prost(int32, optional, tag = "1")]
+    pub since: Option<i32>,
+    /// Start of the interval, UNIX timestamp
+    #[prost(int64, optional, tag = "2")]
+    pub begin: Option<i64>,
+    /// End of the interval, UNIX timestamp
+    #[prost(int64, optional, tag = "3")]
+    pub end: Option<i64>,
+    /// Keyword filter: e.g. "icao24:foobar"
+    #[prost(string, optional, tag = "4")]
+    pub keyword: Option<String>,
+    /// For streams, go back N seconds
+    #[prost(int64, optional, tag = "5")]
+    pub start: Option<i64>,
+    /// Sliding window resolved on every run, e.g. "previous full hour"
+    #[prost(string, optional, tag = "6")]
+    pub window: Option<String>,
+    /// Timezone of the window, e.g. "Europe/Paris"
+    #[prost(string, optional, tag = "7")]
+    pub timezone: Option<String>,
+}
+
+/// Type of sink
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum SinkKind {
+    Save = 0,
+    Split = 1,
+    Store = 2,
+    Postgis = 3,
+}
+
+/// Final stage of a job
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Sink {
+    #[prost(enumeration = "SinkKind", tag = "1")]
+    pub kind: i32,
+    /// File, directory or database URL
+    #[prost(string, tag = "2")]
+    pub path: String,
+    #[prost(string, optional, tag = "3")]
+    pub container: Option<String>,
+    /// Split key
+    #[prost(string, optional, tag = "4")]
+    pub by: Option<String>,
+    /// PostGIS table
+    #[prost(string, optional, tag = "5")]
+    pub table: Option<String>,
+    #[prost(bool, tag = "6")]
+    pub trajectories: bool,
+    #[prost(string, optional, tag = "7")]
+    pub redact: Option<String>,
+    /// Parquet layout, "points" or "tracks"
+    #[prost(string, optional, tag = "8")]
+    pub layout: Option<String>,
+    /// Cat21 columns, "full", "minimal", "ids" or a list of names
+    #[prost(string, optional, tag = "9")]
+    pub columns: Option<String>,
+}
+
+/// Track assembly
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Tracks {
+    /// Seconds
+    #[prost(int64, optional, tag = "1")]
+    pub max_gap: Option<i64>,
+    /// Meters
+    #[prost(double, optional, tag = "2")]
+    pub max_jump: Option<f64>,
+}
+
+/// Labels from flight plans
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Label {
+    /// Flight plans file
+    #[prost(string, tag = "1")]
+    pub plans: String,
+    /// Seconds
+    #[prost(int64, optional, tag = "2")]
+    pub window: Option<i64>,
+    /// Drop records without a plan
+    #[prost(bool, tag = "3")]
+    pub matched_only: bool,
+}
+
+/// Quality checks
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Qc {
+    /// Summary file
+    #[prost(string, optional, tag = "1")]
+    pub summary: Option<String>,
+    /// Seconds
+    #[prost(int64, optional, tag = "2")]
+    pub max_gap: Option<i64>,
+    /// m/s
+    #[prost(double, optional, tag = "3")]
+    pub max_climb: Option<f64>,
+    /// Remove bad records
+    #[prost(bool, tag = "4")]
+    pub drop: bool,
+}
+
+/// Spill in front of the sink
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Spill {
+    /// Largest spill, e.g. "500M"
+    #[prost(string, optional, tag = "1")]
+    pub size: Option<String>,
+    /// Spill directory
+    #[prost(string, optional, tag = "2")]
+    pub path: Option<String>,
+}
+
+/// Export of the artifacts after the job
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Export {
+    /// Destination template, e.g. "s3://bucket/{source}/{date}/{job_id}.csv"
+    #[prost(string, tag = "1")]
+    pub to: String,
+    /// "output", "qc" and/or "raw"
+    #[prost(string, repeated, tag = "2")]
+    pub artifacts: Vec<String>,
+    /// Remove local files once exported
+    #[prost(bool, tag = "3")]
+    pub r#move: bool,
+    #[prost(uint64, optional, tag = "4")]
+    pub retries: Option<u64>,
+    /// S3 region
+    #[prost(string, optional, tag = "5")]
+    pub region: Option<String>,
+    /// S3 endpoint
+    #[prost(string, optional, tag = "6")]
+    pub endpoint: Option<String>,
+}
+
+/// Tuning of one stage
+///
+#[derive(Clone, Copy, PartialEq, prost::Message)]
+pub struct Tuning {
+    /// Batches waiting for the next stage
+    #[prost(uint64, optional, tag = "1")]
+    pub capacity: Option<u64>,
+    /// Worker threads
+    #[prost(uint64, optional, tag = "2")]
+    pub workers: Option<u64>,
+    /// Records per batch
+    #[prost(uint64, optional, tag = "3")]
+    pub batch: Option<u64>,
+}
+
+/// Limits for streams
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Limits {
+    /// Seconds, 0 is no limit
+    #[prost(uint32, tag = "1")]
+    pub duration: u32,
+    /// ms between calls
+    #[prost(uint32, optional, tag = "2")]
+    pub delay: Option<u32>,
+}
+
+/// When to run the job again
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Schedule {
+    #[prost(uint64, tag = "1")]
+    pub every: u64,
+    /// 0 means forever
+    #[prost(uint64, tag = "2")]
+    pub count: u64,
+    /// Check the source before every run
+    #[prost(bool, tag = "3")]
+    pub probe: bool,
+    /// Seconds between two probes while the source is down
+    #[prost(uint64, optional, tag = "4")]
+    pub retry: Option<u64>,
+}
+
+/// Status of a job
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum ResultStatus {
+    Queued = 0,
+    Running = 1,
+    Finished = 2,
+    Failed = 3,
+    TimedOut = 4,
+}
+
+/// Outcome of a job
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct JobResult {
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(enumeration = "ResultStatus", tag = "3")]
+    pub status: i32,
+    /// UNIX timestamps
+    #[prost(int64, tag = "4")]
+    pub queued: i64,
+    #[prost(int64, optional, tag = "5")]
+    pub started: Option<i64>,
+    #[prost(int64, optional, tag = "6")]
+    pub finished: Option<i64>,
+    #[prost(string, optional, tag = "7")]
+    pub error: Option<String>,
+}
+
+// ----- Statistics
+
+/// Endpoints used by a site group
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GroupStats {
+    #[prost(string, tag = "1")]
+    pub endpoint: String,
+    #[prost(uint64, tag = "2")]
+    pub served: u64,
+    #[prost(uint64, tag = "3")]
+    pub failovers: u64,
+}
+
+/// Engine statistics
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Stats {
+    #[prost(uint64, tag = "1")]
+    pub skews: u64,
+    #[prost(int64, tag = "2")]
+    pub max_skew: i64,
+    #[prost(btree_map = "string, message", tag = "3")]
+    pub groups: BTreeMap<String, GroupStats>,
+    #[prost(uint64, tag = "4")]
+    pub space_alerts: u64,
+    #[prost(uint64, tag = "5")]
+    pub timeouts: u64,
+}
+
+// ----- Records
+
+/// One drone position, the common data model of drone sources.
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DronePoint {
+    /// UNIX timestamp
+    #[prost(int64, tag = "1")]
+    pub time: i64,
+    #[prost(string, tag = "2")]
+    pub journey: String,
+    #[prost(string, tag = "3")]
+    pub ident: String,
+    #[prost(string, optional, tag = "4")]
+    pub model: Option<String>,
+    #[prost(double, tag = "5")]
+    pub latitude: f64,
+    #[prost(double, tag = "6")]
+    pub longitude: f64,
+    /// Metres
+    #[prost(double, optional, tag = "7")]
+    pub altitude: Option<f64>,
+    #[prost(double, optional, tag = "8")]
+    pub elevation: Option<f64>,
+    #[prost(double, optional, tag = "9")]
+    pub home_lat: Option<f64>,
+    #[prost(double, optional, tag = "10")]
+    pub home_lon: Option<f64>,
+    #[prost(float, tag = "11")]
+    pub speed: f32,
+    #[prost(float, tag = "12")]
+    pub heading: f32,
+    #[prost(string, optional, tag = "13")]
+    pub station: Option<String>,
+    /// Since artifact v2, `Unknown` before
+    #[prost(enumeration = "VehicleCategory", tag = "14")]
+    pub category: i32,
+}
+
+/// What the target is, see `VehicleCategory` in `fetiche-formats`
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum VehicleCategory {
+    Unknown = 0,
+    FixedWing = 1,
+    Rotorcraft = 2,
+    Glider = 3,
+    LighterThanAir = 4,
+    Parachutist = 5,
+    Uas = 6,
+    UasMultiRotor = 7,
+    UasFixedWing = 8,
+    Ground = 9,
+    Obstacle = 10,
+}
+
+/// How the time of day was calculated
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TodCalculated {
+    N = 0,
+    C = 1,
+    L = 2,
+    R = 3,
+}
+
+/// Our pseudo Cat21
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Cat21 {
+    #[prost(uint32, tag = "1")]
+    pub sac: u32,
+    #[prost(uint32, tag = "2")]
+    pub sic: u32,
+    #[prost(uint32, tag = "3")]
+    pub alt_geo_ft: u32,
+    #[prost(float, tag = "4")]
+    pub pos_lat_deg: f32,
+    #[prost(float, tag = "5")]
+    pub pos_long_deg: f32,
+    #[prost(uint32, tag = "6")]
+    pub alt_baro_ft: u32,
+    #[prost(int64, tag = "7")]
+    pub tod: i64,
+    #[prost(int64, tag = "8")]
+    pub rec_time_posix: i64,
+    #[prost(uint32, tag = "9")]
+    pub rec_time_ms: u32,
+    #[prost(uint32, tag = "10")]
+    pub emitter_category: u32,
+    #[prost(bool, tag = "11")]
+    pub differential_correction: bool,
+    #[prost(bool, tag = "12")]
+    pub ground_bit: bool,
+    #[prost(bool, tag = "13")]
+    pub simulated_target: bool,
+    #[prost(bool, tag = "14")]
+    pub test_target: bool,
+    #[prost(bool, tag = "15")]
+    pub from_ft: bool,
+    #[prost(bool, tag = "16")]
+    pub selected_alt_capability: bool,
+    #[prost(bool, tag = "17")]
+    pub spi: bool,
+    #[prost(bool, tag = "18")]
+    pub link_technology_cddi: bool,
+    #[prost(bool, tag = "19")]
+    pub link_technology_mds: bool,
+    #[prost(bool, tag = "20")]
+    pub link_technology_uat: bool,
+    #[prost(bool, tag = "21")]
+    pub link_technology_vdl: bool,
+    #[prost(bool, tag = "22")]
+    pub link_technology_other: bool,
+    #[prost(uint32, tag = "23")]
+    pub descriptor_atp: u32,
+    #[prost(uint32, tag = "24")]
+    pub alt_reporting_capability_ft: u32,
+    #[prost(uint32, tag = "25")]
+    pub target_addr: u32,
+    #[prost(uint32, tag = "26")]
+    pub cat: u32,
+    #[prost(uint32, tag = "27")]
+    pub line_id: u32,
+    #[prost(uint32, tag = "28")]
+    pub ds_id: u32,
+    #[prost(uint32, tag = "29")]
+    pub report_type: u32,
+    #[prost(enumeration = "TodCalculated", tag = "30")]
+    pub tod_calculated: i32,
+    #[prost(string, tag = "31")]
+    pub callsign: String,
+    #[prost(float, tag = "32")]
+    pub groundspeed_kt: f32,
+    #[prost(float, tag = "33")]
+    pub track_angle_deg: f32,
+    #[prost(uint64, tag = "34")]
+    pub rec_num: u64,
+}
+
+/// Any record
+///
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Data {
+    #[prost(message, tag = "1")]
+    Drone(DronePoint),
+    #[prost(message, tag = "2")]
+    Cat21(Cat21),
+}
+
+/// One record of either type
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(oneof = "Data", tags = "1, 2")]
+    pub record: Option<Data>,
+}
+
+// ----- Artifacts
+
+/// First message of an artifact file, after the magic bytes
+///
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Header {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    /// Format of the records
+    #[prost(string, tag = "2")]
+    pub format: String,
+    /// UNIX timestamp
+    #[prost(int64, tag = "3")]
+    pub created: i64,
+    /// e.g. "acutectl/0.23.0"
+    #[prost(string, tag = "4")]
+    pub producer: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use super::*;
+
+    #[test]
+    fn test_encoding_tags() {
+        // field 1, varint 150 is the canonical protobuf example
+        //
+        let s = Schedule {
+            every: 150,
+            ..Default::default()
+        };
+        assert_eq!(vec![0x08, 0x96, 0x01], s.encode_to_vec());
+
+        // field 31 (callsign), length-delimited: (31 << 3) | 2 = 250 = 0xfa 0x01
+        //
+        let c = Cat21 {
+            callsign: "A".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(vec![0xfa, 0x01, 0x01, b'A'], c.encode_to_vec());
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let r = Record {
+            record: Some(Data::Drone(DronePoint {
+                time: 1_700_000_000,
+                journey: "12".to_string(),
+                latitude: 49.0,
+                longitude: 2.5,
+                altitude: Some(80.),
+                ..Default::default()
+            })),
+        };
+        let buf = r.encode_to_vec();
+        assert_eq!(r, Record::decode(buf.as_slice()).unwrap());
+    }
+}
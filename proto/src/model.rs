@@ -0,0 +1,331 @@
+//! Conversions between our two record models, `DronePoint` and `Cat21`.
+//!
+//! Only some raw formats know how to become a `Cat21`, these work from the common models so any
+//! pipeline can mix both.  Neither model holds everything the other has, so each conversion says
+//! which fields were lost and `Loss` sums them up over many records.
+//!
+//! `DronePoint` → `Cat21` loses `journey`, `model`, `elevation`, `home_lat`/`home_lon` and
+//! `station`, the category is narrowed down to `uas` (emitter category 13) for the various kinds
+//! of UAS.  The other `Cat21` fields get the same values as when converting from ASD.
+//!
+//! `Cat21` → `DronePoint` loses the sensor (`sac`/`sic` other than ours), `target_addr` unless it is used as the
+//! ident (no callsign), `alt_baro_ft` when it differs from the geometric altitude, `rec_time_ms`,
+//! the emitter categories without a vehicle category and the `ground_bit`, `spi`,
+//! `simulated_target` and `test_target` flags.  Bookkeeping fields (`cat`, `line_id`, `ds_id`,
+//! `report_type`, `rec_num`, link technologies...) are not reported.
+//!
+//! A field is only reported as lost when it had a value (other than the default).
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use fetiche_formats::{to_feet, to_knots, Bool, TodCalculated as Tod, DEF_SAC, DEF_SIC};
+
+use crate::{Cat21, Data, DronePoint, Record, VehicleCategory};
+
+/// Feet in a metre, see `to_feet()`
+const FEET: f64 = 3.28084;
+
+/// Knots in a km/h, see `to_knots()`
+const KNOTS: f32 = 0.54;
+
+/// Fields lost over a batch of conversions
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Loss {
+    /// Records converted
+    pub records: usize,
+    /// Number of records having lost each field
+    pub fields: BTreeMap<&'static str, usize>,
+}
+
+impl Loss {
+    /// Add the fields lost by one record
+    ///
+    pub fn add(&mut self, lost: &[&'static str]) -> &mut Self {
+        self.records += 1;
+        lost.iter()
+            .for_each(|f| *self.fields.entry(f).or_default() += 1);
+        self
+    }
+
+    /// Nothing lost at all
+    ///
+    pub fn is_lossless(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl Display for Loss {
+    /// `N records, lost: model (N), station (N)`
+    ///
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} records", self.records)?;
+        if self.is_lossless() {
+            return write!(f, ", nothing lost");
+        }
+        let fields = self
+            .fields
+            .iter()
+            .map(|(name, n)| format!("{name} ({n})"))
+            .collect::<Vec<_>>();
+        write!(f, ", lost: {}", fields.join(", "))
+    }
+}
+
+impl DronePoint {
+    /// Into a `Cat21`, with the fields that could not be kept
+    ///
+    pub fn to_cat21(&self) -> (fetiche_formats::Cat21, Vec<&'static str>) {
+        let mut lost = vec![];
+        if !self.journey.is_empty() {
+            lost.push("journey");
+        }
+        for (name, set) in [
+            ("model", self.model.is_some()),
+            ("elevation", self.elevation.is_some()),
+            ("home_lat", self.home_lat.is_some()),
+            ("home_lon", self.home_lon.is_some()),
+            ("station", self.station.is_some()),
+        ] {
+            if set {
+                lost.push(name);
+            }
+        }
+        let category = fetiche_formats::VehicleCategory::from(self.category());
+        if matches!(
+            self.category(),
+            VehicleCategory::UasMultiRotor | VehicleCategory::UasFixedWing
+        ) {
+            lost.push("category");
+        }
+
+        let alt = to_feet(self.altitude.unwrap_or_default() as f32);
+        let cat21 = fetiche_formats::Cat21 {
+            alt_geo_ft: alt,
+            pos_lat_deg: self.latitude as f32,
+            pos_long_deg: self.longitude as f32,
+            alt_baro_ft: alt,
+            tod: 128 * self.time.rem_euclid(86_400),
+            rec_time_posix: self.time,
+            emitter_category: match category {
+                // Sources of drones do not always know
+                fetiche_formats::VehicleCategory::Unknown => 13,
+                c => c.ecat(),
+            },
+            descriptor_atp: 1,
+            target_addr: 623615,
+            cat: 21,
+            line_id: 1,
+            ds_id: 18,
+            report_type: 3,
+            tod_calculated: Tod::N,
+            callsign: self.ident.clone(),
+            groundspeed_kt: to_knots(self.speed),
+            track_angle_deg: self.heading,
+            rec_num: 1,
+            ..Default::default()
+        };
+        (cat21, lost)
+    }
+
+    /// From a `Cat21`, with the fields that could not be kept
+    ///
+    pub fn from_cat21(r: &fetiche_formats::Cat21) -> (Self, Vec<&'static str>) {
+        let mut lost = vec![];
+        if r.sac != DEF_SAC || r.sic != DEF_SIC {
+            lost.push("sac/sic");
+        }
+        let callsign = r.callsign.trim();
+        if !callsign.is_empty() && r.target_addr != 0 {
+            lost.push("target_addr");
+        }
+        if r.alt_baro_ft != 0 && r.alt_baro_ft != r.alt_geo_ft {
+            lost.push("alt_baro_ft");
+        }
+        if r.rec_time_ms != 0 {
+            lost.push("rec_time_ms");
+        }
+        let category = r.category();
+        if r.emitter_category != 0 && category.ecat() != r.emitter_category {
+            lost.push("emitter_category");
+        }
+        for (name, flag) in [
+            ("ground_bit", &r.ground_bit),
+            ("spi", &r.spi),
+            ("simulated_target", &r.simulated_target),
+            ("test_target", &r.test_target),
+        ] {
+            if matches!(flag, Bool::Y) {
+                lost.push(name);
+            }
+        }
+
+        let ident = match callsign {
+            "" => format!("{:06X}", r.target_addr),
+            c => c.to_string(),
+        };
+        let point = DronePoint {
+            time: r.rec_time_posix,
+            ident,
+            latitude: r.pos_lat_deg as f64,
+            longitude: r.pos_long_deg as f64,
+            altitude: Some(r.alt_geo_ft as f64 / FEET),
+            speed: r.groundspeed_kt / KNOTS,
+            heading: r.track_angle_deg,
+            category: VehicleCategory::from(category) as i32,
+            ..Default::default()
+        };
+        (point, lost)
+    }
+}
+
+impl Record {
+    /// This record as a `Cat21` whatever it is, lost fields are added to `loss`
+    ///
+    pub fn to_cat21(&self, loss: &mut Loss) -> Option<Cat21> {
+        match self.record.as_ref()? {
+            Data::Cat21(r) => {
+                loss.add(&[]);
+                Some(r.clone())
+            }
+            Data::Drone(r) => {
+                let (r, lost) = r.to_cat21();
+                loss.add(&lost);
+                Some(Cat21::from(&r))
+            }
+        }
+    }
+
+    /// This record as a `DronePoint` whatever it is, lost fields are added to `loss`
+    ///
+    pub fn to_drone(&self, loss: &mut Loss) -> Option<DronePoint> {
+        match self.record.as_ref()? {
+            Data::Drone(r) => {
+                loss.add(&[]);
+                Some(r.clone())
+            }
+            Data::Cat21(r) => {
+                let (r, lost) = DronePoint::from_cat21(&fetiche_formats::Cat21::from(r));
+                loss.add(&lost);
+                Some(r)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn drone() -> DronePoint {
+        DronePoint {
+            time: 1_704_067_260,
+            journey: "12".to_string(),
+            ident: "1581F5BK".to_string(),
+            model: Some("Mavic 3".to_string()),
+            latitude: 49.0,
+            longitude: 2.5,
+            altitude: Some(100.0),
+            speed: 36.0,
+            heading: 90.0,
+            category: VehicleCategory::UasMultiRotor as i32,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_drone_to_cat21() {
+        let (r, lost) = drone().to_cat21();
+        assert_eq!(vec!["journey", "model", "category"], lost);
+        assert_eq!(328, r.alt_geo_ft);
+        assert_eq!(128 * 60, r.tod);
+        assert_eq!(13, r.emitter_category);
+        assert_eq!("1581F5BK", r.callsign);
+        assert!((r.groundspeed_kt - 19.44).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_drone_roundtrip() {
+        let orig = DronePoint {
+            journey: "".to_string(),
+            model: None,
+            category: VehicleCategory::Uas as i32,
+            ..drone()
+        };
+        let (r, lost) = orig.to_cat21();
+        assert!(lost.is_empty());
+
+        let (back, lost) = DronePoint::from_cat21(&r);
+        assert_eq!(vec!["target_addr"], lost);
+        assert_eq!(orig.ident, back.ident);
+        assert_eq!(orig.time, back.time);
+        assert_eq!(VehicleCategory::Uas, back.category());
+        assert!((back.altitude.unwrap() - 100.0).abs() < 0.5);
+        assert!((back.speed - orig.speed).abs() < 0.01);
+    }
+
+    #[rstest]
+    #[case(0, "", 0x4CA2D6, "4CA2D6", vec![])]
+    #[case(3, "AFR123 ", 0x4CA2D6, "AFR123", vec!["target_addr", "emitter_category"])]
+    #[case(7, "AFR123", 0, "AFR123", vec!["emitter_category"])]
+    #[case(10, "F-GXYZ", 0, "F-GXYZ", vec![])]
+    fn test_cat21_to_drone(
+        #[case] ecat: usize,
+        #[case] callsign: &str,
+        #[case] addr: u32,
+        #[case] ident: &str,
+        #[case] res: Vec<&str>,
+    ) {
+        let r = fetiche_formats::Cat21 {
+            emitter_category: ecat,
+            callsign: callsign.to_string(),
+            target_addr: addr,
+            ..Default::default()
+        };
+        let (point, lost) = DronePoint::from_cat21(&r);
+        assert_eq!(ident, point.ident);
+        assert_eq!(res, lost);
+    }
+
+    #[test]
+    fn test_record_mix() {
+        let plane = fetiche_formats::Cat21 {
+            sac: 1,
+            sic: 2,
+            callsign: "AFR123".to_string(),
+            emitter_category: 1,
+            spi: Bool::Y,
+            ..Default::default()
+        };
+        let records = [
+            Record::from(drone()),
+            Record::from(Cat21::from(&plane)),
+            Record::default(),
+        ];
+
+        let mut loss = Loss::default();
+        let all = records
+            .iter()
+            .filter_map(|r| r.to_cat21(&mut loss))
+            .collect::<Vec<_>>();
+        assert_eq!(2, all.len());
+        assert_eq!(
+            "2 records, lost: category (1), journey (1), model (1)",
+            loss.to_string()
+        );
+
+        let mut loss = Loss::default();
+        let all = records
+            .iter()
+            .filter_map(|r| r.to_drone(&mut loss))
+            .collect::<Vec<_>>();
+        assert_eq!("AFR123", all[1].ident);
+        assert_eq!(VehicleCategory::FixedWing, all[1].category());
+        assert_eq!("2 records, lost: sac/sic (1), spi (1)", loss.to_string());
+        assert!(!loss.is_lossless());
+    }
+}
@@ -7,23 +7,23 @@
 //! Data fetched is json and not csv but our struct in `formats/aeroscope.rs`  is compatible with
 //! both, even flattening the different lat/long structs in a sensible way.
 //!
-//! This implement the `Fetchable` trait described in `site/lib`.
+//! This implement the `AsyncFetchable` trait described in `site/lib`, the sync `Fetchable` one
+//! comes from the adapter there.
 //!
 
 use std::str::FromStr;
 use std::sync::mpsc::Sender;
 use std::vec;
 
-use clap::{crate_name, crate_version};
 use eyre::Result;
-use reqwest::blocking::Client;
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tracing::{debug, trace};
 
 use fetiche_formats::Format;
 
 use crate::site::Site;
-use crate::{http_get_auth, http_post, Auth, AuthError, Capability, Fetchable};
+use crate::{get_bearer, post_json, AsyncFetchable, Auth, AuthError, Capability, HttpConfig};
 
 /// Data to send to authenticate ourselves and get a token
 ///
@@ -61,7 +61,7 @@ pub struct Aeroscope {
     pub token: String,
     /// Add this to `base_url` to fetch data
     pub get: String,
-    /// reqwest clocking client
+    /// reqwest async client
     pub client: Client,
 }
 
@@ -80,7 +80,7 @@ impl Aeroscope {
             base_url: "".to_owned(),
             get: "".to_owned(),
             token: "".to_owned(),
-            client: Client::new(),
+            client: HttpConfig::default().client(),
         }
     }
 
@@ -92,6 +92,7 @@ impl Aeroscope {
 
         self.format = Format::from_str(&site.format).unwrap();
         self.base_url = site.base_url.to_owned();
+        self.client = site.http().client();
         if let Some(auth) = &site.auth {
             match auth {
                 Auth::Token {
@@ -117,7 +118,7 @@ impl Default for Aeroscope {
     }
 }
 
-impl Fetchable for Aeroscope {
+impl AsyncFetchable for Aeroscope {
     fn name(&self) -> String {
         "aeroscope".to_string()
     }
@@ -125,7 +126,7 @@ impl Fetchable for Aeroscope {
     /// Authenticate to the site with login/password and return a token
     ///
     #[tracing::instrument]
-    fn authenticate(&self) -> Result<String, AuthError> {
+    async fn authenticate(&self) -> Result<String, AuthError> {
         trace!("aeroscope::authenticate({:?})", &self.login);
 
         // Prepare our submission data
@@ -140,9 +141,12 @@ impl Fetchable for Aeroscope {
         let url = format!("{}{}", self.base_url, self.token);
         trace!("Fetching token through {}…", url);
 
-        let resp = http_post!(self, url, &cred).map_err(|e| AuthError::HTTP(e.to_string()))?;
+        let resp = post_json(&self.client, &url, &cred)
+            .await
+            .map_err(|e| AuthError::HTTP(e.to_string()))?;
         let resp = resp
             .text()
+            .await
             .map_err(|_| AuthError::Retrieval(cred.username.clone()))?;
         let res: Token =
             serde_json::from_str(&resp).map_err(|e| AuthError::Decoding(e.to_string()))?;
@@ -154,14 +158,14 @@ impl Fetchable for Aeroscope {
     /// Fetch actual data from the site as a long String.
     ///
     #[tracing::instrument]
-    fn fetch(&self, out: Sender<String>, token: &str, _args: &str) -> Result<()> {
+    async fn fetch(&self, out: Sender<String>, token: &str, _args: &str) -> Result<()> {
         trace!("aeroscope::fetch");
 
         // Use the token to authenticate ourselves
         //
         let url = format!("{}{}", self.base_url, self.get);
-        let resp = http_get_auth!(self, url, token);
-        let resp = resp?.text()?;
+        let resp = get_bearer(&self.client, &url, token).await?;
+        let resp = resp.text().await?;
 
         debug!("{} bytes read. ", resp.len());
         Ok(out.send(resp)?)
@@ -176,13 +180,30 @@ impl Fetchable for Aeroscope {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::mpsc::channel;
+
     use httpmock::prelude::*;
     use serde_json::json;
 
+    use crate::{user_agent, Fetchable};
+
     use super::*;
 
-    #[test]
-    fn test_get_aeroscope_token() {
+    fn setup_aeroscope(server: &MockServer) -> Aeroscope {
+        Aeroscope {
+            features: vec![Capability::Fetch],
+            format: Format::Aeroscope,
+            login: "user".to_string(),
+            password: "pass".to_string(),
+            token: "/login".to_string(),
+            base_url: server.base_url().clone(),
+            get: "/get".to_string(),
+            client: HttpConfig::default().client(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_aeroscope_token() {
         let server = MockServer::start();
         let token = Token {
             access_token: "FOOBAR".to_string(),
@@ -190,33 +211,40 @@ mod tests {
         let jtok = json!(token).to_string();
         let m = server.mock(|when, then| {
             when.method(POST)
-                .header(
-                    "user-agent",
-                    format!("{}/{}", crate_name!(), crate_version!()),
-                )
+                .header("user-agent", user_agent())
                 .header("content-type", "application/json")
                 .path("/login");
             then.status(200).body(&jtok);
         });
 
-        let client = Client::new();
-        let site = Aeroscope {
-            features: vec![Capability::Fetch],
-            format: Format::Aeroscope,
-            login: "user".to_string(),
-            password: "pass".to_string(),
-            token: "/login".to_string(),
-            base_url: server.base_url().clone(),
-            get: "/get".to_string(),
-            client,
-        };
-        let t = site.authenticate();
+        let site = setup_aeroscope(&server);
+        let t = AsyncFetchable::authenticate(&site).await;
 
         m.assert();
         assert!(t.is_ok());
         assert_eq!("FOOBAR", t.as_ref().unwrap());
     }
 
+    #[test]
+    fn test_aeroscope_sync_fetch() {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(GET)
+                .header("user-agent", user_agent())
+                .header("authorization", "Bearer FOOBAR")
+                .path("/get");
+            then.status(200).body("[]");
+        });
+
+        let (tx, rx) = channel();
+        let site = setup_aeroscope(&server);
+        let r = Fetchable::fetch(&site, tx, "FOOBAR", "");
+
+        m.assert();
+        assert!(r.is_ok());
+        assert_eq!("[]", rx.recv().unwrap());
+    }
+
     // #[test]
     // fn test_get_aeroscope_data() {
     //     let server = MockServer::start();
@@ -0,0 +1,753 @@
+//! Bulk download of daily archives
+//!
+//! Some providers publish one archive (zip, tar or tar.gz of CSV files) per day.  For every day
+//! of the requested interval, the archive is downloaded into `dir`, verified against its
+//! checksum if there is one, unpacked and every member is sent down the pipeline in turn so
+//! conversion can happen as usual.
+//!
+//! Downloads go into a `.part` file first and are resumed with a `Range` request if interrupted,
+//! already downloaded (and verified) archives are not downloaded again.  The validator sent by
+//! the server (`ETag` or `Last-Modified`) is kept next to it in a `.tag` file and sent back
+//! with `If-Range` so a file changed in between is downloaded again from the start.  A dropped
+//! connection is resumed right away, up to `MAX_RESUME` times.
+//!
+//! Example in `sources.hcl`:
+//!
+//! ```hcl
+//! site "asd-archive" {
+//!   features = ["archive"]
+//!   type     = "drone"
+//!   format   = "asd"
+//!   base_url = "https://archive.example.com"
+//!   auth     = "login"
+//!   archive  = {
+//!     url      = "/daily/$1.zip"
+//!     checksum = "/daily/$1.zip.sha256"
+//!     suffix   = ".csv"
+//!   }
+//! }
+//! ```
+//!
+//! `$1` is replaced by the day, formatted with `date_fmt` (`%Y-%m-%d` by default).
+//!
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use chrono::{Days, NaiveDate, Utc};
+use eyre::{eyre, Result};
+use flate2::read::GzDecoder;
+use reqwest::blocking::Client;
+use reqwest::blocking::Response;
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, trace};
+
+use fetiche_formats::Format;
+
+use crate::{Auth, AuthError, Capability, Fetchable, Filter, HttpConfig, Site};
+
+/// Number of times an interrupted download is resumed before giving up
+const MAX_RESUME: usize = 3;
+
+/// Archive formats
+///
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveKind {
+    /// Guess from the URL
+    #[default]
+    Auto,
+    Zip,
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// Resolve `Auto` from the file name
+    ///
+    fn guess(self, name: &str) -> Result<Self> {
+        match self {
+            ArchiveKind::Auto => {
+                if name.ends_with(".zip") {
+                    Ok(ArchiveKind::Zip)
+                } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+                    Ok(ArchiveKind::TarGz)
+                } else if name.ends_with(".tar") {
+                    Ok(ArchiveKind::Tar)
+                } else {
+                    Err(eyre!("archive: unknown kind for {name}"))
+                }
+            }
+            kind => Ok(kind),
+        }
+    }
+}
+
+/// Parameters of the archive, `archive` block of a site in `sources.hcl`
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Route to the archive, relative to `base_url`, `$1` is the day
+    pub url: String,
+    /// Route to the SHA-256 of the archive, if any
+    pub checksum: Option<String>,
+    /// Archive format
+    pub kind: ArchiveKind,
+    /// Only send members ending with this
+    pub suffix: Option<String>,
+    /// How to format the day, default is `%Y-%m-%d`
+    pub date_fmt: Option<String>,
+    /// Where to keep downloaded archives, default is a `fetiche-archives` temporary directory
+    pub dir: Option<PathBuf>,
+}
+
+/// Archive source
+///
+#[derive(Clone, Debug)]
+pub struct Archive {
+    /// Describe the different features of the source
+    pub features: Vec<Capability>,
+    /// Format of the archive members
+    pub format: Format,
+    /// Site name
+    pub name: String,
+    /// Base URL
+    pub base_url: String,
+    /// Credentials, only plain login is used
+    pub auth: Auth,
+    /// Archive parameters
+    pub config: ArchiveConfig,
+    /// reqwest blocking client
+    pub client: Client,
+}
+
+impl Archive {
+    #[tracing::instrument]
+    pub fn new() -> Self {
+        Archive {
+            features: vec![Capability::Archive],
+            format: Format::None,
+            name: "archive".to_string(),
+            base_url: String::new(),
+            auth: Auth::Anon,
+            config: ArchiveConfig::default(),
+            client: HttpConfig::default().blocking_client(),
+        }
+    }
+
+    #[tracing::instrument]
+    pub fn load(&mut self, site: &Site) -> &mut Self {
+        trace!("archive::load");
+
+        self.features = site.features.clone();
+        self.format = site.format();
+        self.name = site.name();
+        self.base_url = site.base_url.clone();
+        self.client = site.http().blocking_client();
+        self.auth = site.auth.clone().unwrap_or_default();
+        self.config = site.archive.clone().unwrap_or_default();
+        self
+    }
+
+    /// Days to fetch, archives are daily so we default to yesterday.
+    ///
+    fn days(&self, filter: Filter) -> Vec<NaiveDate> {
+        let today = Utc::now().date_naive();
+        let (begin, end) = match filter {
+            Filter::Interval { begin, end } => (begin.date_naive(), end.date_naive()),
+            Filter::Duration(d) => {
+                let begin = Utc::now() - chrono::Duration::seconds(d.abs() as i64);
+                (begin.date_naive(), today)
+            }
+            _ => {
+                let yesterday = today - Days::new(1);
+                (yesterday, yesterday)
+            }
+        };
+        begin.iter_days().take_while(|d| *d <= end).collect()
+    }
+
+    /// Expand a route for `day`
+    ///
+    fn url(&self, route: &str, day: NaiveDate) -> String {
+        let fmt = self.config.date_fmt.as_deref().unwrap_or("%Y-%m-%d");
+        format!(
+            "{}{}",
+            self.base_url,
+            route.replace("$1", &day.format(fmt).to_string())
+        )
+    }
+
+    /// Directory for downloads
+    ///
+    fn dir(&self) -> PathBuf {
+        self.config
+            .dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("fetiche-archives"))
+    }
+
+    fn get(&self, url: &str) -> reqwest::blocking::RequestBuilder {
+        let req = self.client.get(url);
+        match &self.auth {
+            Auth::Login { username, password } => req.basic_auth(username, Some(password)),
+            _ => req,
+        }
+    }
+
+    /// Download `url` into `path`, resuming from `path.part` if it exists.
+    ///
+    #[tracing::instrument(skip(self))]
+    fn download(&self, url: &str, path: &Path) -> Result<()> {
+        let part = path.with_extension(match path.extension() {
+            Some(ext) => format!("{}.part", ext.to_string_lossy()),
+            None => "part".to_string(),
+        });
+        let tag = part.with_extension("part.tag");
+
+        let mut tries = 0;
+        loop {
+            match self.download_part(url, &part, &tag) {
+                Ok(()) => break,
+                Err(e) if tries < MAX_RESUME && part.exists() => {
+                    tries += 1;
+                    info!(
+                        "{} interrupted ({}), resuming ({}/{})",
+                        url, e, tries, MAX_RESUME
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        fs::rename(&part, path)?;
+        let _ = fs::remove_file(&tag);
+        Ok(())
+    }
+
+    /// One attempt at getting the rest of `url` into `part`, `tag` holding the validator of
+    /// what is already there.
+    ///
+    fn download_part(&self, url: &str, part: &Path, tag: &Path) -> Result<()> {
+        let have = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+
+        let mut req = self.get(url);
+        if have > 0 {
+            debug!("resuming {:?} at {}", part, have);
+            req = req.header(RANGE, format!("bytes={}-", have));
+            if let Ok(validator) = fs::read_to_string(tag) {
+                req = req.header(IF_RANGE, validator.trim());
+            }
+        }
+        let mut resp = req.send()?;
+        let mut fh = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                if range_start(&resp) != Some(have) {
+                    let _ = fs::remove_file(part);
+                    return Err(eyre!("archive: bad range from {}", url));
+                }
+                OpenOptions::new().append(true).open(part)?
+            }
+            // Already complete
+            StatusCode::RANGE_NOT_SATISFIABLE if have > 0 => return Ok(()),
+            s if s.is_success() => {
+                if have > 0 {
+                    info!("{} changed or can not be resumed, starting again", url);
+                }
+                match validator(&resp) {
+                    Some(v) => fs::write(tag, v)?,
+                    None => {
+                        let _ = fs::remove_file(tag);
+                    }
+                }
+                File::create(part)?
+            }
+            s => return Err(eyre!("archive: {} for {}", s, url)),
+        };
+        let n = resp.copy_to(&mut fh)?;
+        debug!("{} bytes written into {:?}", n, part);
+        Ok(())
+    }
+
+    /// Check `path` against the checksum published at `url`.
+    ///
+    #[tracing::instrument(skip(self))]
+    fn verify(&self, url: &str, path: &Path) -> Result<bool> {
+        let resp = self.get(url).send()?.error_for_status()?;
+        let text = resp.text()?;
+        let want = text.split_whitespace().next().unwrap_or_default();
+
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        let got = hex::encode(hasher.finalize());
+        trace!("want={} got={}", want, got);
+        Ok(want.eq_ignore_ascii_case(&got))
+    }
+
+    /// Download and check the archive for `day`, returns its path.
+    ///
+    fn retrieve(&self, day: NaiveDate) -> Result<PathBuf> {
+        let url = self.url(&self.config.url, day);
+        let fname = url.rsplit('/').next().unwrap_or_default();
+
+        let dir = self.dir();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}-{}", self.name, fname));
+
+        let checksum = self.config.checksum.as_ref().map(|c| self.url(c, day));
+        if path.exists() {
+            match &checksum {
+                Some(sum) if !self.verify(sum, &path)? => {
+                    info!("{:?} is corrupted, downloading again", path);
+                    fs::remove_file(&path)?;
+                }
+                _ => {
+                    debug!("{:?} already there", path);
+                    return Ok(path);
+                }
+            }
+        }
+
+        self.download(&url, &path)?;
+        if let Some(sum) = &checksum {
+            if !self.verify(sum, &path)? {
+                fs::remove_file(&path)?;
+                return Err(eyre!("archive: bad checksum for {}", url));
+            }
+        }
+        Ok(path)
+    }
+
+    /// Is this member one we want?
+    ///
+    fn wanted(&self, name: &str) -> bool {
+        !name.ends_with('/')
+            && self
+                .config
+                .suffix
+                .as_ref()
+                .is_none_or(|s| name.ends_with(s.as_str()))
+    }
+
+    /// Send every wanted member of the archive in `path`, returns the number sent.
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn unpack(&self, path: &Path, out: &Sender<String>) -> Result<usize> {
+        let name = path.to_string_lossy();
+        let mut count = 0;
+        match self.config.kind.guess(&name)? {
+            ArchiveKind::Zip => {
+                let mut zip = zip::ZipArchive::new(File::open(path)?)?;
+                for i in 0..zip.len() {
+                    let mut member = zip.by_index(i)?;
+                    if !self.wanted(member.name()) {
+                        continue;
+                    }
+                    trace!("member {}", member.name());
+                    let mut data = String::new();
+                    member.read_to_string(&mut data)?;
+                    out.send(data)?;
+                    count += 1;
+                }
+            }
+            kind => {
+                let fh = File::open(path)?;
+                let rdr: Box<dyn Read> = match kind {
+                    ArchiveKind::TarGz => Box::new(GzDecoder::new(fh)),
+                    _ => Box::new(fh),
+                };
+                for member in TarReader::new(rdr) {
+                    let (name, data) = member?;
+                    if !self.wanted(&name) {
+                        continue;
+                    }
+                    trace!("member {}", name);
+                    out.send(String::from_utf8(data)?)?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Strong `ETag` or `Last-Modified`, what `If-Range` accepts
+///
+fn validator(resp: &Response) -> Option<String> {
+    let etag = resp
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.starts_with("W/"));
+    let date = resp
+        .headers()
+        .get(LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok());
+    etag.or(date).map(|v| v.to_string())
+}
+
+/// Start of the range in `Content-Range: bytes <start>-<end>/<size>`
+///
+fn range_start(resp: &Response) -> Option<u64> {
+    resp.headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .parse()
+        .ok()
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetchable for Archive {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Only HTTP basic auth is supported, done on every request
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    /// Retrieve every day in turn, members are sent one by one.
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn fetch(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        trace!("archive::fetch");
+
+        for day in self.days(Filter::from(args)) {
+            let path = self.retrieve(day)?;
+            let n = self.unpack(&path, &out)?;
+            info!("{}: {} files from {:?}", day, n, path);
+        }
+        Ok(())
+    }
+
+    fn format(&self) -> Format {
+        self.format
+    }
+}
+
+/// Size of a tar block
+const BLOCK: usize = 512;
+
+/// Minimal ustar reader, regular files only, yields (name, content).
+///
+struct TarReader<R: Read> {
+    inner: R,
+    done: bool,
+}
+
+impl<R: Read> TarReader<R> {
+    fn new(inner: R) -> Self {
+        TarReader { inner, done: false }
+    }
+
+    /// Read the next member, `None` at the end of the archive
+    ///
+    fn next_member(&mut self) -> Result<Option<(String, Vec<u8>)>> {
+        loop {
+            let mut hdr = [0u8; BLOCK];
+            match self.inner.read_exact(&mut hdr) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            // Two zero blocks mark the end, one is enough for us
+            //
+            if hdr.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+
+            let field = |r: std::ops::Range<usize>| {
+                let f = &hdr[r];
+                let end = f.iter().position(|&b| b == 0).unwrap_or(f.len());
+                String::from_utf8_lossy(&f[..end]).to_string()
+            };
+            let size = field(124..136).trim().to_string();
+            let size = usize::from_str_radix(&size, 8)
+                .map_err(|_| eyre!("archive: bad tar size {size}"))?;
+            let name = match field(345..500) {
+                prefix if !prefix.is_empty() => format!("{}/{}", prefix, field(0..100)),
+                _ => field(0..100),
+            };
+            let typeflag = hdr[156];
+
+            let mut data = vec![0u8; size.div_ceil(BLOCK) * BLOCK];
+            self.inner.read_exact(&mut data)?;
+            data.truncate(size);
+
+            // Regular files only
+            //
+            if typeflag == b'0' || typeflag == 0 {
+                return Ok(Some((name, data)));
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for TarReader<R> {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_member() {
+            Ok(Some(m)) => Some(Ok(m)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::mpsc::channel;
+
+    use httpmock::prelude::*;
+    use rstest::rstest;
+
+    use super::*;
+
+    fn make_zip() -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(vec![]));
+        let opts = zip::write::FileOptions::default();
+        zip.start_file("a.csv", opts).unwrap();
+        zip.write_all(b"a,b\n1,2\n").unwrap();
+        zip.start_file("README", opts).unwrap();
+        zip.write_all(b"nothing").unwrap();
+        zip.start_file("b.csv", opts).unwrap();
+        zip.write_all(b"a,b\n3,4\n").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn make_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut tar = vec![];
+        for (name, data) in files {
+            let mut hdr = [0u8; BLOCK];
+            hdr[..name.len()].copy_from_slice(name.as_bytes());
+            hdr[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+            hdr[156] = b'0';
+            tar.extend_from_slice(&hdr);
+            tar.extend_from_slice(data);
+            tar.resize(tar.len().div_ceil(BLOCK) * BLOCK, 0);
+        }
+        tar.extend_from_slice(&[0u8; 2 * BLOCK]);
+        tar
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    fn setup(server: &MockServer, dir: &Path, checksum: bool) -> Archive {
+        let mut a = Archive::new();
+        a.name = "test".to_string();
+        a.base_url = server.base_url();
+        a.config = ArchiveConfig {
+            url: "/daily/$1.zip".to_string(),
+            checksum: checksum.then(|| "/daily/$1.zip.sha256".to_string()),
+            suffix: Some(".csv".to_string()),
+            dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        a
+    }
+
+    fn day() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 10, 9).unwrap()
+    }
+
+    #[rstest]
+    #[case("x.zip", ArchiveKind::Zip)]
+    #[case("x.tar.gz", ArchiveKind::TarGz)]
+    #[case("x.tgz", ArchiveKind::TarGz)]
+    #[case("x.tar", ArchiveKind::Tar)]
+    fn test_archive_kind_guess(#[case] name: &str, #[case] kind: ArchiveKind) {
+        assert_eq!(kind, ArchiveKind::Auto.guess(name).unwrap());
+        assert!(ArchiveKind::Auto.guess("x.csv").is_err());
+    }
+
+    #[test]
+    fn test_archive_days() {
+        let a = Archive::new();
+        let f = Filter::interval(
+            "2024-10-09T10:00:00Z".parse().unwrap(),
+            "2024-10-11T00:00:00Z".parse().unwrap(),
+        );
+        let days = a.days(f);
+        assert_eq!(3, days.len());
+        assert_eq!(day(), days[0]);
+        assert_eq!(1, a.days(Filter::None).len());
+    }
+
+    #[test]
+    fn test_archive_fetch_zip() {
+        let server = MockServer::start();
+        let tmp = tempfile::tempdir().unwrap();
+        let zip = make_zip();
+
+        let get = server.mock(|when, then| {
+            when.method(GET).path("/daily/2024-10-09.zip");
+            then.status(200).body(&zip);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/daily/2024-10-09.zip.sha256");
+            then.status(200)
+                .body(format!("{}  2024-10-09.zip\n", sha256(&zip)));
+        });
+
+        let a = setup(&server, tmp.path(), true);
+        let (tx, rx) = channel();
+        let path = a.retrieve(day()).unwrap();
+        assert_eq!(2, a.unpack(&path, &tx).unwrap());
+        assert_eq!("a,b\n1,2\n", rx.recv().unwrap());
+        assert_eq!("a,b\n3,4\n", rx.recv().unwrap());
+
+        // Already there
+        //
+        a.retrieve(day()).unwrap();
+        get.assert_hits(1);
+    }
+
+    #[test]
+    fn test_archive_bad_checksum() {
+        let server = MockServer::start();
+        let tmp = tempfile::tempdir().unwrap();
+
+        server.mock(|when, then| {
+            when.method(GET).path("/daily/2024-10-09.zip");
+            then.status(200).body(make_zip());
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/daily/2024-10-09.zip.sha256");
+            then.status(200).body(sha256(b"something else"));
+        });
+
+        let a = setup(&server, tmp.path(), true);
+        assert!(a.retrieve(day()).is_err());
+        assert!(!tmp.path().join("test-2024-10-09.zip").exists());
+    }
+
+    #[test]
+    fn test_archive_resume() {
+        let server = MockServer::start();
+        let tmp = tempfile::tempdir().unwrap();
+        let zip = make_zip();
+        let (first, rest) = zip.split_at(zip.len() / 2);
+
+        fs::write(tmp.path().join("test-2024-10-09.zip.part"), first).unwrap();
+        fs::write(tmp.path().join("test-2024-10-09.zip.part.tag"), "\"v1\"").unwrap();
+        let range = server.mock(|when, then| {
+            when.method(GET)
+                .path("/daily/2024-10-09.zip")
+                .header("range", format!("bytes={}-", first.len()))
+                .header("if-range", "\"v1\"");
+            then.status(206)
+                .header(
+                    "content-range",
+                    format!("bytes {}-{}/{}", first.len(), zip.len() - 1, zip.len()),
+                )
+                .body(rest);
+        });
+
+        let a = setup(&server, tmp.path(), false);
+        let path = a.retrieve(day()).unwrap();
+        range.assert();
+        assert_eq!(zip, fs::read(path).unwrap());
+        assert!(!tmp.path().join("test-2024-10-09.zip.part.tag").exists());
+    }
+
+    #[test]
+    fn test_archive_resume_changed() {
+        let server = MockServer::start();
+        let tmp = tempfile::tempdir().unwrap();
+        let zip = make_zip();
+
+        // What we have is from an older version, the server sends everything
+        //
+        fs::write(tmp.path().join("test-2024-10-09.zip.part"), b"stale").unwrap();
+        fs::write(tmp.path().join("test-2024-10-09.zip.part.tag"), "\"v1\"").unwrap();
+        let full = server.mock(|when, then| {
+            when.method(GET)
+                .path("/daily/2024-10-09.zip")
+                .header("if-range", "\"v1\"");
+            then.status(200).header("etag", "\"v2\"").body(&zip);
+        });
+
+        let a = setup(&server, tmp.path(), false);
+        let path = a.retrieve(day()).unwrap();
+        full.assert();
+        assert_eq!(zip, fs::read(path).unwrap());
+    }
+
+    #[test]
+    fn test_archive_resume_bad_range() {
+        let server = MockServer::start();
+        let tmp = tempfile::tempdir().unwrap();
+
+        fs::write(tmp.path().join("test-2024-10-09.zip.part"), b"12345").unwrap();
+        let bad = server.mock(|when, then| {
+            when.method(GET).path("/daily/2024-10-09.zip");
+            then.status(206)
+                .header("content-range", "bytes 0-9/10")
+                .body(b"0123456789");
+        });
+
+        // The partial file is dropped and nothing else can be resumed
+        //
+        let a = setup(&server, tmp.path(), false);
+        assert!(a.retrieve(day()).is_err());
+        bad.assert_hits(1);
+        assert!(!tmp.path().join("test-2024-10-09.zip.part").exists());
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_archive_unpack_tar(#[case] gz: bool) {
+        let tmp = tempfile::tempdir().unwrap();
+        let tar = make_tar(&[("a.csv", b"a,b\n1,2\n"), ("dir/b.txt", b"nope")]);
+
+        let path = if gz {
+            let path = tmp.path().join("x.tar.gz");
+            let mut enc =
+                flate2::write::GzEncoder::new(File::create(&path).unwrap(), Default::default());
+            enc.write_all(&tar).unwrap();
+            enc.finish().unwrap();
+            path
+        } else {
+            let path = tmp.path().join("x.tar");
+            fs::write(&path, &tar).unwrap();
+            path
+        };
+
+        let mut a = Archive::new();
+        a.config.suffix = Some(".csv".to_string());
+        let (tx, rx) = channel();
+        assert_eq!(1, a.unpack(&path, &tx).unwrap());
+        assert_eq!("a,b\n1,2\n", rx.recv().unwrap());
+    }
+}
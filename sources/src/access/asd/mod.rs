@@ -10,12 +10,20 @@
 //! Format is different from the json obtained from the actual Aeroscope system but the `Asd` is
 //! compatible with both CSV and JSON output from the site.
 //!
-//! This implement the `Fetchable` trait described in `site/lib`.
+//! This implement the `AsyncFetchable` trait described in `site/lib`, the sync `Fetchable` one
+//! comes from the adapter there.
 //!
 //! Switched from JSON to CSV to work around the size limit from the API ~50 MB
 //!
+//! Besides drone positions, ASD exports other topics (alerts, operators) for the same interval.
+//! Each has its own route in `sources.hcl` named after the topic (`get` for positions) and
+//! `fetch_topics()` gets all those asked for with the same token, one request after the other
+//! as the site does not allow parallel sessions.  Requests are spaced by `TOPIC_DELAY` and a
+//! rate-limited one (429) is retried after the delay given by the site.
+//!
 //! [NDJSON]: https://en.wikipedia.org/wiki/NDJSON
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::ops::Add;
 use std::path::PathBuf;
@@ -23,20 +31,19 @@ use std::str::FromStr;
 use std::sync::mpsc::Sender;
 
 use chrono::{DateTime, Duration, NaiveDateTime, Utc};
-use clap::{crate_name, crate_version};
 use eyre::{eyre, Result};
-use reqwest::blocking::Client;
-use reqwest::StatusCode;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use strum::{EnumString, VariantNames};
-use tap::Tap;
 use tracing::{debug, error, trace, warn};
 
 use fetiche_formats::Format;
 
 use crate::filter::Filter;
 use crate::site::Site;
-use crate::{http_post, Auth, AuthError, Capability, Fetchable, Expirable};
+use crate::{
+    post_bearer, post_json, AsyncFetchable, Auth, AuthError, Capability, Expirable, HttpConfig,
+};
 
 #[cfg(feature = "json")]
 use serde_json::json;
@@ -48,6 +55,56 @@ pub use token::*;
 /// Default token
 const DEF_TOKEN: &str = "asd_default_token";
 
+/// Delay between two requests of the same session
+const TOPIC_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How many times we retry a rate-limited request
+const RATE_RETRIES: usize = 3;
+
+/// Wait this long when rate-limited without a `Retry-After` header, in seconds
+const RATE_BACKOFF: u64 = 10;
+
+/// Export topics, each with its own route
+///
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+    EnumString,
+    strum::Display,
+    VariantNames,
+)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Topic {
+    /// Drone positions, the usual data
+    #[default]
+    Positions,
+    /// Alerts raised by the detection systems
+    Alerts,
+    /// Registered operators
+    Operators,
+}
+
+impl Topic {
+    /// Name of the route in `sources.hcl`
+    ///
+    pub fn route(&self) -> &'static str {
+        match self {
+            Topic::Positions => "get",
+            Topic::Alerts => "alerts",
+            Topic::Operators => "operators",
+        }
+    }
+}
+
 /// Different types of source
 ///
 #[derive(Clone, Debug, Deserialize, Serialize, EnumString, strum::Display, VariantNames)]
@@ -112,7 +169,9 @@ pub struct Asd {
     pub token: String,
     /// Add this to `base_url` to fetch data
     pub get: String,
-    /// reqwest blocking client
+    /// Routes of the other topics defined for the site
+    pub topics: BTreeMap<Topic, String>,
+    /// reqwest async client
     pub client: Client,
 }
 
@@ -132,6 +191,7 @@ impl Asd {
         self.site = site.name.clone();
         self.format = Format::from_str(&site.format).unwrap();
         self.base_url = site.base_url.to_owned();
+        self.client = site.http().client();
         self.token_base = site.token_base.clone();
         if let Some(auth) = &site.auth {
             match auth {
@@ -148,8 +208,21 @@ impl Asd {
             }
         }
         self.get = site.route("get").unwrap().to_owned();
+        self.topics = [Topic::Alerts, Topic::Operators]
+            .into_iter()
+            .filter_map(|t| site.route(t.route()).map(|r| (t, r.to_owned())))
+            .collect();
         self
     }
+
+    /// Route for a given topic, if the site has one
+    ///
+    pub fn topic(&self, topic: Topic) -> Option<&str> {
+        match topic {
+            Topic::Positions => Some(&self.get),
+            t => self.topics.get(&t).map(|r| r.as_str()),
+        }
+    }
     /// Return the content of named token
     ///
     #[tracing::instrument]
@@ -203,7 +276,8 @@ impl Default for Asd {
             base_url: "".to_owned(),
             token: "".to_owned(),
             get: "".to_owned(),
-            client: Client::new(),
+            topics: BTreeMap::new(),
+            client: HttpConfig::default().client(),
         }
     }
 }
@@ -230,7 +304,97 @@ fn prepare_asd_data(data: Param) -> String {
     )
 }
 
-impl Fetchable for Asd {
+/// Time interval from the filter passed to `fetch()`
+///
+fn asd_param(args: &str) -> Result<Param> {
+    const DEF_SOURCES: &[Source] = &[Source::As, Source::Wi];
+
+    let f: Filter = serde_json::from_str(args)?;
+
+    // If we have a filter defined, extract times
+    //
+    let data = match f {
+        Filter::Duration(d) => Param {
+            start_time: NaiveDateTime::default().and_utc(),
+            end_time: NaiveDateTime::default()
+                .and_utc()
+                .add(Duration::try_seconds(d as i64).unwrap()),
+            sources: DEF_SOURCES.to_vec(),
+        },
+        Filter::Interval { begin, end } => Param {
+            start_time: begin,
+            end_time: end,
+            sources: DEF_SOURCES.to_vec(),
+        },
+        _ => Param {
+            start_time: DateTime::<Utc>::MIN_UTC,
+            end_time: DateTime::<Utc>::MIN_UTC,
+            sources: DEF_SOURCES.to_vec(),
+        },
+    };
+    Ok(data)
+}
+
+impl Asd {
+    /// Post the request to one route and return the payload, waiting when rate-limited.
+    ///
+    #[tracing::instrument(skip(self, token))]
+    async fn get_payload(&self, route: &str, token: &str, data: &str) -> Result<Payload> {
+        let url = format!("{}{}", self.base_url, route);
+        trace!("Fetching data through {}…", url);
+
+        let mut tries = 0;
+        let resp = loop {
+            // The body is already encoded as ASD wants it
+            //
+            let resp = post_bearer(&self.client, &url, token, data.to_string()).await?;
+
+            debug!("raw resp={:?}", &resp);
+
+            // Check status
+            //
+            match resp.status() {
+                StatusCode::OK => break resp,
+                StatusCode::UNAUTHORIZED => {
+                    return Err(AuthError::Rejected(self.site.clone()).into())
+                }
+                StatusCode::TOO_MANY_REQUESTS if tries < RATE_RETRIES => {
+                    tries += 1;
+                    let wait = resp
+                        .headers()
+                        .get("retry-after")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .unwrap_or(RATE_BACKOFF);
+                    warn!("{} rate-limited, retry {} in {}s", self.site, tries, wait);
+                    tokio::time::sleep(std::time::Duration::from_secs(wait)).await;
+                }
+                code => {
+                    // This is highly ASD specific
+                    //
+                    use percent_encoding::percent_decode;
+                    trace!("error resp={:?}", resp);
+                    let h = resp.headers();
+                    let errtxt = percent_decode(h["x-debug-exception"].as_bytes()).decode_utf8()?;
+                    let errfile =
+                        percent_decode(h["x-debug-exception-file"].as_bytes()).decode_utf8()?;
+                    return Err(eyre!("Error({}): {} in {}", code, errtxt, errfile));
+                }
+            }
+        };
+
+        // What we receive is an anonymous JSON object containing the filename and CSV content.
+        //
+        let resp = resp.text().await?;
+        trace!("resp={}", resp);
+        let data: Payload = serde_json::from_str(&resp)?;
+
+        trace!("Fetched {}", data.filename);
+        Ok(data)
+    }
+}
+
+impl AsyncFetchable for Asd {
     fn name(&self) -> String {
         self.site.to_string()
     }
@@ -238,7 +402,7 @@ impl Fetchable for Asd {
     /// Authenticate to the site using the supplied credentials and get a token
     ///
     #[tracing::instrument(skip(self))]
-    fn authenticate(&self) -> Result<String, AuthError> {
+    async fn authenticate(&self) -> Result<String, AuthError> {
         trace!("authenticate as ({:?})", &self.login);
 
         // Prepare our submission data
@@ -267,7 +431,7 @@ impl Fetchable for Asd {
 
             // Check stored token expiration date
             //
-            if token.is_expired()  {
+            if token.is_expired() {
                 // Should we delete it?
                 //
                 warn!("Stored token in {:?} has expired, deleting!", fname);
@@ -286,11 +450,14 @@ impl Fetchable for Asd {
             //
             let url = format!("{}{}", self.base_url, self.token);
             trace!("Fetching token through {}…", url);
-            let resp = http_post!(self, url, &cred).map_err(|e| AuthError::HTTP(e.to_string()))?;
+            let resp = post_json(&self.client, &url, &cred)
+                .await
+                .map_err(|e| AuthError::HTTP(e.to_string()))?;
 
             trace!("resp={:?}", resp);
             let resp = resp
                 .text()
+                .await
                 .map_err(|_| AuthError::Retrieval(cred.email.clone()))?;
 
             let res: AsdToken =
@@ -314,87 +481,52 @@ impl Fetchable for Asd {
     /// Fetch actual data using the aforementioned token
     ///
     #[tracing::instrument(skip(self))]
-    fn fetch(&self, out: Sender<String>, token: &str, args: &str) -> Result<()> {
+    async fn fetch(&self, out: Sender<String>, token: &str, args: &str) -> Result<()> {
         trace!("asd::fetch");
 
-        const DEF_SOURCES: &[Source] = &[Source::As, Source::Wi];
-
-        let f: Filter = serde_json::from_str(args)?;
+        let data = prepare_asd_data(asd_param(args)?);
+        debug!("data={}", &data);
 
-        // If we have a filter defined, extract times
-        //
-        let data = match f {
-            Filter::Duration(d) => Param {
-                start_time: NaiveDateTime::default().and_utc(),
-                end_time: NaiveDateTime::default()
-                    .and_utc()
-                    .add(Duration::try_seconds(d as i64).unwrap()),
-                sources: DEF_SOURCES.to_vec(),
-            },
-            Filter::Interval { begin, end } => Param {
-                start_time: begin,
-                end_time: end,
-                sources: DEF_SOURCES.to_vec(),
-            },
-            _ => Param {
-                start_time: DateTime::<Utc>::MIN_UTC,
-                end_time: DateTime::<Utc>::MIN_UTC,
-                sources: DEF_SOURCES.to_vec(),
-            },
-        };
+        let data = self.get_payload(&self.get, token, &data).await?;
+        Ok(out.send(data.content)?)
+    }
 
-        let data = prepare_asd_data(data);
+    /// Fetch every topic for the same interval, in order and in the same session.
+    ///
+    #[tracing::instrument(skip(self, outs))]
+    async fn fetch_topics(
+        &self,
+        outs: Vec<(String, Sender<String>)>,
+        token: &str,
+        args: &str,
+    ) -> Result<()> {
+        trace!("asd::fetch_topics");
+
+        let data = prepare_asd_data(asd_param(args)?);
         debug!("data={}", &data);
 
-        // use token
+        // Check everything before the first request
         //
-        let url = format!("{}{}", self.base_url, self.get);
-        trace!("Fetching data through {}…", url);
-
-        // http_post_auth!() macro seems to be disturbing it.
-        //
-        let resp = self
-            .client
-            .clone()
-            .post(url)
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
-            .header("content-type", "application/json")
-            .header("authorization", format!("Bearer {}", token))
-            .body(data)
-            .tap(|r| debug!("req={:?}", r))
-            .send()?;
-
-        debug!("raw resp={:?}", &resp);
-
-        // Check status
-        //
-        match resp.status() {
-            StatusCode::OK => {}
-            code => {
-                // This is highly ASD specific
-                //
-                use percent_encoding::percent_decode;
-                trace!("error resp={:?}", resp);
-                let h = resp.headers();
-                let errtxt = percent_decode(h["x-debug-exception"].as_bytes()).decode_utf8()?;
-                let errfile =
-                    percent_decode(h["x-debug-exception-file"].as_bytes()).decode_utf8()?;
-                return Err(eyre!("Error({}): {} in {}", code, errtxt, errfile));
+        let routes = outs
+            .iter()
+            .map(|(name, _)| {
+                let topic = Topic::from_str(name)
+                    .map_err(|_| eyre!("{}: unknown topic {}", self.site, name))?;
+                self.topic(topic)
+                    .map(|r| r.to_owned())
+                    .ok_or_else(|| eyre!("{}: no route for topic {}", self.site, topic))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (i, (route, (name, out))) in routes.iter().zip(outs.iter()).enumerate() {
+            if i > 0 {
+                tokio::time::sleep(TOPIC_DELAY).await;
             }
+            let data = self.get_payload(route, token, &data).await?;
+            debug!("{}: {} bytes", name, data.content.len());
+            out.send(data.content)?;
         }
-
-        // What we receive is an anonymous JSON object containing the filename and CSV content.
-        //
-        let resp = resp.text()?;
-        trace!("resp={}", resp);
-        let data: Payload = serde_json::from_str(&resp)?;
-
-        trace!("Fetched {}", data.filename);
-
-        Ok(out.send(data.content)?)
+        Ok(())
     }
 
     /// Return the site's input formats
@@ -402,6 +534,19 @@ impl Fetchable for Asd {
     fn format(&self) -> Format {
         Format::Asd
     }
+
+    /// Remove the stored token, if any
+    ///
+    #[tracing::instrument(skip(self))]
+    fn invalidate(&self) -> Result<()> {
+        let fname = self
+            .token_base
+            .join(format!("{}-{}", DEF_TOKEN, self.login));
+        if fname.exists() {
+            Asd::purge(&fname)?;
+        }
+        Ok(())
+    }
 }
 
 /// ASD is sending us an anonymous JSON array
@@ -432,10 +577,14 @@ fn into_ndjson(resp: &str) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::mpsc::channel;
+
     use env_logger;
     use httpmock::prelude::*;
     use serde_json::json;
 
+    use crate::{user_agent, Fetchable};
+
     use super::*;
 
     fn init() {
@@ -444,7 +593,7 @@ mod tests {
 
     fn setup_asd(server: &MockServer) -> Asd {
         init();
-        let client = Client::new();
+        let client = HttpConfig::default().client();
         Asd {
             features: vec![Capability::Fetch],
             site: "NONE".to_string(),
@@ -455,12 +604,13 @@ mod tests {
             token: "/api/security/login".to_string(),
             base_url: server.base_url().clone(),
             get: "/api/journeys/filteredlocations/json".to_string(),
+            topics: BTreeMap::from([(Topic::Alerts, "/api/alerts".to_string())]),
             client: client.clone(),
         }
     }
 
-    #[test]
-    fn test_get_asd_token() {
+    #[tokio::test]
+    async fn test_get_asd_token() {
         let server = MockServer::start();
         let now = Utc::now().timestamp() + 3600i64;
         let token = AsdToken {
@@ -477,10 +627,7 @@ mod tests {
         let cred = json!(cred).to_string();
         let m = server.mock(|when, then| {
             when.method(POST)
-                .header(
-                    "user-agent",
-                    format!("{}/{}", crate_name!(), crate_version!()),
-                )
+                .header("user-agent", user_agent())
                 .header("content-type", "application/json")
                 .body(&cred)
                 .path("/api/security/login");
@@ -488,12 +635,73 @@ mod tests {
         });
 
         let site = setup_asd(&server);
-        let t = site.authenticate();
+        let t = AsyncFetchable::authenticate(&site).await;
         m.assert();
         assert!(t.is_ok());
         assert_eq!("FOOBAR", t.as_ref().unwrap());
     }
 
+    #[test]
+    fn test_asd_sync_fetch() {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(POST)
+                .header("user-agent", user_agent())
+                .header("authorization", "Bearer FOOBAR")
+                .path("/api/journeys/filteredlocations/json");
+            then.status(200)
+                .body(r#"{"fileName":"today.csv","content":"journey,ident"}"#);
+        });
+
+        let (tx, rx) = channel();
+        let site = setup_asd(&server);
+        let filter = Filter::Duration(60).to_string();
+        let r = Fetchable::fetch(&site, tx, "FOOBAR", &filter);
+
+        m.assert();
+        assert!(r.is_ok());
+        assert_eq!("journey,ident", rx.recv().unwrap());
+    }
+
+    #[test]
+    fn test_asd_fetch_topics() {
+        let server = MockServer::start();
+        let positions = server.mock(|when, then| {
+            when.method(POST)
+                .path("/api/journeys/filteredlocations/json");
+            then.status(200)
+                .body(r#"{"fileName":"today.csv","content":"journey,ident"}"#);
+        });
+        let alerts = server.mock(|when, then| {
+            when.method(POST).path("/api/alerts");
+            then.status(200)
+                .body(r#"{"fileName":"alerts.csv","content":"alert,zone"}"#);
+        });
+
+        let (ptx, prx) = channel();
+        let (atx, arx) = channel();
+        let site = setup_asd(&server);
+        let filter = Filter::Duration(60).to_string();
+        let outs = vec![("positions".to_string(), ptx), ("alerts".to_string(), atx)];
+        let r = Fetchable::fetch_topics(&site, outs, "FOOBAR", &filter);
+
+        positions.assert();
+        alerts.assert();
+        assert!(r.is_ok());
+        assert_eq!("journey,ident", prx.recv().unwrap());
+        assert_eq!("alert,zone", arx.recv().unwrap());
+    }
+
+    #[test]
+    fn test_asd_fetch_topics_no_route() {
+        let server = MockServer::start();
+        let (tx, _rx) = channel();
+        let site = setup_asd(&server);
+        let filter = Filter::Duration(60).to_string();
+        let outs = vec![("operators".to_string(), tx)];
+        assert!(Fetchable::fetch_topics(&site, outs, "FOOBAR", &filter).is_err());
+    }
+
     // #[test]
     // fn test_get_asd_fetch() {
     //     let server = MockServer::start();
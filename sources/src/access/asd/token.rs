@@ -42,6 +42,11 @@ impl Expirable for AsdToken {
     fn key(&self) -> String {
         self.email.clone()
     }
+
+    #[inline]
+    fn expires_at(&self) -> i64 {
+        self.expired_at
+    }
 }
 
 impl Default for AsdToken {
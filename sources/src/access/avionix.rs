@@ -7,7 +7,6 @@
 //!
 
 use chrono::Utc;
-use clap::{crate_name, crate_version};
 use mini_moka::sync::Cache;
 use reqwest::blocking::Client;
 use reqwest::StatusCode;
@@ -23,7 +22,7 @@ use std::{thread, time};
 use tracing::{debug, error, info, trace};
 
 use crate::access::{StatMsg, Stats};
-use crate::{Auth, AuthError, Capability, Filter, Site, Streamable};
+use crate::{Auth, AuthError, Capability, Filter, HttpConfig, Site, Streamable};
 use fetiche_formats::{Format, StateList};
 
 const DEF_SITE: &str = "https://aero-network.com/api";
@@ -67,6 +66,7 @@ impl AvionixCube {
 
         self.format = Format::from_str(&site.format).unwrap();
         self.base_url = site.base_url.to_owned();
+        self.client = site.http().blocking_client();
         if let Some(auth) = &site.auth {
             match auth {
                 Auth::UserKey {
@@ -94,7 +94,7 @@ impl Default for AvionixCube {
             user_key: String::new(),
             base_url: String::from(DEF_SITE),
             get: String::from("/json"),
-            client: Client::new(),
+            client: HttpConfig::default().blocking_client(),
             duration: 0,
         }
     }
@@ -285,10 +285,6 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                 let resp = client
                     .get(&url)
                     .basic_auth(&login, Some(&password))
-                    .header(
-                        "user-agent",
-                        format!("{}/{}", crate_name!(), crate_version!()),
-                    )
                     .header("content-type", "application/json")
                     .send();
 
@@ -0,0 +1,331 @@
+//! SBS-1 "BaseStation" TCP feed
+//!
+//! `dump1090` and its forks (and many other receivers) send SBS-1 messages as CSV text lines
+//! on TCP port 30003, see `fetiche_formats::Sbs1`.  The address is taken from `base_url`, with
+//! or without the `tcp://` prefix.  There is no authentication.  Connecting gives up after the
+//! `connect_timeout` of the `http` block of the site (see `http.rs`).
+//!
+//! - `fetch` reads for `--since` seconds (10 by default) or until the feed is closed and sends
+//!   everything at once,
+//! - `stream` sends what has been received every `delay` ms for `duration` seconds (or forever
+//!   if 0), reconnecting if the feed is closed.
+//!
+//! Example in `sources.hcl`:
+//!
+//! ```hcl
+//! site "dump1090" {
+//!   features = ["fetch", "stream"]
+//!   type     = "adsb"
+//!   format   = "sbs1"
+//!   base_url = "tcp://localhost:30003"
+//! }
+//! ```
+//!
+
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::net::TcpStream;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eyre::Result;
+use tracing::{debug, trace, warn};
+
+use fetiche_formats::Format;
+
+use crate::http::tcp_connect;
+use crate::{AuthError, Capability, Fetchable, Filter, HttpConfig, Site, Streamable};
+
+/// How long we read for `fetch` if not specified, in seconds
+const DEF_DURATION: u64 = 10;
+
+/// Interval between two batches if not specified, in ms
+const DEF_DELAY: u32 = 1_000;
+
+/// How long we block on the socket before looking at the clock
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Why we stopped reading
+///
+#[derive(Debug, PartialEq)]
+enum Ended {
+    /// Duration reached
+    Done,
+    /// Feed closed on the other side
+    Closed,
+    /// Receiver gone
+    Gone,
+}
+
+/// BaseStation source
+///
+#[derive(Clone, Debug)]
+pub struct BaseStation {
+    /// Describe the different features of the source
+    pub features: Vec<Capability>,
+    /// Site name
+    pub name: String,
+    /// `host:port` of the feed
+    pub addr: String,
+    /// Connect timeout
+    pub timeout: Option<Duration>,
+}
+
+impl BaseStation {
+    #[tracing::instrument]
+    pub fn new() -> Self {
+        BaseStation {
+            features: vec![Capability::Fetch, Capability::Stream],
+            name: "dump1090".to_string(),
+            addr: "localhost:30003".to_string(),
+            timeout: HttpConfig::default().connect_timeout(),
+        }
+    }
+
+    #[tracing::instrument]
+    pub fn load(&mut self, site: &Site) -> &mut Self {
+        trace!("basestation::load");
+
+        self.features = site.features.clone();
+        self.name = site.name();
+        self.addr = site
+            .base_url
+            .trim_start_matches("tcp://")
+            .trim_end_matches('/')
+            .to_string();
+        self.timeout = site.http().connect_timeout();
+        self
+    }
+
+    /// Connect to the feed, reads time out so we can look at the clock
+    ///
+    fn connect(&self) -> Result<BufReader<TcpStream>> {
+        trace!("connect to {}", self.addr);
+
+        let stream = tcp_connect(&self.addr, self.timeout)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        Ok(BufReader::new(stream))
+    }
+
+    /// Read lines until `end` (if any) or until the feed is closed, sending what we have every
+    /// `every`.
+    ///
+    fn read(
+        &self,
+        feed: &mut BufReader<TcpStream>,
+        out: &Sender<String>,
+        end: Option<Instant>,
+        every: Duration,
+    ) -> Result<Ended> {
+        let mut batch = String::new();
+        let mut buf = vec![];
+        let mut last = Instant::now();
+
+        let send = |batch: &mut String| {
+            if batch.is_empty() {
+                return true;
+            }
+            out.send(std::mem::take(batch)).is_ok()
+        };
+
+        loop {
+            // An error leaves what has been read so far in `buf`, the line is completed on
+            // the next call.
+            //
+            match feed.read_until(b'\n', &mut buf) {
+                Ok(0) => {
+                    debug!("feed closed");
+                    return Ok(if send(&mut batch) {
+                        Ended::Closed
+                    } else {
+                        Ended::Gone
+                    });
+                }
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buf);
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        batch.push_str(line);
+                        batch.push('\n');
+                    }
+                    buf.clear();
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => {
+                    send(&mut batch);
+                    return Err(e.into());
+                }
+            }
+
+            let done = end.is_some_and(|end| Instant::now() >= end);
+            if done || last.elapsed() >= every {
+                if !send(&mut batch) {
+                    return Ok(Ended::Gone);
+                }
+                last = Instant::now();
+            }
+            if done {
+                return Ok(Ended::Done);
+            }
+        }
+    }
+}
+
+impl Default for BaseStation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetchable for BaseStation {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// No authentication
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    /// Read for the given duration and send everything at once
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn fetch(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        trace!("basestation::fetch");
+
+        let secs = match Filter::from(args) {
+            Filter::Duration(d) if d != 0 => d.unsigned_abs() as u64,
+            _ => DEF_DURATION,
+        };
+        let duration = Duration::from_secs(secs);
+
+        let mut feed = self.connect()?;
+        let end = self.read(&mut feed, &out, Some(Instant::now() + duration), duration)?;
+        debug!("fetch ended: {:?}", end);
+        Ok(())
+    }
+
+    fn format(&self) -> Format {
+        Format::Sbs1
+    }
+}
+
+impl Streamable for BaseStation {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// No authentication
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    /// Send what we received every `delay` ms, reconnecting if needed.
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn stream(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        trace!("basestation::stream");
+
+        let (duration, delay) = match Filter::from(args) {
+            Filter::Stream {
+                duration, delay, ..
+            } => (duration, if delay == 0 { DEF_DELAY } else { delay }),
+            _ => (0, DEF_DELAY),
+        };
+        let delay = Duration::from_millis(delay as u64);
+        let end = match duration {
+            0 => None,
+            d => Some(Instant::now() + Duration::from_secs(d as u64)),
+        };
+
+        loop {
+            let res = self
+                .connect()
+                .and_then(|mut feed| self.read(&mut feed, &out, end, delay));
+            match res {
+                Ok(Ended::Done) | Ok(Ended::Gone) => break,
+                Ok(Ended::Closed) => warn!("{}: feed closed, reconnecting", self.name),
+                // Receivers are restarted at times, keep trying
+                //
+                Err(e) => warn!("{}: {}", self.name, e),
+            }
+            if end.is_some_and(|end| Instant::now() >= end) {
+                break;
+            }
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+
+    fn format(&self) -> Format {
+        Format::Sbs1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    const FEED: &str = "MSG,1,111,11111,4CA2D6,111111,2024/05/12,10:30:14.000,2024/05/12,10:30:14.000,RYR4KX  ,,,,,,,,,,,0\r
+MSG,3,111,11111,4CA2D6,111111,2024/05/12,10:30:15.250,2024/05/12,10:30:15.250,,36000,,,49.61160,6.20610,,,0,0,0,0\r
+";
+
+    /// Serve `FEED` once in two parts then close
+    ///
+    fn feed() -> BaseStation {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut s = BaseStation::new();
+        s.addr = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let (first, second) = FEED.split_at(30);
+            client.write_all(first.as_bytes()).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            client.write_all(second.as_bytes()).unwrap();
+        });
+        s
+    }
+
+    #[test]
+    fn test_basestation_load() {
+        let site = Site {
+            base_url: "tcp://receiver.local:30003/".to_string(),
+            ..Site::default()
+        };
+        assert_eq!("receiver.local:30003", BaseStation::new().load(&site).addr);
+    }
+
+    #[test]
+    fn test_basestation_fetch() -> Result<()> {
+        let s = feed();
+
+        let (tx, rx) = channel();
+        Fetchable::fetch(&s, tx, "", &Filter::Duration(5).to_string())?;
+
+        let data = rx.recv()?;
+        assert_eq!(2, data.lines().count());
+        assert!(!data.contains('\r'));
+        assert!(data.starts_with("MSG,1,111,11111,4CA2D6"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_basestation_stream_closed() -> Result<()> {
+        let s = feed();
+
+        let (tx, rx) = channel();
+        let filter = Filter::stream(0, 1, 100);
+        Streamable::stream(&s, tx, "", &filter.to_string())?;
+
+        let data = rx.iter().collect::<String>();
+        assert_eq!(FEED.replace('\r', ""), data);
+        Ok(())
+    }
+}
@@ -12,18 +12,36 @@
 //! For now, the only event wwe are supporting at this level is `Position`, an ADS-B airplane
 //! position in time and space.  Again, this is not a general FA access library.
 //!
+//! Firehose can filter the subscription server-side, which saves us from paying the bandwidth
+//! for worldwide positions we discard right away.  The filters are set in the `firehose` block
+//! of the site and appended to every command:
+//!
+//! ```hcl
+//! firehose = {
+//!   events   = ["position"]
+//!   latlong  = [[54.0, -6.5, 55.0, -5.5]]
+//!   airports = ["EGAA", "EGAC"]
+//!   idents   = ["EZY*", "RYR*"]
+//! }
+//! ```
+//!
+//! Rectangles are `[lowlat, lowlon, hilat, hilon]`, airports and idents accept the `*` and `?`
+//! wildcards.
+//!
 //! There is not much differences between `Fetch` and `Stream` due to nature of FA's API.  One always
 //! open up a TLS connection to the site and send a request.  If this is a `live` or `pitr` one you
 //! get a stream and `range` gets you a "fixed" stream.
 //!
 
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::net::TcpStream;
 use std::str::FromStr;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 use base64_light::base64_encode;
 use eyre::{eyre, Result};
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
 use native_tls::{TlsConnector, TlsStream};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
@@ -33,7 +51,8 @@ use tracing::trace;
 
 use fetiche_formats::Format;
 
-use crate::{version, Auth, AuthError, Capability, Fetchable, Site, Streamable};
+use crate::http::tcp_connect;
+use crate::{version, Auth, AuthError, Capability, Fetchable, HttpConfig, Site, Streamable};
 
 /// Firehose is out target
 const SITE: &str = "firehose.flightaware.com";
@@ -62,6 +81,77 @@ pub struct Flightaware {
     pub stream: String,
     /// Running time (for streams)
     pub duration: i32,
+    /// Subscription filters
+    pub filters: FirehoseConfig,
+    /// Connect timeout
+    pub timeout: Option<Duration>,
+}
+
+/// Server-side filters of the subscription, `firehose` block of a site in `sources.hcl`
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct FirehoseConfig {
+    /// Events we want, `position` by default
+    pub events: Vec<Events>,
+    /// Rectangles as `[lowlat, lowlon, hilat, hilon]`
+    pub latlong: Vec<[f64; 4]>,
+    /// Origin or destination airports
+    pub airports: Vec<String>,
+    /// Airlines or idents
+    pub idents: Vec<String>,
+    /// Compression of the stream, none by default
+    pub compression: Option<Compress>,
+}
+
+impl Default for FirehoseConfig {
+    fn default() -> Self {
+        FirehoseConfig {
+            events: vec![Events::Position],
+            latlong: vec![],
+            airports: vec![],
+            idents: vec![],
+            compression: None,
+        }
+    }
+}
+
+impl FirehoseConfig {
+    /// Filtering part of a command, `events` replacing the configured ones if not empty.
+    ///
+    fn command(&self, events: &[Events]) -> String {
+        let events = if events.is_empty() {
+            &self.events
+        } else {
+            events
+        };
+        let mut cmd = format!("events \"{}\"", join(events));
+        if !self.latlong.is_empty() {
+            let rects = self
+                .latlong
+                .iter()
+                .map(|r| join(r))
+                .collect::<Vec<_>>()
+                .join(" ");
+            cmd.push_str(&format!(" latlong \"{rects}\""));
+        }
+        if !self.airports.is_empty() {
+            cmd.push_str(&format!(" airport_filter \"{}\"", join(&self.airports)));
+        }
+        if !self.idents.is_empty() {
+            cmd.push_str(&format!(" filter \"{}\"", join(&self.idents)));
+        }
+        cmd
+    }
+}
+
+/// Space-separated list as Firehose wants it
+///
+fn join<T: ToString>(list: &[T]) -> String {
+    list.iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 /// This is the struct holding potential parameters to the API
@@ -74,14 +164,20 @@ struct Param {
     pub begin: Option<String>,
     /// Time to stop to
     pub end: Option<String>,
-    /// Compression type — **UNSUPPORTED**
+    /// Compression type, overrides the one of the site
     pub compress: Option<Compress>,
     /// Events
     pub events: Option<Vec<Events>>,
 }
 
-#[derive(Debug, Deserialize, strum::Display, EnumString, VariantNames, Serialize)]
+/// Compression of the stream, `gzip` and `deflate` (zlib) are decoded on the fly, `compress` (LZW)
+/// is refused.
+///
+#[derive(
+    Clone, Debug, Deserialize, PartialEq, strum::Display, EnumString, VariantNames, Serialize,
+)]
 #[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum Compress {
     Compress,
     Deflate,
@@ -92,8 +188,19 @@ pub enum Compress {
 ///
 /// see `formats/src/flightaware/mod.rs` for details
 ///
-#[derive(Debug, Default, Deserialize, strum::Display, EnumString, VariantNames, Serialize)]
+#[derive(
+    Clone,
+    Debug,
+    Default,
+    Deserialize,
+    PartialEq,
+    strum::Display,
+    EnumString,
+    VariantNames,
+    Serialize,
+)]
 #[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum Events {
     // Airborne
     Arrival,
@@ -149,6 +256,8 @@ impl Flightaware {
             get: "".to_owned(),
             stream: "".to_owned(),
             duration: 0,
+            filters: FirehoseConfig::default(),
+            timeout: HttpConfig::default().connect_timeout(),
         }
     }
 
@@ -177,30 +286,55 @@ impl Flightaware {
         //
         self.get = site.route("get").unwrap().to_owned();
         self.stream = site.route("stream").unwrap().to_owned();
+        self.filters = site.firehose.clone().unwrap_or_default();
+        self.timeout = site.http().connect_timeout();
         self
     }
 
-    /// Generate the proper command string
+    /// Generate the proper command string, `events` and `compress` overriding the configured ones
     ///
     #[tracing::instrument(skip(self))]
-    fn request(&self, cmd: Command) -> Result<String> {
-        let str = match cmd {
-            Command::Live => format!(
-                "live username {} password {} events \"position\"\n",
-                self.login, self.password
-            ),
-            Command::Pitr { pitr } => format!(
-                "pitr {} username {} password {} events \"position\"\n",
-                pitr, self.login, self.password
-            ),
-            Command::Range { begin, end } => format!(
-                "range {} {} username {} password {} events \"{}\"\n",
-                begin, end, self.login, self.password, "position"
-            ),
+    fn request(
+        &self,
+        cmd: Command,
+        events: &[Events],
+        compress: Option<&Compress>,
+    ) -> Result<String> {
+        let start = match cmd {
+            Command::Live => "live".to_string(),
+            Command::Pitr { pitr } => format!("pitr {}", pitr),
+            Command::Range { begin, end } => format!("range {} {}", begin, end),
         };
+        let compression = match compress.or(self.filters.compression.as_ref()) {
+            Some(Compress::Compress) => return Err(eyre!("compress is not supported, use gzip")),
+            Some(c) => format!(" compression {c}"),
+            None => String::new(),
+        };
+        let str = format!(
+            "{} username {} password {}{} {}\n",
+            start,
+            self.login,
+            self.password,
+            compression,
+            self.filters.command(events)
+        );
         Ok(str)
     }
 
+    /// Lines of the answer, decompressed if asked for in the request.
+    ///
+    fn reader<'a, R: Read + 'a>(
+        &self,
+        inp: R,
+        compress: Option<&Compress>,
+    ) -> Box<dyn BufRead + 'a> {
+        match compress.or(self.filters.compression.as_ref()) {
+            Some(Compress::Gzip) => Box::new(BufReader::new(MultiGzDecoder::new(inp))),
+            Some(Compress::Deflate) => Box::new(BufReader::new(ZlibDecoder::new(inp))),
+            _ => Box::new(BufReader::new(inp)),
+        }
+    }
+
     /// Establish the TCP/TLS connection, optionally goes through an HTTP proxy
     ///
     #[tracing::instrument(skip(self))]
@@ -226,7 +360,7 @@ impl Flightaware {
             trace!("Auth token is {}", auth);
 
             trace!("CONNECT");
-            let mut stream = TcpStream::connect(format!("{}:{}", host, port))?;
+            let mut stream = tcp_connect(&format!("{}:{}", host, port), self.timeout)?;
 
             stream.write_all(
                 format!(
@@ -247,7 +381,7 @@ Proxy-Connection: Keep-Alive
         } else {
             trace!("no proxy");
 
-            TcpStream::connect(format!("{}:{}", SITE, PORT))?
+            tcp_connect(&format!("{}:{}", SITE, PORT), self.timeout)?
         };
         // Handover to the TLS engine hopefully
         //
@@ -302,7 +436,11 @@ impl Fetchable for Flightaware {
             return Err(eyre!("No start and/or end, use stream."));
         };
 
-        let req = self.request(cmd)?;
+        let req = self.request(
+            cmd,
+            &args.events.unwrap_or_default(),
+            args.compress.as_ref(),
+        )?;
 
         // Setup TLS connection, check proxy environment var first.
         //
@@ -320,7 +458,7 @@ impl Fetchable for Flightaware {
         stream.write_all(req.as_bytes())?;
 
         trace!("read answer, format as an array");
-        let buf = BufReader::new(&mut stream);
+        let buf = self.reader(&mut stream, args.compress.as_ref());
         let res = buf
             .lines()
             .map(|l| l.unwrap())
@@ -366,7 +504,11 @@ impl Streamable for Flightaware {
             None => Command::Live,
         };
 
-        let req = self.request(cmd)?;
+        let req = self.request(
+            cmd,
+            &args.events.unwrap_or_default(),
+            args.compress.as_ref(),
+        )?;
 
         // Setup TLS connection, check proxy environment var first.
         //
@@ -385,7 +527,7 @@ impl Streamable for Flightaware {
 
         trace!("read answer");
 
-        let buf = BufReader::new(&mut stream);
+        let buf = self.reader(&mut stream, args.compress.as_ref());
         for line in buf.lines() {
             let line = line.unwrap();
             trace!("line={}", line);
@@ -414,4 +556,101 @@ mod tests {
         assert!(t.is_ok());
         assert_eq!(d.timestamp(), t.unwrap());
     }
+
+    #[test]
+    fn test_request_default() -> Result<()> {
+        let mut fa = Flightaware::new();
+        fa.login = "user".to_string();
+        fa.password = "secret".to_string();
+
+        let req = fa.request(Command::Range { begin: 10, end: 20 }, &[], None)?;
+        assert_eq!(
+            "range 10 20 username user password secret events \"position\"\n",
+            req
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_filters() -> Result<()> {
+        let mut fa = Flightaware::new();
+        fa.login = "user".to_string();
+        fa.password = "secret".to_string();
+        fa.filters = FirehoseConfig {
+            latlong: vec![[54.0, -6.5, 55.0, -5.5], [51.25, -1.0, 52.0, 0.5]],
+            airports: vec!["EGAA".to_string(), "EG*".to_string()],
+            idents: vec!["EZY*".to_string()],
+            ..FirehoseConfig::default()
+        };
+
+        let req = fa.request(Command::Live, &[Events::Position, Events::Arrival], None)?;
+        assert_eq!(
+            "live username user password secret events \"position arrival\" \
+             latlong \"54 -6.5 55 -5.5 51.25 -1 52 0.5\" airport_filter \"EGAA EG*\" \
+             filter \"EZY*\"\n",
+            req
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_compression() -> Result<()> {
+        let mut fa = Flightaware::new();
+        fa.filters.compression = Some(Compress::Deflate);
+
+        let req = fa.request(Command::Live, &[], None)?;
+        assert!(req.starts_with("live username  password  compression deflate events"));
+        let req = fa.request(Command::Live, &[], Some(&Compress::Gzip))?;
+        assert!(req.contains(" compression gzip "));
+        assert!(fa
+            .request(Command::Live, &[], Some(&Compress::Compress))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_decompress() -> Result<()> {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+
+        let fa = Flightaware::new();
+        let data = "{\"type\":\"position\"}\n{\"type\":\"arrival\"}\n";
+        let lines = |r: Box<dyn BufRead>| r.lines().collect::<std::io::Result<Vec<_>>>();
+
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(data.as_bytes())?;
+        let gz = gz.finish()?;
+        let res = lines(fa.reader(gz.as_slice(), Some(&Compress::Gzip)))?;
+        assert_eq!(
+            vec!["{\"type\":\"position\"}", "{\"type\":\"arrival\"}"],
+            res
+        );
+
+        let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+        zlib.write_all(data.as_bytes())?;
+        let zlib = zlib.finish()?;
+        assert_eq!(
+            2,
+            lines(fa.reader(zlib.as_slice(), Some(&Compress::Deflate)))?.len()
+        );
+
+        assert_eq!(2, lines(fa.reader(data.as_bytes(), None))?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_firehose_config_hcl() -> Result<()> {
+        let cfg: FirehoseConfig = hcl::from_str(
+            r##"
+latlong  = [[54.0, -6.5, 55.0, -5.5]]
+airports = ["EGAA"]
+compression = "gzip"
+"##,
+        )?;
+        assert_eq!(vec![Events::Position], cfg.events);
+        assert_eq!(vec![[54.0, -6.5, 55.0, -5.5]], cfg.latlong);
+        assert!(cfg.idents.is_empty());
+        assert_eq!(Some(Compress::Gzip), cfg.compression);
+        Ok(())
+    }
 }
@@ -1,13 +1,23 @@
 pub use aeroscope::*;
+pub use archive::*;
 pub use asd::*;
+pub use basestation::*;
 //pub use avionix::*;
 pub use flightaware::*;
+pub use nmb2b::*;
 pub use opensky::*;
 pub use safesky::*;
+pub use simulator::*;
+pub use ussp::*;
 
 mod aeroscope;
+mod archive;
 mod asd;
+mod basestation;
 //mod avionix;
 mod flightaware;
+mod nmb2b;
 mod opensky;
 mod safesky;
+mod simulator;
+mod ussp;
@@ -0,0 +1,404 @@
+//! EUROCONTROL Network Manager B2B specifics
+//!
+//! The NM B2B web services are SOAP over HTTPS, clients being authenticated with a certificate
+//! issued by NM (mutual TLS), given as a PKCS#12 file and its password:
+//!
+//! ```hcl
+//! site "nm-b2b" {
+//!   features = ["fetch"]
+//!   type     = "adsb"
+//!   format   = "nmb2b"
+//!   base_url = "https://www.b2b.nm.eurocontrol.int"
+//!   auth     = {
+//!     cert     = "/etc/acute/nm-b2b.p12"
+//!     password = "..."
+//!   }
+//!   routes   = {
+//!     get = "/B2B_OPS/gateway/spec/27.0.0"
+//!   }
+//!   nm       = {
+//!     airspace = "EBBUFIR"
+//!   }
+//! }
+//! ```
+//!
+//! We use the Flight services to get the flights crossing an airspace (`FlightListByAirspace`)
+//! or using an aerodrome (`FlightListByAerodrome`), with their planned trajectory.  The traffic
+//! window is the interval given as filter, the last `since` seconds or the last hour by default.
+//! An `airspace=<id>` or `aerodrome=<icao>` keyword filter overrides the `nm` block.
+//!
+//! Flights are sent as one JSON object per line, see `fetiche_formats::NmFlight`.
+//!
+
+use std::fs;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+
+use chrono::{DateTime, Duration, Utc};
+use eyre::{eyre, Result};
+use reqwest::blocking::Client;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Identity, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+use fetiche_formats::{Format, NmFlight};
+
+use crate::{Auth, AuthError, Capability, Fetchable, Filter, HttpConfig, Site};
+
+/// Default traffic window, in seconds
+const DEF_WINDOW: i64 = 3_600;
+
+/// Fields we ask for in addition to the flight keys
+const FIELDS: [&str; 3] = ["aircraftType", "aircraftAddress", "ctfmPointProfile"];
+
+/// What to ask NM for, `nm` block of a site in `sources.hcl`
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct NmConfig {
+    /// Airspace whose flights we want
+    pub airspace: Option<String>,
+    /// Aerodrome whose flights we want, if there is no airspace
+    pub aerodrome: Option<String>,
+    /// Dataset, `OPERATIONAL` or `FORECAST`
+    pub dataset: String,
+    /// End user the request is made for, if any
+    pub end_user: Option<String>,
+}
+
+impl Default for NmConfig {
+    fn default() -> Self {
+        NmConfig {
+            airspace: None,
+            aerodrome: None,
+            dataset: "OPERATIONAL".to_string(),
+            end_user: None,
+        }
+    }
+}
+
+/// Flight list request
+///
+#[derive(Clone, Debug, PartialEq)]
+enum Query {
+    Airspace(String),
+    Aerodrome(String),
+}
+
+/// NM B2B client
+///
+#[derive(Clone, Debug)]
+pub struct NmB2b {
+    /// Name of the site
+    pub name: String,
+    /// Describe the different features of the source
+    pub features: Vec<Capability>,
+    /// Input formats
+    pub format: Format,
+    /// Base site url taken from config
+    pub base_url: String,
+    /// Add this to `base_url` to send requests
+    pub get: String,
+    /// Client certificate, PKCS#12
+    pub cert: String,
+    /// Password of the certificate
+    pub password: String,
+    /// What to ask for
+    pub config: NmConfig,
+    /// HTTP settings, the client is created with the certificate for every request
+    pub http: HttpConfig,
+}
+
+impl NmB2b {
+    #[tracing::instrument]
+    pub fn new() -> Self {
+        trace!("nmb2b::new");
+
+        NmB2b {
+            name: "nm-b2b".to_string(),
+            features: vec![Capability::Fetch],
+            format: Format::NmB2b,
+            base_url: "".to_owned(),
+            get: "".to_owned(),
+            cert: "".to_owned(),
+            password: "".to_owned(),
+            config: NmConfig::default(),
+            http: HttpConfig::default(),
+        }
+    }
+
+    #[tracing::instrument]
+    pub fn load(&mut self, site: &Site) -> &mut Self {
+        trace!("nmb2b::load");
+
+        self.name = site.name();
+        self.features = site.features.clone();
+        self.format = Format::from_str(&site.format).unwrap();
+        self.base_url = site.base_url.to_owned();
+        self.http = site.http();
+        if let Some(Auth::Certificate { cert, password }) = &site.auth {
+            self.cert = cert.to_owned();
+            self.password = password.to_owned();
+        }
+        if let Some(config) = &site.nm {
+            self.config = config.clone();
+        }
+        self.get = site.route("get").unwrap().to_owned();
+        self
+    }
+
+    /// Read the certificate
+    ///
+    fn identity(&self) -> Result<Identity, AuthError> {
+        if self.cert.is_empty() {
+            return Err(AuthError::NoAPIKey);
+        }
+        let der = fs::read(&self.cert).map_err(|_| AuthError::Retrieval(self.cert.clone()))?;
+        Identity::from_pkcs12_der(&der, &self.password)
+            .map_err(|_| AuthError::Invalid(self.cert.clone()))
+    }
+
+    /// Client presenting our certificate, if any
+    ///
+    fn client(&self) -> Result<Client> {
+        let builder = self.http.blocking_client_builder()?;
+        let builder = if self.cert.is_empty() {
+            builder
+        } else {
+            builder.identity(self.identity()?)
+        };
+        Ok(builder.build()?)
+    }
+
+    /// Airspace or aerodrome, from the filter or the configuration
+    ///
+    fn query(&self, filter: &Filter) -> Result<Query> {
+        if let Filter::Keyword { name, value } = filter {
+            return match name.as_str() {
+                "airspace" => Ok(Query::Airspace(value.to_owned())),
+                "aerodrome" => Ok(Query::Aerodrome(value.to_owned())),
+                _ => Err(eyre!("{}: unknown keyword {}", self.name, name)),
+            };
+        }
+        match (&self.config.airspace, &self.config.aerodrome) {
+            (Some(airspace), _) => Ok(Query::Airspace(airspace.to_owned())),
+            (None, Some(aerodrome)) => Ok(Query::Aerodrome(aerodrome.to_owned())),
+            _ => Err(eyre!("{}: no airspace nor aerodrome", self.name)),
+        }
+    }
+
+    /// SOAP envelope for a flight list request
+    ///
+    fn request(
+        &self,
+        query: &Query,
+        begin: DateTime<Utc>,
+        end: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> String {
+        let minute = "%Y-%m-%d %H:%M";
+        let (req, filter) = match query {
+            Query::Airspace(id) => (
+                "FlightListByAirspaceRequest",
+                format!("<airspace>{}</airspace>", id),
+            ),
+            Query::Aerodrome(id) => (
+                "FlightListByAerodromeRequest",
+                format!(
+                    "<aerodrome>{}</aerodrome><aerodromeRole>BOTH</aerodromeRole>",
+                    id
+                ),
+            ),
+        };
+        let end_user = self
+            .config
+            .end_user
+            .as_ref()
+            .map(|u| format!("<endUserId>{}</endUserId>", u))
+            .unwrap_or_default();
+        let fields = FIELDS
+            .iter()
+            .map(|f| format!("<requestedFlightFields>{}</requestedFlightFields>", f))
+            .collect::<String>();
+        format!(
+            r##"<?xml version="1.0" encoding="UTF-8"?>
+<soapenv:Envelope xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:fw="eurocontrol/cfmu/b2b/FlightServices">
+<soapenv:Header/>
+<soapenv:Body>
+<fw:{req}>
+{end_user}<sendTime>{}</sendTime>
+<dataset><type>{}</type></dataset>
+<includeProposalFlights>false</includeProposalFlights>
+<includeForecastFlights>false</includeForecastFlights>
+<trafficType>LOAD</trafficType>
+<trafficWindow><wef>{}</wef><unt>{}</unt></trafficWindow>
+{fields}
+{filter}
+</fw:{req}>
+</soapenv:Body>
+</soapenv:Envelope>
+"##,
+            now.format("%Y-%m-%d %H:%M:%S"),
+            self.config.dataset,
+            begin.format(minute),
+            end.format(minute),
+        )
+    }
+}
+
+impl Default for NmB2b {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetchable for NmB2b {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// There is no token, check that the certificate can be used
+    ///
+    #[tracing::instrument(skip(self))]
+    fn authenticate(&self) -> Result<String, AuthError> {
+        trace!("nmb2b::authenticate");
+        self.identity()?;
+        Ok(String::new())
+    }
+
+    /// Get the flight list for the traffic window
+    ///
+    #[tracing::instrument(skip(self, out, _token))]
+    fn fetch(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        trace!("nmb2b::fetch");
+
+        let filter = Filter::from(args);
+        let now = Utc::now();
+        let (begin, end) = match filter {
+            Filter::Interval { begin, end } => (begin, end),
+            Filter::Duration(d) if d != 0 => (now - Duration::seconds(d.abs() as i64), now),
+            _ => (now - Duration::seconds(DEF_WINDOW), now),
+        };
+        let query = self.query(&filter)?;
+        let body = self.request(&query, begin, end, now);
+
+        let url = format!("{}{}", self.base_url, self.get);
+        trace!("Fetching {:?} through {}…", query, url);
+        let resp = self
+            .client()?
+            .post(&url)
+            .header(CONTENT_TYPE, "text/xml; charset=utf-8")
+            .body(body)
+            .send()?;
+
+        // SOAP faults come with a 500 and some XML
+        //
+        let code = resp.status();
+        let text = resp.text()?;
+        if code != StatusCode::OK {
+            debug!("{}", text);
+            return Err(eyre!("{}: HTTP error {}", self.name, code));
+        }
+
+        let all = NmFlight::from_reply(&text)?;
+        debug!("{} flights", all.len());
+        Ok(out.send(NmFlight::to_lines(&all)?)?)
+    }
+
+    fn format(&self) -> Format {
+        Format::NmB2b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use httpmock::Method::POST;
+    use httpmock::MockServer;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const REPLY: &str = r##"<S:Envelope xmlns:S="http://schemas.xmlsoap.org/soap/envelope/"><S:Body>
+<fw:FlightListByAerodromeReply xmlns:fw="eurocontrol/cfmu/b2b/FlightServices">
+<status>OK</status>
+<data><flights><flight>
+<flightId><id>AA12345678</id><keys><aircraftId>LGL4AB</aircraftId><aerodromeOfDeparture>ELLX</aerodromeOfDeparture><aerodromeOfDestination>LFPG</aerodromeOfDestination><estimatedOffBlockTime>2024-05-12 10:00</estimatedOffBlockTime></keys></flightId>
+<aircraftType>DH8D</aircraftType>
+</flight></flights></data>
+</fw:FlightListByAerodromeReply></S:Body></S:Envelope>"##;
+
+    fn setup_nm(server: &MockServer) -> NmB2b {
+        let mut s = NmB2b::new();
+        s.base_url = server.base_url();
+        s.get = "/B2B_OPS/gateway/spec/27.0.0".to_string();
+        s.config.aerodrome = Some("ELLX".to_string());
+        s
+    }
+
+    #[test]
+    fn test_nmb2b_fetch() -> Result<()> {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(POST)
+                .path("/B2B_OPS/gateway/spec/27.0.0")
+                .body_contains("<fw:FlightListByAerodromeRequest>")
+                .body_contains("<aerodrome>ELLX</aerodrome>")
+                .body_contains("<wef>2024-05-12 09:00</wef><unt>2024-05-12 11:00</unt>")
+                .body_contains("<requestedFlightFields>ctfmPointProfile</requestedFlightFields>");
+            then.status(200).body(REPLY);
+        });
+
+        let s = setup_nm(&server);
+        let begin = DateTime::parse_from_rfc3339("2024-05-12T09:00:00Z")?.to_utc();
+        let end = DateTime::parse_from_rfc3339("2024-05-12T11:00:00Z")?.to_utc();
+        let (tx, rx) = channel();
+        s.fetch(tx, "", &Filter::interval(begin, end).to_string())?;
+        m.assert();
+
+        let data = rx.recv()?;
+        assert_eq!(1, data.lines().count());
+        assert!(data.contains("LGL4AB"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nmb2b_keyword() -> Result<()> {
+        let s = NmB2b::new();
+        assert!(s.query(&Filter::None).is_err());
+        assert_eq!(
+            Query::Airspace("EBBUFIR".to_string()),
+            s.query(&Filter::keyword("airspace", "EBBUFIR"))?
+        );
+        assert!(s.query(&Filter::keyword("fir", "EBBU")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_nmb2b_soap_fault() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(POST);
+            then.status(500).body("<S:Fault/>");
+        });
+
+        let s = setup_nm(&server);
+        let (tx, _rx) = channel();
+        assert!(s.fetch(tx, "", "{}").is_err());
+    }
+
+    #[test]
+    fn test_nmb2b_certificate() -> Result<()> {
+        let mut s = NmB2b::new();
+        assert!(matches!(s.authenticate(), Err(AuthError::NoAPIKey)));
+
+        let dir = tempdir()?;
+        let cert = dir.path().join("nm.p12");
+        fs::write(&cert, "not a certificate")?;
+        s.cert = cert.to_string_lossy().to_string();
+        assert!(matches!(s.authenticate(), Err(AuthError::Invalid(_))));
+        Ok(())
+    }
+}
@@ -11,6 +11,11 @@
 //!
 //! So now we cache them.
 //!
+//! The stream polls every `delay` ms unless the site has a `poll` block, see `poll.rs` for
+//! adaptive polling.  Every decision is added to the stream statistics.
+//!
+//! A site without `auth` is used anonymously, this is how the built-in site polls `/states/all`.
+//!
 
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
@@ -21,10 +26,9 @@ use std::time::{Duration, Instant};
 use std::{thread, time};
 
 use chrono::Utc;
-use clap::{crate_name, crate_version};
 use eyre::{eyre, Result};
 use mini_moka::sync::{Cache, ConcurrentCacheExt};
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, Response};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use signal_hook::consts::TERM_SIGNALS;
@@ -33,8 +37,10 @@ use tracing::{debug, error, info, trace};
 
 use fetiche_formats::{Format, StateList};
 
-use crate::{http_get_basic, Auth, Capability, Fetchable, Filter, Streamable};
-use crate::{AuthError, Site};
+use crate::{
+    Auth, Capability, Fetchable, Filter, Observation, PollConfig, Poller, Reason, Streamable,
+};
+use crate::{AuthError, Cadence, HttpConfig, Site};
 
 /// We can go back only 1h in Opensky API
 const MAX_INTERVAL: i64 = 3600;
@@ -46,6 +52,23 @@ const CACHE_MAX: Duration = Duration::from_secs(60);
 /// Cache max entries
 const CACHE_SIZE: u64 = 20;
 
+/// Credits left for the day
+const RATE_LIMIT_REMAINING: &str = "x-rate-limit-remaining";
+/// Time to wait when rate-limited, in seconds
+const RATE_LIMIT_RETRY: &str = "x-rate-limit-retry-after-seconds";
+
+/// GET `url`, anonymously when there is no login (see the built-in `opensky` site).
+///
+fn http_get(client: &Client, url: &str, login: &str, password: &str) -> reqwest::Result<Response> {
+    let req = client.get(url).header("content-type", "application/json");
+    let req = if login.is_empty() {
+        req
+    } else {
+        req.basic_auth(login, Some(password))
+    };
+    req.send()
+}
+
 /// This si the Opensky client/source struct.
 ///
 /// FIXME: this had only the "get" route (which will be "stream" for the streamable part.
@@ -69,6 +92,8 @@ pub struct Opensky {
     pub client: Client,
     /// Running time (for streams)
     pub duration: i32,
+    /// Adaptive polling (for streams)
+    pub poll: Option<PollConfig>,
 }
 
 #[allow(dead_code)]
@@ -106,14 +131,26 @@ pub(crate) struct Stats {
     pub miss: u32,
     pub empty: u32,
     pub err: u32,
+    pub limited: u32,
+    pub delay: u32,
+    pub targets: usize,
 }
 
 impl Display for Stats {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "time={}s pkts={} bytes={} hits={} miss={} empty={} errors={}",
-            self.tm, self.pkts, self.bytes, self.hits, self.miss, self.empty, self.err
+            "time={}s pkts={} bytes={} hits={} miss={} empty={} errors={} limited={} delay={}ms targets={}",
+            self.tm,
+            self.pkts,
+            self.bytes,
+            self.hits,
+            self.miss,
+            self.empty,
+            self.err,
+            self.limited,
+            self.delay,
+            self.targets
         )
     }
 }
@@ -128,6 +165,8 @@ pub(crate) enum StatMsg {
     Miss,
     Empty,
     Error,
+    Limited,
+    Poll(Cadence),
     Print,
     Exit,
 }
@@ -144,8 +183,9 @@ impl Opensky {
             password: "".to_owned(),
             base_url: "".to_owned(),
             get: "".to_owned(),
-            client: Client::new(),
+            client: HttpConfig::default().blocking_client(),
             duration: 0,
+            poll: None,
         }
     }
 
@@ -157,6 +197,7 @@ impl Opensky {
 
         self.format = Format::from_str(&site.format).unwrap();
         self.base_url = site.base_url.to_owned();
+        self.client = site.http().blocking_client();
         if let Some(auth) = &site.auth {
             match auth {
                 Auth::Login {
@@ -171,7 +212,12 @@ impl Opensky {
         }
         // FIXME: should get the entire set of routes
         //
-        self.get = site.route("stream").unwrap().to_owned();
+        self.get = site
+            .route("stream")
+            .or(site.route("get"))
+            .unwrap()
+            .to_owned();
+        self.poll = site.poll.clone();
         self
     }
 }
@@ -231,7 +277,7 @@ impl Fetchable for Opensky {
         trace!("FetchURL: {}", url);
 
         let client = self.client.clone();
-        let resp = http_get_basic!(client, url, login, password)?;
+        let resp = http_get(&client, &url, login, password)?;
 
         debug!("{:?}", &resp);
 
@@ -334,16 +380,21 @@ impl Streamable for Opensky {
             _ => url,
         };
 
+        let mode = match self.poll {
+            Some(_) => "adaptive",
+            None => "fixed",
+        };
         info!(
             r##"
 StreamURL: {}
-Duration {}s with {}ms delay and cache with {} entries for {}s
+Duration {}s with {}ms delay ({}) and cache with {} entries for {}s
 
 <number>: data packet / ".": no traffic / "*": cache hit
         "##,
             url,
             stream_duration,
             stream_delay,
+            mode,
             CACHE_SIZE,
             CACHE_IDLE.as_secs(),
         );
@@ -389,6 +440,7 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
 
         let login = self.login.clone();
         let password = self.password.clone();
+        let mut poller = Poller::new(self.poll.clone(), stream_delay);
 
         // Launch stat gathering thread.
         //
@@ -405,6 +457,11 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                     StatMsg::Miss => stats.miss += 1,
                     StatMsg::Empty => stats.empty += 1,
                     StatMsg::Error => stats.err += 1,
+                    StatMsg::Limited => stats.limited += 1,
+                    StatMsg::Poll(c) => {
+                        stats.delay = c.delay;
+                        stats.targets = c.targets.unwrap_or_default();
+                    }
                     StatMsg::Bytes(n) => stats.bytes += n,
                     StatMsg::Print => {
                         stats.tm = start.elapsed().as_secs();
@@ -447,15 +504,7 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                 .build();
 
             loop {
-                let resp = client
-                    .get(&url)
-                    .basic_auth(&login, Some(&password))
-                    .header(
-                        "user-agent",
-                        format!("{}/{}", crate_name!(), crate_version!()),
-                    )
-                    .header("content-type", "application/json")
-                    .send();
+                let resp = http_get(&client, &url, &login, &password);
 
                 // Do not exit thread on server error, sleep and try to recover
                 //
@@ -470,6 +519,16 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                 };
                 debug!("{:?}", &resp);
 
+                // Credits left, used to slow down before being rate-limited
+                //
+                let header = |name: &str| {
+                    resp.headers()
+                        .get(name)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                };
+                let remaining = header(RATE_LIMIT_REMAINING);
+
                 // Check status of request.  We will ignore any error for now as the server
                 // does not seem to be very stable.  It tends to returns 502 for transient errors.
                 // So we sleep and continue
@@ -478,11 +537,28 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                     StatusCode::OK => {
                         trace!("OK");
                     }
+                    StatusCode::TOO_MANY_REQUESTS => {
+                        let retry = header(RATE_LIMIT_RETRY).unwrap_or_default();
+                        eprintln!("Rate-limited, retry after {}s,", retry);
+                        let c = poller.next(Observation {
+                            targets: None,
+                            remaining,
+                            limited: Some(retry),
+                        });
+                        let _ = stat_tx.send(StatMsg::Limited);
+                        let _ = stat_tx.send(StatMsg::Poll(c));
+                        thread::sleep(Duration::from_millis(c.delay as u64));
+                        continue;
+                    }
                     code => {
                         let h = &resp.headers();
                         eprintln!("Error({}): {:?},", code, h);
                         stat_tx.send(StatMsg::Error).expect("stat::error");
-                        thread::sleep(Duration::from_millis(stream_delay as u64));
+                        let c = poller.next(Observation {
+                            remaining,
+                            ..Observation::default()
+                        });
+                        thread::sleep(Duration::from_millis(c.delay as u64));
                         continue;
                     }
                 }
@@ -493,6 +569,15 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                 //
                 let sl: StateList = serde_json::from_str(buf.as_str()).expect("broken data");
 
+                // How long until the next poll
+                //
+                let c = poller.next(Observation {
+                    targets: Some(sl.states.as_ref().map_or(0, |s| s.len())),
+                    remaining,
+                    limited: None,
+                });
+                let _ = stat_tx.send(StatMsg::Poll(c));
+
                 // Check whether data was returned
                 //
                 if sl.states.is_some() {
@@ -504,7 +589,7 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                         Some(_time) => {
                             eprint!("*");
                             let _ = stat_tx.send(StatMsg::Hits);
-                            thread::sleep(Duration::from_millis(stream_delay as u64));
+                            thread::sleep(Duration::from_millis(c.delay as u64));
                             continue;
                         }
                         // No, send it it and cache its `time`
@@ -522,11 +607,12 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                     }
                 } else {
                     // Are there still entries?  If no, then we have only empty traffic for CACHE_MAX.
+                    // Adaptive polling wants to catch the next flight as soon as possible.
                     //
                     let _ = stat_tx.send(StatMsg::Empty);
 
                     cache.sync();
-                    if cache.entry_count() == 0 {
+                    if cache.entry_count() == 0 && c.reason == Reason::Fixed {
                         eprintln!("No traffic, waiting for 2s.");
                         thread::sleep(Duration::from_secs(2_u64));
                     } else {
@@ -535,8 +621,8 @@ Duration {}s with {}ms delay and cache with {} entries for {}s
                 }
 
                 // Whatever happened, sleep for to avoid CPU/network overload
-                if stream_delay != 0 {
-                    thread::sleep(Duration::from_millis(stream_delay as u64));
+                if c.delay != 0 {
+                    thread::sleep(Duration::from_millis(c.delay as u64));
                 }
             }
         });
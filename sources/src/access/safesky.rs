@@ -18,7 +18,7 @@ use tracing::trace;
 use fetiche_formats::{Format, Position};
 
 use crate::site::Site;
-use crate::{Auth, AuthError, Capability, Fetchable};
+use crate::{Auth, AuthError, Capability, Fetchable, HttpConfig};
 
 /// Define the square inside which we want beacons information
 ///
@@ -74,7 +74,7 @@ impl Safesky {
             base_url: "".to_owned(),
             api_key: "".to_owned(),
             get: "".to_owned(),
-            client: Client::new(),
+            client: HttpConfig::default().blocking_client(),
         }
     }
 
@@ -84,6 +84,7 @@ impl Safesky {
 
         self.format = Format::from_str(&site.format).unwrap();
         self.base_url = site.base_url.to_owned();
+        self.client = site.http().blocking_client();
         if let Some(auth) = &site.auth {
             match auth {
                 Auth::Key { api_key } => {
@@ -140,7 +141,7 @@ mod tests {
     use super::*;
 
     fn setup_safesky(_server: &MockServer) -> Safesky {
-        let client = Client::new();
+        let client = HttpConfig::default().blocking_client();
         Safesky {
             features: vec![Capability::Fetch],
             format: Format::Safesky,
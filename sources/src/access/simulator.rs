@@ -0,0 +1,614 @@
+//! Synthetic traffic generator
+//!
+//! This source does not connect anywhere, it generates traffic around a given location:
+//! - `drones` drones wandering randomly inside a circle of `radius` metres,
+//! - `aircraft` aircraft flying straight "airways" across the same circle at cruise level.
+//!
+//! Output is generated in the format of the site (`asd`, `opensky` or `cat21`) so pipelines,
+//! sinks and alerting can be tested (and demoed) without any credentials.  Given the same
+//! `seed`, the generated traffic is always the same.
+//!
+//! Example in `sources.hcl`:
+//!
+//! ```hcl
+//! site "simulator" {
+//!   features = ["fetch"]
+//!   type     = "drone"
+//!   format   = "asd"
+//!   base_url = "sim://localhost"
+//!   sim      = {
+//!     drones    = 10
+//!     aircraft  = 2
+//!     latitude  = 49.0097
+//!     longitude = 2.5479
+//!     radius    = 20000
+//!     interval  = 1000
+//!     seed      = 42
+//!   }
+//! }
+//! ```
+//!
+
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use csv::WriterBuilder;
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, trace};
+
+use fetiche_common::geo::{bearing, destination, flat_distance};
+use fetiche_formats::{prepare_csv, Asd, Cat21, Format, Source, StateVector};
+
+use crate::{AuthError, Capability, Fetchable, Filter, Site, Streamable};
+
+/// Upper limit on the number of generated time steps for `fetch`
+const MAX_STEPS: usize = 86_400;
+
+/// Parameters of the simulation, `sim` block of a site in `sources.hcl`
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct SimConfig {
+    /// Number of drones
+    pub drones: u32,
+    /// Number of aircraft
+    pub aircraft: u32,
+    /// Latitude of the centre of the area
+    pub latitude: f64,
+    /// Longitude of the centre of the area
+    pub longitude: f64,
+    /// Radius of the area in metres
+    pub radius: u32,
+    /// Time between two positions in ms
+    pub interval: u32,
+    /// Seed for the random generator
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            drones: 10,
+            aircraft: 0,
+            latitude: 49.0097,
+            longitude: 2.5479,
+            radius: 10_000,
+            interval: 1_000,
+            seed: 0,
+        }
+    }
+}
+
+/// Small xorshift64* generator, we do not need anything fancy but we do want reproducible runs.
+///
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(if seed == 0 {
+            0x9e37_79b9_7f4a_7c15
+        } else {
+            seed
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform value in `[lo, hi)`
+    ///
+    fn range(&mut self, lo: f64, hi: f64) -> f64 {
+        let r = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        lo + r * (hi - lo)
+    }
+}
+
+/// What kind of object we are moving
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Drone,
+    Aircraft,
+}
+
+/// One simulated object
+///
+#[derive(Clone, Debug)]
+struct Track {
+    kind: Kind,
+    /// Drone serial or ICAO address
+    ident: String,
+    callsign: String,
+    journey: u32,
+    /// Number of positions emitted so far
+    points: u32,
+    lat: f64,
+    lon: f64,
+    /// Metres
+    alt: f64,
+    /// m/s
+    speed: f64,
+    /// Degrees, true north
+    heading: f64,
+    home_lat: f64,
+    home_lon: f64,
+}
+
+/// Simulator source
+///
+#[derive(Clone, Debug)]
+pub struct Simulator {
+    /// Describe the different features of the source
+    pub features: Vec<Capability>,
+    /// Output format
+    pub format: Format,
+    /// Site name
+    pub name: String,
+    /// Simulation parameters
+    pub config: SimConfig,
+}
+
+impl Simulator {
+    #[tracing::instrument]
+    pub fn new() -> Self {
+        Simulator {
+            features: vec![Capability::Fetch, Capability::Stream],
+            format: Format::Asd,
+            name: "simulator".to_string(),
+            config: SimConfig::default(),
+        }
+    }
+
+    #[tracing::instrument]
+    pub fn load(&mut self, site: &Site) -> &mut Self {
+        trace!("simulator::load");
+
+        self.features = site.features.clone();
+        self.format = site.format();
+        self.name = site.name();
+        self.config = site.sim.clone().unwrap_or_default();
+        self
+    }
+
+    /// Create the initial set of tracks, all positions inside the circle.
+    ///
+    fn tracks(&self, rng: &mut Rng) -> Vec<Track> {
+        let cfg = &self.config;
+        let radius = cfg.radius as f64;
+
+        let mut tracks = (0..cfg.drones)
+            .map(|i| {
+                let (lat, lon) = destination(
+                    cfg.latitude,
+                    cfg.longitude,
+                    rng.range(0., 360.),
+                    rng.range(0., radius),
+                );
+                Track {
+                    kind: Kind::Drone,
+                    ident: format!("1581FSIM{:07}", i),
+                    callsign: String::new(),
+                    journey: i + 1,
+                    points: 0,
+                    lat,
+                    lon,
+                    alt: rng.range(30., 120.),
+                    speed: rng.range(2., 15.),
+                    heading: rng.range(0., 360.),
+                    home_lat: lat,
+                    home_lon: lon,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let aircraft = (0..cfg.aircraft).map(|i| {
+            // Enter on the edge of the circle, heading roughly towards the centre
+            //
+            let entry = rng.range(0., 360.);
+            let (lat, lon) = destination(cfg.latitude, cfg.longitude, entry, radius);
+            Track {
+                kind: Kind::Aircraft,
+                ident: format!("{:06x}", 0xf0_0000 + i),
+                callsign: format!("SIM{:04}", i),
+                journey: cfg.drones + i + 1,
+                points: 0,
+                lat,
+                lon,
+                alt: (rng.range(250., 390.) as i32 / 10 * 10) as f64 * 30.48,
+                speed: rng.range(200., 250.),
+                heading: (entry + 180. + rng.range(-20., 20.)).rem_euclid(360.),
+                home_lat: lat,
+                home_lon: lon,
+            }
+        });
+
+        tracks.extend(aircraft);
+        tracks
+    }
+
+    /// Move every track by `dt` seconds.
+    ///
+    fn step(&self, tracks: &mut [Track], rng: &mut Rng, dt: f64) {
+        let cfg = &self.config;
+        let radius = cfg.radius as f64;
+
+        for t in tracks.iter_mut() {
+            if t.kind == Kind::Drone {
+                t.heading = (t.heading + rng.range(-30., 30.)).rem_euclid(360.);
+                t.speed = (t.speed + rng.range(-1., 1.)).clamp(0., 20.);
+                t.alt = (t.alt + rng.range(-2., 2.)).clamp(10., 150.);
+            }
+            let (lat, lon) = destination(t.lat, t.lon, t.heading, t.speed * dt);
+            t.lat = lat;
+            t.lon = lon;
+
+            // Turn back towards the centre when leaving the area, aircraft just start a new
+            // airway.
+            //
+            if flat_distance(cfg.latitude, cfg.longitude, t.lat, t.lon) > radius {
+                let back = bearing(t.lat, t.lon, cfg.latitude, cfg.longitude);
+                t.heading = match t.kind {
+                    Kind::Drone => back,
+                    Kind::Aircraft => (back + rng.range(-20., 20.)).rem_euclid(360.),
+                };
+            }
+        }
+    }
+
+    /// Generate one snapshot of all tracks at time `tm` in our output format.
+    ///
+    fn render(&self, tracks: &mut [Track], tm: DateTime<Utc>, header: bool) -> Result<String> {
+        match self.format {
+            Format::Asd => {
+                let mut wtr = WriterBuilder::new().has_headers(header).from_writer(vec![]);
+                for t in tracks.iter_mut() {
+                    t.points += 1;
+                    wtr.serialize(to_asd(t, tm, &self.config))?;
+                }
+                Ok(String::from_utf8(wtr.into_inner()?)?)
+            }
+            Format::Opensky => {
+                let states = tracks
+                    .iter()
+                    .map(|t| {
+                        json!([
+                            t.ident,
+                            t.callsign,
+                            "Simulation",
+                            tm.timestamp(),
+                            tm.timestamp(),
+                            t.lon,
+                            t.lat,
+                            t.alt,
+                            false,
+                            t.speed,
+                            t.heading,
+                            0.,
+                            [],
+                            t.alt,
+                            "7000",
+                            false,
+                            Source::AdsB as u8,
+                        ])
+                    })
+                    .collect::<Vec<_>>();
+                Ok(json!({"time": tm.timestamp(), "states": states}).to_string())
+            }
+            Format::Cat21 => {
+                let data = tracks
+                    .iter()
+                    .map(|t| Cat21::from(&to_state(t, tm)))
+                    .collect::<Vec<_>>();
+                prepare_csv(data, header)
+            }
+            _ => Err(eyre!("simulator: unsupported format {}", self.format)),
+        }
+    }
+
+    /// Compute the list of timestamps to generate for a `fetch`.
+    ///
+    fn steps(&self, filter: Filter) -> Vec<DateTime<Utc>> {
+        let now = Utc::now();
+        let (begin, end) = match filter {
+            Filter::Interval { begin, end } => (begin, end),
+            Filter::Duration(d) => (now - chrono::Duration::seconds(d.abs() as i64), now),
+            _ => (now, now),
+        };
+        let interval = self.config.interval.max(1) as i64;
+
+        (0..)
+            .map(|i| begin + chrono::Duration::milliseconds(i * interval))
+            .take_while(|tm| *tm <= end)
+            .take(MAX_STEPS)
+            .collect()
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fetchable for Simulator {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Nothing to authenticate against
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    /// Generate the whole interval (or a single snapshot) in one go.
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn fetch(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        trace!("simulator::fetch");
+
+        let mut rng = Rng::new(self.config.seed);
+        let mut tracks = self.tracks(&mut rng);
+        let dt = self.config.interval as f64 / 1000.;
+
+        let steps = self.steps(Filter::from(args));
+        debug!("{} steps of {} tracks", steps.len(), tracks.len());
+
+        let data = steps
+            .iter()
+            .enumerate()
+            .map(|(i, tm)| {
+                if i != 0 {
+                    self.step(&mut tracks, &mut rng, dt);
+                }
+                self.render(&mut tracks, *tm, i == 0)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // Opensky payloads are separate JSON documents, CSV is a single file.
+        //
+        let data = match self.format {
+            Format::Opensky => data.join("\n"),
+            _ => data.concat(),
+        };
+        Ok(out.send(data)?)
+    }
+
+    fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl Streamable for Simulator {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Nothing to authenticate against
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    /// Send one snapshot every `interval` ms, for `duration` seconds (or forever if 0).
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn stream(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        trace!("simulator::stream");
+
+        let duration = match Filter::from(args) {
+            Filter::Stream { duration, .. } => duration,
+            _ => 0,
+        };
+        let interval = Duration::from_millis(self.config.interval as u64);
+        let dt = self.config.interval as f64 / 1000.;
+
+        let mut rng = Rng::new(self.config.seed);
+        let mut tracks = self.tracks(&mut rng);
+
+        let start = Instant::now();
+        loop {
+            let data = self.render(&mut tracks, Utc::now(), true)?;
+            if out.send(data).is_err() {
+                debug!("receiver gone, stopping");
+                break;
+            }
+            if duration != 0 && start.elapsed().as_secs() >= duration as u64 {
+                debug!("end of stream after {}s", duration);
+                break;
+            }
+            thread::sleep(interval);
+            self.step(&mut tracks, &mut rng, dt);
+        }
+        Ok(())
+    }
+
+    fn format(&self) -> Format {
+        self.format
+    }
+}
+
+/// Generate an ASD record from a track, the detecting station is at the centre of the area.
+///
+fn to_asd(t: &Track, tm: DateTime<Utc>, cfg: &SimConfig) -> Asd {
+    Asd {
+        time: tm,
+        journey: t.journey,
+        // Drone serials are truncated when converting, pad ICAO addresses to a similar length
+        ident: format!("{:0>15}", t.ident),
+        model: Some(
+            match t.kind {
+                Kind::Drone => "Simulated drone",
+                Kind::Aircraft => "Simulated aircraft",
+            }
+            .to_string(),
+        ),
+        source: "simulator".to_string(),
+        location: t.points,
+        timestamp: tm.format("%Y-%m-%d %H:%M:%S").to_string(),
+        latitude: t.lat as f32,
+        longitude: t.lon as f32,
+        altitude: Some(t.alt as i16),
+        elevation: Some(t.alt as i32),
+        gps: Some(12),
+        rssi: None,
+        home_lat: Some(t.home_lat as f32),
+        home_lon: Some(t.home_lon as f32),
+        home_height: Some(t.alt as f32),
+        speed: t.speed as f32,
+        heading: t.heading as f32,
+        station_name: Some("simulator".to_string()),
+        station_latitude: Some(cfg.latitude as f32),
+        station_longitude: Some(cfg.longitude as f32),
+    }
+}
+
+/// Generate an Opensky state vector from a track
+///
+fn to_state(t: &Track, tm: DateTime<Utc>) -> StateVector {
+    StateVector {
+        icao24: t.ident.clone(),
+        callsign: Some(t.callsign.clone()),
+        origin_country: "Simulation".to_string(),
+        time_position: Some(tm.timestamp() as i32),
+        last_contact: tm.timestamp() as i32,
+        longitude: Some(t.lon as f32),
+        latitude: Some(t.lat as f32),
+        baro_altitude: Some(t.alt as f32),
+        on_ground: false,
+        velocity: Some(t.speed as f32),
+        true_track: Some(t.heading as f32),
+        vertical_rate: Some(0.),
+        sensors: None,
+        geo_altitude: Some(t.alt as f32),
+        squawk: Some("7000".to_string()),
+        spi: false,
+        position_source: Source::AdsB,
+        category: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use chrono::TimeZone;
+
+    use fetiche_formats::StateList;
+
+    use super::*;
+
+    fn epoch(t: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(t, 0).unwrap()
+    }
+
+    fn sim(format: Format, drones: u32, aircraft: u32) -> Simulator {
+        let mut s = Simulator::new();
+        s.format = format;
+        s.config.drones = drones;
+        s.config.aircraft = aircraft;
+        s.config.seed = 42;
+        s
+    }
+
+    #[test]
+    fn test_rng_reproducible() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        assert_eq!(a.next_u64(), b.next_u64());
+        let r = a.range(-1., 1.);
+        assert!((-1. ..1.).contains(&r));
+    }
+
+    #[test]
+    fn test_tracks_inside_area() {
+        let s = sim(Format::Asd, 20, 5);
+        let mut rng = Rng::new(42);
+        let mut tracks = s.tracks(&mut rng);
+        assert_eq!(25, tracks.len());
+
+        for _ in 0..600 {
+            s.step(&mut tracks, &mut rng, 1.);
+        }
+        // Drones are slow enough to never leave the area by more than one step
+        //
+        tracks
+            .iter()
+            .filter(|t| t.kind == Kind::Drone)
+            .for_each(|t| {
+                let d = flat_distance(s.config.latitude, s.config.longitude, t.lat, t.lon);
+                assert!(d <= s.config.radius as f64 + 20.);
+            });
+    }
+
+    #[test]
+    fn test_fetch_asd_interval() {
+        let s = sim(Format::Asd, 3, 1);
+        let (tx, rx) = channel();
+
+        let filter = Filter::interval(epoch(0), epoch(9));
+        s.fetch(tx, "", &filter.to_string()).unwrap();
+
+        let data = rx.recv().unwrap();
+        // header + 10 steps of 4 tracks
+        assert_eq!(41, data.lines().count());
+
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let recs = rdr.deserialize::<Asd>().collect::<Result<Vec<_>, _>>();
+        assert!(recs.is_ok());
+
+        // Must survive conversion
+        let recs = recs.unwrap();
+        assert_eq!(40, recs.iter().map(Cat21::from).count());
+    }
+
+    #[test]
+    fn test_fetch_reproducible() {
+        let s = sim(Format::Cat21, 2, 2);
+        let filter = Filter::interval(epoch(0), epoch(5)).to_string();
+
+        let (tx, rx) = channel();
+        s.fetch(tx.clone(), "", &filter).unwrap();
+        s.fetch(tx, "", &filter).unwrap();
+        assert_eq!(rx.recv().unwrap(), rx.recv().unwrap());
+    }
+
+    #[test]
+    fn test_render_opensky() {
+        let s = sim(Format::Opensky, 2, 3);
+        let mut rng = Rng::new(1);
+        let mut tracks = s.tracks(&mut rng);
+
+        let data = s.render(&mut tracks, epoch(1_700_000_000), true).unwrap();
+        let list = StateList::from_json(&data).unwrap();
+        assert_eq!(1_700_000_000, list.time);
+        assert_eq!(5, list.states.unwrap().len());
+    }
+
+    #[test]
+    fn test_render_unsupported() {
+        let s = sim(Format::Safesky, 1, 0);
+        let mut tracks = s.tracks(&mut Rng::new(1));
+        assert!(s.render(&mut tracks, epoch(0), true).is_err());
+    }
+
+    #[test]
+    fn test_stream_duration() {
+        let mut s = sim(Format::Asd, 1, 0);
+        s.config.interval = 10;
+        let (tx, rx) = channel();
+
+        s.stream(tx, "", &Filter::stream(0, 1, 0).to_string())
+            .unwrap();
+        assert!(rx.iter().count() > 1);
+    }
+}
@@ -0,0 +1,416 @@
+//! U-space service providers (USSP) specifics
+//!
+//! Sandboxes implementing the ASTM F3548 USS to USS API are polled for operational intent
+//! telemetry (see `fetiche_formats::Utm`).  Access is through OAuth2, the token is obtained with
+//! the client credentials grant from `token_url` before each fetch or stream and refreshed if
+//! the server says it has expired.
+//!
+//! The `get` route can return either a single `GetOperationalIntentTelemetryResponse` or an
+//! array of them, responses without telemetry are dropped and each record is sent as one JSON
+//! object per line.  When streaming, a record is only sent again if its `time_measured` changed.
+//!
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use eyre::{eyre, Result};
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace, warn};
+
+use fetiche_formats::{Format, Utm};
+
+use crate::{
+    http_get_auth, Auth, AuthError, Capability, Fetchable, Filter, HttpConfig, Site, Streamable,
+};
+
+/// Scope needed to read telemetry
+const DEF_SCOPE: &str = "utm.conformance_monitoring_sa";
+
+/// Delay between two polls if not specified, in ms
+const DEF_DELAY: u32 = 1_000;
+
+/// Parameters for the client credentials grant
+///
+#[derive(Debug, Serialize)]
+struct Grant<'a> {
+    grant_type: &'a str,
+    client_id: &'a str,
+    client_secret: &'a str,
+    scope: &'a str,
+}
+
+/// What we get back from the token endpoint
+///
+#[derive(Debug, Deserialize)]
+struct Token {
+    access_token: String,
+    /// Lifetime in seconds
+    expires_in: Option<u64>,
+}
+
+/// The telemetry route returns one or several responses
+///
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Reply {
+    One(Box<Utm>),
+    Many(Vec<Utm>),
+}
+
+/// USSP client
+///
+#[derive(Clone, Debug)]
+pub struct Ussp {
+    /// Name of the site
+    pub name: String,
+    /// Describe the different features of the source
+    pub features: Vec<Capability>,
+    /// Input formats
+    pub format: Format,
+    /// Base site url taken from config
+    pub base_url: String,
+    /// Add this to `base_url` to fetch data
+    pub get: String,
+    /// OAuth2 token endpoint (full URL)
+    pub token_url: String,
+    /// OAuth2 client ID
+    pub client_id: String,
+    /// OAuth2 client secret
+    pub client_secret: String,
+    /// Requested scope
+    pub scope: String,
+    /// reqwest blocking client
+    pub client: Client,
+}
+
+impl Ussp {
+    #[tracing::instrument]
+    pub fn new() -> Self {
+        trace!("ussp::new");
+
+        Ussp {
+            name: "ussp".to_string(),
+            features: vec![Capability::Fetch],
+            format: Format::Utm,
+            base_url: "".to_owned(),
+            get: "".to_owned(),
+            token_url: "".to_owned(),
+            client_id: "".to_owned(),
+            client_secret: "".to_owned(),
+            scope: DEF_SCOPE.to_owned(),
+            client: HttpConfig::default().blocking_client(),
+        }
+    }
+
+    #[tracing::instrument]
+    pub fn load(&mut self, site: &Site) -> &mut Self {
+        trace!("ussp::load");
+
+        self.name = site.name();
+        self.features = site.features.clone();
+        self.format = Format::from_str(&site.format).unwrap();
+        self.base_url = site.base_url.to_owned();
+        self.client = site.http().blocking_client();
+        if let Some(Auth::Oauth2 {
+            client_id,
+            client_secret,
+            token_url,
+            scope,
+        }) = &site.auth
+        {
+            self.client_id = client_id.to_owned();
+            self.client_secret = client_secret.to_owned();
+            self.token_url = token_url.to_owned();
+            if let Some(scope) = scope {
+                self.scope = scope.to_owned();
+            }
+        }
+        self.get = site.route("get").unwrap().to_owned();
+        self
+    }
+
+    /// Get a token from the token endpoint
+    ///
+    #[tracing::instrument(skip(self))]
+    fn token(&self) -> Result<Token, AuthError> {
+        if self.client_id.is_empty() || self.token_url.is_empty() {
+            return Err(AuthError::NoAPIKey);
+        }
+
+        let grant = Grant {
+            grant_type: "client_credentials",
+            client_id: &self.client_id,
+            client_secret: &self.client_secret,
+            scope: &self.scope,
+        };
+        trace!("Fetching token through {}…", self.token_url);
+        let resp = self
+            .client
+            .post(&self.token_url)
+            .form(&grant)
+            .send()
+            .map_err(|e| AuthError::HTTP(e.to_string()))?;
+
+        if resp.status() != StatusCode::OK {
+            return Err(AuthError::HTTP(resp.status().to_string()));
+        }
+        let resp = resp
+            .text()
+            .map_err(|_| AuthError::Retrieval(self.client_id.clone()))?;
+        let token: Token =
+            serde_json::from_str(&resp).map_err(|_| AuthError::Decoding(self.client_id.clone()))?;
+        debug!("token valid for {:?}s", token.expires_in);
+        Ok(token)
+    }
+
+    /// Poll once, returns `None` if the token has expired
+    ///
+    #[tracing::instrument(skip(self, token))]
+    fn poll(&self, token: &str) -> Result<Option<Vec<Utm>>> {
+        let url = format!("{}{}", self.base_url, self.get);
+        trace!("Fetching data through {}…", url);
+
+        let resp = http_get_auth!(self, url, token)?;
+        match resp.status() {
+            StatusCode::OK => (),
+            StatusCode::UNAUTHORIZED => return Ok(None),
+            code => return Err(eyre!("{}: HTTP error {}", self.name, code)),
+        }
+
+        let all = match resp.json::<Reply>()? {
+            Reply::One(r) => vec![*r],
+            Reply::Many(r) => r,
+        };
+        Ok(Some(
+            all.into_iter().filter(|r| r.telemetry.is_some()).collect(),
+        ))
+    }
+
+    /// Poll and get a new token once if needed
+    ///
+    fn poll_with_refresh(&self, token: &mut String) -> Result<Vec<Utm>> {
+        if let Some(all) = self.poll(token)? {
+            return Ok(all);
+        }
+        info!("{}: token expired, getting a new one", self.name);
+        *token = self.token()?.access_token;
+        self.poll(token)?
+            .ok_or(eyre!("{}: still unauthorised with a new token", self.name))
+    }
+}
+
+impl Default for Ussp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One JSON object per line
+///
+fn to_lines(all: &[Utm]) -> Result<String> {
+    let lines = all
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(lines.join("\n"))
+}
+
+impl Fetchable for Ussp {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Client credentials grant, returns the access token
+    ///
+    #[tracing::instrument(skip(self))]
+    fn authenticate(&self) -> Result<String, AuthError> {
+        trace!("ussp::authenticate");
+        Ok(self.token()?.access_token)
+    }
+
+    /// Get the current telemetry
+    ///
+    #[tracing::instrument(skip(self, out, token))]
+    fn fetch(&self, out: Sender<String>, token: &str, _args: &str) -> Result<()> {
+        trace!("ussp::fetch");
+
+        let mut token = token.to_string();
+        let all = self.poll_with_refresh(&mut token)?;
+        debug!("{} records", all.len());
+        Ok(out.send(to_lines(&all)?)?)
+    }
+
+    fn format(&self) -> Format {
+        Format::Utm
+    }
+}
+
+impl Streamable for Ussp {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn authenticate(&self) -> Result<String, AuthError> {
+        trace!("ussp::authenticate");
+        Ok(self.token()?.access_token)
+    }
+
+    /// Poll every `delay` ms for `duration` seconds (or forever if 0), only new measurements
+    /// are sent.
+    ///
+    #[tracing::instrument(skip(self, out, token))]
+    fn stream(&self, out: Sender<String>, token: &str, args: &str) -> Result<()> {
+        trace!("ussp::stream");
+
+        let (duration, delay) = match Filter::from(args) {
+            Filter::Stream {
+                duration, delay, ..
+            } => (duration, if delay == 0 { DEF_DELAY } else { delay }),
+            _ => (0, DEF_DELAY),
+        };
+        let delay = Duration::from_millis(delay as u64);
+
+        let mut token = token.to_string();
+        let mut seen = BTreeMap::<String, DateTime<Utc>>::new();
+        let start = Instant::now();
+        loop {
+            match self.poll_with_refresh(&mut token) {
+                Ok(all) => {
+                    let new = all
+                        .into_iter()
+                        .filter(|r| {
+                            let tm = r.time().unwrap_or_default();
+                            seen.insert(r.operational_intent_id.clone(), tm) != Some(tm)
+                        })
+                        .collect::<Vec<_>>();
+                    if !new.is_empty() && out.send(to_lines(&new)?).is_err() {
+                        debug!("receiver gone, stopping");
+                        break;
+                    }
+                }
+                // Sandboxes are not always up, keep polling
+                //
+                Err(e) => warn!("{}: {}", self.name, e),
+            }
+            if duration != 0 && start.elapsed().as_secs() >= duration as u64 {
+                debug!("end of stream after {}s", duration);
+                break;
+            }
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+
+    fn format(&self) -> Format {
+        Format::Utm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use httpmock::Method::{GET, POST};
+    use httpmock::MockServer;
+
+    use super::*;
+
+    const REPLY: &str = r##"[
+  {
+    "operational_intent_id": "2f8343be-6482-4d1b-a474-16847cf5b5b6",
+    "telemetry": {
+      "time_measured": {"value": "2024-05-12T10:30:15Z", "format": "RFC3339"},
+      "position": {"longitude": 6.2061, "latitude": 49.6116},
+      "velocity": {"speed": 10.0, "units_speed": "MetersPerSecond", "track": 270.5}
+    }
+  },
+  {"operational_intent_id": "a3b1a9c4-0000-4000-8000-000000000000"}
+]"##;
+
+    fn setup_ussp(server: &MockServer) -> Ussp {
+        let mut s = Ussp::new();
+        s.base_url = server.base_url();
+        s.get = "/uss/v1/telemetry".to_string();
+        s.token_url = server.url("/token");
+        s.client_id = "fetiche".to_string();
+        s.client_secret = "NOPE".to_string();
+        s
+    }
+
+    #[test]
+    fn test_ussp_authenticate() {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(POST)
+                .path("/token")
+                .body_contains("grant_type=client_credentials")
+                .body_contains("scope=utm.conformance_monitoring_sa");
+            then.status(200)
+                .body(r##"{"access_token": "TOKEN", "token_type": "Bearer", "expires_in": 3600}"##);
+        });
+
+        let s = setup_ussp(&server);
+        assert_eq!("TOKEN", Fetchable::authenticate(&s).unwrap());
+        m.assert();
+    }
+
+    #[test]
+    fn test_ussp_no_credentials() {
+        let s = Ussp::new();
+        assert!(Fetchable::authenticate(&s).is_err());
+    }
+
+    #[test]
+    fn test_ussp_fetch() -> Result<()> {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(GET)
+                .path("/uss/v1/telemetry")
+                .header("authorization", "Bearer TOKEN");
+            then.status(200).body(REPLY);
+        });
+
+        let s = setup_ussp(&server);
+        let (tx, rx) = channel();
+        s.fetch(tx, "TOKEN", "{}")?;
+        m.assert();
+
+        let data = rx.recv()?;
+        assert_eq!(1, data.lines().count());
+        assert!(data.contains("2f8343be"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ussp_fetch_refresh() -> Result<()> {
+        let server = MockServer::start();
+        let old = server.mock(|when, then| {
+            when.method(GET).header("authorization", "Bearer OLD");
+            then.status(401);
+        });
+        let token = server.mock(|when, then| {
+            when.method(POST).path("/token");
+            then.status(200).body(r##"{"access_token": "TOKEN"}"##);
+        });
+        let new = server.mock(|when, then| {
+            when.method(GET).header("authorization", "Bearer TOKEN");
+            then.status(200).body(REPLY);
+        });
+
+        let s = setup_ussp(&server);
+        let (tx, rx) = channel();
+        s.fetch(tx, "OLD", "{}")?;
+        old.assert();
+        token.assert();
+        new.assert();
+        assert!(rx.recv()?.contains("2f8343be"));
+        Ok(())
+    }
+}
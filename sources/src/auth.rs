@@ -22,6 +22,82 @@ pub enum Auth {
     },
     /// Using plain login/password
     Login { username: String, password: String },
+    /// OAuth2 client credentials, the token is obtained from `token_url`
+    Oauth2 {
+        client_id: String,
+        client_secret: String,
+        token_url: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scope: Option<String>,
+    },
+    /// Client certificate (PKCS#12 file) for mutual TLS
+    Certificate { cert: String, password: String },
+}
+
+/// Value of all credentials in generated configuration files, to be replaced
+pub const PLACEHOLDER: &str = "CHANGE_ME";
+
+impl Auth {
+    /// Credentials with placeholders for the kind of authentication named in the default
+    /// `sources.hcl` (`token`, `login`, `api_key`, `user_key`, `oauth2` or `certificate`).
+    ///
+    pub fn placeholder(kind: &str) -> Option<Auth> {
+        let p = || PLACEHOLDER.to_string();
+        match kind {
+            "token" => Some(Auth::Token {
+                login: p(),
+                password: p(),
+                token: "/login".to_string(),
+            }),
+            "login" => Some(Auth::Login {
+                username: p(),
+                password: p(),
+            }),
+            "api_key" => Some(Auth::Key { api_key: p() }),
+            "user_key" => Some(Auth::UserKey {
+                api_key: p(),
+                user_key: p(),
+            }),
+            "oauth2" => Some(Auth::Oauth2 {
+                client_id: p(),
+                client_secret: p(),
+                token_url: p(),
+                scope: None,
+            }),
+            "certificate" => Some(Auth::Certificate {
+                cert: p(),
+                password: p(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Whether some credentials are still placeholders
+    ///
+    pub fn is_placeholder(&self) -> bool {
+        match self {
+            Auth::Anon => false,
+            Auth::Key { api_key } => api_key == PLACEHOLDER,
+            Auth::UserKey { api_key, user_key } => {
+                api_key == PLACEHOLDER || user_key == PLACEHOLDER
+            }
+            Auth::Token {
+                login, password, ..
+            } => login == PLACEHOLDER || password == PLACEHOLDER,
+            Auth::Login { username, password } => {
+                username == PLACEHOLDER || password == PLACEHOLDER
+            }
+            Auth::Oauth2 {
+                client_id,
+                client_secret,
+                token_url,
+                ..
+            } => {
+                client_id == PLACEHOLDER || client_secret == PLACEHOLDER || token_url == PLACEHOLDER
+            }
+            Auth::Certificate { cert, password } => cert == PLACEHOLDER || password == PLACEHOLDER,
+        }
+    }
 }
 
 impl Display for Auth {
@@ -48,6 +124,21 @@ impl Display for Auth {
                 token,
                 password: "HIDDEN".to_string(),
             },
+            Auth::Oauth2 {
+                client_id,
+                token_url,
+                scope,
+                ..
+            } => Auth::Oauth2 {
+                client_id,
+                client_secret: "HIDDEN".to_string(),
+                token_url,
+                scope,
+            },
+            Auth::Certificate { cert, .. } => Auth::Certificate {
+                cert,
+                password: "HIDDEN".to_string(),
+            },
             _ => Auth::Anon,
         };
         write!(f, "{:?}", auth)
@@ -16,6 +16,8 @@ pub enum AuthError {
     Storing(String),
     #[error("Token expired")]
     Expired,
+    #[error("Token rejected by {0}")]
+    Rejected(String),
     #[error("Invalid token in {0}")]
     Invalid(String),
     #[error("Unknown error.")]
@@ -0,0 +1,371 @@
+//! Site groups, one logical site served by several regional endpoints.
+//!
+//! Some providers expose the same API in different regions.  A `group` in `sources.hcl` takes
+//! a site as template and lists its endpoints in order of preference:
+//!
+//! ```hcl
+//! group "asd-any" {
+//!   site      = "asd"
+//!   endpoints = ["https://eur.airspacedrone.com/api", "https://us.airspacedrone.com/api"]
+//! }
+//! ```
+//!
+//! The group is then used like any other site.  Each endpoint is tried in turn, the next one
+//! being used only on connection errors (refused, reset, timeout or unreachable authentication
+//! endpoint), any other error is returned as-is.  The endpoint which served the data is
+//! recorded in the `Provenance` shared by all clones of a given `Sources`.
+//!
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, trace, warn};
+
+use fetiche_formats::Format;
+
+use crate::{AuthError, Fetchable, Flow, Sources, Streamable};
+
+/// A group as defined in `sources.hcl`
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    /// Site used as template for every endpoint
+    pub site: String,
+    /// Base URLs, in order of preference
+    pub endpoints: Vec<String>,
+}
+
+impl Group {
+    /// Create the access object for the group `name`, one copy of the template site per
+    /// endpoint.
+    ///
+    #[tracing::instrument(skip(self, cfg))]
+    pub fn load(&self, name: &str, cfg: &Sources) -> Result<Flow> {
+        let site = cfg
+            .get(&self.site)
+            .ok_or_else(|| eyre!("group {}: no such site {}", name, self.site))?;
+        if self.endpoints.is_empty() {
+            return Err(eyre!("group {}: no endpoint", name));
+        }
+
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|url| {
+                let mut site = site.clone();
+                site.base_url = url.clone();
+                Ok((url.clone(), site.flow()?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let f = Failover {
+            name: name.to_string(),
+            format: site.format(),
+            endpoints,
+            provenance: cfg.provenance(),
+        };
+        if site.is_streamable() {
+            Ok(Flow::Streamable(Box::new(f)))
+        } else {
+            Ok(Flow::Fetchable(Box::new(f)))
+        }
+    }
+}
+
+/// What a group has been doing
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct GroupStats {
+    /// Endpoint which served the data last
+    pub endpoint: String,
+    /// Number of successful runs
+    pub served: usize,
+    /// Number of times the next endpoint had to be used
+    pub failovers: usize,
+}
+
+/// Keep track of which endpoint served each group.
+///
+#[derive(Debug, Default)]
+pub struct Provenance {
+    groups: Mutex<BTreeMap<String, GroupStats>>,
+}
+
+impl Provenance {
+    /// Record one run of `group`
+    ///
+    pub fn record(&self, group: &str, endpoint: &str, served: bool, failovers: usize) {
+        let mut groups = self.groups.lock().unwrap();
+        let stats = groups.entry(group.to_string()).or_default();
+        if served {
+            stats.endpoint = endpoint.to_string();
+            stats.served += 1;
+        }
+        stats.failovers += failovers;
+    }
+
+    /// Current statistics for `group`
+    ///
+    pub fn get(&self, group: &str) -> Option<GroupStats> {
+        self.groups.lock().unwrap().get(group).cloned()
+    }
+
+    /// Return everything recorded since the last call
+    ///
+    pub fn take(&self) -> BTreeMap<String, GroupStats> {
+        std::mem::take(&mut *self.groups.lock().unwrap())
+    }
+}
+
+/// Is this error worth trying another endpoint?
+///
+pub fn is_connection_error(e: &eyre::Report) -> bool {
+    e.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            return e.is_connect() || e.is_timeout();
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::TimedOut
+                    | ErrorKind::HostUnreachable
+                    | ErrorKind::NetworkUnreachable
+            );
+        }
+        matches!(e.downcast_ref::<AuthError>(), Some(AuthError::HTTP(_)))
+    })
+}
+
+/// Access object for a group, trying each endpoint in turn.
+///
+#[derive(Debug)]
+pub struct Failover {
+    /// Name of the group
+    name: String,
+    /// Format of the template site
+    format: Format,
+    /// Endpoints with their access object
+    endpoints: Vec<(String, Flow)>,
+    /// Where to record which endpoint was used
+    provenance: Arc<Provenance>,
+}
+
+impl Failover {
+    /// Run `args` on each endpoint until one does not fail with a connection error.
+    /// Each endpoint does its own authentication.
+    ///
+    #[tracing::instrument(skip(self, out))]
+    fn run(&self, out: Sender<String>, args: &str) -> Result<()> {
+        let mut failovers = 0;
+        let mut last = None;
+
+        for (url, flow) in self.endpoints.iter() {
+            trace!("{}: trying {}", self.name, url);
+            let res = match flow {
+                Flow::Fetchable(s) => token(s.authenticate(), || s.authenticate())
+                    .and_then(|t| s.fetch(out.clone(), &t, args)),
+                Flow::Streamable(s) => token(s.authenticate(), || s.authenticate())
+                    .and_then(|t| s.stream(out.clone(), &t, args)),
+            };
+            match res {
+                Ok(()) => {
+                    info!("{} served by {}", self.name, url);
+                    self.provenance.record(&self.name, url, true, failovers);
+                    return Ok(());
+                }
+                Err(e) if is_connection_error(&e) => {
+                    warn!(
+                        "{}: {} failed ({}), trying next endpoint",
+                        self.name, url, e
+                    );
+                    failovers += 1;
+                    last = Some(e);
+                }
+                Err(e) => {
+                    self.provenance.record(&self.name, url, false, failovers);
+                    return Err(e);
+                }
+            }
+        }
+        // We tried one endpoint too many
+        //
+        self.provenance
+            .record(&self.name, "", false, failovers.saturating_sub(1));
+        Err(last.unwrap_or_else(|| eyre!("group {}: no endpoint", self.name)))
+    }
+}
+
+/// Get a token, trying again once if it has expired
+///
+fn token(
+    res: Result<String, AuthError>,
+    again: impl Fn() -> Result<String, AuthError>,
+) -> Result<String> {
+    match res {
+        Err(AuthError::Expired) => Ok(again()?),
+        res => Ok(res?),
+    }
+}
+
+impl Fetchable for Failover {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Endpoints authenticate themselves when used
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    fn fetch(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        self.run(out, args)
+    }
+
+    fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl Streamable for Failover {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Endpoints authenticate themselves when used
+    ///
+    fn authenticate(&self) -> Result<String, AuthError> {
+        Ok(String::new())
+    }
+
+    fn stream(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+        self.run(out, args)
+    }
+
+    fn format(&self) -> Format {
+        self.format
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::mpsc::channel;
+
+    use super::*;
+
+    /// Endpoint failing with the given error kind or sending its name
+    ///
+    #[derive(Debug)]
+    struct Endpoint(Option<ErrorKind>);
+
+    impl Fetchable for Endpoint {
+        fn name(&self) -> String {
+            "test".to_string()
+        }
+
+        fn authenticate(&self) -> Result<String, AuthError> {
+            Ok(String::new())
+        }
+
+        fn fetch(&self, out: Sender<String>, _token: &str, args: &str) -> Result<()> {
+            match self.0 {
+                Some(kind) => Err(io::Error::from(kind).into()),
+                None => Ok(out.send(args.to_string())?),
+            }
+        }
+
+        fn format(&self) -> Format {
+            Format::Asd
+        }
+    }
+
+    fn failover(all: Vec<Option<ErrorKind>>) -> Failover {
+        Failover {
+            name: "any".to_string(),
+            format: Format::Asd,
+            endpoints: all
+                .into_iter()
+                .enumerate()
+                .map(|(i, e)| {
+                    let f: Box<dyn Fetchable> = Box::new(Endpoint(e));
+                    (format!("http://{}", i), Flow::Fetchable(f))
+                })
+                .collect(),
+            provenance: Arc::new(Provenance::default()),
+        }
+    }
+
+    #[test]
+    fn test_failover_next() -> Result<()> {
+        let f = failover(vec![Some(ErrorKind::ConnectionRefused), None, None]);
+        let (tx, rx) = channel();
+
+        f.fetch(tx, "", "data")?;
+        assert_eq!("data", rx.recv()?);
+
+        let stats = f.provenance.get("any").unwrap();
+        assert_eq!("http://1", stats.endpoint);
+        assert_eq!(1, stats.served);
+        assert_eq!(1, stats.failovers);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failover_other_error() {
+        let f = failover(vec![Some(ErrorKind::InvalidData), None]);
+        let (tx, _rx) = channel();
+
+        assert!(f.fetch(tx, "", "data").is_err());
+        assert_eq!(0, f.provenance.get("any").unwrap().served);
+    }
+
+    #[test]
+    fn test_failover_all_down() {
+        let f = failover(vec![
+            Some(ErrorKind::TimedOut),
+            Some(ErrorKind::ConnectionReset),
+        ]);
+        let (tx, _rx) = channel();
+
+        assert!(f.fetch(tx, "", "data").is_err());
+        let stats = f.provenance.take();
+        assert_eq!(1, stats["any"].failovers);
+        assert!(f.provenance.get("any").is_none());
+    }
+
+    #[test]
+    fn test_group_load() -> Result<()> {
+        let cfg = r##"
+site "sim" {
+  features = ["fetch"]
+  type     = "drone"
+  format   = "asd"
+  base_url = "sim://localhost"
+  sim      = {
+    drones = 1
+  }
+}
+
+group "sim-any" {
+  site      = "sim"
+  endpoints = ["sim://eu", "sim://us"]
+}
+"##;
+        let cfg: Sources = hcl::from_str(cfg)?;
+        let f = crate::Site::load("sim-any", &cfg)?;
+        assert_eq!("sim-any", f.name());
+        assert_eq!(Format::Asd, f.format());
+        assert!(crate::Site::load("sim-none", &cfg).is_err());
+        Ok(())
+    }
+}
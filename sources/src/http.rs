@@ -0,0 +1,594 @@
+//! Shared async HTTP layer for the `AsyncFetchable` sources.
+//!
+//! All async sources use the same helpers, so they send the same headers, and the sync
+//! `Fetchable` adapter runs them on a single runtime shared by the whole process instead of
+//! every `reqwest::blocking::Client` starting its own thread.
+//!
+//! Clients of all sources, sync or async, are created from the `http` block of their site so
+//! the user agent and the pinned headers are the same for every request:
+//!
+//! ```hcl
+//! site "opensky" {
+//!   ...
+//!   http = {
+//!     user_agent  = "acute (ops@example.org)"
+//!     accept      = "application/json"
+//!     api_version = "2"
+//!     headers     = {
+//!       "x-client-id" = "acute"
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! `user_agent` is appended to ours, `api_version` is sent as `version_header` (`x-api-version` by
+//! default) and `headers` are sent with every request unless the request sets them itself.
+//!
+//! Clients ask for compressed answers (`gzip`, `deflate`, `br` and `zstd`) and decompress them
+//! transparently, `compression = false` turns that off for servers getting it wrong.
+//!
+//! Connections give up after `connect_timeout` seconds (10 by default) so a dead sensor does not
+//! hold a worker for minutes, and reads after `read_timeout` seconds (30 by default, the whole
+//! request for sync clients).  `tcp_keepalive` is the interval of TCP keepalives in seconds (60
+//! by default), `0` turns a timeout or keepalives off.  HTTP/2 is used when the server offers it,
+//! `http2 = false` sticks to HTTP/1.1 and `http2 = true` only talks HTTP/2.  Raw TCP sources
+//! (BaseStation, Flightaware) use `connect_timeout` too, see `tcp_connect()`.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use clap::{crate_name, crate_version};
+use eyre::{eyre, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT};
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::{Builder, Handle, Runtime};
+use tracing::{trace, warn};
+
+/// Number of worker threads of the shared runtime
+const WORKERS: usize = 2;
+
+/// Shared runtime for the sync adapter
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Default header for `api_version`
+const VERSION_HEADER: &str = "x-api-version";
+
+/// Default connect timeout in seconds
+pub const DEF_CONNECT_TIMEOUT: u64 = 10;
+
+/// Default read timeout in seconds, the default of `reqwest` sync clients
+pub const DEF_READ_TIMEOUT: u64 = 30;
+
+/// Default interval of TCP keepalives in seconds
+pub const DEF_TCP_KEEPALIVE: u64 = 60;
+
+/// Our user agent
+///
+pub(crate) fn user_agent() -> String {
+    format!("{}/{}", crate_name!(), crate_version!())
+}
+
+/// HTTP settings of a site, `http` block in `sources.hcl`
+///
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Appended to our user agent
+    pub user_agent: Option<String>,
+    /// Pinned `Accept` header
+    pub accept: Option<String>,
+    /// Pinned API version
+    pub api_version: Option<String>,
+    /// Header used for `api_version`
+    pub version_header: Option<String>,
+    /// Sent with every request
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Negotiate compressed answers, on by default
+    pub compression: Option<bool>,
+    /// Connect timeout in seconds, 0 for none
+    pub connect_timeout: Option<u64>,
+    /// Read timeout in seconds, 0 for none
+    pub read_timeout: Option<u64>,
+    /// Interval of TCP keepalives in seconds, 0 for none
+    pub tcp_keepalive: Option<u64>,
+    /// Force HTTP/2 (`true`) or HTTP/1.1 (`false`), negotiated by default
+    pub http2: Option<bool>,
+}
+
+impl HttpConfig {
+    /// Full user agent
+    ///
+    pub fn user_agent(&self) -> String {
+        match &self.user_agent {
+            Some(suffix) => format!("{} {}", user_agent(), suffix),
+            None => user_agent(),
+        }
+    }
+
+    /// All headers sent by default, fails on invalid names or values.
+    ///
+    pub fn headers(&self) -> Result<HeaderMap> {
+        let mut map = HeaderMap::new();
+        let mut add = |name: &str, value: &str| -> Result<()> {
+            let hname = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| eyre!("invalid header name {name}"))?;
+            let hvalue = HeaderValue::from_str(value)
+                .map_err(|_| eyre!("invalid value for header {name}"))?;
+            map.insert(hname, hvalue);
+            Ok(())
+        };
+
+        for (name, value) in &self.headers {
+            add(name, value)?;
+        }
+        if let Some(accept) = &self.accept {
+            add(ACCEPT.as_str(), accept)?;
+        }
+        if let Some(version) = &self.api_version {
+            add(
+                self.version_header.as_deref().unwrap_or(VERSION_HEADER),
+                version,
+            )?;
+        }
+        Ok(map)
+    }
+
+    /// Whether compressed answers are negotiated
+    ///
+    pub fn compression(&self) -> bool {
+        self.compression.unwrap_or(true)
+    }
+
+    /// Connect timeout, `None` if disabled
+    ///
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        seconds(self.connect_timeout.unwrap_or(DEF_CONNECT_TIMEOUT))
+    }
+
+    /// Read timeout, `None` if disabled
+    ///
+    pub fn read_timeout(&self) -> Option<Duration> {
+        seconds(self.read_timeout.unwrap_or(DEF_READ_TIMEOUT))
+    }
+
+    /// Interval of TCP keepalives, `None` if disabled
+    ///
+    pub fn tcp_keepalive(&self) -> Option<Duration> {
+        seconds(self.tcp_keepalive.unwrap_or(DEF_TCP_KEEPALIVE))
+    }
+
+    /// Async client builder with our settings, for sources adding their own (like a certificate)
+    ///
+    pub fn client_builder(&self) -> Result<reqwest::ClientBuilder> {
+        let on = self.compression();
+        let mut builder = Client::builder()
+            .user_agent(self.user_agent())
+            .default_headers(self.headers()?)
+            .gzip(on)
+            .deflate(on)
+            .brotli(on)
+            .zstd(on)
+            .tcp_keepalive(self.tcp_keepalive());
+        if let Some(timeout) = self.connect_timeout() {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout() {
+            builder = builder.read_timeout(timeout);
+        }
+        Ok(match self.http2 {
+            Some(true) => builder.http2_prior_knowledge(),
+            Some(false) => builder.http1_only(),
+            None => builder,
+        })
+    }
+
+    /// Async client with our settings
+    ///
+    pub fn client(&self) -> Client {
+        self.client_builder()
+            .and_then(|b| Ok(b.build()?))
+            .unwrap_or_else(|e| {
+                warn!("can not create HTTP client: {}", e);
+                Client::new()
+            })
+    }
+
+    /// Blocking client builder with our settings, for sources adding their own
+    ///
+    pub fn blocking_client_builder(&self) -> Result<reqwest::blocking::ClientBuilder> {
+        let on = self.compression();
+        let builder = reqwest::blocking::Client::builder()
+            .user_agent(self.user_agent())
+            .default_headers(self.headers()?)
+            .gzip(on)
+            .deflate(on)
+            .brotli(on)
+            .zstd(on)
+            .connect_timeout(self.connect_timeout())
+            .timeout(self.read_timeout())
+            .tcp_keepalive(self.tcp_keepalive());
+        Ok(match self.http2 {
+            Some(true) => builder.http2_prior_knowledge(),
+            Some(false) => builder.http1_only(),
+            None => builder,
+        })
+    }
+
+    /// Blocking client with our settings
+    ///
+    pub fn blocking_client(&self) -> reqwest::blocking::Client {
+        self.blocking_client_builder()
+            .and_then(|b| Ok(b.build()?))
+            .unwrap_or_else(|e| {
+                warn!("can not create HTTP client: {}", e);
+                reqwest::blocking::Client::new()
+            })
+    }
+}
+
+/// Connect to `addr` (`host:port`), trying every address it resolves to for at most `timeout`
+/// each.
+///
+pub(crate) fn tcp_connect(addr: &str, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addr);
+    };
+    let mut last = None;
+    for sa in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sa, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = Some(e),
+        }
+    }
+    Err(last
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{addr}: no address"))))
+}
+
+/// Seconds into a duration, 0 being none
+///
+fn seconds(secs: u64) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Summary for statistics and logs, e.g. `fetiche-sources/0.16.0 acute; accept=application/json`
+///
+impl Display for HttpConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.user_agent())?;
+        if let Some(accept) = &self.accept {
+            write!(f, "; accept={}", accept)?;
+        }
+        if let Some(version) = &self.api_version {
+            let name = self.version_header.as_deref().unwrap_or(VERSION_HEADER);
+            write!(f, "; {}={}", name, version)?;
+        }
+        if !self.headers.is_empty() {
+            let names = self.headers.keys().cloned().collect::<Vec<_>>();
+            write!(f, "; headers={}", names.join(","))?;
+        }
+        if !self.compression() {
+            write!(f, "; no compression")?;
+        }
+        if let Some(secs) = self.connect_timeout {
+            write!(f, "; connect_timeout={}s", secs)?;
+        }
+        if let Some(secs) = self.read_timeout {
+            write!(f, "; read_timeout={}s", secs)?;
+        }
+        if let Some(secs) = self.tcp_keepalive {
+            write!(f, "; tcp_keepalive={}s", secs)?;
+        }
+        match self.http2 {
+            Some(true) => write!(f, "; http2 only")?,
+            Some(false) => write!(f, "; http1 only")?,
+            None => (),
+        }
+        Ok(())
+    }
+}
+
+/// POST `data` as JSON without authentication, used to get a token from credentials
+///
+pub(crate) async fn post_json<T: Serialize + ?Sized>(
+    client: &Client,
+    url: &str,
+    data: &T,
+) -> reqwest::Result<Response> {
+    client
+        .post(url)
+        .header("content-type", "application/json")
+        .json(data)
+        .send()
+        .await
+}
+
+/// GET with a bearer token
+///
+pub(crate) async fn get_bearer(
+    client: &Client,
+    url: &str,
+    token: &str,
+) -> reqwest::Result<Response> {
+    client
+        .get(url)
+        .header("content-type", "application/json")
+        .bearer_auth(token)
+        .send()
+        .await
+}
+
+/// POST an already encoded JSON `body` with a bearer token
+///
+pub(crate) async fn post_bearer(
+    client: &Client,
+    url: &str,
+    token: &str,
+    body: String,
+) -> reqwest::Result<Response> {
+    client
+        .post(url)
+        .header("content-type", "application/json")
+        .bearer_auth(token)
+        .body(body)
+        .send()
+        .await
+}
+
+/// Run a future to completion from sync code on the shared runtime.
+///
+/// Engine tasks run in plain threads so this is the usual case, when called from within a
+/// runtime (like `acutectl` or a test), the future is driven from a scoped thread as a runtime
+/// can not be blocked on from one of its own threads.
+///
+pub fn block_on<F>(fut: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    let rt = RUNTIME.get_or_init(|| {
+        trace!("starting shared runtime");
+        Builder::new_multi_thread()
+            .worker_threads(WORKERS)
+            .thread_name("fetiche-sources")
+            .enable_all()
+            .build()
+            .expect("can not create the shared runtime")
+    });
+
+    match Handle::try_current() {
+        Ok(_) => thread::scope(|s| s.spawn(|| rt.block_on(fut)).join().unwrap()),
+        Err(_) => rt.block_on(fut),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httpmock::Method::GET;
+    use httpmock::MockServer;
+
+    use super::*;
+
+    fn pinned() -> HttpConfig {
+        HttpConfig {
+            user_agent: Some("acute".to_string()),
+            accept: Some("application/vnd.example.v2+json".to_string()),
+            api_version: Some("2".to_string()),
+            headers: BTreeMap::from([("x-client-id".to_string(), "fetiche".to_string())]),
+            ..HttpConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_http_user_agent() {
+        assert_eq!(user_agent(), HttpConfig::default().user_agent());
+        assert_eq!(format!("{} acute", user_agent()), pinned().user_agent());
+    }
+
+    #[test]
+    fn test_http_headers() {
+        let h = pinned().headers().unwrap();
+        assert_eq!(3, h.len());
+        assert_eq!("application/vnd.example.v2+json", h[ACCEPT]);
+        assert_eq!("2", h["x-api-version"]);
+        assert_eq!("fetiche", h["x-client-id"]);
+
+        let http = HttpConfig {
+            api_version: Some("2024-01-01".to_string()),
+            version_header: Some("api-version".to_string()),
+            ..HttpConfig::default()
+        };
+        assert_eq!("2024-01-01", http.headers().unwrap()["api-version"]);
+        assert!(HttpConfig::default().headers().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_http_headers_invalid() {
+        let mut http = pinned();
+        http.headers
+            .insert("bad header".to_string(), "x".to_string());
+        assert!(http.headers().is_err());
+
+        let http = HttpConfig {
+            accept: Some("bad\nvalue".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(http.headers().is_err());
+    }
+
+    #[test]
+    fn test_http_display() {
+        assert_eq!(
+            format!(
+                "{} acute; accept=application/vnd.example.v2+json; x-api-version=2; headers=x-client-id",
+                user_agent()
+            ),
+            pinned().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_client_sends_headers() {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(GET)
+                .path("/")
+                .header("user-agent", format!("{} acute", user_agent()))
+                .header("accept", "application/vnd.example.v2+json")
+                .header("x-api-version", "2")
+                .header("x-client-id", "fetiche");
+            then.status(200);
+        });
+
+        let client = pinned().client();
+        let resp = client.get(server.url("/")).send().await.unwrap();
+        assert!(resp.status().is_success());
+        m.assert();
+    }
+
+    #[test]
+    fn test_http_blocking_client_sends_headers() {
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(GET)
+                .path("/")
+                .header("user-agent", format!("{} acute", user_agent()))
+                .header("x-api-version", "2");
+            then.status(200);
+        });
+
+        let client = pinned().blocking_client();
+        let resp = client.get(server.url("/")).send().unwrap();
+        assert!(resp.status().is_success());
+        m.assert();
+    }
+
+    #[test]
+    fn test_http_client_decompresses() {
+        use std::io::Write;
+
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(b"[{\"journey\":1}]").unwrap();
+        let body = gz.finish().unwrap();
+
+        let server = MockServer::start();
+        let m = server.mock(|when, then| {
+            when.method(GET).path("/").header_exists("accept-encoding");
+            then.status(200)
+                .header("content-encoding", "gzip")
+                .body(body.clone());
+        });
+
+        let resp = HttpConfig::default()
+            .blocking_client()
+            .get(server.url("/"))
+            .send()
+            .unwrap();
+        assert_eq!("[{\"journey\":1}]", resp.text().unwrap());
+        m.assert();
+
+        let http = HttpConfig {
+            compression: Some(false),
+            ..HttpConfig::default()
+        };
+        assert!(http.to_string().ends_with("; no compression"));
+        let resp = http.blocking_client().get(server.url("/")).send().unwrap();
+        assert_eq!(404, resp.status().as_u16());
+    }
+
+    #[test]
+    fn test_http_timeouts() {
+        let http = HttpConfig::default();
+        assert_eq!(Some(Duration::from_secs(10)), http.connect_timeout());
+        assert_eq!(Some(Duration::from_secs(30)), http.read_timeout());
+        assert_eq!(Some(Duration::from_secs(60)), http.tcp_keepalive());
+
+        let http = HttpConfig {
+            connect_timeout: Some(2),
+            read_timeout: Some(0),
+            tcp_keepalive: Some(0),
+            http2: Some(false),
+            ..HttpConfig::default()
+        };
+        assert_eq!(Some(Duration::from_secs(2)), http.connect_timeout());
+        assert_eq!(None, http.read_timeout());
+        assert_eq!(None, http.tcp_keepalive());
+        assert!(http
+            .to_string()
+            .ends_with("; connect_timeout=2s; read_timeout=0s; tcp_keepalive=0s; http1 only"));
+    }
+
+    #[test]
+    fn test_http_read_timeout() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/slow");
+            then.status(200).delay(Duration::from_secs(3));
+        });
+
+        let http = HttpConfig {
+            read_timeout: Some(1),
+            ..HttpConfig::default()
+        };
+        let err = http
+            .blocking_client()
+            .get(server.url("/slow"))
+            .send()
+            .unwrap_err();
+        assert!(err.is_timeout());
+
+        let err =
+            block_on(async { http.client().get(server.url("/slow")).send().await }).unwrap_err();
+        assert!(err.is_timeout());
+    }
+
+    #[test]
+    fn test_http_connect_timeout() {
+        // Nothing answers on this non-routable address
+        //
+        let http = HttpConfig {
+            connect_timeout: Some(1),
+            ..HttpConfig::default()
+        };
+        let start = std::time::Instant::now();
+        let res = http.blocking_client().get("http://10.255.255.1/").send();
+        assert!(res.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_tcp_connect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        assert!(tcp_connect(&addr, Some(Duration::from_secs(1))).is_ok());
+        assert!(tcp_connect(&addr, None).is_ok());
+
+        drop(listener);
+        assert!(tcp_connect(&addr, Some(Duration::from_secs(1))).is_err());
+        assert!(tcp_connect("nowhere.invalid:30003", Some(Duration::from_secs(1))).is_err());
+    }
+
+    #[test]
+    fn test_block_on() {
+        assert_eq!(42, block_on(async { 42 }));
+    }
+
+    #[tokio::test]
+    async fn test_block_on_in_runtime() {
+        assert_eq!(42, block_on(async { 42 }));
+    }
+}
@@ -7,9 +7,10 @@
 //!
 
 use enum_dispatch::enum_dispatch;
-use eyre::Result;
+use eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
 use std::sync::mpsc::Sender;
 
 use fetiche_formats::Format;
@@ -20,6 +21,10 @@ pub use access::*;
 pub use auth::*;
 pub use error::*;
 pub use filter::*;
+pub use group::*;
+pub use http::*;
+pub use limit::*;
+pub use poll::*;
 pub use route::*;
 pub use site::*;
 pub use sources::*;
@@ -28,6 +33,10 @@ mod access;
 mod auth;
 mod error;
 mod filter;
+mod group;
+mod http;
+mod limit;
+mod poll;
 mod route;
 mod site;
 mod sources;
@@ -39,6 +48,8 @@ mod macros;
 pub trait Expirable: Debug + Clone {
     fn key(&self) -> String;
     fn is_expired(&self) -> bool;
+    /// Expiration time as a UNIX timestamp
+    fn expires_at(&self) -> i64;
 }
 
 #[enum_dispatch]
@@ -56,6 +67,7 @@ pub enum Capability {
     Fetch = 1,
     Read = 2,
     Stream = 3,
+    Archive = 4,
 }
 
 impl Display for Capability {
@@ -65,6 +77,7 @@ impl Display for Capability {
             Capability::Read => "read",
             Capability::Fetch => "fetch",
             Capability::Stream => "stream",
+            Capability::Archive => "archive",
         };
         write!(f, "{s}")
     }
@@ -80,8 +93,86 @@ pub trait Fetchable: Debug {
     fn authenticate(&self) -> Result<String, AuthError>;
     /// Fetch actual data
     fn fetch(&self, out: Sender<String>, token: &str, args: &str) -> Result<()>;
+    /// Fetch several topics for the same filter, one output per topic
+    fn fetch_topics(
+        &self,
+        _outs: Vec<(String, Sender<String>)>,
+        _token: &str,
+        _args: &str,
+    ) -> Result<()> {
+        Err(eyre!("{} has no topics", self.name()))
+    }
+    /// Returns the input formats
+    fn format(&self) -> Format;
+    /// Forget any stored token so that `authenticate()` gets a new one
+    fn invalidate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Async version of `Fetchable`, sources implementing it share the HTTP layer in `http`.
+///
+pub trait AsyncFetchable: Debug + Sync {
+    /// Return site's name
+    fn name(&self) -> String;
+    /// If credentials are needed, get a token for subsequent operations
+    fn authenticate(&self) -> impl Future<Output = Result<String, AuthError>> + Send;
+    /// Fetch actual data
+    fn fetch(
+        &self,
+        out: Sender<String>,
+        token: &str,
+        args: &str,
+    ) -> impl Future<Output = Result<()>> + Send;
+    /// Fetch several topics for the same filter, one output per topic
+    fn fetch_topics(
+        &self,
+        _outs: Vec<(String, Sender<String>)>,
+        _token: &str,
+        _args: &str,
+    ) -> impl Future<Output = Result<()>> + Send {
+        async move { Err(eyre!("{} has no topics", self.name())) }
+    }
     /// Returns the input formats
     fn format(&self) -> Format;
+    /// Forget any stored token so that `authenticate()` gets a new one
+    fn invalidate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Thin sync adapter for callers not yet async, every call is run on the shared runtime.  The
+/// engine `Fetch` and `Stream` tasks are among them.
+///
+impl<T: AsyncFetchable> Fetchable for T {
+    fn name(&self) -> String {
+        AsyncFetchable::name(self)
+    }
+
+    fn authenticate(&self) -> Result<String, AuthError> {
+        block_on(AsyncFetchable::authenticate(self))
+    }
+
+    fn fetch(&self, out: Sender<String>, token: &str, args: &str) -> Result<()> {
+        block_on(AsyncFetchable::fetch(self, out, token, args))
+    }
+
+    fn fetch_topics(
+        &self,
+        outs: Vec<(String, Sender<String>)>,
+        token: &str,
+        args: &str,
+    ) -> Result<()> {
+        block_on(AsyncFetchable::fetch_topics(self, outs, token, args))
+    }
+
+    fn format(&self) -> Format {
+        AsyncFetchable::format(self)
+    }
+
+    fn invalidate(&self) -> Result<()> {
+        AsyncFetchable::invalidate(self)
+    }
 }
 
 /// This trait enables us to manage different ways of connecting and streaming data under
@@ -97,6 +188,10 @@ pub trait Streamable: Debug {
     fn stream(&self, out: Sender<String>, token: &str, args: &str) -> Result<()>;
     /// Returns the input formats
     fn format(&self) -> Format;
+    /// Forget any stored token so that `authenticate()` gets a new one
+    fn invalidate(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// Default configuration filename
@@ -0,0 +1,177 @@
+//! Per-site concurrency limits.
+//!
+//! Some sites forbid concurrent sessions (ASD for example bans parallel logins) so a site can
+//! define `max_concurrent` in `sources.hcl`.  Every `Fetch` or `Stream` task acquires a `Permit`
+//! for its site before connecting and jobs targeting a busy site wait for their turn instead of
+//! running simultaneously.  This is independent of how many jobs the engine runs in parallel.
+//!
+//! Sessions are counted per `base_url` and not per site name: `asd` and `lux` are two views of
+//! the same ASD account and must not be used at the same time either.
+//!
+//! Waiting is not forever, the caller gives a `stop` check (the job being cancelled or timing
+//! out) looked at every `LIMIT_POLL` and gets no permit if it says so.
+//!
+//! The `Limiter` is shared by all clones of a given `Sources`.
+//!
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+/// How often a waiting task checks whether it should give up
+///
+pub const LIMIT_POLL: Duration = Duration::from_millis(200);
+
+/// Keep track of the number of running sessions per endpoint.
+///
+#[derive(Debug, Default)]
+pub struct Limiter {
+    /// Number of sessions per endpoint
+    used: Mutex<BTreeMap<String, usize>>,
+    /// Wake up waiters when a permit is released
+    freed: Condvar,
+}
+
+impl Limiter {
+    /// Wait until there is less than `max` sessions for `name` and take one, unless `stop`
+    /// returns true in the meantime.
+    ///
+    #[tracing::instrument(skip(self, stop))]
+    pub fn acquire(
+        self: &Arc<Self>,
+        name: &str,
+        max: usize,
+        stop: impl Fn() -> bool,
+    ) -> Option<Permit> {
+        let mut used = self.used.lock().unwrap();
+        while *used.get(name).unwrap_or(&0) >= max {
+            if stop() {
+                debug!("{} no longer waiting for a session", name);
+                return None;
+            }
+            debug!("{} has already {} session(s), waiting", name, max);
+            used = self.freed.wait_timeout(used, LIMIT_POLL).unwrap().0;
+        }
+        *used.entry(name.to_string()).or_default() += 1;
+        trace!("{} permit taken", name);
+
+        Some(Permit {
+            limiter: Some(Arc::clone(self)),
+            name: name.to_string(),
+        })
+    }
+
+    /// Number of running sessions for an endpoint.
+    ///
+    pub fn used(&self, name: &str) -> usize {
+        *self.used.lock().unwrap().get(name).unwrap_or(&0)
+    }
+}
+
+/// Running sessions of a site with its limit, see `Sources::sessions()`.
+///
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Sessions {
+    /// Running sessions
+    pub used: usize,
+    /// `max_concurrent`
+    pub max: usize,
+}
+
+/// Session permit, released when dropped.  Sites without limit get an empty one.
+///
+#[derive(Debug)]
+pub struct Permit {
+    limiter: Option<Arc<Limiter>>,
+    name: String,
+}
+
+impl Permit {
+    /// Permit for a site without `max_concurrent`
+    ///
+    pub fn unlimited(name: &str) -> Self {
+        Permit {
+            limiter: None,
+            name: name.to_string(),
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(limiter) = &self.limiter else {
+            return;
+        };
+        let mut used = limiter.used.lock().unwrap();
+        if let Some(n) = used.get_mut(&self.name) {
+            *n = n.saturating_sub(1);
+        }
+        trace!("{} permit released", self.name);
+        limiter.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_limiter_release() {
+        let l = Arc::new(Limiter::default());
+
+        let p = l.acquire("asd", 2, || false);
+        let q = l.acquire("asd", 2, || false);
+        assert_eq!(2, l.used("asd"));
+        assert_eq!(0, l.used("opensky"));
+
+        drop(p);
+        assert_eq!(1, l.used("asd"));
+        drop(q);
+        assert_eq!(0, l.used("asd"));
+    }
+
+    #[test]
+    fn test_limiter_queue() {
+        let l = Arc::new(Limiter::default());
+        let running = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+
+        let handles = (0..4)
+            .map(|_| {
+                let (l, running, max) = (l.clone(), running.clone(), max.clone());
+                thread::spawn(move || {
+                    let _p = l.acquire("asd", 1, || false);
+                    let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max.fetch_max(n, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect::<Vec<_>>();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        assert_eq!(1, max.load(Ordering::SeqCst));
+        assert_eq!(0, l.used("asd"));
+    }
+
+    #[test]
+    fn test_limiter_stop() {
+        let l = Arc::new(Limiter::default());
+        let _p = l.acquire("asd", 1, || false);
+
+        // Gives up while the other one is still running
+        //
+        let start = Instant::now();
+        let stop = move || start.elapsed() >= 2 * LIMIT_POLL;
+        assert!(l.acquire("asd", 1, stop).is_none());
+        assert!(start.elapsed() < 4 * LIMIT_POLL);
+        assert_eq!(1, l.used("asd"));
+    }
+}
@@ -12,10 +12,6 @@ macro_rules! http_post {
             .client
             .clone()
             .post($url)
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
             .header("content-type", "application/json")
             .json($cred)
             .send()
@@ -33,10 +29,6 @@ macro_rules! http_get_auth {
             .client
             .clone()
             .get($url)
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
             .header("content-type", "application/json")
             .bearer_auth($token)
             .send()
@@ -55,10 +47,6 @@ macro_rules! http_post_auth {
             .client
             .clone()
             .post($url)
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
             .header("content-type", "application/json")
             .bearer_auth($token)
             .json($data)
@@ -69,10 +57,6 @@ macro_rules! http_post_auth {
             .client
             .clone()
             .post($url)
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
             .header("content-type", "application/json")
             .bearer_auth($token)
             .send()
@@ -90,10 +74,6 @@ macro_rules! http_get_basic {
         $self
             .get($url)
             .basic_auth($user, Some($pwd))
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
             .header("content-type", "application/json")
             .json($data)
             .send()
@@ -102,10 +82,6 @@ macro_rules! http_get_basic {
         $self
             .get($url)
             .basic_auth($user, Some($pwd))
-            .header(
-                "user-agent",
-                format!("{}/{}", crate_name!(), crate_version!()),
-            )
             .header("content-type", "application/json")
             .send()
     };
@@ -0,0 +1,260 @@
+//! Adaptive polling for pseudo-streams like Opensky.
+//!
+//! Sources without a real stream are polled every `delay` ms.  With a `poll` block in the site
+//! definition, the interval changes with what the last answer told us:
+//!
+//! - few targets (`sparse` or less) means we could miss a short drone flight so we poll every
+//!   `min` ms,
+//! - otherwise we poll at the requested `delay`,
+//! - when the site complains (HTTP 429) or the remaining credits go below `credits`, the interval
+//!   is doubled (up to `max` ms) until things get better, then halved back at every poll.
+//!
+//! ```hcl
+//! site "opensky" {
+//!   ...
+//!   poll = {
+//!     min     = 500
+//!     max     = 60000
+//!     sparse  = 5
+//!     credits = 100
+//!   }
+//! }
+//! ```
+//!
+
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+/// Largest multiplier of the interval under rate-limit pressure
+const MAX_BACKOFF: u32 = 64;
+
+/// Parameters of adaptive polling, `poll` block of a site in `sources.hcl`
+///
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct PollConfig {
+    /// Shortest interval in ms
+    pub min: u32,
+    /// Longest interval in ms
+    pub max: u32,
+    /// Up to this number of targets, traffic is sparse
+    pub sparse: usize,
+    /// Back off when the remaining credits go below this
+    pub credits: u64,
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        PollConfig {
+            min: 500,
+            max: 60_000,
+            sparse: 5,
+            credits: 100,
+        }
+    }
+}
+
+/// What we learnt from the last poll
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Observation {
+    /// Number of targets returned, `None` if the poll failed
+    pub targets: Option<usize>,
+    /// Remaining credits announced by the site
+    pub remaining: Option<u64>,
+    /// Rate-limited, with the number of seconds to wait if given
+    pub limited: Option<u64>,
+}
+
+/// Why we chose the current interval
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Reason {
+    /// Adaptive polling not configured
+    #[default]
+    Fixed,
+    /// Few targets
+    Sparse,
+    /// Enough targets
+    Dense,
+    /// Rate-limited by the site
+    Limited,
+    /// Low on credits
+    Credits,
+}
+
+/// Interval chosen for the next poll, with its inputs
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Cadence {
+    /// Time to wait before the next poll in ms
+    pub delay: u32,
+    /// Number of targets
+    pub targets: Option<usize>,
+    /// Remaining credits
+    pub remaining: Option<u64>,
+    /// Why
+    pub reason: Reason,
+}
+
+impl Display for Cadence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let opt = |v: Option<String>| v.unwrap_or("-".to_string());
+        write!(
+            f,
+            "delay={}ms targets={} remaining={} ({})",
+            self.delay,
+            opt(self.targets.map(|t| t.to_string())),
+            opt(self.remaining.map(|r| r.to_string())),
+            self.reason
+        )
+    }
+}
+
+/// Compute the interval between two polls
+///
+#[derive(Clone, Debug)]
+pub struct Poller {
+    /// Adaptive parameters, fixed interval if not set
+    pub config: Option<PollConfig>,
+    /// Requested interval in ms
+    pub delay: u32,
+    /// Current multiplier due to rate-limiting
+    backoff: u32,
+    /// Last decision
+    last: Cadence,
+}
+
+impl Poller {
+    pub fn new(config: Option<PollConfig>, delay: u32) -> Self {
+        Poller {
+            config,
+            delay,
+            backoff: 1,
+            last: Cadence {
+                delay,
+                ..Cadence::default()
+            },
+        }
+    }
+
+    /// Decide when to poll next, decisions are logged when the interval changes.
+    ///
+    pub fn next(&mut self, obs: Observation) -> Cadence {
+        let Some(cfg) = &self.config else {
+            return Cadence {
+                delay: self.delay,
+                targets: obs.targets,
+                remaining: obs.remaining,
+                reason: Reason::Fixed,
+            };
+        };
+
+        let low = obs.remaining.is_some_and(|r| r < cfg.credits);
+        let reason = if obs.limited.is_some() {
+            Reason::Limited
+        } else if low {
+            Reason::Credits
+        } else if obs.targets.is_some_and(|t| t <= cfg.sparse) {
+            Reason::Sparse
+        } else {
+            Reason::Dense
+        };
+
+        self.backoff = match reason {
+            Reason::Limited | Reason::Credits => (self.backoff * 2).min(MAX_BACKOFF),
+            _ => (self.backoff / 2).max(1),
+        };
+        let base = match reason {
+            Reason::Sparse if self.backoff == 1 => cfg.min,
+            _ => self.delay,
+        };
+        let mut delay = base.saturating_mul(self.backoff).clamp(cfg.min, cfg.max);
+        if let Some(secs) = obs.limited {
+            delay = delay.max(secs.saturating_mul(1_000).min(u32::MAX as u64) as u32);
+        }
+
+        let cadence = Cadence {
+            delay,
+            targets: obs.targets,
+            remaining: obs.remaining,
+            reason,
+        };
+        if cadence.delay != self.last.delay || cadence.reason != self.last.reason {
+            info!("poll: {}", cadence);
+        } else {
+            debug!("poll: {}", cadence);
+        }
+        self.last = cadence;
+        cadence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn obs(targets: usize) -> Observation {
+        Observation {
+            targets: Some(targets),
+            ..Observation::default()
+        }
+    }
+
+    #[test]
+    fn test_poller_fixed() {
+        let mut p = Poller::new(None, 1_000);
+        let c = p.next(obs(0));
+        assert_eq!(1_000, c.delay);
+        assert_eq!(Reason::Fixed, c.reason);
+    }
+
+    #[rstest]
+    #[case(0, 500, Reason::Sparse)]
+    #[case(5, 500, Reason::Sparse)]
+    #[case(6, 2_000, Reason::Dense)]
+    fn test_poller_density(#[case] targets: usize, #[case] delay: u32, #[case] reason: Reason) {
+        let mut p = Poller::new(Some(PollConfig::default()), 2_000);
+        let c = p.next(obs(targets));
+        assert_eq!(delay, c.delay);
+        assert_eq!(reason, c.reason);
+    }
+
+    #[test]
+    fn test_poller_backoff() {
+        let mut p = Poller::new(Some(PollConfig::default()), 2_000);
+        let limited = Observation {
+            limited: Some(0),
+            ..Observation::default()
+        };
+        assert_eq!(4_000, p.next(limited).delay);
+        assert_eq!(8_000, p.next(limited).delay);
+
+        // Low credits keep us backing off, retry-after wins if longer
+        //
+        let low = Observation {
+            targets: Some(1),
+            remaining: Some(10),
+            limited: None,
+        };
+        let c = p.next(low);
+        assert_eq!((16_000, Reason::Credits), (c.delay, c.reason));
+        let c = p.next(Observation {
+            limited: Some(40),
+            ..Observation::default()
+        });
+        assert_eq!(40_000, c.delay);
+
+        // Then we recover step by step before tightening again
+        //
+        assert_eq!(16_000, p.next(obs(10)).delay);
+        assert_eq!(8_000, p.next(obs(10)).delay);
+        assert_eq!(4_000, p.next(obs(1)).delay);
+        assert_eq!(500, p.next(obs(1)).delay);
+    }
+}
@@ -0,0 +1,62 @@
+// Built-in sites, used when there is no `sources.hcl` at all.
+//
+// Only public endpoints needing no key or account are here, run `acutectl config init` to get
+// the full list of sites.
+//
+version = 4
+
+// Anonymous access: lower resolution and rate limits, every aircraft instead of your own
+// receivers.
+//
+site "opensky" {
+  features = ["fetch"]
+  type     = "adsb"
+  format   = "opensky"
+  base_url = "https://opensky-network.org/api"
+  routes   = {
+    get = "/states/all"
+  }
+}
+
+site "opensky-live" {
+  features = ["stream"]
+  type     = "adsb"
+  format   = "opensky"
+  base_url = "https://opensky-network.org/api"
+  routes   = {
+    stream = "/states/all"
+  }
+  poll     = {
+    min     = 10000
+    max     = 60000
+    credits = 100
+  }
+}
+
+site "simulator" {
+  features = ["fetch"]
+  type     = "drone"
+  format   = "asd"
+  base_url = "sim://localhost"
+  sim      = {
+    drones    = 10
+    aircraft  = 2
+    latitude  = 49.0097
+    longitude = 2.5479
+    radius    = 20000
+    interval  = 1000
+    seed      = 42
+  }
+}
+
+site "simulator-live" {
+  features = ["stream"]
+  type     = "adsb"
+  format   = "opensky"
+  base_url = "sim://localhost"
+  sim      = {
+    drones   = 5
+    aircraft = 10
+    radius   = 50000
+  }
+}
@@ -16,16 +16,20 @@ use std::str::FromStr;
 
 use eyre::{eyre, Result};
 use serde::{Deserialize, Serialize};
-use tracing::trace;
+use tracing::{info, trace};
 
-use fetiche_formats::Format;
+use fetiche_formats::{Format, Strictness};
 
-use crate::{Aeroscope, Asd, Auth, Capability, Flightaware, Opensky, Routes, Safesky, Streamable};
+use crate::{
+    Aeroscope, Archive, ArchiveConfig, Asd, Auth, BaseStation, Capability, FirehoseConfig,
+    Flightaware, HttpConfig, NmB2b, NmConfig, Opensky, PollConfig, Routes, Safesky, SimConfig,
+    Simulator, Streamable, Ussp,
+};
 use crate::{Fetchable, Sources};
 
 /// Describe what a site is, its capabilities, access methods and authentication method.
 ///
-#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct Site {
     /// Features of the site
     pub features: Vec<Capability>,
@@ -46,6 +50,22 @@ pub struct Site {
     pub auth: Option<Auth>,
     /// Different URLs available
     pub routes: Option<Routes>,
+    /// Maximum number of simultaneous sessions (no limit if not set)
+    pub max_concurrent: Option<usize>,
+    /// Simulation parameters, turns the site into a synthetic traffic generator
+    pub sim: Option<SimConfig>,
+    /// Daily archives, turns the site into a bulk downloader
+    pub archive: Option<ArchiveConfig>,
+    /// Adaptive polling for pseudo-streams (fixed interval if not set)
+    pub poll: Option<PollConfig>,
+    /// User agent suffix, pinned API version and extra headers
+    pub http: Option<HttpConfig>,
+    /// Flights to ask the NM B2B services for
+    pub nm: Option<NmConfig>,
+    /// Server-side filters of a Flightaware Firehose subscription
+    pub firehose: Option<FirehoseConfig>,
+    /// What to do with messages in a schema revision we cannot decode (`warn` if not set)
+    pub strictness: Option<Strictness>,
 }
 
 /// Define the kind of data the source is managing
@@ -125,63 +145,126 @@ impl Site {
         Site::default()
     }
 
-    /// Load site by checking whether it is present in the configuration file
+    /// Load site by checking whether it is present in the configuration file, groups are
+    /// loaded as a single site failing over between their endpoints.
     ///
     #[tracing::instrument(skip(cfg))]
     pub fn load(name: &str, cfg: &Sources) -> Result<Flow> {
         trace!("Loading site {}", name);
+        if let Some(group) = cfg.group(name) {
+            return group.load(name, cfg);
+        }
         match cfg.get(name) {
-            Some(site) => {
-                trace!("site={}", site);
-                let fmt = site.format();
+            Some(site) => site.flow(),
+            None => Err(eyre!("no such site {name}")),
+        }
+    }
+
+    /// Create the access object for this site
+    ///
+    pub fn flow(&self) -> Result<Flow> {
+        let site = self;
+        trace!("site={}", site);
+        let fmt = site.format();
+
+        // Invalid headers are an error here rather than silently dropped by every client
+        //
+        if let Some(http) = &site.http {
+            http.headers()
+                .map_err(|e| eyre!("site {}: {}", site.name, e))?;
+            info!("site {}: {}", site.name, http);
+        }
+
+        // Simulated sites can generate any of the supported formats
+        //
+        if site.sim.is_some() {
+            let s = Simulator::new().load(site).clone();
+            return if site.is_streamable() {
+                Ok(Flow::Streamable(Box::new(s)))
+            } else {
+                Ok(Flow::Fetchable(Box::new(s)))
+            };
+        }
+
+        // Archives can contain any format, members are converted as usual
+        //
+        if site.archive.is_some() {
+            let s = Archive::new().load(site).clone();
+            return Ok(Flow::Fetchable(Box::new(s)));
+        }
+
+        // We have to explicitly list all supported formats as we return
+        // an enum whether the site will be streamable or not
+        //
+        match fmt {
+            Format::Asd => {
+                let s = Asd::new().load(site).clone();
+                Ok(Flow::Fetchable(Box::new(s)))
+            }
+            Format::Aeroscope => {
+                let s = Aeroscope::new().load(site).clone();
+                Ok(Flow::Fetchable(Box::new(s)))
+            }
+            Format::Safesky => {
+                let s = Safesky::new().load(site).clone();
+                Ok(Flow::Fetchable(Box::new(s)))
+            }
+            // For now, only Opensky support streaming
+            //
+            Format::Opensky => {
+                let s = Opensky::new().load(site).clone();
 
-                // We have to explicitly list all supported formats as we return
-                // an enum whether the site will be streamable or not
+                // FIXME: handle both cases
                 //
-                match fmt {
-                    Format::Asd => {
-                        let s = Asd::new().load(site).clone();
-                        Ok(Flow::Fetchable(Box::new(s)))
-                    }
-                    Format::Aeroscope => {
-                        let s = Aeroscope::new().load(site).clone();
-                        Ok(Flow::Fetchable(Box::new(s)))
-                    }
-                    Format::Safesky => {
-                        let s = Safesky::new().load(site).clone();
-                        Ok(Flow::Fetchable(Box::new(s)))
-                    }
-                    // For now, only Opensky support streaming
-                    //
-                    Format::Opensky => {
-                        let s = Opensky::new().load(site).clone();
-
-                        // FIXME: handle both cases
-                        //
-                        if site.is_streamable() {
-                            Ok(Flow::Streamable(Box::new(s)))
-                        } else {
-                            Ok(Flow::Fetchable(Box::new(s)))
-                        }
-                    }
-                    Format::Flightaware => {
-                        let s = Flightaware::new().load(site).clone();
-
-                        // FIXME: Handle both cases
-                        //
-                        if site.is_streamable() {
-                            Ok(Flow::Streamable(Box::new(s)))
-                        } else {
-                            Ok(Flow::Fetchable(Box::new(s)))
-                        }
-                    }
-                    _ => Err(eyre!("invalid site {}", name)),
+                if site.is_streamable() {
+                    Ok(Flow::Streamable(Box::new(s)))
+                } else {
+                    Ok(Flow::Fetchable(Box::new(s)))
                 }
             }
-            None => Err(eyre!("no such site {name}")),
+            Format::Flightaware => {
+                let s = Flightaware::new().load(site).clone();
+
+                // FIXME: Handle both cases
+                //
+                if site.is_streamable() {
+                    Ok(Flow::Streamable(Box::new(s)))
+                } else {
+                    Ok(Flow::Fetchable(Box::new(s)))
+                }
+            }
+            Format::Utm => {
+                let s = Ussp::new().load(site).clone();
+
+                if site.is_streamable() {
+                    Ok(Flow::Streamable(Box::new(s)))
+                } else {
+                    Ok(Flow::Fetchable(Box::new(s)))
+                }
+            }
+            Format::NmB2b => {
+                let s = NmB2b::new().load(site).clone();
+                Ok(Flow::Fetchable(Box::new(s)))
+            }
+            Format::Sbs1 => {
+                let s = BaseStation::new().load(site).clone();
+
+                if site.is_streamable() {
+                    Ok(Flow::Streamable(Box::new(s)))
+                } else {
+                    Ok(Flow::Fetchable(Box::new(s)))
+                }
+            }
+            _ => Err(eyre!("invalid site {}", site.name)),
         }
     }
 
+    /// HTTP settings, defaults if there is no `http` block
+    ///
+    pub fn http(&self) -> HttpConfig {
+        self.http.clone().unwrap_or_default()
+    }
+
     /// Return whether a site is streamable
     ///
     pub fn is_streamable(&self) -> bool {
@@ -254,6 +337,7 @@ impl Display for Site {
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
+    use std::time::Duration;
 
     use rstest::rstest;
 
@@ -352,6 +436,85 @@ mod tests {
         assert!(s.has("get"));
     }
 
+    #[test]
+    fn test_site_http() {
+        let cfg = r#"
+version = 4
+
+site "pinned" {
+  features = ["fetch"]
+  type     = "adsb"
+  format   = "opensky"
+  base_url = "https://example.net/api"
+  routes   = {
+    stream = "/states/all"
+  }
+  http = {
+    user_agent  = "acute"
+    api_version = "2"
+    headers     = {
+      "x-client-id" = "fetiche"
+    }
+    connect_timeout = 5
+    http2           = false
+  }
+}
+
+site "broken" {
+  features = ["fetch"]
+  type     = "adsb"
+  format   = "opensky"
+  base_url = "https://example.net/api"
+  routes   = {
+    stream = "/states/all"
+  }
+  http = {
+    headers = {
+      "bad header" = "x"
+    }
+  }
+}
+"#;
+        let cfg = Sources::validate(cfg).unwrap();
+
+        let site = cfg.get("pinned").unwrap();
+        let http = site.http();
+        assert_eq!(Some("2".to_string()), http.api_version);
+        assert_eq!("fetiche", http.headers["x-client-id"]);
+        assert_eq!(Some(Duration::from_secs(5)), http.connect_timeout());
+        assert_eq!(Some(false), http.http2);
+        assert!(Site::load("pinned", &cfg).is_ok());
+
+        assert!(Site::load("broken", &cfg).is_err());
+    }
+
+    #[test]
+    fn test_site_strictness() {
+        let cfg = r#"
+version = 4
+
+site "fusion" {
+  features   = ["stream"]
+  type       = "drone"
+  format     = "senhive"
+  base_url   = "https://example.net/api"
+  strictness = "drop"
+}
+
+site "cube" {
+  features = ["stream"]
+  type     = "drone"
+  format   = "cube"
+  base_url = "https://example.net/api"
+}
+"#;
+        let cfg = Sources::validate(cfg).unwrap();
+
+        assert_eq!(Strictness::Drop, cfg.strictness("fusion"));
+        assert_eq!(Strictness::Warn, cfg.strictness("cube"));
+        assert_eq!(Strictness::Warn, cfg.strictness("nope"));
+    }
+
     #[rstest]
     #[case("adsb", DataType::Adsb)]
     #[case("ads-b", DataType::Invalid)]
@@ -19,8 +19,13 @@ site "asd" {
   format   = "asd"
   base_url = "https://eur.airspacedrone.com/api"
   auth     = "token"
+  max_concurrent = 1
   routes   = {
     get = "/journeys/filteredlocations"
+    // Other export topics, fetched with `--topics`
+    //
+    // alerts    = "/alerts/filtered"
+    // operators = "/operators/filtered"
   }
 }
 
@@ -30,6 +35,7 @@ site "lux" {
   format   = "asd"
   base_url = "https://eur.airspacedrone.com/api"
   auth     = "token"
+  max_concurrent = 1
   routes   = {
     list = "/journeys"
     get  = "/journeys/$1"
@@ -45,6 +51,27 @@ site "opensky" {
   routes   = {
     get = "/states/own"
   }
+  // Poll faster when there are few targets, slower when rate-limited
+  //
+  // poll = {
+  //   min     = 500
+  //   max     = 60000
+  //   sparse  = 5
+  //   credits = 100
+  // }
+  // User agent suffix, pinned API version, extra headers and timeouts (seconds)
+  //
+  // http = {
+  //   user_agent      = "acute"
+  //   api_version     = "2"
+  //   headers         = {
+  //     "x-client-id" = "acute"
+  //   }
+  //   connect_timeout = 5
+  //   read_timeout    = 120
+  //   tcp_keepalive   = 60
+  //   http2           = false
+  // }
 }
 
 site "fa-belfast" {
@@ -57,6 +84,11 @@ site "fa-belfast" {
     get = "range"
     stream = "live"
   }
+  firehose = {
+    events   = ["position"]
+    latlong  = [[54.0, -6.5, 55.0, -5.5]]
+    airports = ["EGAA", "EGAC"]
+  }
 }
 
 site "safesky" {
@@ -69,3 +101,54 @@ site "safesky" {
     get = "/v1/beacons"
   }
 }
+
+// Flight plans from the EUROCONTROL NM B2B services, with the PKCS#12 certificate issued by NM
+//
+site "nm-b2b" {
+  features = ["fetch"]
+  type     = "adsb"
+  format   = "nmb2b"
+  base_url = "https://www.b2b.nm.eurocontrol.int"
+  auth     = "certificate"
+  routes   = {
+    get = "/B2B_OPS/gateway/spec/27.0.0"
+  }
+  nm       = {
+    airspace = "EBBUFIR"
+  }
+}
+
+site "simulator" {
+  features = ["fetch"]
+  type     = "drone"
+  format   = "asd"
+  base_url = "sim://localhost"
+  sim      = {
+    drones    = 10
+    aircraft  = 2
+    latitude  = 49.0097
+    longitude = 2.5479
+    radius    = 20000
+    interval  = 1000
+    seed      = 42
+  }
+}
+
+site "simulator-live" {
+  features = ["stream"]
+  type     = "adsb"
+  format   = "opensky"
+  base_url = "sim://localhost"
+  sim      = {
+    drones   = 5
+    aircraft = 10
+    radius   = 50000
+  }
+}
+
+// Groups fail over between the endpoints of a site, in order, on connection errors
+//
+// group "asd-any" {
+//   site      = "asd"
+//   endpoints = ["https://eur.airspacedrone.com/api", "https://us.airspacedrone.com/api"]
+// }
@@ -7,29 +7,75 @@ use std::collections::btree_map::{IntoValues, Iter, IterMut, Keys, Values, Value
 use std::collections::BTreeMap;
 use std::fs;
 use std::ops::{Index, IndexMut};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use eyre::Result;
+use eyre::{eyre, Result};
+use hcl::format::{Format, Formatter};
+use hcl::{Attribute, Block, Body, Expression, Structure};
 use serde::Deserialize;
-use tabled::builder::Builder;
-use tabled::settings::Style;
 
-use crate::{Auth, Site, CONFIG};
+use crate::{Auth, Group, Limiter, Permit, Provenance, Sessions, Site, CONFIG};
 
-use fetiche_common::{ConfigFile, IntoConfig, Versioned};
+use fetiche_common::{
+    config_dir, ConfigFile, IntoConfig, Listing, Migrations, OutputFormat, Syntax, Versioned,
+};
+use fetiche_formats::Strictness;
 use fetiche_macros::into_configfile;
 
+/// Current version of `sources.hcl`, must match the one below.
+pub const SOURCES_VERSION: usize = 4;
+
 /// List of sources, this is the only exposed struct from here.
 ///
 #[into_configfile(version = 4, filename = "sources.hcl")]
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct SourcesConfig {
     site: BTreeMap<String, Site>,
+    #[serde(default)]
+    group: BTreeMap<String, Group>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct Sources {
     site: BTreeMap<String, Site>,
+    /// Sites with several endpoints
+    #[serde(default)]
+    group: BTreeMap<String, Group>,
+    /// Running sessions per site, shared between clones
+    #[serde(skip)]
+    limiter: Arc<Limiter>,
+    /// Endpoints used by groups, shared between clones
+    #[serde(skip)]
+    provenance: Arc<Provenance>,
+}
+
+/// Replace `auth = "kind"` by the matching credentials with placeholders, unknown kinds
+/// are removed.
+///
+fn placeholders(block: Block) -> Block {
+    let Block {
+        identifier,
+        labels,
+        body,
+    } = block;
+    let body = body
+        .into_iter()
+        .filter_map(|st| match st {
+            Structure::Attribute(a) if a.key() == "auth" => match a.expr() {
+                Expression::String(kind) => Auth::placeholder(kind)
+                    .and_then(|auth| hcl::to_expression(auth).ok())
+                    .map(|expr| Structure::Attribute(Attribute::new("auth", expr))),
+                _ => Some(Structure::Attribute(a)),
+            },
+            st => Some(st),
+        })
+        .collect::<Body>();
+    Block {
+        identifier,
+        labels,
+        body,
+    }
 }
 
 /// Initialise a `Source` from a `BTreeMap`
@@ -38,6 +84,7 @@ impl From<BTreeMap<String, Site>> for Sources {
     fn from(value: BTreeMap<String, Site>) -> Self {
         Sources {
             site: value.clone(),
+            ..Default::default()
         }
     }
 }
@@ -50,7 +97,10 @@ impl From<Vec<(String, Site)>> for Sources {
         value.iter().for_each(|(n, s)| {
             sites.insert(n.clone(), s.clone());
         });
-        Sources { site: sites }
+        Sources {
+            site: sites,
+            ..Default::default()
+        }
     }
 }
 
@@ -58,8 +108,12 @@ impl Sources {
     #[tracing::instrument]
     pub fn load() -> Result<Self> {
         let src_file = ConfigFile::<SourcesConfig>::load(Some("sources.hcl"))?;
-        let src = src_file.inner();
+        Ok(Sources::from_config(src_file.inner(), src_file.root()))
+    }
 
+    /// Fill in names and where tokens are kept
+    ///
+    fn from_config(src: &SourcesConfig, root: PathBuf) -> Self {
         let all = src
             .site
             .iter()
@@ -67,12 +121,101 @@ impl Sources {
                 let mut site = s.clone();
 
                 site.name = n.to_string();
-                site.token_base = src_file.root();
+                site.token_base = root.clone();
                 (n.to_string(), site)
             })
             .collect::<Vec<_>>();
-        let s = Sources::from(all);
-        Ok(s)
+        let mut s = Sources::from(all);
+        s.group = src.group.clone();
+        s
+    }
+
+    /// Keep the tokens of all sites in `dir`
+    ///
+    pub fn tokens_in(&mut self, dir: &Path) -> &mut Self {
+        self.site
+            .values_mut()
+            .for_each(|site| site.token_base = dir.to_path_buf());
+        self
+    }
+
+    /// Parse and check the content of a `sources.hcl` file
+    ///
+    #[tracing::instrument(skip(content))]
+    pub fn validate(content: &str) -> Result<Self> {
+        let src: SourcesConfig = hcl::from_str(content)?;
+        if src.version() != SOURCES_VERSION {
+            return Err(eyre!(
+                "bad sources.hcl version {}, expected {}",
+                src.version(),
+                SOURCES_VERSION
+            ));
+        }
+        Ok(Sources::from_config(&src, PathBuf::new()))
+    }
+
+    /// Is there a `sources.hcl` in the configuration directory?
+    ///
+    pub fn installed() -> bool {
+        config_dir().join(CONFIG).exists()
+    }
+
+    /// Built-in public sites needing no credentials (see `public.hcl`), for when there is no
+    /// `sources.hcl` at all.
+    ///
+    #[tracing::instrument]
+    pub fn builtin() -> Result<Self> {
+        Sources::validate(include_str!("public.hcl"))
+    }
+
+    /// Names of the sites in the default `sources.hcl`
+    ///
+    pub fn defaults() -> Result<Vec<String>> {
+        let body = hcl::parse(include_str!("sources.hcl"))?;
+        Ok(body
+            .blocks()
+            .filter(|b| b.identifier() == "site")
+            .filter_map(|b| b.labels().first().map(|l| l.as_str().to_string()))
+            .collect())
+    }
+
+    /// Default `sources.hcl` restricted to `sites` (all of them if empty), where the kind of
+    /// authentication of each site is replaced by credentials set to `PLACEHOLDER`.
+    ///
+    #[tracing::instrument]
+    pub fn template(sites: &[String]) -> Result<String> {
+        let all = Sources::defaults()?;
+        if let Some(bad) = sites.iter().find(|s| !all.contains(s)) {
+            return Err(eyre!("no such site {bad}, known: {}", all.join(", ")));
+        }
+
+        let body = hcl::parse(include_str!("sources.hcl"))?;
+        let body = body
+            .into_iter()
+            .filter_map(|st| match st {
+                Structure::Block(b) if b.identifier() == "site" => {
+                    let name = b.labels().first().map(|l| l.as_str().to_string());
+                    match name {
+                        Some(name) if sites.is_empty() || sites.contains(&name) => {
+                            Some(Structure::Block(placeholders(b)))
+                        }
+                        _ => None,
+                    }
+                }
+                st => Some(st),
+            })
+            .collect::<Body>();
+        let mut fmt = Formatter::builder()
+            .compact_arrays(true)
+            .prefer_ident_keys(true)
+            .build_vec();
+        Ok(body.format_string(&mut fmt)?)
+    }
+
+    /// Migrations for `sources.hcl`, register a new step here when `SOURCES_VERSION` is bumped.
+    ///
+    pub fn migrations() -> Migrations {
+        Migrations::new(CONFIG, Syntax::Hcl, SOURCES_VERSION)
     }
 
     /// Install default files
@@ -92,25 +235,91 @@ impl Sources {
         fs::write(fname, content)
     }
 
+    /// Take a session permit for `name` if the site has a `max_concurrent` limit, waiting for
+    /// other jobs using the same endpoint (`base_url`) to finish if needed.  The session ends
+    /// when the permit is dropped.  Groups share the limit of their template site.
+    ///
+    /// Returns `None` if `stop` said to give up before a session was free.
+    ///
+    #[tracing::instrument(skip(self, stop))]
+    pub fn acquire(&self, name: &str, stop: impl Fn() -> bool) -> Option<Permit> {
+        let name = self.group(name).map(|g| g.site.as_str()).unwrap_or(name);
+        match self.site.get(name) {
+            Some(site) => match site.max_concurrent {
+                Some(max) if max > 0 => self.limiter.acquire(&site.base_url, max, stop),
+                _ => Some(Permit::unlimited(name)),
+            },
+            None => Some(Permit::unlimited(name)),
+        }
+    }
+
+    /// Running sessions of every site with a `max_concurrent` limit, sites sharing a
+    /// `base_url` report the same sessions.
+    ///
+    pub fn sessions(&self) -> BTreeMap<String, Sessions> {
+        self.site
+            .iter()
+            .filter_map(|(name, site)| match site.max_concurrent {
+                Some(max) if max > 0 => Some((
+                    name.clone(),
+                    Sessions {
+                        used: self.limiter.used(&site.base_url),
+                        max,
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Number of site groups
+    ///
+    #[inline]
+    pub fn groups(&self) -> usize {
+        self.group.len()
+    }
+
+    /// Return the group `name` if there is one
+    ///
+    #[inline]
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.group.get(name)
+    }
+
+    /// Return an `Arc::clone` of the endpoints used by groups
+    ///
+    #[inline]
+    pub fn provenance(&self) -> Arc<Provenance> {
+        Arc::clone(&self.provenance)
+    }
+
     /// List of currently known sources into a nicely formatted string.
     ///
     #[tracing::instrument(skip(self))]
-    pub fn list(&self) -> Result<String> {
-        let header = vec!["Name", "Type", "Format", "URL", "Auth", "Ops"];
-
-        let mut builder = Builder::default();
-        builder.push_record(header);
-
-        self.site.iter().for_each(|(n, s)| {
-            let mut row = vec![];
-
-            let dtype = s.dtype.clone().to_string();
-            let format = s.format.clone().to_string();
-            let base_url = s.base_url.clone();
-            row.push(n);
-            row.push(&dtype);
-            row.push(&format);
-            row.push(&base_url);
+    pub fn list(&self, fmt: OutputFormat) -> Result<String> {
+        let mut list = Listing::new(
+            "Listing all sources",
+            &[
+                ("Name", "name"),
+                ("Type", "type"),
+                ("Format", "format"),
+                ("URL", "url"),
+                ("Auth", "auth"),
+                ("Ops", "ops"),
+                ("Max", "max"),
+            ],
+        );
+        list.rounded();
+
+        // Groups are listed as their template site with all endpoints
+        //
+        let all = self.site.iter().map(|(n, s)| (n, s, s.base_url.clone()));
+        let groups = self.group.iter().filter_map(|(n, g)| {
+            self.site
+                .get(&g.site)
+                .map(|s| (n, s, g.endpoints.join(",")))
+        });
+        all.chain(groups).for_each(|(n, s, url)| {
             let auth = if let Some(auth) = &s.auth {
                 match auth {
                     Auth::Login { .. } => "login",
@@ -118,26 +327,31 @@ impl Sources {
                     Auth::Anon => "open",
                     Auth::Key { .. } => "API key",
                     Auth::UserKey { .. } => "API+User keys",
+                    Auth::Oauth2 { .. } => "OAuth2",
+                    Auth::Certificate { .. } => "certificate",
                 }
                 .to_string()
             } else {
                 "anon".to_owned()
             };
-            row.push(&auth);
             let cap = s
                 .features
-                .clone()
                 .iter()
                 .map(|c| c.to_string())
                 .collect::<Vec<String>>()
                 .join(",");
-            row.push(&cap);
-            builder.push_record(row);
+            let max = s.max_concurrent.map(|m| m.to_string()).unwrap_or_default();
+            list.push(vec![
+                n.clone(),
+                s.dtype.to_string(),
+                s.format.to_string(),
+                url,
+                auth,
+                cap,
+                max,
+            ]);
         });
-
-        let table = builder.build().with(Style::rounded()).to_string();
-        let table = format!("Listing all sources:\n{table}");
-        Ok(table)
+        list.render(fmt)
     }
 }
 
@@ -153,6 +367,15 @@ impl Sources {
         self.site.get(name)
     }
 
+    /// Decoding strictness of the site `name`, the template site for groups
+    ///
+    pub fn strictness(&self, name: &str) -> Strictness {
+        let name = self.group(name).map(|g| g.site.as_str()).unwrap_or(name);
+        self.get(name)
+            .and_then(|site| site.strictness)
+            .unwrap_or_default()
+    }
+
     /// Wrap `get_mut`
     ///
     #[inline]
@@ -359,6 +582,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sources_acquire_shared_account() -> Result<()> {
+        let srcs = Sources::validate(&Sources::template(&[])?)?;
+
+        // `asd` and `lux` use the same ASD account
+        //
+        let p = srcs.acquire("asd", || false);
+        assert!(p.is_some());
+        assert_eq!(1, srcs.sessions()["lux"].used);
+        assert!(srcs.acquire("lux", || true).is_none());
+
+        // No limit there
+        //
+        assert!(srcs.acquire("opensky", || true).is_some());
+
+        drop(p);
+        assert!(srcs.acquire("lux", || true).is_some());
+        Ok(())
+    }
+
     #[test]
     fn test_install_files() -> Result<()> {
         let tempdir = temp_dir();
@@ -373,4 +616,33 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_sources_template() -> Result<()> {
+        let all = Sources::template(&[])?;
+        let srcs = Sources::validate(&all)?;
+        assert_eq!(Sources::defaults()?.len(), srcs.len());
+        assert!(srcs["opensky"].auth.as_ref().unwrap().is_placeholder());
+
+        let some = Sources::template(&["asd".to_string(), "simulator".to_string()])?;
+        let srcs = Sources::validate(&some)?;
+        assert_eq!(2, srcs.len());
+        assert!(matches!(srcs["asd"].auth, Some(Auth::Token { .. })));
+        assert!(srcs["simulator"].auth.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sources_template_unknown() {
+        assert!(Sources::template(&["nowhere".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_sources_builtin() -> Result<()> {
+        let srcs = Sources::builtin()?;
+        assert!(srcs.contains_key("opensky"));
+        assert!(srcs.values().all(|site| site.auth.is_none()));
+        assert_eq!("/states/all", srcs["opensky-live"].route("stream").unwrap());
+        Ok(())
+    }
 }
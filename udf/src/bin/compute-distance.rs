@@ -0,0 +1,34 @@
+//! Clickhouse executable UDF computing the distance and initial bearing between two points.
+//!
+//! Input: `lat1 lon1 lat2 lon2` (degrees) in `TabSeparated` format.
+//! Output: `(distance, bearing)` as a `Tuple(Float64, Float64)`, distance in meters and
+//! bearing in degrees.
+//!
+
+use std::io::{stderr, stdin, stdout, BufWriter};
+
+use eyre::Result;
+
+use fetiche_common::geo::{bearing, haversine};
+use fetiche_udf::{check, run, Row, UdfError, BATCH_SIZE};
+
+fn compute(row: &Row) -> Result<String, UdfError> {
+    row.expect(4)?;
+
+    let (lat1, lon1): (f64, f64) = (row.get(0)?, row.get(1)?);
+    let (lat2, lon2): (f64, f64) = (row.get(2)?, row.get(3)?);
+    check(lat1, lon1)?;
+    check(lat2, lon2)?;
+
+    let d = haversine(lat1, lon1, lat2, lon2);
+    let b = bearing(lat1, lon1, lat2, lon2);
+    Ok(format!("({},{})", d, b))
+}
+
+fn main() -> Result<()> {
+    let stdin = stdin().lock();
+    let stdout = BufWriter::new(stdout().lock());
+
+    run(stdin, stdout, stderr(), BATCH_SIZE, compute)?;
+    Ok(())
+}
@@ -0,0 +1,17 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum UdfError {
+    #[error("Bad column count: expected {0}, got {1}")]
+    BadColumnCount(usize, usize),
+    #[error("Column {0} is NULL")]
+    NullColumn(usize),
+    #[error("Column {0}: can not parse '{1}'")]
+    BadValue(usize, String),
+    #[error("Invalid escape sequence in '{0}'")]
+    BadEscape(String),
+    #[error("Invalid UTF-8 in '{0}'")]
+    BadEncoding(String),
+    #[error("Value out of range: {0}")]
+    OutOfRange(String),
+}
@@ -0,0 +1,31 @@
+//! Coordinate checks shared by the UDF.
+//!
+//! The computations themselves (`haversine()`, `bearing()`...) are in `fetiche_common::geo`,
+//! we only make sure Clickhouse did not send us garbage before calling them.
+//!
+
+use crate::UdfError;
+
+/// Check that a pair of coordinates is valid.
+///
+pub fn check(lat: f64, lon: f64) -> Result<(), UdfError> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(UdfError::OutOfRange(format!("({}, {})", lat, lon)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(0.0, 0.0, true)]
+    #[case(91.0, 0.0, false)]
+    #[case(45.0, -181.0, false)]
+    fn test_check(#[case] lat: f64, #[case] lon: f64, #[case] ok: bool) {
+        assert_eq!(ok, check(lat, lon).is_ok());
+    }
+}
@@ -0,0 +1,213 @@
+//! This library holds the common code for the different Clickhouse executable UDF helpers.
+//!
+//! Clickhouse starts the UDF binary once and keeps sending blocks of rows on `stdin` in the
+//! `TabSeparated` format, waiting for exactly one output row per input row on `stdout`.  Any
+//! crash or missing line makes the whole query fail so we must never panic on bad input.
+//!
+//! `run()` implements the protocol:
+//! - read rows as they come and process them in batches,
+//! - flush the output whenever the rows already read are all processed (end of a block from
+//!   Clickhouse), when the batch is full and at the end,
+//! - answer a row we can not handle (including one that is not UTF-8) with `\N` and report the
+//!   error on `stderr`.
+//!
+//! Input is read through our own `BufReader` so we know, without blocking, when everything
+//! received so far has been processed: Clickhouse waits for the answers of a block before
+//! sending the next one so the output must be flushed before reading again.
+//!
+
+use std::io::{BufRead, BufReader, Read, Write};
+
+use eyre::Result;
+
+pub use error::*;
+pub use geo::*;
+pub use tsv::*;
+
+mod error;
+mod geo;
+mod tsv;
+
+/// Default number of rows processed before forcing a flush.
+pub const BATCH_SIZE: usize = 1_024;
+
+/// Summary of a run, mostly for tests and debugging.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Rows read
+    pub rows: usize,
+    /// Rows answered with NULL
+    pub errors: usize,
+    /// Number of flushes
+    pub batches: usize,
+}
+
+/// Main loop of every UDF, `f` is called for each row and must return the (already escaped)
+/// output line without its final `\n`.
+///
+pub fn run<R, W, E, F>(input: R, mut output: W, mut errors: E, batch: usize, f: F) -> Result<Stats>
+where
+    R: Read,
+    W: Write,
+    E: Write,
+    F: Fn(&Row) -> Result<String, UdfError>,
+{
+    let mut stats = Stats::default();
+    let mut input = BufReader::new(input);
+    let mut pending = 0;
+    let mut line = vec![];
+
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        stats.rows += 1;
+
+        let res = match std::str::from_utf8(&line) {
+            Ok(line) => Row::parse(line.trim_end_matches(['\n', '\r'])).and_then(|row| f(&row)),
+            Err(_) => Err(UdfError::BadEncoding(
+                String::from_utf8_lossy(&line).trim_end().to_string(),
+            )),
+        };
+        match res {
+            Ok(res) => writeln!(output, "{}", res)?,
+            Err(e) => {
+                stats.errors += 1;
+                writeln!(errors, "row {}: {}", stats.rows, e)?;
+                writeln!(output, "{}", NULL)?;
+            }
+        }
+        pending += 1;
+
+        // Full batch or nothing left from what was received, reading again could block
+        //
+        if pending >= batch || input.buffer().is_empty() {
+            output.flush()?;
+            stats.batches += 1;
+            pending = 0;
+        }
+    }
+    if pending != 0 {
+        output.flush()?;
+        stats.batches += 1;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn double(row: &Row) -> Result<String, UdfError> {
+        row.expect(1)?;
+        let v: i64 = row.get(0)?;
+        Ok(format!("{}", v * 2))
+    }
+
+    #[test]
+    fn test_run_ok() {
+        let input = Cursor::new("1\n2\n3\n");
+        let mut out = vec![];
+        let mut err = vec![];
+
+        let stats = run(input, &mut out, &mut err, 2, double).unwrap();
+        assert_eq!("2\n4\n6\n", String::from_utf8(out).unwrap());
+        assert!(err.is_empty());
+        assert_eq!(3, stats.rows);
+        assert_eq!(2, stats.batches);
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let input = Cursor::new("1\nfoo\n\\N\n1\t2\n4\n");
+        let mut out = vec![];
+        let mut err = vec![];
+
+        let stats = run(input, &mut out, &mut err, BATCH_SIZE, double).unwrap();
+        assert_eq!("2\n\\N\n\\N\n\\N\n8\n", String::from_utf8(out).unwrap());
+        assert_eq!(3, stats.errors);
+        assert_eq!(3, String::from_utf8(err).unwrap().lines().count());
+    }
+
+    #[test]
+    fn test_run_bad_utf8() {
+        let input = Cursor::new(b"1\n\xff\xfe\n3\n".to_vec());
+        let mut out = vec![];
+        let mut err = vec![];
+
+        let stats = run(input, &mut out, &mut err, BATCH_SIZE, double).unwrap();
+        assert_eq!("2\n\\N\n6\n", String::from_utf8(out).unwrap());
+        assert_eq!(3, stats.rows);
+        assert_eq!(1, stats.errors);
+        assert!(String::from_utf8(err).unwrap().contains("UTF-8"));
+    }
+
+    /// Sends its blocks one `read()` at a time, checking that the answers to the previous
+    /// block were flushed before being asked for the next one.
+    ///
+    struct Blocks {
+        blocks: Vec<(&'static str, &'static str)>,
+        flushed: Rc<RefCell<String>>,
+        expected: String,
+    }
+
+    impl Read for Blocks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            assert_eq!(self.expected, *self.flushed.borrow());
+            if self.blocks.is_empty() {
+                return Ok(0);
+            }
+            let (block, answer) = self.blocks.remove(0);
+            self.expected.push_str(answer);
+            buf[..block.len()].copy_from_slice(block.as_bytes());
+            Ok(block.len())
+        }
+    }
+
+    /// Only shows what was written once flushed, like a `BufWriter`
+    ///
+    struct Flushed {
+        buf: Vec<u8>,
+        flushed: Rc<RefCell<String>>,
+    }
+
+    impl Write for Flushed {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            let buf = std::mem::take(&mut self.buf);
+            self.flushed
+                .borrow_mut()
+                .push_str(&String::from_utf8_lossy(&buf));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_run_flush_blocks() {
+        let flushed = Rc::new(RefCell::new(String::new()));
+        let input = Blocks {
+            blocks: vec![("1\n2\n", "2\n4\n"), ("3\n", "6\n")],
+            flushed: flushed.clone(),
+            expected: String::new(),
+        };
+        let out = Flushed {
+            buf: vec![],
+            flushed: flushed.clone(),
+        };
+
+        let stats = run(input, out, vec![], BATCH_SIZE, double).unwrap();
+        assert_eq!("2\n4\n6\n", *flushed.borrow());
+        assert_eq!(3, stats.rows);
+        assert_eq!(2, stats.batches);
+    }
+}
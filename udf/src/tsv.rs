@@ -0,0 +1,145 @@
+//! TSV framing as used by Clickhouse in the `TabSeparated` format.
+//!
+//! Each line is one row, columns are separated by `\t` and some characters are escaped with
+//! a backslash.  `\N` alone is NULL.
+//!
+
+use std::str::FromStr;
+
+use crate::UdfError;
+
+/// Marker for NULL values.
+pub const NULL: &str = "\\N";
+
+/// One row of data as received from Clickhouse, already split and unescaped.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row(Vec<Option<String>>);
+
+impl Row {
+    /// Split and unescape a single line (without its `\n`).
+    ///
+    pub fn parse(line: &str) -> Result<Self, UdfError> {
+        let cols = line
+            .split('\t')
+            .map(|col| {
+                if col == NULL {
+                    Ok(None)
+                } else {
+                    unescape(col).map(Some)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Row(cols))
+    }
+
+    /// Number of columns
+    ///
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Check we have exactly `n` columns.
+    ///
+    pub fn expect(&self, n: usize) -> Result<&Self, UdfError> {
+        if self.len() != n {
+            return Err(UdfError::BadColumnCount(n, self.len()));
+        }
+        Ok(self)
+    }
+
+    /// Get column `i` parsed into `T`, NULL being an error.
+    ///
+    pub fn get<T: FromStr>(&self, i: usize) -> Result<T, UdfError> {
+        match self.0.get(i) {
+            Some(Some(s)) => s
+                .parse::<T>()
+                .map_err(|_| UdfError::BadValue(i, s.to_owned())),
+            Some(None) => Err(UdfError::NullColumn(i)),
+            None => Err(UdfError::BadColumnCount(i + 1, self.len())),
+        }
+    }
+}
+
+/// Escape a single value for output.
+///
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Reverse of `escape()`.
+///
+pub fn unescape(s: &str) -> Result<String, UdfError> {
+    if !s.contains('\\') {
+        return Ok(s.to_owned());
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut iter = s.chars();
+    while let Some(c) = iter.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match iter.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('\'') => out.push('\''),
+            _ => return Err(UdfError::BadEscape(s.to_owned())),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("foo", "foo")]
+    #[case("a\\tb", "a\tb")]
+    #[case("a\\\\b", "a\\b")]
+    #[case("line\\nnext", "line\nnext")]
+    fn test_unescape(#[case] input: &str, #[case] res: &str) {
+        assert_eq!(res, unescape(input).unwrap());
+        assert_eq!(input, escape(res));
+    }
+
+    #[test]
+    fn test_unescape_bad() {
+        assert!(unescape("foo\\").is_err());
+    }
+
+    #[test]
+    fn test_row_parse() {
+        let r = Row::parse("1.5\t\\N\tfoo").unwrap();
+        assert_eq!(3, r.len());
+        assert_eq!(1.5, r.get::<f64>(0).unwrap());
+        assert_eq!(Err(UdfError::NullColumn(1)), r.get::<f64>(1));
+        assert!(r.get::<f64>(2).is_err());
+        assert!(r.get::<f64>(3).is_err());
+        assert!(r.expect(3).is_ok());
+        assert_eq!(Err(UdfError::BadColumnCount(4, 3)), r.expect(4).map(|_| ()));
+    }
+}